@@ -0,0 +1,287 @@
+//! Benchmarks of representative patches.
+//!
+//! Each topology is built out of `Processor` modules and timed two ways: through the
+//! `BlockScheduler` on the calling thread, and as tasks pulling frames through ports on a thread
+//! pool. The time per iteration is the latency of one block, and the throughput is in samples per
+//! second, which gives a rough idea of how big a patch a machine can run in real time.
+//!
+//! Run with `cargo bench`. Arguments after `--` are passed on to criterion, so benchmarks can be
+//! filtered by name.
+
+#[macro_use]
+extern crate criterion;
+extern crate flow_synth;
+extern crate futures;
+extern crate ndarray;
+
+use criterion::{Bencher, Benchmark, Criterion, Throughput};
+
+use futures::executor::{self, ThreadPool};
+use futures::prelude::*;
+use futures::task::SpawnExt;
+
+use flow_synth::future_ext::Breaker;
+use flow_synth::install::DynModule;
+use flow_synth::module::mix::{Gain, Mixer};
+use flow_synth::module::pool::FramePool;
+use flow_synth::module::process::{Process, Processor};
+use flow_synth::module::scheduler::BlockScheduler;
+use flow_synth::module::{audio_io::Frame, flow, Module};
+
+use ndarray::Array2;
+
+use std::sync::Arc;
+
+pub const BLOCK_SIZE: usize = 256;
+pub const CHANNELS: usize = 2;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Topology {
+    /// Gains one after the other.
+    Chain(usize),
+    /// Gains side by side, each with its own input, summed by a tree of mixers.
+    Fan(usize),
+    /// A mixer feeding a chain of gains, whose output goes back into the mixer.
+    Feedback(usize),
+}
+
+/// Sizes that are benchmarked by default.
+pub const TOPOLOGIES: &[Topology] = &[
+    Topology::Chain(1),
+    Topology::Chain(16),
+    Topology::Chain(64),
+    Topology::Fan(4),
+    Topology::Fan(16),
+    Topology::Fan(64),
+    Topology::Feedback(4),
+    Topology::Feedback(16),
+];
+
+impl Topology {
+    pub fn name(&self) -> String {
+        match *self {
+            Topology::Chain(n) => format!("chain/{}", n),
+            Topology::Fan(n) => format!("fan/{}", n),
+            Topology::Feedback(n) => format!("feedback/{}", n),
+        }
+    }
+    /// Whether the topology can run as tasks. Pulling through a loop would wait on itself, so
+    /// only the scheduler, which delays feedback by a block, runs loops.
+    pub fn runs_as_tasks(&self) -> bool {
+        match *self {
+            Topology::Feedback(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Copies its input to both outputs, so a signal can be sent two places.
+struct Split;
+
+impl Process for Split {
+    const NAME: &'static str = "Split";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Out 1", "Out 2"];
+    fn new() -> Split {
+        Split
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        for output in outputs {
+            output.data.assign(&inputs[0].data);
+        }
+    }
+}
+
+/// A built topology, with the inputs it reads from and the output it produces.
+pub struct Patch {
+    pub graph: Arc<flow::Graph>,
+    modules: Modules,
+    pub inputs: Vec<Arc<flow::Port<Frame, ()>>>,
+    pub output: Arc<flow::Port<(), Frame>>,
+}
+
+type Modules = Vec<Box<dyn DynModule>>;
+
+fn input(ifc: &flow::Interface, name: &str) -> Arc<flow::Port<Frame, ()>> {
+    ifc.find_port(name).unwrap()
+}
+
+fn output(ifc: &flow::Interface, name: &str) -> Arc<flow::Port<(), Frame>> {
+    ifc.find_port(name).unwrap()
+}
+
+fn add<P: Process>(graph: &Arc<flow::Graph>, modules: &mut Modules) -> Arc<flow::Interface> {
+    let ifc = graph.add_node();
+    modules.push(Box::new(Processor::<P>::new(ifc.clone())));
+    ifc
+}
+
+/// `n` gains in a row, returning the first input and the last output.
+fn chain(
+    graph: &Arc<flow::Graph>,
+    modules: &mut Modules,
+    n: usize,
+) -> (Arc<flow::Port<Frame, ()>>, Arc<flow::Port<(), Frame>>) {
+    let ifc = add::<Gain>(graph, modules);
+    let (first, mut last) = (input(&ifc, "Input"), output(&ifc, "Output"));
+    for _ in 1..n {
+        let ifc = add::<Gain>(graph, modules);
+        last.connect(&input(&ifc, "Input")).unwrap();
+        last = output(&ifc, "Output");
+    }
+    (first, last)
+}
+
+impl Patch {
+    pub fn new(topology: Topology) -> Patch {
+        let graph = flow::Graph::new();
+        let mut modules = Vec::new();
+        let (inputs, out) = match topology {
+            Topology::Chain(n) => {
+                let (first, last) = chain(&graph, &mut modules, n);
+                (vec![first], last)
+            }
+            Topology::Fan(n) => {
+                let (inputs, mut level): (Vec<_>, Vec<_>) =
+                    (0..n).map(|_| chain(&graph, &mut modules, 1)).unzip();
+                // sum four at a time until one signal is left
+                while level.len() > 1 {
+                    level = level
+                        .chunks(Mixer::INPUTS.len())
+                        .map(|group| {
+                            let mixer = add::<Mixer>(&graph, &mut modules);
+                            for (src, &name) in group.iter().zip(Mixer::INPUTS) {
+                                src.connect(&input(&mixer, name)).unwrap();
+                            }
+                            output(&mixer, "Output")
+                        })
+                        .collect();
+                }
+                (inputs, level.pop().unwrap())
+            }
+            Topology::Feedback(n) => {
+                let mixer = add::<Mixer>(&graph, &mut modules);
+                let split = add::<Split>(&graph, &mut modules);
+                let (first, last) = chain(&graph, &mut modules, n);
+                output(&mixer, "Output").connect(&first).unwrap();
+                last.connect(&input(&split, "Input")).unwrap();
+                output(&split, "Out 1").connect(&input(&mixer, "In 2")).unwrap();
+                (vec![input(&mixer, "In 1")], output(&split, "Out 2"))
+            }
+        };
+        Patch {
+            graph,
+            modules,
+            inputs,
+            output: out,
+        }
+    }
+}
+
+impl Drop for Patch {
+    fn drop(&mut self) {
+        for module in &mut self.modules {
+            module.stop();
+        }
+        // fail any reads still waiting, so the tasks notice they were stopped
+        for node in self.graph.nodes() {
+            for port in node.ports() {
+                let _ = port.disconnect();
+            }
+        }
+    }
+}
+
+fn capture() -> Frame {
+    Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((BLOCK_SIZE, CHANNELS), 0.5),
+        meta: None,
+    }
+}
+
+/// Run the patch through a `BlockScheduler`, with the first input reading the capture.
+fn bench_scheduler(b: &mut Bencher, topology: Topology) {
+    let patch = Patch::new(topology);
+    let host = patch.graph.add_node();
+    let host_in = host.get_or_create_port::<Frame, ()>("Input".into());
+    let host_out = host.get_or_create_port::<(), Frame>("Output".into());
+    host_out.connect(&patch.inputs[0]).unwrap();
+    patch.output.connect(&host_in).unwrap();
+    let mut scheduler = BlockScheduler::new(patch.graph.clone(), host.id());
+    let pool = patch.graph.pool();
+    let capture = capture();
+    b.iter(|| pool.recycle(scheduler.run(&capture)));
+}
+
+/// Answer every request on `port` with a copy of `frame`, until `breaker` is braked.
+fn start_source(
+    port: Arc<flow::Port<(), Frame>>,
+    frame: Frame,
+    pool: Arc<FramePool>,
+    breaker: Breaker,
+    exec: &ThreadPool,
+) {
+    exec.spawn(async move {
+        loop {
+            let frame = pool.copy(&frame);
+            let answered = port
+                .clone()
+                .read1()
+                .and_then(move |(port, _req)| port.write1(frame));
+            if let Err((_port, err)) = answered.await {
+                println!("bench source err: {:?}", err);
+                break;
+            }
+            if breaker.test() {
+                break;
+            }
+        }
+    })
+    .unwrap();
+}
+
+/// Run the patch as tasks, feeding every input from its own source and pulling the output.
+fn bench_tasks(b: &mut Bencher, topology: Topology) {
+    let exec = ThreadPool::new().unwrap();
+    let mut patch = Patch::new(topology);
+    let host = patch.graph.add_node();
+    let pool = patch.graph.pool();
+    let breaker = Breaker::new();
+    for (idx, input) in patch.inputs.iter().enumerate() {
+        let source = host.get_or_create_port::<(), Frame>(format!("Source {}", idx + 1));
+        source.connect(input).unwrap();
+        start_source(source, capture(), pool.clone(), breaker.clone(), &exec);
+    }
+    let sink = host.get_or_create_port::<Frame, ()>("Sink".into());
+    patch.output.connect(&sink).unwrap();
+    for module in &mut patch.modules {
+        module.start(exec.clone());
+    }
+    b.iter(|| {
+        let pulled = executor::block_on(sink.clone().write1(()).and_then(|port| port.read1()));
+        match pulled {
+            Ok((_port, frame)) => pool.recycle(frame),
+            Err((_port, err)) => panic!("pull failed: {:?}", err),
+        }
+    });
+    breaker.brake();
+}
+
+/// Register a benchmark for each of `TOPOLOGIES`.
+pub fn run(c: &mut Criterion) {
+    for &topology in TOPOLOGIES {
+        let mut benchmark = Benchmark::new("scheduler", move |b| bench_scheduler(b, topology));
+        if topology.runs_as_tasks() {
+            benchmark = benchmark.with_function("tasks", move |b| bench_tasks(b, topology));
+        }
+        c.bench(
+            &topology.name(),
+            benchmark.throughput(Throughput::Elements(BLOCK_SIZE as u32)),
+        );
+    }
+}
+
+criterion_group!(benches, run);
+criterion_main!(benches);
@@ -0,0 +1,138 @@
+//! A/B comparison of two versions of a patch.
+//!
+//! `Graph::store_compare` snapshots every node into slot A or B, as a scene, and selecting a slot
+//! recalls it over `FADE`, short enough to hear the two side by side but long enough not to
+//! click. While a slot is selected, edits belong to it: toggling stores the patch into the slot
+//! being left before moving to the other, so both versions can be worked on in turn. The slots
+//! aren't saved with the patch, keep a version worth keeping as a scene.
+
+use crate::flow::NodeId;
+use crate::scene::Scene;
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// How long switching between the slots takes.
+pub const FADE: Duration = Duration::from_millis(50);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Compare {
+    pub a: Option<Scene>,
+    pub b: Option<Scene>,
+    /// The slot last stored or selected, which edits belong to.
+    pub active: Option<Slot>,
+}
+
+impl Compare {
+    pub fn slot(&self, slot: Slot) -> Option<&Scene> {
+        match slot {
+            Slot::A => self.a.as_ref(),
+            Slot::B => self.b.as_ref(),
+        }
+    }
+    pub fn set_slot(&mut self, slot: Slot, scene: Scene) {
+        match slot {
+            Slot::A => self.a = Some(scene),
+            Slot::B => self.b = Some(scene),
+        }
+    }
+}
+
+/// A param set differently in the two slots. A side is `None` if its snapshot doesn't have the
+/// param, like a node added after it was taken.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Difference {
+    pub node: NodeId,
+    pub param: String,
+    pub a: Option<f32>,
+    pub b: Option<f32>,
+}
+
+/// The params which differ between `a` and `b`, by node and name.
+pub fn differences(a: &Scene, b: &Scene) -> Vec<Difference> {
+    let nodes: BTreeSet<_> = a.nodes.keys().chain(b.nodes.keys()).collect();
+    let mut differences = Vec::new();
+    for &node in nodes {
+        let param = |scene: &Scene, name: &str| {
+            scene
+                .nodes
+                .get(&node)
+                .and_then(|state| state.params.get(name))
+                .cloned()
+        };
+        let names: BTreeSet<_> = a
+            .nodes
+            .get(&node)
+            .into_iter()
+            .chain(b.nodes.get(&node))
+            .flat_map(|state| state.params.keys())
+            .collect();
+        for name in names {
+            let (a, b) = (param(a, name), param(b, name));
+            if a != b {
+                differences.push(Difference {
+                    node,
+                    param: name.clone(),
+                    a,
+                    b,
+                });
+            }
+        }
+    }
+    differences
+}
+
+#[test]
+fn test_compare() {
+    use crate::flow::Graph;
+    use crate::scene::ParamList;
+
+    use std::thread;
+
+    let graph = Graph::new();
+    let gain = graph.add_node();
+    gain.set_params(ParamList::new(&[("Gain", 1.0, (0.0, 2.0))]));
+    let mixer = graph.add_node();
+    mixer.set_params(ParamList::new(&[("Level 1", 1.0, (0.0, 1.0)), ("Level 2", 1.0, (0.0, 1.0))]));
+    assert!(graph.toggle_compare().is_err());
+
+    graph.store_compare(Slot::A);
+    gain.params().unwrap().set("Gain", 0.25);
+    graph.store_compare(Slot::B);
+    assert_eq!(
+        graph.compare_differences().unwrap(),
+        vec![Difference {
+            node: gain.id(),
+            param: "Gain".into(),
+            a: Some(1.0),
+            b: Some(0.25),
+        }]
+    );
+
+    assert_eq!(graph.toggle_compare().unwrap(), Slot::A);
+    thread::sleep(FADE * 4);
+    assert_eq!(gain.params().unwrap().get("Gain"), Some(1.0));
+    // edits made on A stay with A
+    mixer.params().unwrap().set("Level 1", 0.5);
+    assert_eq!(graph.toggle_compare().unwrap(), Slot::B);
+    thread::sleep(FADE * 4);
+    assert_eq!(gain.params().unwrap().get("Gain"), Some(0.25));
+    assert_eq!(mixer.params().unwrap().get("Level 1"), Some(1.0));
+    assert_eq!(graph.compare_differences().unwrap().len(), 2);
+}
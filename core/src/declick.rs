@@ -0,0 +1,94 @@
+//! Click suppression for audio streams whose source changes while running.
+//!
+//! Connecting, disconnecting or bypassing a live signal makes it jump. A `Declick` remembers
+//! what the previous frame came from, and when that changes it crossfades from the last sample
+//! before the change to the new signal. Audio ports opt in by setting `PortMeta::ramp`.
+
+use crate::frame::Frame;
+
+use ndarray::{Array1, Axis};
+
+/// Crossfade length used by `Processor` ports, in seconds.
+pub const DEFAULT_RAMP: f32 = 0.005;
+
+pub struct Declick<K> {
+    /// What the previous frame came from.
+    key: Option<K>,
+    /// The last sample of each channel in the previous frame.
+    last: Option<Array1<f32>>,
+    /// The sample being faded out, and how many samples of the fade are done.
+    fade: Option<(Array1<f32>, usize)>,
+}
+
+impl<K: PartialEq> Declick<K> {
+    pub fn new() -> Declick<K> {
+        Declick {
+            key: None,
+            last: None,
+            fade: None,
+        }
+    }
+
+    /// Process a frame which came from `key`, fading over `ramp` seconds if `key` differs from
+    /// the previous frame's.
+    pub fn process(&mut self, key: K, ramp: f32, frame: &mut Frame) {
+        let changed = self.key.as_ref().map(|old| *old != key).unwrap_or(false);
+        self.key = Some(key);
+        let (n_samples, n_channels) = frame.data.dim();
+        if changed {
+            // restart from wherever we are, even in the middle of a fade
+            self.fade = self
+                .last
+                .take()
+                .filter(|last| last.len() == n_channels)
+                .map(|last| (last, 0));
+        }
+
+        let len = ((ramp * frame.rate) as usize).max(1);
+        if let Some((held, mut pos)) = self.fade.take() {
+            for mut sample in frame.data.outer_iter_mut() {
+                if pos >= len {
+                    break;
+                }
+                let gain = pos as f32 / len as f32;
+                for (x, &h) in sample.iter_mut().zip(held.iter()) {
+                    *x = *x * gain + h * (1.0 - gain);
+                }
+                pos += 1;
+            }
+            if pos < len && held.len() == n_channels {
+                self.fade = Some((held, pos));
+            }
+        }
+        if n_samples > 0 {
+            self.last = Some(frame.data.subview(Axis(0), n_samples - 1).to_owned());
+        }
+    }
+}
+
+#[test]
+fn test_declick() {
+    use ndarray::Array2;
+
+    let frame = |value: f32| Frame {
+        rate: 1000.0,
+        time: None,
+        data: Array2::from_elem((4, 1), value),
+        meta: None,
+    };
+    let mut declick = Declick::new();
+    let mut a = frame(1.0);
+    declick.process(0, 0.006, &mut a);
+    assert!(a.data.iter().all(|&x| x == 1.0));
+
+    // switching to silence ramps down over 6 samples, across frames
+    let mut b = frame(0.0);
+    declick.process(1, 0.006, &mut b);
+    let b: Vec<_> = b.data.iter().cloned().collect();
+    assert_eq!(b[0], 1.0);
+    assert!(b.windows(2).all(|w| w[1] < w[0]));
+    let mut c = frame(0.0);
+    declick.process(1, 0.006, &mut c);
+    assert!(c.data[[0, 0]] > 0.0 && c.data[[0, 0]] < b[3]);
+    assert!(c.data.iter().skip(2).all(|&x| x == 0.0));
+}
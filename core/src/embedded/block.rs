@@ -0,0 +1,97 @@
+//! Processing a block of samples at a time, and a few blocks to build patches from.
+
+use core::f32::consts::PI;
+
+/// The most inputs or outputs a block may have, so the scheduler can pass them without allocating.
+pub const MAX_PORTS: usize = 16;
+
+/// Processes one block of samples at a time.
+pub trait Block {
+    fn inputs(&self) -> usize;
+    fn outputs(&self) -> usize;
+    /// Fill in `outputs` from `inputs`, all as long as the scheduler's block size. Outputs start
+    /// zeroed.
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]);
+}
+
+/// Scales its input.
+pub struct Gain(pub f32);
+
+impl Block for Gain {
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        for (out, &x) in outputs[0].iter_mut().zip(inputs[0]) {
+            *out = x * self.0;
+        }
+    }
+}
+
+/// Sums its inputs, as many as it's given.
+pub struct Mix(pub usize);
+
+impl Block for Mix {
+    fn inputs(&self) -> usize {
+        self.0
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        for input in inputs {
+            for (out, &x) in outputs[0].iter_mut().zip(input.iter()) {
+                *out += x;
+            }
+        }
+    }
+}
+
+/// A sine oscillator at a fixed frequency, in cycles per sample.
+pub struct Sine {
+    pub frequency: f32,
+    phase: f32,
+}
+
+impl Sine {
+    pub fn new(frequency: f32) -> Sine {
+        Sine {
+            frequency,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Block for Sine {
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn process(&mut self, _inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        for out in outputs[0].iter_mut() {
+            *out = sin(self.phase * 2.0 * PI);
+            self.phase = (self.phase + self.frequency) % 1.0;
+        }
+    }
+}
+
+/// Without std there's no `f32::sin`, so approximate it. Good to about 0.001 over `-PI..PI`.
+fn sin(x: f32) -> f32 {
+    // fold into -PI..PI, then a parabola with a correction towards the peaks
+    let x = x - 2.0 * PI * ((x + PI) / (2.0 * PI) - if x + PI < 0.0 { 1.0 } else { 0.0 }) as i32 as f32;
+    let y = 4.0 / PI * x - 4.0 / (PI * PI) * x * if x < 0.0 { -x } else { x };
+    0.225 * (y * if y < 0.0 { -y } else { y } - y) + y
+}
+
+#[test]
+fn test_sin() {
+    for i in -100..100 {
+        let x = i as f32 * 0.1;
+        assert!((sin(x) - x.sin()).abs() < 0.002, "{}", x);
+    }
+}
@@ -0,0 +1,151 @@
+//! Nodes and the connections between their ports.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::embedded::block::{Block, MAX_PORTS};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(pub usize);
+
+/// The host running the graph. Its outputs are the inputs passed to `Scheduler::poll`, and its
+/// inputs are the outputs filled in.
+pub const HOST: NodeId = NodeId(usize::MAX);
+
+/// An input or output of a node, by index.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PortRef {
+    pub node: NodeId,
+    pub port: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidNode,
+    InvalidPort,
+    /// A block has more than `MAX_PORTS` inputs or outputs.
+    TooManyPorts,
+    /// The buffers passed to `Scheduler::poll` don't match the host or the block size.
+    InvalidBuffers,
+}
+
+pub(crate) struct Node {
+    pub block: Box<dyn Block>,
+    /// The output each input reads from, if it's connected.
+    pub inputs: Vec<Option<PortRef>>,
+}
+
+pub struct Graph {
+    /// By id, with removed nodes leaving a gap so ids stay valid.
+    pub(crate) nodes: Vec<Option<Node>>,
+    /// The output each of the host's inputs reads from.
+    pub(crate) host_inputs: Vec<Option<PortRef>>,
+    host_outputs: usize,
+    /// Bumped on every change, so the scheduler knows to recompile.
+    generation: usize,
+}
+
+impl Graph {
+    /// Make an empty graph for a host with `inputs` channels going into the graph and `outputs`
+    /// coming out.
+    pub fn new(inputs: usize, outputs: usize) -> Graph {
+        Graph {
+            nodes: Vec::new(),
+            host_inputs: (0..outputs).map(|_| None).collect(),
+            host_outputs: inputs,
+            generation: 0,
+        }
+    }
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+    pub fn add_node(&mut self, block: Box<dyn Block>) -> Result<NodeId, Error> {
+        if block.inputs() > MAX_PORTS || block.outputs() > MAX_PORTS {
+            return Err(Error::TooManyPorts);
+        }
+        let inputs = (0..block.inputs()).map(|_| None).collect();
+        self.nodes.push(Some(Node { block, inputs }));
+        self.generation += 1;
+        Ok(NodeId(self.nodes.len() - 1))
+    }
+    /// Take a node out of the graph, disconnecting everything it was connected to.
+    pub fn remove_node(&mut self, id: NodeId) -> Result<Box<dyn Block>, Error> {
+        let node = self
+            .nodes
+            .get_mut(id.0)
+            .and_then(|node| node.take())
+            .ok_or(Error::InvalidNode)?;
+        let inputs = self
+            .nodes
+            .iter_mut()
+            .filter_map(|node| node.as_mut())
+            .flat_map(|node| node.inputs.iter_mut())
+            .chain(self.host_inputs.iter_mut());
+        for input in inputs {
+            if input.map(|source| source.node) == Some(id) {
+                *input = None;
+            }
+        }
+        self.generation += 1;
+        Ok(node.block)
+    }
+    /// The block of a node, for changing its settings.
+    pub fn block_mut(&mut self, id: NodeId) -> Option<&mut (dyn Block + 'static)> {
+        match self.nodes.get_mut(id.0) {
+            Some(&mut Some(ref mut node)) => Some(&mut *node.block),
+            _ => None,
+        }
+    }
+    /// Connect `output` to `input`, replacing the input's previous connection. An output can feed
+    /// any number of inputs.
+    pub fn connect(&mut self, output: PortRef, input: PortRef) -> Result<(), Error> {
+        self.check_output(output)?;
+        *self.input_mut(input)? = Some(output);
+        self.generation += 1;
+        Ok(())
+    }
+    pub fn disconnect(&mut self, input: PortRef) -> Result<(), Error> {
+        *self.input_mut(input)? = None;
+        self.generation += 1;
+        Ok(())
+    }
+    /// The output `input` reads from.
+    pub fn source(&self, input: PortRef) -> Result<Option<PortRef>, Error> {
+        let inputs = if input.node == HOST {
+            &self.host_inputs
+        } else {
+            match self.nodes.get(input.node.0) {
+                Some(Some(node)) => &node.inputs,
+                _ => return Err(Error::InvalidNode),
+            }
+        };
+        inputs.get(input.port).cloned().ok_or(Error::InvalidPort)
+    }
+
+    fn check_output(&self, output: PortRef) -> Result<(), Error> {
+        let outputs = if output.node == HOST {
+            self.host_outputs
+        } else {
+            match self.nodes.get(output.node.0) {
+                Some(Some(node)) => node.block.outputs(),
+                _ => return Err(Error::InvalidNode),
+            }
+        };
+        if output.port < outputs {
+            Ok(())
+        } else {
+            Err(Error::InvalidPort)
+        }
+    }
+    fn input_mut(&mut self, input: PortRef) -> Result<&mut Option<PortRef>, Error> {
+        let inputs = if input.node == HOST {
+            &mut self.host_inputs
+        } else {
+            match self.nodes.get_mut(input.node.0) {
+                Some(&mut Some(ref mut node)) => &mut node.inputs,
+                _ => return Err(Error::InvalidNode),
+            }
+        };
+        inputs.get_mut(input.port).ok_or(Error::InvalidPort)
+    }
+}
@@ -0,0 +1,20 @@
+//! The graph and block scheduling cut down for embedded targets.
+//!
+//! Only `alloc` is needed: no threads, no executor and no OS, so patches can run on a
+//! microcontroller driving an installation. A `Graph` holds nodes with a `Block` each, wired output
+//! to input like the ports of `flow::Graph`, and a `Scheduler` runs the nodes the host hears in
+//! dependency order, one block of samples per `poll`, from the main loop or an audio interrupt.
+//! Buffers are allocated when the schedule is compiled, after the graph changes, so polling
+//! doesn't allocate.
+//!
+//! Signals are mono `f32` buffers, one per port, so a stereo signal takes two ports. Connections
+//! that close a loop read the buffer from the previous poll, giving one block of feedback delay,
+//! like `scheduler::BlockScheduler`.
+
+pub mod block;
+pub mod graph;
+pub mod scheduler;
+
+pub use self::block::Block;
+pub use self::graph::{Error, Graph, NodeId, PortRef};
+pub use self::scheduler::Scheduler;
@@ -0,0 +1,208 @@
+//! Single threaded, poll based block scheduling.
+
+use alloc::vec::Vec;
+
+use crate::embedded::block::MAX_PORTS;
+use crate::embedded::graph::{Error, Graph, NodeId, PortRef, HOST};
+
+enum Source {
+    Silence,
+    /// A channel passed in by the host.
+    Host(usize),
+    /// An output of a node, by node index and output index.
+    Node(usize, usize),
+}
+
+struct Step {
+    node: usize,
+    inputs: Vec<Source>,
+    /// Where the inputs are copied before the block runs, as it may read its own outputs.
+    scratch: Vec<Vec<f32>>,
+}
+
+/// Runs a graph one block at a time.
+pub struct Scheduler {
+    graph: Graph,
+    block_size: usize,
+    generation: Option<usize>,
+    steps: Vec<Step>,
+    /// The outputs of every node from the most recent poll, by node index.
+    buffers: Vec<Vec<Vec<f32>>>,
+    /// What each of the host's inputs reads.
+    results: Vec<Source>,
+}
+
+impl Scheduler {
+    pub fn new(graph: Graph, block_size: usize) -> Scheduler {
+        Scheduler {
+            graph,
+            block_size,
+            generation: None,
+            steps: Vec::new(),
+            buffers: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+    /// The graph, for editing. The schedule is compiled again on the next poll if it changes.
+    pub fn graph_mut(&mut self) -> &mut Graph {
+        &mut self.graph
+    }
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Work out the processing order, and allocate the buffers for it.
+    fn compile(&mut self) {
+        self.generation = Some(self.graph.generation());
+        let nodes = &self.graph.nodes;
+
+        // depth first from the host's inputs, ordering each node after everything it reads from
+        let mut order = Vec::new();
+        let mut visiting = Vec::new();
+        fn visit(node: NodeId, graph: &Graph, visiting: &mut Vec<usize>, order: &mut Vec<usize>) {
+            if node == HOST || visiting.contains(&node.0) || order.contains(&node.0) {
+                return;
+            }
+            let inputs = match graph.nodes.get(node.0) {
+                Some(Some(node)) => &node.inputs,
+                _ => return,
+            };
+            visiting.push(node.0);
+            for source in inputs.iter().filter_map(|source| *source) {
+                visit(source.node, graph, visiting, order);
+            }
+            visiting.pop();
+            order.push(node.0);
+        }
+        for source in self.graph.host_inputs.iter().filter_map(|source| *source) {
+            visit(source.node, &self.graph, &mut visiting, &mut order);
+        }
+
+        let position = |source: &Option<PortRef>| match *source {
+            Some(PortRef { node: HOST, port }) => Source::Host(port),
+            Some(PortRef { node, port }) if order.contains(&node.0) => Source::Node(node.0, port),
+            _ => Source::Silence,
+        };
+        let block_size = self.block_size;
+        self.steps = order
+            .iter()
+            .map(|&idx| {
+                let inputs = &nodes[idx].as_ref().unwrap().inputs;
+                Step {
+                    node: idx,
+                    inputs: inputs.iter().map(&position).collect(),
+                    scratch: inputs.iter().map(|_| vec![0.0; block_size]).collect(),
+                }
+            })
+            .collect();
+        self.buffers = nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| match *node {
+                Some(ref node) if order.contains(&idx) => {
+                    (0..node.block.outputs()).map(|_| vec![0.0; block_size]).collect()
+                }
+                _ => Vec::new(),
+            })
+            .collect();
+        self.results = self.graph.host_inputs.iter().map(&position).collect();
+    }
+
+    /// Process one block. `inputs` are the channels going into the graph, and `outputs` are filled
+    /// with the channels coming out, all `block_size` long.
+    pub fn poll(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) -> Result<(), Error> {
+        let block_size = self.block_size;
+        if inputs.iter().any(|input| input.len() != block_size)
+            || outputs.len() != self.graph.host_inputs.len()
+            || outputs.iter().any(|output| output.len() != block_size)
+        {
+            return Err(Error::InvalidBuffers);
+        }
+        if self.generation != Some(self.graph.generation()) {
+            self.compile();
+        }
+
+        for step in &mut self.steps {
+            for (source, scratch) in step.inputs.iter().zip(&mut step.scratch) {
+                read(source, inputs, &self.buffers, scratch);
+            }
+            let mut ins: [&[f32]; MAX_PORTS] = Default::default();
+            for (input, scratch) in ins.iter_mut().zip(&step.scratch) {
+                *input = scratch;
+            }
+            let node = self.graph.nodes[step.node].as_mut().unwrap();
+            let buffers = &mut self.buffers[step.node];
+            let count = buffers.len();
+            let mut outs: [&mut [f32]; MAX_PORTS] = Default::default();
+            for (output, buffer) in outs.iter_mut().zip(buffers.iter_mut()) {
+                for x in buffer.iter_mut() {
+                    *x = 0.0;
+                }
+                *output = buffer;
+            }
+            node.block.process(&ins[..step.scratch.len()], &mut outs[..count]);
+        }
+        for (source, output) in self.results.iter().zip(outputs) {
+            read(source, inputs, &self.buffers, output);
+        }
+        Ok(())
+    }
+}
+
+/// Copy what `source` holds into `out`.
+fn read(source: &Source, inputs: &[&[f32]], buffers: &[Vec<Vec<f32>>], out: &mut [f32]) {
+    let data: &[f32] = match *source {
+        Source::Host(channel) if channel < inputs.len() => inputs[channel],
+        Source::Node(node, output) => &buffers[node][output],
+        _ => &[],
+    };
+    if data.is_empty() {
+        for x in out.iter_mut() {
+            *x = 0.0;
+        }
+    } else {
+        out.copy_from_slice(data);
+    }
+}
+
+#[test]
+fn test_scheduler() {
+    use alloc::boxed::Box;
+    use crate::embedded::block::{Gain, Mix};
+
+    // host -> gain -> mix -> host, then with the mix fed back into its second input
+    let mut graph = Graph::new(1, 1);
+    let gain = graph.add_node(Box::new(Gain(0.5))).unwrap();
+    let mix = graph.add_node(Box::new(Mix(2))).unwrap();
+    let feedback = graph.add_node(Box::new(Gain(1.0))).unwrap();
+    let port = |node, port| PortRef { node, port };
+    graph.connect(port(HOST, 0), port(gain, 0)).unwrap();
+    graph.connect(port(gain, 0), port(mix, 0)).unwrap();
+    graph.connect(port(mix, 0), port(HOST, 0)).unwrap();
+    assert_eq!(graph.connect(port(mix, 1), port(HOST, 0)), Err(Error::InvalidPort));
+    assert_eq!(graph.source(port(mix, 0)), Ok(Some(port(gain, 0))));
+
+    let mut scheduler = Scheduler::new(graph, 2);
+    let input = [1.0, 2.0];
+    let mut output = [0.0; 2];
+    scheduler.poll(&[&input], &mut [&mut output]).unwrap();
+    assert_eq!(output, [0.5, 1.0]);
+    assert_eq!(scheduler.poll(&[&input[..1]], &mut [&mut output]), Err(Error::InvalidBuffers));
+
+    // feedback through the mix reads the previous block
+    scheduler.graph_mut().connect(port(mix, 0), port(feedback, 0)).unwrap();
+    scheduler.graph_mut().connect(port(feedback, 0), port(mix, 1)).unwrap();
+    scheduler.poll(&[&input], &mut [&mut output]).unwrap();
+    assert_eq!(output, [0.5, 1.0]);
+    scheduler.poll(&[&input], &mut [&mut output]).unwrap();
+    assert_eq!(output, [1.0, 2.0]);
+
+    // removing a node disconnects it
+    scheduler.graph_mut().remove_node(feedback).unwrap();
+    assert_eq!(scheduler.graph().source(port(mix, 1)), Ok(None));
+    scheduler.poll(&[&input], &mut [&mut output]).unwrap();
+    assert_eq!(output, [0.5, 1.0]);
+}
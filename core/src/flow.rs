@@ -0,0 +1,3286 @@
+/*!
+ * This is some kind of a library for dataflow computation. It's still very experimental and may
+ * become something completely different in the end.
+ */
+
+use crate::compare::{self, Compare, Difference, Slot};
+use crate::frame::Frame;
+use crate::future_ext::{Breaker, Lock};
+use crate::mapping::{Control, Mapping, Mappings, Target};
+use crate::pool::FramePool;
+use crate::randomize::{self, Rng, Scope};
+use crate::scene::{Params, Scene};
+use crate::scheduler::{BlockNode, CONTROL_DIVISION};
+use crate::simd;
+use crate::snapshot::GraphSnapshot;
+use crate::solo::{self, Solo, SoloMode};
+use crate::timeline::TempoMap;
+use crate::tuning::Tuning;
+use crate::workers::SchedulerConfig;
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future::{FutureExt, TryFutureExt};
+use futures::stream::Stream;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use std::any::{self, Any, TypeId};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::Pin;
+#[cfg(not(feature = "safe-ports"))]
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// Identifies a graph within the running process. Unlike node and port ids these are not
+/// persistent, since they're only needed to tell live graphs apart.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
+pub struct GraphId(usize);
+
+static GRAPH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A lightweight persistent identifier for a node. Only guaranteed to be unique within a specific
+/// graph.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct NodeId(pub usize);
+
+/// The metadata entry listing the params of a node which are locked, see `Node::lock_param`.
+const LOCKED_PARAMS: &str = "params.locked";
+
+/// A lightweight persistent identifier for a port. Only guaranteed to be unique within a specific
+/// graph.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PortId(pub usize);
+
+/// A graph holds a collection of Nodes. Nodes have a collection of Ports. Ports can be connected
+/// to each other one-to-one.
+pub struct Graph {
+    id: GraphId,
+    nodes: RwLock<HashMap<NodeId, Arc<Node>>>,
+    id_counter: AtomicUsize,
+    /// Bumped on every change to the topology, so schedulers know to recompile.
+    generation: Arc<AtomicUsize>,
+    /// Sample buffers shared by everything processing audio in this graph.
+    pool: Arc<FramePool>,
+    lifecycle: Mutex<Lifecycle>,
+    scenes: Mutex<BTreeMap<String, Scene>>,
+    /// Stops the scene transition in progress.
+    transition: Mutex<Breaker>,
+    /// How key numbers map to pitch, see `tuning`.
+    tuning: Mutex<Arc<Tuning>>,
+    /// Where the bars and beats fall on the transport, see `timeline`.
+    tempo_map: Mutex<Arc<TempoMap>>,
+    /// Held for writing while a batch from `apply` is being checked and applied.
+    edits: RwLock<()>,
+    /// Buffers the audio host missed, see `xrun`.
+    xruns: AtomicUsize,
+    /// Buffers between runs of control rate blocks, see `Signal::Control`.
+    control_division: AtomicUsize,
+    /// Threads the block scheduler runs the graph on, see `workers`.
+    scheduler_config: Mutex<Arc<SchedulerConfig>>,
+    /// See `solo`.
+    solo: Mutex<Solo>,
+    /// See `mapping`.
+    mappings: Mutex<Mappings>,
+    /// See `randomize`.
+    rng: Mutex<Rng>,
+    /// See `compare`.
+    compare: Mutex<Compare>,
+}
+
+/// Whether the nodes of a graph are processing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RunState {
+    Running,
+    /// No new blocks start, and everything in flight is kept for when the graph resumes.
+    Paused,
+    /// No new blocks start, and port buffers are handled according to the `BufferPolicy`.
+    Stopped,
+}
+
+/// What happens to data waiting in port buffers when a graph is stopped.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BufferPolicy {
+    /// Discard it, so the graph starts again from silence.
+    Flush,
+    /// Keep it, so the graph picks up where it left off.
+    Preserve,
+}
+
+struct Lifecycle {
+    state: RunState,
+    policy: BufferPolicy,
+    /// Blocks of pre-roll for nodes waking up, if nodes nobody listens to are put to sleep.
+    lazy: Option<usize>,
+    /// Tasks waiting for the graph to start again.
+    waiting: Vec<Waker>,
+}
+
+impl Graph {
+    /// Make a new empty graph.
+    pub fn new() -> Arc<Graph> {
+        Arc::new(Graph {
+            id: GraphId(GRAPH_COUNTER.fetch_add(1, Ordering::SeqCst)),
+            nodes: RwLock::new(HashMap::new()),
+            id_counter: 0.into(),
+            generation: Arc::new(0.into()),
+            pool: Arc::new(FramePool::new()),
+            lifecycle: Mutex::new(Lifecycle {
+                state: RunState::Running,
+                policy: BufferPolicy::Flush,
+                lazy: None,
+                waiting: Vec::new(),
+            }),
+            scenes: Mutex::new(BTreeMap::new()),
+            transition: Mutex::new(Breaker::new()),
+            tuning: Mutex::new(Arc::new(Tuning::equal())),
+            tempo_map: Mutex::new(Arc::new(TempoMap::new())),
+            edits: RwLock::new(()),
+            xruns: 0.into(),
+            control_division: CONTROL_DIVISION.into(),
+            scheduler_config: Mutex::new(Arc::new(SchedulerConfig::default())),
+            solo: Mutex::new(Solo::default()),
+            mappings: Mutex::new(Mappings::default()),
+            rng: Mutex::new(Rng::from_time()),
+            compare: Mutex::new(Compare::default()),
+        })
+    }
+    pub fn id(&self) -> GraphId {
+        self.id
+    }
+    pub fn pool(&self) -> Arc<FramePool> {
+        self.pool.clone()
+    }
+    /// Count a buffer the audio host had to skip because processing took too long.
+    pub fn xrun(&self) {
+        self.xruns.fetch_add(1, Ordering::Relaxed);
+    }
+    /// The number of buffers the audio host has skipped, for telemetry.
+    pub fn xruns(&self) -> usize {
+        self.xruns.load(Ordering::Relaxed)
+    }
+    /// Construct a new node from the given metadata and argument.
+    pub fn add_node(self: &Arc<Graph>) -> Arc<Interface> {
+        self.add_node_with_id(NodeId(self.generate_id()))
+    }
+    /// Construct a new node from the given metadata and argument.
+    pub fn add_node_with_id(self: &Arc<Graph>, id: NodeId) -> Arc<Interface> {
+        self.constrain_min_id(id.0 + 1);
+        let ifc = Arc::new(Interface::new(self, id));
+        let node = Arc::new(Node {
+            ifc: ifc.clone(),
+        });
+        self.nodes.write().unwrap().insert(node.id(), node);
+        self.touch();
+        ifc
+    }
+    /// Pick an id for a node to be added with `GraphOp::AddNode`.
+    pub fn reserve_id(&self) -> NodeId {
+        NodeId(self.generate_id())
+    }
+    /// Delete a node by id.
+    pub fn remove_node(&self, node: NodeId) -> Result<Arc<Node>, Error> {
+        let node = self
+            .nodes
+            .write()
+            .unwrap()
+            .remove(&node)
+            .ok_or(Error::InvalidNode)?;
+        self.touch();
+        self.apply_solo();
+        Ok(node)
+    }
+    /// Get a handle to the group of nodes tagged `name`. Groups exist implicitly while any node
+    /// carries the tag.
+    pub fn group(self: &Arc<Graph>, name: &str) -> Group {
+        Group {
+            graph: self.clone(),
+            name: name.into(),
+        }
+    }
+    /// The names of all groups with at least one node.
+    pub fn groups(&self) -> BTreeSet<String> {
+        self.nodes().iter().flat_map(|node| node.tags()).collect()
+    }
+    /// A counter which changes whenever nodes or ports are added or removed, or ports are connected
+    /// or disconnected.
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+    fn touch(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+    pub fn state(&self) -> RunState {
+        self.lifecycle.lock().unwrap().state
+    }
+    /// Set what `stop` does with buffered data. Defaults to `BufferPolicy::Flush`.
+    pub fn set_buffer_policy(&self, policy: BufferPolicy) {
+        self.lifecycle.lock().unwrap().policy = policy;
+    }
+    /// Whether nodes whose output nobody is listening to are skipped, and if so how many blocks
+    /// they're run for without being heard when they're needed again, to settle their state.
+    pub fn lazy(&self) -> Option<usize> {
+        self.lifecycle.lock().unwrap().lazy
+    }
+    /// Put nodes nobody is listening to to sleep, with `preroll` blocks of warm up when they
+    /// wake, or pass None to always run everything. See `BlockScheduler`.
+    pub fn set_lazy(&self, preroll: Option<usize>) {
+        self.lifecycle.lock().unwrap().lazy = preroll;
+    }
+    /// How many buffers control rate blocks hold their outputs for. See `Signal::Control`.
+    pub fn control_division(&self) -> usize {
+        self.control_division.load(Ordering::Relaxed)
+    }
+    /// Run control rate blocks every `division` buffers, at least every one.
+    pub fn set_control_division(&self, division: usize) {
+        self.control_division.store(division.max(1), Ordering::Relaxed);
+    }
+    /// How many threads block schedulers run the graph on, and which cores they use.
+    pub fn scheduler_config(&self) -> Arc<SchedulerConfig> {
+        self.scheduler_config.lock().unwrap().clone()
+    }
+    /// Takes effect on the next buffer, on the audio thread. See `workers`.
+    pub fn set_scheduler_config(&self, config: SchedulerConfig) {
+        *self.scheduler_config.lock().unwrap() = Arc::new(config);
+    }
+    pub fn workers(&self) -> usize {
+        self.scheduler_config().workers
+    }
+    /// Split the graph into branches run on up to `workers` threads, or keep it on the audio
+    /// thread alone with 1, the default. See `partition`.
+    pub fn set_workers(&self, workers: usize) {
+        let config = (*self.scheduler_config()).clone().with_workers(workers);
+        self.set_scheduler_config(config);
+    }
+    /// Let nodes process again after a `pause` or `stop`. Graphs are created running.
+    pub fn start(&self) {
+        let waiting = {
+            let mut lifecycle = self.lifecycle.lock().unwrap();
+            lifecycle.state = RunState::Running;
+            lifecycle.waiting.drain(..).collect::<Vec<_>>()
+        };
+        for waker in waiting {
+            waker.wake();
+        }
+    }
+    /// Hold every node at its next block boundary. Blocks already underway are finished.
+    pub fn pause(&self) {
+        self.lifecycle.lock().unwrap().state = RunState::Paused;
+    }
+    /// Like `pause`, but also flushes port buffers if the buffer policy says so.
+    pub fn stop(&self) {
+        let policy = {
+            let mut lifecycle = self.lifecycle.lock().unwrap();
+            lifecycle.state = RunState::Stopped;
+            lifecycle.policy
+        };
+        if policy == BufferPolicy::Flush {
+            for node in self.nodes() {
+                for port in node.ports() {
+                    port.flush();
+                }
+            }
+            // schedulers keep frames between blocks for feedback, which recompiling clears
+            self.touch();
+        }
+    }
+    /// Returns a `Future` which completes once the graph is running. Nodes wait on this at the
+    /// start of every block.
+    pub fn block_boundary(self: &Arc<Graph>) -> BlockBoundary {
+        BlockBoundary {
+            graph: self.clone(),
+        }
+    }
+    /// Snapshot the current state of every node as the scene `name`, replacing any scene with that
+    /// name.
+    pub fn save_scene(&self, name: &str) -> Scene {
+        let scene = Scene::capture(name, self);
+        self.insert_scene(scene.clone());
+        scene
+    }
+    pub fn insert_scene(&self, scene: Scene) {
+        self.scenes.lock().unwrap().insert(scene.name.clone(), scene);
+    }
+    pub fn remove_scene(&self, name: &str) -> Option<Scene> {
+        self.scenes.lock().unwrap().remove(name)
+    }
+    pub fn scene(&self, name: &str) -> Option<Scene> {
+        self.scenes.lock().unwrap().get(name).cloned()
+    }
+    /// All saved scenes, by name.
+    pub fn scenes(&self) -> Vec<Scene> {
+        self.scenes.lock().unwrap().values().cloned().collect()
+    }
+    /// Move to the scene `name` over `time`. See `Scene::recall`.
+    pub fn recall_scene(self: &Arc<Graph>, name: &str, time: Duration) -> Result<(), Error> {
+        self.scene(name).ok_or(Error::InvalidScene)?.recall(self, time);
+        Ok(())
+    }
+    /// The A/B slots. See `compare`.
+    pub fn compare(&self) -> Compare {
+        self.compare.lock().unwrap().clone()
+    }
+    /// Snapshot every node into `slot`, which becomes the one edits belong to.
+    pub fn store_compare(&self, slot: Slot) {
+        let mut compare = self.compare.lock().unwrap();
+        compare.set_slot(slot, Scene::capture(&format!("{:?}", slot), self));
+        compare.active = Some(slot);
+    }
+    /// Fade over to `slot`, storing the patch into the slot selected before.
+    pub fn select_compare(self: &Arc<Graph>, slot: Slot) -> Result<(), Error> {
+        let scene = {
+            let mut compare = self.compare.lock().unwrap();
+            let scene = compare.slot(slot).cloned().ok_or(Error::InvalidScene)?;
+            if let Some(active) = compare.active {
+                if active != slot {
+                    compare.set_slot(active, Scene::capture(&format!("{:?}", active), self));
+                }
+            }
+            compare.active = Some(slot);
+            scene
+        };
+        scene.recall(self, compare::FADE);
+        Ok(())
+    }
+    /// Fade over to the slot not selected, and give it. Both slots have to be stored.
+    pub fn toggle_compare(self: &Arc<Graph>) -> Result<Slot, Error> {
+        let slot = {
+            let compare = self.compare.lock().unwrap();
+            if compare.a.is_none() || compare.b.is_none() {
+                return Err(Error::InvalidScene);
+            }
+            compare.active.map_or(Slot::A, Slot::other)
+        };
+        self.select_compare(slot)?;
+        Ok(slot)
+    }
+    /// The params set differently in the two slots. Both slots have to be stored.
+    pub fn compare_differences(&self) -> Result<Vec<Difference>, Error> {
+        let compare = self.compare.lock().unwrap();
+        match (compare.a.as_ref(), compare.b.as_ref()) {
+            (Some(a), Some(b)) => Ok(compare::differences(a, b)),
+            _ => Err(Error::InvalidScene),
+        }
+    }
+    /// The nodes soloed, and how soloing another one affects them. See `solo`.
+    pub fn solo(&self) -> Solo {
+        self.solo.lock().unwrap().clone()
+    }
+    /// Solo the node `id`, silencing the other sources and channels, or release it.
+    pub fn set_solo(&self, id: NodeId, solo: bool) -> Result<(), Error> {
+        self.node(id).ok_or(Error::InvalidNode)?;
+        self.solo.lock().unwrap().set(id, solo);
+        self.apply_solo();
+        Ok(())
+    }
+    pub fn set_solo_mode(&self, mode: SoloMode) {
+        self.solo.lock().unwrap().set_mode(mode);
+        self.apply_solo();
+    }
+    /// Release every solo.
+    pub fn clear_solo(&self) {
+        self.solo.lock().unwrap().soloed.clear();
+        self.apply_solo();
+    }
+    /// Update which nodes the solo silences, after it or the nodes taking part changed. Soloed
+    /// nodes which were removed don't count, but stay soloed in case they come back, like a
+    /// restarted module.
+    fn apply_solo(&self) {
+        let nodes = self.nodes();
+        let mut solo = self.solo();
+        solo.soloed.retain(|id| nodes.iter().any(|node| node.id() == *id));
+        for node in nodes {
+            node.ifc.solo_muted.store(solo.silences(&node), Ordering::Relaxed);
+        }
+    }
+    /// The controls mapped to params. See `mapping`.
+    pub fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.lock().unwrap().mappings.clone()
+    }
+    pub fn set_mappings(&self, mappings: Vec<Mapping>) {
+        self.mappings.lock().unwrap().mappings = mappings;
+    }
+    /// Map the next control to move to `target`.
+    pub fn learn(&self, target: Target) {
+        self.mappings.lock().unwrap().learning = Some(target);
+    }
+    /// The param waiting for a control, if `learn` hasn't found one yet.
+    pub fn learning(&self) -> Option<Target> {
+        self.mappings.lock().unwrap().learning.clone()
+    }
+    pub fn cancel_learn(&self) {
+        self.mappings.lock().unwrap().learning = None;
+    }
+    /// Remove the mappings to a param.
+    pub fn unmap(&self, node: NodeId, param: &str) {
+        self.mappings.lock().unwrap().unmap(node, param);
+    }
+    /// Set the params `control` is mapped to for its new value `x`, in `0.0..=1.0`.
+    pub fn control(&self, control: &Control, x: f32) {
+        let changes = self.mappings.lock().unwrap().control(control, x);
+        self.apply_params(&changes);
+    }
+    /// Move the params of the nodes in `scope` by `amount`, in `0.0..=1.0`, of the way to random
+    /// values of their ranges, and give the new values. See `randomize`.
+    pub fn randomize(&self, scope: &Scope, amount: f32) -> Vec<(NodeId, String, f32)> {
+        let candidates = randomize::candidates(self, scope);
+        let changes = randomize::randomize(&candidates, amount, &mut self.rng.lock().unwrap());
+        self.apply_params(&changes);
+        changes
+    }
+    /// Nudge a few params of the nodes in `scope`, and give their new values.
+    pub fn mutate(&self, scope: &Scope) -> Vec<(NodeId, String, f32)> {
+        let candidates = randomize::candidates(self, scope);
+        let changes = randomize::mutate(&candidates, &mut self.rng.lock().unwrap());
+        self.apply_params(&changes);
+        changes
+    }
+    fn apply_params(&self, changes: &[(NodeId, String, f32)]) {
+        for &(id, ref param, value) in changes {
+            if let Some(params) = self.node(id).and_then(|node| node.params()) {
+                params.set(param, value);
+            }
+        }
+    }
+    /// The active tuning. Twelve tone equal temperament unless something set another.
+    pub fn tuning(&self) -> Arc<Tuning> {
+        self.tuning.lock().unwrap().clone()
+    }
+    pub fn set_tuning(&self, tuning: Arc<Tuning>) {
+        *self.tuning.lock().unwrap() = tuning;
+    }
+    /// The active tempo map. 120 BPM in 4/4 unless something set another.
+    pub fn tempo_map(&self) -> Arc<TempoMap> {
+        self.tempo_map.lock().unwrap().clone()
+    }
+    pub fn set_tempo_map(&self, map: Arc<TempoMap>) {
+        *self.tempo_map.lock().unwrap() = map;
+    }
+    /// Stop the scene transition in progress, returning the breaker for a new one.
+    pub fn begin_transition(&self) -> Breaker {
+        let mut transition = self.transition.lock().unwrap();
+        transition.brake();
+        *transition = Breaker::new();
+        transition.clone()
+    }
+    /// Returns a vector containing references to all nodes active at the time of the call.
+    pub fn nodes(&self) -> Vec<Arc<Node>> {
+        self.nodes.read().unwrap().values().cloned().collect()
+    }
+    /// Returns a hash map from id to node references for all nodes active at the time of the call.
+    pub fn node_map(&self) -> HashMap<NodeId, Arc<Node>> {
+        self.nodes.read().unwrap().clone()
+    }
+    /// Get a node by id.
+    pub fn node(&self, id: NodeId) -> Option<Arc<Node>> {
+        self.nodes.read().unwrap().get(&id).cloned()
+    }
+    /// Find a port by node and port id.
+    pub fn port(&self, node: NodeId, port: PortId) -> Option<Arc<OpaquePort>> {
+        self.node(node)?.ports().into_iter().find(|p| p.id() == port)
+    }
+    /// A consistent copy of the nodes, ports, connections, params and stats, for drawing the graph
+    /// without seeing half an edit. See `snapshot`.
+    pub fn snapshot(&self) -> GraphSnapshot {
+        GraphSnapshot::capture(self)
+    }
+    /// Check a batch of edits and apply all of them, or none if any would fail. Ops are checked in
+    /// order against the graph as the earlier ops leave it, so a batch can add a node and connect
+    /// its ports. While the batch is applied no block starts, schedulers keep their previous plan
+    /// and snapshots wait, so nothing sees the graph half rewired.
+    pub fn apply(self: &Arc<Graph>, mut ops: Vec<GraphOp>) -> Result<(), BatchError> {
+        // added nodes are built first, so later ops can refer to their ports
+        let built: Vec<Option<Arc<Interface>>> = ops
+            .iter_mut()
+            .map(|op| match *op {
+                GraphOp::AddNode(id, ref mut build) => {
+                    let ifc = Arc::new(Interface::new(self, id));
+                    build(ifc.clone());
+                    Some(ifc)
+                }
+                _ => None,
+            })
+            .collect();
+
+        let edits = self.edits.write().unwrap();
+        self.check_batch(&ops, &built)?;
+        for (op, ifc) in ops.into_iter().zip(built) {
+            // checked above, so these only fail if something edits the graph outside a batch
+            match op {
+                GraphOp::AddNode(id, _) => {
+                    self.constrain_min_id(id.0 + 1);
+                    let node = Arc::new(Node {
+                        ifc: ifc.unwrap(),
+                    });
+                    self.nodes.write().unwrap().insert(id, node);
+                    self.touch();
+                }
+                GraphOp::RemoveNode(id) => {
+                    if let Ok(node) = self.remove_node(id) {
+                        for port in node.ports() {
+                            let _ = port.disconnect();
+                        }
+                    }
+                }
+                GraphOp::Connect(a, b, options) => {
+                    if let (Some(a), Some(b)) = (self.port(a.node, a.port), self.port(b.node, b.port)) {
+                        let _ = a.connect_with(&b, options);
+                    }
+                }
+                GraphOp::Disconnect(port) => {
+                    if let Some(port) = self.port(port.node, port.port) {
+                        let _ = port.disconnect();
+                    }
+                }
+                GraphOp::SetParam(id, name, value) => {
+                    if let Some(params) = self.node(id).and_then(|node| node.params()) {
+                        params.set(&name, value);
+                    }
+                }
+                GraphOp::SetGain(port, gain) => {
+                    if let Some(port) = self.port(port.node, port.port) {
+                        let _ = port.set_gain(gain);
+                    }
+                }
+            }
+        }
+
+        // release the blocks held at their boundary, see `BlockBoundary`
+        let waiting = {
+            let mut lifecycle = self.lifecycle.lock().unwrap();
+            drop(edits);
+            lifecycle.waiting.drain(..).collect::<Vec<_>>()
+        };
+        for waker in waiting {
+            waker.wake();
+        }
+        Ok(())
+    }
+    /// Check that every op of a batch can be applied after the ones before it, tracking the nodes
+    /// and connections the batch would leave.
+    fn check_batch(&self, ops: &[GraphOp], built: &[Option<Arc<Interface>>]) -> Result<(), BatchError> {
+        fn find(nodes: &HashMap<NodeId, Arc<Interface>>, port: PortRef) -> Option<Arc<OpaquePort>> {
+            nodes
+                .get(&port.node)?
+                .ports()
+                .into_iter()
+                .find(|p| p.id() == port.port)
+        }
+        fn edge(edges: &HashMap<PortRef, Option<PortRef>>, port: &Arc<OpaquePort>) -> Option<PortRef> {
+            match edges.get(&port.port_ref()) {
+                Some(&edge) => edge,
+                None => port.edge().map(|other| other.port_ref()),
+            }
+        }
+
+        let mut nodes: HashMap<NodeId, Arc<Interface>> = self
+            .nodes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(&id, node)| (id, node.ifc.clone()))
+            .collect();
+        // connections changed by the batch so far
+        let mut edges: HashMap<PortRef, Option<PortRef>> = HashMap::new();
+        for (idx, op) in ops.iter().enumerate() {
+            match *op {
+                GraphOp::AddNode(id, _) => {
+                    if nodes.contains_key(&id) {
+                        return Err(BatchError::DuplicateNode(idx));
+                    }
+                    nodes.insert(id, built[idx].clone().unwrap());
+                }
+                GraphOp::RemoveNode(id) => {
+                    let ifc = nodes.remove(&id).ok_or(BatchError::InvalidNode(idx))?;
+                    for port in ifc.ports() {
+                        if let Some(other) = edge(&edges, &port) {
+                            edges.insert(other, None);
+                        }
+                        edges.insert(port.port_ref(), None);
+                    }
+                }
+                GraphOp::Connect(a, b, _) => {
+                    let port_a = find(&nodes, a).ok_or(BatchError::InvalidPort(idx))?;
+                    let port_b = find(&nodes, b).ok_or(BatchError::InvalidPort(idx))?;
+                    let (meta_a, meta_b) = (port_a.meta(), port_b.meta());
+                    let error = if !port_a.can_connect(&port_b) {
+                        Some(ConnectError::TypeMismatch)
+                    } else if !channels_match(&meta_a, &meta_b) {
+                        Some(ConnectError::FormatMismatch(
+                            meta_a.channels.unwrap(),
+                            meta_b.channels.unwrap(),
+                        ))
+                    } else if edge(&edges, &port_a).is_some() || edge(&edges, &port_b).is_some() {
+                        Some(ConnectError::AlreadyConnected)
+                    } else {
+                        None
+                    };
+                    if let Some(error) = error {
+                        return Err(BatchError::Connect(idx, error));
+                    }
+                    edges.insert(a, Some(b));
+                    edges.insert(b, Some(a));
+                }
+                GraphOp::Disconnect(port) => {
+                    let port = find(&nodes, port).ok_or(BatchError::InvalidPort(idx))?;
+                    let other =
+                        edge(&edges, &port).ok_or(BatchError::Connect(idx, ConnectError::NotConnected))?;
+                    edges.insert(port.port_ref(), None);
+                    edges.insert(other, None);
+                }
+                GraphOp::SetParam(id, ref name, _) => {
+                    let ifc = nodes.get(&id).ok_or(BatchError::InvalidNode(idx))?;
+                    if !ifc.params().map_or(false, |params| params.names().contains(name)) {
+                        return Err(BatchError::InvalidParam(idx));
+                    }
+                }
+                GraphOp::SetGain(port, _) => {
+                    let port = find(&nodes, port).ok_or(BatchError::InvalidPort(idx))?;
+                    if !port.carries_audio() {
+                        return Err(BatchError::Connect(idx, ConnectError::TypeMismatch));
+                    }
+                    edge(&edges, &port).ok_or(BatchError::Connect(idx, ConnectError::NotConnected))?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Hold off batches of edits while the guard is alive, to read the graph between them.
+    pub fn settled(&self) -> RwLockReadGuard<()> {
+        self.edits.read().unwrap()
+    }
+    /// Like `settled`, but returns None instead of waiting while a batch is being applied.
+    pub fn try_settled(&self) -> Option<RwLockReadGuard<()>> {
+        self.edits.try_read().ok()
+    }
+    /// Lint the graph, returning a list of problems found. An empty list means the graph looks
+    /// ready to run.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        self.validate_with(&[])
+    }
+    /// Like `validate`, additionally checking that each of the `pending` connections could be made.
+    pub fn validate_with(&self, pending: &[(PortRef, PortRef)]) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut nodes = self.nodes();
+        nodes.sort_by_key(|node| node.id());
+
+        // directed adjacency, ignoring edges that pass through a feedback delay
+        let mut successors: BTreeMap<NodeId, Vec<NodeId>> = BTreeMap::new();
+        let mut any_edges = false;
+        for node in &nodes {
+            let mut connected = false;
+            for port in node.ports() {
+                let meta = port.meta();
+                let other = match port.edge() {
+                    Some(other) => other,
+                    None => {
+                        if meta.required {
+                            diagnostics.push(Diagnostic::DanglingInput(port.port_ref()));
+                        }
+                        continue;
+                    }
+                };
+                connected = true;
+                any_edges = true;
+                let other_meta = other.meta();
+                if let (Some(rate), Some(other_rate)) = (meta.sample_rate, other_meta.sample_rate) {
+                    // report each mismatched connection once
+                    if rate != other_rate && port.port_ref() < other.port_ref() {
+                        diagnostics.push(Diagnostic::SampleRateMismatch(
+                            (port.port_ref(), rate),
+                            (other.port_ref(), other_rate),
+                        ));
+                    }
+                }
+                if meta.direction == Some(Direction::Output)
+                    && other_meta.direction != Some(Direction::Output)
+                    && !meta.feedback_delay
+                    && !other_meta.feedback_delay
+                {
+                    successors.entry(node.id()).or_default().push(other.node_id());
+                }
+            }
+            if !connected {
+                diagnostics.push(Diagnostic::Unreachable(node.id()));
+            }
+        }
+        if !any_edges {
+            // a graph without any connections hasn't been patched yet, so isolation is expected
+            diagnostics.retain(|d| match d {
+                Diagnostic::Unreachable(_) => false,
+                _ => true,
+            });
+        }
+
+        let ids: Vec<_> = nodes.iter().map(|node| node.id()).collect();
+        for cycle in find_cycles(&ids, &successors) {
+            diagnostics.push(Diagnostic::Cycle(cycle));
+        }
+
+        for &(a, b) in pending {
+            match (self.port(a.node, a.port), self.port(b.node, b.port)) {
+                (Some(port_a), Some(port_b)) => {
+                    if !port_a.can_connect(&port_b) {
+                        diagnostics.push(Diagnostic::TypeMismatch(a, b));
+                    } else if !channels_match(&port_a.meta(), &port_b.meta()) {
+                        diagnostics.push(Diagnostic::FormatMismatch(a, b));
+                    }
+                }
+                (None, _) => diagnostics.push(Diagnostic::InvalidPort(a)),
+                (_, None) => diagnostics.push(Diagnostic::InvalidPort(b)),
+            }
+        }
+
+        diagnostics
+    }
+
+    fn generate_id(&self) -> usize {
+        self.id_counter.fetch_add(1, Ordering::SeqCst)
+    }
+    fn constrain_min_id(&self, min: usize) -> usize {
+        self.id_counter.fetch_max(min, Ordering::SeqCst)
+    }
+}
+
+/// Find the cycles in a directed graph, returning the nodes of each cycle in order.
+fn find_cycles(nodes: &[NodeId], successors: &BTreeMap<NodeId, Vec<NodeId>>) -> Vec<Vec<NodeId>> {
+    #[derive(Copy, Clone, PartialEq)]
+    enum Mark {
+        Unvisited,
+        Active,
+        Done,
+    }
+    fn visit(
+        node: NodeId,
+        successors: &BTreeMap<NodeId, Vec<NodeId>>,
+        marks: &mut HashMap<NodeId, Mark>,
+        stack: &mut Vec<NodeId>,
+        cycles: &mut Vec<Vec<NodeId>>,
+    ) {
+        marks.insert(node, Mark::Active);
+        stack.push(node);
+        for &next in successors.get(&node).map(|s| &s[..]).unwrap_or(&[]) {
+            match marks.get(&next).cloned().unwrap_or(Mark::Unvisited) {
+                Mark::Unvisited => visit(next, successors, marks, stack, cycles),
+                Mark::Active => {
+                    let start = stack.iter().position(|&n| n == next).unwrap();
+                    cycles.push(stack[start..].to_vec());
+                }
+                Mark::Done => {}
+            }
+        }
+        stack.pop();
+        marks.insert(node, Mark::Done);
+    }
+
+    let mut marks = HashMap::new();
+    let mut cycles = Vec::new();
+    for &node in nodes {
+        if marks.get(&node).cloned().unwrap_or(Mark::Unvisited) == Mark::Unvisited {
+            visit(node, successors, &mut marks, &mut Vec::new(), &mut cycles);
+        }
+    }
+    cycles
+}
+
+pub struct BlockBoundary {
+    graph: Arc<Graph>,
+}
+
+impl Future for BlockBoundary {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut lifecycle = self.graph.lifecycle.lock().unwrap();
+        // a batch of edits being applied holds blocks back too, and wakes them when it's done
+        if lifecycle.state == RunState::Running && self.graph.try_settled().is_some() {
+            Poll::Ready(())
+        } else {
+            lifecycle.waiting.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// A node is the public interface for generic functionality on a module in the graph.
+/// It holds a `Module`.
+pub struct Node {
+    ifc: Arc<Interface>,
+}
+
+impl Node {
+    /// Get the node ID.
+    pub fn id(&self) -> NodeId {
+        self.ifc.id()
+    }
+    /// Get the ID of the graph this node belongs to.
+    pub fn graph_id(&self) -> GraphId {
+        self.ifc.graph_id()
+    }
+    /// Find a port by name
+    pub fn find_port(&self, name: &'static str) -> Option<Arc<OpaquePort>> {
+        self.ifc.ports().into_iter().find(|port| port.name() == name)
+    }
+    /// Get a vector of references to all associated ports at the time of the call.
+    pub fn ports(&self) -> Vec<Arc<OpaquePort>> {
+        self.ifc.ports()
+    }
+    /// Get the processing latency declared by the module, in samples.
+    pub fn latency(&self) -> usize {
+        self.ifc.latency()
+    }
+    /// Cancel the pending reads and writes of every port. See `Port::abort_pending`.
+    pub fn abort_pending(&self) {
+        self.ifc.abort_pending()
+    }
+    /// Shut the node down gracefully. See `Interface::shutdown`.
+    pub fn shutdown(&self, drain: bool) {
+        self.ifc.shutdown(drain)
+    }
+    /// Get the block processor registered by the module, if it supports block scheduling.
+    pub fn block(&self) -> Option<BlockNode> {
+        self.ifc.block()
+    }
+    /// Get the user defined tags of this node.
+    pub fn tags(&self) -> BTreeSet<String> {
+        self.ifc.tags.read().unwrap().clone()
+    }
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.ifc.tags.read().unwrap().contains(tag)
+    }
+    pub fn add_tag(&self, tag: &str) {
+        self.ifc.tags.write().unwrap().insert(tag.into());
+        self.retag(tag);
+    }
+    pub fn remove_tag(&self, tag: &str) {
+        self.ifc.tags.write().unwrap().remove(tag);
+        self.retag(tag);
+    }
+    /// Bring the solo up to date when the node joins or leaves the nodes taking part in it.
+    fn retag(&self, tag: &str) {
+        if let (true, Some(graph)) = (solo::TAGS.contains(&tag), self.ifc.graph.upgrade()) {
+            graph.apply_solo();
+        }
+    }
+    /// Get whether the node was muted. A solo elsewhere can silence it too, see `silenced`.
+    pub fn muted(&self) -> bool {
+        self.ifc.muted.load(Ordering::Relaxed)
+    }
+    /// Get whether a solo of other nodes silences this one. See `solo`.
+    pub fn solo_muted(&self) -> bool {
+        self.ifc.solo_muted.load(Ordering::Relaxed)
+    }
+    /// Get whether the node's outputs are silenced, by muting it or a solo. See
+    /// `Interface::muted`.
+    pub fn silenced(&self) -> bool {
+        self.ifc.muted()
+    }
+    pub fn set_muted(&self, muted: bool) {
+        self.ifc.muted.store(muted, Ordering::Relaxed);
+    }
+    /// Get whether the node is bypassed. See `Interface::bypassed`.
+    pub fn bypassed(&self) -> bool {
+        self.ifc.bypassed()
+    }
+    pub fn set_bypassed(&self, bypassed: bool) {
+        self.ifc.bypassed.store(bypassed, Ordering::Relaxed);
+    }
+    /// Get whether the node is processing. See `Interface::active`.
+    pub fn active(&self) -> bool {
+        self.ifc.active()
+    }
+    pub fn set_active(&self, active: bool) {
+        self.ifc.active.store(active, Ordering::Relaxed);
+    }
+    /// Get the total time the node has spent processing blocks, as counted by its module or the
+    /// block scheduler. Sampled twice, it gives the node's share of the CPU in between.
+    pub fn busy(&self) -> Duration {
+        let nanos = self.ifc.busy.load(Ordering::Relaxed) as u64;
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+    /// Count time spent processing a block, for schedulers running the node's block processor.
+    pub fn add_busy(&self, elapsed: Duration) {
+        self.ifc.add_busy(elapsed)
+    }
+    /// Get the gain applied to the node's outputs. See `Interface::level`.
+    pub fn level(&self) -> f32 {
+        self.ifc.level()
+    }
+    pub fn set_level(&self, level: f32) {
+        self.ifc.level.store(level.to_bits() as usize, Ordering::Relaxed);
+    }
+    /// Get the parameters registered by the module, if any.
+    pub fn params(&self) -> Option<Arc<dyn Params>> {
+        self.ifc.params()
+    }
+    /// Get a metadata entry, if it's set and has the expected type.
+    pub fn meta<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.ifc.meta.read().unwrap().get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+    /// Store a value under `key` for frontends and other tools, such as `"ui.pos"`. Metadata is
+    /// saved with the patch but otherwise ignored by the graph. Values that can't be represented
+    /// as JSON are ignored.
+    pub fn set_meta<T: Serialize>(&self, key: &str, value: T) {
+        if let Ok(value) = serde_json::to_value(value) {
+            self.ifc.meta.write().unwrap().insert(key.into(), value.clone());
+            self.notify_meta(key, Some(value));
+        }
+    }
+    /// Keep the param `name` out of randomizations and mutations, or let it back in. Locks are
+    /// kept in the metadata entry `"params.locked"`, so they're saved with the patch.
+    pub fn lock_param(&self, name: &str, locked: bool) {
+        let mut params = self.locked_params();
+        params.retain(|param| param != name);
+        if locked {
+            params.push(name.into());
+        }
+        if params.is_empty() {
+            self.remove_meta(LOCKED_PARAMS);
+        } else {
+            self.set_meta(LOCKED_PARAMS, params);
+        }
+    }
+    pub fn param_locked(&self, name: &str) -> bool {
+        self.locked_params().iter().any(|param| param == name)
+    }
+    pub fn locked_params(&self) -> Vec<String> {
+        self.meta(LOCKED_PARAMS).unwrap_or_default()
+    }
+    pub fn remove_meta(&self, key: &str) {
+        if self.ifc.meta.write().unwrap().remove(key).is_some() {
+            self.notify_meta(key, None);
+        }
+    }
+    /// Get every metadata entry, e.g. for saving.
+    pub fn meta_map(&self) -> BTreeMap<String, Value> {
+        self.ifc.meta.read().unwrap().clone()
+    }
+    /// Get a stream of changes to the metadata.
+    pub fn meta_changes(&self) -> UnboundedReceiver<MetaChange> {
+        let (tx, rx) = mpsc::unbounded();
+        self.ifc.meta_subscribers.lock().unwrap().push(tx);
+        rx
+    }
+    fn notify_meta(&self, key: &str, value: Option<Value>) {
+        let change = MetaChange {
+            key: key.into(),
+            value,
+        };
+        self.ifc
+            .meta_subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.unbounded_send(change.clone()).is_ok());
+    }
+}
+
+/// Sent to `Node::meta_changes` subscribers when an entry is set, or removed if `value` is None.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetaChange {
+    pub key: String,
+    pub value: Option<Value>,
+}
+
+/// A named set of nodes, defined by a shared tag, which can be controlled together.
+pub struct Group {
+    graph: Arc<Graph>,
+    name: String,
+}
+
+impl Group {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// The nodes currently in the group.
+    pub fn nodes(&self) -> Vec<Arc<Node>> {
+        let mut nodes: Vec<_> = self
+            .graph
+            .nodes()
+            .into_iter()
+            .filter(|node| node.has_tag(&self.name))
+            .collect();
+        nodes.sort_by_key(|node| node.id());
+        nodes
+    }
+    pub fn add(&self, node: NodeId) -> Result<(), Error> {
+        self.graph
+            .node(node)
+            .ok_or(Error::InvalidNode)?
+            .add_tag(&self.name);
+        Ok(())
+    }
+    pub fn remove(&self, node: NodeId) -> Result<(), Error> {
+        self.graph
+            .node(node)
+            .ok_or(Error::InvalidNode)?
+            .remove_tag(&self.name);
+        Ok(())
+    }
+    pub fn set_muted(&self, muted: bool) {
+        for node in self.nodes() {
+            node.set_muted(muted);
+        }
+    }
+    pub fn set_bypassed(&self, bypassed: bool) {
+        for node in self.nodes() {
+            node.set_bypassed(bypassed);
+        }
+    }
+    /// Start or stop processing in every node of the group.
+    pub fn set_active(&self, active: bool) {
+        for node in self.nodes() {
+            node.set_active(active);
+        }
+    }
+}
+
+/// The private interface for a module. The module is provided with an `Interface` upon construction.
+/// An `Interface` has a superset of the functionality of a `Node`. It can be used to manipulate the
+/// associated Ports.
+pub struct Interface {
+    id: NodeId,
+    ports: RwLock<BTreeMap<PortId, Arc<OpaquePort>>>,
+    graph: Weak<Graph>,
+    graph_id: GraphId,
+    latency: AtomicUsize,
+    block: Mutex<Option<BlockNode>>,
+    tags: RwLock<BTreeSet<String>>,
+    muted: AtomicBool,
+    /// Set while a solo of other nodes silences this one.
+    solo_muted: AtomicBool,
+    bypassed: AtomicBool,
+    active: AtomicBool,
+    /// The bits of an `f32` gain.
+    level: AtomicUsize,
+    /// Nanoseconds spent processing blocks.
+    busy: AtomicUsize,
+    params: Mutex<Option<Arc<dyn Params>>>,
+    meta: RwLock<BTreeMap<String, Value>>,
+    meta_subscribers: Mutex<Vec<UnboundedSender<MetaChange>>>,
+}
+
+impl Interface {
+    fn new(graph: &Arc<Graph>, id: NodeId) -> Interface {
+        Interface {
+            id,
+            ports: RwLock::new(BTreeMap::new()),
+            graph: Arc::downgrade(graph),
+            graph_id: graph.id(),
+            latency: 0.into(),
+            block: Mutex::new(None),
+            tags: RwLock::new(BTreeSet::new()),
+            muted: AtomicBool::new(false),
+            solo_muted: AtomicBool::new(false),
+            bypassed: AtomicBool::new(false),
+            active: AtomicBool::new(true),
+            level: AtomicUsize::new(1.0f32.to_bits() as usize),
+            busy: 0.into(),
+            params: Mutex::new(None),
+            meta: RwLock::new(BTreeMap::new()),
+            meta_subscribers: Mutex::new(Vec::new()),
+        }
+    }
+    /// Get the node ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+    /// Get the graph this node belongs to.
+    pub fn graph(&self) -> Arc<Graph> {
+        self.graph.upgrade().unwrap()
+    }
+    /// Get the ID of the graph this node belongs to.
+    pub fn graph_id(&self) -> GraphId {
+        self.graph_id
+    }
+    /// Get the processing latency declared by the module, in samples.
+    pub fn latency(&self) -> usize {
+        self.latency.load(Ordering::Relaxed)
+    }
+    /// Shut the node down gracefully, usually as its module stops. Its ports stop taking writes,
+    /// and the nodes connected to it get `Error::EndOfStream` once they've read everything it
+    /// wrote, rather than being cut off. With `drain`, whatever is buffered on its ports can still
+    /// be read before they end too. Without it, the buffers are flushed, and pending reads and
+    /// writes are cancelled. See `Port::shutdown`.
+    pub fn shutdown(&self, drain: bool) {
+        for port in self.ports() {
+            port.shutdown(drain);
+        }
+        if !drain {
+            self.abort_pending();
+        }
+    }
+    /// Cancel the pending reads and writes of every port. See `Port::abort_pending`.
+    pub fn abort_pending(&self) {
+        for port in self.ports() {
+            port.abort_pending();
+        }
+    }
+    /// Declare how many samples the module delays data passing from its inputs to its outputs, so
+    /// parallel paths can be compensated.
+    pub fn set_latency(&self, samples: usize) {
+        self.latency.store(samples, Ordering::Relaxed);
+    }
+    /// Whether the module should output silence, because it was muted or another node is soloed.
+    /// It keeps processing, so it can be unmuted without a jump in its internal state.
+    pub fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed) || self.solo_muted.load(Ordering::Relaxed)
+    }
+    /// Whether the module's primary input should be passed straight to its primary output, for
+    /// comparing a signal with and without an effect. Modules without inputs output silence.
+    pub fn bypassed(&self) -> bool {
+        self.bypassed.load(Ordering::Relaxed)
+    }
+    /// Whether the module should process at all. Inactive modules output silence and skip their
+    /// processing to save CPU.
+    pub fn active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+    /// Gain the module should apply to its outputs, for fading it in and out. Normally 1.
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed) as u32)
+    }
+    /// Count time spent processing a block towards the node's CPU use. See `Node::busy`.
+    pub fn add_busy(&self, elapsed: Duration) {
+        let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+        self.busy.fetch_add(nanos as usize, Ordering::Relaxed);
+    }
+    /// Get the registered parameters.
+    pub fn params(&self) -> Option<Arc<dyn Params>> {
+        self.params.lock().unwrap().clone()
+    }
+    /// Expose the module's parameters, so scenes can save and recall them.
+    pub fn set_params(&self, params: Arc<dyn Params>) {
+        *self.params.lock().unwrap() = Some(params);
+    }
+    /// Get the registered block processor.
+    pub fn block(&self) -> Option<BlockNode> {
+        self.block.lock().unwrap().clone()
+    }
+    /// Register a processor that a `BlockScheduler` may call synchronously instead of the module's
+    /// own tasks.
+    pub fn set_block(&self, block: BlockNode) {
+        *self.block.lock().unwrap() = Some(block);
+        if let Some(graph) = self.graph.upgrade() {
+            graph.touch();
+        }
+    }
+    /// Find a port by name and type.
+    pub fn find_port<I: 'static, O: 'static>(&self, name: &str) -> Option<Arc<Port<I, O>>> {
+        self.ports
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|&(_, port)| port.name() == name)
+            .filter_map(|(_, port)| port.as_typed::<I, O>())
+            .next()
+    }
+    /// Get a vector of references to all associated ports at the time of the call.
+    pub fn ports(&self) -> Vec<Arc<OpaquePort>> {
+        self.ports.read().unwrap().values().cloned().collect()
+    }
+    /// Find a port by name and type if it exists, or add a new one if not.
+    pub fn get_or_create_port<I: 'static, O: 'static>(&self, name: String) -> Arc<Port<I, O>> {
+        if let Some(port) = self.find_port(&name) {
+            port
+        } else {
+            let port = Port::new(&self.graph.upgrade().unwrap(), self.id(), name);
+            self.ports
+                .write()
+                .unwrap()
+                .insert(port.id, port.as_opaque());
+            self.graph().touch();
+            port
+        }
+    }
+    /// Find or create a group of ports named `"{name} {channel}"` for each channel, e.g. a stereo
+    /// pair with channels `["L", "R"]`.
+    pub fn get_or_create_group<I: 'static, O: 'static, S: AsRef<str>>(
+        &self,
+        name: &str,
+        channels: &[S],
+    ) -> PortGroup<I, O> {
+        PortGroup {
+            name: name.into(),
+            channels: channels.iter().map(|c| c.as_ref().to_string()).collect(),
+            ports: channels
+                .iter()
+                .map(|c| self.get_or_create_port(format!("{} {}", name, c.as_ref())))
+                .collect(),
+        }
+    }
+    /// Find or create a variadic set of ports named from `template`, where `%d` is replaced by the
+    /// index of each port starting at 1. A new port is added whenever the last one is connected.
+    pub fn get_or_create_variadic<I: 'static, O: 'static>(
+        self: &Arc<Interface>,
+        template: &str,
+    ) -> Variadic<I, O> {
+        let variadic = Variadic {
+            inner: Arc::new(Mutex::new(VariadicInner {
+                template: template.into(),
+                ifc: Arc::downgrade(self),
+                ports: Vec::new(),
+                subscribers: Vec::new(),
+            })),
+        };
+        {
+            let mut inner = variadic.inner.lock().unwrap();
+            // pick up ports created by an earlier call
+            while let Some(port) = self.find_port(&inner.name(inner.ports.len())) {
+                port.set_on_connect(variadic.hook(port.id()));
+                inner.ports.push(port);
+            }
+            let last_connected = inner.ports.last().map(|port| port.edge().is_some());
+            if last_connected.unwrap_or(true) {
+                variadic.grow(&mut inner);
+            }
+        }
+        variadic
+    }
+    /// Remove a port by ID.
+    pub fn remove_port(&self, port: PortId) -> Result<Arc<OpaquePort>, Error> {
+        let port = self
+            .ports
+            .write()
+            .unwrap()
+            .remove(&port)
+            .ok_or(Error::InvalidPort)?;
+        if let Some(graph) = self.graph.upgrade() {
+            graph.touch();
+        }
+        Ok(port)
+    }
+}
+
+/// Ports are the connection points of modules. They can be connected one-to-one with other ports,
+/// allowing data of type `I` to flow in and data of type `O` to flow out.
+///
+/// A port belongs to the graph its node was created in, and can only be connected to ports of the
+/// same graph. Ids are only unique within a graph, so ports of different graphs may share them.
+pub struct Port<I: 'static, O: 'static> {
+    // the data is kept untyped in `inner`, so the port doesn't hold any I or O itself
+    _in: PhantomData<fn() -> I>,
+    _out: PhantomData<fn() -> O>,
+    /// The port itself, to connect it through an `OpaquePort`.
+    this: Weak<Port<I, O>>,
+    in_ty: TypeId,
+    out_ty: TypeId,
+    in_ty_name: &'static str,
+    out_ty_name: &'static str,
+
+    name: String,
+    id: PortId,
+    inner: Lock<PortInner>,
+    edge: Lock<Edge<I, O>>,
+    node_id: NodeId,
+    graph_id: GraphId,
+    meta: RwLock<PortMeta>,
+    generation: Arc<AtomicUsize>,
+    /// Counts calls to `abort_pending`, so reads and writes can tell if they were cancelled.
+    cancelled: AtomicUsize,
+    /// Called after the port is connected.
+    on_connect: Mutex<Option<Box<dyn Fn() + Send>>>,
+    connection_events: Mutex<Vec<UnboundedSender<ConnectionEvent>>>,
+}
+
+/// A bundle of ports of the same type which are patched together, like a stereo pair or a bank of
+/// control voltages.
+pub struct PortGroup<I: 'static, O: 'static> {
+    name: String,
+    channels: Vec<String>,
+    ports: Vec<Arc<Port<I, O>>>,
+}
+
+impl<I: 'static, O: 'static> PortGroup<I, O> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+    /// The ports of the group, in channel order.
+    pub fn ports(&self) -> &[Arc<Port<I, O>>] {
+        &self.ports
+    }
+    /// Find the port for a channel by name.
+    pub fn channel(&self, channel: &str) -> Option<&Arc<Port<I, O>>> {
+        self.channels
+            .iter()
+            .position(|c| c == channel)
+            .map(|idx| &self.ports[idx])
+    }
+    /// Pair up the channels of two groups. Channels are matched by name when both groups have the
+    /// same set of channel names, and by index otherwise.
+    fn pairs<'a>(
+        &'a self,
+        other: &'a PortGroup<O, I>,
+    ) -> Option<Vec<(&'a Arc<Port<I, O>>, &'a Arc<Port<O, I>>)>> {
+        if self.ports.len() != other.ports.len() {
+            return None;
+        }
+        let by_name: Option<Vec<_>> = self
+            .channels
+            .iter()
+            .zip(&self.ports)
+            .map(|(channel, port)| other.channel(channel).map(|other| (port, other)))
+            .collect();
+        Some(by_name.unwrap_or_else(|| self.ports.iter().zip(&other.ports).collect()))
+    }
+    /// Connect every channel of this group to the matching channel of another. Either all channels
+    /// are connected or none are: if any connection fails, the ones already made are undone.
+    /// Fails with ConnectError::ChannelMismatch if the groups have different numbers of channels.
+    pub fn connect(&self, other: &PortGroup<O, I>) -> Result<(), ConnectError> {
+        let pairs = self.pairs(other).ok_or(ConnectError::ChannelMismatch)?;
+        for (idx, &(a, b)) in pairs.iter().enumerate() {
+            if let Err(e) = a.connect(b) {
+                for &(a, _) in &pairs[..idx] {
+                    let _ = a.disconnect();
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+    /// Disconnect every connected channel of the group.
+    pub fn disconnect(&self) {
+        for port in &self.ports {
+            let _ = port.disconnect();
+        }
+    }
+}
+
+/// A set of ports created on demand from a name template, which always has an unconnected port
+/// at the end, like the inputs of a mixer that grows as channels are plugged in.
+pub struct Variadic<I: 'static, O: 'static> {
+    inner: Arc<Mutex<VariadicInner<I, O>>>,
+}
+
+struct VariadicInner<I: 'static, O: 'static> {
+    template: String,
+    ifc: Weak<Interface>,
+    ports: Vec<Arc<Port<I, O>>>,
+    subscribers: Vec<UnboundedSender<Arc<Port<I, O>>>>,
+}
+
+impl<I: 'static, O: 'static> VariadicInner<I, O> {
+    fn name(&self, idx: usize) -> String {
+        self.template.replace("%d", &(idx + 1).to_string())
+    }
+}
+
+impl<I: 'static, O: 'static> Clone for Variadic<I, O> {
+    fn clone(&self) -> Variadic<I, O> {
+        Variadic {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<I: 'static, O: 'static> Variadic<I, O> {
+    /// The ports created so far, in order.
+    pub fn ports(&self) -> Vec<Arc<Port<I, O>>> {
+        self.inner.lock().unwrap().ports.clone()
+    }
+    /// Get a stream of ports as they're created, so the module can start handling them.
+    pub fn subscribe(&self) -> UnboundedReceiver<Arc<Port<I, O>>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.inner.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+    /// Add the next port, if the node still exists.
+    fn grow(&self, inner: &mut VariadicInner<I, O>) {
+        let ifc = match inner.ifc.upgrade() {
+            Some(ifc) => ifc,
+            None => return,
+        };
+        let port = ifc.get_or_create_port::<I, O>(inner.name(inner.ports.len()));
+        port.set_on_connect(self.hook(port.id()));
+        inner.ports.push(port.clone());
+        inner
+            .subscribers
+            .retain(|tx| tx.unbounded_send(port.clone()).is_ok());
+    }
+    /// The connect hook for the port `id`, which grows the set if `id` is the last port. It only
+    /// holds a weak reference, since the ports themselves hold the hook.
+    fn hook(&self, id: PortId) -> Box<dyn Fn() + Send> {
+        let weak = Arc::downgrade(&self.inner);
+        Box::new(move || {
+            if let Some(inner) = weak.upgrade() {
+                let variadic = Variadic { inner };
+                let mut inner = variadic.inner.lock().unwrap();
+                if inner.ports.last().map(|port| port.id()) == Some(id) {
+                    variadic.grow(&mut inner);
+                }
+            }
+        })
+    }
+}
+
+/// Identifies a port within a graph.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PortRef {
+    pub node: NodeId,
+    pub port: PortId,
+}
+
+/// Sent to a port's `connection_events` subscribers, with the port on the other end.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ConnectionEvent {
+    Connected(PortRef),
+    Disconnected(PortRef),
+}
+
+/// Which way the primary data of a port flows.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+/// How often the signal on a port changes.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Signal {
+    /// A sample for every frame of audio, processed a block at a time.
+    Audio,
+    /// Slowly changing values, like envelopes and LFOs or the scalars of params. Blocks whose
+    /// outputs are all control rate only run every `Graph::control_division` buffers, holding
+    /// their outputs in between, and audio inputs reading them get a ramp from the previous value
+    /// to the latest over that time instead, so they don't step.
+    Control,
+    /// Discrete messages, like notes or triggers, arriving at any time.
+    Event,
+}
+
+/// Static information a module declares about a port, used by `Graph::validate` and the audio
+/// processors.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct PortMeta {
+    /// Inferred from the port types when one side only carries `()` requests.
+    pub direction: Option<Direction>,
+    /// The module can't do anything useful while this port is disconnected.
+    pub required: bool,
+    /// Data passing through this port is delayed, so it may safely close a cycle.
+    pub feedback_delay: bool,
+    pub sample_rate: Option<u32>,
+    /// For audio ports, crossfade over this many seconds when the connection changes instead of
+    /// jumping to the new signal. See `declick`.
+    pub ramp: Option<f32>,
+    /// For audio ports, the number of channels in each frame, e.g. 1 for mono or 2 for stereo.
+    /// Ports declaring different counts can't be connected, so mismatched signals are caught
+    /// when patching instead of being misread. Put a converter from flow-synth's
+    /// `module::channels` between them.
+    pub channels: Option<usize>,
+    /// The rate of the signal, if the module declares it. Audio ports without one are taken to
+    /// be audio rate.
+    #[serde(default)]
+    pub signal: Option<Signal>,
+}
+
+/// What a write does when the receiving buffer is full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Overflow {
+    /// Wait until the reader makes room.
+    Block,
+    /// Discard the oldest buffered items to make room.
+    DropOldest,
+    /// Discard the items being written.
+    DropNewest,
+    /// Replace the most recently buffered items, so the latest data always gets through.
+    Overwrite,
+}
+
+impl Default for Overflow {
+    fn default() -> Overflow {
+        Overflow::Block
+    }
+}
+
+/// Buffering behaviour of a connection, applied to both ends.
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ConnectOptions {
+    /// Maximum number of buffered items, or None for unbounded.
+    pub capacity: Option<usize>,
+    pub overflow: Overflow,
+    /// For audio connections, scale every frame passing through, for mixing without a `Gain`
+    /// module in between. Other items pass unchanged. See `Port::set_gain`.
+    #[serde(default)]
+    pub gain: Option<f32>,
+}
+
+struct PortInner {
+    buffer: Buffer,
+    disconnect_occured: bool,
+    /// The writer closed the stream, so the next read fails with `Error::EndOfStream` once the
+    /// buffer runs out. Stays set for ports that were shut down.
+    ended: bool,
+    /// The port was shut down, and writes to it fail with `Error::Closed`.
+    refusing: bool,
+    read_wait: Vec<Waker>,
+    write_wait: Vec<Waker>,
+    options: ConnectOptions,
+    /// Items discarded by the overflow policy.
+    dropped: usize,
+    /// Items written to the buffer over the port's lifetime.
+    received: usize,
+}
+
+impl PortInner {
+    /// Take `n` items, or all of them, if there are enough, with the writers waiting for room.
+    fn take<I: 'static>(&mut self, n: Option<usize>) -> Option<(Box<[I]>, Vec<Waker>)> {
+        let n = match n {
+            Some(n) if self.buffer.len() >= n => n,
+            None if self.buffer.len() > 0 => self.buffer.len(),
+            _ => return None,
+        };
+        let data = self.buffer.pop_front::<I>(n);
+        Some((data, self.write_wait.drain(..).collect()))
+    }
+    /// Whether the stream has ended, for a reader that found too little data. The end is only
+    /// reported once, like a marker in the stream, so readers in a loop wait for the next stream
+    /// after it, unless the port was shut down.
+    fn end_of_stream(&mut self) -> bool {
+        let ended = self.ended;
+        if !self.refusing {
+            self.ended = false;
+        }
+        ended
+    }
+    /// Whether writing `n` items has to wait for the reader to make room. A write bigger than the
+    /// whole buffer is let through once it's empty, or it could never complete.
+    fn blocks(&self, n: usize) -> bool {
+        self.options.overflow == Overflow::Block && self.overflow(n) > 0 && self.buffer.len() > 0
+    }
+    fn overflow(&self, n: usize) -> usize {
+        self.options
+            .capacity
+            .map(|capacity| (self.buffer.len() + n).saturating_sub(capacity))
+            .unwrap_or(0)
+    }
+    /// Add `data` to the buffer, making room by the overflow policy unless it `blocks`, and give
+    /// the readers waiting for it.
+    fn accept<O: 'static>(&mut self, mut data: Items) -> Vec<Waker> {
+        // a write bigger than the whole buffer is cut down to it as well, so the buffer stays in bounds
+        let extra = self
+            .options
+            .capacity
+            .map_or(0, |capacity| data.len().saturating_sub(capacity));
+        let overflow = self.overflow(data.len());
+        if overflow > 0 {
+            // discarded items must still be dropped properly
+            match self.options.overflow {
+                Overflow::Block => {}
+                Overflow::DropNewest if self.buffer.len() > 0 => {
+                    self.dropped += data.len();
+                    mem::replace(&mut data, Items::empty()).discard::<O>();
+                }
+                Overflow::DropNewest | Overflow::Overwrite => {
+                    let excess = overflow.min(self.buffer.len());
+                    drop(self.buffer.pop_back::<O>(excess));
+                    let kept = data.len() - extra;
+                    data.split_off::<O>(kept).discard::<O>();
+                    self.dropped += excess + extra;
+                }
+                Overflow::DropOldest => {
+                    let excess = overflow.min(self.buffer.len());
+                    drop(self.buffer.pop_front::<O>(excess));
+                    let newest = data.split_off::<O>(extra);
+                    mem::replace(&mut data, newest).discard::<O>();
+                    self.dropped += excess + extra;
+                }
+            }
+        }
+        self.received += data.len();
+        self.buffer.push(data);
+        // writing after the stream was closed starts a new one
+        self.ended = false;
+        self.read_wait.drain(..).collect()
+    }
+}
+
+struct Edge<I: 'static, O: 'static> {
+    other: Option<Weak<Port<O, I>>>,
+    connect_wait: Vec<Waker>,
+}
+
+// the `Lock`s are only `Sync` with the `safe-ports` feature
+#[cfg(not(feature = "safe-ports"))]
+unsafe impl<I: 'static, O: 'static> Send for Port<I, O> {}
+#[cfg(not(feature = "safe-ports"))]
+unsafe impl<I: 'static, O: 'static> Sync for Port<I, O> {}
+
+/// An OpaquePort is a port with erased types at the type level. It can be downcast to a typed port
+/// by calling `as_typed`.
+pub type OpaquePort = dyn AnyPort;
+
+/// The methods of a port that don't depend on its types, which is what an `OpaquePort` has. They
+/// are the same as the methods of `Port` with these names.
+pub trait AnyPort: Any + Send + Sync {
+    fn id(&self) -> PortId;
+    fn name(&self) -> &str;
+    fn node_id(&self) -> NodeId;
+    fn graph_id(&self) -> GraphId;
+    fn port_ref(&self) -> PortRef;
+    fn connection_events(&self) -> UnboundedReceiver<ConnectionEvent>;
+    fn meta(&self) -> PortMeta;
+    fn set_meta(&self, meta: PortMeta);
+    fn in_type_name(&self) -> &'static str;
+    fn out_type_name(&self) -> &'static str;
+    fn buffered(&self) -> usize;
+    fn connect_options(&self) -> ConnectOptions;
+    fn gain(&self) -> Option<f32>;
+    fn carries_audio(&self) -> bool;
+    fn set_gain(&self, gain: Option<f32>) -> Result<(), ConnectError>;
+    fn dropped(&self) -> usize;
+    fn received(&self) -> usize;
+    fn flush(&self) -> usize;
+    fn can_connect(&self, other: &Arc<OpaquePort>) -> bool;
+    fn connect(&self, other: &Arc<OpaquePort>) -> Result<(), ConnectError>;
+    fn connect_with(&self, other: &Arc<OpaquePort>, options: ConnectOptions) -> Result<(), ConnectError>;
+    fn disconnect(&self) -> Result<(), ConnectError>;
+    fn edge(&self) -> Option<Arc<OpaquePort>>;
+    fn shutdown(&self, drain: bool);
+    fn close(&self) -> Result<(), ConnectError>;
+    fn abort_pending(&self);
+    /// The port as an `Any`, for `as_typed`.
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+}
+
+impl OpaquePort {
+    /// Downcasts this `OpaquePort` to a port with the given types. Returns None if the given types
+    /// do not match the underlying port.
+    pub fn as_typed<NewI: 'static, NewO: 'static>(self: &Arc<OpaquePort>) -> Option<Arc<Port<NewI, NewO>>> {
+        Arc::clone(self).into_any().downcast().ok()
+    }
+}
+
+impl<I: 'static, O: 'static> AnyPort for Port<I, O> {
+    fn id(&self) -> PortId {
+        Port::id(self)
+    }
+    fn name(&self) -> &str {
+        Port::name(self)
+    }
+    fn node_id(&self) -> NodeId {
+        Port::node_id(self)
+    }
+    fn graph_id(&self) -> GraphId {
+        Port::graph_id(self)
+    }
+    fn port_ref(&self) -> PortRef {
+        Port::port_ref(self)
+    }
+    fn connection_events(&self) -> UnboundedReceiver<ConnectionEvent> {
+        Port::connection_events(self)
+    }
+    fn meta(&self) -> PortMeta {
+        Port::meta(self)
+    }
+    fn set_meta(&self, meta: PortMeta) {
+        Port::set_meta(self, meta)
+    }
+    fn in_type_name(&self) -> &'static str {
+        Port::in_type_name(self)
+    }
+    fn out_type_name(&self) -> &'static str {
+        Port::out_type_name(self)
+    }
+    fn buffered(&self) -> usize {
+        Port::buffered(self)
+    }
+    fn connect_options(&self) -> ConnectOptions {
+        Port::connect_options(self)
+    }
+    fn gain(&self) -> Option<f32> {
+        Port::gain(self)
+    }
+    fn carries_audio(&self) -> bool {
+        Port::carries_audio(self)
+    }
+    fn set_gain(&self, gain: Option<f32>) -> Result<(), ConnectError> {
+        Port::set_gain(self, gain)
+    }
+    fn dropped(&self) -> usize {
+        Port::dropped(self)
+    }
+    fn received(&self) -> usize {
+        Port::received(self)
+    }
+    fn flush(&self) -> usize {
+        Port::flush(self)
+    }
+    fn can_connect(&self, other: &Arc<OpaquePort>) -> bool {
+        other.as_typed::<O, I>().map_or(false, |other| Port::can_connect(self, &other))
+    }
+    fn connect(&self, other: &Arc<OpaquePort>) -> Result<(), ConnectError> {
+        AnyPort::connect_with(self, other, ConnectOptions::default())
+    }
+    fn connect_with(&self, other: &Arc<OpaquePort>, options: ConnectOptions) -> Result<(), ConnectError> {
+        // checked first, like in `Port::connect_with`
+        if self.graph_id != other.graph_id() {
+            return Err(ConnectError::CrossGraph);
+        }
+        let other = other.as_typed::<O, I>().ok_or(ConnectError::TypeMismatch)?;
+        self.arc().connect_with(&other, options)
+    }
+    fn disconnect(&self) -> Result<(), ConnectError> {
+        self.arc().disconnect()
+    }
+    fn edge(&self) -> Option<Arc<OpaquePort>> {
+        Some(Port::edge(self)?)
+    }
+    fn shutdown(&self, drain: bool) {
+        Port::shutdown(self, drain)
+    }
+    fn close(&self) -> Result<(), ConnectError> {
+        Port::close(self)
+    }
+    fn abort_pending(&self) {
+        Port::abort_pending(self)
+    }
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl<I: 'static, O: 'static> Port<I, O> {
+    fn new(graph: &Graph, node_id: NodeId, name: String) -> Arc<Port<I, O>> {
+        Arc::new_cyclic(|this| Port {
+            _in: PhantomData,
+            _out: PhantomData,
+            this: Weak::clone(this),
+            in_ty: TypeId::of::<I>(),
+            out_ty: TypeId::of::<O>(),
+            in_ty_name: any::type_name::<I>(),
+            out_ty_name: any::type_name::<O>(),
+            name,
+            id: PortId(graph.generate_id()),
+            inner: Lock::new(PortInner {
+                buffer: Buffer::new::<I>(),
+                disconnect_occured: false,
+                ended: false,
+                refusing: false,
+                read_wait: Vec::new(),
+                write_wait: Vec::new(),
+                options: ConnectOptions::default(),
+                dropped: 0,
+                received: 0,
+            }),
+            edge: Lock::new(Edge {
+                other: None,
+                connect_wait: Vec::new(),
+            }),
+            node_id,
+            graph_id: graph.id,
+            generation: graph.generation.clone(),
+            cancelled: AtomicUsize::new(0),
+            on_connect: Mutex::new(None),
+            connection_events: Mutex::new(Vec::new()),
+            meta: RwLock::new(PortMeta {
+                direction: if TypeId::of::<I>() == TypeId::of::<()>() {
+                    Some(Direction::Output)
+                } else if TypeId::of::<O>() == TypeId::of::<()>() {
+                    Some(Direction::Input)
+                } else {
+                    None
+                },
+                ..PortMeta::default()
+            }),
+        })
+    }
+
+    /// Erases types from the signature of this port, returning the corresponding OpaquePort.
+    pub fn as_opaque(self: &Arc<Port<I, O>>) -> Arc<OpaquePort> {
+        Arc::clone(self) as Arc<OpaquePort>
+    }
+    /// The `Arc` this port is kept in, which is alive as long as the port can be borrowed.
+    fn arc(&self) -> Arc<Port<I, O>> {
+        self.this.upgrade().unwrap()
+    }
+    /// Get the PortId.
+    pub fn id(&self) -> PortId {
+        self.id
+    }
+    /// Get the port name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Get the NodeId.
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+    /// Get the ID of the graph the port belongs to.
+    pub fn graph_id(&self) -> GraphId {
+        self.graph_id
+    }
+    /// Get the node and port id together.
+    pub fn port_ref(&self) -> PortRef {
+        PortRef {
+            node: self.node_id,
+            port: self.id,
+        }
+    }
+    fn set_on_connect(&self, hook: Box<dyn Fn() + Send>) {
+        *self.on_connect.lock().unwrap() = Some(hook);
+    }
+    /// Get a stream of this port being connected and disconnected, so a module can set up for a
+    /// connection or stop producing once nobody is listening.
+    pub fn connection_events(&self) -> UnboundedReceiver<ConnectionEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.connection_events.lock().unwrap().push(tx);
+        rx
+    }
+    fn notify(&self, event: ConnectionEvent) {
+        self.connection_events
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.unbounded_send(event).is_ok());
+    }
+    /// Get the metadata declared for this port.
+    pub fn meta(&self) -> PortMeta {
+        *self.meta.read().unwrap()
+    }
+    /// Declare metadata for this port.
+    pub fn set_meta(&self, meta: PortMeta) {
+        *self.meta.write().unwrap() = meta;
+    }
+    /// Get the name of the type of data flowing in to this port.
+    pub fn in_type_name(&self) -> &'static str {
+        self.in_ty_name
+    }
+    /// Get the name of the type of data flowing out of this port.
+    pub fn out_type_name(&self) -> &'static str {
+        self.out_ty_name
+    }
+    /// Number of items currently buffered for reading on this port.
+    pub fn buffered(&self) -> usize {
+        self.inner.spin_lock().buffer.len()
+    }
+    /// Get the buffering behaviour of the current connection.
+    pub fn connect_options(&self) -> ConnectOptions {
+        self.inner.spin_lock().options
+    }
+    /// The gain of the current connection, see `ConnectOptions::gain`.
+    pub fn gain(&self) -> Option<f32> {
+        self.edge()?;
+        self.inner.spin_lock().options.gain
+    }
+    /// Whether either side of the port carries audio frames, so a connection gain applies to it.
+    pub fn carries_audio(&self) -> bool {
+        self.in_ty == TypeId::of::<Frame>() || self.out_ty == TypeId::of::<Frame>()
+    }
+    /// Change the gain of the current connection, at both ends, or remove it with None. Fails
+    /// with ConnectError::TypeMismatch if the port doesn't carry audio, and with
+    /// ConnectError::NotConnected if it isn't connected.
+    pub fn set_gain(&self, gain: Option<f32>) -> Result<(), ConnectError> {
+        if !self.carries_audio() {
+            return Err(ConnectError::TypeMismatch);
+        }
+        let other = self.edge().ok_or(ConnectError::NotConnected)?;
+        self.inner.spin_lock().options.gain = gain;
+        other.inner.spin_lock().options.gain = gain;
+        // block schedulers read the gain when they plan the graph
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+    /// Number of items written to this port that were discarded because its buffer was full.
+    pub fn dropped(&self) -> usize {
+        self.inner.spin_lock().dropped
+    }
+    /// Number of items written to this port since it was created, a measure of activity.
+    pub fn received(&self) -> usize {
+        self.inner.spin_lock().received
+    }
+    /// Discard everything buffered for reading on this port, returning how many items there were.
+    pub fn flush(&self) -> usize {
+        let (data, n, writers);
+        {
+            let mut inner = self.inner.spin_lock();
+            n = inner.buffer.len();
+            data = inner.buffer.drain();
+            writers = inner.write_wait.drain(..).collect::<Vec<_>>();
+        }
+        drop(data);
+        // there is room for blocked writers now
+        for writer in writers {
+            writer.wake();
+        }
+        n
+    }
+    /// Determines if two ports can be connected to each other.
+    pub fn can_connect(&self, other: &Port<O, I>) -> bool {
+        self.graph_id == other.graph_id
+            && self.id() != other.id()
+            && self.in_ty == other.out_ty
+            && self.out_ty == other.in_ty
+    }
+    /// Connect this port to another. If either port is opaque and the ports have unmatched
+    /// underlying types, this fails with ConnectError::TypeMismatch. Fails with
+    /// ConnectError::AlreadyConnected if either port is already connected, with
+    /// ConnectError::CrossGraph if the ports belong to different graphs, and with
+    /// ConnectError::FormatMismatch if they declare different channel counts.
+    pub fn connect(self: &Arc<Port<I, O>>, other: &Arc<Port<O, I>>) -> Result<(), ConnectError> {
+        self.connect_with(other, ConnectOptions::default())
+    }
+    /// Connect this port to another with the given buffering behaviour. See `connect`.
+    pub fn connect_with(
+        self: &Arc<Port<I, O>>,
+        other: &Arc<Port<O, I>>,
+        options: ConnectOptions,
+    ) -> Result<(), ConnectError> {
+        // checked first, since ports of different graphs can have the same id
+        if self.graph_id != other.graph_id {
+            return Err(ConnectError::CrossGraph);
+        }
+        if !self.can_connect(other) {
+            return Err(ConnectError::TypeMismatch);
+        }
+        let (meta, other_meta) = (self.meta(), other.meta());
+        if !channels_match(&meta, &other_meta) {
+            return Err(ConnectError::FormatMismatch(
+                meta.channels.unwrap(),
+                other_meta.channels.unwrap(),
+            ));
+        }
+        if self.id() == other.id() {
+            // self edges are currently not supported
+            unimplemented!();
+        } else {
+            // always lock the port with lower id first to prevent deadlock
+            // (circular wait condition)
+            let (mut self_edge, mut other_edge);
+            if self.id().0 < other.id().0 {
+                self_edge = self.edge.spin_lock();
+                other_edge = other.edge.spin_lock();
+            } else {
+                other_edge = other.edge.spin_lock();
+                self_edge = self.edge.spin_lock();
+            }
+            if self_edge.other.as_ref().and_then(|x| x.upgrade()).is_some()
+                || other_edge.other.as_ref().and_then(|x| x.upgrade()).is_some()
+            {
+                return Err(ConnectError::AlreadyConnected);
+            }
+            self_edge.other = Some(Arc::downgrade(other));
+            other_edge.other = Some(Arc::downgrade(self));
+            for inner in &[&self.inner, &other.inner] {
+                let mut inner = inner.spin_lock();
+                inner.options = options;
+                // a new connection starts a new stream, unless the port was shut down
+                inner.ended = inner.refusing;
+            }
+
+            // UnsafeCells protected by edge mutex
+            for waker in self_edge.connect_wait.drain(..).chain(other_edge.connect_wait.drain(..)) {
+                waker.wake();
+            }
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            // hooks may create ports and connect them, so release the edges first
+            drop((self_edge, other_edge));
+            for on_connect in &[&self.on_connect, &other.on_connect] {
+                if let Some(ref hook) = *on_connect.lock().unwrap() {
+                    hook();
+                }
+            }
+            self.notify(ConnectionEvent::Connected(other.port_ref()));
+            other.notify(ConnectionEvent::Connected(self.port_ref()));
+            Ok(())
+        }
+    }
+
+    /// Disconnect this port from another.
+    /// Fails with ConnectError::NotConnected if the port is already disconnected.
+    pub fn disconnect(self: &Arc<Port<I, O>>) -> Result<(), ConnectError> {
+        // similarly to with `connect`, we need to lock the edges of the two ports in
+        // a deterministic order to prevent a deadlock.
+        // but here, we don't know the other port until we lock this port.
+        // so, we read the other port with `edge()`, lock the two in the required order,
+        // verify nothing changed in between reading and locking,
+        // then finally clear the connection.
+        // if verification fails we race again until it succeeds.
+        loop {
+            let other = &self.edge().ok_or(ConnectError::NotConnected)?;
+            if other.id() == self.id() {
+                // self edges are currently not supported
+                unimplemented!();
+            } else {
+                let (mut a_edge, mut b_edge);
+                if self.id().0 < other.id().0 {
+                    a_edge = self.edge.spin_lock();
+                    b_edge = other.edge.spin_lock();
+                } else {
+                    b_edge = other.edge.spin_lock();
+                    a_edge = self.edge.spin_lock();
+                };
+                // check that the port this one is connected to hasn't changed in between
+                // finding `other` and locking the edges
+                if !a_edge
+                    .other
+                    .as_ref()
+                    .and_then(|x| x.upgrade())
+                    .map(|self_other| Arc::ptr_eq(other, &self_other))
+                    .unwrap_or(false)
+                {
+                    continue;
+                }
+                // other should definitely be connected to self if we made it here
+                assert!(Arc::ptr_eq(
+                    &b_edge.other.as_ref().unwrap().upgrade().unwrap(),
+                    self
+                ));
+                a_edge.other = None;
+                b_edge.other = None;
+
+                drop(a_edge);
+                drop(b_edge);
+                self.generation.fetch_add(1, Ordering::SeqCst);
+
+                // fail any waiting readers so that the task isn't left half finished across a
+                // disconnect/reconnect
+                self.disconnect_abort();
+                other.disconnect_abort();
+                self.notify(ConnectionEvent::Disconnected(other.port_ref()));
+                other.notify(ConnectionEvent::Disconnected(self.port_ref()));
+                break;
+            }
+        }
+        Ok(())
+    }
+    fn disconnect_abort(&self) {
+        let readers;
+        {
+            let mut inner = self.inner.spin_lock();
+            inner.disconnect_occured = true;
+            readers = inner.read_wait.drain(..).collect::<Vec<_>>();
+        };
+
+        // wake any readers that were waiting, since they need to fail now
+        for reader in readers {
+            reader.wake();
+        }
+    }
+    pub fn edge(&self) -> Option<Arc<Port<O, I>>> {
+        self.edge.spin_lock().other.as_ref().and_then(|x| x.upgrade())
+    }
+    /// Shut the port down: writes to and from it fail from now on, and once their buffers run
+    /// out, reads of it fail with `Error::EndOfStream`, as does the next read of the port on the
+    /// other end. Unless `drain` is set, its buffer is flushed first. See `Interface::shutdown`.
+    pub fn shutdown(&self, drain: bool) {
+        let (readers, writers);
+        {
+            let mut inner = self.inner.spin_lock();
+            inner.refusing = true;
+            inner.ended = true;
+            readers = inner.read_wait.drain(..).collect::<Vec<_>>();
+            writers = inner.write_wait.drain(..).collect::<Vec<_>>();
+        }
+        if !drain {
+            self.flush();
+        }
+        for waker in readers.into_iter().chain(writers) {
+            waker.wake();
+        }
+        if let Some(other) = self.edge() {
+            other.end_stream();
+        }
+    }
+    /// End the stream written from this port, for when a file has finished playing or a render is
+    /// done. Once it has read everything written before, the next read of the port on the other
+    /// end fails with `Error::EndOfStream`, and reads after that wait for a new stream to be
+    /// written. Fails with ConnectError::NotConnected if the port isn't connected.
+    pub fn close(&self) -> Result<(), ConnectError> {
+        self.edge().ok_or(ConnectError::NotConnected)?.end_stream();
+        Ok(())
+    }
+    /// Let readers of this port know that nothing more will be written to it.
+    fn end_stream(&self) {
+        let readers;
+        {
+            let mut inner = self.inner.spin_lock();
+            inner.ended = true;
+            readers = inner.read_wait.drain(..).collect::<Vec<_>>();
+        }
+        for reader in readers {
+            reader.wake();
+        }
+    }
+    /// Fail every read and write of this port that has been started but not finished with
+    /// `Error::Cancelled`, so the tasks waiting on them can finish, e.g. when the module stops.
+    /// Items being written are dropped, and data already buffered is left for later reads.
+    pub fn abort_pending(&self) {
+        self.cancelled.fetch_add(1, Ordering::SeqCst);
+        // writers wait for a connection on this port, or for room on the other end
+        let mut wakers = self.edge.spin_lock().connect_wait.drain(..).collect::<Vec<_>>();
+        wakers.extend(self.inner.spin_lock().read_wait.drain(..));
+        if let Some(other) = self.edge() {
+            wakers.extend(other.inner.spin_lock().write_wait.drain(..));
+        }
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Returns a `Future` which writes a `Vec` of data to a port, returning the port.
+    /// Writing cannot currently fail: TODO make the type signature reflect this.
+    pub fn write(
+        self: Arc<Port<I, O>>,
+        mut data: Vec<O>,
+    ) -> impl Future<Output = Result<Arc<Port<I, O>>, (Arc<Port<I, O>>, Error)>>
+    where
+        O: PortData,
+    {
+        let gain = self.inner.spin_lock().options.gain;
+        apply_gain(&mut data, gain);
+        WriteFuture {
+            epoch: self.cancelled.load(Ordering::SeqCst),
+            port: Some(self),
+            other: None,
+            data: Items::new(data),
+        }.fuse()
+    }
+    /// Write a single item. Equivalent to `write(vec![data])`
+    pub fn write1(
+        self: Arc<Port<I, O>>,
+        data: O,
+    ) -> impl Future<Output = Result<Arc<Port<I, O>>, (Arc<Port<I, O>>, Error)>>
+    where
+        O: PortData,
+    {
+        self.write(vec![data])
+    }
+
+    /// Returns a `Future` which reads all available data from a port, returning the port and the
+    /// data. Succeeds when at least one item is available. Returns an error if the port has been
+    /// disconnected since the task began.
+    pub fn read(
+        self: Arc<Port<I, O>>,
+    ) -> impl Future<Output = Result<(Arc<Port<I, O>>, Box<[I]>), (Arc<Port<I, O>>, Error)>> {
+        ReadFuture {
+            epoch: self.cancelled.load(Ordering::SeqCst),
+            port: Some(self),
+            n: None,
+        }.fuse()
+    }
+    /// Read exactly n items from a port. Completes when at least n items become available. See
+    /// `read` for more information.
+    pub fn read_n(
+        self: Arc<Port<I, O>>,
+        n: usize,
+    ) -> impl Future<Output = Result<(Arc<Port<I, O>>, Box<[I]>), (Arc<Port<I, O>>, Error)>> {
+        ReadFuture {
+            epoch: self.cancelled.load(Ordering::SeqCst),
+            port: Some(self),
+            n: Some(n),
+        }.fuse()
+    }
+    /// Equivalent to `read_n(1)`, but returns the item itself instead of a singleton array
+    pub fn read1(
+        self: Arc<Port<I, O>>,
+    ) -> impl Future<Output = Result<(Arc<Port<I, O>>, I), (Arc<Port<I, O>>, Error)>> {
+        self.read_n(1)
+            .map_ok(|(port, data)| (port, data.into_vec().drain(..).next().unwrap()))
+    }
+    /// Returns a `Stream` of windows of n items, each starting hop items after the last, for
+    /// STFT-style analysis. The overlap is kept by the stream, so each window only waits for hop
+    /// new items. Errors are passed on without ending the stream, and the end of a stream of data
+    /// drops the overlap, so windows don't span two streams.
+    pub fn read_overlapping(self: Arc<Port<I, O>>, n: usize, hop: usize) -> Windows<I, O>
+    where
+        I: Clone,
+    {
+        assert!(hop > 0 && hop <= n, "hop must be between 1 and the window size");
+        Windows {
+            port: self,
+            read: None,
+            window: Vec::with_capacity(n),
+            n,
+            hop,
+        }
+    }
+
+    /// Read all available data without waiting, for realtime callbacks that can't poll a future.
+    /// Fails with `Error::WouldBlock` if there is none, or another thread has the port locked, and
+    /// with `Error::EndOfStream` like `read`.
+    pub fn try_read(&self) -> Result<Box<[I]>, Error> {
+        self.try_take(None)
+    }
+    /// Read exactly n items without waiting. See `try_read`.
+    pub fn try_read_n(&self, n: usize) -> Result<Box<[I]>, Error> {
+        self.try_take(Some(n))
+    }
+    fn try_take(&self, n: Option<usize>) -> Result<Box<[I]>, Error> {
+        let (data, writers) = {
+            let mut inner = self.inner.try_lock().ok_or(Error::WouldBlock)?;
+            match inner.take::<I>(n) {
+                Some(taken) => taken,
+                None if inner.end_of_stream() => return Err(Error::EndOfStream),
+                None => return Err(Error::WouldBlock),
+            }
+        };
+        for writer in writers {
+            writer.wake();
+        }
+        Ok(data)
+    }
+    /// Write without waiting, for realtime callbacks that can't poll a future. The data is given
+    /// back with `Error::WouldBlock` if the buffer is full and the connection blocks on overflow, or
+    /// another thread has a port locked, with `Error::NotConnected` if the port isn't connected,
+    /// and with `Error::Closed` like `write`.
+    pub fn try_write(&self, mut data: Vec<O>) -> Result<(), (Vec<O>, Error)>
+    where
+        O: PortData,
+    {
+        let gain = match self.inner.try_lock() {
+            Some(ref inner) if inner.refusing => return Err((data, Error::Closed)),
+            Some(ref inner) => inner.options.gain,
+            None => return Err((data, Error::WouldBlock)),
+        };
+        let other = match self.edge.try_lock() {
+            Some(edge) => edge.other.as_ref().and_then(|x| x.upgrade()),
+            None => return Err((data, Error::WouldBlock)),
+        };
+        let other = match other {
+            Some(other) => other,
+            None => return Err((data, Error::NotConnected)),
+        };
+        let readers = {
+            let mut inner = match other.inner.try_lock() {
+                Some(inner) => inner,
+                None => return Err((data, Error::WouldBlock)),
+            };
+            if inner.refusing {
+                return Err((data, Error::Closed));
+            }
+            if inner.blocks(data.len()) {
+                return Err((data, Error::WouldBlock));
+            }
+            apply_gain(&mut data, gain);
+            inner.accept::<O>(Items::new(data))
+        };
+        for reader in readers {
+            reader.wake();
+        }
+        Ok(())
+    }
+}
+
+pub struct ReadFuture<I: 'static, O: 'static> {
+    port: Option<Arc<Port<I, O>>>,
+    n: Option<usize>,
+    /// The port's `abort_pending` count when the read started.
+    epoch: usize,
+}
+
+impl<I: 'static, O: 'static> Future for ReadFuture<I, O> {
+    type Output = Result<(Arc<Port<I, O>>, Box<[I]>), (Arc<Port<I, O>>, Error)>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let port = this.port.as_ref().unwrap();
+        if port.cancelled.load(Ordering::SeqCst) != this.epoch {
+            return Poll::Ready(Err((this.port.take().unwrap(), Error::Cancelled)));
+        }
+        {
+            let mut inner = match port.inner.poll_lock(cx) {
+                Poll::Ready(inner) => inner,
+                Poll::Pending => return Poll::Pending,
+            };
+            // if a disconnect has occured, then we fail the future so that the task isn't left
+            // in a half finished state.
+            if inner.disconnect_occured {
+                inner.disconnect_occured = false;
+                drop(inner);
+                return Poll::Ready(Err((this.port.take().unwrap(), Error::Disconnected)));
+            }
+            // attempt read
+            match inner.take::<I>(this.n) {
+                Some((data, writers)) => {
+                    drop(inner);
+                    for writer in writers {
+                        writer.wake();
+                    }
+                    return Poll::Ready(Ok((this.port.take().unwrap(), data)));
+                }
+                None if inner.end_of_stream() => {
+                    drop(inner);
+                    return Poll::Ready(Err((this.port.take().unwrap(), Error::EndOfStream)));
+                }
+                None => {
+                    // not enough data available
+                    // register to wake on next write
+                    inner.read_wait.push(cx.waker().clone());
+                }
+            }
+        }
+
+        // the waker would have been put into inner.read_wait if we get here
+        Poll::Pending
+    }
+}
+
+/// Windows read by `Port::read_overlapping`.
+pub struct Windows<I: 'static, O: 'static> {
+    port: Arc<Port<I, O>>,
+    read: Option<ReadFuture<I, O>>,
+    /// The overlap kept from the last window.
+    window: Vec<I>,
+    n: usize,
+    hop: usize,
+}
+
+// the overlap is never pinned, only the stream
+impl<I: 'static, O: 'static> Unpin for Windows<I, O> {}
+
+impl<I: Clone + 'static, O: 'static> Stream for Windows<I, O> {
+    type Item = Result<Box<[I]>, Error>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.read.is_none() {
+            this.read = Some(ReadFuture {
+                epoch: this.port.cancelled.load(Ordering::SeqCst),
+                port: Some(this.port.clone()),
+                n: Some(this.n - this.window.len()),
+            });
+        }
+        match Pin::new(this.read.as_mut().unwrap()).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok((_, data))) => {
+                this.read = None;
+                this.window.extend(data.into_vec());
+                let window = this.window.clone().into_boxed_slice();
+                this.window.drain(..this.hop);
+                Poll::Ready(Some(Ok(window)))
+            }
+            Poll::Ready(Err((_, err))) => {
+                this.read = None;
+                if let Error::EndOfStream = err {
+                    this.window.clear();
+                }
+                Poll::Ready(Some(Err(err)))
+            }
+        }
+    }
+}
+
+pub struct WriteFuture<I: 'static, O: 'static> {
+    port: Option<Arc<Port<I, O>>>,
+    data: Items,
+    other: Option<Arc<Port<O, I>>>,
+    /// The port's `abort_pending` count when the write started.
+    epoch: usize,
+}
+
+impl<I: 'static, O: 'static> Future for WriteFuture<I, O> {
+    type Output = Result<Arc<Port<I, O>>, (Arc<Port<I, O>>, Error)>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.port.as_ref().unwrap().cancelled.load(Ordering::SeqCst) != this.epoch {
+            mem::replace(&mut this.data, Items::empty()).discard::<O>();
+            return Poll::Ready(Err((this.port.take().unwrap(), Error::Cancelled)));
+        }
+        {
+            // a port that was shut down can't write either
+            let inner = match this.port.as_ref().unwrap().inner.poll_lock(cx) {
+                Poll::Ready(inner) => inner,
+                Poll::Pending => return Poll::Pending,
+            };
+            if inner.refusing {
+                drop(inner);
+                mem::replace(&mut this.data, Items::empty()).discard::<O>();
+                return Poll::Ready(Err((this.port.take().unwrap(), Error::Closed)));
+            }
+        }
+        if this.other.is_none() {
+            let port = this.port.as_ref().unwrap();
+            this.other = Some({
+                let mut edge = match port.edge.poll_lock(cx) {
+                    Poll::Ready(edge) => edge,
+                    Poll::Pending => return Poll::Pending,
+                };
+                match edge.other.as_ref().and_then(|x| x.upgrade()) {
+                    Some(other) => other,
+                    None => {
+                        // register to wake on connect
+                        edge.connect_wait.push(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                }
+            });
+        }
+        let other = this.other.as_ref().unwrap();
+
+        let readers;
+        {
+            let mut inner = match other.inner.poll_lock(cx) {
+                Poll::Ready(inner) => inner,
+                Poll::Pending => return Poll::Pending,
+            };
+            if inner.refusing {
+                drop(inner);
+                mem::replace(&mut this.data, Items::empty()).discard::<O>();
+                return Poll::Ready(Err((this.port.take().unwrap(), Error::Closed)));
+            }
+            if inner.blocks(this.data.len()) {
+                inner.write_wait.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            readers = inner.accept::<O>(mem::replace(&mut this.data, Items::empty()));
+        }
+
+        // wake any readers that are waiting for a write here
+        for reader in readers {
+            reader.wake();
+        }
+
+        Poll::Ready(Ok(this.port.take().unwrap()))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    AlreadyConnected,
+    TypeMismatch,
+    NotConnected,
+    ChannelMismatch,
+    /// The ports belong to different graphs.
+    CrossGraph,
+    /// The ports declare different numbers of audio channels, given as (this port, other port).
+    FormatMismatch(usize, usize),
+}
+
+/// Scale the frames among `data` by a connection's gain.
+fn apply_gain<O: 'static>(data: &mut Vec<O>, gain: Option<f32>) {
+    let gain = match gain {
+        Some(gain) if gain != 1.0 => gain,
+        _ => return,
+    };
+    if let Some(frames) = (data as &mut dyn Any).downcast_mut::<Vec<Frame>>() {
+        for frame in frames {
+            simd::scale_array(&mut frame.data, gain);
+        }
+    }
+}
+
+fn channels_match(a: &PortMeta, b: &PortMeta) -> bool {
+    match (a.channels, b.channels) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// One edit in a batch for `Graph::apply`. Ops other than `AddNode` can be serialized, e.g. to
+/// log or replay a sequence of edits, see the fuzz targets in `fuzz`.
+#[derive(Serialize, Deserialize)]
+pub enum GraphOp {
+    /// Add a node with an unused id, see `Graph::reserve_id`. `build` is called with the new
+    /// node's interface before the batch is checked, to create its ports, usually by constructing
+    /// a module. If the batch is rejected the node is never added, and the module should be
+    /// dropped.
+    #[serde(skip)]
+    AddNode(NodeId, Box<dyn FnMut(Arc<Interface>) + Send>),
+    /// Remove a node, disconnecting all of its ports.
+    RemoveNode(NodeId),
+    Connect(PortRef, PortRef, ConnectOptions),
+    Disconnect(PortRef),
+    /// Set a parameter the node exposes through `Interface::set_params`.
+    SetParam(NodeId, String, f32),
+    /// Set the gain of the audio connection of a port, see `Port::set_gain`.
+    SetGain(PortRef, Option<f32>),
+}
+
+impl fmt::Debug for GraphOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GraphOp::AddNode(id, _) => write!(f, "AddNode({:?})", id),
+            GraphOp::RemoveNode(id) => write!(f, "RemoveNode({:?})", id),
+            GraphOp::Connect(a, b, options) => write!(f, "Connect({:?}, {:?}, {:?})", a, b, options),
+            GraphOp::Disconnect(port) => write!(f, "Disconnect({:?})", port),
+            GraphOp::SetParam(id, ref name, value) => write!(f, "SetParam({:?}, {:?}, {})", id, name, value),
+            GraphOp::SetGain(port, gain) => write!(f, "SetGain({:?}, {:?})", port, gain),
+        }
+    }
+}
+
+/// Why `Graph::apply` rejected a batch, with the index of the op at fault.
+#[derive(Debug)]
+pub enum BatchError {
+    /// The node doesn't exist, or was removed earlier in the batch.
+    InvalidNode(usize),
+    /// An added node's id is already in use.
+    DuplicateNode(usize),
+    InvalidPort(usize),
+    /// The node has no parameter of that name.
+    InvalidParam(usize),
+    Connect(usize, ConnectError),
+}
+
+/// Problems found by `Graph::validate`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Diagnostic {
+    /// A required port is not connected.
+    DanglingInput(PortRef),
+    /// A pending connection between ports of incompatible types.
+    TypeMismatch(PortRef, PortRef),
+    /// A pending connection between ports declaring different channel counts.
+    FormatMismatch(PortRef, PortRef),
+    /// A pending connection refers to a port that doesn't exist.
+    InvalidPort(PortRef),
+    /// None of the node's ports are connected.
+    Unreachable(NodeId),
+    /// The nodes form a cycle with no feedback delay to break it.
+    Cycle(Vec<NodeId>),
+    /// Connected ports declare different sample rates.
+    SampleRateMismatch((PortRef, u32), (PortRef, u32)),
+}
+
+/// Error cases
+#[derive(Debug)]
+pub enum Error {
+    NotConnected,
+    InvalidNode,
+    InvalidPort,
+    NotAvailable,
+    Disconnected,
+    InvalidScene,
+    /// The read or write was pending when `Port::abort_pending` was called.
+    Cancelled,
+    /// The port or the one it's connected to was shut down with `Interface::shutdown`, so the
+    /// write can't go through.
+    Closed,
+    /// A `try_read` or `try_write` would have had to wait.
+    WouldBlock,
+    /// Everything written to the port has been read, and the writer closed the stream, see
+    /// `Port::close`.
+    EndOfStream,
+}
+
+/// Data that can be written to ports. It must be `Send`, as ports are read and written from any
+/// thread.
+pub trait PortData: Send + 'static {}
+impl<T: Send + 'static> PortData for T {}
+
+/// Items buffered for reading on a port. They are stored without their type, so that `PortInner`
+/// isn't generic: as bytes, or with the `safe-ports` feature, as boxed `Any`s, which keeps unsafe
+/// code away from them at the cost of an allocation per item.
+#[cfg(not(feature = "safe-ports"))]
+struct Buffer {
+    bytes: VecDeque<u8>,
+    len: usize,
+    /// Drops items taken out of the buffer, which only the typed port knows how to do.
+    drop_items: fn(Box<[u8]>, usize),
+}
+
+/// Items being written to a `Buffer`.
+#[cfg(not(feature = "safe-ports"))]
+struct Items {
+    bytes: Box<[u8]>,
+    len: usize,
+}
+
+/// Items taken out of a `Buffer` without knowing their type, which are dropped properly.
+#[cfg(not(feature = "safe-ports"))]
+struct Drained {
+    bytes: Box<[u8]>,
+    len: usize,
+    drop_items: fn(Box<[u8]>, usize),
+}
+
+#[cfg(not(feature = "safe-ports"))]
+impl Items {
+    fn new<T: PortData>(items: Vec<T>) -> Items {
+        let len = items.len();
+        let size = len * mem::size_of::<T>();
+        Items {
+            bytes: typed_as_bytes(items.into(), size),
+            len,
+        }
+    }
+    fn empty() -> Items {
+        Items {
+            bytes: Vec::new().into_boxed_slice(),
+            len: 0,
+        }
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+    fn discard<T: 'static>(self) {
+        drop(bytes_as_typed::<T>(self.bytes, self.len));
+    }
+    /// Split off the items from `at` on.
+    fn split_off<T: 'static>(&mut self, at: usize) -> Items {
+        let at = at.min(self.len);
+        let mut bytes = mem::replace(&mut self.bytes, Vec::new().into_boxed_slice()).into_vec();
+        let rest = bytes.split_off(at * mem::size_of::<T>());
+        let len = mem::replace(&mut self.len, at);
+        self.bytes = bytes.into_boxed_slice();
+        Items {
+            bytes: rest.into_boxed_slice(),
+            len: len - at,
+        }
+    }
+}
+
+#[cfg(not(feature = "safe-ports"))]
+impl Drop for Drained {
+    fn drop(&mut self) {
+        let bytes = mem::replace(&mut self.bytes, Vec::new().into_boxed_slice());
+        (self.drop_items)(bytes, self.len);
+    }
+}
+
+#[cfg(not(feature = "safe-ports"))]
+impl Buffer {
+    fn new<T: 'static>() -> Buffer {
+        Buffer {
+            bytes: VecDeque::new(),
+            len: 0,
+            drop_items: drop_typed::<T>,
+        }
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+    fn push(&mut self, items: Items) {
+        self.bytes.extend(items.bytes.iter());
+        self.len += items.len;
+    }
+    fn pop_front<T: 'static>(&mut self, n: usize) -> Box<[T]> {
+        let data = self.bytes.drain(..n * mem::size_of::<T>()).collect::<Vec<_>>();
+        self.len -= n;
+        bytes_as_typed(data.into(), n)
+    }
+    fn pop_back<T: 'static>(&mut self, n: usize) -> Box<[T]> {
+        let start = self.bytes.len() - n * mem::size_of::<T>();
+        let data = self.bytes.drain(start..).collect::<Vec<_>>();
+        self.len -= n;
+        bytes_as_typed(data.into(), n)
+    }
+    /// Take out every item, to be dropped once the port is unlocked.
+    fn drain(&mut self) -> Drained {
+        let len = mem::replace(&mut self.len, 0);
+        Drained {
+            bytes: self.bytes.drain(..).collect::<Vec<_>>().into(),
+            len,
+            drop_items: self.drop_items,
+        }
+    }
+}
+
+#[cfg(feature = "safe-ports")]
+struct Buffer {
+    items: VecDeque<Box<dyn Any + Send>>,
+}
+
+#[cfg(feature = "safe-ports")]
+struct Items {
+    items: Vec<Box<dyn Any + Send>>,
+}
+
+#[cfg(feature = "safe-ports")]
+type Drained = VecDeque<Box<dyn Any + Send>>;
+
+#[cfg(feature = "safe-ports")]
+impl Items {
+    fn new<T: PortData>(items: Vec<T>) -> Items {
+        Items {
+            items: items
+                .into_iter()
+                .map(|item| Box::new(item) as Box<dyn Any + Send>)
+                .collect(),
+        }
+    }
+    fn empty() -> Items {
+        Items { items: Vec::new() }
+    }
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+    fn discard<T: 'static>(self) {}
+    /// Split off the items from `at` on.
+    fn split_off<T: 'static>(&mut self, at: usize) -> Items {
+        let at = at.min(self.items.len());
+        Items {
+            items: self.items.split_off(at),
+        }
+    }
+}
+
+#[cfg(feature = "safe-ports")]
+fn downcast_items<T: 'static, It: Iterator<Item = Box<dyn Any + Send>>>(items: It) -> Box<[T]> {
+    items
+        .map(|item| *item.downcast::<T>().expect("item of the wrong type in a port buffer"))
+        .collect::<Vec<_>>()
+        .into_boxed_slice()
+}
+
+#[cfg(feature = "safe-ports")]
+impl Buffer {
+    fn new<T: 'static>() -> Buffer {
+        Buffer {
+            items: VecDeque::new(),
+        }
+    }
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+    fn push(&mut self, items: Items) {
+        self.items.extend(items.items);
+    }
+    fn pop_front<T: 'static>(&mut self, n: usize) -> Box<[T]> {
+        downcast_items(self.items.drain(..n))
+    }
+    fn pop_back<T: 'static>(&mut self, n: usize) -> Box<[T]> {
+        let start = self.items.len() - n;
+        downcast_items(self.items.drain(start..))
+    }
+    /// Take out every item, to be dropped once the port is unlocked.
+    fn drain(&mut self) -> Drained {
+        mem::replace(&mut self.items, VecDeque::new())
+    }
+}
+
+#[cfg(not(feature = "safe-ports"))]
+fn typed_as_bytes<T: 'static>(data: Box<[T]>, size: usize) -> Box<[u8]> {
+    assert!(mem::size_of::<T>() == 0 || size == data.len() * mem::size_of::<T>());
+    let raw = Box::into_raw(data);
+    unsafe { Box::from_raw(slice::from_raw_parts_mut(raw as *mut u8, size)) }
+}
+
+#[cfg(not(feature = "safe-ports"))]
+fn bytes_as_typed<T: 'static>(data: Box<[u8]>, size: usize) -> Box<[T]> {
+    assert!(
+        mem::size_of::<T>() == 0
+            || (data.len() % mem::size_of::<T>() == 0 && size == data.len() / mem::size_of::<T>())
+    );
+    let raw = Box::into_raw(data);
+    unsafe { Box::from_raw(slice::from_raw_parts_mut(raw as *mut T, size)) }
+}
+
+#[cfg(not(feature = "safe-ports"))]
+fn drop_typed<T: 'static>(data: Box<[u8]>, size: usize) {
+    drop(bytes_as_typed::<T>(data, size));
+}
+
+#[test]
+fn test_validate() {
+    let graph = Graph::new();
+    let a = graph.add_node();
+    let b = graph.add_node();
+    let c = graph.add_node();
+    let a_out = a.get_or_create_port::<(), f32>("Output".into());
+    let b_in = b.get_or_create_port::<f32, ()>("Input".into());
+    let b_out = b.get_or_create_port::<(), f32>("Output".into());
+    let a_in = a.get_or_create_port::<f32, ()>("Input".into());
+    let c_in = c.get_or_create_port::<f32, ()>("Input".into());
+    c_in.set_meta(PortMeta {
+        required: true,
+        ..c_in.meta()
+    });
+    a_out.connect(&b_in).unwrap();
+    b_out.connect(&a_in).unwrap();
+
+    let diagnostics = graph.validate();
+    assert!(diagnostics.contains(&Diagnostic::DanglingInput(c_in.port_ref())));
+    assert!(diagnostics.contains(&Diagnostic::Unreachable(c.id())));
+    assert!(diagnostics.contains(&Diagnostic::Cycle(vec![a.id(), b.id()])));
+
+    // a delay on the feedback path breaks the cycle
+    a_in.set_meta(PortMeta {
+        feedback_delay: true,
+        ..a_in.meta()
+    });
+    let diagnostics = graph.validate_with(&[(c_in.port_ref(), b_in.port_ref())]);
+    assert!(!diagnostics.iter().any(|d| match d {
+        Diagnostic::Cycle(_) => true,
+        _ => false,
+    }));
+    assert!(diagnostics.contains(&Diagnostic::TypeMismatch(c_in.port_ref(), b_in.port_ref())));
+}
+
+#[test]
+fn test_port_groups() {
+    let graph = Graph::new();
+    let a = graph.add_node();
+    let b = graph.add_node();
+    let out = a.get_or_create_group::<(), f32, _>("Out", &["L", "R"]);
+    let inp = b.get_or_create_group::<f32, (), _>("In", &["R", "L"]);
+    assert_eq!(out.ports()[0].name(), "Out L");
+    out.connect(&inp).unwrap();
+    // matched by channel name rather than position
+    assert_eq!(out.ports()[0].edge().unwrap().id(), inp.channel("L").unwrap().id());
+
+    out.disconnect();
+    let mono = b.get_or_create_group::<f32, (), _>("Mono", &["M"]);
+    assert!(match out.connect(&mono) {
+        Err(ConnectError::ChannelMismatch) => true,
+        _ => false,
+    });
+    assert!(out.ports().iter().all(|port| port.edge().is_none()));
+}
+
+#[test]
+fn test_overflow() {
+    use futures::executor::block_on;
+
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let send = |policy| {
+        let out = node.get_or_create_port::<(), i32>(format!("Out {:?}", policy));
+        let inp = node.get_or_create_port::<i32, ()>(format!("In {:?}", policy));
+        let options = ConnectOptions {
+            capacity: Some(2),
+            overflow: policy,
+            gain: None,
+        };
+        out.connect_with(&inp, options).unwrap();
+        for i in 1..4 {
+            block_on(out.clone().write1(i)).ok().unwrap();
+        }
+        let (inp, data) = block_on(inp.clone().read()).ok().unwrap();
+        (data.into_vec(), inp.dropped())
+    };
+    assert_eq!(send(Overflow::DropOldest), (vec![2, 3], 1));
+    assert_eq!(send(Overflow::DropNewest), (vec![1, 2], 1));
+    assert_eq!(send(Overflow::Overwrite), (vec![1, 3], 1));
+
+    // a single write bigger than the whole buffer is cut down to fit
+    let send_many = |policy| {
+        let out = node.get_or_create_port::<(), i32>(format!("Out many {:?}", policy));
+        let inp = node.get_or_create_port::<i32, ()>(format!("In many {:?}", policy));
+        let options = ConnectOptions {
+            capacity: Some(2),
+            overflow: policy,
+            gain: None,
+        };
+        out.connect_with(&inp, options).unwrap();
+        block_on(out.clone().write1(0)).ok().unwrap();
+        block_on(out.clone().write(vec![1, 2, 3, 4])).ok().unwrap();
+        let (inp, data) = block_on(inp.clone().read()).ok().unwrap();
+        (data.into_vec(), inp.dropped())
+    };
+    assert_eq!(send_many(Overflow::DropOldest), (vec![3, 4], 3));
+    assert_eq!(send_many(Overflow::Overwrite), (vec![1, 2], 3));
+}
+
+#[test]
+fn test_abort_pending() {
+    use futures::executor::block_on;
+    use std::thread;
+
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), i32>("Output".into());
+    let inp = node.get_or_create_port::<i32, ()>("Input".into());
+    out.connect(&inp).unwrap();
+    let reader = {
+        let inp = inp.clone();
+        thread::spawn(move || match block_on(inp.read1()) {
+            Err((_, Error::Cancelled)) => true,
+            _ => false,
+        })
+    };
+    while inp.inner.spin_lock().read_wait.is_empty() {
+        thread::yield_now();
+    }
+    node.abort_pending();
+    assert!(reader.join().unwrap());
+    // reads started afterwards go through
+    block_on(out.clone().write1(1)).ok().unwrap();
+    assert_eq!(block_on(inp.clone().read1()).ok().unwrap().1, 1);
+}
+
+#[test]
+fn test_shutdown() {
+    use futures::executor::block_on;
+
+    let graph = Graph::new();
+    let (a, b) = (graph.add_node(), graph.add_node());
+    let out = a.get_or_create_port::<(), i32>("Output".into());
+    let inp = b.get_or_create_port::<i32, ()>("Input".into());
+    out.connect(&inp).unwrap();
+    block_on(out.clone().write(vec![1, 2])).ok().unwrap();
+
+    // a's output ends, but what it wrote can still be read
+    a.shutdown(false);
+    assert!(match block_on(out.clone().write1(3)) {
+        Err((_, Error::Closed)) => true,
+        _ => false,
+    });
+    assert_eq!(block_on(inp.clone().read1()).ok().unwrap().1, 1);
+    assert_eq!(block_on(inp.clone().read1()).ok().unwrap().1, 2);
+    assert!(match block_on(inp.clone().read1()) {
+        Err((_, Error::EndOfStream)) => true,
+        _ => false,
+    });
+
+    // b drains what's left in its own buffer before ending
+    out.disconnect().unwrap();
+    // the first read after the disconnect reports it
+    assert!(match block_on(inp.clone().read()) {
+        Err((_, Error::Disconnected)) => true,
+        _ => false,
+    });
+    let out = a.get_or_create_port::<(), i32>("Other".into());
+    out.connect(&inp).unwrap();
+    assert_eq!(inp.buffered(), 0);
+    block_on(out.clone().write1(4)).ok().unwrap();
+    b.shutdown(true);
+    assert_eq!(block_on(inp.clone().read()).ok().unwrap().1.into_vec(), vec![4]);
+    assert!(block_on(inp.clone().read()).is_err());
+}
+
+#[test]
+fn test_close() {
+    use futures::executor::block_on;
+
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), i32>("Output".into());
+    let inp = node.get_or_create_port::<i32, ()>("Input".into());
+    assert!(out.close().is_err());
+    out.connect(&inp).unwrap();
+    block_on(out.clone().write1(1)).ok().unwrap();
+    out.close().unwrap();
+    assert_eq!(block_on(inp.clone().read1()).ok().unwrap().1, 1);
+    assert!(match block_on(inp.clone().read1()) {
+        Err((_, Error::EndOfStream)) => true,
+        _ => false,
+    });
+    // and the next write starts over
+    block_on(out.clone().write1(2)).ok().unwrap();
+    assert_eq!(block_on(inp.clone().read1()).ok().unwrap().1, 2);
+}
+
+#[test]
+fn test_try_read_write() {
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), i32>("Output".into());
+    let inp = node.get_or_create_port::<i32, ()>("Input".into());
+    assert_eq!(out.try_write(vec![1]).unwrap_err().0, vec![1]);
+    let options = ConnectOptions {
+        capacity: Some(2),
+        overflow: Overflow::Block,
+        gain: None,
+    };
+    out.connect_with(&inp, options).unwrap();
+    assert!(match inp.try_read() {
+        Err(Error::WouldBlock) => true,
+        _ => false,
+    });
+    out.try_write(vec![1, 2]).unwrap();
+    // full, so the data comes back
+    assert_eq!(out.try_write(vec![3]).unwrap_err().0, vec![3]);
+    assert!(match inp.try_read_n(3) {
+        Err(Error::WouldBlock) => true,
+        _ => false,
+    });
+    assert_eq!(inp.try_read_n(1).unwrap().into_vec(), vec![1]);
+    out.try_write(vec![3]).unwrap();
+    assert_eq!(inp.try_read().unwrap().into_vec(), vec![2, 3]);
+    out.close().unwrap();
+    assert!(match inp.try_read() {
+        Err(Error::EndOfStream) => true,
+        _ => false,
+    });
+    assert!(match inp.try_read() {
+        Err(Error::WouldBlock) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn test_connection_gain() {
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), Frame>("Output".into());
+    let inp = node.get_or_create_port::<Frame, ()>("Input".into());
+    let ones = || {
+        let mut frame = graph.pool().zeros(48000.0, None, (4, 1));
+        frame.data.fill(1.0);
+        frame
+    };
+    let options = ConnectOptions {
+        gain: Some(0.5),
+        ..ConnectOptions::default()
+    };
+    out.connect_with(&inp, options).unwrap();
+    assert_eq!(inp.gain(), Some(0.5));
+    assert!(out.try_write(vec![ones()]).is_ok());
+    assert!(inp.try_read().unwrap()[0].data.iter().all(|&x| x == 0.5));
+    // set from either end
+    inp.set_gain(Some(2.0)).unwrap();
+    assert!(out.try_write(vec![ones()]).is_ok());
+    assert!(inp.try_read().unwrap()[0].data.iter().all(|&x| x == 2.0));
+    let value_out = node.get_or_create_port::<(), f32>("Value Out".into());
+    let value_in = node.get_or_create_port::<f32, ()>("Value In".into());
+    value_out.connect(&value_in).unwrap();
+    assert!(match value_out.set_gain(Some(2.0)) {
+        Err(ConnectError::TypeMismatch) => true,
+        _ => false,
+    });
+    out.disconnect().unwrap();
+    assert!(match out.set_gain(None) {
+        Err(ConnectError::NotConnected) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn test_read_overlapping() {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), i32>("Output".into());
+    let inp = node.get_or_create_port::<i32, ()>("Input".into());
+    out.connect(&inp).unwrap();
+    block_on(out.clone().write(vec![1, 2, 3, 4, 5, 6, 7])).ok().unwrap();
+    let mut windows = inp.clone().read_overlapping(4, 2);
+    let window = block_on(windows.next()).unwrap().ok().unwrap();
+    assert_eq!(window.into_vec(), vec![1, 2, 3, 4]);
+    let window = block_on(windows.next()).unwrap().ok().unwrap();
+    assert_eq!(window.into_vec(), vec![3, 4, 5, 6]);
+    // one new item isn't enough for the next window
+    assert_eq!(inp.buffered(), 1);
+    out.close().unwrap();
+    let err = block_on(windows.next()).unwrap().err().unwrap();
+    assert!(match err {
+        Error::EndOfStream => true,
+        _ => false,
+    });
+    // the overlap is dropped, but not what's left in the buffer
+    block_on(out.clone().write(vec![8, 9, 10])).ok().unwrap();
+    let window = block_on(windows.next()).unwrap().ok().unwrap();
+    assert_eq!(window.into_vec(), vec![7, 8, 9, 10]);
+}
+
+#[test]
+fn test_buffer_drops() {
+    use futures::executor::block_on;
+
+    // items discarded by the port, rather than read, must still be dropped
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), Arc<i32>>("Output".into());
+    let inp = node.get_or_create_port::<Arc<i32>, ()>("Input".into());
+    let options = ConnectOptions {
+        capacity: Some(1),
+        overflow: Overflow::DropOldest,
+        gain: None,
+    };
+    out.connect_with(&inp, options).unwrap();
+    let item = Arc::new(0);
+    for _ in 0..3 {
+        block_on(out.clone().write1(item.clone())).ok().unwrap();
+    }
+    assert_eq!(Arc::strong_count(&item), 2);
+    assert_eq!(inp.as_opaque().flush(), 1);
+    assert_eq!(Arc::strong_count(&item), 1);
+}
+
+#[test]
+fn test_groups() {
+    let graph = Graph::new();
+    let a = graph.add_node();
+    let b = graph.add_node();
+    let group = graph.group("visuals");
+    group.add(a.id()).unwrap();
+    group.add(b.id()).unwrap();
+    graph.group("lights").add(b.id()).unwrap();
+    assert_eq!(graph.groups().into_iter().collect::<Vec<_>>(), vec!["lights", "visuals"]);
+
+    group.set_muted(true);
+    assert!(a.muted() && b.muted());
+    group.set_bypassed(true);
+    assert!(a.bypassed() && b.bypassed());
+    group.remove(a.id()).unwrap();
+    group.set_active(false);
+    assert!(a.active() && !b.active());
+    assert_eq!(group.nodes().len(), 1);
+    assert!(group.add(NodeId(100)).is_err());
+}
+
+#[test]
+fn test_cross_graph() {
+    let a = Graph::new();
+    let b = Graph::new();
+    assert_ne!(a.id(), b.id());
+    // fresh graphs hand out the same ids, which must not be confused
+    let out = a.add_node().get_or_create_port::<(), u8>("Output".into());
+    let input = b.add_node().get_or_create_port::<u8, ()>("Input".into());
+    assert_eq!(out.id(), input.id());
+    match out.connect(&input) {
+        Err(ConnectError::CrossGraph) => {}
+        other => panic!("expected CrossGraph, got {:?}", other),
+    }
+    assert!(out.edge().is_none());
+}
+
+#[test]
+fn test_variadic() {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+
+    let graph = Graph::new();
+    let mixer = graph.add_node();
+    let inputs = mixer.get_or_create_variadic::<u8, ()>("In %d");
+    let added = inputs.subscribe();
+    assert_eq!(inputs.ports().len(), 1);
+    assert_eq!(inputs.ports()[0].name(), "In 1");
+
+    let source = graph.add_node();
+    let a = source.get_or_create_port::<(), u8>("A".into());
+    let b = source.get_or_create_port::<(), u8>("B".into());
+    a.connect(&inputs.ports()[0]).unwrap();
+    assert_eq!(inputs.ports().len(), 2);
+    // connecting anything but the last port doesn't grow the set
+    a.disconnect().unwrap();
+    a.connect(&inputs.ports()[0]).unwrap();
+    assert_eq!(inputs.ports().len(), 2);
+    b.connect(&inputs.ports()[1]).unwrap();
+
+    let names: Vec<_> = mixer.ports().iter().map(|port| port.name().to_string()).collect();
+    assert_eq!(names, vec!["In 1", "In 2", "In 3"]);
+    let added: Vec<_> = block_on(added.take(2).collect());
+    assert_eq!(added.iter().map(|port| port.name()).collect::<Vec<_>>(), vec!["In 2", "In 3"]);
+}
+
+#[test]
+fn test_lifecycle() {
+    use futures::executor::block_on;
+
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), String>("Out".into());
+    let inp = node.get_or_create_port::<String, ()>("In".into());
+    out.connect(&inp).unwrap();
+    block_on(out.clone().write1("a".into())).ok().unwrap();
+
+    graph.set_buffer_policy(BufferPolicy::Preserve);
+    graph.pause();
+    assert_eq!(graph.state(), RunState::Paused);
+    graph.stop();
+    assert_eq!(inp.buffered(), 1);
+    graph.set_buffer_policy(BufferPolicy::Flush);
+    graph.stop();
+    assert_eq!(inp.buffered(), 0);
+
+    graph.start();
+    block_on(graph.block_boundary());
+    block_on(out.clone().write1("b".into())).ok().unwrap();
+    let (_inp, data) = block_on(inp.clone().read()).ok().unwrap();
+    assert_eq!(data.into_vec(), vec!["b".to_string()]);
+}
+
+#[test]
+fn test_connection_events() {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+
+    let graph = Graph::new();
+    let node = graph.add_node();
+    let out = node.get_or_create_port::<(), u8>("Out".into());
+    let inp = node.get_or_create_port::<u8, ()>("In".into());
+    let events = out.connection_events();
+    out.connect(&inp).unwrap();
+    inp.disconnect().unwrap();
+    let events: Vec<_> = block_on(events.take(2).collect());
+    assert_eq!(
+        events,
+        vec![
+            ConnectionEvent::Connected(inp.port_ref()),
+            ConnectionEvent::Disconnected(inp.port_ref()),
+        ]
+    );
+}
+
+#[test]
+fn test_node_meta() {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+
+    let graph = Graph::new();
+    let ifc = graph.add_node();
+    let node = graph.node(ifc.id()).unwrap();
+    let changes = node.meta_changes();
+    node.set_meta("ui.pos", (1.5, 2.0));
+    node.set_meta("ui.comment", "lead");
+    assert_eq!(node.meta::<(f32, f32)>("ui.pos"), Some((1.5, 2.0)));
+    // the wrong type reads as missing
+    assert_eq!(node.meta::<u32>("ui.comment"), None);
+    node.remove_meta("ui.comment");
+    assert_eq!(node.meta_map().len(), 1);
+
+    let changes: Vec<_> = block_on(changes.take(3).collect());
+    let keys: Vec<_> = changes.iter().map(|change| (change.key.as_str(), change.value.is_some())).collect();
+    assert_eq!(keys, vec![("ui.pos", true), ("ui.comment", true), ("ui.comment", false)]);
+}
+
+#[test]
+fn test_apply() {
+    let graph = Graph::new();
+    let a = graph.add_node();
+    let b = graph.add_node();
+    let c = graph.add_node();
+    let out = a.get_or_create_port::<(), u8>("Out".into());
+    let b_in = b.get_or_create_port::<u8, ()>("In".into());
+    let c_in = c.get_or_create_port::<u8, ()>("In".into());
+    out.connect(&b_in).unwrap();
+
+    // fails on the last op, after the first two would have rewired the graph
+    let result = graph.apply(vec![
+        GraphOp::Disconnect(b_in.port_ref()),
+        GraphOp::Connect(out.port_ref(), c_in.port_ref(), ConnectOptions::default()),
+        GraphOp::Connect(out.port_ref(), b_in.port_ref(), ConnectOptions::default()),
+    ]);
+    assert!(match result {
+        Err(BatchError::Connect(2, ConnectError::AlreadyConnected)) => true,
+        _ => false,
+    });
+    assert_eq!(out.edge().unwrap().id(), b_in.id());
+    assert!(c_in.edge().is_none());
+
+    let result = graph.apply(vec![
+        GraphOp::RemoveNode(b.id()),
+        GraphOp::SetParam(c.id(), "Gain".into(), 1.0),
+    ]);
+    assert!(match result {
+        Err(BatchError::InvalidParam(1)) => true,
+        _ => false,
+    });
+    assert!(graph.node(b.id()).is_some());
+
+    let generation = graph.generation();
+    let id = graph.reserve_id();
+    graph
+        .apply(vec![
+            GraphOp::RemoveNode(b.id()),
+            GraphOp::Connect(out.port_ref(), c_in.port_ref(), ConnectOptions::default()),
+            GraphOp::AddNode(
+                id,
+                Box::new(|ifc: Arc<Interface>| {
+                    ifc.get_or_create_port::<(), u8>("Out".into());
+                }),
+            ),
+        ])
+        .unwrap();
+    assert!(graph.generation() > generation);
+    assert!(graph.node(b.id()).is_none());
+    assert!(b_in.edge().is_none());
+    assert_eq!(out.edge().unwrap().id(), c_in.id());
+    assert_eq!(graph.node(id).unwrap().ports().len(), 1);
+
+    // ops other than AddNode can be logged and replayed
+    let ops = vec![GraphOp::RemoveNode(NodeId(1)), GraphOp::SetParam(NodeId(2), "Gain".into(), 0.5)];
+    let json = ::serde_json::to_string(&ops).unwrap();
+    let ops: Vec<GraphOp> = ::serde_json::from_str(&json).unwrap();
+    assert_eq!(format!("{:?}", ops[1]), "SetParam(NodeId(2), \"Gain\", 0.5)");
+}
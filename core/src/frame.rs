@@ -0,0 +1,40 @@
+//! Frames of audio, the signal most ports carry.
+
+use ndarray::Array2;
+
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Frame {
+    pub rate: f32,
+    /// Sample counter of the first sample in the frame, if the source keeps time.
+    pub time: Option<u64>,
+    pub data: Array2<f32>,
+    /// Metadata carried along with the frame, shared between copies of it.
+    pub meta: Option<Arc<FrameMeta>>,
+}
+
+/// Structured data travelling with a frame, like beat markers set upstream. Modules processing
+/// frames pass on the metadata of their first input, so it survives an effects chain.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameMeta {
+    /// Names of the channels in order, if the source knows them.
+    pub layout: Option<Vec<String>>,
+    /// Values set by modules along the way, by name.
+    pub tags: BTreeMap<String, Value>,
+}
+
+impl Frame {
+    /// The tag `name` set on the frame upstream.
+    pub fn tag(&self, name: &str) -> Option<&Value> {
+        self.meta.as_ref().and_then(|meta| meta.tags.get(name))
+    }
+    /// Tag the frame, copying its metadata first if other frames share it.
+    pub fn set_tag(&mut self, name: &str, value: Value) {
+        let meta = self.meta.get_or_insert_with(Arc::default);
+        Arc::make_mut(meta).tags.insert(name.into(), value);
+    }
+}
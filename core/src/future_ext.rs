@@ -0,0 +1,239 @@
+#[cfg(not(feature = "safe-ports"))]
+use std::cell::UnsafeCell;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+#[cfg(feature = "safe-ports")]
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::task::{Context, Poll, Waker};
+
+use crossbeam::sync::SegQueue;
+
+use futures::future::{BoxFuture, FutureObj};
+use futures::task::{Spawn, SpawnError};
+
+/// Spawns tasks with a function, to start modules on an executor that doesn't implement `Spawn`,
+/// like tokio, async-std or smol:
+///
+/// ```ignore
+/// module.start(SpawnFn(|task| {
+///     tokio::spawn(task);
+/// }));
+/// ```
+#[derive(Clone)]
+pub struct SpawnFn<F>(pub F);
+
+impl<F: Fn(BoxFuture<'static, ()>)> Spawn for SpawnFn<F> {
+    fn spawn_obj(&self, task: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        (self.0)(Box::pin(task));
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Breaker {
+    broken: Arc<AtomicBool>,
+}
+impl Breaker {
+    pub fn new() -> Breaker {
+        Breaker {
+            broken: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    pub fn brake(&self) {
+        self.broken.store(true, Ordering::Relaxed);
+    }
+    pub fn test(&self) -> bool {
+        self.broken.load(Ordering::Relaxed)
+    }
+}
+
+/// A lock/mutex where attempting to lock produces a Future
+/// But you can also spin with `spin_lock` or try with `try_lock`
+///
+/// TODO think about/implement poisoning
+///
+/// With the `safe-ports` feature, the data is kept in a `Mutex` instead of an `UnsafeCell` guarded
+/// by a flag, and `spin_lock` blocks on it rather than spinning.
+#[cfg(not(feature = "safe-ports"))]
+pub struct Lock<T> {
+    flag: AtomicBool,
+    queue: SegQueue<Waker>,
+    data: UnsafeCell<T>,
+}
+#[cfg(not(feature = "safe-ports"))]
+impl<T> Lock<T> {
+    pub fn new(data: T) -> Lock<T> {
+        Lock {
+            flag: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+            queue: SegQueue::new(),
+        }
+    }
+    pub fn lock(&self) -> LockFuture<T> {
+        LockFuture {
+            lock: self,
+        }
+    }
+    pub fn try_lock(&self) -> Option<LockGuard<T>> {
+        if self.acquire() {
+            Some(LockGuard {
+                lock: self,
+            })
+        } else {
+            None
+        }
+    }
+    pub fn spin_lock(&self) -> LockGuard<T> {
+        while !self.acquire() {}
+        LockGuard {
+            lock: self,
+        }
+    }
+    /// Lock from within `poll`, waking the task once the lock is released if it's held.
+    pub fn poll_lock(&self, cx: &mut Context) -> Poll<LockGuard<T>> {
+        for attempt in 0..2 {
+            if !self.acquire() {
+                // if we failed to lock, register this future to be notified upon next release and
+                // try again in case it gets unlocked in between trying to lock and pushing to the
+                // queue. tuning the number of tries before pushing to the queue may marginally
+                // improve performance.
+                if attempt == 0 {
+                    self.queue.push(cx.waker().clone());
+                } else {
+                    return Poll::Pending;
+                }
+            } else {
+                break;
+            }
+        }
+        Poll::Ready(LockGuard {
+            lock: self,
+        })
+    }
+    /// Set the flag if it isn't already, returning whether it was set.
+    fn acquire(&self) -> bool {
+        self.flag
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+#[cfg(not(feature = "safe-ports"))]
+pub struct LockGuard<'a, T: 'a> {
+    lock: &'a Lock<T>,
+}
+#[cfg(not(feature = "safe-ports"))]
+impl<'a, T: 'a> Drop for LockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.flag.store(false, Ordering::Release);
+
+        // wake anyone that was waiting for the lock
+        self.lock.queue.try_pop().map(|x| x.wake());
+    }
+}
+#[cfg(not(feature = "safe-ports"))]
+impl<'a, T: 'a> Deref for LockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+#[cfg(not(feature = "safe-ports"))]
+impl<'a, T: 'a> DerefMut for LockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+#[cfg(feature = "safe-ports")]
+pub struct Lock<T> {
+    data: Mutex<T>,
+    queue: SegQueue<Waker>,
+}
+#[cfg(feature = "safe-ports")]
+impl<T> Lock<T> {
+    pub fn new(data: T) -> Lock<T> {
+        Lock {
+            data: Mutex::new(data),
+            queue: SegQueue::new(),
+        }
+    }
+    pub fn lock(&self) -> LockFuture<T> {
+        LockFuture {
+            lock: self,
+        }
+    }
+    pub fn try_lock(&self) -> Option<LockGuard<T>> {
+        let guard = match self.data.try_lock() {
+            Ok(guard) => guard,
+            // a panic while locked leaves the data as it was, like the flag does
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(LockGuard {
+            lock: self,
+            guard: Some(guard),
+        })
+    }
+    pub fn spin_lock(&self) -> LockGuard<T> {
+        let guard = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        LockGuard {
+            lock: self,
+            guard: Some(guard),
+        }
+    }
+    /// Lock from within `poll`, waking the task once the lock is released if it's held.
+    pub fn poll_lock(&self, cx: &mut Context) -> Poll<LockGuard<T>> {
+        // as above, try again after registering in case the lock was released in between
+        for attempt in 0..2 {
+            match self.try_lock() {
+                Some(guard) => return Poll::Ready(guard),
+                None if attempt == 0 => self.queue.push(cx.waker().clone()),
+                None => {}
+            }
+        }
+        Poll::Pending
+    }
+}
+#[cfg(feature = "safe-ports")]
+pub struct LockGuard<'a, T: 'a> {
+    lock: &'a Lock<T>,
+    /// Only taken when dropped, to unlock before waking anyone.
+    guard: Option<MutexGuard<'a, T>>,
+}
+#[cfg(feature = "safe-ports")]
+impl<'a, T: 'a> Drop for LockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.guard.take();
+
+        // wake anyone that was waiting for the lock
+        self.lock.queue.try_pop().map(|x| x.wake());
+    }
+}
+#[cfg(feature = "safe-ports")]
+impl<'a, T: 'a> Deref for LockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+#[cfg(feature = "safe-ports")]
+impl<'a, T: 'a> DerefMut for LockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+pub struct LockFuture<'a, T: 'a> {
+    lock: &'a Lock<T>,
+}
+impl<'a, T: 'a> Future for LockFuture<'a, T> {
+    type Output = LockGuard<'a, T>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<LockGuard<'a, T>> {
+        self.lock.poll_lock(cx)
+    }
+}
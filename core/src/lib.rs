@@ -0,0 +1,78 @@
+//! The graph, ports and block scheduling of flow-synth.
+//!
+//! With the `std` feature, which flow-synth turns on, this is the dataflow graph the synth runs on:
+//! nodes and their typed ports in `flow`, the `Frame`s most ports carry, `Process` and the
+//! `BlockScheduler` running blocks on the audio thread, and what the graph keeps besides, like
+//! scenes, mappings, tempo and tuning. The modules, audio interfaces and GUI stay in flow-synth,
+//! which re-exports these modules.
+//!
+//! Without it only `alloc` is needed, and `embedded` has a graph and a single threaded, poll based
+//! scheduler cut down for microcontrollers driving an installation, re-exported at the root.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "std", feature(never_type))]
+#![cfg_attr(feature = "std", allow(dead_code, unused_variables))]
+
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+#[cfg(feature = "std")]
+extern crate crossbeam;
+#[cfg(feature = "std")]
+extern crate futures;
+#[cfg(feature = "std")]
+extern crate libc;
+#[cfg(feature = "std")]
+extern crate ndarray;
+#[cfg(feature = "std")]
+extern crate serde;
+#[cfg(feature = "std")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "std")]
+extern crate serde_json;
+
+pub mod embedded;
+
+#[cfg(feature = "std")]
+pub mod compare;
+#[cfg(feature = "std")]
+pub mod declick;
+#[cfg(feature = "std")]
+pub mod flow;
+#[cfg(feature = "std")]
+pub mod frame;
+#[cfg(feature = "std")]
+pub mod future_ext;
+#[cfg(feature = "std")]
+pub mod mapping;
+#[cfg(feature = "std")]
+pub mod partition;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod process;
+#[cfg(feature = "std")]
+pub mod randomize;
+#[cfg(feature = "std")]
+pub mod scene;
+#[cfg(feature = "std")]
+pub mod scheduler;
+#[cfg(feature = "std")]
+pub mod simd;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod solo;
+#[cfg(feature = "std")]
+pub mod timeline;
+#[cfg(feature = "std")]
+pub mod tuning;
+#[cfg(feature = "std")]
+pub mod workers;
+
+pub use crate::embedded::{Block, Error, Graph, NodeId, PortRef, Scheduler};
@@ -0,0 +1,108 @@
+//! Mapping MIDI controllers and OSC addresses to the params of any node.
+//!
+//! A `Mapping` binds a control, a MIDI CC or an OSC address, to a param a node exposes through
+//! `Interface::set_params`, scaling the control's `0.0..=1.0` onto a range of the param along a
+//! curve. Mappings belong to the graph, so every module with params can be played from a
+//! controller without doing anything itself, and they're saved with the patch.
+//!
+//! To map a control, `Graph::learn` a param and move the control: the first control to move after
+//! that is bound to it, replacing whatever the param was mapped to before, and learning ends.
+//!
+//! The `Control In` module of flow-synth feeds its graph the controls it receives over MIDI and
+//! OSC.
+
+use crate::flow::NodeId;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Control {
+    /// A MIDI control change, with a zero based channel.
+    Cc {
+        channel: u8,
+        number: u8,
+    },
+    Osc(String),
+}
+
+/// How a control value moves through the range of a param.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Curve {
+    Linear,
+    /// Equal ratios for equal steps, for frequencies and times. Ranges not above zero at both
+    /// ends are taken linearly.
+    Exponential,
+    /// Moving quickly at first and then slowing down, for levels.
+    Logarithmic,
+}
+
+impl Default for Curve {
+    fn default() -> Curve {
+        Curve::Linear
+    }
+}
+
+/// A param to set from a control, and the values it ranges over.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Target {
+    pub node: NodeId,
+    pub param: String,
+    /// The value at the bottom of the control.
+    pub min: f32,
+    /// The value at the top of the control.
+    pub max: f32,
+    #[serde(default)]
+    pub curve: Curve,
+}
+
+impl Target {
+    /// The value of the param for the control at `x`.
+    pub fn value(&self, x: f32) -> f32 {
+        let x = x.max(0.0).min(1.0);
+        match self.curve {
+            Curve::Exponential if self.min > 0.0 && self.max > 0.0 => {
+                self.min * (self.max / self.min).powf(x)
+            }
+            Curve::Logarithmic => self.min + (self.max - self.min) * (1.0 + 9.0 * x).log10(),
+            _ => self.min + (self.max - self.min) * x,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mapping {
+    pub control: Control,
+    pub target: Target,
+}
+
+/// The mappings of a graph, and the param being learned, if any.
+#[derive(Clone, Debug, Default)]
+pub struct Mappings {
+    pub mappings: Vec<Mapping>,
+    pub learning: Option<Target>,
+}
+
+impl Mappings {
+    /// Take a control's new value, binding it to the param being learned first, and give the
+    /// params it sets.
+    pub fn control(&mut self, control: &Control, x: f32) -> Vec<(NodeId, String, f32)> {
+        if let Some(target) = self.learning.take() {
+            self.unmap(target.node, &target.param);
+            self.mappings.push(Mapping {
+                control: control.clone(),
+                target,
+            });
+        }
+        self.mappings
+            .iter()
+            .filter(|mapping| mapping.control == *control)
+            .map(|mapping| {
+                let target = &mapping.target;
+                (target.node, target.param.clone(), target.value(x))
+            })
+            .collect()
+    }
+    /// Remove the mappings to the param `param` of `node`.
+    pub fn unmap(&mut self, node: NodeId, param: &str) {
+        self.mappings
+            .retain(|mapping| !(mapping.target.node == node && mapping.target.param == param));
+    }
+}
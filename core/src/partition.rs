@@ -0,0 +1,121 @@
+//! Splitting the block schedule across threads.
+//!
+//! With more than one worker (`Graph::set_workers`), `BlockScheduler` cuts its steps into branches:
+//! chains of blocks where each one is the only reader of the one before. A step reading more than
+//! one other step, or a step read by more than one, starts a new branch. Branches are shared out
+//! between the workers by how long their blocks take, and each worker runs its branches in order
+//! on its own thread.
+//!
+//! Edges between branches are double buffered: the reading side gets the frame written on the
+//! previous buffer, so the branches of one buffer never wait for each other. This delays the signal
+//! by one buffer at every branch boundary, whichever workers the branches end up on, so moving a
+//! branch to another worker can't be heard. The scheduler lays the branches out again whenever the
+//! graph changes, and every `REBALANCE_INTERVAL` buffers if the measured load has drifted.
+
+/// Buffers between checks of whether the branches are still spread evenly.
+pub const REBALANCE_INTERVAL: usize = 256;
+/// How much longer than a fresh layout the busiest worker may take before branches are moved.
+pub const REBALANCE_THRESHOLD: f32 = 1.25;
+/// How quickly the measured cost of a step follows its latest buffer.
+pub const LOAD_SMOOTHING: f32 = 0.05;
+/// Seconds counted for a step which hasn't been measured yet, so new branches still get spread.
+pub const MIN_COST: f32 = 1e-6;
+
+/// The branch of each step, numbered from 0 in order of their first steps. `sources` lists the
+/// steps each step reads, which come before it unless the edge is feedback.
+pub fn branches(sources: &[Vec<usize>]) -> Vec<usize> {
+    let mut readers = vec![0; sources.len()];
+    for (idx, sources) in sources.iter().enumerate() {
+        let mut seen = Vec::new();
+        for &source in sources {
+            if source < idx && !seen.contains(&source) {
+                seen.push(source);
+                readers[source] += 1;
+            }
+        }
+    }
+    let mut branches = Vec::with_capacity(sources.len());
+    let mut count = 0;
+    for (idx, sources) in sources.iter().enumerate() {
+        let mut earlier: Vec<_> = sources.iter().cloned().filter(|&source| source < idx).collect();
+        earlier.sort();
+        earlier.dedup();
+        let branch = match earlier.len() {
+            1 if readers[earlier[0]] == 1 => branches[earlier[0]],
+            _ => {
+                count += 1;
+                count - 1
+            }
+        };
+        branches.push(branch);
+    }
+    branches
+}
+
+/// Share the branches between at most `workers` lanes, heaviest first to the least loaded lane,
+/// returning the steps of each non-empty lane in order. `costs` is the time each step takes.
+pub fn lanes(branches: &[usize], costs: &[f32], workers: usize) -> Vec<Vec<usize>> {
+    let count = branches.iter().map(|&branch| branch + 1).max().unwrap_or(0);
+    let mut loads = vec![0.0; count];
+    for (&branch, &cost) in branches.iter().zip(costs) {
+        loads[branch] += cost.max(MIN_COST);
+    }
+    let mut order: Vec<_> = (0..count).collect();
+    order.sort_by(|&a, &b| loads[b].partial_cmp(&loads[a]).unwrap_or(a.cmp(&b)));
+
+    let mut lanes = vec![0.0f32; workers.max(1).min(count.max(1))];
+    let mut lane_of = vec![0; count];
+    for branch in order {
+        let lightest = (0..lanes.len())
+            .min_by(|&a, &b| lanes[a].partial_cmp(&lanes[b]).unwrap_or(a.cmp(&b)))
+            .unwrap_or(0);
+        lanes[lightest] += loads[branch];
+        lane_of[branch] = lightest;
+    }
+    let mut steps = vec![Vec::new(); lanes.len()];
+    for (idx, &branch) in branches.iter().enumerate() {
+        steps[lane_of[branch]].push(idx);
+    }
+    steps.retain(|lane| !lane.is_empty());
+    steps
+}
+
+/// How long the busiest of `lanes` takes.
+pub fn load(lanes: &[Vec<usize>], costs: &[f32]) -> f32 {
+    lanes
+        .iter()
+        .map(|lane| lane.iter().map(|&idx| costs[idx]).sum::<f32>())
+        .fold(0.0, f32::max)
+}
+
+/// Whether laying the branches out again would take the busiest lane down by more than
+/// `REBALANCE_THRESHOLD`.
+pub fn unbalanced(lanes: &[Vec<usize>], branches: &[usize], costs: &[f32], workers: usize) -> bool {
+    let fresh = load(&self::lanes(branches, costs, workers), costs);
+    load(lanes, costs) > fresh * REBALANCE_THRESHOLD
+}
+
+#[test]
+fn test_partition() {
+    // two chains, 0 -> 1 and 2 -> 3, mixed by 4, which also feeds back into 0
+    let sources = vec![vec![4], vec![0], vec![], vec![2], vec![1, 3]];
+    let branches = branches(&sources);
+    assert_eq!(branches, vec![0, 0, 1, 1, 2]);
+    // a split starts a branch on both sides
+    assert_eq!(self::branches(&[vec![], vec![0], vec![0]]), vec![0, 1, 2]);
+
+    let costs = [1.0, 1.0, 3.0, 1.0, 1.0];
+    let two = lanes(&branches, &costs, 2);
+    assert_eq!(two, vec![vec![2, 3], vec![0, 1, 4]]);
+    assert_eq!(load(&two, &costs), 4.0);
+    // no more lanes than branches
+    assert_eq!(lanes(&branches, &costs, 8).len(), 3);
+    assert_eq!(lanes(&branches, &costs, 1), vec![vec![0, 1, 2, 3, 4]]);
+    // before anything is measured, branches are spread evenly
+    assert_eq!(lanes(&branches, &[0.0; 5], 2), vec![vec![0, 1, 4], vec![2, 3]]);
+
+    // the mixer gets heavier, so it's better off on its own
+    let costs = [1.0, 1.0, 0.5, 0.5, 4.0];
+    assert!(unbalanced(&two, &branches, &costs, 2));
+    assert!(!unbalanced(&lanes(&branches, &costs, 2), &branches, &costs, 2));
+}
@@ -0,0 +1,135 @@
+//! Recycling of frame buffers.
+//!
+//! Every block of audio needs fresh sample buffers, and allocating them on the audio path costs
+//! time and fragments the heap. Each graph has a `FramePool` holding buffers which finished
+//! frames give back, sorted by size, so steady state processing allocates nothing.
+
+use crate::frame::Frame;
+
+use ndarray::Array2;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Buffers kept per size. Beyond this, returned buffers are freed.
+const MAX_FREE: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PoolStats {
+    /// Buffers allocated because none of the right size were free.
+    pub allocated: usize,
+    /// Buffers handed out again after being recycled.
+    pub reused: usize,
+    /// Buffers waiting to be reused.
+    pub free: usize,
+}
+
+pub struct FramePool {
+    free: Mutex<HashMap<usize, Vec<Vec<f32>>>>,
+    allocated: AtomicUsize,
+    reused: AtomicUsize,
+}
+
+impl FramePool {
+    pub fn new() -> FramePool {
+        FramePool {
+            free: Mutex::new(HashMap::new()),
+            allocated: AtomicUsize::new(0),
+            reused: AtomicUsize::new(0),
+        }
+    }
+    /// Get a buffer of `len` samples with unspecified contents.
+    fn take(&self, len: usize) -> Vec<f32> {
+        let buffer = match self.free.lock().unwrap().get_mut(&len) {
+            Some(free) => free.pop(),
+            None => None,
+        };
+        match buffer {
+            Some(buffer) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                vec![0.0; len]
+            }
+        }
+    }
+    /// A silent frame of `dim` (samples, channels).
+    pub fn zeros(&self, rate: f32, time: Option<u64>, dim: (usize, usize)) -> Frame {
+        let mut buffer = self.take(dim.0 * dim.1);
+        for x in &mut buffer {
+            *x = 0.0;
+        }
+        Frame {
+            rate,
+            time,
+            data: Array2::from_shape_vec(dim, buffer).unwrap(),
+            meta: None,
+        }
+    }
+    /// A silent frame with the rate, time, shape and metadata of `like`.
+    pub fn silence(&self, like: &Frame) -> Frame {
+        Frame {
+            meta: like.meta.clone(),
+            ..self.zeros(like.rate, like.time, like.data.dim())
+        }
+    }
+    /// Copy a frame into a pooled buffer.
+    pub fn copy(&self, frame: &Frame) -> Frame {
+        let dim = frame.data.dim();
+        let buffer = self.take(dim.0 * dim.1);
+        let mut data = Array2::from_shape_vec(dim, buffer).unwrap();
+        data.assign(&frame.data);
+        Frame {
+            rate: frame.rate,
+            time: frame.time,
+            data,
+            meta: frame.meta.clone(),
+        }
+    }
+    /// Give a frame's buffer back once it's no longer needed.
+    pub fn recycle(&self, frame: Frame) {
+        if !frame.data.is_standard_layout() {
+            return;
+        }
+        let buffer = frame.data.into_raw_vec();
+        let mut free = self.free.lock().unwrap();
+        let free = free.entry(buffer.len()).or_insert_with(Vec::new);
+        if free.len() < MAX_FREE {
+            free.push(buffer);
+        }
+    }
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            allocated: self.allocated.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            free: self.free.lock().unwrap().values().map(|free| free.len()).sum(),
+        }
+    }
+}
+
+#[test]
+fn test_pool() {
+    let pool = FramePool::new();
+    let mut a = pool.zeros(48000.0, None, (4, 2));
+    a.data.fill(1.0);
+    let b = pool.copy(&a);
+    assert_eq!(b.data, a.data);
+    pool.recycle(a);
+    pool.recycle(b);
+
+    // recycled buffers come back cleared, and other sizes still allocate
+    let c = pool.zeros(48000.0, None, (2, 4));
+    assert!(c.data.iter().all(|&x| x == 0.0));
+    pool.zeros(48000.0, None, (8, 2));
+    assert_eq!(
+        pool.stats(),
+        PoolStats {
+            allocated: 3,
+            reused: 1,
+            free: 1,
+        }
+    );
+}
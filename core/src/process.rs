@@ -0,0 +1,41 @@
+//! Processes turning input frames into output frames, the unit the block scheduler runs.
+//!
+//! flow-synth's `Processor` wraps a `Process` into a module, doing the port plumbing and
+//! registering it with the `BlockScheduler`.
+
+use crate::flow;
+use crate::frame::Frame;
+
+use std::sync::Arc;
+
+pub trait Process: Send + 'static {
+    const NAME: &'static str;
+    /// Names of the audio input ports. There must be at least one.
+    const INPUTS: &'static [&'static str];
+    /// Names of the audio output ports.
+    const OUTPUTS: &'static [&'static str];
+    /// Names and initial values of the control inputs, which take `f32`s.
+    const PARAMS: &'static [(&'static str, f32)] = &[];
+    /// Lowest and highest values of the params, in the order of `PARAMS`, see `Params::range`.
+    /// Params past the end have no range.
+    const RANGES: &'static [(f32, f32)] = &[];
+    /// Channel counts declared on the audio inputs and outputs, see `PortMeta::channels`. Frames
+    /// on ports without a count have as many channels as the first input.
+    const INPUT_CHANNELS: Option<usize> = None;
+    const OUTPUT_CHANNELS: Option<usize> = None;
+    /// Whether the outputs are control rate rather than audio, for processes like envelopes and
+    /// LFOs, which are then run less often. See `flow::Signal::Control`.
+    const CONTROL_RATE: bool = false;
+    /// Samples of delay between the inputs and outputs, declared with `Interface::set_latency`.
+    const LATENCY: usize = 0;
+    fn new() -> Self;
+    /// Called once on construction with the node's interface, for processes that consult their
+    /// graph, like its tempo map. Keep only a `Weak` to the graph, which holds the process.
+    fn attach(&mut self, ifc: &Arc<flow::Interface>) {}
+    /// Called when a value arrives on the control input `PARAMS[idx]`, and once with each initial
+    /// value on construction.
+    fn set_param(&mut self, idx: usize, value: f32) {}
+    /// Fill in `outputs` from `inputs`. Outputs start as silent frames as long as the first input,
+    /// with `OUTPUT_CHANNELS` channels.
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]);
+}
@@ -0,0 +1,189 @@
+//! Randomizing and mutating the params of a patch, for exploring variations of it.
+//!
+//! Only params which declare a range through `Params::range` are changed, and never beyond it.
+//! `randomize` moves each param towards a random value of its range, all the way with an amount of
+//! `1.0`, while `mutate` nudges a few params by a small step, like a step in a search. Params can
+//! be locked to keep them as they are, see `Node::lock_param`.
+
+use crate::flow::{Graph, NodeId};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The chance of each param in scope to change in a mutation.
+pub const MUTATION_RATE: f32 = 0.2;
+/// How far a mutation moves a param at most, in parts of its range.
+pub const MUTATION_STEP: f32 = 0.1;
+
+/// The nodes whose params are changed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    All,
+    Nodes(Vec<NodeId>),
+    /// The nodes with a tag.
+    Tagged(String),
+}
+
+/// A tiny xorshift generator. The state must not be zero.
+#[derive(Clone, Debug)]
+pub struct Rng(pub u64);
+
+impl Rng {
+    /// A generator seeded from the clock.
+    pub fn from_time() -> Rng {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Rng((now.as_secs() << 32 ^ now.subsec_nanos() as u64).max(1))
+    }
+    /// The next value, between 0.0 and 1.0.
+    pub fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A param which may be changed, with its value and range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub node: NodeId,
+    pub param: String,
+    pub value: f32,
+    pub range: (f32, f32),
+}
+
+impl Candidate {
+    fn clamp(&self, value: f32) -> f32 {
+        value.max(self.range.0).min(self.range.1)
+    }
+}
+
+/// The unlocked params with a range of the nodes in `scope`, in node order.
+pub fn candidates(graph: &Graph, scope: &Scope) -> Vec<Candidate> {
+    let mut nodes: Vec<_> = graph
+        .nodes()
+        .into_iter()
+        .filter(|node| match *scope {
+            Scope::All => true,
+            Scope::Nodes(ref ids) => ids.contains(&node.id()),
+            Scope::Tagged(ref tag) => node.has_tag(tag),
+        })
+        .collect();
+    nodes.sort_by_key(|node| node.id());
+    let mut candidates = Vec::new();
+    for node in nodes {
+        let params = match node.params() {
+            Some(params) => params,
+            None => continue,
+        };
+        let locked = node.locked_params();
+        for param in params.names() {
+            if locked.contains(&param) {
+                continue;
+            }
+            if let (Some(value), Some(range)) = (params.get(&param), params.range(&param)) {
+                candidates.push(Candidate {
+                    node: node.id(),
+                    param,
+                    value,
+                    range,
+                });
+            }
+        }
+    }
+    candidates
+}
+
+/// The new values of a randomization: each param moves by `amount`, in `0.0..=1.0`, of the way to
+/// a random value of its range.
+pub fn randomize(candidates: &[Candidate], amount: f32, rng: &mut Rng) -> Vec<(NodeId, String, f32)> {
+    let amount = amount.max(0.0).min(1.0);
+    candidates
+        .iter()
+        .map(|candidate| {
+            let (min, max) = candidate.range;
+            let random = min + (max - min) * rng.next();
+            let value = candidate.value + (random - candidate.value) * amount;
+            (candidate.node, candidate.param.clone(), candidate.clamp(value))
+        })
+        .collect()
+}
+
+/// The new values of a mutation: each param changes with a chance of `MUTATION_RATE`, and at least
+/// one does, by up to `MUTATION_STEP` of its range either way.
+pub fn mutate(candidates: &[Candidate], rng: &mut Rng) -> Vec<(NodeId, String, f32)> {
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+    let mut chosen: Vec<_> = candidates.iter().filter(|_| rng.next() < MUTATION_RATE).collect();
+    if chosen.is_empty() {
+        let idx = (rng.next() * candidates.len() as f32) as usize;
+        chosen.push(&candidates[idx.min(candidates.len() - 1)]);
+    }
+    chosen
+        .into_iter()
+        .map(|candidate| {
+            let (min, max) = candidate.range;
+            // the sum of two uniform values leans towards small steps
+            let step = (rng.next() + rng.next() - 1.0) * MUTATION_STEP * (max - min);
+            (
+                candidate.node,
+                candidate.param.clone(),
+                candidate.clamp(candidate.value + step),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_randomize() {
+    use crate::scene::ParamList;
+
+    let graph = Graph::new();
+    let gain = graph.add_node();
+    gain.set_params(ParamList::new(&[("Gain", 1.0, (0.0, 2.0))]));
+    let mixer = graph.add_node();
+    let levels = ["Level 1", "Level 2", "Level 3", "Level 4"];
+    let levels: Vec<_> = levels.iter().map(|&name| (name, 1.0, (0.0, 1.0))).collect();
+    mixer.set_params(ParamList::new(&levels));
+    let node = graph.node(mixer.id()).unwrap();
+    node.add_tag("mix");
+    node.lock_param("Level 2", true);
+    assert!(node.param_locked("Level 2"));
+
+    let names = |scope| {
+        candidates(&graph, &scope)
+            .into_iter()
+            .map(|candidate| candidate.param)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(Scope::All), vec!["Gain", "Level 1", "Level 3", "Level 4"]);
+    assert_eq!(
+        names(Scope::Tagged("mix".into())),
+        vec!["Level 1", "Level 3", "Level 4"]
+    );
+    assert_eq!(names(Scope::Nodes(vec![gain.id()])), vec!["Gain"]);
+
+    let mut rng = Rng(1);
+    let all = candidates(&graph, &Scope::All);
+    for _ in 0..100 {
+        for (candidate, (_, _, value)) in all.iter().zip(randomize(&all, 1.0, &mut rng)) {
+            assert!(value >= candidate.range.0 && value <= candidate.range.1);
+        }
+        let changes = mutate(&all, &mut rng);
+        assert!(!changes.is_empty());
+        for (node, param, value) in changes {
+            let candidate = all.iter().find(|c| c.node == node && c.param == param).unwrap();
+            let (min, max) = candidate.range;
+            assert!((value - candidate.value).abs() <= MUTATION_STEP * (max - min) + 1e-6);
+        }
+    }
+    assert!(randomize(&all, 0.0, &mut rng)
+        .iter()
+        .zip(&all)
+        .all(|(&(_, _, value), candidate)| value == candidate.value));
+
+    graph.randomize(&Scope::All, 1.0);
+    let params = node.params().unwrap();
+    assert_eq!(params.get("Level 2"), Some(1.0));
+}
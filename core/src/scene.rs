@@ -0,0 +1,219 @@
+//! Scenes, named snapshots of a patch that can be recalled during a performance.
+//!
+//! A scene records whether each node is muted, bypassed and active, along with the parameters the
+//! node exposes through `Interface::set_params`. Recalling a scene over a transition time
+//! interpolates the parameters and fades the level of nodes that fall silent or start sounding,
+//! so one scene crossfades into the next instead of cutting. Nodes the scene doesn't mention are
+//! left alone, so a scene can cover just part of a patch.
+
+use crate::flow::{Graph, Node, NodeId};
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often parameters and levels are updated during a transition.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Named parameters of a node, which scenes save and interpolate.
+pub trait Params: Send + Sync {
+    fn names(&self) -> Vec<String>;
+    fn get(&self, name: &str) -> Option<f32>;
+    fn set(&self, name: &str, value: f32);
+    /// The lowest and highest values that make sense for the param `name`, if it declares them.
+    /// Tools exploring a patch, like `Graph::randomize`, leave params without a range alone.
+    fn range(&self, name: &str) -> Option<(f32, f32)> {
+        None
+    }
+}
+
+/// Params with fixed names and ranges, standing in for a module's in tests.
+#[cfg(test)]
+pub struct ParamList(::std::sync::Mutex<Vec<(&'static str, f32, (f32, f32))>>);
+
+#[cfg(test)]
+impl ParamList {
+    /// Params with names, initial values and ranges.
+    pub fn new(params: &[(&'static str, f32, (f32, f32))]) -> Arc<ParamList> {
+        Arc::new(ParamList(::std::sync::Mutex::new(params.to_vec())))
+    }
+}
+
+#[cfg(test)]
+impl Params for ParamList {
+    fn names(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().map(|&(name, _, _)| name.to_string()).collect()
+    }
+    fn get(&self, name: &str) -> Option<f32> {
+        self.0.lock().unwrap().iter().find(|param| param.0 == name).map(|param| param.1)
+    }
+    fn set(&self, name: &str, value: f32) {
+        if let Some(param) = self.0.lock().unwrap().iter_mut().find(|param| param.0 == name) {
+            param.1 = value;
+        }
+    }
+    fn range(&self, name: &str) -> Option<(f32, f32)> {
+        self.0.lock().unwrap().iter().find(|param| param.0 == name).map(|param| param.2)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeState {
+    pub muted: bool,
+    pub bypassed: bool,
+    pub active: bool,
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+}
+
+impl NodeState {
+    pub fn capture(node: &Node) -> NodeState {
+        let params = node
+            .params()
+            .map(|params| {
+                params
+                    .names()
+                    .into_iter()
+                    .filter_map(|name| params.get(&name).map(|value| (name, value)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        NodeState {
+            muted: node.muted(),
+            bypassed: node.bypassed(),
+            active: node.active(),
+            params,
+        }
+    }
+    fn audible(&self) -> bool {
+        self.active && !self.muted
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub name: String,
+    pub nodes: BTreeMap<NodeId, NodeState>,
+}
+
+impl Scene {
+    /// Snapshot every node in the graph.
+    pub fn capture(name: &str, graph: &Graph) -> Scene {
+        Scene {
+            name: name.into(),
+            nodes: graph
+                .nodes()
+                .iter()
+                .map(|node| (node.id(), NodeState::capture(node)))
+                .collect(),
+        }
+    }
+
+    /// Move the graph to this scene over `time`, taking over from any transition in progress.
+    pub fn recall(&self, graph: &Arc<Graph>, time: Duration) {
+        let breaker = graph.begin_transition();
+        let fades: Vec<_> = self
+            .nodes
+            .iter()
+            .filter_map(|(&id, to)| graph.node(id).map(|node| Fade::new(node, to.clone())))
+            .collect();
+        if time == Duration::from_secs(0) {
+            for fade in &fades {
+                fade.apply(1.0);
+            }
+            return;
+        }
+        let time = time.as_secs() as f32 + time.subsec_nanos() as f32 * 1e-9;
+        thread::spawn(move || {
+            let start = Instant::now();
+            loop {
+                if breaker.test() {
+                    return;
+                }
+                let elapsed = start.elapsed();
+                let elapsed = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+                let t = (elapsed / time).min(1.0);
+                for fade in &fades {
+                    fade.apply(t);
+                }
+                if t >= 1.0 {
+                    return;
+                }
+                thread::sleep(TICK);
+            }
+        });
+    }
+}
+
+/// The transition of one node from where it was when the recall started.
+struct Fade {
+    node: Arc<Node>,
+    from: NodeState,
+    to: NodeState,
+    levels: (f32, f32),
+}
+
+impl Fade {
+    fn new(node: Arc<Node>, to: NodeState) -> Fade {
+        let from = NodeState::capture(&node);
+        let start = if from.audible() { node.level() } else { 0.0 };
+        let end = if to.audible() { 1.0 } else { 0.0 };
+        if to.audible() {
+            // start sounding right away, faded all the way down
+            node.set_level(start);
+            node.set_muted(to.muted);
+            node.set_active(to.active);
+        }
+        Fade {
+            node,
+            from,
+            to,
+            levels: (start, end),
+        }
+    }
+    fn apply(&self, t: f32) {
+        if let Some(params) = self.node.params() {
+            for (name, &to) in &self.to.params {
+                let from = self.from.params.get(name).cloned().unwrap_or(to);
+                params.set(name, from + (to - from) * t);
+            }
+        }
+        let (start, end) = self.levels;
+        self.node.set_level(start + (end - start) * t);
+        if t >= 0.5 {
+            self.node.set_bypassed(self.to.bypassed);
+        }
+        if t >= 1.0 {
+            self.node.set_muted(self.to.muted);
+            self.node.set_active(self.to.active);
+            self.node.set_level(1.0);
+        }
+    }
+}
+
+#[test]
+fn test_scene() {
+    let graph = Graph::new();
+    let ifc = graph.add_node();
+    ifc.set_params(ParamList::new(&[("Gain", 1.0, (0.0, 2.0))]));
+    let node = graph.node(ifc.id()).unwrap();
+    graph.save_scene("a");
+    node.params().unwrap().set("Gain", 0.0);
+    node.set_muted(true);
+    graph.save_scene("b");
+
+    graph.recall_scene("a", Duration::from_secs(0)).unwrap();
+    assert_eq!(node.params().unwrap().get("Gain"), Some(1.0));
+    assert!(!node.muted());
+
+    // halfway through, the gain is interpolated and the level is fading out
+    let fade = Fade::new(node.clone(), graph.scene("b").unwrap().nodes[&ifc.id()].clone());
+    fade.apply(0.5);
+    assert_eq!(node.params().unwrap().get("Gain"), Some(0.5));
+    assert_eq!(node.level(), 0.5);
+    assert!(!node.muted());
+    fade.apply(1.0);
+    assert!(node.muted());
+    assert_eq!(node.level(), 1.0);
+}
@@ -0,0 +1,742 @@
+//! Synchronous block scheduling.
+//!
+//! Besides running as tasks on the executor, modules may register a `Block` that processes one
+//! buffer at a time. A `BlockScheduler` runs every block module feeding the `Input` of its host
+//! node directly, in dependency order, once per buffer, on the audio thread of flow-synth's
+//! `BlockAudioIO`. This avoids a round trip through the executor per module and keeps the
+//! processing order deterministic, like a plugin host.
+//!
+//! The scheduled region is everything upstream of the host's `Input` that has a block. Ports
+//! connected to anything else read as silence. Connections that close a loop read the frame produced
+//! on the previous buffer instead, giving one buffer of feedback delay.
+//!
+//! In lazy mode (`Graph::set_lazy`) only blocks that are heard are run: those feeding the host
+//! through nodes that are active and not muted. The rest sleep, producing silence. A block that
+//! wakes up is first run for the configured number of pre-roll blocks on its current inputs with
+//! the output discarded, so filters and envelopes have settled by the time it's heard.
+//!
+//! Blocks whose outputs are all declared control rate (`flow::Signal::Control`) only run every
+//! `Graph::control_division` buffers, and hold their outputs in between. Audio inputs connected to
+//! them don't read the held frames, but the last value of each channel ramping from the one before
+//! over the division, so control signals are smoothed up to audio rate.
+//!
+//! With `Graph::set_workers` above 1, independent branches of the schedule run in parallel on
+//! worker threads, trading a buffer of delay between branches for spreading big patches over
+//! cores. See `partition`, and `workers` for keeping the threads on given cores.
+
+use crate::declick::Declick;
+use crate::flow;
+use crate::frame::Frame;
+use crate::partition;
+use crate::pool::FramePool;
+use crate::process::Process;
+use crate::simd;
+use crate::workers::{self, SchedulerConfig, Workers};
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Buffers between runs of control rate blocks, unless the graph sets another division.
+pub const CONTROL_DIVISION: usize = 4;
+
+/// Processes one buffer at a time.
+pub trait Block: Send {
+    /// Fill in `outputs` from `inputs`. Outputs start as silent frames shaped like the buffer.
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]);
+}
+
+impl<P: Process> Block for P {
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        Process::process(self, inputs, outputs);
+    }
+}
+
+/// A block along with the ports it reads from and writes to, in order.
+#[derive(Clone)]
+pub struct BlockNode {
+    pub inputs: Vec<flow::PortId>,
+    pub outputs: Vec<flow::PortId>,
+    pub block: Arc<Mutex<dyn Block>>,
+}
+
+enum Source {
+    Silence,
+    /// The host's capture frame.
+    Capture,
+    /// An output of a scheduled block, by step index and output index.
+    Step(usize, usize),
+}
+
+struct Input {
+    port: flow::PortRef,
+    source: Source,
+    /// The port this input is connected to, so a new connection can be crossfaded.
+    edge: Option<flow::PortRef>,
+    ramp: Option<f32>,
+    channels: Option<usize>,
+    /// The gain of the connection, see `flow::ConnectOptions::gain`.
+    gain: Option<f32>,
+    /// Whether the input is audio rate but reads a control rate step, so it's upsampled.
+    upsample: bool,
+    /// Whether the input reads another branch while the graph is split across workers, so it gets
+    /// the frame from the previous buffer. See `partition`.
+    held: bool,
+}
+
+struct Step {
+    /// The graph node, for its mute, bypass and active flags.
+    owner: Arc<flow::Node>,
+    node: BlockNode,
+    inputs: Vec<Input>,
+    /// Each output's crossfade length.
+    ramps: Vec<Option<f32>>,
+    /// Each output's declared channel count.
+    channels: Vec<Option<usize>>,
+    /// Whether every output is control rate, so the step runs at a divided rate.
+    control: bool,
+}
+
+/// What a step keeps from one buffer to the next. Only the thread running the step's lane touches
+/// it while a buffer is processed.
+struct State {
+    /// Outputs from the most recent buffer.
+    frames: Vec<Frame>,
+    /// Crossfade state of the step's ports, carried over recompiles so that graph changes can be
+    /// smoothed.
+    in_declick: HashMap<flow::PortRef, Declick<Option<flow::PortRef>>>,
+    out_declick: HashMap<flow::PortRef, Declick<(bool, bool, bool, bool)>>,
+    /// Whether the step was skipped on the last buffer in lazy mode.
+    asleep: bool,
+    /// For every output, the values of its channels before the latest run and after, while a
+    /// control step is being upsampled. Empty before the step first runs.
+    controls: Vec<(Vec<f32>, Vec<f32>)>,
+    /// Seconds the step takes per buffer, smoothed.
+    cost: f32,
+}
+
+/// An output read across branches, as it was at the end of the previous buffer.
+struct Held {
+    frame: Frame,
+    control: (Vec<f32>, Vec<f32>),
+}
+
+pub struct BlockScheduler {
+    graph: Arc<flow::Graph>,
+    host: flow::NodeId,
+    generation: Option<usize>,
+    steps: Vec<Step>,
+    result: Source,
+    states: Vec<Mutex<State>>,
+    /// Buffers run, for the divided rate of control steps and rebalancing.
+    tick: usize,
+    /// The branch of each step, see `partition`.
+    branches: Vec<usize>,
+    /// The steps each worker runs, in order.
+    lanes: Vec<Vec<usize>>,
+    /// The thread configuration the lanes were laid out and the workers started for.
+    config: Arc<SchedulerConfig>,
+    threads: Workers,
+    /// The outputs read across branches, with their step and output index.
+    boundary: Vec<(flow::PortRef, (usize, usize))>,
+    /// Their frames from the previous buffer, kept over recompiles so edits don't drop a buffer.
+    held: HashMap<flow::PortRef, Held>,
+}
+
+impl BlockScheduler {
+    /// Schedule the blocks feeding the `Input` port of the node `host`.
+    pub fn new(graph: Arc<flow::Graph>, host: flow::NodeId) -> BlockScheduler {
+        BlockScheduler {
+            graph,
+            host,
+            generation: None,
+            steps: Vec::new(),
+            result: Source::Silence,
+            states: Vec::new(),
+            tick: 0,
+            branches: Vec::new(),
+            lanes: Vec::new(),
+            config: Arc::new(SchedulerConfig::default()),
+            threads: Workers::new(&SchedulerConfig::default()),
+            boundary: Vec::new(),
+            held: HashMap::new(),
+        }
+    }
+    pub fn graph(&self) -> &Arc<flow::Graph> {
+        &self.graph
+    }
+    /// How many lanes the steps were last laid out in, one per thread running them.
+    pub fn lanes(&self) -> usize {
+        self.lanes.len()
+    }
+    /// The worker threads running the lanes besides the audio thread.
+    pub fn workers(&self) -> &Workers {
+        &self.threads
+    }
+
+    /// Work out the processing order. Called automatically when the graph changes.
+    fn compile(&mut self) {
+        self.generation = Some(self.graph.generation());
+        let nodes = self.graph.node_map();
+        let host_input = nodes
+            .get(&self.host)
+            .and_then(|node| node.ports().into_iter().find(|port| port.name() == "Input"));
+
+        // depth first from the host's input, ordering each node after everything it reads from
+        let mut order: Vec<(flow::NodeId, BlockNode)> = Vec::new();
+        let mut visiting = HashSet::new();
+        fn visit(
+            node: flow::NodeId,
+            nodes: &HashMap<flow::NodeId, Arc<flow::Node>>,
+            visiting: &mut HashSet<flow::NodeId>,
+            order: &mut Vec<(flow::NodeId, BlockNode)>,
+        ) {
+            if visiting.contains(&node) || order.iter().any(|&(id, _)| id == node) {
+                return;
+            }
+            let block = match nodes.get(&node).and_then(|node| node.block()) {
+                Some(block) => block,
+                None => return,
+            };
+            visiting.insert(node);
+            let ports = nodes[&node].ports();
+            for input in &block.inputs {
+                if let Some(other) = ports.iter().find(|p| p.id() == *input).and_then(|p| p.edge()) {
+                    visit(other.node_id(), nodes, visiting, order);
+                }
+            }
+            visiting.remove(&node);
+            order.push((node, block));
+        }
+        let upstream = host_input.as_ref().and_then(|port| port.edge());
+        if let Some(ref upstream) = upstream {
+            visit(upstream.node_id(), &nodes, &mut visiting, &mut order);
+        }
+
+        let host = self.host;
+        let position = |port: &Arc<flow::OpaquePort>| -> Source {
+            if port.node_id() == host {
+                return Source::Capture;
+            }
+            order
+                .iter()
+                .position(|&(id, _)| id == port.node_id())
+                .and_then(|step| {
+                    let output = order[step].1.outputs.iter().position(|&id| id == port.id())?;
+                    Some(Source::Step(step, output))
+                })
+                .unwrap_or(Source::Silence)
+        };
+        let previous: Vec<_> = self.steps.iter().map(|step| step.owner.id()).collect();
+        self.steps = order
+            .iter()
+            .map(|&(id, ref block)| {
+                let ports = nodes[&id].ports();
+                let meta = |id: flow::PortId| ports.iter().find(|p| p.id() == id).map(|p| p.meta());
+                let inputs = block
+                    .inputs
+                    .iter()
+                    .map(|&input| {
+                        let port = ports.iter().find(|p| p.id() == input);
+                        let edge = port.and_then(|p| p.edge());
+                        Input {
+                            port: flow::PortRef {
+                                node: id,
+                                port: input,
+                            },
+                            source: edge
+                                .as_ref()
+                                .map(|other| position(other))
+                                .unwrap_or(Source::Silence),
+                            edge: edge.map(|other| other.port_ref()),
+                            ramp: meta(input).and_then(|meta| meta.ramp),
+                            channels: meta(input).and_then(|meta| meta.channels),
+                            gain: port.and_then(|p| p.gain()),
+                            upsample: meta(input).and_then(|meta| meta.signal)
+                                != Some(flow::Signal::Control),
+                            held: false,
+                        }
+                    })
+                    .collect();
+                Step {
+                    owner: nodes[&id].clone(),
+                    node: block.clone(),
+                    inputs,
+                    ramps: block
+                        .outputs
+                        .iter()
+                        .map(|&output| meta(output).and_then(|meta| meta.ramp))
+                        .collect(),
+                    channels: block
+                        .outputs
+                        .iter()
+                        .map(|&output| meta(output).and_then(|meta| meta.channels))
+                        .collect(),
+                    control: !block.outputs.is_empty()
+                        && block.outputs.iter().all(|&output| {
+                            meta(output).and_then(|meta| meta.signal) == Some(flow::Signal::Control)
+                        }),
+                }
+            })
+            .collect();
+        // only inputs reading control steps are upsampled
+        let control: Vec<_> = self.steps.iter().map(|step| step.control).collect();
+        for step in &mut self.steps {
+            for input in &mut step.inputs {
+                input.upsample &= match input.source {
+                    Source::Step(source, _) => control[source],
+                    _ => false,
+                };
+            }
+        }
+        self.result = upstream.map(|port| position(&port)).unwrap_or(Source::Silence);
+
+        // crossfades carry over to the new plan by port and costs by node, everything else starts
+        // afresh
+        let pool = self.graph.pool();
+        let mut in_declick = HashMap::new();
+        let mut out_declick = HashMap::new();
+        let mut costs = HashMap::new();
+        for (id, state) in previous.into_iter().zip(self.states.drain(..)) {
+            let state = state.into_inner().unwrap();
+            costs.insert(id, state.cost);
+            for frame in state.frames {
+                pool.recycle(frame);
+            }
+            in_declick.extend(state.in_declick);
+            out_declick.extend(state.out_declick);
+        }
+        self.states = self
+            .steps
+            .iter()
+            .map(|step| {
+                let outputs = step.node.outputs.iter().map(|&port| flow::PortRef {
+                    node: step.owner.id(),
+                    port,
+                });
+                Mutex::new(State {
+                    frames: Vec::new(),
+                    in_declick: step
+                        .inputs
+                        .iter()
+                        .filter_map(|input| in_declick.remove(&input.port).map(|d| (input.port, d)))
+                        .collect(),
+                    out_declick: outputs
+                        .filter_map(|port| out_declick.remove(&port).map(|d| (port, d)))
+                        .collect(),
+                    asleep: false,
+                    controls: vec![(Vec::new(), Vec::new()); step.node.outputs.len()],
+                    cost: costs.get(&step.owner.id()).cloned().unwrap_or(0.0),
+                })
+            })
+            .collect();
+        let sources: Vec<Vec<usize>> = self
+            .steps
+            .iter()
+            .map(|step| {
+                step.inputs
+                    .iter()
+                    .filter_map(|input| match input.source {
+                        Source::Step(source, _) => Some(source),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        self.branches = partition::branches(&sources);
+        self.layout();
+    }
+
+    /// Apply a new thread configuration. Called on the audio thread, so that it can be pinned.
+    fn configure(&mut self, config: Arc<SchedulerConfig>) {
+        if config.audio_cores != self.config.audio_cores {
+            if let Err(e) = workers::pin(&config.audio_cores) {
+                println!("block scheduler err: {}", e);
+            }
+        }
+        if config.workers != self.config.workers || config.worker_cores != self.config.worker_cores {
+            self.threads = Workers::new(&config);
+        }
+        let relayout = config.workers != self.config.workers;
+        self.config = config;
+        if relayout {
+            self.layout();
+        }
+    }
+
+    /// Share the steps out between the workers, marking the inputs which read across branches if
+    /// there's more than one.
+    fn layout(&mut self) {
+        let workers = self.config.workers;
+        let parallel = workers > 1;
+        let mut boundary = Vec::new();
+        for (idx, step) in self.steps.iter_mut().enumerate() {
+            for input in &mut step.inputs {
+                input.held = match (&input.source, input.edge) {
+                    (&Source::Step(source, output), Some(edge))
+                        if parallel && self.branches[source] != self.branches[idx] =>
+                    {
+                        boundary.push((edge, (source, output)));
+                        true
+                    }
+                    _ => false,
+                };
+            }
+        }
+        boundary.sort();
+        boundary.dedup();
+        let pool = self.graph.pool();
+        let stale: Vec<_> = self
+            .held
+            .keys()
+            .cloned()
+            .filter(|key| !boundary.iter().any(|&(edge, _)| edge == *key))
+            .collect();
+        for key in stale {
+            pool.recycle(self.held.remove(&key).unwrap().frame);
+        }
+        self.boundary = boundary;
+        self.lanes = if parallel {
+            partition::lanes(&self.branches, &self.costs(), workers)
+        } else {
+            vec![(0..self.steps.len()).collect()]
+        };
+    }
+
+    /// The measured time each step takes.
+    fn costs(&self) -> Vec<f32> {
+        self.states.iter().map(|state| state.lock().unwrap().cost).collect()
+    }
+
+    /// Which steps are heard, following inputs back from the host through every node that's
+    /// active and not muted.
+    fn observed(&self) -> Vec<bool> {
+        let mut observed = vec![false; self.steps.len()];
+        if let Source::Step(step, _) = self.result {
+            observed[step] = true;
+        }
+        // feedback reads from later steps, so go until nothing changes
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (idx, step) in self.steps.iter().enumerate().rev() {
+                if !observed[idx] || !step.owner.active() || step.owner.silenced() {
+                    continue;
+                }
+                for input in &step.inputs {
+                    if let Source::Step(source, _) = input.source {
+                        if !observed[source] {
+                            observed[source] = true;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        observed
+    }
+
+    /// Process one buffer, given the host's capture frame, returning the frame arriving at the
+    /// host's input. The frame comes from the graph's pool, and can be recycled once copied out.
+    pub fn run(&mut self, capture: &Frame) -> Frame {
+        // the host keeps asking for audio while the graph is paused, so give it silence
+        if self.graph.state() != flow::RunState::Running {
+            return self.graph.pool().silence(capture);
+        }
+        let config = self.graph.scheduler_config();
+        if !Arc::ptr_eq(&config, &self.config) {
+            self.configure(config);
+        }
+        // while a batch of edits is being applied, keep running the plan from before it
+        let graph = self.graph.clone();
+        if self.generation != Some(graph.generation()) {
+            if let Some(_settled) = graph.try_settled() {
+                self.compile();
+            }
+        }
+        let pool = self.graph.pool();
+        // frames from the previous buffer only make sense if the block size hasn't changed
+        let samples = capture.data.dim().0;
+        let reset = self.steps.iter().zip(&self.states).any(|(step, state)| {
+            let state = state.lock().unwrap();
+            state.frames.len() != step.channels.len()
+                || state.frames.iter().any(|frame| frame.data.dim().0 != samples)
+        }) || self.held.values().any(|held| held.frame.data.dim().0 != samples);
+        if reset {
+            for (step, state) in self.steps.iter().zip(&self.states) {
+                let mut state = state.lock().unwrap();
+                let frames = step
+                    .channels
+                    .iter()
+                    .map(|&channels| silent(capture, channels, &pool))
+                    .collect();
+                for frame in mem::replace(&mut state.frames, frames) {
+                    pool.recycle(frame);
+                }
+            }
+            for (_, held) in self.held.drain() {
+                pool.recycle(held.frame);
+            }
+        }
+
+        let division = self.graph.control_division().max(1);
+        let phase = self.tick % division;
+        self.tick = self.tick.wrapping_add(1);
+
+        // spread the branches out again if the load has shifted
+        let workers = self.config.workers;
+        if workers > 1
+            && self.tick % partition::REBALANCE_INTERVAL == 0
+            && partition::unbalanced(&self.lanes, &self.branches, &self.costs(), workers)
+        {
+            self.layout();
+        }
+
+        let preroll = self.graph.lazy();
+        let observed = match preroll {
+            Some(_) => self.observed(),
+            None => vec![true; self.steps.len()],
+        };
+        {
+            let pass = Pass {
+                steps: &self.steps,
+                states: &self.states,
+                held: &self.held,
+                observed: &observed,
+                capture,
+                pool: &pool,
+                preroll,
+                reset,
+                phase,
+                division,
+            };
+            if let Some((first, rest)) = self.lanes.split_first() {
+                let pass = &pass;
+                let jobs = rest.iter().map(|lane| move || pass.run(lane));
+                self.threads.scoped(jobs, || pass.run(first));
+            }
+        }
+
+        // keep what crosses branches for the next buffer
+        for &(edge, (source, output)) in &self.boundary {
+            let state = self.states[source].lock().unwrap();
+            let held = Held {
+                frame: pool.copy(&state.frames[output]),
+                control: state.controls[output].clone(),
+            };
+            if let Some(old) = self.held.insert(edge, held) {
+                pool.recycle(old.frame);
+            }
+        }
+        resolve(&self.result, None, capture, &pool, &self.states)
+    }
+}
+
+/// One buffer being processed, shared by the threads running its lanes.
+struct Pass<'a> {
+    steps: &'a [Step],
+    states: &'a [Mutex<State>],
+    held: &'a HashMap<flow::PortRef, Held>,
+    observed: &'a [bool],
+    capture: &'a Frame,
+    pool: &'a FramePool,
+    preroll: Option<usize>,
+    /// Whether the previous buffer's frames were just replaced by silence.
+    reset: bool,
+    phase: usize,
+    division: usize,
+}
+
+impl<'a> Pass<'a> {
+    /// Run the steps of a lane, in order.
+    fn run(&self, lane: &[usize]) {
+        for &idx in lane {
+            self.step(idx);
+        }
+    }
+
+    fn step(&self, idx: usize) {
+        let step = &self.steps[idx];
+        let (capture, pool) = (self.capture, self.pool);
+        let silence = |channels: Option<usize>| silent(capture, channels, pool);
+        let began = Instant::now();
+        // control steps hold their outputs between runs, unless they haven't got any yet
+        let fresh = self.reset
+            || self.states[idx]
+                .lock()
+                .unwrap()
+                .controls
+                .iter()
+                .any(|&(_, ref values)| values.is_empty());
+        if step.control && self.phase != 0 && !fresh {
+            return;
+        }
+        let observed = self.observed[idx];
+        let mut outputs: Vec<_> = step.channels.iter().map(|&channels| silence(channels)).collect();
+        let waking = mem::replace(&mut self.states[idx].lock().unwrap().asleep, !observed) && observed;
+        if step.owner.active() && observed {
+            // inputs are read before taking the step's own state, which feedback may read too
+            let mut inputs: Vec<_> = step.inputs.iter().map(|input| self.read(input)).collect();
+            {
+                let mut state = self.states[idx].lock().unwrap();
+                for (input, frame) in step.inputs.iter().zip(&mut inputs) {
+                    match input.gain {
+                        Some(gain) if gain != 1.0 => simd::scale_array(&mut frame.data, gain),
+                        _ => {}
+                    }
+                    if let Some(ramp) = input.ramp {
+                        state
+                            .in_declick
+                            .entry(input.port)
+                            .or_insert_with(Declick::new)
+                            .process(input.edge, ramp, frame);
+                    }
+                }
+            }
+            // the block may replace it, but otherwise metadata flows on from the first input
+            if let Some(input) = inputs.first() {
+                for output in &mut outputs {
+                    output.meta = input.meta.clone();
+                }
+            }
+            if step.owner.bypassed() {
+                if let (Some(input), Some(output)) = (inputs.first(), outputs.first_mut()) {
+                    pool.recycle(mem::replace(output, pool.copy(input)));
+                }
+            } else {
+                let start = Instant::now();
+                let mut block = step.node.block.lock().unwrap();
+                if waking {
+                    for _ in 0..self.preroll.unwrap_or(0) {
+                        block.process(&inputs, &mut outputs);
+                        for output in &mut outputs {
+                            output.data.fill(0.0);
+                        }
+                    }
+                }
+                block.process(&inputs, &mut outputs);
+                step.owner.add_busy(start.elapsed());
+            }
+            for input in inputs {
+                pool.recycle(input);
+            }
+            if step.owner.silenced() {
+                for output in &mut outputs {
+                    output.data.fill(0.0);
+                }
+            }
+            let level = step.owner.level();
+            if level != 1.0 {
+                for output in &mut outputs {
+                    simd::scale_array(&mut output.data, level);
+                }
+            }
+        }
+        let flags = (
+            step.owner.active(),
+            step.owner.bypassed(),
+            step.owner.silenced(),
+            observed,
+        );
+        let mut state = self.states[idx].lock().unwrap();
+        for ((&port, ramp), frame) in step.node.outputs.iter().zip(&step.ramps).zip(&mut outputs) {
+            if let Some(ramp) = *ramp {
+                let port = flow::PortRef {
+                    node: step.owner.id(),
+                    port,
+                };
+                state
+                    .out_declick
+                    .entry(port)
+                    .or_insert_with(Declick::new)
+                    .process(flags, ramp, frame);
+            }
+        }
+        if step.control {
+            for (values, frame) in state.controls.iter_mut().zip(&outputs) {
+                let latest: Vec<f32> = match frame.data.outer_iter().last() {
+                    Some(row) => row.to_vec(),
+                    None => vec![0.0; frame.data.dim().1],
+                };
+                let previous = mem::replace(&mut values.1, latest);
+                values.0 = if previous.is_empty() {
+                    values.1.clone()
+                } else {
+                    previous
+                };
+            }
+        }
+        for frame in mem::replace(&mut state.frames, outputs) {
+            pool.recycle(frame);
+        }
+        let elapsed = began.elapsed();
+        let seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+        state.cost += (seconds - state.cost) * partition::LOAD_SMOOTHING;
+    }
+
+    /// The frame arriving at an input.
+    fn read(&self, input: &Input) -> Frame {
+        let (capture, pool) = (self.capture, self.pool);
+        if input.held {
+            return match input.edge.and_then(|edge| self.held.get(&edge)) {
+                Some(held) if input.upsample => {
+                    upsample(&held.control, self.phase, self.division, capture, pool)
+                }
+                Some(held) => pool.copy(&held.frame),
+                None => silent(capture, input.channels, pool),
+            };
+        }
+        match input.source {
+            Source::Step(source, output) if input.upsample => {
+                let state = self.states[source].lock().unwrap();
+                upsample(&state.controls[output], self.phase, self.division, capture, pool)
+            }
+            ref source => resolve(source, input.channels, capture, pool, self.states),
+        }
+    }
+}
+
+/// A silent frame as long as `capture`, with `channels` channels or as many as `capture` has.
+fn silent(capture: &Frame, channels: Option<usize>, pool: &FramePool) -> Frame {
+    let (samples, capture_channels) = capture.data.dim();
+    pool.zeros(capture.rate, capture.time, (samples, channels.unwrap_or(capture_channels)))
+}
+
+/// Copy the frame an input reads into a buffer from `pool`. Unconnected inputs get silence with
+/// `channels` channels.
+fn resolve(
+    source: &Source,
+    channels: Option<usize>,
+    capture: &Frame,
+    pool: &FramePool,
+    states: &[Mutex<State>],
+) -> Frame {
+    match *source {
+        Source::Silence => silent(capture, channels, pool),
+        Source::Capture => pool.copy(capture),
+        Source::Step(step, output) => pool.copy(&states[step].lock().unwrap().frames[output]),
+    }
+}
+
+/// An audio frame shaped like `capture`, moving from the values of a control output before its
+/// latest run to the latest ones over the `division` buffers until the next, reaching them at the
+/// end. `phase` is how many of those buffers have gone by.
+fn upsample(
+    values: &(Vec<f32>, Vec<f32>),
+    phase: usize,
+    division: usize,
+    capture: &Frame,
+    pool: &FramePool,
+) -> Frame {
+    let (ref from, ref to) = *values;
+    let samples = capture.data.dim().0;
+    let mut frame = pool.zeros(capture.rate, capture.time, (samples, to.len()));
+    let period = (division * samples) as f32;
+    for ((row, channel), x) in frame.data.indexed_iter_mut() {
+        let t = (phase * samples + row + 1) as f32 / period;
+        let from = from.get(channel).cloned().unwrap_or(to[channel]);
+        *x = from + (to[channel] - from) * t;
+    }
+    frame
+}
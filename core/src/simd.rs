@@ -0,0 +1,219 @@
+//! Vectorized kernels for block processing.
+//!
+//! Each kernel checks what the CPU supports at runtime and falls back to a scalar loop, so
+//! builds stay portable. The `_array` variants take frame data, and fall back to ndarray's own
+//! operations when the data isn't contiguous.
+
+use ndarray::Array2;
+
+/// Multiply every sample by `gain`.
+pub fn scale(data: &mut [f32], gain: f32) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            return unsafe { x86::scale(data, gain) };
+        }
+    }
+    scale_scalar(data, gain)
+}
+
+/// Add `src` multiplied by `gain` to `dst`, which must be the same length.
+pub fn mix(dst: &mut [f32], src: &[f32], gain: f32) {
+    assert_eq!(dst.len(), src.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            return unsafe { x86::mix(dst, src, gain) };
+        }
+    }
+    mix_scalar(dst, src, gain)
+}
+
+pub fn scale_array(data: &mut Array2<f32>, gain: f32) {
+    match data.as_slice_mut() {
+        Some(data) => scale(data, gain),
+        None => *data *= gain,
+    }
+}
+
+pub fn mix_array(dst: &mut Array2<f32>, src: &Array2<f32>, gain: f32) {
+    if dst.dim() == src.dim() {
+        if let (Some(dst), Some(src)) = (dst.as_slice_mut(), src.as_slice()) {
+            return mix(dst, src, gain);
+        }
+    }
+    dst.scaled_add(gain, src);
+}
+
+fn scale_scalar(data: &mut [f32], gain: f32) {
+    for x in data {
+        *x *= gain;
+    }
+}
+
+fn mix_scalar(dst: &mut [f32], src: &[f32], gain: f32) {
+    for (x, &y) in dst.iter_mut().zip(src) {
+        *x += y * gain;
+    }
+}
+
+/// A second order filter applied to every channel of interleaved data, in transposed direct
+/// form II. Channels are filtered four at a time.
+pub struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    /// Filter state for each channel.
+    z1: Vec<f32>,
+    z2: Vec<f32>,
+}
+
+impl Biquad {
+    /// A filter that passes everything through.
+    pub fn new() -> Biquad {
+        Biquad {
+            b: [1.0, 0.0, 0.0],
+            a: [0.0, 0.0],
+            z1: Vec::new(),
+            z2: Vec::new(),
+        }
+    }
+    /// Set the coefficients for a resonant lowpass, from the Audio EQ Cookbook.
+    pub fn set_lowpass(&mut self, cutoff: f32, q: f32, rate: f32) {
+        let w0 = 2.0 * ::std::f32::consts::PI * (cutoff / rate).min(0.49).max(0.0);
+        let alpha = w0.sin() / (2.0 * q.max(0.01));
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        self.b = [(1.0 - cos) / 2.0 / a0, (1.0 - cos) / a0, (1.0 - cos) / 2.0 / a0];
+        self.a = [-2.0 * cos / a0, (1.0 - alpha) / a0];
+    }
+    /// Filter interleaved samples with `channels` channels in place.
+    pub fn process(&mut self, data: &mut [f32], channels: usize) {
+        if channels == 0 {
+            return;
+        }
+        if self.z1.len() != channels {
+            self.z1 = vec![0.0; channels];
+            self.z2 = vec![0.0; channels];
+        }
+        let mut channel = 0;
+        #[cfg(target_arch = "x86_64")]
+        {
+            // SSE is part of x86_64, so there's nothing to detect
+            while channel + 4 <= channels {
+                unsafe { x86::biquad4(self, data, channels, channel) };
+                channel += 4;
+            }
+        }
+        for channel in channel..channels {
+            self.process_scalar(data, channels, channel);
+        }
+    }
+    fn process_scalar(&mut self, data: &mut [f32], channels: usize, channel: usize) {
+        let (b, a) = (self.b, self.a);
+        let (mut z1, mut z2) = (self.z1[channel], self.z2[channel]);
+        let mut idx = channel;
+        while idx < data.len() {
+            let x = data[idx];
+            let y = b[0] * x + z1;
+            z1 = b[1] * x - a[0] * y + z2;
+            z2 = b[2] * x - a[1] * y;
+            data[idx] = y;
+            idx += channels;
+        }
+        self.z1[channel] = z1;
+        self.z2[channel] = z2;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{mix_scalar, scale_scalar, Biquad};
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn scale(data: &mut [f32], gain: f32) {
+        let n = data.len() / 8 * 8;
+        let ptr = data.as_mut_ptr();
+        let gain_v = _mm256_set1_ps(gain);
+        let mut i = 0;
+        while i < n {
+            _mm256_storeu_ps(ptr.add(i), _mm256_mul_ps(_mm256_loadu_ps(ptr.add(i)), gain_v));
+            i += 8;
+        }
+        scale_scalar(&mut data[n..], gain);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn mix(dst: &mut [f32], src: &[f32], gain: f32) {
+        let n = dst.len() / 8 * 8;
+        let (dst_ptr, src_ptr) = (dst.as_mut_ptr(), src.as_ptr());
+        let gain_v = _mm256_set1_ps(gain);
+        let mut i = 0;
+        while i < n {
+            let y = _mm256_mul_ps(_mm256_loadu_ps(src_ptr.add(i)), gain_v);
+            _mm256_storeu_ps(dst_ptr.add(i), _mm256_add_ps(_mm256_loadu_ps(dst_ptr.add(i)), y));
+            i += 8;
+        }
+        mix_scalar(&mut dst[n..], &src[n..], gain);
+    }
+
+    /// Filter the four channels starting at `channel`.
+    pub unsafe fn biquad4(filter: &mut Biquad, data: &mut [f32], channels: usize, channel: usize) {
+        let b0 = _mm_set1_ps(filter.b[0]);
+        let b1 = _mm_set1_ps(filter.b[1]);
+        let b2 = _mm_set1_ps(filter.b[2]);
+        let a1 = _mm_set1_ps(filter.a[0]);
+        let a2 = _mm_set1_ps(filter.a[1]);
+        let mut z1 = _mm_loadu_ps(filter.z1.as_ptr().add(channel));
+        let mut z2 = _mm_loadu_ps(filter.z2.as_ptr().add(channel));
+        let ptr = data.as_mut_ptr();
+        let mut idx = channel;
+        while idx + 4 <= data.len() {
+            let x = _mm_loadu_ps(ptr.add(idx));
+            let y = _mm_add_ps(_mm_mul_ps(b0, x), z1);
+            z1 = _mm_add_ps(_mm_sub_ps(_mm_mul_ps(b1, x), _mm_mul_ps(a1, y)), z2);
+            z2 = _mm_sub_ps(_mm_mul_ps(b2, x), _mm_mul_ps(a2, y));
+            _mm_storeu_ps(ptr.add(idx), y);
+            idx += channels;
+        }
+        _mm_storeu_ps(filter.z1.as_mut_ptr().add(channel), z1);
+        _mm_storeu_ps(filter.z2.as_mut_ptr().add(channel), z2);
+    }
+}
+
+#[test]
+fn test_kernels() {
+    // odd lengths exercise the scalar tails
+    let src: Vec<f32> = (0..37).map(|x| x as f32 * 0.25 - 3.0).collect();
+    let mut scaled = src.clone();
+    scale(&mut scaled, 0.5);
+    let mut expected = src.clone();
+    scale_scalar(&mut expected, 0.5);
+    assert_eq!(scaled, expected);
+
+    let mut mixed = vec![1.0; 37];
+    mix(&mut mixed, &src, 2.0);
+    let mut expected = vec![1.0; 37];
+    mix_scalar(&mut expected, &src, 2.0);
+    assert_eq!(mixed, expected);
+
+    // five channels, so both the vector and scalar paths run
+    let channels = 5;
+    let mut data: Vec<f32> = (0..channels * 64)
+        .map(|x| ((x * 7919) % 13) as f32 - 6.0)
+        .collect();
+    let mut filter = Biquad::new();
+    filter.set_lowpass(1000.0, 0.707, 48000.0);
+    let mut reference = Biquad::new();
+    reference.set_lowpass(1000.0, 0.707, 48000.0);
+    let mut expected = data.clone();
+    reference.z1 = vec![0.0; channels];
+    reference.z2 = vec![0.0; channels];
+    for channel in 0..channels {
+        reference.process_scalar(&mut expected, channels, channel);
+    }
+    filter.process(&mut data, channels);
+    for (x, y) in data.iter().zip(&expected) {
+        assert!((x - y).abs() < 1e-4);
+    }
+}
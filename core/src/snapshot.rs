@@ -0,0 +1,203 @@
+//! Consistent pictures of a graph, for renderers and other observers.
+//!
+//! Walking `Graph::nodes()` and querying each port can observe the graph in the middle of an
+//! edit, e.g. a connection whose other end was just removed. `Graph::snapshot` copies everything
+//! needed to draw a frame of a UI in one go, and starts over if the topology changed while it was
+//! copying, so the picture is always of the graph between two edits. Parameter values, levels and
+//! port counters are live values, read once each.
+
+use crate::flow::{Graph, Node, NodeId, OpaquePort, PortId, PortMeta, PortRef, RunState};
+use crate::pool::PoolStats;
+
+use serde_json::Value;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortSnapshot {
+    pub id: PortId,
+    pub name: String,
+    pub in_type: &'static str,
+    pub out_type: &'static str,
+    pub meta: PortMeta,
+    /// The port on the other end, which is always in the same snapshot.
+    pub edge: Option<PortRef>,
+    /// The gain of the connection, see `ConnectOptions::gain`.
+    pub gain: Option<f32>,
+    pub buffered: usize,
+    pub dropped: usize,
+    pub received: usize,
+}
+
+impl PortSnapshot {
+    fn capture(port: &OpaquePort) -> PortSnapshot {
+        PortSnapshot {
+            id: port.id(),
+            name: port.name().into(),
+            in_type: port.in_type_name(),
+            out_type: port.out_type_name(),
+            meta: port.meta(),
+            edge: port.edge().map(|other| other.port_ref()),
+            gain: port.gain(),
+            buffered: port.buffered(),
+            dropped: port.dropped(),
+            received: port.received(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeSnapshot {
+    pub id: NodeId,
+    /// Ports in id order.
+    pub ports: Vec<PortSnapshot>,
+    pub tags: BTreeSet<String>,
+    pub muted: bool,
+    /// Silenced by a solo of other nodes, see `solo`.
+    pub solo_muted: bool,
+    pub bypassed: bool,
+    pub active: bool,
+    pub level: f32,
+    pub latency: usize,
+    pub params: BTreeMap<String, f32>,
+    pub meta: BTreeMap<String, Value>,
+}
+
+impl NodeSnapshot {
+    fn capture(node: &Node) -> NodeSnapshot {
+        let mut ports: Vec<_> = node
+            .ports()
+            .iter()
+            .map(|port| PortSnapshot::capture(&**port))
+            .collect();
+        ports.sort_by_key(|port| port.id);
+        let params = node
+            .params()
+            .map(|params| {
+                params
+                    .names()
+                    .into_iter()
+                    .filter_map(|name| params.get(&name).map(|value| (name, value)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        NodeSnapshot {
+            id: node.id(),
+            ports,
+            tags: node.tags(),
+            muted: node.muted(),
+            solo_muted: node.solo_muted(),
+            bypassed: node.bypassed(),
+            active: node.active(),
+            level: node.level(),
+            latency: node.latency(),
+            params,
+            meta: node.meta_map(),
+        }
+    }
+    pub fn port(&self, id: PortId) -> Option<&PortSnapshot> {
+        self.ports.iter().find(|port| port.id == id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphSnapshot {
+    /// The graph's `generation` when the snapshot was taken.
+    pub generation: usize,
+    pub state: RunState,
+    pub nodes: BTreeMap<NodeId, NodeSnapshot>,
+    pub pool: PoolStats,
+}
+
+impl GraphSnapshot {
+    /// Copy the state of `graph`. See `Graph::snapshot`.
+    pub fn capture(graph: &Graph) -> GraphSnapshot {
+        // batches from `Graph::apply` are waited out, and single edits are rare next to reads, so
+        // retrying until none lands in between is cheap
+        let _settled = graph.settled();
+        loop {
+            let generation = graph.generation();
+            let mut nodes: BTreeMap<_, _> = graph
+                .nodes()
+                .iter()
+                .map(|node| (node.id(), NodeSnapshot::capture(node)))
+                .collect();
+            if graph.generation() != generation {
+                continue;
+            }
+            // ports of a removed node stay connected until they're dropped, but aren't in the graph
+            let ids: BTreeSet<PortRef> = nodes
+                .values()
+                .flat_map(|node| {
+                    node.ports.iter().map(move |port| PortRef {
+                        node: node.id,
+                        port: port.id,
+                    })
+                })
+                .collect();
+            for port in nodes.values_mut().flat_map(|node| node.ports.iter_mut()) {
+                if port.edge.map_or(false, |edge| !ids.contains(&edge)) {
+                    port.edge = None;
+                }
+            }
+            return GraphSnapshot {
+                generation,
+                state: graph.state(),
+                nodes,
+                pool: graph.pool().stats(),
+            };
+        }
+    }
+    pub fn node(&self, id: NodeId) -> Option<&NodeSnapshot> {
+        self.nodes.get(&id)
+    }
+    pub fn port(&self, port: PortRef) -> Option<&PortSnapshot> {
+        self.node(port.node)?.port(port.port)
+    }
+    /// Every connection once, with the lower port first.
+    pub fn edges(&self) -> Vec<(PortRef, PortRef)> {
+        self.nodes
+            .values()
+            .flat_map(|node| {
+                node.ports.iter().filter_map(move |port| {
+                    let from = PortRef {
+                        node: node.id,
+                        port: port.id,
+                    };
+                    port.edge.filter(|&to| from < to).map(|to| (from, to))
+                })
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_snapshot() {
+    let graph = Graph::new();
+    let a = graph.add_node();
+    let b = graph.add_node();
+    let a_out = a.get_or_create_port::<(), f32>("Output".into());
+    let b_in = b.get_or_create_port::<f32, ()>("Input".into());
+    a_out.connect(&b_in).unwrap();
+    graph.node(b.id()).unwrap().set_muted(true);
+
+    let snapshot = graph.snapshot();
+    assert_eq!(snapshot.generation, graph.generation());
+    assert_eq!(snapshot.edges(), vec![(a_out.port_ref(), b_in.port_ref())]);
+    assert_eq!(
+        snapshot.port(b_in.port_ref()).unwrap().edge,
+        Some(a_out.port_ref())
+    );
+    assert!(snapshot.node(b.id()).unwrap().muted);
+
+    // adding a port is an edit, and the other end of a removed node's connection is let go
+    let generation = graph.generation();
+    b.get_or_create_port::<(), f32>("Output".into());
+    assert!(graph.generation() > generation);
+    graph.remove_node(a.id()).unwrap();
+    let snapshot = graph.snapshot();
+    assert_eq!(snapshot.nodes.len(), 1);
+    assert_eq!(snapshot.node(b.id()).unwrap().ports.len(), 2);
+    assert!(snapshot.edges().is_empty());
+    assert_eq!(snapshot.port(b_in.port_ref()).unwrap().edge, None);
+}
@@ -0,0 +1,111 @@
+//! Soloing sources and channels of a mix.
+//!
+//! Nodes tagged `source` or `channel` take part: soloing one silences all the others that aren't
+//! soloed too, through the same flag as muting them, so schedulers and modules fade them out as
+//! they would a muted node. In `Exclusive` mode soloing a node releases the one soloed before,
+//! while in `Additive` mode solos add up. The solo is separate from each node's own mute, which
+//! keeps its setting and still silences the node when it's soloed, and isn't saved with the patch.
+
+use crate::flow::{Graph, Node, NodeId};
+
+use std::collections::BTreeSet;
+
+/// Tags of the nodes a solo applies to.
+pub const TAGS: &[&str] = &["source", "channel"];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SoloMode {
+    /// One node is soloed at a time.
+    Exclusive,
+    /// Any number of nodes are soloed together.
+    Additive,
+}
+
+impl Default for SoloMode {
+    fn default() -> SoloMode {
+        SoloMode::Exclusive
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Solo {
+    pub mode: SoloMode,
+    pub soloed: BTreeSet<NodeId>,
+}
+
+impl Solo {
+    /// Solo the node `id`, or release it.
+    pub fn set(&mut self, id: NodeId, solo: bool) {
+        if !solo {
+            self.soloed.remove(&id);
+            return;
+        }
+        if self.mode == SoloMode::Exclusive {
+            self.soloed.clear();
+        }
+        self.soloed.insert(id);
+    }
+    /// Change the mode, keeping only the lowest soloed node when switching to `Exclusive`.
+    pub fn set_mode(&mut self, mode: SoloMode) {
+        self.mode = mode;
+        if mode == SoloMode::Exclusive {
+            let first = self.soloed.iter().next().cloned();
+            self.soloed = first.into_iter().collect();
+        }
+    }
+    /// Whether the solo silences `node`.
+    pub fn silences(&self, node: &Node) -> bool {
+        !self.soloed.is_empty() && !self.soloed.contains(&node.id()) && takes_part(node)
+    }
+}
+
+/// Whether `node` is tagged as a source or a channel.
+pub fn takes_part(node: &Node) -> bool {
+    TAGS.iter().any(|tag| node.has_tag(tag))
+}
+
+/// The nodes taking part in solos, in id order.
+pub fn nodes(graph: &Graph) -> Vec<NodeId> {
+    let mut ids: Vec<_> = graph
+        .nodes()
+        .iter()
+        .filter(|node| takes_part(node))
+        .map(|node| node.id())
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn test_solo() {
+    let graph = Graph::new();
+    let ids: Vec<_> = (0..4).map(|_| graph.add_node().id()).collect();
+    for (&id, tag) in ids.iter().zip(&["source", "source", "channel", "bus"]) {
+        graph.node(id).unwrap().add_tag(tag);
+    }
+    assert_eq!(nodes(&graph), &ids[..3]);
+    let silenced = || {
+        ids.iter()
+            .map(|&id| graph.node(id).unwrap().solo_muted())
+            .collect::<Vec<_>>()
+    };
+
+    graph.set_solo(ids[0], true).unwrap();
+    assert_eq!(silenced(), vec![false, true, true, false]);
+    graph.set_solo(ids[1], true).unwrap();
+    assert_eq!(silenced(), vec![true, false, true, false]);
+
+    graph.set_solo_mode(SoloMode::Additive);
+    graph.set_solo(ids[2], true).unwrap();
+    assert_eq!(silenced(), vec![true, false, false, false]);
+    // muting by hand is kept apart from the solo
+    let node = graph.node(ids[0]).unwrap();
+    assert!(!node.muted() && node.silenced());
+
+    graph.set_solo_mode(SoloMode::Exclusive);
+    assert_eq!(graph.solo().soloed.into_iter().collect::<Vec<_>>(), vec![ids[1]]);
+    graph.clear_solo();
+    assert_eq!(silenced(), vec![false; 4]);
+    assert!(graph.set_solo(NodeId(100), true).is_err());
+}
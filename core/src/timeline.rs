@@ -0,0 +1,209 @@
+//! Tempo maps, placing bars and beats along the transport.
+//!
+//! Every graph has a tempo map, see `Graph::set_tempo_map`, for modules that follow the bars and
+//! beats, like flow-synth's `Transport`.
+
+/// A change of tempo, and possibly of time signature, on a `TempoMap`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempoChange {
+    /// Where the change falls, in quarter notes from the start.
+    pub beat: f64,
+    /// Quarter notes per minute.
+    pub tempo: f32,
+    /// Beats per bar, and the note value of a beat, e.g. `(6, 8)`.
+    pub signature: (u32, u32),
+}
+
+impl TempoChange {
+    /// Quarter notes in a beat.
+    fn beat_len(&self) -> f64 {
+        4.0 / self.signature.1 as f64
+    }
+    /// Quarter notes in a bar.
+    fn bar_len(&self) -> f64 {
+        self.signature.0 as f64 * self.beat_len()
+    }
+}
+
+/// Where a time falls on a `TempoMap`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Position {
+    /// Bars since the start, counting from 0.
+    pub bar: u64,
+    /// Beats into the bar, in the signature's note value, the fraction being the progress
+    /// towards the next one.
+    pub beat: f64,
+    pub tempo: f32,
+    pub signature: (u32, u32),
+}
+
+/// The run of a `TempoMap` from one change to the next.
+struct Segment<'a> {
+    change: &'a TempoChange,
+    /// Seconds from the start to the change.
+    seconds: f64,
+    /// Bars from the start to the change.
+    bar: f64,
+}
+
+/// Tempo and time signature changes along the transport. The first change is at the start, and
+/// each holds until the next. A tempo change keeps the bar lines running, while a signature
+/// change starts a new bar, cutting short the one it falls in.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempoMap {
+    /// Sorted by beat, never empty.
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    /// 120 BPM in 4/4 throughout.
+    pub fn new() -> TempoMap {
+        TempoMap {
+            changes: vec![TempoChange {
+                beat: 0.0,
+                tempo: 120.0,
+                signature: (4, 4),
+            }],
+        }
+    }
+    /// Parse changes of the form `beat tempo [beats/value]`, separated by `;` or newlines, with
+    /// beats in quarter notes. A change without a signature keeps the one before, and there has
+    /// to be a change at beat 0. For example `0 120 4/4; 32 90; 64 140 7/8`.
+    pub fn parse(s: &str) -> Option<TempoMap> {
+        let mut parsed = Vec::new();
+        let lines = s.split(|c| c == ';' || c == '\n').filter(|change| !change.trim().is_empty());
+        for change in lines {
+            let mut words = change.split_whitespace();
+            let beat: f64 = words.next()?.parse().ok()?;
+            let tempo: f32 = words.next()?.parse().ok()?;
+            let signature = match words.next() {
+                Some(word) => {
+                    let mut parts = word.splitn(2, '/');
+                    let beats: u32 = parts.next()?.parse().ok()?;
+                    let value: u32 = parts.next()?.parse().ok()?;
+                    if beats == 0 || value == 0 {
+                        return None;
+                    }
+                    Some((beats, value))
+                }
+                None => None,
+            };
+            if !(beat >= 0.0) || !(tempo > 0.0) || words.next().is_some() {
+                return None;
+            }
+            parsed.push((beat, tempo, signature));
+        }
+        parsed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        if parsed.first().map_or(true, |first| first.0 != 0.0) {
+            return None;
+        }
+        let mut changes: Vec<TempoChange> = Vec::new();
+        for (beat, tempo, signature) in parsed {
+            if changes.last().map_or(false, |last| last.beat == beat) {
+                return None;
+            }
+            let signature = signature.unwrap_or_else(|| changes.last().map_or((4, 4), |last| last.signature));
+            changes.push(TempoChange {
+                beat,
+                tempo,
+                signature,
+            });
+        }
+        Some(TempoMap { changes })
+    }
+    pub fn describe(&self) -> String {
+        let mut signature = None;
+        let changes: Vec<String> = self
+            .changes
+            .iter()
+            .map(|change| {
+                let mut s = format!("{} {}", change.beat, change.tempo);
+                if signature != Some(change.signature) {
+                    s += &format!(" {}/{}", change.signature.0, change.signature.1);
+                    signature = Some(change.signature);
+                }
+                s
+            })
+            .collect();
+        changes.join("; ")
+    }
+    /// The last segment for which `before` holds, walking from the start.
+    fn find<F: Fn(&Segment) -> bool>(&self, before: F) -> Segment {
+        let mut found = Segment {
+            change: &self.changes[0],
+            seconds: 0.0,
+            bar: 0.0,
+        };
+        for change in &self.changes[1..] {
+            let quarters = change.beat - found.change.beat;
+            let mut bar = found.bar + quarters / found.change.bar_len();
+            if change.signature != found.change.signature {
+                bar = bar.ceil();
+            }
+            let next = Segment {
+                change,
+                seconds: found.seconds + quarters * 60.0 / found.change.tempo as f64,
+                bar,
+            };
+            if !before(&next) {
+                break;
+            }
+            found = next;
+        }
+        found
+    }
+    /// Quarter notes from the start to `seconds`.
+    pub fn beats_at(&self, seconds: f64) -> f64 {
+        let segment = self.find(|segment| segment.seconds <= seconds);
+        segment.change.beat + (seconds - segment.seconds) * segment.change.tempo as f64 / 60.0
+    }
+    /// Seconds from the start to `beats` quarter notes.
+    pub fn seconds_at(&self, beats: f64) -> f64 {
+        let segment = self.find(|segment| segment.change.beat <= beats);
+        segment.seconds + (beats - segment.change.beat) * 60.0 / segment.change.tempo as f64
+    }
+    /// Seconds from the start to the first beat of `bar`.
+    pub fn bar_seconds(&self, bar: u64) -> f64 {
+        let segment = self.find(|segment| segment.bar <= bar as f64);
+        self.seconds_at(segment.change.beat + (bar as f64 - segment.bar) * segment.change.bar_len())
+    }
+    pub fn position(&self, seconds: f64) -> Position {
+        let beats = self.beats_at(seconds);
+        let segment = self.find(|segment| segment.change.beat <= beats);
+        let quarters = beats - segment.change.beat;
+        let bar = (segment.bar + quarters / segment.change.bar_len()).floor();
+        // counted from the bar line rather than the fraction of the bar, to keep whole beats whole
+        let into_bar = quarters - (bar - segment.bar) * segment.change.bar_len();
+        Position {
+            bar: bar as u64,
+            beat: into_bar / segment.change.beat_len(),
+            tempo: segment.change.tempo,
+            signature: segment.change.signature,
+        }
+    }
+}
+
+#[test]
+fn test_tempo_map() {
+    let map = TempoMap::parse("0 120 4/4; 8 240\n 14 60 3/4").unwrap();
+    assert_eq!(map.describe(), "0 120 4/4; 8 240; 14 60 3/4");
+    assert_eq!(map.beats_at(5.0), 12.0);
+    assert_eq!(map.seconds_at(16.0), 7.5);
+    // the 3/4 starts a new bar halfway through the fourth
+    assert_eq!(
+        map.position(7.5),
+        Position {
+            bar: 4,
+            beat: 2.0,
+            tempo: 60.0,
+            signature: (3, 4),
+        }
+    );
+    assert_eq!(map.bar_seconds(3), 5.0);
+    assert_eq!(map.bar_seconds(4), 5.5);
+    assert_eq!(map.bar_seconds(5), 8.5);
+    assert_eq!(map.position(3.75).beat, 3.5);
+    assert!(TempoMap::parse("4 120").is_none());
+    assert!(TempoMap::parse("0 120 4/0").is_none());
+    assert!(TempoMap::parse("0 120; 0 90").is_none());
+}
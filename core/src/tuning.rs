@@ -0,0 +1,337 @@
+//! Tunings, mapping key numbers to frequencies.
+//!
+//! A tuning is a scale in the Scala `.scl` format, with the keyboard mapping of a `.kbm` file
+//! placing it on the keys, or by default one scale step per key with degree 0 on key 60 and key 69
+//! at 440 Hz. Every graph has one active tuning, twelve tone equal temperament until a `Tuning`
+//! node sets another, see `Graph::set_tuning`.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The degrees of a scale, in cents above its first, which isn't listed. The last degree is the
+/// period the scale repeats at, usually the octave.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scale {
+    pub description: String,
+    pub cents: Vec<f64>,
+}
+
+/// Where a scale lies on the keys, as in a `.kbm` file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyboardMapping {
+    /// The range of keys which are tuned at all.
+    pub first: i32,
+    pub last: i32,
+    /// The key playing the scale's first degree.
+    pub middle: i32,
+    /// The key tuned to `frequency`.
+    pub reference: i32,
+    pub frequency: f64,
+    /// The degree the mapping repeats at, once per `map.len()` keys.
+    pub octave_degree: usize,
+    /// The degree played by each key from the middle one on, or None for keys left silent. Empty
+    /// to play one degree per key.
+    pub map: Vec<Option<usize>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tuning {
+    pub scale: Scale,
+    pub mapping: KeyboardMapping,
+}
+
+#[derive(Debug)]
+pub enum TuningError {
+    Io(io::Error),
+    /// The file isn't valid, with the line number and what was wrong.
+    Parse(usize, String),
+}
+
+impl From<io::Error> for TuningError {
+    fn from(e: io::Error) -> TuningError {
+        TuningError::Io(e)
+    }
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TuningError::Io(ref e) => write!(f, "{}", e),
+            TuningError::Parse(line, ref e) => write!(f, "line {}: {}", line, e),
+        }
+    }
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    let div = a / b;
+    if a % b < 0 {
+        div - 1
+    } else {
+        div
+    }
+}
+
+/// The lines of a Scala file which aren't comments, with their line numbers.
+fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+/// The first word of the next line, and its line number.
+fn next_word<'a, I: Iterator<Item = (usize, &'a str)> + ?Sized>(
+    lines: &mut I,
+) -> Result<(usize, &'a str), TuningError> {
+    match lines.next() {
+        Some((idx, line)) => Ok((idx, line.split_whitespace().next().unwrap_or(""))),
+        None => Err(TuningError::Parse(0, "unexpected end of file".into())),
+    }
+}
+
+fn parse<T: ::std::str::FromStr>(idx: usize, word: &str) -> Result<T, TuningError> {
+    word.parse()
+        .map_err(|_| TuningError::Parse(idx, format!("not a number: {}", word)))
+}
+
+impl Scale {
+    /// `steps` equal steps to the period of `period` cents.
+    pub fn equal(steps: usize, period: f64) -> Scale {
+        Scale {
+            description: format!("{} equal divisions of {} cents", steps, period),
+            cents: (1..steps + 1)
+                .map(|step| period * step as f64 / steps as f64)
+                .collect(),
+        }
+    }
+
+    /// Read a `.scl` file. Degrees with a period are in cents, the others are ratios like `3/2`.
+    pub fn parse_scl(text: &str) -> Result<Scale, TuningError> {
+        let mut lines = content_lines(text);
+        let description = lines.next().map(|(_, line)| line.to_string()).unwrap_or_default();
+        let (idx, count) = next_word(&mut lines)?;
+        let count: usize = parse(idx, count)?;
+        if count == 0 {
+            return Err(TuningError::Parse(
+                idx,
+                "a scale needs at least one degree".into(),
+            ));
+        }
+        let mut cents = Vec::new();
+        for _ in 0..count {
+            let (idx, word) = next_word(&mut lines)?;
+            let value = if word.contains('.') {
+                parse(idx, word)?
+            } else {
+                let mut parts = word.splitn(2, '/');
+                let numerator: f64 = parse(idx, parts.next().unwrap())?;
+                let denominator: f64 = match parts.next() {
+                    Some(denominator) => parse(idx, denominator)?,
+                    None => 1.0,
+                };
+                if numerator <= 0.0 || denominator <= 0.0 {
+                    return Err(TuningError::Parse(idx, format!("not a positive ratio: {}", word)));
+                }
+                1200.0 * (numerator / denominator).log2()
+            };
+            cents.push(value);
+        }
+        Ok(Scale { description, cents })
+    }
+
+    pub fn len(&self) -> usize {
+        self.cents.len()
+    }
+
+    /// The cents of `steps` degrees up from the first, going on into further periods.
+    pub fn degree_cents(&self, steps: i64) -> f64 {
+        let len = self.cents.len() as i64;
+        let periods = div_floor(steps, len);
+        let degree = steps - periods * len;
+        let cents = if degree == 0 {
+            0.0
+        } else {
+            self.cents[degree as usize - 1]
+        };
+        periods as f64 * self.cents[len as usize - 1] + cents
+    }
+}
+
+impl KeyboardMapping {
+    /// One degree per key, with degree 0 on key 60 and key 69 at 440 Hz.
+    pub fn standard() -> KeyboardMapping {
+        KeyboardMapping {
+            first: 0,
+            last: 127,
+            middle: 60,
+            reference: 69,
+            frequency: 440.0,
+            octave_degree: 0,
+            map: Vec::new(),
+        }
+    }
+
+    /// Read a `.kbm` file.
+    pub fn parse_kbm(text: &str) -> Result<KeyboardMapping, TuningError> {
+        let mut lines = content_lines(text).filter(|(_, line)| !line.is_empty());
+        let number = |lines: &mut dyn Iterator<Item = (usize, &str)>| -> Result<i32, TuningError> {
+            let (idx, word) = next_word(lines)?;
+            parse(idx, word)
+        };
+        let size = number(&mut lines)?.max(0) as usize;
+        let first = number(&mut lines)?;
+        let last = number(&mut lines)?;
+        let middle = number(&mut lines)?;
+        let reference = number(&mut lines)?;
+        let (idx, word) = next_word(&mut lines)?;
+        let frequency = parse(idx, word)?;
+        let octave_degree = number(&mut lines)?.max(0) as usize;
+        let mut map = Vec::new();
+        for _ in 0..size {
+            let (idx, word) = next_word(&mut lines)?;
+            map.push(match word {
+                "x" | "X" => None,
+                _ => Some(parse(idx, word)?),
+            });
+        }
+        Ok(KeyboardMapping {
+            first,
+            last,
+            middle,
+            reference,
+            frequency,
+            octave_degree,
+            map,
+        })
+    }
+}
+
+impl Tuning {
+    /// Twelve tone equal temperament, with A at 440 Hz.
+    pub fn equal() -> Tuning {
+        Tuning::new(Scale::equal(12, 1200.0))
+    }
+
+    /// `scale` with the standard keyboard mapping.
+    pub fn new(scale: Scale) -> Tuning {
+        Tuning {
+            scale,
+            mapping: KeyboardMapping::standard(),
+        }
+    }
+
+    /// Read a `.scl` file, and the `.kbm` file placing it on the keys if there is one.
+    pub fn load<P: AsRef<Path>>(scale: P, mapping: Option<P>) -> Result<Tuning, TuningError> {
+        let scale = Scale::parse_scl(&fs::read_to_string(scale)?)?;
+        let mapping = match mapping {
+            Some(mapping) => KeyboardMapping::parse_kbm(&fs::read_to_string(mapping)?)?,
+            None => KeyboardMapping::standard(),
+        };
+        Ok(Tuning { scale, mapping })
+    }
+
+    /// The cents of `key` above the middle key, if it's mapped.
+    fn cents(&self, key: i32) -> Option<f64> {
+        let offset = (key - self.mapping.middle) as i64;
+        let map = &self.mapping.map;
+        if map.is_empty() {
+            return Some(self.scale.degree_cents(offset));
+        }
+        let size = map.len() as i64;
+        let repeats = div_floor(offset, size);
+        let degree = map[(offset - repeats * size) as usize]?;
+        let period = self.scale.degree_cents(self.mapping.octave_degree as i64);
+        Some(repeats as f64 * period + self.scale.degree_cents(degree as i64))
+    }
+
+    /// The frequency of `key` in Hz, or None if the mapping leaves it silent.
+    pub fn frequency(&self, key: i32) -> Option<f32> {
+        if key < self.mapping.first || key > self.mapping.last {
+            return None;
+        }
+        // a reference key that isn't mapped itself is tuned as if it were one degree per key
+        let reference = self.cents(self.mapping.reference).unwrap_or_else(|| {
+            self.scale
+                .degree_cents((self.mapping.reference - self.mapping.middle) as i64)
+        });
+        let cents = self.cents(key)? - reference;
+        Some((self.mapping.frequency * (cents / 1200.0).exp2()) as f32)
+    }
+
+    /// The frequency of a fractional key, bending between the keys either side of it.
+    pub fn bend(&self, key: f32) -> Option<f32> {
+        let below = key.floor();
+        let frac = key - below;
+        let low = self.frequency(below as i32);
+        match (low, self.frequency(below as i32 + 1)) {
+            (Some(low), Some(high)) if frac > 0.0 => Some(low * (high / low).powf(frac)),
+            _ if frac < 0.5 => low,
+            (_, high) => high,
+        }
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning::equal()
+    }
+}
+
+#[test]
+fn test_tuning() {
+    let equal = Tuning::equal();
+    for key in 0..128 {
+        let expected = 440.0 * 2f32.powf((key as f32 - 69.0) / 12.0);
+        assert!((equal.frequency(key).unwrap() - expected).abs() < expected * 1e-5);
+    }
+    assert!((equal.bend(69.5).unwrap() - 440.0 * 2f32.powf(0.5 / 12.0)).abs() < 1e-3);
+    assert_eq!(equal.frequency(128), None);
+
+    let scale = Scale::parse_scl(
+        "! just.scl\n\
+         !\n\
+         Just major pentatonic\n\
+         5\n\
+         !\n\
+         9/8\n\
+         5/4\n\
+         701.955 fifth\n\
+         5/3\n\
+         2\n",
+    )
+    .unwrap();
+    assert_eq!(scale.description, "Just major pentatonic");
+    assert_eq!(scale.len(), 5);
+    assert!((scale.cents[1] - 386.3137).abs() < 1e-3);
+    assert_eq!(scale.cents[4], 1200.0);
+    assert_eq!(scale.degree_cents(-5), -1200.0);
+    let pentatonic = Tuning::new(scale.clone());
+    // one degree per key, so five keys up is an octave
+    let middle = pentatonic.frequency(60).unwrap();
+    assert!((pentatonic.frequency(65).unwrap() - middle * 2.0).abs() < 1e-3);
+    assert!((pentatonic.frequency(62).unwrap() / middle - 1.25).abs() < 1e-5);
+
+    let mapping = KeyboardMapping::parse_kbm(
+        "! white keys only\n\
+         7\n0\n127\n60\n60\n261.6256\n5\n\
+         0\nx\n1\nx\n2\nx\n3\n",
+    )
+    .unwrap();
+    let mapped = Tuning { scale, mapping };
+    assert!((mapped.frequency(60).unwrap() - 261.6256).abs() < 1e-3);
+    assert_eq!(mapped.frequency(61), None);
+    assert!((mapped.frequency(62).unwrap() / 261.6256 - 9.0 / 8.0).abs() < 1e-5);
+    assert!((mapped.frequency(67).unwrap() / 261.6256 - 2.0).abs() < 1e-5);
+    assert!((mapped.frequency(53).unwrap() / 261.6256 - 0.5).abs() < 1e-5);
+
+    match Scale::parse_scl("bad\n2\n3/2\n") {
+        Err(TuningError::Parse(0, _)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    match Scale::parse_scl("bad\n1\n-3/2\n") {
+        Err(TuningError::Parse(3, _)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
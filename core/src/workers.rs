@@ -0,0 +1,279 @@
+//! The threads a block schedule runs on, and the cores they're kept on.
+//!
+//! `SchedulerConfig` sets how many threads `BlockScheduler` spreads a graph over (see `partition`)
+//! and where they run. Keeping the audio thread and the workers on cores of their own, away from
+//! the GUI and the executor, keeps their caches warm and their timing steady, which matters on
+//! laptops that move threads between fast and slow cores, and on small boards.
+//! `numa_cores` lists the cores of a NUMA node, for keeping the threads close to their memory.
+//!
+//! Pinning is only supported on Linux. Elsewhere the threads are left to the OS and an error is
+//! printed if cores were asked for.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// Threads a graph is spread over, counting the audio thread.
+    pub workers: usize,
+    /// The core each worker besides the audio thread is pinned to, in order, wrapping around if
+    /// there are more workers than cores. Empty leaves them to the OS.
+    pub worker_cores: Vec<usize>,
+    /// The cores the realtime audio thread may run on. Empty allows all of them.
+    pub audio_cores: Vec<usize>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> SchedulerConfig {
+        SchedulerConfig {
+            workers: 1,
+            worker_cores: Vec::new(),
+            audio_cores: Vec::new(),
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// Everything on the audio thread, wherever the OS puts it.
+    pub fn new() -> SchedulerConfig {
+        SchedulerConfig::default()
+    }
+    pub fn with_workers(mut self, workers: usize) -> SchedulerConfig {
+        self.workers = workers.max(1);
+        self
+    }
+    pub fn with_worker_cores(mut self, cores: Vec<usize>) -> SchedulerConfig {
+        self.worker_cores = cores;
+        self
+    }
+    pub fn with_audio_cores(mut self, cores: Vec<usize>) -> SchedulerConfig {
+        self.audio_cores = cores;
+        self
+    }
+    /// The core worker `idx` is pinned to, counting the workers after the audio thread from 0.
+    pub fn worker_core(&self, idx: usize) -> Option<usize> {
+        if self.worker_cores.is_empty() {
+            None
+        } else {
+            Some(self.worker_cores[idx % self.worker_cores.len()])
+        }
+    }
+}
+
+/// Keep the calling thread on `cores`, or let it run on any of them if empty.
+#[cfg(target_os = "linux")]
+pub fn pin(cores: &[usize]) -> io::Result<()> {
+    let cores: Vec<usize> = if cores.is_empty() {
+        let count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+        (0..count.max(1) as usize).collect()
+    } else {
+        cores.to_vec()
+    };
+    let capacity = 8 * mem::size_of::<libc::cpu_set_t>();
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for core in cores {
+            if core >= capacity {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no core {}", core),
+                ));
+            }
+            libc::CPU_SET(core, &mut set);
+        }
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin(cores: &[usize]) -> io::Result<()> {
+    if cores.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "pinning threads is only supported on Linux",
+        ))
+    }
+}
+
+/// Give the calling thread a realtime `priority` from 1 to 99, first in first out, ahead of every
+/// normal thread. Needs the privilege to, like an rtprio limit set for the user.
+#[cfg(target_os = "linux")]
+pub fn set_realtime(priority: i32) -> io::Result<()> {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_realtime(priority: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "realtime scheduling is only supported on Linux",
+    ))
+}
+
+/// The cores of NUMA node `node`, as the kernel lists them.
+pub fn numa_cores(node: usize) -> io::Result<Vec<usize>> {
+    let list = fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", node))?;
+    parse_cpulist(&list).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad cpu list"))
+}
+
+/// Parse a list of cores like `0-3,8,10-11`.
+fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let mut cores = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let mut bounds = range.splitn(2, '-');
+        let first: usize = bounds.next()?.parse().ok()?;
+        let last = match bounds.next() {
+            Some(last) => last.parse().ok()?,
+            None => first,
+        };
+        cores.extend(first..last + 1);
+    }
+    Some(cores)
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Threads kept around for running jobs which borrow from the caller, each pinned to its core.
+pub struct Workers {
+    threads: Vec<(mpsc::Sender<Job>, thread::JoinHandle<()>)>,
+    /// How many jobs are still running, for the caller to wait on.
+    pending: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl Workers {
+    /// Start the workers of `config`, besides the audio thread.
+    pub fn new(config: &SchedulerConfig) -> Workers {
+        let pending = Arc::new((Mutex::new(0), Condvar::new()));
+        let threads = (0..config.workers.saturating_sub(1))
+            .map(|idx| {
+                let (send, recv) = mpsc::channel::<Job>();
+                let core = config.worker_core(idx);
+                let pending = pending.clone();
+                let thread = thread::Builder::new()
+                    .name(format!("flow-synth-worker-{}", idx + 1))
+                    .spawn(move || {
+                        if let Some(core) = core {
+                            if let Err(e) = pin(&[core]) {
+                                println!("worker err: {}", e);
+                            }
+                        }
+                        for job in recv {
+                            // a job that panics still has to be counted, or the caller would hang
+                            if panic::catch_unwind(AssertUnwindSafe(move || job())).is_err() {
+                                println!("worker err: job panicked");
+                            }
+                            let (ref count, ref done) = *pending;
+                            *count.lock().unwrap() -= 1;
+                            done.notify_all();
+                        }
+                    })
+                    .unwrap();
+                (send, thread)
+            })
+            .collect();
+        Workers { threads, pending }
+    }
+
+    /// How many threads there are, besides the caller's.
+    pub fn count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Run each of `jobs` on a worker of its own and `local` on the calling thread, returning once
+    /// they've all finished. Jobs beyond the number of workers run on the calling thread as well.
+    pub fn scoped<'a, I, J, F>(&self, jobs: I, local: F)
+    where
+        I: IntoIterator<Item = J>,
+        J: FnOnce() + Send + 'a,
+        F: FnOnce(),
+    {
+        let (ref count, ref done) = *self.pending;
+        let mut inline = Vec::new();
+        for (idx, job) in jobs.into_iter().enumerate() {
+            let send = match self.threads.get(idx) {
+                Some(&(ref send, _)) => send,
+                None => {
+                    inline.push(job);
+                    continue;
+                }
+            };
+            let job: Box<dyn FnOnce() + Send + 'a> = Box::new(job);
+            // the job may borrow from the caller, which is sound as long as it's finished before
+            // this returns, so wait for it below whatever happens
+            let job: Job = unsafe { mem::transmute(job) };
+            *count.lock().unwrap() += 1;
+            if let Err(mpsc::SendError(job)) = send.send(job) {
+                *count.lock().unwrap() -= 1;
+                job();
+            }
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            local();
+            for job in inline {
+                job();
+            }
+        }));
+        let mut pending = count.lock().unwrap();
+        while *pending > 0 {
+            pending = done.wait(pending).unwrap();
+        }
+        if let Err(e) = result {
+            panic::resume_unwind(e);
+        }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        for (send, thread) in self.threads.drain(..) {
+            drop(send);
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn test_workers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    assert_eq!(parse_cpulist("0-3,8,10-11\n"), Some(vec![0, 1, 2, 3, 8, 10, 11]));
+    assert_eq!(parse_cpulist("0-x"), None);
+
+    let config = SchedulerConfig::new().with_workers(3).with_worker_cores(vec![0]);
+    assert_eq!(config.worker_core(1), Some(0));
+    let workers = Workers::new(&config);
+    assert_eq!(workers.count(), 2);
+
+    // jobs borrow from the caller, and are all done once it returns
+    let count = AtomicUsize::new(0);
+    let names = Mutex::new(Vec::new());
+    let job = || {
+        count.fetch_add(1, Ordering::SeqCst);
+        names
+            .lock()
+            .unwrap()
+            .push(thread::current().name().map(String::from));
+    };
+    workers.scoped(vec![&job, &job, &job], || job());
+    assert_eq!(count.load(Ordering::SeqCst), 4);
+    let names = names.into_inner().unwrap();
+    assert!(names.contains(&Some("flow-synth-worker-2".into())));
+}
@@ -0,0 +1,85 @@
+//! Arbitrary batches of edits, referring mostly to nodes and ports which exist.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+use flow_synth::module::flow::{ConnectOptions, Graph, GraphOp, NodeId, PortId, PortRef};
+use flow_synth_fuzz::{apply_checked, build, nodes, ports, with_reader, Kind};
+
+#[derive(Arbitrary, Clone, Copy, Debug)]
+enum Param {
+    Gain,
+    Level1,
+    /// A param no node has.
+    Missing,
+}
+
+/// An edit, with nodes and ports given by their index among those in the graph. Indices past the
+/// end stand for ones that don't exist.
+#[derive(Arbitrary, Debug)]
+enum Edit {
+    AddNode(Kind),
+    RemoveNode(u8),
+    Connect(u16, u16, Option<u8>),
+    Disconnect(u16),
+    SetParam(u8, Param, u8),
+    SetGain(u16, Option<u8>),
+}
+
+fn node(nodes: &[NodeId], idx: u8) -> NodeId {
+    match nodes.get(idx as usize % (nodes.len() + 1)) {
+        Some(&node) => node,
+        None => NodeId(idx as usize),
+    }
+}
+
+fn port(ports: &[PortRef], idx: u16) -> PortRef {
+    match ports.get(idx as usize % (ports.len() + 1)) {
+        Some(&port) => port,
+        None => PortRef {
+            node: NodeId((idx & 0xff) as usize),
+            port: PortId((idx >> 8) as usize),
+        },
+    }
+}
+
+fn gain(gain: Option<u8>) -> Option<f32> {
+    gain.map(|gain| gain as f32 / 64.0)
+}
+
+fn to_op(graph: &Graph, edit: &Edit) -> GraphOp {
+    let (nodes, ports) = (nodes(graph), ports(graph));
+    match *edit {
+        Edit::AddNode(kind) => GraphOp::AddNode(graph.reserve_id(), build(kind)),
+        Edit::RemoveNode(idx) => GraphOp::RemoveNode(node(&nodes, idx)),
+        Edit::Connect(src, dst, level) => GraphOp::Connect(
+            port(&ports, src),
+            port(&ports, dst),
+            ConnectOptions {
+                gain: gain(level),
+                ..ConnectOptions::default()
+            },
+        ),
+        Edit::Disconnect(idx) => GraphOp::Disconnect(port(&ports, idx)),
+        Edit::SetParam(idx, param, value) => {
+            let name = match param {
+                Param::Gain => "Gain",
+                Param::Level1 => "Level 1",
+                Param::Missing => "Missing",
+            };
+            GraphOp::SetParam(node(&nodes, idx), name.into(), value as f32 / 64.0)
+        }
+        Edit::SetGain(idx, level) => GraphOp::SetGain(port(&ports, idx), gain(level)),
+    }
+}
+
+fuzz_target!(|batches: Vec<Vec<Edit>>| {
+    with_reader(|graph| {
+        for batch in &batches {
+            let ops = batch.iter().map(|edit| to_op(graph, edit)).collect();
+            apply_checked(graph, ops);
+        }
+    });
+});
@@ -0,0 +1,26 @@
+//! Batches of serialized `GraphOp`s, as they would be replayed from a log, applied to a small
+//! patch with one node of each kind.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use flow_synth::module::flow::GraphOp;
+use flow_synth_fuzz::{apply_checked, build, with_reader, KINDS};
+
+fuzz_target!(|data: &[u8]| {
+    let batches: Vec<Vec<GraphOp>> = match serde_json::from_slice(data) {
+        Ok(batches) => batches,
+        Err(_) => return,
+    };
+    with_reader(|graph| {
+        let nodes = KINDS
+            .iter()
+            .map(|&kind| GraphOp::AddNode(graph.reserve_id(), build(kind)))
+            .collect();
+        graph.apply(nodes).unwrap();
+        for batch in batches {
+            apply_checked(graph, batch);
+        }
+    });
+});
@@ -0,0 +1,122 @@
+//! Shared parts of the fuzz targets for the structural API.
+//!
+//! Batches of `GraphOp`s are applied to a graph of a few kinds of nodes while another thread keeps
+//! taking snapshots and validating it. After every batch the connections have to be symmetric,
+//! and a rejected batch must have left the graph as it was. Anything else, or a panic or deadlock,
+//! is a bug.
+
+use arbitrary::Arbitrary;
+
+use flow_synth::module::audio_io::Frame;
+use flow_synth::module::flow::{self, Graph, GraphOp, Interface, NodeId, PortRef};
+use flow_synth::module::mix::{Gain, Mixer};
+use flow_synth::module::process::Processor;
+use flow_synth::module::Module;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// The kinds of nodes that can be added.
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub enum Kind {
+    Gain,
+    Mixer,
+    /// Control ports, which don't connect to audio ones.
+    Control,
+    /// Audio outputs declaring channel counts, so some connections are format mismatches.
+    Channels,
+}
+
+pub const KINDS: &[Kind] = &[Kind::Gain, Kind::Mixer, Kind::Control, Kind::Channels];
+
+/// Build the ports of an added node of `kind`.
+pub fn build(kind: Kind) -> Box<dyn FnMut(Arc<Interface>) + Send> {
+    Box::new(move |ifc: Arc<Interface>| match kind {
+        Kind::Gain => drop(Processor::<Gain>::new(ifc)),
+        Kind::Mixer => drop(Processor::<Mixer>::new(ifc)),
+        Kind::Control => {
+            ifc.get_or_create_port::<f32, ()>("Input".into());
+            ifc.get_or_create_port::<(), f32>("Output".into());
+        }
+        Kind::Channels => {
+            for &(name, channels) in &[("Mono", 1), ("Stereo", 2)] {
+                let port = ifc.get_or_create_port::<(), Frame>(name.into());
+                port.set_meta(flow::PortMeta {
+                    channels: Some(channels),
+                    ..port.meta()
+                });
+            }
+        }
+    })
+}
+
+/// The nodes of the graph, in a stable order.
+pub fn nodes(graph: &Graph) -> Vec<NodeId> {
+    graph.snapshot().nodes.keys().cloned().collect()
+}
+
+/// Every port of the graph, in a stable order.
+pub fn ports(graph: &Graph) -> Vec<PortRef> {
+    graph
+        .snapshot()
+        .nodes
+        .values()
+        .flat_map(|node| {
+            node.ports.iter().map(move |port| PortRef {
+                node: node.id,
+                port: port.id,
+            })
+        })
+        .collect()
+}
+
+/// Check that every connection is known to both ends.
+pub fn check_edges(graph: &Graph) {
+    let snapshot = graph.snapshot();
+    for node in snapshot.nodes.values() {
+        for port in &node.ports {
+            if let Some(edge) = port.edge {
+                let this = PortRef {
+                    node: node.id,
+                    port: port.id,
+                };
+                let other = snapshot
+                    .port(edge)
+                    .expect("connected to a port outside the graph");
+                assert_eq!(other.edge, Some(this), "connection from {:?} is one-sided", this);
+            }
+        }
+    }
+}
+
+/// Apply a batch, panicking if the graph ends up inconsistent.
+pub fn apply_checked(graph: &Graph, ops: Vec<GraphOp>) {
+    let before = graph.snapshot();
+    if graph.apply(ops).is_err() {
+        assert_eq!(
+            graph.snapshot().nodes,
+            before.nodes,
+            "a rejected batch changed the graph"
+        );
+    }
+    check_edges(graph);
+}
+
+/// Run `edit` on a new graph, while another thread keeps reading it.
+pub fn with_reader<F: FnOnce(&Arc<Graph>)>(edit: F) {
+    let graph = Graph::new();
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let (graph, done) = (graph.clone(), done.clone());
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                graph.snapshot();
+                graph.validate();
+            }
+        })
+    };
+    edit(&graph);
+    done.store(true, Ordering::Relaxed);
+    reader.join().unwrap();
+}
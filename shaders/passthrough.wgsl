@@ -0,0 +1,29 @@
+// The default shader of the `GPU Shader` module, passing audio through unchanged. Shaders are
+// run once for every sample of a batch of frames, with each invocation processing every channel
+// of its sample. Frames are stored one after another, each sample by sample, each sample
+// channel by channel.
+
+struct Block {
+    // samples in each frame
+    samples: u32,
+    channels: u32,
+    // frames in the batch
+    frames: u32,
+    rate: f32,
+}
+
+@group(0) @binding(0) var<storage, read> input: array<f32>;
+@group(0) @binding(1) var<storage, read_write> output: array<f32>;
+@group(0) @binding(2) var<uniform> block: Block;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let sample = id.x;
+    if (sample >= block.samples * block.frames) {
+        return;
+    }
+    for (var channel = 0u; channel < block.channels; channel = channel + 1u) {
+        let idx = sample * block.channels + channel;
+        output[idx] = input[idx];
+    }
+}
@@ -1,4 +1,4 @@
-use gui::{component::*, event::*, geom::*, RenderContext};
+use crate::gui::{component::*, event::*, geom::*, RenderContext};
 
 use gfx_device_gl as gl;
 
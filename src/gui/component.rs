@@ -6,7 +6,7 @@
 // - homogenous
 //
 
-use gui::{event::Event, geom::*, render::RenderContext};
+use crate::gui::{event::Event, geom::*, render::RenderContext};
 
 use gfx_device_gl as gl;
 
@@ -1,5 +1,5 @@
-use gui::{component::*, event::*, geom::*, render::*};
-use module::flow;
+use crate::gui::{component::*, event::*, geom::*, render::*};
+use crate::module::flow;
 
 use gfx_device_gl as gl;
 
@@ -16,16 +16,16 @@ pub trait JackBackend {
 
 impl JackBackend for Arc<flow::OpaquePort> {
     fn name(&self) -> &str {
-        flow::OpaquePort::name(self)
+        flow::OpaquePort::name(&**self)
     }
     fn can_connect(&self, other: &Self) -> bool {
-        flow::OpaquePort::can_connect(self, other)
+        flow::OpaquePort::can_connect(&**self, other)
     }
     fn connect(&self, other: &Self) {
-        flow::OpaquePort::connect(self, other).unwrap();
+        flow::OpaquePort::connect(&**self, other).unwrap();
     }
     fn disconnect(&self) {
-        flow::OpaquePort::disconnect(self).unwrap();
+        flow::OpaquePort::disconnect(&**self).unwrap();
     }
 }
 
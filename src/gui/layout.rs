@@ -1,4 +1,4 @@
-use gui::geom::{Box3, Pt3};
+use crate::gui::geom::{Box3, Pt3};
 
 pub use cassowary::strength::{MEDIUM, REQUIRED, STRONG, WEAK};
 use cassowary::WeightedRelation::*;
@@ -1,4 +1,4 @@
-use gui::{component::*, event::*, geom::*, render::*, RenderContext};
+use crate::gui::{component::*, event::*, geom::*, render::*, RenderContext};
 
 use gfx_device_gl as gl;
 
@@ -7,6 +7,7 @@ pub mod layout;
 pub mod menu;
 pub mod module_gui;
 pub mod render;
+pub mod ron_value;
 pub mod root;
 pub mod textbox;
 
@@ -19,6 +20,7 @@ use self::root::*;
 use glutin::{self, ContextBuilder, EventsLoop, GlContext, WindowBuilder};
 
 use std::collections::VecDeque;
+use std::env;
 use std::time::Instant;
 
 use gfx::Device;
@@ -126,6 +128,9 @@ pub fn gui_main() {
     let mut ctx = RenderContext::new(factory.clone());
 
     let mut model = Model::new(ctx.clone());
+    if let Ok(addr) = env::var("FLOW_SYNTH_RPC") {
+        println!("RPC on {}: {:?}", addr, model.root.serve_rpc(&addr));
+    }
 
     // begin main loop
     let mut running = true;
@@ -1,8 +1,11 @@
-use gui::{button::*, component::*, connect::*, event::*, geom::*, layout, render::*};
-use module::*;
+use crate::gui::{button::*, component::*, connect::*, event::*, geom::*, layout, render::*};
+use crate::install;
+use crate::module::*;
 
 use futures::executor::ThreadPool;
 
+use serde_json;
+
 use std::marker::PhantomData;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -16,10 +19,15 @@ pub struct GuiModuleConfig {
     pub jack_ctx: Rc<JackContext<Arc<flow::OpaquePort>>>,
     pub executor: ThreadPool,
     pub node_id: Option<flow::NodeId>,
+    /// State from `Module::save_state`, or null for a fresh module.
+    pub state: serde_json::Value,
 }
 pub trait GuiModuleFactory {
     fn name(&self) -> &str;
+    fn describe(&self) -> ModuleInfo;
     fn new(&mut self, arg: GuiModuleConfig) -> Box<dyn GuiModule>;
+    /// Construct the module alone, for running without a window.
+    fn new_headless(&self, ifc: Arc<flow::Interface>) -> Box<dyn install::DynModule>;
 }
 
 #[derive(Default)]
@@ -37,9 +45,59 @@ impl<T: Module + 'static> GuiModuleFactory for BasicGuiModuleFactory<T> {
     fn name(&self) -> &str {
         T::name()
     }
+    fn describe(&self) -> ModuleInfo {
+        ModuleInfo::of::<T>()
+    }
     fn new(&mut self, cfg: GuiModuleConfig) -> Box<dyn GuiModule> {
         Box::new(GuiModuleWrapper::<T>::new(cfg))
     }
+    fn new_headless(&self, ifc: Arc<flow::Interface>) -> Box<dyn install::DynModule> {
+        Box::new(T::new(ifc))
+    }
+}
+
+/// Creates modules of type `T` starting from `state` rather than empty, to register one type once
+/// for each of its configurations under their own names, like installed plugins.
+pub struct StatefulGuiModuleFactory<T: Module + 'static> {
+    name: String,
+    state: serde_json::Value,
+    _t: PhantomData<T>,
+}
+impl<T: Module + 'static> StatefulGuiModuleFactory<T> {
+    pub fn new(name: String, state: serde_json::Value) -> StatefulGuiModuleFactory<T> {
+        StatefulGuiModuleFactory {
+            name,
+            state,
+            _t: PhantomData,
+        }
+    }
+}
+impl<T: Module + 'static> GuiModuleFactory for StatefulGuiModuleFactory<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn describe(&self) -> ModuleInfo {
+        let graph = flow::Graph::new();
+        let mut module = T::new(graph.add_node());
+        module.load_state(self.state.clone());
+        ModuleInfo {
+            name: self.name.clone(),
+            category: String::new(),
+            description: String::new(),
+            ports: module.ports().iter().map(|port| PortInfo::of(&**port)).collect(),
+        }
+    }
+    fn new(&mut self, mut cfg: GuiModuleConfig) -> Box<dyn GuiModule> {
+        if cfg.state.is_null() {
+            cfg.state = self.state.clone();
+        }
+        Box::new(GuiModuleWrapper::<T>::new(cfg))
+    }
+    fn new_headless(&self, ifc: Arc<flow::Interface>) -> Box<dyn install::DynModule> {
+        let mut module = T::new(ifc);
+        module.load_state(self.state.clone());
+        Box::new(module)
+    }
 }
 
 pub type BodyUpdate = bool;
@@ -92,6 +150,9 @@ pub struct GuiModuleWrapper<T: Module + 'static> {
 impl<T: Module + 'static> Drop for GuiModuleWrapper<T> {
     fn drop(&mut self) {
         self.module.stop();
+        // wake the module's tasks, so they see they've been stopped, and end the streams of the
+        // nodes it was connected to
+        self.node.shutdown(false);
     }
 }
 
@@ -104,6 +165,7 @@ impl<T: Module + 'static> GuiModuleWrapper<T> {
             graph,
             executor,
             node_id,
+            state,
         } = cfg;
         let target = TextureTarget::new(ctx.clone(), bounds.size.drop_z());
         let ifc = if let Some(id) = node_id {
@@ -113,6 +175,9 @@ impl<T: Module + 'static> GuiModuleWrapper<T> {
         };
         let node = graph.node(ifc.id()).unwrap();
         let mut module = T::new(ifc);
+        if !state.is_null() {
+            module.load_state(state);
+        }
         let ports = module.ports();
 
         // Bounds pos is relative to the window, so we drop it and keep just the size,
@@ -218,6 +283,7 @@ pub trait GuiModule: GuiComponent<GuiModuleUpdate> {
     fn node(&self) -> Arc<flow::Node>;
     fn name(&self) -> &'static str;
     fn jacks(&self) -> &[Rc<Jack<Arc<flow::OpaquePort>>>];
+    fn save_state(&self) -> serde_json::Value;
 }
 
 impl<T: Module> GuiModule for GuiModuleWrapper<T> {
@@ -230,6 +296,9 @@ impl<T: Module> GuiModule for GuiModuleWrapper<T> {
     fn jacks(&self) -> &[Rc<Jack<Arc<flow::OpaquePort>>>] {
         &self.jacks
     }
+    fn save_state(&self) -> serde_json::Value {
+        self.module.save_state()
+    }
 }
 
 pub enum GuiModuleUpdate {
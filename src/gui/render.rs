@@ -1,4 +1,4 @@
-use gui::geom::*;
+use crate::gui::geom::*;
 
 use std::sync::{Arc, Mutex};
 
@@ -0,0 +1,463 @@
+//! Untyped RON documents, for migrating saved patches before they're read into their types.
+//!
+//! `ron::value::Value` drops the names of structs and enum variants and can't read newtypes like
+//! `NodeId`, so patches are parsed into this `Value` instead, which keeps everything as written.
+//! Printing a `Value` gives RON again, for `ron::de` to read.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    /// A number as written, so it comes back unchanged.
+    Number(String),
+    Char(char),
+    String(String),
+    Option(Option<Box<Value>>),
+    Seq(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    /// A struct or struct variant, `Name(field: value, ...)`, or `(field: value, ...)` unnamed.
+    Struct(Option<String>, Vec<(String, Value)>),
+    /// A tuple, newtype or tuple variant, `Name(value, ...)`, or `(value, ...)` unnamed.
+    Tuple(Option<String>, Vec<Value>),
+    /// A unit struct or unit variant.
+    Ident(String),
+}
+
+impl Value {
+    /// Parse a RON document.
+    pub fn parse(s: &str) -> Result<Value, String> {
+        let mut parser = Parser { s, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_ws();
+        if parser.pos < s.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    pub fn number<T: fmt::Display>(n: T) -> Value {
+        Value::Number(n.to_string())
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(ref n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_seq_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match *self {
+            Value::Seq(ref mut seq) => Some(seq),
+            _ => None,
+        }
+    }
+
+    /// The field `name` of a struct.
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        match *self {
+            Value::Struct(_, ref fields) => fields.iter().find(|field| field.0 == name).map(|field| &field.1),
+            _ => None,
+        }
+    }
+
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut Value> {
+        match *self {
+            Value::Struct(_, ref mut fields) => fields
+                .iter_mut()
+                .find(|field| field.0 == name)
+                .map(|field| &mut field.1),
+            _ => None,
+        }
+    }
+
+    /// Set the field `name` of a struct, adding it if it's missing. Other values are left alone.
+    pub fn set_field(&mut self, name: &str, value: Value) {
+        if let Value::Struct(_, ref mut fields) = *self {
+            match fields.iter_mut().find(|field| field.0 == name) {
+                Some(field) => field.1 = value,
+                None => fields.push((name.into(), value)),
+            }
+        }
+    }
+
+    /// Take the field `name` out of a struct.
+    pub fn remove_field(&mut self, name: &str) -> Option<Value> {
+        match *self {
+            Value::Struct(_, ref mut fields) => {
+                let idx = fields.iter().position(|field| field.0 == name)?;
+                Some(fields.remove(idx).1)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn write_escaped(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    for c in s.chars() {
+        write!(f, "{}", c.escape_debug())?;
+    }
+    Ok(())
+}
+
+fn write_name(f: &mut fmt::Formatter, name: &Option<String>) -> fmt::Result {
+    match *name {
+        Some(ref name) => write!(f, "{}", name),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Unit => write!(f, "()"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(ref n) => write!(f, "{}", n),
+            Value::Char(c) => write!(f, "'{}'", c.escape_debug()),
+            Value::String(ref s) => {
+                write!(f, "\"")?;
+                write_escaped(f, s)?;
+                write!(f, "\"")
+            }
+            Value::Option(None) => write!(f, "None"),
+            Value::Option(Some(ref value)) => write!(f, "Some({})", value),
+            Value::Seq(ref items) => {
+                write!(f, "[")?;
+                for item in items {
+                    write!(f, "{},", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(ref entries) => {
+                write!(f, "{{")?;
+                for &(ref key, ref value) in entries {
+                    write!(f, "{}:{},", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Struct(ref name, ref fields) => {
+                write_name(f, name)?;
+                write!(f, "(")?;
+                for &(ref name, ref value) in fields {
+                    write!(f, "{}:{},", name, value)?;
+                }
+                write!(f, ")")
+            }
+            Value::Tuple(ref name, ref items) => {
+                write_name(f, name)?;
+                write!(f, "(")?;
+                for item in items {
+                    write!(f, "{},", item)?;
+                }
+                write!(f, ")")
+            }
+            Value::Ident(ref name) => write!(f, "{}", name),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        let line = self.s[..self.pos].matches('\n').count() + 1;
+        format!("{} at line {}", message, line)
+    }
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+    fn skip_ws(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or_else(|| trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed
+                    .find("*/")
+                    .map(|end| end + 2)
+                    .unwrap_or_else(|| trimmed.len());
+            } else {
+                return;
+            }
+        }
+    }
+    /// Skip whitespace and take `c` if it's next.
+    fn consume(&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.consume(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {:?}", c)))
+        }
+    }
+    fn identifier(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        if !rest.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return None;
+        }
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or_else(|| rest.len());
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_ws();
+        match self.peek() {
+            Some('(') => self.parens(None),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                while !self.consume(']') {
+                    items.push(self.value()?);
+                    if !self.consume(',') {
+                        self.expect(']')?;
+                        break;
+                    }
+                }
+                Ok(Value::Seq(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut entries = Vec::new();
+                while !self.consume('}') {
+                    let key = self.value()?;
+                    self.expect(':')?;
+                    entries.push((key, self.value()?));
+                    if !self.consume(',') {
+                        self.expect('}')?;
+                        break;
+                    }
+                }
+                Ok(Value::Map(entries))
+            }
+            Some('"') => {
+                self.pos += 1;
+                self.string().map(Value::String)
+            }
+            Some('r') if self.rest()[1..].starts_with(|c| c == '"' || c == '#') => {
+                self.pos += 1;
+                self.raw_string().map(Value::String)
+            }
+            Some('\'') => {
+                self.pos += 1;
+                let c = match self.next() {
+                    Some('\\') => self.escape()?,
+                    Some(c) => c,
+                    None => return Err(self.error("unterminated char")),
+                };
+                self.expect('\'')?;
+                Ok(Value::Char(c))
+            }
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let rest = self.rest();
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || "+-._".contains(c)))
+                    .unwrap_or_else(|| rest.len());
+                self.pos += len;
+                Ok(Value::Number(rest[..len].into()))
+            }
+            Some(_) => {
+                let name = self
+                    .identifier()
+                    .ok_or_else(|| self.error("unexpected character"))?;
+                match name {
+                    "true" => return Ok(Value::Bool(true)),
+                    "false" => return Ok(Value::Bool(false)),
+                    "None" => return Ok(Value::Option(None)),
+                    "inf" | "NaN" => return Ok(Value::Number(name.into())),
+                    _ => (),
+                }
+                self.skip_ws();
+                if self.peek() != Some('(') {
+                    return Ok(Value::Ident(name.into()));
+                }
+                if name == "Some" {
+                    self.pos += 1;
+                    let value = self.value()?;
+                    self.consume(',');
+                    self.expect(')')?;
+                    return Ok(Value::Option(Some(Box::new(value))));
+                }
+                self.parens(Some(name.into()))
+            }
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    /// A struct or tuple, with the `(` next.
+    fn parens(&mut self, name: Option<String>) -> Result<Value, String> {
+        self.pos += 1;
+        if self.consume(')') {
+            return Ok(match name {
+                Some(name) => Value::Tuple(Some(name), Vec::new()),
+                None => Value::Unit,
+            });
+        }
+        // a struct starts with a field name and a colon
+        let start = self.pos;
+        let is_struct = self.identifier().is_some() && self.consume(':');
+        self.pos = start;
+        if is_struct {
+            let mut fields = Vec::new();
+            while !self.consume(')') {
+                self.skip_ws();
+                let field = self
+                    .identifier()
+                    .ok_or_else(|| self.error("expected a field name"))?;
+                self.expect(':')?;
+                fields.push((field.into(), self.value()?));
+                if !self.consume(',') {
+                    self.expect(')')?;
+                    break;
+                }
+            }
+            Ok(Value::Struct(name, fields))
+        } else {
+            let mut items = Vec::new();
+            while !self.consume(')') {
+                items.push(self.value()?);
+                if !self.consume(',') {
+                    self.expect(')')?;
+                    break;
+                }
+            }
+            Ok(Value::Tuple(name, items))
+        }
+    }
+
+    /// The rest of a string, after the `"`.
+    fn string(&mut self) -> Result<String, String> {
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(self.escape()?),
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// The rest of a raw string, after the `r`.
+    fn raw_string(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        self.pos += hashes;
+        self.expect('"')?;
+        let end = format!("\"{}", &rest[..hashes]);
+        let rest = self.rest();
+        let len = rest.find(&end).ok_or_else(|| self.error("unterminated string"))?;
+        self.pos += len + end.len();
+        Ok(rest[..len].into())
+    }
+
+    /// An escaped character, after the `\`.
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('x') => {
+                let hex = self.rest().get(..2).ok_or_else(|| self.error("invalid escape"))?;
+                self.pos += 2;
+                u8::from_str_radix(hex, 16).map_err(|_| self.error("invalid escape"))? as char
+            }
+            Some('u') => {
+                self.expect('{')?;
+                let rest = self.rest();
+                let len = rest.find('}').ok_or_else(|| self.error("invalid escape"))?;
+                self.pos += len + 1;
+                u32::from_str_radix(&rest[..len], 16)
+                    .ok()
+                    .and_then(::std::char::from_u32)
+                    .ok_or_else(|| self.error("invalid escape"))?
+            }
+            Some(c) => c,
+            None => return Err(self.error("unterminated string")),
+        };
+        Ok(c)
+    }
+}
+
+#[test]
+fn test_ron_value() {
+    let text = r##"Root(
+        // a comment
+        id: (5),
+        name: "a \"b\"\n\u{e9}",
+        raw: r#"x"y"#,
+        c: '\'',
+        control: Cc(channel: 1, number: 2),
+        osc: Osc("/a"),
+        curve: Linear,
+        nodes: {(3): 1.5, (4): -2e3},
+        some: Some(()),
+        none: None,
+        list: [1, 2,],
+        empty: (),
+    )"##;
+    let value = Value::parse(text).unwrap();
+    assert_eq!(
+        value.field("id"),
+        Some(&Value::Tuple(None, vec![Value::number(5)]))
+    );
+    assert_eq!(
+        value.field("name").and_then(Value::as_str),
+        Some("a \"b\"\n\u{e9}")
+    );
+    assert_eq!(value.field("raw").and_then(Value::as_str), Some("x\"y"));
+    assert_eq!(value.field("c"), Some(&Value::Char('\'')));
+    assert_eq!(
+        value.field("control"),
+        Some(&Value::Struct(
+            Some("Cc".into()),
+            vec![
+                ("channel".into(), Value::number(1)),
+                ("number".into(), Value::number(2))
+            ]
+        ))
+    );
+    assert_eq!(value.field("curve"), Some(&Value::Ident("Linear".into())));
+    assert_eq!(
+        value.field("some"),
+        Some(&Value::Option(Some(Box::new(Value::Unit))))
+    );
+    assert_eq!(value.field("empty"), Some(&Value::Unit));
+    // printing gives the same document back
+    assert_eq!(Value::parse(&value.to_string()).unwrap(), value);
+    assert!(Value::parse("(a: 1").is_err());
+    assert!(Value::parse("[1] 2").is_err());
+}
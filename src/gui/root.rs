@@ -1,14 +1,20 @@
 //! Root component that holds the application
 
-use gui::{component::*, connect::*, event::*, geom::*, menu::*, module_gui::*, render::*};
-use module::flow;
+use crate::gui::{component::*, connect::*, event::*, geom::*, menu::*, module_gui::*, render::*};
+use crate::module::flow;
+use crate::module::missing::{self, Missing, Placeholder};
+use crate::registry::Registry;
+use crate::rpc;
 
 use futures::executor::ThreadPool;
 use gfx_device_gl as gl;
 use ron;
+use serde_json;
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::io;
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -18,10 +24,11 @@ pub struct Root {
 
     ctx: RenderContext,
     modules: Vec<Box<dyn GuiModule>>,
-    module_types: Vec<Box<dyn GuiModuleFactory>>,
+    module_types: Registry,
     context_menu: Option<MenuView>,
     jack_ctx: Rc<JackContext<Arc<flow::OpaquePort>>>,
     executor: ThreadPool,
+    rpc: Option<rpc::Server>,
 }
 
 impl Root {
@@ -30,31 +37,51 @@ impl Root {
             graph: flow::Graph::new(),
             bounds,
             modules: Vec::new(),
-            module_types: load_metamodules(),
+            module_types: Registry::standard(),
             context_menu: None,
             jack_ctx: JackContext::new(bounds),
             executor: ThreadPool::new().unwrap(),
+            rpc: None,
 
             ctx,
         }
     }
 
+    /// Expose the graph over JSON-RPC for introspection by external tools.
+    pub fn serve_rpc(&mut self, addr: &str) -> io::Result<()> {
+        let modules = self.module_types.available_modules();
+        self.rpc = Some(rpc::Server::start(addr, self.graph.clone(), modules)?);
+        Ok(())
+    }
+
+    fn module_config(
+        &self,
+        bounds: Box3,
+        node_id: Option<flow::NodeId>,
+        state: serde_json::Value,
+    ) -> GuiModuleConfig {
+        GuiModuleConfig {
+            bounds,
+            jack_ctx: Rc::clone(&self.jack_ctx),
+            graph: Arc::clone(&self.graph),
+            ctx: self.ctx.clone(),
+            executor: self.executor.clone(),
+            node_id,
+            state,
+        }
+    }
+
     fn new_module(
         &mut self,
         name: &str,
         bounds: Box3,
         node_id: Option<flow::NodeId>,
+        state: serde_json::Value,
     ) -> Result<flow::NodeId, ()> {
         // dummy z, overwritten by move_to_front
-        if let Some(factory) = self.module_types.iter_mut().find(|ty| ty.name() == name) {
-            let module = factory.new(GuiModuleConfig {
-                bounds,
-                jack_ctx: Rc::clone(&self.jack_ctx),
-                graph: Arc::clone(&self.graph),
-                ctx: self.ctx.clone(),
-                executor: self.executor.clone(),
-                node_id,
-            });
+        let cfg = self.module_config(bounds, node_id, state);
+        if let Some(factory) = self.module_types.factory_mut(name) {
+            let module = factory.new(cfg);
             let id = module.node().id();
             self.modules.push(module);
             Ok(id)
@@ -63,6 +90,96 @@ impl Root {
         }
     }
 
+    /// Add a `Missing` module standing in for a module of an unknown type.
+    fn new_placeholder(&mut self, bounds: Box3, node_id: flow::NodeId, placeholder: &Placeholder) {
+        let state = serde_json::to_value(placeholder).unwrap();
+        let cfg = self.module_config(bounds, Some(node_id), state);
+        self.modules.push(Box::new(GuiModuleWrapper::<Missing>::new(cfg)));
+    }
+
+    /// Connect the jacks of a saved connection. Connections of placeholders are left to them.
+    fn connect_saved(&self, connection: &serial::Connection) {
+        let find = |id| self.modules.iter().find(|module| module.node().id() == id);
+        let (src_node, dst_node) = match (find(connection.src_node), find(connection.dst_node)) {
+            (Some(src_node), Some(dst_node)) => (src_node, dst_node),
+            _ => return,
+        };
+        if src_node.name() == missing::NAME || dst_node.name() == missing::NAME {
+            return;
+        }
+        let src_jack = src_node
+            .jacks()
+            .iter()
+            .find(|jack| jack.name() == connection.src_port);
+        let dst_jack = dst_node
+            .jacks()
+            .iter()
+            .find(|jack| jack.name() == connection.dst_port);
+        if let (Some(src_jack), Some(dst_jack)) = (src_jack, dst_jack) {
+            src_jack.connect(dst_jack);
+            if connection.gain.is_some() {
+                let port = src_node
+                    .node()
+                    .ports()
+                    .into_iter()
+                    .find(|port| port.name() == connection.src_port);
+                if let Some(Err(e)) = port.map(|port| port.set_gain(connection.gain)) {
+                    println!("connection gain err: {:?}", e);
+                }
+            }
+        } else {
+            println!(
+                "Could not find port(s) needed to connect {:?}:{:?} and {:?}:{:?}",
+                src_node.name(),
+                connection.src_port,
+                dst_node.name(),
+                connection.dst_port
+            );
+        }
+    }
+
+    /// Replace the placeholders of module types which have been registered since the patch was
+    /// loaded with the real modules, connected as the patch had them.
+    pub fn fill_missing(&mut self) {
+        for idx in (0..self.modules.len()).rev() {
+            if self.modules[idx].name() != missing::NAME {
+                continue;
+            }
+            let placeholder: Placeholder = match serde_json::from_value(self.modules[idx].save_state()) {
+                Ok(placeholder) => placeholder,
+                Err(_) => continue,
+            };
+            if self.module_types.factory(&placeholder.type_name).is_none() {
+                continue;
+            }
+            let old = self.modules.remove(idx);
+            let (node, bounds) = (old.node(), old.bounds());
+            drop(old);
+            let id = node.id();
+            let _ = self.graph.remove_node(id);
+            let state = placeholder.state.clone();
+            self.new_module(&placeholder.type_name, bounds, Some(id), state)
+                .unwrap();
+            let new_node = self.graph.node(id).unwrap();
+            for tag in node.tags() {
+                if tag != missing::TAG {
+                    new_node.add_tag(&tag);
+                }
+            }
+            for (key, value) in node.meta_map() {
+                if key != missing::META_KEY {
+                    new_node.set_meta(&key, value);
+                }
+            }
+            new_node.set_muted(node.muted());
+            new_node.set_bypassed(node.bypassed());
+            new_node.set_active(node.active());
+            for connection in serial::connections(id, &placeholder) {
+                self.connect_saved(&connection);
+            }
+        }
+    }
+
     fn open_new_module_menu(&mut self, pos: Pt2) {
         self.context_menu = Some(MenuView::new(
             self.ctx.clone(),
@@ -70,8 +187,12 @@ impl Root {
             Menu::new(
                 &self
                     .module_types
+                    .categories()
                     .iter()
-                    .map(|ty| item(&ty.name()))
+                    .map(|category| {
+                        let names = self.module_types.names_in(category);
+                        sub_menu(category, &names.iter().map(|name| item(name)).collect::<Vec<_>>())
+                    })
                     .collect::<Vec<_>>(),
             ),
         ));
@@ -114,12 +235,24 @@ impl Root {
         for module in &self.modules {
             let bounds = module.bounds();
             let node = module.node();
-            let module = serial::Module {
+            let mut saved = serial::Module {
                 bounds,
                 id: node.id(),
                 type_name: module.name().into(),
+                tags: node.tags().into_iter().collect(),
+                meta: node.meta_map(),
+                muted: node.muted(),
+                bypassed: node.bypassed(),
+                state: module.save_state(),
+                active: node.active(),
             };
-            modules.push(module);
+            // placeholders are saved as the modules they stand in for
+            for connection in serial::unwrap_placeholder(&mut saved) {
+                if !connections.contains(&connection) {
+                    connections.push(connection);
+                }
+            }
+            modules.push(saved);
 
             for port in node.ports() {
                 visited_ports.insert((port.node_id(), port.id()));
@@ -131,6 +264,7 @@ impl Root {
                             src_port: port.name().into(),
                             dst_node: dst.node_id(),
                             dst_port: dst.name().into(),
+                            gain: port.gain(),
                         };
                         connections.push(connection);
                     }
@@ -138,8 +272,11 @@ impl Root {
             }
         }
         let root = serial::Root {
+            version: serial::VERSION,
             modules,
             connections,
+            scenes: self.graph.scenes(),
+            mappings: self.graph.mappings(),
         };
 
         let data = ron::ser::to_string(&root).unwrap();
@@ -149,67 +286,127 @@ impl Root {
         Ok(())
     }
 
-    fn load(&mut self, filename: &str) -> ron::de::Result<()> {
+    /// Load a patch, replacing the current one. With `allow_missing`, modules of unknown types
+    /// are loaded as placeholder nodes instead of failing.
+    fn load(&mut self, filename: &str, allow_missing: bool) -> Result<(), serial::Error> {
         use std::fs::File;
 
-        // reset current state
-        ::std::mem::replace(self, Root::new(self.ctx.clone(), self.bounds));
-
         let file = File::open(filename)?;
-        let root: serial::Root = ron::de::from_reader(file)?;
+        let migrations = serial::Migrations::standard();
+        let root = serial::read(file, &migrations, &self.module_types, allow_missing)?;
+
+        // reset current state, keeping the rpc server running
+        let rpc = self.rpc.take();
+        ::std::mem::replace(self, Root::new(self.ctx.clone(), self.bounds));
+        if let Some(rpc) = rpc {
+            rpc.set_graph(self.graph.clone());
+            self.rpc = Some(rpc);
+        }
 
         for module in root.modules {
-            if let Err(_) = self.new_module(&module.type_name, module.bounds, Some(module.id)) {
+            self.add_saved(module, &root.connections);
+        }
+
+        for scene in root.scenes {
+            self.graph.insert_scene(scene);
+        }
+        self.graph.set_mappings(root.mappings);
+
+        for connection in &root.connections {
+            self.connect_saved(connection);
+        }
+
+        Ok(())
+    }
+
+    /// Add a saved module, as a placeholder if its type is unknown.
+    fn add_saved(&mut self, module: serial::Module, connections: &[serial::Connection]) {
+        if self.module_types.factory(&module.type_name).is_none() {
+            println!("Loading module {:?} as a placeholder", module.type_name);
+            let placeholder = serial::placeholder(&module, connections);
+            self.new_placeholder(module.bounds, module.id, &placeholder);
+        } else {
+            let (bounds, id) = (module.bounds, Some(module.id));
+            let created = self.new_module(&module.type_name, bounds, id, module.state);
+            if let Err(_) = created {
                 println!("Error creating module {:?}", module.type_name);
+                return;
             }
         }
-
-        for connection in root.connections {
-            let src_node = self
-                .modules
-                .iter()
-                .find(|module| module.node().id() == connection.src_node)
-                .unwrap();
-            let dst_node = self
-                .modules
-                .iter()
-                .find(|module| module.node().id() == connection.dst_node)
-                .unwrap();
-            let src_jack = src_node
-                .jacks()
-                .iter()
-                .find(|jack| jack.name() == connection.src_port);
-            let dst_jack = dst_node
-                .jacks()
-                .iter()
-                .find(|jack| jack.name() == connection.dst_port);
-            if let (Some(src_jack), Some(dst_jack)) = (src_jack, dst_jack) {
-                src_jack.connect(dst_jack);
-            } else {
-                println!(
-                    "Could not find port(s) needed to connect {:?}:{:?} and {:?}:{:?}",
-                    src_node.name(),
-                    connection.src_port,
-                    dst_node.name(),
-                    connection.dst_port
-                );
+        if let Some(node) = self.graph.node(module.id) {
+            for tag in &module.tags {
+                node.add_tag(tag);
+            }
+            for (key, value) in module.meta {
+                node.set_meta(&key, value);
             }
+            node.set_muted(module.muted);
+            node.set_bypassed(module.bypassed);
+            node.set_active(module.active);
         }
+    }
 
+    /// Add the modules of a template to the patch, with `args` substituted into their params.
+    fn add_template(&mut self, filename: &str, args: &BTreeMap<String, f32>) -> Result<(), serial::Error> {
+        let file = File::open(filename)?;
+        let migrations = serial::Migrations::standard();
+        let template = serial::read_template(file, &migrations, &self.module_types)?;
+        let graph = self.graph.clone();
+        let instance = template.instantiate(args, || graph.reserve_id())?;
+
+        for module in instance.modules {
+            self.add_saved(module, &instance.connections);
+        }
+        for connection in &instance.connections {
+            self.connect_saved(connection);
+        }
+        for (id, param, value) in instance.params {
+            match self.graph.node(id).and_then(|node| node.params()) {
+                Some(params) => params.set(&param, value),
+                None => println!("Template {:?} binds {:?} of a module without params", template.name, param),
+            }
+        }
         Ok(())
     }
 }
 
-mod serial {
-    use gui::geom::*;
-    use module::flow::NodeId;
+/// The saved patch format.
+///
+/// Patches carry the `VERSION` of the format they were saved with. Older ones are brought up to
+/// date on load by the `Migrator`s in `Migrations::standard`, one per version bump, so a change to
+/// the format or a renamed module type doesn't break patches saved before it. Migrators work on
+/// the patch as an untyped `ron_value::Value`, so fields which have since changed shape can still
+/// be read, and the patch is only read into a `Root` once it's up to date.
+pub mod serial {
+    use crate::gui::geom::*;
+    use crate::gui::ron_value::Value;
+    use crate::module::expr::Expression;
+    use crate::module::flow::NodeId;
+    use crate::module::mapping::Mapping;
+    use crate::module::missing::{self, Link, Placeholder};
+    use crate::module::scene::Scene;
+    use crate::registry::Registry;
     use ron;
+    use serde::de::DeserializeOwned;
+    use serde_json;
+    use std::collections::BTreeMap;
+    use std::fmt;
     use std::io;
 
+    /// The format version written by `Root::save`. Patches saved before versioning read as 0.
+    pub const VERSION: u32 = 2;
+
     #[derive(Debug)]
     pub enum Error {
         IO(io::Error),
         Serialize(ron::ser::Error),
+        Deserialize(ron::de::Error),
+        /// Saved by a newer version of the format, which can't be read.
+        TooNew(u32),
+        /// Module types which aren't registered, each listed once.
+        UnknownModules(Vec<String>),
+        /// A template's arguments or bindings don't fit together.
+        Template(String),
     }
     impl From<io::Error> for Error {
         fn from(e: io::Error) -> Error {
@@ -221,24 +418,546 @@ mod serial {
             Error::Serialize(e)
         }
     }
+    impl From<ron::de::Error> for Error {
+        fn from(e: ron::de::Error) -> Error {
+            Error::Deserialize(e)
+        }
+    }
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                Error::IO(ref e) => write!(f, "{}", e),
+                Error::Serialize(ref e) => write!(f, "{}", e),
+                Error::Deserialize(ref e) => write!(f, "{}", e),
+                Error::TooNew(version) => write!(
+                    f,
+                    "patch format version {} is newer than the supported {}",
+                    version, VERSION
+                ),
+                Error::UnknownModules(ref types) => write!(
+                    f,
+                    "unknown module types: {} (they can be loaded as placeholders)",
+                    types.join(", ")
+                ),
+                Error::Template(ref message) => write!(f, "{}", message),
+            }
+        }
+    }
+
+    /// Rewrites a patch saved with the previous format version into the one it is registered for.
+    /// Templates are migrated too, and have the `modules` and `connections` of a patch.
+    pub trait Migrator {
+        fn migrate(&self, patch: &mut Value);
+    }
+
+    /// Version 2 saves whether a module is `active`, like scenes do, instead of `inactive`.
+    struct ActiveFlag;
+
+    impl Migrator for ActiveFlag {
+        fn migrate(&self, patch: &mut Value) {
+            let modules = match patch.field_mut("modules").and_then(Value::as_seq_mut) {
+                Some(modules) => modules,
+                None => return,
+            };
+            for module in modules {
+                if let Some(Value::Bool(inactive)) = module.remove_field("inactive") {
+                    module.set_field("active", Value::Bool(!inactive));
+                }
+            }
+        }
+    }
+
+    /// The migrators to run on patches older than `VERSION`.
+    pub struct Migrations {
+        /// By the version they migrate to.
+        migrators: BTreeMap<u32, Box<dyn Migrator>>,
+    }
+
+    impl Migrations {
+        pub fn new() -> Migrations {
+            Migrations {
+                migrators: BTreeMap::new(),
+            }
+        }
+        /// The migrations of every format change so far. Version 1 only added the version itself.
+        pub fn standard() -> Migrations {
+            let mut migrations = Migrations::new();
+            migrations.register(2, Box::new(ActiveFlag));
+            migrations
+        }
+        /// Run `migrator` on patches older than `version`. A version bump without one leaves
+        /// patches as they are.
+        pub fn register(&mut self, version: u32, migrator: Box<dyn Migrator>) {
+            self.migrators.insert(version, migrator);
+        }
+        /// Bring `patch` up to `VERSION`, one version at a time.
+        pub fn migrate(&self, patch: &mut Value) -> Result<(), Error> {
+            let saved = patch.field("version").and_then(Value::as_f64).unwrap_or(0.0) as u32;
+            if saved > VERSION {
+                return Err(Error::TooNew(saved));
+            }
+            for (&version, migrator) in self.migrators.range(saved + 1..VERSION + 1) {
+                migrator.migrate(patch);
+                patch.set_field("version", Value::number(version));
+            }
+            patch.set_field("version", Value::number(VERSION));
+            Ok(())
+        }
+    }
+
+    /// Read RON into a `T`, after migrating it.
+    fn read_migrated<R: io::Read, T: DeserializeOwned>(
+        mut reader: R,
+        migrations: &Migrations,
+    ) -> Result<T, Error> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut patch = Value::parse(&text).map_err(ron::de::Error::Message)?;
+        migrations.migrate(&mut patch)?;
+        Ok(ron::de::from_str(&patch.to_string())?)
+    }
+
+    /// Read a patch and migrate it to the current format. Unless `allow_missing` is set, a patch
+    /// using module types which aren't in `registry` is an error listing them.
+    pub fn read<R: io::Read>(
+        reader: R,
+        migrations: &Migrations,
+        registry: &Registry,
+        allow_missing: bool,
+    ) -> Result<Root, Error> {
+        let root: Root = read_migrated(reader, migrations)?;
+        let unknown = root.unknown_types(registry);
+        if !unknown.is_empty() && !allow_missing {
+            return Err(Error::UnknownModules(unknown));
+        }
+        Ok(root)
+    }
+
+    /// What a `Missing` module standing in for `module` keeps, with the connections to it.
+    pub fn placeholder(module: &Module, connections: &[Connection]) -> Placeholder {
+        let mut links = Vec::new();
+        for connection in connections {
+            if connection.src_node == module.id {
+                links.push(Link {
+                    port: connection.src_port.clone(),
+                    node: connection.dst_node,
+                    other_port: connection.dst_port.clone(),
+                    outgoing: true,
+                    gain: connection.gain,
+                });
+            }
+            if connection.dst_node == module.id {
+                links.push(Link {
+                    port: connection.dst_port.clone(),
+                    node: connection.src_node,
+                    other_port: connection.src_port.clone(),
+                    outgoing: false,
+                    gain: connection.gain,
+                });
+            }
+        }
+        Placeholder {
+            type_name: module.type_name.clone(),
+            state: module.state.clone(),
+            links,
+        }
+    }
+
+    /// The connections remembered by the placeholder of node `id`, as they were saved.
+    pub fn connections(id: NodeId, placeholder: &Placeholder) -> Vec<Connection> {
+        placeholder
+            .links
+            .iter()
+            .map(|link| {
+                let this = (id, link.port.clone());
+                let other = (link.node, link.other_port.clone());
+                let ((src_node, src_port), (dst_node, dst_port)) = if link.outgoing {
+                    (this, other)
+                } else {
+                    (other, this)
+                };
+                Connection {
+                    src_node,
+                    src_port,
+                    dst_node,
+                    dst_port,
+                    gain: link.gain,
+                }
+            })
+            .collect()
+    }
+
+    /// Turn a saved `Missing` module back into the module it stands in for, returning its
+    /// connections. Other modules are left alone.
+    pub fn unwrap_placeholder(module: &mut Module) -> Vec<Connection> {
+        if module.type_name != missing::NAME {
+            return Vec::new();
+        }
+        let placeholder: Placeholder = match serde_json::from_value(module.state.clone()) {
+            Ok(placeholder) => placeholder,
+            Err(_) => return Vec::new(),
+        };
+        module.tags.retain(|tag| tag != missing::TAG);
+        module.meta.remove(missing::META_KEY);
+        module.type_name = placeholder.type_name.clone();
+        module.state = placeholder.state.clone();
+        connections(module.id, &placeholder)
+    }
 
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Root {
+        #[serde(default)]
+        pub version: u32,
         pub modules: Vec<Module>,
         pub connections: Vec<Connection>,
+        #[serde(default)]
+        pub scenes: Vec<Scene>,
+        /// See `module::mapping`.
+        #[serde(default)]
+        pub mappings: Vec<Mapping>,
     }
-    #[derive(Debug, Serialize, Deserialize)]
+
+    impl Root {
+        /// Module types in the patch which `registry` can't create.
+        pub fn unknown_types(&self, registry: &Registry) -> Vec<String> {
+            unknown_types(&self.modules, registry)
+        }
+    }
+
+    fn unknown_types(modules: &[Module], registry: &Registry) -> Vec<String> {
+        let mut types: Vec<String> = Vec::new();
+        for module in modules {
+            if registry.factory(&module.type_name).is_none() && !types.contains(&module.type_name) {
+                types.push(module.type_name.clone());
+            }
+        }
+        types
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
     pub struct Module {
         pub bounds: Box3,
         pub id: NodeId,
         pub type_name: String,
+        /// Groups the node belongs to, e.g. for showing and hiding layers.
+        #[serde(default)]
+        pub tags: Vec<String>,
+        #[serde(default)]
+        pub muted: bool,
+        #[serde(default)]
+        pub bypassed: bool,
+        /// Internal state from `Module::save_state`.
+        #[serde(default)]
+        pub state: serde_json::Value,
+        #[serde(default = "active")]
+        pub active: bool,
+        /// See `Node::set_meta`.
+        #[serde(default)]
+        pub meta: BTreeMap<String, serde_json::Value>,
     }
-    #[derive(Debug, Serialize, Deserialize)]
+    fn active() -> bool {
+        true
+    }
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
     pub struct Connection {
         pub src_node: NodeId,
         pub src_port: String,
         pub dst_node: NodeId,
         pub dst_port: String,
+        /// See `flow::ConnectOptions::gain`.
+        #[serde(default)]
+        pub gain: Option<f32>,
+    }
+
+    /// A piece of a patch saved for reuse, like a voice of a synth. It declares arguments, such as
+    /// a base frequency, which its bindings turn into params of its modules each time it's added.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Template {
+        #[serde(default)]
+        pub version: u32,
+        pub name: String,
+        pub args: Vec<Arg>,
+        pub modules: Vec<Module>,
+        pub connections: Vec<Connection>,
+        #[serde(default)]
+        pub bindings: Vec<Binding>,
+    }
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct Arg {
+        pub name: String,
+        /// The value of arguments which aren't given.
+        pub default: f32,
+    }
+    /// Sets the param `param` of the module `node` to `value`, an expression of the arguments like
+    /// `base * 2`, in the syntax of `module::expr`.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct Binding {
+        pub node: NodeId,
+        pub param: String,
+        pub value: String,
+    }
+
+    /// The modules of a template with new ids, ready to be added to a patch.
+    #[derive(Debug)]
+    pub struct Instance {
+        pub modules: Vec<Module>,
+        pub connections: Vec<Connection>,
+        /// Bound params, as (node, param, value).
+        pub params: Vec<(NodeId, String, f32)>,
+    }
+
+    impl Template {
+        /// A template of the modules `nodes` of `root` and the connections between them, without
+        /// arguments. They can be added to the saved template by hand.
+        pub fn extract(name: &str, root: &Root, nodes: &[NodeId]) -> Template {
+            Template {
+                version: root.version,
+                name: name.into(),
+                args: Vec::new(),
+                modules: root
+                    .modules
+                    .iter()
+                    .filter(|module| nodes.contains(&module.id))
+                    .cloned()
+                    .collect(),
+                connections: root
+                    .connections
+                    .iter()
+                    .filter(|c| nodes.contains(&c.src_node) && nodes.contains(&c.dst_node))
+                    .cloned()
+                    .collect(),
+                bindings: Vec::new(),
+            }
+        }
+        /// Give the modules ids from `new_id` and work out the bound params, with `args` in place
+        /// of the defaults they name.
+        pub fn instantiate<F: FnMut() -> NodeId>(
+            &self,
+            args: &BTreeMap<String, f32>,
+            mut new_id: F,
+        ) -> Result<Instance, Error> {
+            for name in args.keys() {
+                if !self.args.iter().any(|arg| &arg.name == name) {
+                    return Err(Error::Template(format!("{} has no argument {:?}", self.name, name)));
+                }
+            }
+            let value = |name: &str| {
+                let arg = self.args.iter().find(|arg| arg.name == name)?;
+                Some(args.get(name).cloned().unwrap_or(arg.default))
+            };
+            let ids: BTreeMap<NodeId, NodeId> = self
+                .modules
+                .iter()
+                .map(|module| (module.id, new_id()))
+                .collect();
+
+            let mut params = Vec::new();
+            for binding in &self.bindings {
+                let invalid = |message: String| {
+                    Error::Template(format!("{} binding of {:?}: {}", self.name, binding.param, message))
+                };
+                let node = *ids
+                    .get(&binding.node)
+                    .ok_or_else(|| invalid(format!("no module {}", binding.node.0)))?;
+                let expression = Expression::compile(&binding.value).map_err(|e| invalid(e.to_string()))?;
+                let values = expression
+                    .variables()
+                    .iter()
+                    .map(|name| value(name).ok_or_else(|| invalid(format!("no argument {:?}", name))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                params.push((node, binding.param.clone(), expression.eval(&values)));
+            }
+            let modules = self
+                .modules
+                .iter()
+                .map(|module| Module {
+                    id: ids[&module.id],
+                    ..module.clone()
+                })
+                .collect();
+            let connections = self
+                .connections
+                .iter()
+                .filter_map(|c| {
+                    Some(Connection {
+                        src_node: *ids.get(&c.src_node)?,
+                        src_port: c.src_port.clone(),
+                        dst_node: *ids.get(&c.dst_node)?,
+                        dst_port: c.dst_port.clone(),
+                        gain: c.gain,
+                    })
+                })
+                .collect();
+            Ok(Instance {
+                modules,
+                connections,
+                params,
+            })
+        }
+    }
+
+    /// Read a template and migrate it to the current format, like `read`.
+    pub fn read_template<R: io::Read>(
+        reader: R,
+        migrations: &Migrations,
+        registry: &Registry,
+    ) -> Result<Template, Error> {
+        let template: Template = read_migrated(reader, migrations)?;
+        let unknown = unknown_types(&template.modules, registry);
+        if !unknown.is_empty() {
+            return Err(Error::UnknownModules(unknown));
+        }
+        Ok(template)
+    }
+
+    #[test]
+    fn test_migrate() {
+        struct Rename;
+        impl Migrator for Rename {
+            fn migrate(&self, patch: &mut Value) {
+                let modules = patch.field_mut("modules").and_then(Value::as_seq_mut);
+                for module in modules.into_iter().flatten() {
+                    if module.field("type_name").and_then(Value::as_str) == Some("Amp") {
+                        module.set_field("type_name", Value::String("Gain".into()));
+                    }
+                }
+            }
+        }
+        let module = |id, type_name: &str| Module {
+            bounds: Box3::default(),
+            id: NodeId(id),
+            type_name: type_name.into(),
+            tags: Vec::new(),
+            muted: false,
+            bypassed: false,
+            state: serde_json::Value::Null,
+            active: true,
+            meta: BTreeMap::new(),
+        };
+        let old = Root {
+            version: 0,
+            modules: vec![module(1, "Amp"), module(2, "Theremin"), module(3, "Theremin")],
+            connections: Vec::new(),
+            scenes: Vec::new(),
+            mappings: Vec::new(),
+        };
+        let data = ron::ser::to_string(&old).unwrap();
+        let registry = Registry::standard();
+        let mut migrations = Migrations::new();
+        migrations.register(1, Box::new(Rename));
+
+        match read(data.as_bytes(), &migrations, &registry, false) {
+            Err(Error::UnknownModules(types)) => assert_eq!(types, vec!["Theremin".to_string()]),
+            result => panic!("unexpected {:?}", result),
+        }
+        let root = read(data.as_bytes(), &migrations, &registry, true).unwrap();
+        assert_eq!(root.version, VERSION);
+        assert_eq!(root.modules[0].type_name, "Gain");
+
+        // a placeholder is saved as the module it stands in for
+        let connections = vec![Connection {
+            src_node: NodeId(2),
+            src_port: "Output".into(),
+            dst_node: NodeId(1),
+            dst_port: "Input".into(),
+            gain: Some(0.5),
+        }];
+        let placeholder = placeholder(&root.modules[1], &connections);
+        assert_eq!(placeholder.port_names(), vec!["Output".to_string()]);
+        let mut saved = module(2, missing::NAME);
+        saved.tags.push(missing::TAG.into());
+        saved.state = serde_json::to_value(&placeholder).unwrap();
+        assert_eq!(unwrap_placeholder(&mut saved), connections);
+        assert_eq!(saved.type_name, "Theremin");
+        assert!(saved.tags.is_empty());
+
+        let mut newer = old;
+        newer.version = VERSION + 1;
+        let newer = ron::ser::to_string(&newer).unwrap();
+        assert!(match read(newer.as_bytes(), &migrations, &registry, true) {
+            Err(Error::TooNew(version)) => version == VERSION + 1,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_migrate_standard() {
+        // saved before versioning, with inactive modules flagged as such
+        let bounds = ron::ser::to_string(&Box3::default()).unwrap();
+        let data = format!(
+            "(modules: [
+                (bounds: {0}, id: (1), type_name: \"Gain\", inactive: true),
+                (bounds: {0}, id: (2), type_name: \"Gain\"),
+            ], connections: [])",
+            bounds
+        );
+        let registry = Registry::standard();
+        let root = read(data.as_bytes(), &Migrations::standard(), &registry, false).unwrap();
+        assert_eq!(root.version, VERSION);
+        assert!(!root.modules[0].active);
+        assert!(root.modules[1].active);
+        // without the migration the flag would be lost
+        let root = read(data.as_bytes(), &Migrations::new(), &registry, false).unwrap();
+        assert!(root.modules[0].active);
+    }
+
+    #[test]
+    fn test_template() {
+        let module = |id, type_name: &str| Module {
+            bounds: Box3::default(),
+            id: NodeId(id),
+            type_name: type_name.into(),
+            tags: Vec::new(),
+            muted: false,
+            bypassed: false,
+            state: serde_json::Value::Null,
+            active: true,
+            meta: BTreeMap::new(),
+        };
+        let connection = |src_node, dst_node| Connection {
+            src_node: NodeId(src_node),
+            src_port: "Output".into(),
+            dst_node: NodeId(dst_node),
+            dst_port: "Input".into(),
+            gain: None,
+        };
+        let root = Root {
+            version: VERSION,
+            modules: vec![module(1, "Gain"), module(2, "Filter"), module(3, "Mixer")],
+            connections: vec![connection(1, 2), connection(2, 3)],
+            scenes: Vec::new(),
+            mappings: Vec::new(),
+        };
+        let mut template = Template::extract("Voice", &root, &[NodeId(1), NodeId(2)]);
+        assert_eq!(template.connections, vec![connection(1, 2)]);
+        template.args.push(Arg {
+            name: "base".into(),
+            default: 110.0,
+        });
+        template.bindings.push(Binding {
+            node: NodeId(2),
+            param: "Cutoff".into(),
+            value: "base * 4".into(),
+        });
+
+        let mut next = 10;
+        let mut new_id = || {
+            next += 1;
+            NodeId(next)
+        };
+        let instance = template.instantiate(&BTreeMap::new(), &mut new_id).unwrap();
+        assert_eq!(instance.modules[1].id, NodeId(12));
+        assert_eq!(instance.connections, vec![connection(11, 12)]);
+        assert_eq!(instance.params, vec![(NodeId(12), "Cutoff".to_string(), 440.0)]);
+        let mut args = BTreeMap::new();
+        args.insert("base".to_string(), 55.0);
+        let instance = template.instantiate(&args, &mut new_id).unwrap();
+        assert_eq!(instance.params[0].2, 220.0);
+
+        args.insert("voices".to_string(), 4.0);
+        assert!(template.instantiate(&args, &mut new_id).is_err());
+        template.bindings[0].value = "octave * 2".into();
+        assert!(template.instantiate(&BTreeMap::new(), &mut new_id).is_err());
     }
 }
 
@@ -292,7 +1011,34 @@ impl GuiComponent for Root {
                     },
                 state: ButtonState::Pressed,
             }) => {
-                println!("Load: {:?}", self.load("project.fsy"));
+                println!("Load: {:?}", self.load("project.fsy", false));
+            }
+            EventData::Key(KeyEvent {
+                code: VirtualKeyCode::L,
+                modifiers:
+                    KeyModifiers {
+                        ctrl: true,
+                        shift: true,
+                        alt: false,
+                        logo: false,
+                    },
+                state: ButtonState::Pressed,
+            }) => {
+                // keep modules of unknown types as placeholders
+                println!("Load: {:?}", self.load("project.fsy", true));
+            }
+            EventData::Key(KeyEvent {
+                code: VirtualKeyCode::T,
+                modifiers:
+                    KeyModifiers {
+                        ctrl: true,
+                        shift: false,
+                        alt: false,
+                        logo: false,
+                    },
+                state: ButtonState::Pressed,
+            }) => {
+                println!("Template: {:?}", self.add_template("template.fsy", &BTreeMap::new()));
             }
             EventData::Key(_) | EventData::Character(_) => {
                 for module in &mut self.modules {
@@ -311,9 +1057,11 @@ impl GuiComponent for Root {
                         let status = menu.handle(&event.with_focus(true));
                         match status {
                             MenuUpdate::Select(path) => {
-                                let name: &str = path[0].as_ref();
+                                let name: &str = path.last().unwrap().as_ref();
                                 let bounds = Box3::new(pos.with_z(0.0), Pt2::from(256.0).with_z(0.0));
-                                let id = self.new_module(name, bounds, None).unwrap();
+                                let id = self
+                                    .new_module(name, bounds, None, serde_json::Value::Null)
+                                    .unwrap();
                                 self.move_to_front(id);
                                 self.context_menu = None;
                             }
@@ -370,15 +1118,3 @@ impl GuiComponent for Root {
         }
     }
 }
-
-fn load_metamodules() -> Vec<Box<dyn GuiModuleFactory>> {
-    use module::audio_io::*;
-    use module::debug::*;
-    use module::livecode::*;
-    vec![
-        Box::new(BasicGuiModuleFactory::<Printer<i32>>::new()),
-        Box::new(BasicGuiModuleFactory::<Counter<i32>>::new()),
-        Box::new(BasicGuiModuleFactory::<AudioIO>::new()),
-        Box::new(BasicGuiModuleFactory::<LiveCode>::new()),
-    ]
-}
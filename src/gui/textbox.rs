@@ -1,4 +1,4 @@
-use gui::{component::*, event::*, geom::*, RenderContext};
+use crate::gui::{component::*, event::*, geom::*, RenderContext};
 
 use gfx_device_gl as gl;
 
@@ -21,8 +21,12 @@ impl TextBox {
             focused: false,
         }
     }
+    pub fn content(&self) -> &str {
+        &self.content
+    }
     pub fn set_content(&mut self, content: String) {
         self.content = content;
+        self.cursor = self.cursor.min(self.content.len());
     }
     pub fn set_focused(&mut self, focused: bool) {
         self.focused = focused;
@@ -69,7 +73,7 @@ impl GuiComponent<TextBoxUpdate> for TextBox {
             EventData::Click(pos, button, state)
                 if button == MouseButton::Left && state == ButtonState::Pressed =>
             {
-                self.focused = event.focus;
+                self.focused = event.focus && self.bounds.flatten().drop_z().intersect(pos);
                 TextBoxUpdate::NeedRender
             }
             EventData::Key(kev) if self.focused => {
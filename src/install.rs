@@ -0,0 +1,814 @@
+//! Headless runtime for long running installations.
+//!
+//! Runs a patch saved from the GUI without a window, and keeps it running. A module whose audio
+//! outputs have requests waiting that go unanswered for too long is considered faulted, and is
+//! rebuilt in place with its connections and state. If a module keeps faulting, or the audio
+//! interface stops pulling audio altogether, the whole patch is reloaded. Everything the watchdog
+//! does is appended to an event log, one JSON object per line.
+//!
+//! Started by setting `FLOW_SYNTH_INSTALLATION` to the path of the patch. Setting
+//! `FLOW_SYNTH_WATCH` as well makes it a live coding runner: whenever the patch file is saved, the
+//! running patch is brought in line with it, so a patch can be edited in a text editor and heard
+//! straight away. Only what changed is touched: modules which were added, removed, or given another
+//! type or state are rebuilt, connections are made and broken one by one, and everything else
+//! keeps playing.
+
+use futures::executor::ThreadPool;
+
+use crate::gui::root::serial;
+use crate::module::missing::{self, Missing, Placeholder};
+use crate::module::{audio_io::Frame, flow, Module};
+use crate::registry::Registry;
+use crate::rpc;
+
+use serde_json::{self, Value};
+
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Module types which play audio out of the machine, so the patch is dead if they stop pulling.
+const AUDIO_OUTPUTS: &[&str] = &["AudioIO"];
+
+/// Object safe view of a module, for hosts which don't know its type.
+pub trait DynModule: Send {
+    fn start(&mut self, exec: ThreadPool);
+    fn stop(&mut self);
+    fn save_state(&self) -> Value;
+    fn load_state(&mut self, state: Value);
+}
+
+impl<T: Module> DynModule for T {
+    fn start(&mut self, exec: ThreadPool) {
+        Module::start(self, exec)
+    }
+    fn stop(&mut self) {
+        Module::stop(self)
+    }
+    fn save_state(&self) -> Value {
+        Module::save_state(self)
+    }
+    fn load_state(&mut self, state: Value) {
+        Module::load_state(self, state)
+    }
+}
+
+pub struct Config {
+    pub patch: PathBuf,
+    pub log: PathBuf,
+    /// How often the watchdog looks at the graph.
+    pub check_interval: Duration,
+    /// How long audio may go unanswered before the module responsible is restarted.
+    pub stall_timeout: Duration,
+    /// Restarts of one module within `restart_window` before giving up and reloading the patch.
+    pub max_restarts: usize,
+    pub restart_window: Duration,
+    /// Load modules of unknown types as placeholders, rather than refusing the whole patch.
+    pub allow_missing: bool,
+    /// Apply changes to the patch file as it's saved, checked every `check_interval`.
+    pub watch: bool,
+}
+
+impl Config {
+    /// Default settings for a patch, logging to the same path with a `.log` extension.
+    pub fn new(patch: PathBuf) -> Config {
+        Config {
+            log: patch.with_extension("log"),
+            patch,
+            check_interval: Duration::from_secs(1),
+            stall_timeout: Duration::from_secs(5),
+            max_restarts: 3,
+            restart_window: Duration::from_secs(600),
+            allow_missing: true,
+            watch: false,
+        }
+    }
+}
+
+/// What changed between two versions of a patch file, as `Installation` applies it in watch mode.
+/// Changes to a module's tags, meta, mute, bypass and active flags are made on its node in place,
+/// so they aren't part of the diff.
+#[derive(Debug, Default, PartialEq)]
+pub struct PatchDiff {
+    /// Modules which are gone, or have another type or state, so their nodes are taken out.
+    pub removed: BTreeSet<flow::NodeId>,
+    /// Modules to build, both new ones and those replacing removed ones.
+    pub added: BTreeSet<flow::NodeId>,
+    /// Connections to break. Those of removed modules go with them, so they aren't listed.
+    pub disconnect: Vec<serial::Connection>,
+    /// Connections to make, which includes every connection of an added module.
+    pub connect: Vec<serial::Connection>,
+}
+
+impl PatchDiff {
+    pub fn new(old: &serial::Root, new: &serial::Root) -> PatchDiff {
+        fn find(root: &serial::Root, id: flow::NodeId) -> Option<&serial::Module> {
+            root.modules.iter().find(|module| module.id == id)
+        }
+        let mut diff = PatchDiff::default();
+        for module in &old.modules {
+            match find(new, module.id) {
+                Some(saved) if saved.type_name == module.type_name && saved.state == module.state => {}
+                _ => {
+                    diff.removed.insert(module.id);
+                }
+            }
+        }
+        for saved in &new.modules {
+            if find(old, saved.id).is_none() || diff.removed.contains(&saved.id) {
+                diff.added.insert(saved.id);
+            }
+        }
+        let touches = |ids: &BTreeSet<flow::NodeId>, connection: &serial::Connection| {
+            ids.contains(&connection.src_node) || ids.contains(&connection.dst_node)
+        };
+        for connection in &old.connections {
+            if !new.connections.contains(connection) && !touches(&diff.removed, connection) {
+                diff.disconnect.push(connection.clone());
+            }
+        }
+        for connection in &new.connections {
+            if !old.connections.contains(connection) || touches(&diff.added, connection) {
+                diff.connect.push(connection.clone());
+            }
+        }
+        diff
+    }
+}
+
+struct Running {
+    id: flow::NodeId,
+    type_name: String,
+    module: Box<dyn DynModule>,
+    /// When the module was last restarted, within the restart window.
+    restarts: Vec<Instant>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.module.stop();
+    }
+}
+
+pub struct Installation {
+    config: Config,
+    graph: Arc<flow::Graph>,
+    registry: Registry,
+    modules: Vec<Running>,
+    exec: ThreadPool,
+    log: File,
+    rpc: Option<rpc::Server>,
+    /// Last seen activity counter of each watched port, and when it last changed.
+    activity: HashMap<flow::PortRef, (usize, Instant)>,
+    /// The patch as it was last read, to compare changes to the file with.
+    loaded: Option<serial::Root>,
+    /// When the patch file was last modified, as of the last read.
+    modified: Option<SystemTime>,
+}
+
+impl Installation {
+    pub fn start(config: Config) -> io::Result<Installation> {
+        let log = OpenOptions::new().create(true).append(true).open(&config.log)?;
+        let mut installation = Installation {
+            config,
+            graph: flow::Graph::new(),
+            registry: Registry::standard(),
+            modules: Vec::new(),
+            exec: ThreadPool::new()?,
+            log,
+            rpc: None,
+            activity: HashMap::new(),
+            loaded: None,
+            modified: None,
+        };
+        if let Ok(addr) = env::var("FLOW_SYNTH_RPC") {
+            let modules = installation.registry.available_modules();
+            installation.rpc = Some(rpc::Server::start(&addr, installation.graph.clone(), modules)?);
+        }
+        let patch = installation.config.patch.display().to_string();
+        installation.log(json!({ "event": "start", "patch": patch }));
+        installation.load();
+        Ok(installation)
+    }
+
+    /// Watch the patch forever.
+    pub fn run(mut self) {
+        loop {
+            thread::sleep(self.config.check_interval);
+            self.check();
+        }
+    }
+
+    fn log(&mut self, mut event: Value) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_secs())
+            .unwrap_or(0);
+        event["time"] = json!(time);
+        println!("installation: {}", event);
+        if let Err(e) = writeln!(self.log, "{}", event) {
+            println!("installation log err: {:?}", e);
+        }
+    }
+
+    /// Read the patch file, logging why if that fails.
+    fn read_patch(&mut self) -> Option<serial::Root> {
+        self.modified = fs::metadata(&self.config.patch)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let migrations = serial::Migrations::standard();
+        let read = File::open(&self.config.patch)
+            .map_err(serial::Error::from)
+            .and_then(|file| serial::read(file, &migrations, &self.registry, self.config.allow_missing));
+        match read {
+            Ok(root) => Some(root),
+            Err(e) => {
+                self.log(json!({ "event": "load_failed", "error": e.to_string() }));
+                None
+            }
+        }
+    }
+
+    /// Build and start the patch in the current graph.
+    fn load(&mut self) {
+        let root = match self.read_patch() {
+            Some(root) => root,
+            None => return,
+        };
+        for saved in &root.modules {
+            self.add_saved(saved.clone(), &root.connections);
+        }
+        for scene in &root.scenes {
+            self.graph.insert_scene(scene.clone());
+        }
+        self.graph.set_mappings(root.mappings.clone());
+        for connection in &root.connections {
+            self.connect_saved(connection);
+        }
+        for running in &mut self.modules {
+            running.module.start(self.exec.clone());
+        }
+        self.loaded = Some(root);
+    }
+
+    /// Build a saved module in the current graph, without starting it.
+    fn add_saved(&mut self, saved: serial::Module, connections: &[serial::Connection]) {
+        let (type_name, mut module, state) = match self.registry.factory(&saved.type_name) {
+            Some(factory) => {
+                let module = factory.new_headless(self.graph.add_node_with_id(saved.id));
+                (saved.type_name, module, saved.state)
+            }
+            None => {
+                self.log(json!({ "event": "unknown_module", "type": saved.type_name }));
+                let placeholder = serial::placeholder(&saved, connections);
+                let ifc = self.graph.add_node_with_id(saved.id);
+                let module: Box<dyn DynModule> = Box::new(Missing::new(ifc));
+                let state = serde_json::to_value(placeholder).unwrap();
+                (missing::NAME.to_string(), module, state)
+            }
+        };
+        let node = self.graph.node(saved.id).unwrap();
+        for tag in &saved.tags {
+            node.add_tag(tag);
+        }
+        for (key, value) in saved.meta {
+            node.set_meta(&key, value);
+        }
+        node.set_muted(saved.muted);
+        node.set_bypassed(saved.bypassed);
+        node.set_active(saved.active);
+        if !state.is_null() {
+            module.load_state(state);
+        }
+        self.modules.push(Running {
+            id: saved.id,
+            type_name,
+            module,
+            restarts: Vec::new(),
+        });
+    }
+
+    /// Stop a module and take its node out of the graph, disconnecting it.
+    fn remove_module(&mut self, id: flow::NodeId) {
+        if let Some(idx) = self.modules.iter().position(|running| running.id == id) {
+            drop(self.modules.remove(idx));
+        }
+        if let Some(node) = self.graph.node(id) {
+            for port in node.ports() {
+                let _ = port.disconnect();
+            }
+        }
+        let _ = self.graph.remove_node(id);
+        self.activity.retain(|port, _| port.node != id);
+    }
+
+    /// Bring the running patch in line with the patch file, touching only what changed in it.
+    fn reload(&mut self) {
+        let root = match self.read_patch() {
+            Some(root) => root,
+            None => return,
+        };
+        let old = match self.loaded.take() {
+            Some(old) => old,
+            // nothing was loaded to compare with
+            None => return self.restart_graph("patch changed"),
+        };
+        let diff = PatchDiff::new(&old, &root);
+        for &id in &diff.removed {
+            self.remove_module(id);
+        }
+        for saved in &root.modules {
+            if diff.added.contains(&saved.id) {
+                self.add_saved(saved.clone(), &root.connections);
+                continue;
+            }
+            let node = match self.graph.node(saved.id) {
+                Some(node) => node,
+                None => continue,
+            };
+            for tag in node.tags() {
+                if !saved.tags.contains(&tag) {
+                    node.remove_tag(&tag);
+                }
+            }
+            for tag in &saved.tags {
+                node.add_tag(tag);
+            }
+            for key in node.meta_map().keys() {
+                if !saved.meta.contains_key(key) {
+                    node.remove_meta(key);
+                }
+            }
+            for (key, value) in &saved.meta {
+                if node.meta_map().get(key) != Some(value) {
+                    node.set_meta(key, value.clone());
+                }
+            }
+            node.set_muted(saved.muted);
+            node.set_bypassed(saved.bypassed);
+            node.set_active(saved.active);
+        }
+        for connection in &diff.disconnect {
+            self.disconnect_saved(connection);
+        }
+        for connection in &diff.connect {
+            self.connect_saved(connection);
+        }
+
+        for scene in &old.scenes {
+            self.graph.remove_scene(&scene.name);
+        }
+        for scene in &root.scenes {
+            self.graph.insert_scene(scene.clone());
+        }
+        self.graph.set_mappings(root.mappings.clone());
+        for running in &mut self.modules {
+            if diff.added.contains(&running.id) {
+                running.module.start(self.exec.clone());
+            }
+        }
+        let rebuilt: Vec<_> = diff.added.iter().map(|id| id.0).collect();
+        let dropped: Vec<_> = diff.removed.difference(&diff.added).map(|id| id.0).collect();
+        self.log(json!({
+            "event": "reload",
+            "built": rebuilt,
+            "removed": dropped,
+            "connected": diff.connect.len(),
+            "disconnected": diff.disconnect.len(),
+        }));
+        self.loaded = Some(root);
+    }
+
+    /// Whether the patch file was modified since it was last read.
+    fn patch_changed(&self) -> bool {
+        let modified = fs::metadata(&self.config.patch)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        modified.is_some() && modified != self.modified
+    }
+
+    /// Connect the ports of a saved connection, logging if that fails. Connections of placeholders
+    /// are left to them.
+    fn connect_saved(&mut self, connection: &serial::Connection) {
+        let is_placeholder = |id| {
+            self.graph
+                .node(id)
+                .map(|node| node.has_tag(missing::TAG))
+                .unwrap_or(false)
+        };
+        if is_placeholder(connection.src_node) || is_placeholder(connection.dst_node) {
+            return;
+        }
+        let port = |node: flow::NodeId, name: &str| {
+            self.graph
+                .node(node)
+                .and_then(|node| node.ports().into_iter().find(|port| port.name() == name))
+        };
+        let src = port(connection.src_node, &connection.src_port);
+        let dst = port(connection.dst_node, &connection.dst_port);
+        let result = match (src, dst) {
+            (Some(src), Some(dst)) => {
+                let options = flow::ConnectOptions {
+                    gain: connection.gain,
+                    ..flow::ConnectOptions::default()
+                };
+                src.connect_with(&dst, options).map_err(|e| format!("{:?}", e))
+            }
+            _ => Err("port not found".to_string()),
+        };
+        if let Err(e) = result {
+            self.log(json!({
+                "event": "connect_failed",
+                "src": [connection.src_node.0, connection.src_port],
+                "dst": [connection.dst_node.0, connection.dst_port],
+                "error": e,
+            }));
+        }
+    }
+
+    /// Break a saved connection, if the ports are still connected to each other.
+    fn disconnect_saved(&mut self, connection: &serial::Connection) {
+        let src = self.graph.node(connection.src_node).and_then(|node| {
+            node.ports()
+                .into_iter()
+                .find(|port| port.name() == connection.src_port)
+        });
+        if let Some(src) = src {
+            let connected = src.edge().map_or(false, |dst| {
+                dst.node_id() == connection.dst_node && dst.name() == connection.dst_port
+            });
+            if connected {
+                let _ = src.disconnect();
+            }
+        }
+    }
+
+    /// Replace the placeholders of module types which have been registered since the patch was
+    /// loaded with the real modules, connected as the patch had them.
+    fn fill_missing(&mut self) {
+        let ids: Vec<flow::NodeId> = self
+            .modules
+            .iter()
+            .filter(|running| running.type_name == missing::NAME)
+            .map(|running| running.id)
+            .collect();
+        for id in ids {
+            let idx = self.modules.iter().position(|running| running.id == id).unwrap();
+            let state = self.modules[idx].module.save_state();
+            let placeholder: Placeholder = match serde_json::from_value(state) {
+                Ok(placeholder) => placeholder,
+                Err(_) => continue,
+            };
+            let mut module = match self.registry.factory(&placeholder.type_name) {
+                Some(factory) => {
+                    drop(self.modules.remove(idx));
+                    let node = self.graph.node(id).unwrap();
+                    let _ = self.graph.remove_node(id);
+                    let module = factory.new_headless(self.graph.add_node_with_id(id));
+                    let new_node = self.graph.node(id).unwrap();
+                    for tag in node.tags() {
+                        if tag != missing::TAG {
+                            new_node.add_tag(&tag);
+                        }
+                    }
+                    for (key, value) in node.meta_map() {
+                        if key != missing::META_KEY {
+                            new_node.set_meta(&key, value);
+                        }
+                    }
+                    new_node.set_muted(node.muted());
+                    new_node.set_bypassed(node.bypassed());
+                    new_node.set_active(node.active());
+                    module
+                }
+                None => continue,
+            };
+            self.log(json!({ "event": "fill_missing", "node": id.0, "type": placeholder.type_name }));
+            if !placeholder.state.is_null() {
+                module.load_state(placeholder.state.clone());
+            }
+            for connection in serial::connections(id, &placeholder) {
+                self.connect_saved(&connection);
+            }
+            module.start(self.exec.clone());
+            self.modules.push(Running {
+                id,
+                type_name: placeholder.type_name,
+                module,
+                restarts: Vec::new(),
+            });
+        }
+    }
+
+    /// One pass of the watchdog.
+    fn check(&mut self) {
+        if self.config.watch && self.patch_changed() {
+            self.reload();
+        }
+        self.fill_missing();
+        let now = Instant::now();
+        let timeout = self.config.stall_timeout;
+        let mut faulted = BTreeSet::new();
+        let mut audio_stalled = false;
+        let mut seen = HashMap::new();
+        for node in self.graph.nodes() {
+            let type_name = self
+                .modules
+                .iter()
+                .find(|running| running.id == node.id())
+                .map(|running| running.type_name.as_str());
+            let is_audio_output = type_name
+                .map(|name| AUDIO_OUTPUTS.contains(&name))
+                .unwrap_or(false);
+            for port in node.ports() {
+                let other = match port.edge() {
+                    Some(other) => other,
+                    None => continue,
+                };
+                if port.as_typed::<(), Frame>().is_some() {
+                    // every request written to an output is answered by a frame arriving at the
+                    // other end, so requests outnumbering answers that haven't moved in a while
+                    // mean the module is stuck
+                    let (requests, answers) = (port.received(), other.received());
+                    let (last, since) = *self.activity.get(&port.port_ref()).unwrap_or(&(answers, now));
+                    let since = if answers != last || requests <= answers {
+                        now
+                    } else {
+                        since
+                    };
+                    if now.duration_since(since) > timeout {
+                        faulted.insert(node.id());
+                    }
+                    seen.insert(port.port_ref(), (answers, since));
+                } else if is_audio_output && port.as_typed::<Frame, ()>().is_some() {
+                    let received = port.received();
+                    let (last, since) = *self.activity.get(&port.port_ref()).unwrap_or(&(received, now));
+                    let since = if received != last { now } else { since };
+                    if now.duration_since(since) > timeout {
+                        audio_stalled = true;
+                    }
+                    seen.insert(port.port_ref(), (received, since));
+                }
+            }
+        }
+        self.activity = seen;
+
+        if !faulted.is_empty() {
+            for id in faulted {
+                if !self.restart_module(id) {
+                    self.restart_graph("module keeps faulting");
+                    return;
+                }
+            }
+        } else if audio_stalled {
+            self.restart_graph("audio output stalled");
+        }
+    }
+
+    /// Rebuild a module in place. Returns false if it has been restarted too often.
+    fn restart_module(&mut self, id: flow::NodeId) -> bool {
+        let idx = match self.modules.iter().position(|running| running.id == id) {
+            Some(idx) => idx,
+            None => return true,
+        };
+        let now = Instant::now();
+        let window = self.config.restart_window;
+        let mut restarts = self.modules[idx].restarts.clone();
+        restarts.retain(|&time| now.duration_since(time) < window);
+        if restarts.len() >= self.config.max_restarts {
+            return false;
+        }
+        restarts.push(now);
+        let type_name = self.modules[idx].type_name.clone();
+        self.log(json!({ "event": "restart_module", "node": id.0, "type": type_name }));
+
+        let old = self.modules.remove(idx);
+        let state = old.module.save_state();
+        let node = self.graph.node(id).unwrap();
+        let mut connections = Vec::new();
+        for port in node.ports() {
+            if let Some(other) = port.edge() {
+                connections.push((port.name().to_string(), other.port_ref(), port.connect_options()));
+                let _ = port.disconnect();
+            }
+        }
+        drop(old);
+        let _ = self.graph.remove_node(id);
+        self.activity.retain(|port, _| port.node != id);
+
+        let factory = self.registry.factory(&type_name).unwrap();
+        let mut module = factory.new_headless(self.graph.add_node_with_id(id));
+        let new_node = self.graph.node(id).unwrap();
+        for tag in node.tags() {
+            new_node.add_tag(&tag);
+        }
+        for (key, value) in node.meta_map() {
+            new_node.set_meta(&key, value);
+        }
+        new_node.set_muted(node.muted());
+        new_node.set_bypassed(node.bypassed());
+        new_node.set_active(node.active());
+        if !state.is_null() {
+            module.load_state(state);
+        }
+        for (name, other, options) in connections {
+            let port = new_node.ports().into_iter().find(|port| port.name() == name);
+            let other = self.graph.port(other.node, other.port);
+            if let (Some(port), Some(other)) = (port, other) {
+                if let Err(e) = port.connect_with(&other, options) {
+                    self.log(json!({
+                        "event": "reconnect_failed",
+                        "node": id.0,
+                        "port": name,
+                        "error": format!("{:?}", e),
+                    }));
+                }
+            }
+        }
+        module.start(self.exec.clone());
+        self.modules.push(Running {
+            id,
+            type_name,
+            module,
+            restarts,
+        });
+        true
+    }
+
+    /// Tear everything down and load the patch again into a fresh graph.
+    fn restart_graph(&mut self, reason: &str) {
+        self.log(json!({ "event": "restart_graph", "reason": reason }));
+        self.modules.clear();
+        self.activity.clear();
+        self.loaded = None;
+        self.graph = flow::Graph::new();
+        if let Some(ref rpc) = self.rpc {
+            rpc.set_graph(self.graph.clone());
+        }
+        self.load();
+    }
+}
+
+/// Run the patch at `FLOW_SYNTH_INSTALLATION`, if set, returning false otherwise.
+pub fn main() -> bool {
+    let patch = match env::var("FLOW_SYNTH_INSTALLATION") {
+        Ok(patch) => patch,
+        Err(_) => return false,
+    };
+    let mut config = Config::new(patch.into());
+    config.watch = env::var("FLOW_SYNTH_WATCH").is_ok();
+    match Installation::start(config) {
+        Ok(installation) => installation.run(),
+        Err(e) => println!("installation err: {:?}", e),
+    }
+    true
+}
+
+#[test]
+fn test_installation() {
+    use crate::gui::geom::Box3;
+    use ron;
+    use std::collections::BTreeMap;
+
+    let dir = env::temp_dir().join(format!("flow-synth-install-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut config = Config::new(dir.join("patch.ron"));
+    config.max_restarts = 2;
+    assert_eq!(config.log, dir.join("patch.log"));
+    let events = |log: &PathBuf| -> Vec<String> {
+        fs::read_to_string(log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["event"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    // a missing patch is logged and leaves the graph empty
+    let missing = Installation::start(Config::new(dir.join("missing.ron"))).unwrap();
+    assert!(missing.graph.nodes().is_empty());
+    assert_eq!(events(&dir.join("missing.log")), vec!["start", "load_failed"]);
+
+    let module = |id, type_name: &str| serial::Module {
+        bounds: Box3::default(),
+        id: flow::NodeId(id),
+        type_name: type_name.into(),
+        tags: vec!["layer".into()],
+        muted: false,
+        bypassed: false,
+        state: Value::Null,
+        active: true,
+        meta: BTreeMap::new(),
+    };
+    let root = serial::Root {
+        version: 0,
+        modules: vec![module(1, "Gain"), module(2, "Gain"), module(3, "Theremin")],
+        connections: vec![serial::Connection {
+            src_node: flow::NodeId(1),
+            src_port: "Output".into(),
+            dst_node: flow::NodeId(2),
+            dst_port: "Input".into(),
+            gain: None,
+        }],
+        scenes: Vec::new(),
+        mappings: Vec::new(),
+    };
+    fs::write(&config.patch, ron::ser::to_string(&root).unwrap()).unwrap();
+    let log = config.log.clone();
+    let mut installation = Installation::start(config).unwrap();
+    assert_eq!(installation.graph.nodes().len(), 3);
+    assert_eq!(installation.modules[2].type_name, missing::NAME);
+    let connected = |installation: &Installation| {
+        let node = installation.graph.node(flow::NodeId(2)).unwrap();
+        let input = node.ports().into_iter().find(|port| port.name() == "Input").unwrap();
+        input.edge().map(|other| other.node_id()) == Some(flow::NodeId(1))
+    };
+    assert!(connected(&installation));
+
+    // a restarted module keeps its connections and tags, until it has restarted too often
+    assert!(installation.restart_module(flow::NodeId(1)));
+    assert!(installation.restart_module(flow::NodeId(1)));
+    assert!(connected(&installation));
+    assert!(installation.graph.node(flow::NodeId(1)).unwrap().has_tag("layer"));
+    assert!(!installation.restart_module(flow::NodeId(1)));
+
+    installation.restart_graph("test");
+    assert_eq!(installation.graph.nodes().len(), 3);
+    assert!(connected(&installation));
+    assert_eq!(
+        events(&log),
+        vec![
+            "start",
+            "unknown_module",
+            "restart_module",
+            "restart_module",
+            "restart_graph",
+            "unknown_module",
+        ]
+    );
+    drop(installation);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_patch_diff() {
+    use crate::gui::geom::Box3;
+    use std::collections::BTreeMap;
+
+    let module = |id, state: Value| serial::Module {
+        bounds: Box3::default(),
+        id: flow::NodeId(id),
+        type_name: "Gain".into(),
+        tags: Vec::new(),
+        muted: false,
+        bypassed: false,
+        state,
+        active: true,
+        meta: BTreeMap::new(),
+    };
+    let connection = |src, dst| serial::Connection {
+        src_node: flow::NodeId(src),
+        src_port: "Output".into(),
+        dst_node: flow::NodeId(dst),
+        dst_port: "Input".into(),
+        gain: None,
+    };
+    let root = |modules, connections| serial::Root {
+        version: serial::VERSION,
+        modules,
+        connections,
+        scenes: Vec::new(),
+        mappings: Vec::new(),
+    };
+    let old = root(
+        vec![module(1, Value::Null), module(2, Value::Null), module(3, Value::Null), module(5, Value::Null)],
+        vec![connection(1, 2), connection(2, 3), connection(1, 5)],
+    );
+    assert_eq!(PatchDiff::new(&old, &old), PatchDiff::default());
+
+    // 2 gets another state, 3 is removed, 4 is added, and 5 is rewired to feed 4
+    let mut muted = module(5, Value::Null);
+    muted.muted = true;
+    let new = root(
+        vec![module(1, Value::Null), module(2, json!({ "Gain": 0.5 })), module(4, Value::Null), muted],
+        vec![connection(1, 2), connection(1, 4), connection(5, 4)],
+    );
+    let ids = |ids: &[usize]| ids.iter().map(|&id| flow::NodeId(id)).collect::<BTreeSet<_>>();
+    let diff = PatchDiff::new(&old, &new);
+    assert_eq!(diff.removed, ids(&[2, 3]));
+    assert_eq!(diff.added, ids(&[2, 4]));
+    // the connection to 3 goes with it, and the one to 2 is made again to its new node
+    assert_eq!(diff.disconnect, vec![connection(1, 5)]);
+    assert_eq!(diff.connect, vec![connection(1, 2), connection(1, 4), connection(5, 4)]);
+
+    // and back again, where 4 is removed with its connections
+    let diff = PatchDiff::new(&new, &old);
+    assert_eq!(diff.removed, ids(&[2, 4]));
+    assert_eq!(diff.added, ids(&[2, 3]));
+    assert!(diff.disconnect.is_empty());
+    assert_eq!(diff.connect, vec![connection(1, 2), connection(2, 3), connection(1, 5)]);
+}
@@ -0,0 +1,57 @@
+#![feature(specialization)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![deny(bare_trait_objects)]
+
+#[cfg(feature = "alsa-io")]
+extern crate alsa;
+#[cfg(feature = "clap")]
+extern crate clap_sys;
+extern crate futures;
+#[macro_use]
+extern crate gfx;
+extern crate cassowary;
+extern crate flow_synth_core;
+extern crate gfx_device_gl;
+extern crate gfx_glyph;
+extern crate gfx_window_glutin;
+#[cfg(feature = "hardware")]
+extern crate gilrs;
+extern crate glutin;
+#[cfg(feature = "dsp")]
+extern crate hound;
+extern crate jack;
+#[cfg(any(feature = "clap", feature = "ndi"))]
+extern crate libloading;
+#[cfg(feature = "lv2")]
+extern crate livi;
+extern crate ndarray;
+extern crate nfd;
+#[cfg(feature = "livecode")]
+extern crate notify;
+extern crate num;
+#[cfg(feature = "gpu")]
+extern crate pollster;
+extern crate ron;
+#[cfg(feature = "gpio")]
+extern crate rppal;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "hardware")]
+extern crate serialport;
+#[cfg(feature = "onnx")]
+extern crate tract_onnx;
+#[cfg(feature = "gpu")]
+extern crate wgpu;
+
+pub mod gui;
+pub mod install;
+pub mod module;
+pub mod plugin;
+pub mod registry;
+pub mod rpc;
+
+pub use flow_synth_core::future_ext;
@@ -0,0 +1,382 @@
+//! Audio straight through an ALSA device, for low latency boards like Bela.
+//!
+//! Like `BlockAudioIO`, `AlsaAudioIO` runs the blocks feeding its `Input` with a `BlockScheduler`,
+//! but it drives the hardware itself from a realtime thread instead of going through JACK, so the
+//! period size is whatever the device can do. Every period is captured, run through the graph and
+//! played back without allocating: the frames come from the graph's pool and the sample buffers
+//! are made when the device is opened.
+//!
+//! Pinning the audio thread to a core of its own with `Graph::set_scheduler_config` helps keep
+//! small periods from running late. If the device has no capture side, the graph gets silence.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use alsa::pcm::{Access, Format, HwParams, PCM};
+use alsa::{Direction, ValueOr};
+
+use crate::future_ext::Breaker;
+use crate::module::audio_io::Frame;
+use crate::module::pool::FramePool;
+use crate::module::scheduler::BlockScheduler;
+use crate::module::{flow, workers, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Priority of the audio thread, below the kernel's interrupt threads.
+const REALTIME_PRIORITY: i32 = 80;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlsaConfig {
+    pub device: String,
+    pub rate: u32,
+    /// Frames per period, asked of the device, which may pick the nearest it supports.
+    pub period: usize,
+    /// Periods in the device's buffer. Two gives the least latency.
+    pub periods: u32,
+    pub channels: usize,
+}
+
+impl AlsaConfig {
+    /// Parse a config of the form `device [rate [period [periods [channels]]]]`.
+    pub fn parse(s: &str) -> Option<AlsaConfig> {
+        let mut words = s.split_whitespace();
+        let device = words.next()?;
+        let rate = words.next().map(|w| w.parse().ok()).unwrap_or(Some(48000))?;
+        let period = words.next().map(|w| w.parse().ok()).unwrap_or(Some(64))?;
+        let periods = words.next().map(|w| w.parse().ok()).unwrap_or(Some(2))?;
+        let channels = words.next().map(|w| w.parse().ok()).unwrap_or(Some(2))?;
+        if rate == 0 || period == 0 || periods < 2 || channels == 0 {
+            return None;
+        }
+        Some(AlsaConfig {
+            device: device.into(),
+            rate,
+            period,
+            periods,
+            channels,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Device {
+    /// The config line, kept for saving and the GUI.
+    config: Option<String>,
+    session: Option<Breaker>,
+}
+
+pub struct AlsaAudioIO {
+    ifc: Arc<flow::Interface>,
+    graph: Arc<flow::Graph>,
+    cmd_rx: Option<UnboundedReceiver<String>>,
+    cmd_tx: Option<UnboundedSender<String>>,
+    device: Arc<Mutex<Device>>,
+}
+
+impl Drop for AlsaAudioIO {
+    fn drop(&mut self) {
+        self.device.lock().unwrap().session.take().map(|session| session.brake());
+    }
+}
+
+impl Module for AlsaAudioIO {
+    fn new(ifc: Arc<flow::Interface>) -> AlsaAudioIO {
+        // the ports only describe the topology, frames never travel over them
+        ifc.get_or_create_port::<Frame, ()>("Input".into());
+        ifc.get_or_create_port::<(), Frame>("Output".into());
+        let graph = ifc.graph();
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        AlsaAudioIO {
+            ifc,
+            graph,
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            device: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "AlsaAudioIO"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let device = self.device.clone();
+        let graph = self.graph.clone();
+        let host = self.ifc.id();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(line) = cmd_rx.next().await {
+                if let Some(config) = AlsaConfig::parse(&line) {
+                    // the previous device is closed by its thread once the session is braked
+                    let session = Breaker::new();
+                    let mut device = device.lock().unwrap();
+                    device.session.take().map(|old| old.brake());
+                    device.session = Some(session.clone());
+                    device.config = Some(line);
+                    let graph = graph.clone();
+                    thread::Builder::new()
+                        .name("flow-synth-alsa".into())
+                        .spawn(move || run(config, graph, host, session))
+                        .unwrap();
+                }
+            }
+        })
+        .unwrap();
+    }
+    fn stop(&mut self) {
+        self.device.lock().unwrap().session.take().map(|session| session.brake());
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        match self.device.lock().unwrap().config {
+            Some(ref config) => json!({ "config": config }),
+            None => serde_json::Value::Null,
+        }
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        if let (Some(config), Some(cmd_tx)) = (state["config"].as_str(), self.cmd_tx.as_ref()) {
+            // opened once the module starts, and shown in the GUI meanwhile
+            self.device.lock().unwrap().config = Some(config.into());
+            cmd_tx.unbounded_send(config.into()).unwrap();
+        }
+    }
+}
+
+/// Open one side of the device, returning it with the period size it settled on.
+fn open(config: &AlsaConfig, direction: Direction) -> Result<(PCM, usize), String> {
+    let pcm = PCM::new(&config.device, direction, false).map_err(|e| e.to_string())?;
+    let period = {
+        let params = HwParams::any(&pcm).map_err(|e| e.to_string())?;
+        params
+            .set_channels(config.channels as u32)
+            .and_then(|_| params.set_rate(config.rate, ValueOr::Nearest))
+            .and_then(|_| params.set_format(Format::s16()))
+            .and_then(|_| params.set_access(Access::RWInterleaved))
+            .and_then(|_| params.set_period_size_near(config.period as _, ValueOr::Nearest))
+            .and_then(|_| params.set_periods(config.periods, ValueOr::Nearest))
+            .and_then(|_| pcm.hw_params(&params))
+            .map_err(|e| e.to_string())?;
+        params.get_period_size().map_err(|e| e.to_string())? as usize
+    };
+    Ok((pcm, period))
+}
+
+fn run(config: AlsaConfig, graph: Arc<flow::Graph>, host: flow::NodeId, session: Breaker) {
+    if let Err(e) = workers::set_realtime(REALTIME_PRIORITY) {
+        println!("alsa realtime err: {}", e);
+    }
+    let (playback, period) = match open(&config, Direction::Playback) {
+        Ok(playback) => playback,
+        Err(e) => {
+            println!("alsa open {} err: {}", config.device, e);
+            return;
+        }
+    };
+    let capture = match open(&config, Direction::Capture) {
+        Ok((capture, capture_period)) if capture_period == period => Some(capture),
+        Ok(_) => {
+            println!("alsa capture err: period differs from playback");
+            None
+        }
+        Err(e) => {
+            println!("alsa capture err: {}", e);
+            None
+        }
+    };
+    let (playback_io, capture_io) = match (playback.io_i16(), capture.as_ref().map(|pcm| pcm.io_i16())) {
+        (Ok(playback_io), Some(Ok(capture_io))) => (playback_io, Some(capture_io)),
+        (Ok(playback_io), _) => (playback_io, None),
+        (Err(e), _) => {
+            println!("alsa io err: {}", e);
+            return;
+        }
+    };
+
+    let mut scheduler = BlockScheduler::new(graph.clone(), host);
+    let pool = graph.pool();
+    let channels = config.channels;
+    let mut input = vec![0i16; period * channels];
+    let mut output = vec![0i16; period * channels];
+    // fill the buffer with silence first, so the first periods don't underrun
+    for _ in 0..config.periods {
+        if let Err(e) = playback_io.writei(&output) {
+            println!("alsa write err: {}", e);
+            return;
+        }
+    }
+    let mut time = 0;
+    while !session.test() {
+        if let Some(ref capture_io) = capture_io {
+            if let Err(e) = capture_io.readi(&mut input) {
+                // an overrun, so start again from silence
+                graph.xrun();
+                for x in &mut input {
+                    *x = 0;
+                }
+                let _ = capture.as_ref().unwrap().try_recover(e, true);
+            }
+        }
+        process(&mut scheduler, &pool, config.rate as f32, time, channels, &input, &mut output);
+        time += period as u64;
+        if let Err(e) = playback_io.writei(&output) {
+            graph.xrun();
+            if let Err(e) = playback.try_recover(e, true) {
+                println!("alsa write err: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Run one period of interleaved samples through the graph.
+fn process(
+    scheduler: &mut BlockScheduler,
+    pool: &FramePool,
+    rate: f32,
+    time: u64,
+    channels: usize,
+    input: &[i16],
+    output: &mut [i16],
+) {
+    let mut capture = pool.zeros(rate, Some(time), (input.len() / channels, channels));
+    for (x, &sample) in capture.data.iter_mut().zip(input) {
+        *x = sample as f32 / 32768.0;
+    }
+    let frame = scheduler.run(&capture);
+    pool.recycle(capture);
+    for (row, samples) in output.chunks_mut(channels).zip(frame.data.outer_iter()) {
+        for (sample, x) in row.iter_mut().zip(samples.iter().chain(::std::iter::repeat(&0.0))) {
+            *sample = (x.max(-1.0).min(1.0) * 32767.0) as i16;
+        }
+    }
+    pool.recycle(frame);
+}
+
+#[test]
+fn test_alsa_io() {
+    use crate::module::mix::Gain;
+    use crate::module::process::Processor;
+
+    assert_eq!(
+        AlsaConfig::parse("hw:0,0 44100 32"),
+        Some(AlsaConfig {
+            device: "hw:0,0".into(),
+            rate: 44100,
+            period: 32,
+            periods: 2,
+            channels: 2,
+        })
+    );
+    assert_eq!(AlsaConfig::parse("hw:0,0 44100 32 1"), None);
+
+    // host -> gain -> host
+    let graph = flow::Graph::new();
+    let host = graph.add_node();
+    let host_in = host.get_or_create_port::<Frame, ()>("Input".into());
+    let host_out = host.get_or_create_port::<(), Frame>("Output".into());
+    let gain_ifc = graph.add_node();
+    let gain = Processor::<Gain>::new(gain_ifc.clone());
+    gain.process().lock().unwrap().set_param(0, 0.5);
+    host_out.connect(&gain_ifc.find_port("Input").unwrap()).unwrap();
+    gain_ifc
+        .find_port::<(), Frame>("Output")
+        .unwrap()
+        .connect(&host_in)
+        .unwrap();
+    for node in graph.nodes() {
+        for port in node.ports() {
+            port.set_meta(flow::PortMeta {
+                ramp: None,
+                ..port.meta()
+            });
+        }
+    }
+
+    let mut scheduler = BlockScheduler::new(graph.clone(), host.id());
+    let pool = graph.pool();
+    let input = vec![16384i16; 8];
+    let mut output = vec![0i16; 8];
+    process(&mut scheduler, &pool, 48000.0, 0, 2, &input, &mut output);
+    assert_eq!(output, vec![8191i16; 8]);
+
+    // once warmed up, periods don't allocate
+    process(&mut scheduler, &pool, 48000.0, 4, 2, &input, &mut output);
+    let allocated = pool.stats().allocated;
+    for time in 2..10 {
+        process(&mut scheduler, &pool, 48000.0, time * 4, 2, &input, &mut output);
+    }
+    assert_eq!(pool.stats().allocated, allocated);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct AlsaGui {
+    bounds: Box3,
+    config_box: TextBox,
+    open_button: Button,
+    cmd_tx: UnboundedSender<String>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl AlsaGui {
+    fn label(config: &AlsaConfig) -> String {
+        format!("{} @ {} Hz, {} frames", config.device, config.rate, config.period)
+    }
+}
+impl ModuleGui for AlsaAudioIO {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = self.device.lock().unwrap().config.clone();
+        let label = config
+            .as_ref()
+            .and_then(|config| AlsaConfig::parse(config))
+            .map(|config| AlsaGui::label(&config))
+            .unwrap_or("Open".into());
+        Box::new(AlsaGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), config.unwrap_or("hw:0,0 48000 64 2 2".into()), row(0.0)),
+            open_button: Button::new(ctx.clone(), label, row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for AlsaGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.open_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.open_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let line = self.config_box.content().to_string();
+                match AlsaConfig::parse(&line) {
+                    Some(config) => {
+                        self.open_button.set_label(AlsaGui::label(&config));
+                        self.cmd_tx.unbounded_send(line).unwrap();
+                    }
+                    None => self
+                        .open_button
+                        .set_label("Invalid: device [rate [period [periods [channels]]]]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! First order ambisonics, for installations with multichannel speaker arrays.
+//!
+//! The encoder pans a mono source into a four channel B-format signal from its azimuth and
+//! elevation, and the decoder renders B-format to any layout of speakers around the listener.
+//! Sources are encoded separately, summed with a mixer, and decoded once. Channels are in AmbiX
+//! order and normalization (ACN: W, Y, Z, X with SN3D), which most plugins and recordings use.
+//! Angles are in degrees, with azimuth counter-clockwise from the front, so 90 is hard left, and
+//! elevation up from the horizon.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::process::Process;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Array2;
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+/// Channels of a first order B-format signal.
+pub const B_FORMAT_CHANNELS: usize = 4;
+
+/// The ACN/SN3D gains of a source in the direction `azimuth`, `elevation` in degrees.
+pub fn encoding(azimuth: f32, elevation: f32) -> [f32; B_FORMAT_CHANNELS] {
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    [
+        1.0,
+        azimuth.sin() * elevation.cos(),
+        elevation.sin(),
+        azimuth.cos() * elevation.cos(),
+    ]
+}
+
+/// Pans its input, mixed down to mono, by the `Azimuth` and `Elevation` controls. Direction
+/// changes are ramped over a block.
+pub struct AmbisonicEncoder {
+    azimuth: f32,
+    elevation: f32,
+    /// The gains reached by the end of the last block, or `None` before the first one.
+    gains: Option<[f32; B_FORMAT_CHANNELS]>,
+}
+
+impl Process for AmbisonicEncoder {
+    const NAME: &'static str = "Ambisonic Encoder";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["B-Format"];
+    const PARAMS: &'static [(&'static str, f32)] = &[("Azimuth", 0.0), ("Elevation", 0.0)];
+    const RANGES: &'static [(f32, f32)] = &[(-180.0, 180.0), (-90.0, 90.0)];
+    const OUTPUT_CHANNELS: Option<usize> = Some(B_FORMAT_CHANNELS);
+    fn new() -> AmbisonicEncoder {
+        AmbisonicEncoder {
+            azimuth: 0.0,
+            elevation: 0.0,
+            gains: None,
+        }
+    }
+    fn set_param(&mut self, idx: usize, value: f32) {
+        match idx {
+            0 => self.azimuth = value,
+            _ => self.elevation = value.max(-90.0).min(90.0),
+        }
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let targets = encoding(self.azimuth, self.elevation);
+        let from = self.gains.unwrap_or(targets);
+        let len = inputs[0].data.dim().0;
+        let rows = inputs[0].data.outer_iter().zip(outputs[0].data.outer_iter_mut());
+        for (i, (input, mut output)) in rows.enumerate() {
+            if input.len() == 0 {
+                break;
+            }
+            let sample = input.iter().sum::<f32>() / input.len() as f32;
+            let t = (i + 1) as f32 / len as f32;
+            for ((out, &from), &to) in output.iter_mut().zip(&from).zip(&targets) {
+                *out = sample * (from + (to - from) * t);
+            }
+        }
+        self.gains = Some(targets);
+    }
+}
+
+/// Speaker directions in degrees, in output channel order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeakerLayout {
+    /// Azimuth and elevation of each speaker.
+    pub speakers: Vec<(f32, f32)>,
+}
+
+impl SpeakerLayout {
+    /// Parse a layout of the form `azimuth [elevation]; ...`, one entry per speaker.
+    pub fn parse(s: &str) -> Option<SpeakerLayout> {
+        let speakers = s
+            .split(';')
+            .filter(|speaker| !speaker.trim().is_empty())
+            .map(|speaker| {
+                let values: Vec<f32> = speaker
+                    .split_whitespace()
+                    .map(|w| w.parse().ok())
+                    .collect::<Option<_>>()?;
+                match values[..] {
+                    [azimuth] => Some((azimuth, 0.0)),
+                    [azimuth, elevation] if elevation >= -90.0 && elevation <= 90.0 => {
+                        Some((azimuth, elevation))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if speakers.is_empty() {
+            return None;
+        }
+        Some(SpeakerLayout { speakers })
+    }
+    pub fn describe(&self) -> String {
+        let speakers: Vec<String> = self
+            .speakers
+            .iter()
+            .map(|&(azimuth, elevation)| {
+                if elevation == 0.0 {
+                    format!("{}", azimuth)
+                } else {
+                    format!("{} {}", azimuth, elevation)
+                }
+            })
+            .collect();
+        speakers.join("; ")
+    }
+    /// The gain of each B-format channel in each speaker, by speaker. This is the basic sampling
+    /// decoder, which works best with speakers spread evenly. Layouts on the horizon are decoded
+    /// in 2D, ignoring height.
+    fn decoding(&self) -> Vec<[f32; B_FORMAT_CHANNELS]> {
+        let flat = self.speakers.iter().all(|&(_, elevation)| elevation == 0.0);
+        let weight = if flat { 2.0 } else { 3.0 };
+        let scale = 1.0 / self.speakers.len() as f32;
+        let directional = scale * weight;
+        self.speakers
+            .iter()
+            .map(|&(azimuth, elevation)| {
+                let gains = encoding(azimuth, elevation);
+                let height = if flat { 0.0 } else { gains[2] };
+                [
+                    scale,
+                    directional * gains[1],
+                    directional * height,
+                    directional * gains[3],
+                ]
+            })
+            .collect()
+    }
+}
+
+impl Default for SpeakerLayout {
+    /// A square of four speakers: front left, front right, rear left, rear right.
+    fn default() -> SpeakerLayout {
+        SpeakerLayout {
+            speakers: vec![(45.0, 0.0), (-45.0, 0.0), (135.0, 0.0), (-135.0, 0.0)],
+        }
+    }
+}
+
+struct Decoder {
+    layout: SpeakerLayout,
+    gains: Vec<[f32; B_FORMAT_CHANNELS]>,
+}
+
+impl Decoder {
+    fn new(layout: SpeakerLayout) -> Decoder {
+        Decoder {
+            gains: layout.decoding(),
+            layout,
+        }
+    }
+    /// Render a B-format frame to one channel per speaker. Missing B-format channels are silent.
+    fn process(&self, frame: &Frame) -> Frame {
+        let mut data = Array2::zeros((frame.data.dim().0, self.gains.len()));
+        for (input, mut output) in frame.data.outer_iter().zip(data.outer_iter_mut()) {
+            for (out, gains) in output.iter_mut().zip(&self.gains) {
+                *out = input.iter().zip(gains).map(|(x, gain)| x * gain).sum();
+            }
+        }
+        Frame {
+            rate: frame.rate,
+            time: frame.time,
+            data,
+            meta: frame.meta.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Layout(SpeakerLayout),
+}
+
+/// Decodes B-format to the speakers of its layout, declaring one output channel per speaker.
+pub struct AmbisonicDecoder {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    decoder: Arc<Mutex<Decoder>>,
+}
+
+fn declare_speakers(port: &flow::Port<(), Frame>, layout: &SpeakerLayout) {
+    port.set_meta(flow::PortMeta {
+        channels: Some(layout.speakers.len()),
+        ..port.meta()
+    });
+}
+
+impl Module for AmbisonicDecoder {
+    fn new(ifc: Arc<flow::Interface>) -> AmbisonicDecoder {
+        let in_port: Arc<flow::Port<Frame, ()>> = ifc.get_or_create_port("B-Format".into());
+        in_port.set_meta(flow::PortMeta {
+            channels: Some(B_FORMAT_CHANNELS),
+            ..in_port.meta()
+        });
+        let out_port = ifc.get_or_create_port("Output".into());
+        let layout = SpeakerLayout::default();
+        declare_speakers(&out_port, &layout);
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        AmbisonicDecoder {
+            ifc,
+            in_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            decoder: Arc::new(Mutex::new(Decoder::new(layout))),
+        }
+    }
+    fn name() -> &'static str {
+        "Ambisonic Decoder"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let decoder = self.decoder.clone();
+        let out_port = self.out_port.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Layout(layout) => {
+                        declare_speakers(&out_port, &layout);
+                        *decoder.lock().unwrap() = Decoder::new(layout);
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let decoder = self.decoder.clone();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame { decoder.lock().unwrap().process(&frame) },
+            self.ifc.clone(),
+            self.in_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(&self.decoder.lock().unwrap().layout).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(layout) => {
+                declare_speakers(&self.out_port, &layout);
+                *self.decoder.lock().unwrap() = Decoder::new(layout);
+            }
+            Err(e) => println!("ambisonic decoder state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_ambisonics() {
+    let quad = SpeakerLayout::parse("45; -45; 135; -135");
+    assert_eq!(quad, Some(SpeakerLayout::default()));
+    assert_eq!(SpeakerLayout::parse("0 100"), None);
+    let dome = SpeakerLayout::parse("0; 120; -120; 0 90").unwrap();
+    assert_eq!(SpeakerLayout::parse(&dome.describe()), Some(dome));
+
+    let frame = Frame {
+        rate: 1000.0,
+        time: None,
+        data: Array2::from_elem((8, 1), 1.0),
+        meta: None,
+    };
+    let mut encoder = AmbisonicEncoder::new();
+    encoder.set_param(0, 45.0);
+    let mut b_format = [Frame {
+        data: Array2::zeros((8, B_FORMAT_CHANNELS)),
+        ..frame.clone()
+    }];
+    encoder.process(&[frame], &mut b_format);
+    let half = 0.5f32.sqrt();
+    for (out, expected) in b_format[0].data.row(7).iter().zip(&[1.0, half, 0.0, half]) {
+        assert!((out - expected).abs() < 1e-6);
+    }
+
+    // front left gets most of a source between the front left and front right
+    let speakers = Decoder::new(SpeakerLayout::default()).process(&b_format[0]);
+    let expected = [0.75, 0.25, 0.25, -0.25];
+    for (out, expected) in speakers.data.row(7).iter().zip(&expected) {
+        assert!((out - expected).abs() < 1e-6);
+    }
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct AmbisonicDecoderGui {
+    bounds: Box3,
+    layout_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for AmbisonicDecoder {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let layout = self.decoder.lock().unwrap().layout.describe();
+        Box::new(AmbisonicDecoderGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            layout_box: TextBox::new(ctx.clone(), layout, row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for AmbisonicDecoderGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.layout_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.layout_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match SpeakerLayout::parse(self.layout_box.content()) {
+                    Some(layout) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::Layout(layout)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: az [el]; ...".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Art-Net output for driving DMX stage lighting.
+//!
+//! Each `Ch N` input takes control values in `0.0..=1.0` which are mapped onto consecutive DMX
+//! channels, starting at a configurable address. The whole universe is retransmitted at a fixed
+//! rate so fixtures don't time out.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{flow, util, Module};
+
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const ARTNET_PORT: u16 = 6454;
+const N_INPUTS: usize = 8;
+const UNIVERSE_SIZE: usize = 512;
+/// Roughly the maximum refresh rate of DMX512 itself.
+const SEND_INTERVAL: Duration = Duration::from_millis(23);
+
+/// Where to send the universe, and which DMX channel the first input drives.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArtNetConfig {
+    pub target: String,
+    pub universe: u16,
+    pub start_channel: usize,
+}
+
+impl ArtNetConfig {
+    /// Parse a config of the form `host [universe [start_channel]]`, with a 1-based start channel.
+    pub fn parse(s: &str) -> Option<ArtNetConfig> {
+        let mut words = s.split_whitespace();
+        let host = words.next()?;
+        let universe = words.next().map(|w| w.parse().ok()).unwrap_or(Some(0))?;
+        let start_channel: usize = words.next().map(|w| w.parse().ok()).unwrap_or(Some(1))?;
+        if universe > 0x7fff || start_channel < 1 || start_channel + N_INPUTS - 1 > UNIVERSE_SIZE {
+            return None;
+        }
+        let target = if host.contains(':') {
+            host.into()
+        } else {
+            format!("{}:{}", host, ARTNET_PORT)
+        };
+        Some(ArtNetConfig {
+            target,
+            universe,
+            start_channel: start_channel - 1,
+        })
+    }
+}
+
+/// Encode an ArtDmx packet.
+pub fn art_dmx(sequence: u8, universe: u16, data: &[u8]) -> Vec<u8> {
+    // DMX payloads must have an even length
+    let len = (data.len() + 1) & !1;
+    let mut packet = Vec::with_capacity(18 + len);
+    packet.extend(b"Art-Net\0");
+    packet.extend(&[0x00, 0x50]); // OpDmx, little endian
+    packet.extend(&[0, 14]); // protocol version
+    packet.push(sequence);
+    packet.push(0); // physical port
+    packet.push(universe as u8);
+    packet.push((universe >> 8) as u8 & 0x7f);
+    packet.push((len >> 8) as u8);
+    packet.push(len as u8);
+    packet.extend(data);
+    packet.resize(18 + len, 0);
+    packet
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Configure(ArtNetConfig),
+}
+
+pub struct ArtNetOut {
+    ifc: Arc<flow::Interface>,
+    inputs: Vec<Arc<flow::Port<f32, ()>>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    config: Arc<Mutex<Option<ArtNetConfig>>>,
+    values: Arc<Mutex<[u8; N_INPUTS]>>,
+}
+
+impl Module for ArtNetOut {
+    fn new(ifc: Arc<flow::Interface>) -> ArtNetOut {
+        let channels: Vec<_> = (1..=N_INPUTS).map(|i| i.to_string()).collect();
+        let inputs = ifc.get_or_create_group("Ch", &channels).ports().to_vec();
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        ArtNetOut {
+            ifc,
+            inputs,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            config: Arc::default(),
+            values: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "ArtNetOut"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let config_handle = self.config.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Configure(config) => *config_handle.lock().unwrap() = Some(config),
+                }
+            }
+        })
+        .unwrap();
+
+        for (idx, port) in self.inputs.iter().enumerate() {
+            let values = self.values.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| {
+                    values.lock().unwrap()[idx] = (value.max(0.0).min(1.0) * 255.0).round() as u8;
+                },
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let config = self.config.clone();
+        let values = self.values.clone();
+        let breaker = self.breaker.clone();
+        thread::spawn(move || transmit(config, values, breaker));
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+fn transmit(config: Arc<Mutex<Option<ArtNetConfig>>>, values: Arc<Mutex<[u8; N_INPUTS]>>, breaker: Breaker) {
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("artnet bind err: {:?}", e);
+            return;
+        }
+    };
+    // targets are commonly broadcast addresses
+    let _ = socket.set_broadcast(true);
+    let mut universe = [0u8; UNIVERSE_SIZE];
+    // sequence 0 disables reordering on the receiver, so cycle through 1..=255
+    let mut sequence = 1u8;
+    while !breaker.test() {
+        if let Some(config) = config.lock().unwrap().clone() {
+            let start = config.start_channel;
+            universe[start..start + N_INPUTS].copy_from_slice(&*values.lock().unwrap());
+            let packet = art_dmx(sequence, config.universe, &universe);
+            if let Err(e) = socket.send_to(&packet, &config.target[..]) {
+                println!("artnet send err: {:?}", e);
+            }
+            sequence = sequence.checked_add(1).unwrap_or(1);
+        }
+        thread::sleep(SEND_INTERVAL);
+    }
+}
+
+#[test]
+fn test_art_dmx() {
+    let packet = art_dmx(3, 0x0102, &[255, 128, 0]);
+    assert_eq!(&packet[..8], b"Art-Net\0");
+    assert_eq!(&packet[8..18], &[0x00, 0x50, 0, 14, 3, 0, 0x02, 0x01, 0, 4]);
+    assert_eq!(&packet[18..], &[255, 128, 0, 0]);
+
+    let config = ArtNetConfig::parse("10.0.0.255 1 505").unwrap();
+    assert_eq!(config.target, "10.0.0.255:6454");
+    assert_eq!(config.universe, 1);
+    assert_eq!(config.start_channel, 504);
+    assert!(ArtNetConfig::parse("10.0.0.255 1 506").is_none());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct ArtNetGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for ArtNetOut {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(ArtNetGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), "255.255.255.255 0 1".into(), row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Send".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for ArtNetGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match ArtNetConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.apply_button.set_label(format!("Sending to {}", config.target));
+                        self.cmd_tx.unbounded_send(UserCommand::Configure(config)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: host [universe [channel]]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -1,22 +1,21 @@
 use futures::channel::mpsc;
-use futures::executor;
-use futures::future;
+use futures::future::{self, BoxFuture};
 use futures::prelude::*;
-use futures::task;
+use futures::task::{Spawn, SpawnExt};
 
-use future_ext::{Breaker, FutureWrapExt};
-use module::{flow, Module};
+use crate::future_ext::Breaker;
+use crate::module::{flow, Module};
+
+pub use flow_synth_core::frame::{Frame, FrameMeta};
 
 use jack::*;
 
-use ndarray::{Array, Array2, Axis};
+use ndarray::{Array, Axis};
 
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 
-pub struct Frame {
-    pub rate: f32,
-    pub data: Array2<f32>,
-}
 pub struct AudioIO {
     ifc: Arc<flow::Interface>,
     in_port: Option<Arc<flow::Port<Frame, ()>>>,
@@ -37,8 +36,8 @@ impl Module for AudioIO {
     fn name() -> &'static str {
         "AudioIO"
     }
-    fn start<Ex: executor::Executor>(&mut self, mut exec: Ex) {
-        exec.spawn(Box::new(AudioIOFuture::new(self))).unwrap();
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        exec.spawn(AudioIOFuture::new(self)).unwrap();
     }
     fn stop(&mut self) {
         self.breaker.brake();
@@ -49,8 +48,9 @@ impl Module for AudioIO {
 }
 
 struct AudioIOFuture {
-    client: Option<AsyncClient<(), Processor>>,
-    future: Box<dyn Future<Item = (), Error = Never> + Send>,
+    client: Option<AsyncClient<Xruns, Processor>>,
+    graph: Arc<flow::Graph>,
+    future: BoxFuture<'static, ()>,
     output_rx: Option<mpsc::Receiver<Frame>>,
     input_tx: Option<mpsc::Sender<Frame>>,
     breaker: Breaker,
@@ -62,68 +62,62 @@ impl AudioIOFuture {
         let (output_tx, output_rx) = mpsc::channel(1);
         let in_port = base.in_port.take().unwrap();
         let out_port = base.out_port.take().unwrap();
-        let in_future = future::loop_fn(
-            (input_rx, out_port, base.breaker.clone()),
-            |(recv, port, breaker)| {
-                port.read1()
-                    .wrap(recv)
-                    .map_err(|(recv, (port, err))| (recv, port, format!("read1 {:?}", err)))
-                    .and_then(|(recv, (port, _req))| {
-                        recv.next()
-                            .map(|(frame, recv)| (frame.unwrap(), recv, port))
-                            .map_err(|(_err, _recv)| panic!()) // error: Never, panic impossible!
-                    })
-                    .and_then(|(frame, recv, port)| {
-                        port.write1(frame)
-                            .wrap(recv)
-                            .map_err(|(recv, (port, err))| (recv, port, format!("write1 {:?}", err)))
-                    })
-                    .recover(|(recv, port, err)| {
-                        println!("In err: {}", err);
-                        (recv, port)
-                    })
-                    .map(|(recv, port)| {
-                        if breaker.test() {
-                            future::Loop::Break(())
-                        } else {
-                            future::Loop::Continue((recv, port, breaker))
-                        }
-                    })
-            },
-        );
-        let out_future = future::loop_fn(
-            (output_tx, in_port, base.breaker.clone()),
-            |(tx, port, breaker)| {
-                port.write1(())
-                    .wrap(tx)
-                    .map_err(|(tx, (port, err))| (tx, port, format!("write1 {:?}", err)))
-                    .and_then(|(tx, port)| {
-                        port.read1()
-                            .wrap(tx)
-                            .map_err(|(tx, (port, err))| (tx, port, format!("read1 {:?}", err)))
-                    })
-                    .and_then(|(tx, (port, frame))| {
-                        tx.send(frame).map(|tx| (tx, port)).map_err(|err| panic!())
-                        // error: Never, panic impossible!
-                    })
-                    .recover(|(tx, port, err)| {
-                        println!("Out err: {}", err);
-                        (tx, port)
-                    })
-                    .map(|(tx, port)| {
-                        if breaker.test() {
-                            future::Loop::Break(())
-                        } else {
-                            future::Loop::Continue((tx, port, breaker))
-                        }
-                    })
-            },
-        );
+        let in_breaker = base.breaker.clone();
+        let in_future = async move {
+            let (mut recv, port, breaker) = (input_rx, out_port, in_breaker);
+            loop {
+                let result = async {
+                    let (port, _req) = port
+                        .clone()
+                        .read1()
+                        .await
+                        .map_err(|(_port, err)| format!("read1 {:?}", err))?;
+                    let frame = recv.next().await.unwrap();
+                    port.write1(frame)
+                        .await
+                        .map_err(|(_port, err)| format!("write1 {:?}", err))?;
+                    Ok::<(), String>(())
+                };
+                if let Err(err) = result.await {
+                    println!("In err: {}", err);
+                }
+                if breaker.test() {
+                    break;
+                }
+            }
+        };
+        let out_breaker = base.breaker.clone();
+        let out_future = async move {
+            let (mut tx, port, breaker) = (output_tx, in_port, out_breaker);
+            loop {
+                let result = async {
+                    let port = port
+                        .clone()
+                        .write1(())
+                        .await
+                        .map_err(|(_port, err)| format!("write1 {:?}", err))?;
+                    let (_port, frame) = port
+                        .read1()
+                        .await
+                        .map_err(|(_port, err)| format!("read1 {:?}", err))?;
+                    // the receiver lives as long as the jack client, so this can't fail
+                    tx.send(frame).await.unwrap();
+                    Ok::<(), String>(())
+                };
+                if let Err(err) = result.await {
+                    println!("Out err: {}", err);
+                }
+                if breaker.test() {
+                    break;
+                }
+            }
+        };
         AudioIOFuture {
             client: None,
+            graph: base.ifc.graph(),
             input_tx: Some(input_tx),
             output_rx: Some(output_rx),
-            future: Box::new(in_future.join(out_future).map(|((), ())| ())),
+            future: future::join(in_future, out_future).map(|((), ())| ()).boxed(),
             breaker: base.breaker.clone(),
         }
     }
@@ -156,17 +150,27 @@ impl AudioIOFuture {
                 input_tx: self.input_tx.take().unwrap(),
                 output_rx: self.output_rx.take().unwrap(),
                 breaker: self.breaker.clone(),
+                time: 0,
             };
-            self.client = Some(AsyncClient::new(client, (), processor).unwrap());
+            self.client = Some(AsyncClient::new(client, Xruns(self.graph.clone()), processor).unwrap());
         }
     }
 }
 impl Future for AudioIOFuture {
-    type Item = ();
-    type Error = Never;
-    fn poll(&mut self, cx: &mut task::Context) -> Poll<Self::Item, Self::Error> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
         self.initialize();
-        self.future.poll(cx)
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Counts the buffers JACK reports as missed towards the graph's xruns.
+pub struct Xruns(pub Arc<flow::Graph>);
+
+impl NotificationHandler for Xruns {
+    fn xrun(&mut self, _: &Client) -> Control {
+        self.0.xrun();
+        Control::Continue
     }
 }
 
@@ -176,15 +180,18 @@ struct Processor {
     input_tx: mpsc::Sender<Frame>,
     output_rx: mpsc::Receiver<Frame>,
     breaker: Breaker,
+    time: u64,
 }
 impl ProcessHandler for Processor {
     fn process(&mut self, client: &Client, ps: &ProcessScope) -> Control {
         let in_frame = Frame {
             rate: client.sample_rate() as f32,
+            time: Some(self.time),
             data: Array::from_iter(self.inputs.iter().flat_map(|input| input.as_slice(ps).to_vec()))
                 .into_shape((self.inputs.len(), client.buffer_size() as usize))
                 .unwrap()
                 .reversed_axes(),
+            meta: None,
         };
 
         // ignore errors, prefer to drop the frame
@@ -203,6 +210,7 @@ impl ProcessHandler for Processor {
                 }
             }
         }
+        self.time += ps.n_frames() as u64;
         let _ = self.input_tx.try_send(in_frame);
 
         if self.breaker.test() {
@@ -0,0 +1,464 @@
+//! Cellular automata for rhythms and visuals.
+//!
+//! `Automaton` runs either an elementary automaton, a row of cells following one of Wolfram's
+//! numbered rules with the grid scrolling up to show its history, or a two dimensional one like
+//! Conway's Life, with a rule like `B3/S23`. The grid wraps around at its edges. Every value on
+//! `Step` moves it on a generation and plays a row: the newest for elementary rules, and for two
+//! dimensional ones the row under a cursor moving down a row a step, like a playhead. The row is
+//! sent as the column of each live cell on `Triggers`, for rhythms, and as a frame of 1.0s and 0.0s
+//! on `Row`. `Grid` answers with the whole grid as a grayscale frame, one row per line of cells,
+//! and is only rendered while connected. Frames are one per step, with the step count as time.
+//!
+//! `Randomize` fills the grid with live cells at the density it is given, 0.0 clearing it, and
+//! `Rule` changes the number of an elementary rule.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::physical::Noise;
+use crate::module::pool::FramePool;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Rule {
+    /// The next state of a cell for each state of it and its two neighbours, as the bits of the
+    /// rule number.
+    Elementary(u8),
+    /// The counts of live neighbours that bring a dead cell to life and keep a live one alive,
+    /// as bits.
+    Life { birth: u16, survive: u16 },
+}
+
+impl Rule {
+    /// Parse a rule number or a rule like `B3/S23`.
+    pub fn parse(s: &str) -> Option<Rule> {
+        if let Ok(number) = s.parse() {
+            return Some(Rule::Elementary(number));
+        }
+        let counts = |s: &str, prefix: char| -> Option<u16> {
+            let mut chars = s.chars();
+            if chars.next()?.to_ascii_uppercase() != prefix {
+                return None;
+            }
+            chars.try_fold(0, |bits, c| match c.to_digit(10) {
+                Some(count) if count <= 8 => Some(bits | 1 << count),
+                _ => None,
+            })
+        };
+        let mut parts = s.splitn(2, '/');
+        let birth = counts(parts.next()?, 'B')?;
+        let survive = counts(parts.next()?, 'S')?;
+        Some(Rule::Life { birth, survive })
+    }
+    fn describe(&self) -> String {
+        match *self {
+            Rule::Elementary(number) => number.to_string(),
+            Rule::Life { birth, survive } => {
+                let counts = |bits: u16| -> String {
+                    (0..9)
+                        .filter(|count| bits & 1 << count != 0)
+                        .map(|count| count.to_string())
+                        .collect()
+                };
+                format!("B{}/S{}", counts(birth), counts(survive))
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AutomatonConfig {
+    pub rule: Rule,
+    /// Size of the grid in cells.
+    pub width: usize,
+    pub height: usize,
+}
+
+impl AutomatonConfig {
+    /// Parse a config of the form `rule width height`.
+    pub fn parse(s: &str) -> Option<AutomatonConfig> {
+        let mut words = s.split_whitespace();
+        let rule = Rule::parse(words.next()?)?;
+        let width: usize = words.next()?.parse().ok()?;
+        let height: usize = words.next()?.parse().ok()?;
+        if width == 0 || height == 0 || words.next().is_some() {
+            return None;
+        }
+        Some(AutomatonConfig { rule, width, height })
+    }
+    fn describe(&self) -> String {
+        format!("{} {} {}", self.rule.describe(), self.width, self.height)
+    }
+}
+
+impl Default for AutomatonConfig {
+    fn default() -> AutomatonConfig {
+        AutomatonConfig {
+            rule: Rule::Elementary(30),
+            width: 16,
+            height: 16,
+        }
+    }
+}
+
+pub struct Automaton {
+    config: AutomatonConfig,
+    /// Row by row from the top.
+    cells: Vec<bool>,
+    /// The row played by two dimensional rules on the next step.
+    cursor: usize,
+    steps: u64,
+    noise: Noise,
+}
+
+impl Automaton {
+    pub fn new(config: AutomatonConfig) -> Automaton {
+        let mut automaton = Automaton {
+            config,
+            cells: Vec::new(),
+            cursor: 0,
+            steps: 0,
+            noise: Noise(0x1b87_3593),
+        };
+        automaton.seed();
+        automaton
+    }
+    pub fn config(&self) -> AutomatonConfig {
+        self.config
+    }
+    /// Change the config, starting over unless only the rule number changed.
+    pub fn set_config(&mut self, config: AutomatonConfig) {
+        let reseed = match (self.config.rule, config.rule) {
+            (Rule::Elementary(_), Rule::Elementary(_)) => {
+                (self.config.width, self.config.height) != (config.width, config.height)
+            }
+            _ => true,
+        };
+        self.config = config;
+        if reseed {
+            self.seed();
+        }
+    }
+    pub fn set_rule_number(&mut self, number: f32) {
+        if let Rule::Elementary(_) = self.config.rule {
+            self.config.rule = Rule::Elementary(number.round().max(0.0).min(255.0) as u8);
+        }
+    }
+    /// Start over, from a single live cell in the middle of the newest row for elementary rules
+    /// and from a random grid otherwise.
+    pub fn seed(&mut self) {
+        let AutomatonConfig { width, height, .. } = self.config;
+        self.cells = vec![false; width * height];
+        self.cursor = 0;
+        match self.config.rule {
+            Rule::Elementary(_) => self.cells[(height - 1) * width + width / 2] = true,
+            Rule::Life { .. } => self.randomize(0.25),
+        }
+    }
+    /// Make each cell live with a chance of `density`.
+    pub fn randomize(&mut self, density: f32) {
+        for cell in &mut self.cells {
+            *cell = (self.noise.next() + 1.0) / 2.0 < density;
+        }
+    }
+    pub fn get(&self, row: usize, column: usize) -> bool {
+        self.cells[row * self.config.width + column]
+    }
+    pub fn set(&mut self, row: usize, column: usize, alive: bool) {
+        self.cells[row * self.config.width + column] = alive;
+    }
+    /// Move on a generation, returning the row played.
+    pub fn step(&mut self) -> Vec<bool> {
+        let AutomatonConfig { rule, width, height } = self.config;
+        self.steps += 1;
+        match rule {
+            Rule::Elementary(number) => {
+                let last = (height - 1) * width;
+                let next: Vec<bool> = (0..width)
+                    .map(|column| {
+                        let cell = |offset: usize| self.cells[last + (column + offset) % width] as u8;
+                        let state = cell(width - 1) << 2 | cell(0) << 1 | cell(1);
+                        number & 1 << state != 0
+                    })
+                    .collect();
+                // scroll up, the newest row at the bottom
+                self.cells.drain(..width);
+                self.cells.extend(next.iter().cloned());
+                next
+            }
+            Rule::Life { birth, survive } => {
+                let mut next = vec![false; width * height];
+                for row in 0..height {
+                    for column in 0..width {
+                        let mut neighbours = 0;
+                        for &(dr, dc) in &[(0, 1), (1, 0), (1, 1), (1, width - 1)] {
+                            // each pair of neighbours once, above and below or either side
+                            neighbours += self.get((row + dr) % height, (column + dc) % width) as u16;
+                            neighbours += self
+                                .get((row + height - dr) % height, (column + width * 2 - dc) % width)
+                                as u16;
+                        }
+                        let bits = if self.get(row, column) { survive } else { birth };
+                        next[row * width + column] = bits & 1 << neighbours != 0;
+                    }
+                }
+                self.cells = next;
+                let row = self.cells[self.cursor * width..][..width].to_vec();
+                self.cursor = (self.cursor + 1) % height;
+                row
+            }
+        }
+    }
+    /// `row` as a frame of one row with a column per cell.
+    pub fn row_frame(&self, row: &[bool], pool: &FramePool) -> Frame {
+        let mut out = pool.zeros(1.0, Some(self.steps), (1, row.len()));
+        for (x, &alive) in out.data.iter_mut().zip(row) {
+            *x = alive as u8 as f32;
+        }
+        out
+    }
+    pub fn render(&self, pool: &FramePool) -> Frame {
+        let AutomatonConfig { width, height, .. } = self.config;
+        let mut out = pool.zeros(1.0, Some(self.steps), (height, width));
+        for (x, &alive) in out.data.iter_mut().zip(&self.cells) {
+            *x = alive as u8 as f32;
+        }
+        out
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Configure(AutomatonConfig),
+}
+
+pub struct CellularAutomaton {
+    ifc: Arc<flow::Interface>,
+    step_port: Arc<flow::Port<f32, ()>>,
+    randomize_port: Arc<flow::Port<f32, ()>>,
+    rule_port: Arc<flow::Port<f32, ()>>,
+    triggers_port: Arc<flow::Port<(), f32>>,
+    row_port: Arc<flow::Port<(), Frame>>,
+    grid_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    automaton: Arc<Mutex<Automaton>>,
+}
+
+/// Declare the columns of the frames on `row` and `grid`.
+fn declare_width(ports: &[&Arc<flow::Port<(), Frame>>], config: &AutomatonConfig) {
+    for port in ports {
+        port.set_meta(flow::PortMeta {
+            channels: Some(config.width),
+            ..port.meta()
+        });
+    }
+}
+
+impl Module for CellularAutomaton {
+    fn new(ifc: Arc<flow::Interface>) -> CellularAutomaton {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        let config = AutomatonConfig::default();
+        let row_port = ifc.get_or_create_port("Row".into());
+        let grid_port = ifc.get_or_create_port("Grid".into());
+        declare_width(&[&row_port, &grid_port], &config);
+        CellularAutomaton {
+            step_port: ifc.get_or_create_port("Step".into()),
+            randomize_port: ifc.get_or_create_port("Randomize".into()),
+            rule_port: ifc.get_or_create_port("Rule".into()),
+            triggers_port: ifc.get_or_create_port("Triggers".into()),
+            row_port,
+            grid_port,
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            automaton: Arc::new(Mutex::new(Automaton::new(config))),
+        }
+    }
+    fn name() -> &'static str {
+        "Automaton"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let automaton = self.automaton.clone();
+        let (row_port, grid_port) = (self.row_port.clone(), self.grid_port.clone());
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Configure(config) => {
+                        declare_width(&[&row_port, &grid_port], &config);
+                        automaton.lock().unwrap().set_config(config);
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let automaton = self.automaton.clone();
+        util::start_sink(
+            self.randomize_port.clone(),
+            move |density: f32| automaton.lock().unwrap().randomize(density),
+            self.breaker.clone(),
+            &exec,
+        );
+        let automaton = self.automaton.clone();
+        util::start_sink(
+            self.rule_port.clone(),
+            move |number: f32| automaton.lock().unwrap().set_rule_number(number),
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let (mut trigger_tx, trigger_rx) = mpsc::channel(64);
+        let (mut row_tx, row_rx) = mpsc::channel(1);
+        let (mut grid_tx, grid_rx) = mpsc::channel(1);
+        let automaton = self.automaton.clone();
+        let grid_port = self.grid_port.clone();
+        let pool = self.ifc.graph().pool();
+        util::start_sink(
+            self.step_port.clone(),
+            move |_: f32| {
+                let mut automaton = automaton.lock().unwrap();
+                let row = automaton.step();
+                for (column, _) in row.iter().enumerate().filter(|&(_, &alive)| alive) {
+                    let _ = trigger_tx.try_send(column as f32);
+                }
+                let _ = row_tx.try_send(automaton.row_frame(&row, &pool));
+                if grid_port.edge().is_some() {
+                    let _ = grid_tx.try_send(automaton.render(&pool));
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(trigger_rx, self.triggers_port.clone(), &exec);
+        util::start_source(row_rx, self.row_port.clone(), &exec);
+        util::start_source(grid_rx, self.grid_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.automaton.lock().unwrap().config()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(config) => {
+                declare_width(&[&self.row_port, &self.grid_port], &config);
+                self.automaton.lock().unwrap().set_config(config);
+            }
+            Err(e) => println!("automaton state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_automaton() {
+    let row = |automaton: &Automaton, row: usize| -> String {
+        let width = automaton.config().width;
+        (0..width)
+            .map(|column| if automaton.get(row, column) { '#' } else { '.' })
+            .collect()
+    };
+    let mut elementary = Automaton::new(AutomatonConfig::parse("30 7 3").unwrap());
+    assert_eq!(row(&elementary, 2), "...#...");
+    let played = elementary.step();
+    assert_eq!(played, vec![false, false, true, true, true, false, false]);
+    assert_eq!(row(&elementary, 1), "...#...");
+    elementary.step();
+    assert_eq!(row(&elementary, 2), ".##..#.");
+    // rule 2 moves cells left, wrapping around the edges
+    elementary.set_rule_number(2.0);
+    elementary.step();
+    assert_eq!(row(&elementary, 2), "#...#..");
+    elementary.step();
+    assert_eq!(row(&elementary, 2), "...#..#");
+
+    let config = AutomatonConfig::parse("b3/s23 5 5").unwrap();
+    assert_eq!(config.describe(), "B3/S23 5 5");
+    let mut life = Automaton::new(config);
+    life.randomize(0.0);
+    for row in 1..4 {
+        life.set(row, 2, true);
+    }
+    // a blinker turns on its side, and the cursor plays the rows in turn
+    assert_eq!(life.step(), vec![false; 5]);
+    assert_eq!(row(&life, 2), ".###.");
+    assert_eq!(life.step(), vec![false, false, true, false, false]);
+    assert_eq!(life.step(), vec![false, true, true, true, false]);
+    assert_eq!(row(&life, 1), ".....");
+
+    let pool = FramePool::new();
+    assert_eq!(life.render(&pool).data.dim(), (5, 5));
+    assert_eq!(life.render(&pool).data.iter().sum::<f32>(), 3.0);
+    assert!(AutomatonConfig::parse("B9/S23 5 5").is_none());
+    assert!(AutomatonConfig::parse("256 5 5").is_none());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct AutomatonGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for CellularAutomaton {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = self.automaton.lock().unwrap().config();
+        Box::new(AutomatonGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), config.describe(), row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for AutomatonGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match AutomatonConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx
+                            .unbounded_send(UserCommand::Configure(config))
+                            .unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: rule width height".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Setting up graphs in code, without adding nodes, looking up ports and connecting them by hand.
+//!
+//! `Builder::node` creates a module on a new node of the graph, and the `Handle` it gives sets
+//! params and chains modules by their main ports:
+//!
+//! ```ignore
+//! let builder = Builder::new(flow::Graph::new());
+//! let out = builder.node::<AudioIO>();
+//! builder
+//!     .node::<Processor<Filter>>()
+//!     .param("Cutoff", 220.0)
+//!     .pipe(builder.node::<Processor<Gain>>())
+//!     .pipe(out);
+//! let modules = builder.start(ThreadPool::new().unwrap());
+//! ```
+//!
+//! `pipe` connects the main output of a module to the main input of the next, as declared by the
+//! `Source` and `Sink` traits, so a chain of mismatched items doesn't compile. Other ports are
+//! connected by name through `Handle::port`, checked as the graph is built.
+
+use crate::install::DynModule;
+use crate::module::audio_io::{AudioIO, Frame};
+use crate::module::debug::Printer;
+use crate::module::flow::{self, NodeId};
+use crate::module::process::{Process, Processor};
+use crate::module::scheduler::BlockAudioIO;
+use crate::module::slew::Slew;
+use crate::module::Module;
+
+use futures::executor::ThreadPool;
+
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+/// A module with a main output, the start of a `pipe`.
+pub trait Source: Module {
+    /// The items the module writes.
+    type Out: 'static;
+    /// The items read back from downstream, usually `()`.
+    type Back: 'static;
+    fn output() -> &'static str;
+}
+
+/// A module with a main input, the end of a `pipe`.
+pub trait Sink: Module {
+    /// The items the module reads.
+    type In: 'static;
+    /// The items written back upstream, usually `()`.
+    type Back: 'static;
+    fn input() -> &'static str;
+}
+
+/// Creates modules on a graph, holding them until they're started.
+pub struct Builder {
+    graph: Arc<flow::Graph>,
+    modules: Mutex<Vec<Box<dyn DynModule>>>,
+}
+
+impl Builder {
+    pub fn new(graph: Arc<flow::Graph>) -> Builder {
+        Builder {
+            graph,
+            modules: Mutex::new(Vec::new()),
+        }
+    }
+    pub fn graph(&self) -> &Arc<flow::Graph> {
+        &self.graph
+    }
+    /// Create a module of type `M` on a new node.
+    pub fn node<M: Module + 'static>(&self) -> Handle<M> {
+        let ifc = self.graph.add_node();
+        let module = M::new(ifc.clone());
+        self.modules.lock().unwrap().push(Box::new(module));
+        Handle {
+            ifc,
+            _module: PhantomData,
+        }
+    }
+    /// Start every module created, in order. They run until stopped, so hold on to them.
+    pub fn start(self, exec: ThreadPool) -> Vec<Box<dyn DynModule>> {
+        let mut modules = self.modules.into_inner().unwrap();
+        for module in &mut modules {
+            module.start(exec.clone());
+        }
+        modules
+    }
+}
+
+/// A module created by a `Builder`, to set up before it starts.
+pub struct Handle<M> {
+    ifc: Arc<flow::Interface>,
+    _module: PhantomData<fn() -> M>,
+}
+
+impl<M> Clone for Handle<M> {
+    fn clone(&self) -> Handle<M> {
+        Handle {
+            ifc: self.ifc.clone(),
+            _module: PhantomData,
+        }
+    }
+}
+
+impl<M: Module> Handle<M> {
+    pub fn id(&self) -> NodeId {
+        self.ifc.id()
+    }
+    pub fn interface(&self) -> &Arc<flow::Interface> {
+        &self.ifc
+    }
+    /// The module's port `name`. Panics if it has no port of that name and type.
+    pub fn port<I: 'static, O: 'static>(&self, name: &str) -> Arc<flow::Port<I, O>> {
+        self.ifc
+            .find_port::<I, O>(name)
+            .unwrap_or_else(|| panic!("{} has no port {:?} of that type", M::name(), name))
+    }
+    /// Set the param `name`. Panics if the module has no such param.
+    pub fn param(self, name: &str, value: f32) -> Handle<M> {
+        match self.ifc.params() {
+            Some(ref params) if params.names().iter().any(|param| param == name) => params.set(name, value),
+            _ => panic!("{} has no param {:?}", M::name(), name),
+        }
+        self
+    }
+    /// Connect the main output of this module to the main input of `next`, and carry on from
+    /// `next`. Panics if either port is already connected.
+    pub fn pipe<N>(self, next: Handle<N>) -> Handle<N>
+    where
+        M: Source,
+        N: Sink<In = M::Out, Back = M::Back>,
+    {
+        let output = self.port::<M::Back, M::Out>(M::output());
+        let input = next.port::<N::In, N::Back>(N::input());
+        if let Err(e) = output.connect(&input) {
+            panic!("pipe {} to {} err: {:?}", M::name(), N::name(), e);
+        }
+        next
+    }
+}
+
+impl<P: Process> Source for Processor<P> {
+    type Out = Frame;
+    type Back = ();
+    fn output() -> &'static str {
+        // a `pipe` from a processor without outputs fails to build, rather than panicking as it runs
+        const { assert!(!P::OUTPUTS.is_empty(), "a processor without outputs can't start a pipe") };
+        P::OUTPUTS[0]
+    }
+}
+
+impl<P: Process> Sink for Processor<P> {
+    type In = Frame;
+    type Back = ();
+    fn input() -> &'static str {
+        const { assert!(!P::INPUTS.is_empty(), "a processor without inputs can't end a pipe") };
+        P::INPUTS[0]
+    }
+}
+
+impl Source for AudioIO {
+    type Out = Frame;
+    type Back = ();
+    fn output() -> &'static str {
+        "Output"
+    }
+}
+
+impl Sink for AudioIO {
+    type In = Frame;
+    type Back = ();
+    fn input() -> &'static str {
+        "Input"
+    }
+}
+
+impl Source for BlockAudioIO {
+    type Out = Frame;
+    type Back = ();
+    fn output() -> &'static str {
+        "Output"
+    }
+}
+
+impl Sink for BlockAudioIO {
+    type In = Frame;
+    type Back = ();
+    fn input() -> &'static str {
+        "Input"
+    }
+}
+
+impl Source for Slew {
+    type Out = f32;
+    type Back = ();
+    fn output() -> &'static str {
+        "Output"
+    }
+}
+
+impl Sink for Slew {
+    type In = f32;
+    type Back = ();
+    fn input() -> &'static str {
+        "Input"
+    }
+}
+
+impl<T: Debug + Send + Sync + 'static> Sink for Printer<T> {
+    type In = T;
+    type Back = usize;
+    fn input() -> &'static str {
+        "Input"
+    }
+}
+
+#[test]
+fn test_builder() {
+    use crate::module::mix::Gain;
+
+    let builder = Builder::new(flow::Graph::new());
+    let first = builder.node::<Processor<Gain>>().param("Gain", 0.5);
+    let middle = builder.node::<Processor<Gain>>();
+    let out = first.clone().pipe(middle.clone()).pipe(builder.node::<AudioIO>());
+    assert_eq!(
+        first.port::<(), Frame>("Output").edge().unwrap().node_id(),
+        middle.id()
+    );
+    assert_eq!(
+        out.port::<Frame, ()>("Input").edge().unwrap().node_id(),
+        middle.id()
+    );
+    assert_eq!(first.interface().params().unwrap().get("Gain"), Some(0.5));
+    assert_eq!(middle.interface().params().unwrap().get("Gain"), Some(1.0));
+}
@@ -0,0 +1,89 @@
+//! Converters between channel layouts, built with `Process`.
+//!
+//! Audio ports can declare how many channels their frames carry through `PortMeta::channels`,
+//! and ports declaring different counts refuse to connect. These modules go in between.
+
+use crate::module::audio_io::Frame;
+use crate::module::process::Process;
+
+use ndarray::Axis;
+
+/// Copies a mono signal to both channels of a stereo one.
+pub struct MonoToStereo;
+
+impl Process for MonoToStereo {
+    const NAME: &'static str = "Mono to Stereo";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const INPUT_CHANNELS: Option<usize> = Some(1);
+    const OUTPUT_CHANNELS: Option<usize> = Some(2);
+    fn new() -> MonoToStereo {
+        MonoToStereo
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let mono = inputs[0].data.column(0);
+        for mut channel in outputs[0].data.axis_iter_mut(Axis(1)) {
+            channel.assign(&mono);
+        }
+    }
+}
+
+/// Averages the channels of a stereo signal.
+pub struct StereoToMono;
+
+impl Process for StereoToMono {
+    const NAME: &'static str = "Stereo to Mono";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const INPUT_CHANNELS: Option<usize> = Some(2);
+    const OUTPUT_CHANNELS: Option<usize> = Some(1);
+    fn new() -> StereoToMono {
+        StereoToMono
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let inputs = inputs[0].data.outer_iter();
+        for (mut output, input) in outputs[0].data.outer_iter_mut().zip(inputs) {
+            output[0] = input.iter().sum::<f32>() / input.len() as f32;
+        }
+    }
+}
+
+#[test]
+fn test_channels() {
+    use crate::module::flow::{ConnectError, Graph, Interface, Port};
+    use crate::module::mix::Gain;
+    use crate::module::process::Processor;
+    use crate::module::Module;
+    use ndarray::Array2;
+    use std::sync::Arc;
+
+    let graph = Graph::new();
+    let (a, b, c) = (graph.add_node(), graph.add_node(), graph.add_node());
+    let (_to_mono, _gain, _to_stereo) = (
+        Processor::<StereoToMono>::new(a.clone()),
+        Processor::<Gain>::new(b.clone()),
+        Processor::<MonoToStereo>::new(c.clone()),
+    );
+    let input = |ifc: &Interface| -> Arc<Port<Frame, ()>> { ifc.find_port("Input").unwrap() };
+    let output = |ifc: &Interface| -> Arc<Port<(), Frame>> { ifc.find_port("Output").unwrap() };
+    assert!(match output(&a).connect(&input(&a)) {
+        Err(ConnectError::FormatMismatch(1, 2)) => true,
+        _ => false,
+    });
+    // ports without a declared count take anything
+    output(&a).connect(&input(&b)).unwrap();
+    output(&b).connect(&input(&c)).unwrap();
+
+    let frame = |data: Vec<f32>, channels: usize| Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_shape_vec((2, channels), data).unwrap(),
+        meta: None,
+    };
+    let mut mono = [frame(vec![0.0; 2], 1)];
+    StereoToMono.process(&[frame(vec![1.0, 0.0, 0.5, 0.5], 2)], &mut mono);
+    assert_eq!(mono[0].data.as_slice().unwrap(), &[0.5, 0.5]);
+    let mut stereo = [frame(vec![0.0; 4], 2)];
+    MonoToStereo.process(&mono, &mut stereo);
+    assert_eq!(stereo[0].data.as_slice().unwrap(), &[0.5, 0.5, 0.5, 0.5]);
+}
@@ -0,0 +1,489 @@
+//! Modulation sources simulating systems that move organically: the chaotic `Lorenz` and
+//! `Rossler` attractors, which never quite repeat, a `Bouncing Ball` and a `Spring`.
+//!
+//! Like the generators in `timeline`, these are clocked by their `Input` frames, each of which
+//! moves the simulation on by its duration in small fixed steps, so the motion doesn't depend on
+//! the frame size. Every output sends a value per frame. The attractors send their three
+//! coordinates scaled to about -1.0 to 1.0, moving through `Speed` units of the equations' time a
+//! second, with `Chaos` the parameter that makes them chaotic: rho of Lorenz and c of Rössler.
+
+use futures::channel::mpsc;
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use std::f64::consts::PI;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+/// Longest step of the attractors, in their own time.
+const ATTRACTOR_STEP: f64 = 0.005;
+/// Longest step of the physics, in seconds.
+const PHYSICS_STEP: f64 = 0.001;
+/// Most steps taken for one frame, so a long frame or a high speed can't stall the clock.
+const MAX_STEPS: usize = 10_000;
+/// Speed below which a bouncing ball comes to rest.
+const SETTLED: f64 = 0.01;
+
+/// Seconds covered by `frame`.
+fn duration(frame: &Frame) -> f64 {
+    frame.data.dim().0 as f64 / frame.rate as f64
+}
+
+/// Split `time` into at most `MAX_STEPS` equal steps no longer than `step`.
+fn steps(time: f64, step: f64) -> (usize, f64) {
+    let count = ((time / step).ceil().max(0.0) as usize).min(MAX_STEPS);
+    (count, if count > 0 { time / count as f64 } else { 0.0 })
+}
+
+/// The differential equations of an attractor in three dimensions.
+pub trait Equations: Send + 'static {
+    const NAME: &'static str;
+    /// Where the system starts, and starts over if it ever blows up.
+    const START: [f64; 3];
+    /// The middle and extent of the attractor along each axis, to scale it to about -1.0 to 1.0.
+    const CENTER: [f64; 3];
+    const SCALE: [f64; 3];
+    /// The usual value of the chaos parameter.
+    const CHAOS: f64;
+    fn derivative(point: [f64; 3], chaos: f64) -> [f64; 3];
+}
+
+pub struct Lorenz;
+
+impl Equations for Lorenz {
+    const NAME: &'static str = "Lorenz";
+    const START: [f64; 3] = [1.0, 1.0, 1.0];
+    const CENTER: [f64; 3] = [0.0, 0.0, 25.0];
+    const SCALE: [f64; 3] = [20.0, 27.0, 25.0];
+    const CHAOS: f64 = 28.0;
+    fn derivative(p: [f64; 3], rho: f64) -> [f64; 3] {
+        let (sigma, beta) = (10.0, 8.0 / 3.0);
+        [
+            sigma * (p[1] - p[0]),
+            p[0] * (rho - p[2]) - p[1],
+            p[0] * p[1] - beta * p[2],
+        ]
+    }
+}
+
+pub struct Rossler;
+
+impl Equations for Rossler {
+    const NAME: &'static str = "Rossler";
+    const START: [f64; 3] = [1.0, 1.0, 0.0];
+    const CENTER: [f64; 3] = [0.0, 0.0, 10.0];
+    const SCALE: [f64; 3] = [12.0, 12.0, 10.0];
+    const CHAOS: f64 = 5.7;
+    fn derivative(p: [f64; 3], c: f64) -> [f64; 3] {
+        let (a, b) = (0.2, 0.2);
+        [-p[1] - p[2], p[0] + a * p[1], b + p[2] * (p[0] - c)]
+    }
+}
+
+/// Follows an attractor with fourth order Runge-Kutta steps.
+pub struct Integrator<E: Equations> {
+    point: [f64; 3],
+    /// Units of the equations' time a second.
+    pub speed: f64,
+    pub chaos: f64,
+    equations: PhantomData<E>,
+}
+
+impl<E: Equations> Integrator<E> {
+    pub fn new() -> Integrator<E> {
+        Integrator {
+            point: E::START,
+            speed: 1.0,
+            chaos: E::CHAOS,
+            equations: PhantomData,
+        }
+    }
+    fn step(&mut self, dt: f64) {
+        let offset = |p: [f64; 3], d: [f64; 3], scale: f64| {
+            [p[0] + d[0] * scale, p[1] + d[1] * scale, p[2] + d[2] * scale]
+        };
+        let p = self.point;
+        let k1 = E::derivative(p, self.chaos);
+        let k2 = E::derivative(offset(p, k1, dt / 2.0), self.chaos);
+        let k3 = E::derivative(offset(p, k2, dt / 2.0), self.chaos);
+        let k4 = E::derivative(offset(p, k3, dt), self.chaos);
+        for axis in 0..3 {
+            self.point[axis] += dt / 6.0 * (k1[axis] + 2.0 * k2[axis] + 2.0 * k3[axis] + k4[axis]);
+        }
+        if !self.point.iter().all(|x| x.is_finite()) {
+            self.point = E::START;
+        }
+    }
+    /// Move on by `seconds`, returning the scaled coordinates reached.
+    pub fn advance(&mut self, seconds: f64) -> [f32; 3] {
+        let (count, dt) = steps(seconds * self.speed.max(0.0), ATTRACTOR_STEP);
+        for _ in 0..count {
+            self.step(dt);
+        }
+        let mut out = [0.0; 3];
+        for axis in 0..3 {
+            out[axis] = ((self.point[axis] - E::CENTER[axis]) / E::SCALE[axis]) as f32;
+        }
+        out
+    }
+}
+
+/// A ball dropped onto the floor, in units above it.
+pub struct Ball {
+    height: f64,
+    velocity: f64,
+    /// Units per second squared.
+    pub gravity: f64,
+    /// The fraction of its speed the ball keeps at each bounce.
+    pub restitution: f64,
+}
+
+impl Ball {
+    pub fn new() -> Ball {
+        Ball {
+            height: 0.0,
+            velocity: 0.0,
+            gravity: 9.8,
+            restitution: 0.8,
+        }
+    }
+    /// Drop the ball from `height`.
+    pub fn drop_from(&mut self, height: f64) {
+        self.height = height.max(0.0);
+        self.velocity = 0.0;
+    }
+    pub fn height(&self) -> f64 {
+        self.height
+    }
+    /// Move on by `seconds`, returning the speed of the hardest bounce in that time, if any.
+    pub fn advance(&mut self, seconds: f64) -> Option<f64> {
+        let mut impact: Option<f64> = None;
+        let (count, dt) = steps(seconds, PHYSICS_STEP);
+        for _ in 0..count {
+            if self.height <= 0.0 && self.velocity == 0.0 {
+                break;
+            }
+            self.velocity -= self.gravity * dt;
+            self.height += self.velocity * dt;
+            if self.height <= 0.0 {
+                let speed = -self.velocity;
+                impact = Some(impact.map_or(speed, |impact| impact.max(speed)));
+                self.height = 0.0;
+                self.velocity = speed * self.restitution.max(0.0).min(1.0);
+                if self.velocity < SETTLED {
+                    self.velocity = 0.0;
+                }
+            }
+        }
+        impact
+    }
+}
+
+/// A mass on a spring pulled towards `target`.
+pub struct DampedSpring {
+    position: f64,
+    velocity: f64,
+    pub target: f64,
+    /// Hz the spring swings at without damping.
+    pub frequency: f64,
+    /// 1.0 settles as fast as possible without overshooting, lower values swing.
+    pub damping: f64,
+}
+
+impl DampedSpring {
+    pub fn new() -> DampedSpring {
+        DampedSpring {
+            position: 0.0,
+            velocity: 0.0,
+            target: 0.0,
+            frequency: 1.0,
+            damping: 0.2,
+        }
+    }
+    /// Push the mass, changing its velocity by `impulse` units a second.
+    pub fn kick(&mut self, impulse: f64) {
+        self.velocity += impulse;
+    }
+    /// Move on by `seconds`, returning the position and velocity reached.
+    pub fn advance(&mut self, seconds: f64) -> (f64, f64) {
+        let omega = 2.0 * PI * self.frequency.max(0.0);
+        let (count, dt) = steps(seconds, PHYSICS_STEP);
+        for _ in 0..count {
+            let acceleration =
+                -omega * omega * (self.position - self.target) - 2.0 * self.damping * omega * self.velocity;
+            // semi-implicit Euler, which keeps an undamped spring swinging as far as it started
+            self.velocity += acceleration * dt;
+            self.position += self.velocity * dt;
+        }
+        (self.position, self.velocity)
+    }
+}
+
+pub struct Attractor<E: Equations> {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    speed_port: Arc<flow::Port<f32, ()>>,
+    chaos_port: Arc<flow::Port<f32, ()>>,
+    out_ports: Vec<Arc<flow::Port<(), f32>>>,
+    breaker: Breaker,
+    integrator: Arc<Mutex<Integrator<E>>>,
+}
+
+impl<E: Equations> Module for Attractor<E> {
+    fn new(ifc: Arc<flow::Interface>) -> Attractor<E> {
+        Attractor {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            speed_port: ifc.get_or_create_port("Speed".into()),
+            chaos_port: ifc.get_or_create_port("Chaos".into()),
+            out_ports: ["X", "Y", "Z"]
+                .iter()
+                .map(|&name| ifc.get_or_create_port(name.into()))
+                .collect(),
+            ifc,
+            breaker: Breaker::new(),
+            integrator: Arc::new(Mutex::new(Integrator::new())),
+        }
+    }
+    fn name() -> &'static str {
+        E::NAME
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let integrator = self.integrator.clone();
+        util::start_sink(
+            self.speed_port.clone(),
+            move |speed: f32| integrator.lock().unwrap().speed = speed as f64,
+            self.breaker.clone(),
+            &exec,
+        );
+        let integrator = self.integrator.clone();
+        util::start_sink(
+            self.chaos_port.clone(),
+            move |chaos: f32| integrator.lock().unwrap().chaos = chaos as f64,
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let mut txs = Vec::new();
+        for port in &self.out_ports {
+            let (tx, rx) = mpsc::channel(1);
+            txs.push(tx);
+            util::start_source(rx, port.clone(), &exec);
+        }
+        let integrator = self.integrator.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                let point = integrator.lock().unwrap().advance(duration(&frame));
+                for (tx, &value) in txs.iter_mut().zip(&point) {
+                    let _ = tx.try_send(value);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// A ball dropped from the height given on `Drop`, bouncing under `Gravity` and keeping `Bounce`
+/// of its speed each time it lands. `Height` follows it, and `Impact` sends its speed on landing,
+/// to trigger and accent sounds.
+pub struct BouncingBall {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    drop_port: Arc<flow::Port<f32, ()>>,
+    gravity_port: Arc<flow::Port<f32, ()>>,
+    bounce_port: Arc<flow::Port<f32, ()>>,
+    height_port: Arc<flow::Port<(), f32>>,
+    impact_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    ball: Arc<Mutex<Ball>>,
+}
+
+impl Module for BouncingBall {
+    fn new(ifc: Arc<flow::Interface>) -> BouncingBall {
+        BouncingBall {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            drop_port: ifc.get_or_create_port("Drop".into()),
+            gravity_port: ifc.get_or_create_port("Gravity".into()),
+            bounce_port: ifc.get_or_create_port("Bounce".into()),
+            height_port: ifc.get_or_create_port("Height".into()),
+            impact_port: ifc.get_or_create_port("Impact".into()),
+            ifc,
+            breaker: Breaker::new(),
+            ball: Arc::new(Mutex::new(Ball::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Bouncing Ball"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let controls: [(&Arc<flow::Port<f32, ()>>, fn(&mut Ball, f32)); 3] = [
+            (&self.drop_port, |ball, height| ball.drop_from(height as f64)),
+            (&self.gravity_port, |ball, gravity| ball.gravity = gravity as f64),
+            (&self.bounce_port, |ball, bounce| ball.restitution = bounce as f64),
+        ];
+        for &(port, set) in &controls {
+            let ball = self.ball.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| set(&mut ball.lock().unwrap(), value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let (mut height_tx, height_rx) = mpsc::channel(1);
+        let (mut impact_tx, impact_rx) = mpsc::channel(1);
+        let ball = self.ball.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                let mut ball = ball.lock().unwrap();
+                if let Some(speed) = ball.advance(duration(&frame)) {
+                    let _ = impact_tx.try_send(speed as f32);
+                }
+                let _ = height_tx.try_send(ball.height() as f32);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(height_rx, self.height_port.clone(), &exec);
+        util::start_source(impact_rx, self.impact_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// A mass on a spring swinging towards `Target` at `Frequency`, slowed by `Damping`, and pushed by
+/// the values on `Kick`. `Position` and `Velocity` follow it.
+pub struct Spring {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    target_port: Arc<flow::Port<f32, ()>>,
+    frequency_port: Arc<flow::Port<f32, ()>>,
+    damping_port: Arc<flow::Port<f32, ()>>,
+    kick_port: Arc<flow::Port<f32, ()>>,
+    position_port: Arc<flow::Port<(), f32>>,
+    velocity_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    spring: Arc<Mutex<DampedSpring>>,
+}
+
+impl Module for Spring {
+    fn new(ifc: Arc<flow::Interface>) -> Spring {
+        Spring {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            target_port: ifc.get_or_create_port("Target".into()),
+            frequency_port: ifc.get_or_create_port("Frequency".into()),
+            damping_port: ifc.get_or_create_port("Damping".into()),
+            kick_port: ifc.get_or_create_port("Kick".into()),
+            position_port: ifc.get_or_create_port("Position".into()),
+            velocity_port: ifc.get_or_create_port("Velocity".into()),
+            ifc,
+            breaker: Breaker::new(),
+            spring: Arc::new(Mutex::new(DampedSpring::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Spring"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let controls: [(&Arc<flow::Port<f32, ()>>, fn(&mut DampedSpring, f32)); 4] = [
+            (&self.target_port, |spring, target| spring.target = target as f64),
+            (&self.frequency_port, |spring, frequency| {
+                spring.frequency = frequency as f64
+            }),
+            (&self.damping_port, |spring, damping| {
+                spring.damping = damping as f64
+            }),
+            (&self.kick_port, |spring, impulse| spring.kick(impulse as f64)),
+        ];
+        for &(port, set) in &controls {
+            let spring = self.spring.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| set(&mut spring.lock().unwrap(), value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let (mut position_tx, position_rx) = mpsc::channel(1);
+        let (mut velocity_tx, velocity_rx) = mpsc::channel(1);
+        let spring = self.spring.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                let (position, velocity) = spring.lock().unwrap().advance(duration(&frame));
+                let _ = position_tx.try_send(position as f32);
+                let _ = velocity_tx.try_send(velocity as f32);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(position_rx, self.position_port.clone(), &exec);
+        util::start_source(velocity_rx, self.velocity_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_chaos() {
+    // both attractors stay within about their scale, but keep moving
+    let mut lorenz = Integrator::<Lorenz>::new();
+    let mut rossler = Integrator::<Rossler>::new();
+    let (mut lorenz_min, mut lorenz_max) = (0.0f32, 0.0f32);
+    for _ in 0..2000 {
+        let point = lorenz.advance(0.01);
+        assert!(point.iter().all(|x| x.abs() < 1.5));
+        lorenz_min = lorenz_min.min(point[0]);
+        lorenz_max = lorenz_max.max(point[0]);
+        assert!(rossler.advance(0.05).iter().all(|x| x.abs() < 2.5));
+    }
+    assert!(lorenz_min < -0.5 && lorenz_max > 0.5);
+    // a step that blows up starts over
+    lorenz.point = [1e300, 1e300, 1e300];
+    lorenz.advance(0.01);
+    assert!(lorenz.point.iter().all(|x| x.is_finite()));
+
+    let mut ball = Ball::new();
+    ball.drop_from(1.0);
+    assert_eq!(ball.advance(0.4), None);
+    // lands after (2 / 9.8).sqrt() seconds at 4.43 units a second
+    let impact = ball.advance(0.1).unwrap();
+    assert!((impact - 4.43).abs() < 0.02);
+    assert!(ball.height() < 0.2);
+    // and comes to rest eventually
+    ball.advance(10.0);
+    assert_eq!(ball.height(), 0.0);
+    assert_eq!(ball.advance(1.0), None);
+
+    let mut spring = DampedSpring::new();
+    spring.damping = 0.0;
+    spring.target = 1.0;
+    // half a swing overshoots to twice the target
+    let (position, _) = spring.advance(0.5);
+    assert!((position - 2.0).abs() < 0.02);
+    spring.damping = 1.0;
+    let (position, velocity) = spring.advance(10.0);
+    assert!((position - 1.0).abs() < 1e-3 && velocity.abs() < 1e-3);
+    spring.kick(1.0);
+    assert!(spring.advance(0.1).0 > 1.0);
+}
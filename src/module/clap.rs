@@ -0,0 +1,943 @@
+//! Hosting CLAP plugins, without their editors.
+//!
+//! A `CLAP Plugin` node loads the plugin with the `id` in its state from the `.clap` file at
+//! `path`. Each of the plugin's audio ports becomes a port of frames with as many channels, and
+//! each parameter a control input, which is also a parameter of the node for scenes. Plugins
+//! without audio inputs get a `Clock` input instead, which only sets the rate and length of the
+//! blocks. Like `Processor`s, plugins run as tasks or in the `BlockScheduler`.
+//!
+//! The patch saves the plugin's own state, through its state extension, along with the parameter
+//! values, so everything set up in the plugin comes back with the patch and is recalled with
+//! scenes as far as its parameters go.
+//!
+//! Blocks ask for the plugin to be activated with their rate, which a task of the node does off the
+//! audio thread, and again if the rate changes. Blocks are left silent until it has. Note events
+//! aren't sent yet. Parameter changes the plugin sends back are taken on by the node's controls,
+//! and its other events are dropped. Every plugin found in the CLAP search path is also registered
+//! under its own name, see `add_installed`.
+//!
+//! Built with the `clap` feature.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::gui::module_gui::StatefulGuiModuleFactory;
+use crate::module::declick::DEFAULT_RAMP;
+use crate::module::process::start_blocks;
+use crate::module::scene::Params;
+use crate::module::scheduler::{Block, BlockNode};
+use crate::module::{audio_io::Frame, flow, util, Module};
+use crate::registry::Registry;
+
+use clap_sys::audio_buffer::clap_audio_buffer;
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::{
+    clap_event_header, clap_event_param_value, clap_input_events, clap_output_events,
+    CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_PARAM_VALUE,
+};
+use clap_sys::ext::audio_ports::{clap_audio_port_info, clap_plugin_audio_ports, CLAP_EXT_AUDIO_PORTS};
+use clap_sys::ext::params::{clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::host::clap_host;
+use clap_sys::plugin::clap_plugin;
+use clap_sys::process::{clap_process, CLAP_PROCESS_ERROR};
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::CLAP_VERSION;
+
+use libloading::Library;
+
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The longest block a plugin is run with. Longer blocks are cut short.
+pub const MAX_BLOCK: usize = 8192;
+
+/// An open `.clap` file.
+struct Bundle {
+    _library: Library,
+    entry: *const clap_plugin_entry,
+}
+
+unsafe impl Send for Bundle {}
+unsafe impl Sync for Bundle {}
+
+/// Open the bundle at `path`, or return it if it is already open. CLAP wants every bundle
+/// initialized only once, so they stay open until the process exits.
+fn open_bundle(path: &Path) -> Result<Arc<Bundle>, String> {
+    static BUNDLES: OnceLock<Mutex<HashMap<PathBuf, Arc<Bundle>>>> = OnceLock::new();
+    let mut bundles = BUNDLES.get_or_init(Default::default).lock().unwrap();
+    if let Some(bundle) = bundles.get(path) {
+        return Ok(bundle.clone());
+    }
+    let library = Library::new(path).map_err(|e| e.to_string())?;
+    let entry = unsafe {
+        let symbol = library
+            .get::<*const clap_plugin_entry>(b"clap_entry\0")
+            .map_err(|e| e.to_string())?;
+        *symbol
+    };
+    let c_path = CString::new(path.to_string_lossy().into_owned()).map_err(|e| e.to_string())?;
+    let init = unsafe { (*entry).init }.ok_or("no init")?;
+    if !unsafe { init(c_path.as_ptr()) } {
+        return Err(format!("{} failed to initialize", path.display()));
+    }
+    let bundle = Arc::new(Bundle {
+        _library: library,
+        entry,
+    });
+    bundles.insert(path.to_path_buf(), bundle.clone());
+    Ok(bundle)
+}
+
+impl Bundle {
+    fn factory(&self) -> Option<&clap_plugin_factory> {
+        unsafe {
+            let get_factory = (*self.entry).get_factory?;
+            (get_factory(CLAP_PLUGIN_FACTORY_ID.as_ptr()) as *const clap_plugin_factory).as_ref()
+        }
+    }
+    /// The id and name of every plugin in the bundle.
+    fn plugins(&self) -> Vec<(String, String)> {
+        let factory = match self.factory() {
+            Some(factory) => factory,
+            None => return Vec::new(),
+        };
+        let (count, get) = match (factory.get_plugin_count, factory.get_plugin_descriptor) {
+            (Some(count), Some(get)) => (count, get),
+            _ => return Vec::new(),
+        };
+        unsafe {
+            (0..count(factory))
+                .filter_map(|idx| get(factory, idx).as_ref())
+                .map(|desc| (string(desc.id), string(desc.name)))
+                .collect()
+        }
+    }
+}
+
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().into_owned()
+    }
+}
+
+const HOST_NAME: &[u8] = b"flow-synth\0";
+const HOST_URL: &[u8] = b"https://github.com/flipscholtz/flow-synth\0";
+const HOST_VERSION: &[u8] = b"0.1.0\0";
+
+unsafe extern "C" fn host_get_extension(_host: *const clap_host, _id: *const c_char) -> *const c_void {
+    ptr::null()
+}
+unsafe extern "C" fn host_request(_host: *const clap_host) {}
+
+fn new_host() -> Box<clap_host> {
+    Box::new(clap_host {
+        clap_version: CLAP_VERSION,
+        host_data: ptr::null_mut(),
+        name: HOST_NAME.as_ptr() as *const c_char,
+        vendor: HOST_NAME.as_ptr() as *const c_char,
+        url: HOST_URL.as_ptr() as *const c_char,
+        version: HOST_VERSION.as_ptr() as *const c_char,
+        get_extension: Some(host_get_extension),
+        request_restart: Some(host_request),
+        request_process: Some(host_request),
+        request_callback: Some(host_request),
+    })
+}
+
+struct ParamInfo {
+    id: u32,
+    name: String,
+    default: f64,
+}
+
+/// An audio port of the plugin, by name and channel count.
+struct AudioPort {
+    name: String,
+    channels: usize,
+}
+
+/// One instance of a plugin.
+struct Instance {
+    _bundle: Arc<Bundle>,
+    /// Must outlive the plugin.
+    _host: Box<clap_host>,
+    plugin: *const clap_plugin,
+    /// The rate the plugin was activated with.
+    rate: Option<f32>,
+    /// Whether processing was started since it was activated.
+    processing: bool,
+}
+
+unsafe impl Send for Instance {}
+
+impl Instance {
+    fn new(path: &Path, id: &str) -> Result<Instance, String> {
+        let bundle = open_bundle(path)?;
+        let host = new_host();
+        let plugin = {
+            let factory = bundle.factory().ok_or("no plugin factory")?;
+            let create = factory.create_plugin.ok_or("no create_plugin")?;
+            let c_id = CString::new(id).map_err(|e| e.to_string())?;
+            unsafe { create(factory, &*host, c_id.as_ptr()) }
+        };
+        if plugin.is_null() {
+            return Err(format!("{} has no plugin {}", path.display(), id));
+        }
+        let instance = Instance {
+            _bundle: bundle,
+            _host: host,
+            plugin,
+            rate: None,
+            processing: false,
+        };
+        let init = instance.plugin().init.ok_or("no init")?;
+        if !unsafe { init(plugin) } {
+            return Err(format!("{} failed to initialize", id));
+        }
+        Ok(instance)
+    }
+    fn plugin(&self) -> &clap_plugin {
+        unsafe { &*self.plugin }
+    }
+    fn extension<T>(&self, id: &CStr) -> Option<&T> {
+        let get = self.plugin().get_extension?;
+        unsafe { (get(self.plugin, id.as_ptr()) as *const T).as_ref() }
+    }
+    fn audio_ports(&self, input: bool) -> Vec<AudioPort> {
+        let ext = match self.extension::<clap_plugin_audio_ports>(CLAP_EXT_AUDIO_PORTS) {
+            Some(ext) => ext,
+            None => return Vec::new(),
+        };
+        let (count, get) = match (ext.count, ext.get) {
+            (Some(count), Some(get)) => (count, get),
+            _ => return Vec::new(),
+        };
+        unsafe {
+            (0..count(self.plugin, input))
+                .filter_map(|idx| {
+                    let mut info: clap_audio_port_info = mem::zeroed();
+                    if get(self.plugin, idx, input, &mut info) {
+                        Some(AudioPort {
+                            name: string(info.name.as_ptr()),
+                            channels: info.channel_count as usize,
+                        })
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        }
+    }
+    fn params(&self) -> Vec<ParamInfo> {
+        let ext = match self.extension::<clap_plugin_params>(CLAP_EXT_PARAMS) {
+            Some(ext) => ext,
+            None => return Vec::new(),
+        };
+        let (count, get_info) = match (ext.count, ext.get_info) {
+            (Some(count), Some(get_info)) => (count, get_info),
+            _ => return Vec::new(),
+        };
+        unsafe {
+            (0..count(self.plugin))
+                .filter_map(|idx| {
+                    let mut info: clap_param_info = mem::zeroed();
+                    if get_info(self.plugin, idx, &mut info) {
+                        Some(ParamInfo {
+                            id: info.id,
+                            name: string(info.name.as_ptr()),
+                            default: info.default_value,
+                        })
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        }
+    }
+    fn param_value(&self, id: u32) -> Option<f64> {
+        let get_value = self.extension::<clap_plugin_params>(CLAP_EXT_PARAMS)?.get_value?;
+        let mut value = 0.0;
+        if unsafe { get_value(self.plugin, id, &mut value) } {
+            Some(value)
+        } else {
+            None
+        }
+    }
+    fn save(&self) -> Option<Vec<u8>> {
+        let save = self.extension::<clap_plugin_state>(CLAP_EXT_STATE)?.save?;
+        let mut data: Vec<u8> = Vec::new();
+        let stream = clap_ostream {
+            ctx: &mut data as *mut Vec<u8> as *mut c_void,
+            write: Some(write_stream),
+        };
+        if unsafe { save(self.plugin, &stream) } {
+            Some(data)
+        } else {
+            None
+        }
+    }
+    fn load(&self, data: &[u8]) -> bool {
+        let load = match self
+            .extension::<clap_plugin_state>(CLAP_EXT_STATE)
+            .and_then(|ext| ext.load)
+        {
+            Some(load) => load,
+            None => return false,
+        };
+        let mut reader = data;
+        let stream = clap_istream {
+            ctx: &mut reader as *mut &[u8] as *mut c_void,
+            read: Some(read_stream),
+        };
+        unsafe { load(self.plugin, &stream) }
+    }
+    /// Activate the plugin for `rate`, if it isn't already. CLAP wants this done off the audio
+    /// thread.
+    fn activate(&mut self, rate: f32) -> bool {
+        if self.rate == Some(rate) {
+            return true;
+        }
+        self.deactivate();
+        let activate = match self.plugin().activate {
+            Some(activate) => activate,
+            None => return false,
+        };
+        if !unsafe { activate(self.plugin, rate as f64, 1, MAX_BLOCK as u32) } {
+            return false;
+        }
+        self.rate = Some(rate);
+        true
+    }
+    /// Start processing once activated, on the audio thread.
+    fn start_processing(&mut self) -> bool {
+        if !self.processing {
+            let start = match self.plugin().start_processing {
+                Some(start) => start,
+                None => return false,
+            };
+            self.processing = unsafe { start(self.plugin) };
+        }
+        self.processing
+    }
+    /// Stop processing and deactivate. Processing is stopped here rather than on the audio thread,
+    /// but never during a block, since the block is locked for this.
+    fn deactivate(&mut self) {
+        if self.rate.take().is_none() {
+            return;
+        }
+        let processing = mem::replace(&mut self.processing, false);
+        let plugin = self.plugin();
+        unsafe {
+            if processing {
+                if let Some(stop) = plugin.stop_processing {
+                    stop(self.plugin);
+                }
+            }
+            if let Some(deactivate) = plugin.deactivate {
+                deactivate(self.plugin);
+            }
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        self.deactivate();
+        if let Some(destroy) = self.plugin().destroy {
+            unsafe { destroy(self.plugin) };
+        }
+    }
+}
+
+unsafe extern "C" fn write_stream(stream: *const clap_ostream, buffer: *const c_void, size: u64) -> i64 {
+    let data = &mut *((*stream).ctx as *mut Vec<u8>);
+    data.extend_from_slice(slice::from_raw_parts(buffer as *const u8, size as usize));
+    size as i64
+}
+
+unsafe extern "C" fn read_stream(stream: *const clap_istream, buffer: *mut c_void, size: u64) -> i64 {
+    let reader = &mut *((*stream).ctx as *mut &[u8]);
+    let len = reader.len().min(size as usize);
+    ptr::copy_nonoverlapping(reader.as_ptr(), buffer as *mut u8, len);
+    *reader = &reader[len..];
+    len as i64
+}
+
+unsafe extern "C" fn events_size(list: *const clap_input_events) -> u32 {
+    (*((*list).ctx as *const Vec<clap_event_param_value>)).len() as u32
+}
+
+unsafe extern "C" fn events_get(list: *const clap_input_events, idx: u32) -> *const clap_event_header {
+    let events = &*((*list).ctx as *const Vec<clap_event_param_value>);
+    match events.get(idx as usize) {
+        Some(event) => &event.header,
+        None => ptr::null(),
+    }
+}
+
+/// Keeps the parameter changes the plugin sends, dropping its other events.
+unsafe extern "C" fn events_push(list: *const clap_output_events, event: *const clap_event_header) -> bool {
+    let events = &mut *((*list).ctx as *mut Vec<clap_event_param_value>);
+    let header = &*event;
+    if header.space_id == CLAP_CORE_EVENT_SPACE_ID && header.type_ == CLAP_EVENT_PARAM_VALUE {
+        events.push(ptr::read(event as *const clap_event_param_value));
+    }
+    true
+}
+
+fn param_event(id: u32, value: f32) -> clap_event_param_value {
+    clap_event_param_value {
+        header: clap_event_header {
+            size: mem::size_of::<clap_event_param_value>() as u32,
+            time: 0,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_: CLAP_EVENT_PARAM_VALUE,
+            flags: 0,
+        },
+        param_id: id,
+        cookie: ptr::null_mut(),
+        note_id: -1,
+        port_index: -1,
+        channel: -1,
+        key: -1,
+        value: value as f64,
+    }
+}
+
+/// The parameters of a plugin.
+struct Controls {
+    names: Vec<String>,
+    ids: Vec<u32>,
+    values: Mutex<Vec<f32>>,
+}
+
+impl Controls {
+    fn set_index(&self, idx: usize, value: f32) {
+        self.values.lock().unwrap()[idx] = value;
+    }
+}
+
+impl Params for Controls {
+    fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+    fn get(&self, name: &str) -> Option<f32> {
+        let idx = self.names.iter().position(|param| param == name)?;
+        Some(self.values.lock().unwrap()[idx])
+    }
+    fn set(&self, name: &str, value: f32) {
+        if let Some(idx) = self.names.iter().position(|param| param == name) {
+            self.set_index(idx, value);
+        }
+    }
+}
+
+/// The buffers of one audio port, with the pointers handed to the plugin.
+struct Buffer {
+    channels: Vec<Vec<f32>>,
+    pointers: Vec<*mut f32>,
+}
+
+impl Buffer {
+    fn new(channels: usize) -> Buffer {
+        Buffer {
+            channels: (0..channels).map(|_| vec![0.0; MAX_BLOCK]).collect(),
+            pointers: Vec::new(),
+        }
+    }
+    fn clap(&mut self) -> clap_audio_buffer {
+        self.pointers = self
+            .channels
+            .iter_mut()
+            .map(|channel| channel.as_mut_ptr())
+            .collect();
+        clap_audio_buffer {
+            data32: self.pointers.as_mut_ptr(),
+            data64: ptr::null_mut(),
+            channel_count: self.channels.len() as u32,
+            latency: 0,
+            constant_mask: 0,
+        }
+    }
+}
+
+struct ClapBlock {
+    instance: Instance,
+    controls: Arc<Controls>,
+    /// The parameter values last sent to the plugin.
+    sent: Vec<f32>,
+    clocked: bool,
+    inputs: Vec<Buffer>,
+    outputs: Vec<Buffer>,
+    events: Vec<clap_event_param_value>,
+    /// Parameter changes sent back by the plugin during a block.
+    out_events: Vec<clap_event_param_value>,
+    steady_time: i64,
+    /// Asks the node's task to activate the plugin with a rate.
+    rate_tx: UnboundedSender<f32>,
+    /// The rate last asked for.
+    requested: Option<f32>,
+}
+
+unsafe impl Send for ClapBlock {}
+
+impl Block for ClapBlock {
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let len = inputs[0].data.dim().0.min(MAX_BLOCK);
+        let rate = inputs[0].rate;
+        if self.instance.rate != Some(rate) {
+            if self.requested != Some(rate) {
+                self.requested = Some(rate);
+                let _ = self.rate_tx.unbounded_send(rate);
+            }
+            return;
+        }
+        if !self.instance.start_processing() {
+            return;
+        }
+        self.events.clear();
+        for ((&id, &value), sent) in self
+            .controls
+            .ids
+            .iter()
+            .zip(self.controls.values.lock().unwrap().iter())
+            .zip(&mut self.sent)
+        {
+            if value != *sent {
+                self.events.push(param_event(id, value));
+                *sent = value;
+            }
+        }
+
+        let audio = if self.clocked { &inputs[1..] } else { inputs };
+        for (buffer, frame) in self.inputs.iter_mut().zip(audio) {
+            let (rows, channels) = frame.data.dim();
+            for (idx, channel) in buffer.channels.iter_mut().enumerate() {
+                for row in 0..len {
+                    channel[row] = if row < rows && channels > 0 {
+                        frame.data[[row, idx.min(channels - 1)]]
+                    } else {
+                        0.0
+                    };
+                }
+            }
+        }
+        let audio_inputs: Vec<clap_audio_buffer> =
+            self.inputs.iter_mut().map(|buffer| buffer.clap()).collect();
+        let mut audio_outputs: Vec<clap_audio_buffer> =
+            self.outputs.iter_mut().map(|buffer| buffer.clap()).collect();
+        let in_events = clap_input_events {
+            ctx: &self.events as *const Vec<clap_event_param_value> as *mut c_void,
+            size: Some(events_size),
+            get: Some(events_get),
+        };
+        self.out_events.clear();
+        let out_events = clap_output_events {
+            ctx: &mut self.out_events as *mut Vec<clap_event_param_value> as *mut c_void,
+            try_push: Some(events_push),
+        };
+        let process = clap_process {
+            steady_time: self.steady_time,
+            frames_count: len as u32,
+            transport: ptr::null(),
+            audio_inputs: audio_inputs.as_ptr(),
+            audio_outputs: audio_outputs.as_mut_ptr(),
+            audio_inputs_count: audio_inputs.len() as u32,
+            audio_outputs_count: audio_outputs.len() as u32,
+            in_events: &in_events,
+            out_events: &out_events,
+        };
+        self.steady_time += len as i64;
+        let status = match self.instance.plugin().process {
+            Some(process_fn) => unsafe { process_fn(self.instance.plugin, &process) },
+            None => CLAP_PROCESS_ERROR,
+        };
+        if status == CLAP_PROCESS_ERROR {
+            return;
+        }
+
+        // the plugin's own changes aren't sent back to it
+        for event in &self.out_events {
+            if let Some(idx) = self.controls.ids.iter().position(|&id| id == event.param_id) {
+                self.controls.set_index(idx, event.value as f32);
+                self.sent[idx] = event.value as f32;
+            }
+        }
+
+        for (frame, buffer) in outputs.iter_mut().zip(&self.outputs) {
+            let (rows, channels) = frame.data.dim();
+            for (idx, channel) in buffer.channels.iter().enumerate().take(channels) {
+                for row in 0..len.min(rows) {
+                    frame.data[[row, idx]] = channel[row];
+                }
+            }
+        }
+    }
+}
+
+/// A loaded plugin's part of a `ClapPlugin`.
+struct Loaded {
+    path: PathBuf,
+    id: String,
+    inputs: Vec<Arc<flow::Port<Frame, ()>>>,
+    outputs: Vec<Arc<flow::Port<(), Frame>>>,
+    params: Vec<Arc<flow::Port<f32, ()>>>,
+    controls: Arc<Controls>,
+    block: Arc<Mutex<ClapBlock>>,
+    rate_rx: Option<UnboundedReceiver<f32>>,
+}
+
+/// Hosts the CLAP plugin given by the `path` and `id` in its state.
+pub struct ClapPlugin {
+    ifc: Arc<flow::Interface>,
+    breaker: Breaker,
+    loaded: Option<Loaded>,
+}
+
+impl ClapPlugin {
+    /// The id of the loaded plugin.
+    pub fn plugin_id(&self) -> Option<&str> {
+        self.loaded.as_ref().map(|loaded| loaded.id.as_str())
+    }
+
+    fn load(&mut self, path: &Path, id: &str) -> Result<(), String> {
+        let instance = Instance::new(path, id)?;
+        let audio_in = instance.audio_ports(true);
+        let audio_out = instance.audio_ports(false);
+        let params = instance.params();
+        let clocked = audio_in.is_empty();
+
+        let mut inputs: Vec<Arc<flow::Port<Frame, ()>>> = Vec::new();
+        if clocked {
+            inputs.push(self.ifc.get_or_create_port("Clock".into()));
+        }
+        for port in &audio_in {
+            let input = self.ifc.get_or_create_port(port.name.clone());
+            input.set_meta(flow::PortMeta {
+                ramp: Some(DEFAULT_RAMP),
+                channels: Some(port.channels),
+                ..input.meta()
+            });
+            inputs.push(input);
+        }
+        inputs[0].set_meta(flow::PortMeta {
+            required: true,
+            ..inputs[0].meta()
+        });
+        let outputs: Vec<Arc<flow::Port<(), Frame>>> = audio_out
+            .iter()
+            .map(|port| {
+                let output = self.ifc.get_or_create_port(port.name.clone());
+                output.set_meta(flow::PortMeta {
+                    ramp: Some(DEFAULT_RAMP),
+                    channels: Some(port.channels),
+                    ..output.meta()
+                });
+                output
+            })
+            .collect();
+
+        let values: Vec<f32> = params.iter().map(|param| param.default as f32).collect();
+        let controls = Arc::new(Controls {
+            names: params.iter().map(|param| param.name.clone()).collect(),
+            ids: params.iter().map(|param| param.id).collect(),
+            values: Mutex::new(values.clone()),
+        });
+        let param_ports = controls
+            .names
+            .iter()
+            .map(|name| self.ifc.get_or_create_port(name.clone()))
+            .collect();
+        let (rate_tx, rate_rx) = mpsc::unbounded();
+        let block = Arc::new(Mutex::new(ClapBlock {
+            instance,
+            controls: controls.clone(),
+            sent: values,
+            clocked,
+            inputs: audio_in.iter().map(|port| Buffer::new(port.channels)).collect(),
+            outputs: audio_out.iter().map(|port| Buffer::new(port.channels)).collect(),
+            events: Vec::new(),
+            out_events: Vec::with_capacity(controls.ids.len()),
+            steady_time: 0,
+            rate_tx,
+            requested: None,
+        }));
+        self.ifc.set_params(controls.clone());
+        self.ifc.set_block(BlockNode {
+            inputs: inputs.iter().map(|port| port.id()).collect(),
+            outputs: outputs.iter().map(|port| port.id()).collect(),
+            block: block.clone(),
+        });
+        self.loaded = Some(Loaded {
+            path: path.to_path_buf(),
+            id: id.into(),
+            inputs,
+            outputs,
+            params: param_ports,
+            controls,
+            block,
+            rate_rx: Some(rate_rx),
+        });
+        Ok(())
+    }
+
+    /// Restore the plugin's own state, and take on the parameter values it implies.
+    fn restore(&self, data: &[u8]) {
+        let loaded = match self.loaded {
+            Some(ref loaded) => loaded,
+            None => return,
+        };
+        let mut block = loaded.block.lock().unwrap();
+        if !block.instance.load(data) {
+            println!("clap state err: {} didn't load its state", loaded.id);
+            return;
+        }
+        let values: Vec<f32> = loaded
+            .controls
+            .ids
+            .iter()
+            .zip(block.sent.iter())
+            .map(|(&id, &value)| {
+                block
+                    .instance
+                    .param_value(id)
+                    .map(|value| value as f32)
+                    .unwrap_or(value)
+            })
+            .collect();
+        *loaded.controls.values.lock().unwrap() = values.clone();
+        block.sent = values;
+    }
+}
+
+impl Module for ClapPlugin {
+    fn new(ifc: Arc<flow::Interface>) -> ClapPlugin {
+        ClapPlugin {
+            ifc,
+            breaker: Breaker::new(),
+            loaded: None,
+        }
+    }
+    fn name() -> &'static str {
+        "CLAP Plugin"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let loaded = match self.loaded {
+            Some(ref mut loaded) => loaded,
+            None => return,
+        };
+        let block = loaded.block.clone();
+        let id = loaded.id.clone();
+        let mut rate_rx = loaded.rate_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(rate) = rate_rx.next().await {
+                if !block.lock().unwrap().instance.activate(rate) {
+                    println!("clap err: {} didn't activate at {} Hz", id, rate);
+                }
+            }
+        })
+        .unwrap();
+        for (idx, port) in loaded.params.iter().enumerate() {
+            let controls = loaded.controls.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| controls.set_index(idx, value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+        start_blocks(
+            ClapPlugin::name(),
+            self.ifc.clone(),
+            loaded.inputs.clone(),
+            loaded.outputs.clone(),
+            loaded.block.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> Value {
+        let loaded = match self.loaded {
+            Some(ref loaded) => loaded,
+            None => return Value::Null,
+        };
+        let values = loaded.controls.values.lock().unwrap();
+        let params: serde_json::Map<String, Value> = loaded
+            .controls
+            .names
+            .iter()
+            .zip(values.iter())
+            .map(|(name, &value)| (name.clone(), json!(value)))
+            .collect();
+        let state = loaded
+            .block
+            .lock()
+            .unwrap()
+            .instance
+            .save()
+            .map(|data| to_hex(&data));
+        json!({
+            "path": loaded.path,
+            "id": loaded.id,
+            "params": params,
+            "state": state,
+        })
+    }
+    fn load_state(&mut self, state: Value) {
+        if self.loaded.is_some() {
+            println!("clap state err: a plugin is already loaded");
+            return;
+        }
+        let (path, id) = match (state["path"].as_str(), state["id"].as_str()) {
+            (Some(path), Some(id)) => (path, id),
+            _ => {
+                println!("clap state err: no path or id");
+                return;
+            }
+        };
+        if let Err(e) = self.load(Path::new(path), id) {
+            println!("clap load err: {}", e);
+            return;
+        }
+        if let Some(data) = state["state"].as_str().and_then(from_hex) {
+            self.restore(&data);
+        }
+        if let (Some(params), Some(loaded)) = (state["params"].as_object(), self.loaded.as_ref()) {
+            for (name, value) in params {
+                if let Some(value) = value.as_f64() {
+                    loaded.controls.set(name, value as f32);
+                }
+            }
+        }
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/// The directories searched for plugins: those in `CLAP_PATH`, then the standard ones.
+pub fn search_path() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = env::var_os("CLAP_PATH")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    if let Some(home) = env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".clap"));
+    }
+    dirs.push("/usr/lib/clap".into());
+    dirs
+}
+
+/// The `.clap` files in `dir` and the directories below it.
+fn find_bundles(dir: &Path, bundles: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.extension().map(|ext| ext == "clap").unwrap_or(false) {
+            bundles.push(path);
+        } else if path.is_dir() {
+            find_bundles(&path, bundles);
+        }
+    }
+}
+
+/// Register every plugin in the search path under its own name, in the `CLAP` category. Patches
+/// save them as `CLAP Plugin`s.
+pub fn add_installed(registry: &mut Registry) {
+    let mut paths = Vec::new();
+    for dir in search_path() {
+        find_bundles(&dir, &mut paths);
+    }
+    for path in paths {
+        let bundle = match open_bundle(&path) {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                println!("clap scan err: {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        for (id, name) in bundle.plugins() {
+            let state = json!({ "path": path, "id": id });
+            let factory = StatefulGuiModuleFactory::<ClapPlugin>::new(name, state);
+            registry.add_factory(Box::new(factory), "CLAP", "An installed CLAP plugin");
+        }
+    }
+}
+
+#[test]
+fn test_hex() {
+    let data = vec![0, 1, 0x7f, 0x80, 0xff];
+    assert_eq!(to_hex(&data), "00017f80ff");
+    assert_eq!(from_hex("00017f80ff"), Some(data));
+    assert_eq!(from_hex("0"), None);
+    assert_eq!(from_hex("zz"), None);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{component::*, event::*, geom::*, module_gui::*, render::*};
+struct ClapGui {
+    bounds: Box3,
+    id: String,
+}
+const PADDING: f32 = 4.0;
+impl ModuleGui for ClapPlugin {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        Box::new(ClapGui {
+            bounds,
+            id: self.plugin_id().unwrap_or("No plugin loaded").into(),
+        })
+    }
+}
+impl GuiComponent<bool> for ClapGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        ctx.draw_text(
+            &self.id,
+            self.bounds.pos + Pt3::new(PADDING, PADDING, 0.0),
+            [1.0, 1.0, 1.0],
+        );
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        false
+    }
+}
@@ -0,0 +1,121 @@
+//! Notes placed in a patch.
+//!
+//! A `Comment` has no ports and does nothing when running. Its text, which may be Markdown, is
+//! saved with the patch and mirrored into the node's `"comment"` metadata entry, so tools that
+//! only see the graph, like the RPC server, can show it too and follow edits through
+//! `Node::meta_changes`.
+
+use futures::task::Spawn;
+
+use crate::module::{flow, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+/// The metadata key holding a comment's text.
+pub const META_KEY: &str = "comment";
+
+pub struct Comment {
+    ifc: Arc<flow::Interface>,
+    text: Arc<Mutex<String>>,
+}
+
+impl Comment {
+    pub fn text(&self) -> String {
+        self.text.lock().unwrap().clone()
+    }
+    pub fn set_text(&self, text: String) {
+        set_text(&self.ifc, &self.text, text);
+    }
+}
+
+fn set_text(ifc: &flow::Interface, shared: &Mutex<String>, text: String) {
+    if let Some(node) = ifc.graph().node(ifc.id()) {
+        node.set_meta(META_KEY, &text);
+    }
+    *shared.lock().unwrap() = text;
+}
+
+impl Module for Comment {
+    fn new(ifc: Arc<flow::Interface>) -> Comment {
+        Comment {
+            ifc,
+            text: Arc::new(Mutex::new(String::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Comment"
+    }
+    fn start<Ex: Spawn>(&mut self, _exec: Ex) {}
+    fn stop(&mut self) {}
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        json!({ "text": self.text() })
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match state["text"].as_str() {
+            Some(text) => self.set_text(text.into()),
+            None => println!("comment state err: no text"),
+        }
+    }
+}
+
+#[test]
+fn test_comment() {
+    let graph = flow::Graph::new();
+    let ifc = graph.add_node();
+    let mut comment = Comment::new(ifc.clone());
+    comment.load_state(json!({ "text": "**Lead** voice" }));
+    assert_eq!(comment.save_state(), json!({ "text": "**Lead** voice" }));
+    let node = graph.node(ifc.id()).unwrap();
+    assert_eq!(node.meta::<String>(META_KEY), Some("**Lead** voice".to_string()));
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct CommentGui {
+    bounds: Box3,
+    ifc: Arc<flow::Interface>,
+    text: Arc<Mutex<String>>,
+    text_box: TextBox,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Comment {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING, 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(CommentGui {
+            bounds,
+            ifc: self.ifc.clone(),
+            text: self.text.clone(),
+            text_box: TextBox::new(ctx.clone(), self.text(), row),
+        })
+    }
+}
+impl GuiComponent<bool> for CommentGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.text_box.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        match self.text_box.handle(event) {
+            TextBoxUpdate::Unchanged => false,
+            TextBoxUpdate::NeedRender => true,
+            TextBoxUpdate::Modified => {
+                set_text(&self.ifc, &self.text, self.text_box.content().into());
+                true
+            }
+        }
+    }
+}
@@ -1,9 +1,7 @@
-use futures::executor;
-use futures::future;
-use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
 
-use future_ext::Breaker;
-use module::{flow, Module};
+use crate::future_ext::Breaker;
+use crate::module::{flow, Module};
 
 use num::{One, Zero};
 use std::ops::Add;
@@ -21,6 +19,11 @@ pub struct Printer<T: Debug + Send + Sync + 'static> {
 impl<T: Debug + Send + Sync + 'static> Module for Printer<T> {
     fn new(ifc: Arc<flow::Interface>) -> Printer<T> {
         let port = ifc.get_or_create_port::<T, usize>("Input".into());
+        port.set_meta(flow::PortMeta {
+            direction: Some(flow::Direction::Input),
+            required: true,
+            ..port.meta()
+        });
         Printer {
             ifc,
             port,
@@ -31,29 +34,26 @@ impl<T: Debug + Send + Sync + 'static> Module for Printer<T> {
     fn name() -> &'static str {
         "Printer"
     }
-    fn start<Ex: executor::Executor>(&mut self, mut exec: Ex) {
-        exec.spawn(Box::new(future::loop_fn(
-            (self.port.clone(), self.breaker.clone()),
-            |(port, breaker)| {
-                port.write1(1) // request 1 item
-                .and_then(|port| port.read1()) // read the item
-                .map(|(port, input)| {
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let port = self.port.clone();
+        let breaker = self.breaker.clone();
+        exec.spawn(async move {
+            loop {
+                let result = async {
+                    let port = port.clone().write1(1).await?; // request 1 item
+                    let (_port, input) = port.read1().await?; // read the item
                     println!("{:?}", input); // print it to console
-                    port
-                })
-                .recover(|(port, err)| {
+                    Ok(())
+                };
+                if let Err((_port, err)) = result.await {
                     println!("PErr {:?}", err);
-                    port
-                })
-                .map(|port| {
-                    if breaker.test() {
-                        future::Loop::Break(())
-                    } else {
-                        future::Loop::Continue((port, breaker))
-                    }
-                })
-            },
-        ))).unwrap();
+                }
+                if breaker.test() {
+                    break;
+                }
+            }
+        })
+        .unwrap();
     }
     fn stop(&mut self) {
         self.breaker.brake();
@@ -72,6 +72,10 @@ pub struct Counter<T: Copy + One + Zero + Add + Send + 'static> {
 impl<T: Copy + One + Zero + Add + Send + 'static> Module for Counter<T> {
     fn new(ifc: Arc<flow::Interface>) -> Counter<T> {
         let port = ifc.get_or_create_port::<usize, T>("Output".into());
+        port.set_meta(flow::PortMeta {
+            direction: Some(flow::Direction::Output),
+            ..port.meta()
+        });
         Counter {
             ifc,
             port,
@@ -82,35 +86,23 @@ impl<T: Copy + One + Zero + Add + Send + 'static> Module for Counter<T> {
     fn name() -> &'static str {
         "Counter"
     }
-    fn start<Ex: executor::Executor>(&mut self, mut exec: Ex) {
-        exec.spawn(Box::new(future::loop_fn(
-            (self.port.clone(), T::zero(), self.breaker.clone()),
-            |(port, mut count, breaker)| {
-                port.read1() // read n
-                    .and_then(move |(port, n)| {
-                        // increment the current value n times, writing each value
-                        port.write(
-                            (0..n)
-                                .map(|_| {
-                                    count = count + T::one();
-                                    count
-                                })
-                                .collect(),
-                        ).map(move |port| (port, count)) // pass the new counter along
-                    })
-                    .recover(move |(data, err)| {
-                        println!("CErr {:?}", err);
-                        (data, count) // on error, reset back to the previous count (captured via move keyword)
-                    })
-                    .map(|(port, count)| {
-                        if breaker.test() {
-                            future::Loop::Break(())
-                        } else {
-                            future::Loop::Continue((port, count, breaker))
-                        }
-                    })
-            },
-        ))).unwrap();
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let port = self.port.clone();
+        let breaker = self.breaker.clone();
+        exec.spawn(async move {
+            let mut count = T::zero();
+            loop {
+                match count_up(port.clone(), count).await {
+                    Ok(next) => count = next,
+                    // on error, stay at the previous count
+                    Err((_port, err)) => println!("CErr {:?}", err),
+                }
+                if breaker.test() {
+                    break;
+                }
+            }
+        })
+        .unwrap();
     }
     fn stop(&mut self) {
         self.breaker.brake();
@@ -119,3 +111,20 @@ impl<T: Copy + One + Zero + Add + Send + 'static> Module for Counter<T> {
         self.ifc.ports()
     }
 }
+
+/// Reads a request for `n` values, then writes the next `n` counts after `count`.
+async fn count_up<T: Copy + One + Zero + Add + Send + 'static>(
+    port: Arc<flow::Port<usize, T>>,
+    mut count: T,
+) -> Result<T, (Arc<flow::Port<usize, T>>, flow::Error)> {
+    let (port, n) = port.read1().await?; // read n
+    // increment the current value n times, writing each value
+    let values = (0..n)
+        .map(|_| {
+            count = count + T::one();
+            count
+        })
+        .collect();
+    port.write(values).await?;
+    Ok(count) // pass the new counter along
+}
@@ -0,0 +1,513 @@
+//! 2D vector drawing for visuals.
+//!
+//! A drawing is a short program of commands separated by `;` or newlines, drawn in a square
+//! running from -1.0 to 1.0 on both axes with up being positive:
+//!
+//! - `move x y` starts a new path, `line x y` extends it and `close` joins it back to its start.
+//! - `circle x y r` adds a closed circle to the path.
+//! - `stroke width [level]` draws the path's outline, `fill [level]` fills it by the even-odd
+//!   rule. Either one clears the path afterwards. The level defaults to 1.0.
+//!
+//! Any number can be given as `a`, `b`, `c` or `d` instead, taking the latest value from the
+//! matching input port, so audio analysis can move and scale the shapes. A program sent to
+//! `Commands` replaces the current one, which is how scripts stream new shapes in.
+//!
+//! Like the oscillators, `Input` frames only set the timing, and each one is answered with the
+//! drawing rasterized into a grayscale frame, one row per line of pixels.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::pool::FramePool;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use serde_json;
+
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// The input ports numbers can be taken from.
+pub const INPUTS: [&str; 4] = ["A", "B", "C", "D"];
+/// Segments in a full circle.
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operand {
+    Value(f32),
+    /// The latest value from one of `INPUTS`.
+    Input(usize),
+}
+
+impl Operand {
+    fn parse(s: &str) -> Option<Operand> {
+        match s {
+            "a" => Some(Operand::Input(0)),
+            "b" => Some(Operand::Input(1)),
+            "c" => Some(Operand::Input(2)),
+            "d" => Some(Operand::Input(3)),
+            _ => s.parse().ok().map(Operand::Value),
+        }
+    }
+    fn eval(&self, inputs: &[f32; 4]) -> f32 {
+        match *self {
+            Operand::Value(value) => value,
+            Operand::Input(idx) => inputs[idx],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    Move(Operand, Operand),
+    Line(Operand, Operand),
+    Close,
+    Circle(Operand, Operand, Operand),
+    /// Width and level.
+    Stroke(Operand, Operand),
+    /// Level.
+    Fill(Operand),
+}
+
+impl Command {
+    fn parse(s: &str) -> Option<Command> {
+        let mut words = s.split_whitespace();
+        let name = words.next()?;
+        let operands = words.map(Operand::parse).collect::<Option<Vec<_>>>()?;
+        let one = Operand::Value(1.0);
+        Some(match (name, &operands[..]) {
+            ("move", &[x, y]) => Command::Move(x, y),
+            ("line", &[x, y]) => Command::Line(x, y),
+            ("close", &[]) => Command::Close,
+            ("circle", &[x, y, r]) => Command::Circle(x, y, r),
+            ("stroke", &[width]) => Command::Stroke(width, one),
+            ("stroke", &[width, level]) => Command::Stroke(width, level),
+            ("fill", &[]) => Command::Fill(one),
+            ("fill", &[level]) => Command::Fill(level),
+            _ => return None,
+        })
+    }
+}
+
+/// Parse a drawing program, failing if any command is malformed.
+pub fn parse_program(s: &str) -> Option<Vec<Command>> {
+    s.split(|c| c == ';' || c == '\n')
+        .filter(|command| !command.trim().is_empty())
+        .map(Command::parse)
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DrawingConfig {
+    /// Size of the image in pixels.
+    pub width: usize,
+    pub height: usize,
+    pub program: String,
+}
+
+impl Default for DrawingConfig {
+    fn default() -> DrawingConfig {
+        DrawingConfig {
+            width: 128,
+            height: 128,
+            program: "circle 0 0 0.5; stroke 0.02".into(),
+        }
+    }
+}
+
+/// Parse an image size of the form `width height`.
+fn parse_size(s: &str) -> Option<(usize, usize)> {
+    let mut words = s.split_whitespace();
+    let width: usize = words.next()?.parse().ok()?;
+    let height: usize = words.next()?.parse().ok()?;
+    if width == 0 || height == 0 || words.next().is_some() {
+        return None;
+    }
+    Some((width, height))
+}
+
+/// A path's points, split into subpaths which are all treated as closed when filling.
+#[derive(Default)]
+struct Path {
+    subpaths: Vec<Vec<(f32, f32)>>,
+    /// Which subpaths were closed, and so get their closing edge stroked.
+    closed: Vec<bool>,
+}
+
+impl Path {
+    fn move_to(&mut self, point: (f32, f32)) {
+        self.subpaths.push(vec![point]);
+        self.closed.push(false);
+    }
+    fn line_to(&mut self, point: (f32, f32)) {
+        match self.subpaths.last_mut() {
+            Some(subpath) => subpath.push(point),
+            None => self.move_to(point),
+        }
+    }
+    fn close(&mut self) {
+        if let Some(closed) = self.closed.last_mut() {
+            *closed = true;
+        }
+    }
+    /// Every edge, including the closing edges of subpaths when `all_closed` or closed.
+    fn edges(&self, all_closed: bool) -> Vec<((f32, f32), (f32, f32))> {
+        let mut edges = Vec::new();
+        for (subpath, &closed) in self.subpaths.iter().zip(&self.closed) {
+            edges.extend(subpath.windows(2).map(|pair| (pair[0], pair[1])));
+            if (closed || all_closed) && subpath.len() > 2 {
+                edges.push((subpath[subpath.len() - 1], subpath[0]));
+            }
+        }
+        edges
+    }
+}
+
+/// Distance from `p` to the segment from `a` to `b`.
+fn segment_distance(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).max(0.0).min(1.0)
+    } else {
+        0.0
+    };
+    (p.0 - a.0 - t * dx).hypot(p.1 - a.1 - t * dy)
+}
+
+pub struct Drawing {
+    config: DrawingConfig,
+    program: Vec<Command>,
+    inputs: [f32; 4],
+}
+
+impl Drawing {
+    pub fn new(config: DrawingConfig) -> Drawing {
+        let default = DrawingConfig::default();
+        let mut drawing = Drawing {
+            program: parse_program(&default.program).unwrap(),
+            config: default,
+            inputs: [0.0; 4],
+        };
+        drawing.set_config(config);
+        drawing
+    }
+    pub fn config(&self) -> DrawingConfig {
+        self.config.clone()
+    }
+    /// Take a new config, keeping the current program if the new one doesn't parse.
+    pub fn set_config(&mut self, config: DrawingConfig) {
+        if !self.set_program(&config.program) {
+            println!("drawing: invalid program {:?}", config.program);
+        }
+        self.config.width = config.width;
+        self.config.height = config.height;
+    }
+    /// Replace the program, returning whether it parsed.
+    pub fn set_program(&mut self, program: &str) -> bool {
+        match parse_program(program) {
+            Some(commands) => {
+                self.program = commands;
+                self.config.program = program.into();
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn set_input(&mut self, idx: usize, value: f32) {
+        self.inputs[idx] = value;
+    }
+    /// Run the program, drawing into a `height` by `width` frame. Up is the first row.
+    pub fn render(&self, rate: f32, time: Option<u64>, pool: &FramePool) -> Frame {
+        let (width, height) = (self.config.width, self.config.height);
+        let mut out = pool.zeros(rate, time, (height, width));
+        // the center of each pixel, in drawing coordinates
+        let pixel_size = 2.0 / width.min(height) as f32;
+        let x = |column: usize| (column as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+        let y = |row: usize| 1.0 - (row as f32 + 0.5) / height as f32 * 2.0;
+
+        let mut path = Path::default();
+        let point = |x: Operand, y: Operand| (x.eval(&self.inputs), y.eval(&self.inputs));
+        for &command in &self.program {
+            match command {
+                Command::Move(px, py) => path.move_to(point(px, py)),
+                Command::Line(px, py) => path.line_to(point(px, py)),
+                Command::Close => path.close(),
+                Command::Circle(cx, cy, r) => {
+                    let (cx, cy) = point(cx, cy);
+                    let r = r.eval(&self.inputs);
+                    path.move_to((cx + r, cy));
+                    for i in 1..CIRCLE_SEGMENTS {
+                        let angle = 2.0 * PI * i as f32 / CIRCLE_SEGMENTS as f32;
+                        path.line_to((cx + r * angle.cos(), cy + r * angle.sin()));
+                    }
+                    path.close();
+                }
+                Command::Stroke(stroke_width, level) => {
+                    let half = stroke_width.eval(&self.inputs).abs() / 2.0;
+                    let level = level.eval(&self.inputs);
+                    let edges = path.edges(false);
+                    for ((row, column), pixel) in out.data.indexed_iter_mut() {
+                        let p = (x(column), y(row));
+                        let distance = edges.iter().fold(::std::f32::INFINITY, |d, &(a, b)| {
+                            d.min(segment_distance(p, a, b))
+                        });
+                        // fade over one pixel at the edges to soften the stairs
+                        let coverage = ((half - distance) / pixel_size + 0.5).max(0.0).min(1.0);
+                        *pixel += (level - *pixel) * coverage;
+                    }
+                    path = Path::default();
+                }
+                Command::Fill(level) => {
+                    let level = level.eval(&self.inputs);
+                    let edges = path.edges(true);
+                    for ((row, column), pixel) in out.data.indexed_iter_mut() {
+                        let (px, py) = (x(column), y(row));
+                        let crossings = edges
+                            .iter()
+                            .filter(|&&(a, b)| {
+                                (a.1 > py) != (b.1 > py) && px < a.0 + (py - a.1) / (b.1 - a.1) * (b.0 - a.0)
+                            })
+                            .count();
+                        if crossings % 2 == 1 {
+                            *pixel = level;
+                        }
+                    }
+                    path = Path::default();
+                }
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Resize(usize, usize),
+    Program(String),
+}
+
+pub struct Draw {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    commands_port: Arc<flow::Port<String, ()>>,
+    input_ports: Vec<Arc<flow::Port<f32, ()>>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    drawing: Arc<Mutex<Drawing>>,
+}
+
+/// Declare the image width of `config` on the output.
+fn declare_width(port: &flow::Port<(), Frame>, config: &DrawingConfig) {
+    port.set_meta(flow::PortMeta {
+        channels: Some(config.width),
+        ..port.meta()
+    });
+}
+
+impl Module for Draw {
+    fn new(ifc: Arc<flow::Interface>) -> Draw {
+        let out_port = ifc.get_or_create_port("Output".into());
+        let config = DrawingConfig::default();
+        declare_width(&out_port, &config);
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Draw {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            commands_port: ifc.get_or_create_port("Commands".into()),
+            input_ports: INPUTS
+                .iter()
+                .map(|name| ifc.get_or_create_port(name.to_string()))
+                .collect(),
+            out_port,
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            drawing: Arc::new(Mutex::new(Drawing::new(config))),
+        }
+    }
+    fn name() -> &'static str {
+        "Draw"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let drawing = self.drawing.clone();
+        let out_port = self.out_port.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                let mut drawing = drawing.lock().unwrap();
+                match cmd {
+                    UserCommand::Resize(width, height) => {
+                        let config = DrawingConfig {
+                            width,
+                            height,
+                            ..drawing.config()
+                        };
+                        declare_width(&out_port, &config);
+                        drawing.set_config(config);
+                    }
+                    UserCommand::Program(program) => {
+                        drawing.set_program(&program);
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let drawing = self.drawing.clone();
+        util::start_sink(
+            self.commands_port.clone(),
+            move |program: String| {
+                if !drawing.lock().unwrap().set_program(&program) {
+                    println!("drawing: invalid program {:?}", program);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        for (idx, port) in self.input_ports.iter().enumerate() {
+            let drawing = self.drawing.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| drawing.lock().unwrap().set_input(idx, value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let drawing = self.drawing.clone();
+        let pool = self.ifc.graph().pool();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                // one image per input frame
+                let rate = frame.rate / frame.data.dim().0.max(1) as f32;
+                let image = drawing.lock().unwrap().render(rate, frame.time, &pool);
+                pool.recycle(frame);
+                image
+            },
+            self.ifc.clone(),
+            self.clock_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.drawing.lock().unwrap().config()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(config) => {
+                declare_width(&self.out_port, &config);
+                self.drawing.lock().unwrap().set_config(config);
+            }
+            Err(e) => println!("draw state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_draw() {
+    let pool = FramePool::new();
+    assert!(parse_program("move 0 0; line 1 x").is_none());
+    assert!(parse_program("fill 1 2").is_none());
+
+    let mut drawing = Drawing::new(DrawingConfig {
+        width: 20,
+        height: 20,
+        program: "move -0.5 -0.5; line 0.5 -0.5; line 0.5 a; line -0.5 a; fill b".into(),
+    });
+    drawing.set_input(0, 0.5);
+    drawing.set_input(1, 0.75);
+    let image = drawing.render(30.0, None, &pool);
+    assert_eq!(image.data.dim(), (20, 20));
+    // the middle half is filled, the rest is empty
+    assert_eq!(image.data[[10, 10]], 0.75);
+    assert_eq!(image.data[[2, 10]], 0.0);
+    assert_eq!(image.data.iter().filter(|&&x| x > 0.0).count(), 100);
+    // moving the top edge with an input changes the shape
+    drawing.set_input(0, 0.0);
+    let image = drawing.render(30.0, None, &pool);
+    assert_eq!(image.data.iter().filter(|&&x| x > 0.0).count(), 50);
+
+    assert!(drawing.set_program("circle 0 0 0.5\nstroke 0.2"));
+    assert!(!drawing.set_program("circle 0 0"));
+    let image = drawing.render(30.0, None, &pool);
+    // the ring is drawn, its middle and the corners are not
+    assert_eq!(image.data[[10, 14]], 1.0);
+    assert_eq!(image.data[[10, 10]], 0.0);
+    assert_eq!(image.data[[0, 0]], 0.0);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct DrawGui {
+    bounds: Box3,
+    size_box: TextBox,
+    program_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Draw {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = self.drawing.lock().unwrap().config();
+        let size = format!("{} {}", config.width, config.height);
+        Box::new(DrawGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            size_box: TextBox::new(ctx.clone(), size, row(0.0)),
+            program_box: TextBox::new(ctx.clone(), config.program, row(1.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(2.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for DrawGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.size_box.render(device, ctx);
+        self.program_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.size_box.handle(event) != TextBoxUpdate::Unchanged;
+        let dirty = self.program_box.handle(event) != TextBoxUpdate::Unchanged || dirty;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let size = parse_size(self.size_box.content());
+                let program = self.program_box.content().to_string();
+                match (size, parse_program(&program)) {
+                    (Some((width, height)), Some(_)) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::Resize(width, height)).unwrap();
+                        self.cmd_tx.unbounded_send(UserCommand::Program(program)).unwrap();
+                    }
+                    (None, _) => self.apply_button.set_label("Invalid: width height".into()),
+                    (_, None) => self.apply_button.set_label("Invalid program".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! Compressor and noise gate, with an optional side-chain, and an envelope follower.
+//!
+//! The detector follows the `Side Chain` input when one is connected, and the main input otherwise.
+//! Pair it with a `Tap` to key the dynamics from a signal that is also patched somewhere else, e.g.
+//! ducking a pad under the kick drum. `Follower` sends the envelope of its input as a control
+//! value once per frame, to modulate any parameter with the level of a sound.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Axis;
+
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// Reduce the gain above the threshold by the given ratio.
+    Compress(f32),
+    /// Silence everything below the threshold.
+    Gate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DynamicsConfig {
+    pub mode: Mode,
+    pub threshold_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl DynamicsConfig {
+    /// Parse a config of the form `compress <threshold dB> <ratio> [attack ms [release ms]]` or
+    /// `gate <threshold dB> [attack ms [release ms]]`.
+    pub fn parse(s: &str) -> Option<DynamicsConfig> {
+        let mut words = s.split_whitespace();
+        let kind = words.next()?;
+        let threshold_db = words.next()?.parse().ok()?;
+        let mode = match kind {
+            "compress" => match words.next()?.parse().ok()? {
+                ratio if ratio >= 1.0 => Mode::Compress(ratio),
+                _ => return None,
+            },
+            "gate" => Mode::Gate,
+            _ => return None,
+        };
+        let attack_ms = words.next().map(|w| w.parse().ok()).unwrap_or(Some(5.0))?;
+        let release_ms = words.next().map(|w| w.parse().ok()).unwrap_or(Some(100.0))?;
+        if attack_ms < 0.0 || release_ms < 0.0 {
+            return None;
+        }
+        Some(DynamicsConfig {
+            mode,
+            threshold_db,
+            attack_ms,
+            release_ms,
+        })
+    }
+}
+
+impl Default for DynamicsConfig {
+    fn default() -> DynamicsConfig {
+        DynamicsConfig {
+            mode: Mode::Compress(4.0),
+            threshold_db: -20.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+        }
+    }
+}
+
+/// One pole smoothing coefficient reaching ~63% of a step after `ms`.
+fn coefficient(ms: f32, rate: f32) -> f32 {
+    if ms <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (ms * 0.001 * rate)).exp()
+    }
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.max(1e-6).log10()
+}
+
+fn from_db(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// The gain computer and envelope state.
+pub struct Dynamics {
+    config: DynamicsConfig,
+    envelope: f32,
+    gain: f32,
+}
+
+impl Dynamics {
+    pub fn new(config: DynamicsConfig) -> Dynamics {
+        Dynamics {
+            config,
+            envelope: 0.0,
+            gain: 1.0,
+        }
+    }
+    pub fn set_config(&mut self, config: DynamicsConfig) {
+        self.config = config;
+    }
+    /// Apply the gain to `frame`, detecting levels on `key` if given. A key frame of a different
+    /// length is stretched over the main frame.
+    pub fn process(&mut self, mut frame: Frame, key: Option<&Frame>) -> Frame {
+        let attack = coefficient(self.config.attack_ms, frame.rate);
+        let release = coefficient(self.config.release_ms, frame.rate);
+        let detector = key.unwrap_or(&frame).data.clone();
+        let len = frame.data.len_of(Axis(0));
+        let key_len = detector.len_of(Axis(0));
+        for (i, mut samples) in frame.data.axis_iter_mut(Axis(0)).enumerate() {
+            let level = if key_len == 0 {
+                0.0
+            } else {
+                let row = detector.subview(Axis(0), i * key_len / len);
+                row.iter().fold(0.0f32, |max, sample| max.max(sample.abs()))
+            };
+            // peak envelope follower
+            let coeff = if level > self.envelope { attack } else { release };
+            self.envelope = level + coeff * (self.envelope - level);
+
+            let level_db = to_db(self.envelope);
+            let target = match self.config.mode {
+                Mode::Compress(ratio) if level_db > self.config.threshold_db => {
+                    from_db((self.config.threshold_db - level_db) * (1.0 - 1.0 / ratio))
+                }
+                Mode::Gate if level_db < self.config.threshold_db => 0.0,
+                _ => 1.0,
+            };
+            // gain reduction engages at the attack rate and recovers at the release rate
+            let coeff = if target < self.gain { attack } else { release };
+            self.gain = target + coeff * (self.gain - target);
+            for sample in samples.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+        frame
+    }
+}
+
+/// Rectifies a signal and smooths it, rising at the attack rate and falling at the release rate.
+pub struct EnvelopeFollower {
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    envelope: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new() -> EnvelopeFollower {
+        EnvelopeFollower {
+            attack_ms: 5.0,
+            release_ms: 100.0,
+            envelope: 0.0,
+        }
+    }
+    /// Follow the peaks of all channels of `frame`, returning the envelope at its end.
+    pub fn process(&mut self, frame: &Frame) -> f32 {
+        let attack = coefficient(self.attack_ms, frame.rate);
+        let release = coefficient(self.release_ms, frame.rate);
+        for samples in frame.data.axis_iter(Axis(0)) {
+            let level = samples.iter().fold(0.0f32, |max, sample| max.max(sample.abs()));
+            let coeff = if level > self.envelope { attack } else { release };
+            self.envelope = level + coeff * (self.envelope - level);
+        }
+        self.envelope
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Configure(DynamicsConfig),
+}
+
+pub struct DynamicsModule {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    key_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    dynamics: Arc<Mutex<Dynamics>>,
+}
+
+impl Module for DynamicsModule {
+    fn new(ifc: Arc<flow::Interface>) -> DynamicsModule {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let key_port = ifc.get_or_create_port("Side Chain".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        DynamicsModule {
+            ifc,
+            in_port,
+            key_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            dynamics: Arc::new(Mutex::new(Dynamics::new(DynamicsConfig::default()))),
+        }
+    }
+    fn name() -> &'static str {
+        "Dynamics"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let dynamics = self.dynamics.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Configure(config) => dynamics.lock().unwrap().set_config(config),
+                }
+            }
+        })
+        .unwrap();
+
+        // the side chain runs at its own pace, keep its latest frame for the detector
+        let key = Arc::new(Mutex::new(None));
+        let key_handle = key.clone();
+        util::start_sink(
+            self.key_port.clone(),
+            move |frame: Frame| *key_handle.lock().unwrap() = Some(frame),
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let dynamics = self.dynamics.clone();
+        let key_port = self.key_port.clone();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                let key = if key_port.edge().is_some() {
+                    key.lock().unwrap().take()
+                } else {
+                    None
+                };
+                dynamics.lock().unwrap().process(frame, key.as_ref())
+            },
+            self.ifc.clone(),
+            self.in_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Sends the envelope of its audio input, with `Attack` and `Release` times in milliseconds.
+pub struct Follower {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    attack_port: Arc<flow::Port<f32, ()>>,
+    release_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    follower: Arc<Mutex<EnvelopeFollower>>,
+}
+
+impl Module for Follower {
+    fn new(ifc: Arc<flow::Interface>) -> Follower {
+        Follower {
+            in_port: ifc.get_or_create_port("Input".into()),
+            attack_port: ifc.get_or_create_port("Attack".into()),
+            release_port: ifc.get_or_create_port("Release".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            follower: Arc::new(Mutex::new(EnvelopeFollower::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Envelope Follower"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let follower = self.follower.clone();
+        util::start_sink(
+            self.attack_port.clone(),
+            move |ms: f32| follower.lock().unwrap().attack_ms = ms,
+            self.breaker.clone(),
+            &exec,
+        );
+        let follower = self.follower.clone();
+        util::start_sink(
+            self.release_port.clone(),
+            move |ms: f32| follower.lock().unwrap().release_ms = ms,
+            self.breaker.clone(),
+            &exec,
+        );
+        // values are dropped while nothing reads them, so the envelope never lags behind
+        let (mut value_tx, value_rx) = mpsc::channel(1);
+        let follower = self.follower.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |frame: Frame| {
+                let _ = value_tx.try_send(follower.lock().unwrap().process(&frame));
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(value_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_dynamics() {
+    use ndarray::Array2;
+
+    let frame = |value: f32| Frame {
+        rate: 1000.0,
+        time: None,
+        data: Array2::from_elem((100, 2), value),
+        meta: None,
+    };
+    let config = DynamicsConfig::parse("compress -20 4 0 0").unwrap();
+    assert_eq!(config.mode, Mode::Compress(4.0));
+    // 0 dB in, 20 dB over the threshold, reduced to 5 dB over
+    let out = Dynamics::new(config).process(frame(1.0), None);
+    assert!((to_db(out.data[[99, 0]]) - -15.0).abs() < 0.01);
+
+    // a loud key ducks a quiet signal which wouldn't trigger on its own
+    let mut dynamics = Dynamics::new(config);
+    let out = dynamics.process(frame(0.01), Some(&frame(1.0)));
+    assert!((to_db(out.data[[99, 1]]) - -55.0).abs() < 0.01);
+
+    let mut gate = Dynamics::new(DynamicsConfig::parse("gate -40 0 0").unwrap());
+    assert_eq!(gate.process(frame(0.001), None).data[[99, 0]], 0.0);
+    assert_eq!(gate.process(frame(0.5), None).data[[99, 0]], 0.5);
+    assert!(DynamicsConfig::parse("compress -20 0.5").is_none());
+}
+
+#[test]
+fn test_envelope_follower() {
+    use ndarray::Array2;
+
+    let frame = |value: f32| Frame {
+        rate: 1000.0,
+        time: None,
+        data: Array2::from_elem((10, 2), value),
+        meta: None,
+    };
+    let mut follower = EnvelopeFollower::new();
+    follower.attack_ms = 0.0;
+    follower.release_ms = 10.0;
+    // negative samples are rectified
+    assert_eq!(follower.process(&frame(-0.5)), 0.5);
+    // falls to 1/e of its level over the release time
+    let envelope = follower.process(&frame(0.0));
+    assert!((envelope - 0.5 * (-1.0f32).exp()).abs() < 1e-5);
+    assert_eq!(follower.process(&frame(1.0)), 1.0);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct DynamicsGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for DynamicsModule {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(DynamicsGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), "compress -20 4 5 100".into(), row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for DynamicsGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match DynamicsConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::Configure(config)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: mode dB [ratio] [atk rel]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! Searching for sounds by evolving the params of a patch.
+//!
+//! An `Evolution` keeps a population of param sets for the params a `randomize::Scope` covers,
+//! starting from the patch as it is and random variations of it. Each generation renders every
+//! set offline, the way `golden::render_graph_to_vec` does, with an input if the config has one,
+//! scores what reaches the host with a fitness function, keeps the best and breeds the rest from
+//! them, with crossovers and the mutations of `module::randomize`. Locked params and params
+//! without a range are left alone.
+//!
+//! The fitness function sees the rendered frames, and can be anything from a comparison with a
+//! recording to a model's opinion. `Targets` scores the audio features of `Features` instead,
+//! for the common case of looking for something about this loud and this bright:
+//!
+//! ```ignore
+//! let graph = flow::Graph::new();
+//! let (host_in, host_out) = golden::add_host(&graph);
+//! // ... build the patch, connected to host_in
+//! let targets = Targets { centroid: Some(2000.0), ..Targets::default() };
+//! let mut evolution = Evolution::new(graph, Config::default());
+//! evolution.run(&mut |frames: &[Frame]| targets.score(&Features::of(frames)));
+//! ```
+//!
+//! The graph is played with the params of each set in turn, so it has to be one that's not
+//! playing, like a copy of the patch loaded for the search. It's left with the best set found.
+
+use crate::module::audio_io::Frame;
+use crate::module::flow::{Graph, NodeId};
+use crate::module::golden::{self, RATE};
+use crate::module::randomize::{self, Candidate, Rng, Scope};
+
+use num::complex::Complex32;
+
+use std::sync::Arc;
+
+/// Samples of each spectrum taken for the centroid.
+const SPECTRUM: usize = 1024;
+
+#[derive(Clone)]
+pub struct Config {
+    pub scope: Scope,
+    /// Sets of params alive at a time.
+    pub population: usize,
+    /// The best sets carried over unchanged to the next generation.
+    pub survivors: usize,
+    pub generations: usize,
+    /// Blocks rendered before scoring, for params to settle after they're set.
+    pub settle: usize,
+    /// Blocks rendered for scoring.
+    pub blocks: usize,
+    /// Played on the host's output for every block, for evolving effects. Silence if `None`.
+    pub input: Option<Frame>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            scope: Scope::All,
+            population: 16,
+            survivors: 4,
+            generations: 20,
+            settle: 8,
+            // about a second at the rate of `golden`
+            blocks: 750,
+            input: None,
+        }
+    }
+}
+
+/// A set of params, in the order of the evolution's candidates, and its score.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Individual {
+    pub values: Vec<f32>,
+    /// `None` until it's rendered.
+    pub score: Option<f32>,
+}
+
+pub struct Evolution {
+    graph: Arc<Graph>,
+    config: Config,
+    candidates: Vec<Candidate>,
+    population: Vec<Individual>,
+    generation: usize,
+    rng: Rng,
+}
+
+impl Evolution {
+    /// Start from the params of `graph` and random variations of them.
+    pub fn new(graph: Arc<Graph>, config: Config) -> Evolution {
+        Evolution::with_rng(graph, config, Rng::from_time())
+    }
+    /// Like `new`, with the random numbers of `rng`, to repeat a search.
+    pub fn with_rng(graph: Arc<Graph>, config: Config, mut rng: Rng) -> Evolution {
+        let candidates = randomize::candidates(&graph, &config.scope);
+        let current: Vec<_> = candidates.iter().map(|candidate| candidate.value).collect();
+        let mut population = vec![Individual {
+            values: current,
+            score: None,
+        }];
+        while population.len() < config.population.max(1) {
+            let values = randomize::randomize(&candidates, 1.0, &mut rng)
+                .into_iter()
+                .map(|(_, _, value)| value)
+                .collect();
+            population.push(Individual { values, score: None });
+        }
+        Evolution {
+            graph,
+            config,
+            candidates,
+            population,
+            generation: 0,
+            rng,
+        }
+    }
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+    /// The population, best first once it's scored.
+    pub fn population(&self) -> &[Individual] {
+        &self.population
+    }
+    /// The params `individual` sets, by node.
+    pub fn params(&self, individual: &Individual) -> Vec<(NodeId, String, f32)> {
+        self.candidates
+            .iter()
+            .zip(&individual.values)
+            .map(|(candidate, &value)| (candidate.node, candidate.param.clone(), value))
+            .collect()
+    }
+    /// Score every set not scored yet, and sort the population by score.
+    pub fn score(&mut self, fitness: &mut dyn FnMut(&[Frame]) -> f32) {
+        for idx in 0..self.population.len() {
+            if self.population[idx].score.is_some() {
+                continue;
+            }
+            let frames = self.render(&self.population[idx]);
+            // a NaN would break the order, and probably comes from a patch blowing up
+            let score = fitness(&frames);
+            self.population[idx].score = Some(if score.is_nan() { ::std::f32::MIN } else { score });
+        }
+        self.population
+            .sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    }
+    /// Score the population and breed the next generation from the best of it.
+    pub fn step(&mut self, fitness: &mut dyn FnMut(&[Frame]) -> f32) {
+        self.score(fitness);
+        let survivors = self.config.survivors.max(1).min(self.population.len());
+        self.population.truncate(survivors);
+        while self.population.len() < self.config.population.max(1) {
+            let child = self.breed(survivors);
+            self.population.push(child);
+        }
+        self.generation += 1;
+    }
+    /// Run every generation of the config, leave the graph with the best set found and give it.
+    pub fn run(&mut self, fitness: &mut dyn FnMut(&[Frame]) -> f32) -> Individual {
+        for _ in 0..self.config.generations {
+            self.step(fitness);
+        }
+        self.score(fitness);
+        let best = self.population[0].clone();
+        self.apply(&best);
+        best
+    }
+    /// Set the params of the graph to those of `individual`.
+    pub fn apply(&self, individual: &Individual) {
+        for (id, param, value) in self.params(individual) {
+            if let Some(params) = self.graph.node(id).and_then(|node| node.params()) {
+                params.set(&param, value);
+            }
+        }
+    }
+    fn render(&self, individual: &Individual) -> Vec<Frame> {
+        self.apply(individual);
+        let blocks = self.config.settle + self.config.blocks;
+        let mut frames = match self.config.input {
+            Some(ref input) => golden::render_with_capture(&self.graph, blocks, input.clone()),
+            None => golden::render_graph_to_vec(&self.graph, blocks),
+        };
+        frames.split_off(self.config.settle.min(frames.len()))
+    }
+    /// A child of two of the first `parents` sets, each param from either, and mutated.
+    fn breed(&mut self, parents: usize) -> Individual {
+        let a = (self.rng.next() * parents as f32) as usize;
+        let b = (self.rng.next() * parents as f32) as usize;
+        let (a, b) = (
+            &self.population[a.min(parents - 1)],
+            &self.population[b.min(parents - 1)],
+        );
+        let mut values: Vec<f32> = {
+            let rng = &mut self.rng;
+            a.values
+                .iter()
+                .zip(&b.values)
+                .map(|(&a, &b)| if rng.next() < 0.5 { a } else { b })
+                .collect()
+        };
+        let child: Vec<_> = self
+            .candidates
+            .iter()
+            .zip(&values)
+            .map(|(candidate, &value)| Candidate {
+                value,
+                ..candidate.clone()
+            })
+            .collect();
+        for (node, param, value) in randomize::mutate(&child, &mut self.rng) {
+            if let Some(idx) = child.iter().position(|c| c.node == node && c.param == param) {
+                values[idx] = value;
+            }
+        }
+        Individual { values, score: None }
+    }
+}
+
+/// Audio features of rendered frames, averaged over their channels.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Features {
+    pub rms: f32,
+    pub peak: f32,
+    /// The spectral centroid in Hz, where the energy of the sound is centered, for brightness.
+    pub centroid: f32,
+    /// Sign changes per second, for noisiness.
+    pub zero_crossings: f32,
+}
+
+impl Features {
+    pub fn of(frames: &[Frame]) -> Features {
+        let rate = frames.first().map_or(RATE, |frame| frame.rate);
+        let mono: Vec<f32> = frames
+            .iter()
+            .flat_map(|frame| {
+                frame
+                    .data
+                    .outer_iter()
+                    .map(|row| row.iter().sum::<f32>() / row.len().max(1) as f32)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        if mono.is_empty() {
+            return Features::default();
+        }
+        let rms = (mono.iter().map(|x| x * x).sum::<f32>() / mono.len() as f32).sqrt();
+        let peak = mono.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+        let crossings = mono
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        Features {
+            rms,
+            peak,
+            centroid: centroid(&mono, rate),
+            zero_crossings: crossings as f32 * rate / mono.len() as f32,
+        }
+    }
+}
+
+/// The spectral centroid of `samples` over spectra of `SPECTRUM` samples, weighted by magnitude.
+fn centroid(samples: &[f32], rate: f32) -> f32 {
+    let (mut weighted, mut total) = (0.0, 0.0);
+    for chunk in samples.chunks(SPECTRUM) {
+        let mut spectrum: Vec<_> = chunk.iter().map(|&x| Complex32::new(x, 0.0)).collect();
+        spectrum.resize(SPECTRUM, Complex32::new(0.0, 0.0));
+        crate::module::fft::fft(&mut spectrum, false);
+        for (bin, value) in spectrum[..SPECTRUM / 2].iter().enumerate() {
+            let magnitude = value.norm();
+            weighted += magnitude * bin as f32 * rate / SPECTRUM as f32;
+            total += magnitude;
+        }
+    }
+    if total > 0.0 {
+        weighted / total
+    } else {
+        0.0
+    }
+}
+
+/// Features to look for, scoring higher the closer a sound comes to all of them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Targets {
+    pub rms: Option<f32>,
+    pub peak: Option<f32>,
+    pub centroid: Option<f32>,
+    pub zero_crossings: Option<f32>,
+}
+
+impl Targets {
+    /// Minus the sum of the distances to the targets, relative to them, so each counts the same
+    /// whatever its scale. `0.0` is a perfect match.
+    pub fn score(&self, features: &Features) -> f32 {
+        let pairs = [
+            (self.rms, features.rms),
+            (self.peak, features.peak),
+            (self.centroid, features.centroid),
+            (self.zero_crossings, features.zero_crossings),
+        ];
+        -pairs
+            .iter()
+            .filter_map(|&(target, actual)| {
+                let target = target?;
+                Some((actual - target).abs() / target.abs().max(1e-6))
+            })
+            .sum::<f32>()
+    }
+}
+
+#[test]
+fn test_evolve() {
+    use crate::module::mix::Gain;
+    use crate::module::process::Processor;
+    use crate::module::Module;
+    use ndarray::Array2;
+
+    let sine = |freq: f32| Frame {
+        rate: RATE,
+        time: Some(0),
+        data: Array2::from_shape_fn((4096, 1), |(i, _)| {
+            (i as f32 * freq / RATE * 2.0 * ::std::f32::consts::PI).sin()
+        }),
+        meta: None,
+    };
+    let features = Features::of(&[sine(1500.0)]);
+    assert!((features.rms - 0.5f32.sqrt()).abs() < 0.01);
+    assert!((features.centroid - 1500.0).abs() < 200.0);
+    assert!((features.zero_crossings - 3000.0).abs() < 30.0);
+    assert_eq!(Features::of(&[]), Features::default());
+
+    // evolve a gain towards passing on half of a constant signal
+    let graph = Graph::new();
+    let (host_in, host_out) = golden::add_host(&graph);
+    let node = graph.add_node();
+    let _gain = Processor::<Gain>::new(node.clone());
+    host_out
+        .connect(&node.get_or_create_port("Input".into()))
+        .unwrap();
+    node.get_or_create_port::<(), Frame>("Output".into())
+        .connect(&host_in)
+        .unwrap();
+    let config = Config {
+        population: 8,
+        survivors: 2,
+        generations: 10,
+        settle: 8,
+        blocks: 1,
+        input: Some(Frame {
+            rate: RATE,
+            time: Some(0),
+            data: Array2::from_elem((golden::BLOCK_SIZE, golden::CHANNELS), 1.0),
+            meta: None,
+        }),
+        ..Config::default()
+    };
+    let mut evolution = Evolution::with_rng(graph.clone(), config, Rng(7));
+    assert_eq!(evolution.population().len(), 8);
+    let best = evolution.run(&mut |frames: &[Frame]| -(frames[0].data[[0, 0]] - 0.5).abs());
+    assert_eq!(evolution.generation(), 10);
+    assert!(best.score.unwrap() > -0.1);
+    let gain = graph
+        .node(node.id())
+        .unwrap()
+        .params()
+        .unwrap()
+        .get("Gain")
+        .unwrap();
+    assert!((gain - 0.5).abs() < 0.1);
+}
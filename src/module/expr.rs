@@ -0,0 +1,650 @@
+//! Math expressions over control values, like Pd's `[expr]`.
+//!
+//! `Expr` compiles an expression such as `sin(a * 2) + b * 0.5` and gives it an input port for
+//! every variable it names. Whenever a value arrives on one of them, the expression is evaluated
+//! with the latest value of each variable, starting at 0.0, and the result is sent on `Output`.
+//! A new expression sent to `Expression` or applied in the GUI is compiled on the spot, adding
+//! ports for new variables and removing unconnected ports that are no longer used.
+//!
+//! Expressions have `+ - * / %`, `^` for powers, comparisons giving 1.0 or 0.0, parentheses, the
+//! constant `pi` and the functions `sin cos tan abs sqrt exp log floor ceil min max pow`.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::select;
+use futures::stream::FuturesUnordered;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{flow, util, Module};
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Op {
+    fn apply(self, a: f32, b: f32) -> f32 {
+        let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+        match self {
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+            Op::Rem => a % b,
+            Op::Pow => a.powf(b),
+            Op::Less => truth(a < b),
+            Op::Greater => truth(a > b),
+            Op::LessEqual => truth(a <= b),
+            Op::GreaterEqual => truth(a >= b),
+            Op::Equal => truth(a == b),
+            Op::NotEqual => truth(a != b),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Abs,
+    Sqrt,
+    Exp,
+    Log,
+    Floor,
+    Ceil,
+    Min,
+    Max,
+    Pow,
+}
+
+impl Function {
+    /// The function called `name`, with the number of arguments it takes.
+    fn find(name: &str) -> Option<(Function, usize)> {
+        Some(match name {
+            "sin" => (Function::Sin, 1),
+            "cos" => (Function::Cos, 1),
+            "tan" => (Function::Tan, 1),
+            "abs" => (Function::Abs, 1),
+            "sqrt" => (Function::Sqrt, 1),
+            "exp" => (Function::Exp, 1),
+            "log" => (Function::Log, 1),
+            "floor" => (Function::Floor, 1),
+            "ceil" => (Function::Ceil, 1),
+            "min" => (Function::Min, 2),
+            "max" => (Function::Max, 2),
+            "pow" => (Function::Pow, 2),
+            _ => return None,
+        })
+    }
+    fn apply(self, args: &[f32]) -> f32 {
+        match self {
+            Function::Sin => args[0].sin(),
+            Function::Cos => args[0].cos(),
+            Function::Tan => args[0].tan(),
+            Function::Abs => args[0].abs(),
+            Function::Sqrt => args[0].sqrt(),
+            Function::Exp => args[0].exp(),
+            Function::Log => args[0].ln(),
+            Function::Floor => args[0].floor(),
+            Function::Ceil => args[0].ceil(),
+            Function::Min => args[0].min(args[1]),
+            Function::Max => args[0].max(args[1]),
+            Function::Pow => args[0].powf(args[1]),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(f32),
+    /// An index into the expression's variables.
+    Variable(usize),
+    Negate(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn eval(&self, values: &[f32]) -> f32 {
+        match *self {
+            Node::Number(value) => value,
+            Node::Variable(idx) => values[idx],
+            Node::Negate(ref node) => -node.eval(values),
+            Node::Binary(op, ref a, ref b) => op.apply(a.eval(values), b.eval(values)),
+            Node::Call(function, ref args) => {
+                let args: Vec<f32> = args.iter().map(|arg| arg.eval(values)).collect();
+                function.apply(&args)
+            }
+        }
+    }
+}
+
+/// Why an expression didn't compile, and where.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CompileError {
+    /// Byte offset into the source.
+    pub position: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.position)
+    }
+}
+
+/// A recursive descent parser, one function per precedence level.
+struct Parser<'a> {
+    source: &'a [u8],
+    position: usize,
+    variables: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &'static str) -> Result<T, CompileError> {
+        Err(CompileError {
+            position: self.position,
+            message,
+        })
+    }
+    fn skip_space(&mut self) {
+        while self.position < self.source.len() && self.source[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+    /// Consume `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        if self.source[self.position..].starts_with(token.as_bytes()) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+    /// Consume a run of bytes matching `class`.
+    fn take_while<F: Fn(u8) -> bool>(&mut self, class: F) -> &'a str {
+        let start = self.position;
+        while self.position < self.source.len() && class(self.source[self.position]) {
+            self.position += 1;
+        }
+        // only ASCII bytes are taken, so this is always valid
+        ::std::str::from_utf8(&self.source[start..self.position]).unwrap()
+    }
+
+    fn comparison(&mut self) -> Result<Node, CompileError> {
+        let a = self.sum()?;
+        // two character operators first, so `<=` isn't read as `<`
+        let ops = [
+            ("<=", Op::LessEqual),
+            (">=", Op::GreaterEqual),
+            ("==", Op::Equal),
+            ("!=", Op::NotEqual),
+            ("<", Op::Less),
+            (">", Op::Greater),
+        ];
+        for &(token, op) in &ops {
+            if self.eat(token) {
+                return Ok(Node::Binary(op, Box::new(a), Box::new(self.sum()?)));
+            }
+        }
+        Ok(a)
+    }
+    fn sum(&mut self) -> Result<Node, CompileError> {
+        let mut a = self.product()?;
+        loop {
+            let op = if self.eat("+") {
+                Op::Add
+            } else if self.eat("-") {
+                Op::Sub
+            } else {
+                return Ok(a);
+            };
+            a = Node::Binary(op, Box::new(a), Box::new(self.product()?));
+        }
+    }
+    fn product(&mut self) -> Result<Node, CompileError> {
+        let mut a = self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                Op::Mul
+            } else if self.eat("/") {
+                Op::Div
+            } else if self.eat("%") {
+                Op::Rem
+            } else {
+                return Ok(a);
+            };
+            a = Node::Binary(op, Box::new(a), Box::new(self.unary()?));
+        }
+    }
+    fn unary(&mut self) -> Result<Node, CompileError> {
+        if self.eat("-") {
+            Ok(Node::Negate(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+    fn power(&mut self) -> Result<Node, CompileError> {
+        let base = self.primary()?;
+        if self.eat("^") {
+            // right associative, and binding tighter than a minus on the exponent's left
+            Ok(Node::Binary(Op::Pow, Box::new(base), Box::new(self.unary()?)))
+        } else {
+            Ok(base)
+        }
+    }
+    fn primary(&mut self) -> Result<Node, CompileError> {
+        self.skip_space();
+        let next = match self.source.get(self.position) {
+            Some(&next) => next,
+            None => return self.error("unexpected end"),
+        };
+        if self.eat("(") {
+            let node = self.comparison()?;
+            if !self.eat(")") {
+                return self.error("expected )");
+            }
+            Ok(node)
+        } else if next.is_ascii_digit() || next == b'.' {
+            let start = self.position;
+            let number = self.take_while(|c| c.is_ascii_digit() || c == b'.');
+            match number.parse() {
+                Ok(value) => Ok(Node::Number(value)),
+                Err(_) => {
+                    self.position = start;
+                    self.error("invalid number")
+                }
+            }
+        } else if next.is_ascii_alphabetic() || next == b'_' {
+            let start = self.position;
+            let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == b'_');
+            if self.eat("(") {
+                let (function, arity) = match Function::find(name) {
+                    Some(function) => function,
+                    None => {
+                        self.position = start;
+                        return self.error("unknown function");
+                    }
+                };
+                let mut args = vec![self.comparison()?];
+                while self.eat(",") {
+                    args.push(self.comparison()?);
+                }
+                if !self.eat(")") {
+                    return self.error("expected )");
+                }
+                if args.len() != arity {
+                    self.position = start;
+                    return self.error("wrong number of arguments");
+                }
+                Ok(Node::Call(function, args))
+            } else if name == "pi" {
+                Ok(Node::Number(PI))
+            } else {
+                let idx = match self.variables.iter().position(|variable| variable == name) {
+                    Some(idx) => idx,
+                    None => {
+                        self.variables.push(name.into());
+                        self.variables.len() - 1
+                    }
+                };
+                Ok(Node::Variable(idx))
+            }
+        } else {
+            self.error("unexpected character")
+        }
+    }
+}
+
+/// A compiled expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    root: Node,
+    variables: Vec<String>,
+}
+
+impl Expression {
+    pub fn compile(source: &str) -> Result<Expression, CompileError> {
+        let mut parser = Parser {
+            source: source.as_bytes(),
+            position: 0,
+            variables: Vec::new(),
+        };
+        let root = parser.comparison()?;
+        parser.skip_space();
+        if parser.position < source.len() {
+            return parser.error("unexpected character");
+        }
+        Ok(Expression {
+            root,
+            variables: parser.variables,
+        })
+    }
+    /// The variables named, in order of first appearance.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+    /// Evaluate with `values[i]` for `variables()[i]`.
+    pub fn eval(&self, values: &[f32]) -> f32 {
+        self.root.eval(values)
+    }
+}
+
+/// An expression with the latest value of each variable.
+struct Evaluator {
+    source: String,
+    expression: Expression,
+    /// Kept by name, so values survive recompiling.
+    values: HashMap<String, f32>,
+}
+
+impl Evaluator {
+    fn set(&mut self, variable: &str, value: f32) -> f32 {
+        self.values.insert(variable.into(), value);
+        let values: Vec<f32> = self
+            .expression
+            .variables()
+            .iter()
+            .map(|variable| self.values.get(variable).cloned().unwrap_or(0.0))
+            .collect();
+        self.expression.eval(&values)
+    }
+}
+
+type InputPorts = Arc<Mutex<HashMap<String, Arc<flow::Port<f32, ()>>>>>;
+
+/// Create ports for the variables of `expression` that have none, returning them, and remove the
+/// unconnected ports of variables it doesn't use.
+fn sync_ports(
+    ifc: &flow::Interface,
+    expression: &Expression,
+    ports: &InputPorts,
+) -> Vec<(String, Arc<flow::Port<f32, ()>>)> {
+    let mut ports = ports.lock().unwrap();
+    let unused: Vec<String> = ports
+        .iter()
+        .filter(|&(name, port)| !expression.variables().contains(name) && port.edge().is_none())
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in unused {
+        // a task still waiting on the removed port stays parked, since nothing can connect it
+        let port = ports.remove(&name).unwrap();
+        let _ = ifc.remove_port(port.id());
+    }
+    let mut added = Vec::new();
+    for name in expression.variables() {
+        if !ports.contains_key(name) {
+            let port = ifc.get_or_create_port(name.clone());
+            ports.insert(name.clone(), port.clone());
+            added.push((name.clone(), port));
+        }
+    }
+    added
+}
+
+/// A task evaluating the expression whenever a value arrives for `variable`.
+fn input_task(
+    variable: String,
+    port: Arc<flow::Port<f32, ()>>,
+    evaluator: Arc<Mutex<Evaluator>>,
+    mut out_tx: mpsc::Sender<f32>,
+    breaker: Breaker,
+) -> impl Future<Output = ()> + Send {
+    util::sink_task(
+        port,
+        move |value: f32| {
+            let result = evaluator.lock().unwrap().set(&variable, value);
+            // drop results if nobody is consuming them
+            let _ = out_tx.try_send(result);
+        },
+        breaker,
+    )
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Compile(String),
+}
+
+pub struct Expr {
+    ifc: Arc<flow::Interface>,
+    expression_port: Arc<flow::Port<String, ()>>,
+    out_port: Arc<flow::Port<(), f32>>,
+    input_ports: InputPorts,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    /// Feeds expressions arriving on `expression_port` into the same commands as the GUI.
+    compile_tx: UnboundedSender<UserCommand>,
+    evaluator: Arc<Mutex<Evaluator>>,
+}
+
+impl Expr {
+    /// Compile `source` and update the ports, returning the new ports.
+    fn compile(&self, source: &str) -> Vec<(String, Arc<flow::Port<f32, ()>>)> {
+        compile(&self.ifc, &self.evaluator, &self.input_ports, source)
+    }
+}
+
+fn compile(
+    ifc: &flow::Interface,
+    evaluator: &Mutex<Evaluator>,
+    input_ports: &InputPorts,
+    source: &str,
+) -> Vec<(String, Arc<flow::Port<f32, ()>>)> {
+    match Expression::compile(source) {
+        Ok(expression) => {
+            let added = sync_ports(ifc, &expression, input_ports);
+            let mut evaluator = evaluator.lock().unwrap();
+            evaluator.source = source.into();
+            evaluator.expression = expression;
+            added
+        }
+        Err(e) => {
+            println!("expr {:?}: {}", source, e);
+            Vec::new()
+        }
+    }
+}
+
+impl Module for Expr {
+    fn new(ifc: Arc<flow::Interface>) -> Expr {
+        let source = "a + b";
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        let expr = Expr {
+            expression_port: ifc.get_or_create_port("Expression".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            input_ports: Arc::default(),
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            compile_tx: cmd_tx.clone(),
+            cmd_tx: Some(cmd_tx),
+            evaluator: Arc::new(Mutex::new(Evaluator {
+                source: source.into(),
+                expression: Expression::compile(source).unwrap(),
+                values: HashMap::new(),
+            })),
+        };
+        expr.compile(source);
+        expr
+    }
+    fn name() -> &'static str {
+        "Expr"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (out_tx, out_rx) = mpsc::channel(16);
+        for (name, port) in self.input_ports.lock().unwrap().iter() {
+            let task = input_task(
+                name.clone(),
+                port.clone(),
+                self.evaluator.clone(),
+                out_tx.clone(),
+                self.breaker.clone(),
+            );
+            exec.spawn(task).unwrap();
+        }
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+
+        let compile_tx = self.compile_tx.clone();
+        util::start_sink(
+            self.expression_port.clone(),
+            move |source: String| compile_tx.unbounded_send(UserCommand::Compile(source)).unwrap(),
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let ifc = self.ifc.clone();
+        let evaluator = self.evaluator.clone();
+        let input_ports = self.input_ports.clone();
+        let breaker = self.breaker.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            // ports added while running get their tasks polled alongside this one
+            let mut added_tasks = FuturesUnordered::new();
+            loop {
+                select! {
+                    cmd = cmd_rx.next() => match cmd {
+                        Some(UserCommand::Compile(source)) => {
+                            for (name, port) in compile(&ifc, &evaluator, &input_ports, &source) {
+                                let evaluator = evaluator.clone();
+                                let task = input_task(name, port, evaluator, out_tx.clone(), breaker.clone());
+                                added_tasks.push(task);
+                            }
+                        }
+                        None => break,
+                    },
+                    () = added_tasks.select_next_some() => {}
+                }
+            }
+            while let Some(()) = added_tasks.next().await {}
+        })
+        .unwrap();
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        json!(self.evaluator.lock().unwrap().source)
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match state.as_str() {
+            Some(source) => {
+                self.compile(source);
+            }
+            None => println!("expr state err: expected a string"),
+        }
+    }
+}
+
+#[test]
+fn test_expr() {
+    use crate::module::flow::Graph;
+
+    let expression = Expression::compile("sin(a * 2) + b*0.5 - -c^2").unwrap();
+    assert_eq!(expression.variables(), &["a", "b", "c"]);
+    assert_eq!(expression.eval(&[0.0, 3.0, 2.0]), 5.5);
+    let eval = |source: &str| Expression::compile(source).unwrap().eval(&[]);
+    assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+    assert_eq!(eval("-2 ^ 2"), -4.0);
+    assert_eq!(eval("1 + 2 * 3 <= 7"), 1.0);
+    assert_eq!(eval("max(1, min(5, 3)) % 2"), 1.0);
+    assert!((eval("cos(pi)") + 1.0).abs() < 1e-6);
+    let error = |source: &str| Expression::compile(source).unwrap_err();
+    assert_eq!(error("(a + 1").to_string(), "expected ) at 6");
+    assert_eq!(error("foo(1)").message, "unknown function");
+    assert_eq!(error("max(1)").message, "wrong number of arguments");
+    assert_eq!(error("1 $ 2").position, 2);
+
+    // ports follow the variables
+    let graph = Graph::new();
+    let ifc = graph.add_node();
+    let mut expr = Expr::new(ifc.clone());
+    assert!(ifc.find_port::<f32, ()>("a").is_some());
+    expr.load_state(json!("b * x"));
+    assert!(ifc.find_port::<f32, ()>("a").is_none());
+    assert!(ifc.find_port::<f32, ()>("x").is_some());
+    assert_eq!(expr.save_state(), json!("b * x"));
+    assert_eq!(expr.evaluator.lock().unwrap().set("x", 2.0), 0.0);
+    assert_eq!(expr.evaluator.lock().unwrap().set("b", 3.0), 6.0);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct ExprGui {
+    bounds: Box3,
+    expression_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Expr {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let source = self.evaluator.lock().unwrap().source.clone();
+        Box::new(ExprGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            expression_box: TextBox::new(ctx.clone(), source, row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for ExprGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.expression_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.expression_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let source = self.expression_box.content().to_string();
+                match Expression::compile(&source) {
+                    Ok(_) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::Compile(source)).unwrap();
+                    }
+                    Err(e) => self.apply_button.set_label(format!("{}", e)),
+                }
+                true
+            }
+        }
+    }
+}
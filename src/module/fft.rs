@@ -0,0 +1,68 @@
+//! A small in-place radix-2 FFT for DSP modules that need to work in the frequency domain.
+
+use num::complex::Complex32;
+
+use std::f32::consts::PI;
+
+/// Transform `data` in place. Its length must be a power of two. The inverse transform is scaled by
+/// `1 / len`, so a forward transform followed by an inverse one is the identity.
+pub fn fft(data: &mut [Complex32], inverse: bool) {
+    let len = data.len();
+    assert!(len.is_power_of_two(), "fft length must be a power of two");
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..len {
+        let mut bit = len >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut size = 2;
+    while size <= len {
+        let step = Complex32::from_polar(&1.0, &(sign * 2.0 * PI / size as f32));
+        for start in (0..len).step_by(size) {
+            let mut w = Complex32::new(1.0, 0.0);
+            for k in 0..size / 2 {
+                let a = data[start + k];
+                let b = data[start + k + size / 2] * w;
+                data[start + k] = a + b;
+                data[start + k + size / 2] = a - b;
+                w = w * step;
+            }
+        }
+        size *= 2;
+    }
+
+    if inverse {
+        let scale = 1.0 / len as f32;
+        for x in data.iter_mut() {
+            *x = *x * scale;
+        }
+    }
+}
+
+#[test]
+fn test_fft() {
+    let signal: Vec<_> = (0..16)
+        .map(|i| Complex32::new((2.0 * PI * 3.0 * i as f32 / 16.0).cos(), 0.0))
+        .collect();
+    let mut spectrum = signal.clone();
+    fft(&mut spectrum, false);
+    // a cosine at bin 3 shows up at bins 3 and 13, each with half the energy
+    for (bin, x) in spectrum.iter().enumerate() {
+        let expected = if bin == 3 || bin == 13 { 8.0 } else { 0.0 };
+        assert!((x.norm() - expected).abs() < 1e-3);
+    }
+    fft(&mut spectrum, true);
+    for (a, b) in spectrum.iter().zip(&signal) {
+        assert!((a - b).norm() < 1e-5);
+    }
+}
@@ -0,0 +1,50 @@
+//! Resonant lowpass filter, built with `Process`.
+
+use crate::module::audio_io::Frame;
+use crate::module::process::Process;
+use crate::module::simd::Biquad;
+
+pub struct Filter {
+    biquad: Biquad,
+    cutoff: f32,
+    resonance: f32,
+    /// The sample rate the coefficients were computed for, or zero if they're out of date.
+    rate: f32,
+}
+
+impl Process for Filter {
+    const NAME: &'static str = "Filter";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] = &[("Cutoff", 1000.0), ("Resonance", 0.707)];
+    const RANGES: &'static [(f32, f32)] = &[(20.0, 20000.0), (0.5, 10.0)];
+    fn new() -> Filter {
+        Filter {
+            biquad: Biquad::new(),
+            cutoff: 1000.0,
+            resonance: 0.707,
+            rate: 0.0,
+        }
+    }
+    fn set_param(&mut self, idx: usize, value: f32) {
+        match idx {
+            0 => self.cutoff = value,
+            _ => self.resonance = value,
+        }
+        self.rate = 0.0;
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let input = &inputs[0];
+        if self.rate != input.rate {
+            self.rate = input.rate;
+            self.biquad.set_lowpass(self.cutoff, self.resonance, input.rate);
+        }
+        let output = &mut outputs[0];
+        output.data.assign(&input.data);
+        let channels = output.data.cols();
+        match output.data.as_slice_mut() {
+            Some(data) => self.biquad.process(data, channels),
+            None => println!("filter err: non-contiguous frame"),
+        }
+    }
+}
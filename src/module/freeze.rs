@@ -0,0 +1,245 @@
+//! Freeze an expensive chain of modules into a recording.
+//!
+//! `Freeze` sits between the chain and whatever consumes its audio. Normally frames pass straight
+//! through. Freezing records the next few seconds, then loops the recording instead. Since the graph
+//! is pull driven, a frozen module stops requesting frames from its input, so everything upstream
+//! goes idle until it is unfrozen.
+
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, Module};
+
+use ndarray::Axis;
+
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// Pass frames through unchanged.
+    Thru,
+    /// Pass frames through while recording this many more seconds of them.
+    Recording(f32),
+    /// Loop the recording, ignoring the input.
+    Frozen,
+}
+
+/// The recording and playback state.
+pub struct Tape {
+    mode: Mode,
+    frames: Vec<Frame>,
+    position: usize,
+}
+
+impl Tape {
+    fn new() -> Tape {
+        Tape {
+            mode: Mode::Thru,
+            frames: Vec::new(),
+            position: 0,
+        }
+    }
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+    /// Start recording the next `seconds` of input, replacing any previous recording.
+    pub fn freeze(&mut self, seconds: f32) {
+        self.frames.clear();
+        self.mode = Mode::Recording(seconds);
+    }
+    /// Go back to passing frames through, discarding the recording.
+    pub fn unfreeze(&mut self) {
+        self.frames.clear();
+        self.mode = Mode::Thru;
+    }
+    fn record(&mut self, frame: &Frame) {
+        if let Mode::Recording(remaining) = self.mode {
+            self.frames.push(Frame {
+                time: None,
+                ..frame.clone()
+            });
+            let remaining = remaining - frame.data.len_of(Axis(0)) as f32 / frame.rate;
+            if remaining <= 0.0 {
+                self.mode = Mode::Frozen;
+                self.position = 0;
+            } else {
+                self.mode = Mode::Recording(remaining);
+            }
+        }
+    }
+    /// The next frame of the recording, if frozen.
+    fn playback(&mut self) -> Option<Frame> {
+        if self.mode != Mode::Frozen || self.frames.is_empty() {
+            return None;
+        }
+        let frame = self.frames[self.position].clone();
+        self.position = (self.position + 1) % self.frames.len();
+        Some(frame)
+    }
+}
+
+pub struct Freeze {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    tape: Arc<Mutex<Tape>>,
+}
+
+/// Answers one request, from the tape when it's frozen and otherwise from the input.
+async fn step(
+    tape: &Mutex<Tape>,
+    in_port: &Arc<flow::Port<Frame, ()>>,
+    out_port: &Arc<flow::Port<(), Frame>>,
+) -> Result<(), String> {
+    // wait for a request
+    let (out_port, _req) = out_port
+        .clone()
+        .read1()
+        .await
+        .map_err(|(_port, err)| format!("out read1 {:?}", err))?;
+    let frozen = tape.lock().unwrap().playback();
+    let frame = match frozen {
+        Some(frame) => frame,
+        None => {
+            let pulled = async { in_port.clone().write1(()).await?.read1().await };
+            let (_port, frame) = pulled
+                .await
+                .map_err(|(_port, err)| format!("in read1 {:?}", err))?;
+            tape.lock().unwrap().record(&frame);
+            frame
+        }
+    };
+    out_port
+        .write1(frame)
+        .await
+        .map_err(|(_port, err)| format!("out write1 {:?}", err))?;
+    Ok(())
+}
+
+impl Module for Freeze {
+    fn new(ifc: Arc<flow::Interface>) -> Freeze {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        Freeze {
+            ifc,
+            in_port,
+            out_port,
+            breaker: Breaker::new(),
+            tape: Arc::new(Mutex::new(Tape::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Freeze"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let tape = self.tape.clone();
+        let in_port = self.in_port.clone();
+        let out_port = self.out_port.clone();
+        let breaker = self.breaker.clone();
+        exec.spawn(async move {
+            loop {
+                if let Err(err) = step(&tape, &in_port, &out_port).await {
+                    println!("freeze err: {}", err);
+                }
+                if breaker.test() {
+                    break;
+                }
+            }
+        })
+        .unwrap();
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_tape() {
+    use ndarray::Array2;
+
+    let frame = |value: f32| Frame {
+        rate: 4.0,
+        time: Some(0),
+        data: Array2::from_elem((2, 1), value),
+        meta: None,
+    };
+    let mut tape = Tape::new();
+    tape.record(&frame(0.0));
+    assert!(tape.playback().is_none());
+
+    tape.freeze(1.0);
+    tape.record(&frame(1.0));
+    assert_eq!(tape.mode(), Mode::Recording(0.5));
+    tape.record(&frame(2.0));
+    assert_eq!(tape.mode(), Mode::Frozen);
+    let played: Vec<_> = (0..3).map(|_| tape.playback().unwrap().data[[0, 0]]).collect();
+    assert_eq!(played, vec![1.0, 2.0, 1.0]);
+
+    tape.unfreeze();
+    assert!(tape.playback().is_none());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct FreezeGui {
+    bounds: Box3,
+    seconds_box: TextBox,
+    freeze_button: Button,
+    tape: Arc<Mutex<Tape>>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Freeze {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(FreezeGui {
+            bounds,
+            seconds_box: TextBox::new(ctx.clone(), "4".into(), row(0.0)),
+            freeze_button: Button::new(ctx.clone(), "Freeze".into(), row(1.0)),
+            tape: self.tape.clone(),
+        })
+    }
+}
+impl GuiComponent<bool> for FreezeGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.seconds_box.render(device, ctx);
+        self.freeze_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.seconds_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.freeze_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let mut tape = self.tape.lock().unwrap();
+                let label = if tape.mode() == Mode::Thru {
+                    match self.seconds_box.content().trim().parse::<f32>() {
+                        Ok(seconds) if seconds > 0.0 => {
+                            tape.freeze(seconds);
+                            "Unfreeze"
+                        }
+                        _ => "Invalid: seconds",
+                    }
+                } else {
+                    tape.unfreeze();
+                    "Freeze"
+                };
+                self.freeze_button.set_label(label.into());
+                true
+            }
+        }
+    }
+}
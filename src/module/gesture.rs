@@ -0,0 +1,408 @@
+//! A modulation looper: `Gesture` records the values arriving on a control input, like the moves of
+//! a MIDI knob or the mouse, for as long as `Record` is held, and then plays them back in time.
+//!
+//! Like the generators in `timeline`, it is clocked by its `Input` frames, and values are placed
+//! on the transport by the frames' sample counters. The gesture loops while `Loop` is up and
+//! otherwise plays once, and `Play` starts it over. With `Quantize` up, recording waits for the
+//! next bar line of the graph's tempo map and the gesture is rounded to whole bars, so it stays
+//! in step with the music. Playback sends a value per frame at most, whenever it changes.
+
+use futures::channel::mpsc;
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::timeline::TempoMap;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+/// A recorded gesture.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gesture {
+    /// Seconds into the gesture and the values arriving then, in order.
+    points: Vec<(f64, f32)>,
+    /// Seconds in the gesture.
+    length: f64,
+}
+
+impl Gesture {
+    /// The value at `seconds` into the gesture, if anything was recorded by then.
+    fn at(&self, seconds: f64) -> Option<f32> {
+        let after = self.points.iter().position(|&(time, _)| time > seconds);
+        let before = after.unwrap_or(self.points.len()).checked_sub(1)?;
+        Some(self.points[before].1)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum State {
+    Empty,
+    /// Waiting for the transport to reach this time to start recording.
+    Armed(f64),
+    /// Recording since this time.
+    Recording(f64),
+    /// Playing since this time.
+    Playing(f64),
+    /// Recorded, and finished playing or not started.
+    Stopped,
+}
+
+/// Records and plays back a gesture, with times in seconds on the transport.
+pub struct GestureRecorder {
+    pub looping: bool,
+    state: State,
+    gesture: Gesture,
+    /// The last value to arrive, which a recording starts from.
+    input: Option<f32>,
+    /// The last value played, so values are only sent when they change.
+    sent: Option<f32>,
+}
+
+impl GestureRecorder {
+    pub fn new() -> GestureRecorder {
+        GestureRecorder {
+            looping: true,
+            state: State::Empty,
+            gesture: Gesture {
+                points: Vec::new(),
+                length: 0.0,
+            },
+            input: None,
+            sent: None,
+        }
+    }
+    pub fn state(&self) -> State {
+        self.state
+    }
+    pub fn gesture(&self) -> &Gesture {
+        &self.gesture
+    }
+    /// Replace the gesture, playing it from the start of the transport.
+    pub fn set_gesture(&mut self, gesture: Gesture) {
+        self.state = if gesture.length > 0.0 {
+            State::Playing(0.0)
+        } else {
+            State::Empty
+        };
+        self.gesture = gesture;
+        self.sent = None;
+    }
+    /// Start recording at `at`, which may be later than now.
+    pub fn record(&mut self, at: f64) {
+        self.state = State::Armed(at);
+    }
+    /// Stop recording at `now`, ending the gesture at `end`. With `end` before `now` the values
+    /// after it are dropped, and after `now` the gesture holds the last value until then.
+    /// Playback starts at `end`, so a gesture rounded to bars stays on them.
+    pub fn finish(&mut self, now: f64, end: f64) {
+        let start = match self.state {
+            State::Recording(start) => start,
+            // released before recording started
+            State::Armed(_) => {
+                self.state = State::Empty;
+                return;
+            }
+            _ => return,
+        };
+        self.arrive(now);
+        let length = end - start;
+        self.gesture.points.retain(|&(time, _)| time < length);
+        if length > 0.0 && !self.gesture.points.is_empty() {
+            self.gesture.length = length;
+            self.state = State::Playing(end);
+        } else {
+            self.state = State::Empty;
+        }
+        self.sent = None;
+    }
+    /// Play the gesture from the start at `at`.
+    pub fn play(&mut self, at: f64) {
+        match self.state {
+            State::Playing(_) | State::Stopped => {
+                self.state = State::Playing(at);
+                self.sent = None;
+            }
+            _ => {}
+        }
+    }
+    /// Start an armed recording if the transport has reached it by `now`.
+    fn arrive(&mut self, now: f64) {
+        if let State::Armed(start) = self.state {
+            if now >= start {
+                self.state = State::Recording(start);
+                self.gesture.points.clear();
+                // the gesture starts from wherever the input was
+                self.gesture.points.extend(self.input.map(|value| (0.0, value)));
+            }
+        }
+    }
+    /// A value arriving at `now`.
+    pub fn input(&mut self, now: f64, value: f32) {
+        self.arrive(now);
+        if let State::Recording(start) = self.state {
+            self.gesture.points.push(((now - start).max(0.0), value));
+        }
+        self.input = Some(value);
+    }
+    /// Move the transport on to `now`, returning the value played then if it changed.
+    pub fn advance(&mut self, now: f64) -> Option<f32> {
+        self.arrive(now);
+        let start = match self.state {
+            State::Playing(start) if now >= start => start,
+            _ => return None,
+        };
+        let mut seconds = now - start;
+        if seconds >= self.gesture.length {
+            if self.looping {
+                seconds %= self.gesture.length;
+            } else {
+                self.state = State::Stopped;
+                seconds = self.gesture.length;
+            }
+        }
+        let value = self.gesture.at(seconds)?;
+        if self.sent == Some(value) {
+            return None;
+        }
+        self.sent = Some(value);
+        Some(value)
+    }
+}
+
+/// Holds whether a gate is up, to act on its rising and falling edges.
+struct Gate(bool);
+
+impl Gate {
+    /// Whether `value` raised or lowered the gate, and which.
+    fn edge(&mut self, value: f32) -> Option<bool> {
+        let up = value > 0.5;
+        if up == self.0 {
+            return None;
+        }
+        self.0 = up;
+        Some(up)
+    }
+}
+
+struct Shared {
+    recorder: GestureRecorder,
+    /// Seconds on the transport, at the end of the last frame.
+    now: f64,
+    quantize: bool,
+    record: Gate,
+    play: Gate,
+}
+
+impl Shared {
+    /// The first bar line of `map` at or after `seconds`.
+    fn next_bar(map: &TempoMap, seconds: f64) -> f64 {
+        let bar = map.position(seconds).bar;
+        let start = map.bar_seconds(bar);
+        if start >= seconds {
+            start
+        } else {
+            map.bar_seconds(bar + 1)
+        }
+    }
+    /// The bar line of `map` nearest to `seconds`, at least a bar after `start`.
+    fn nearest_bar(map: &TempoMap, start: f64, seconds: f64) -> f64 {
+        let first = map.bar_seconds(map.position(start).bar + 1);
+        let bar = map.position(seconds).bar;
+        let (before, after) = (map.bar_seconds(bar), map.bar_seconds(bar + 1));
+        let nearest = if seconds - before < after - seconds {
+            before
+        } else {
+            after
+        };
+        nearest.max(first)
+    }
+}
+
+pub struct GestureModule {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    in_port: Arc<flow::Port<f32, ()>>,
+    record_port: Arc<flow::Port<f32, ()>>,
+    play_port: Arc<flow::Port<f32, ()>>,
+    loop_port: Arc<flow::Port<f32, ()>>,
+    quantize_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Module for GestureModule {
+    fn new(ifc: Arc<flow::Interface>) -> GestureModule {
+        GestureModule {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            in_port: ifc.get_or_create_port("Value".into()),
+            record_port: ifc.get_or_create_port("Record".into()),
+            play_port: ifc.get_or_create_port("Play".into()),
+            loop_port: ifc.get_or_create_port("Loop".into()),
+            quantize_port: ifc.get_or_create_port("Quantize".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            shared: Arc::new(Mutex::new(Shared {
+                recorder: GestureRecorder::new(),
+                now: 0.0,
+                quantize: false,
+                record: Gate(false),
+                play: Gate(false),
+            })),
+        }
+    }
+    fn name() -> &'static str {
+        "Gesture"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |value: f32| {
+                let mut shared = shared.lock().unwrap();
+                let now = shared.now;
+                shared.recorder.input(now, value);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        let shared = self.shared.clone();
+        let graph = self.ifc.graph();
+        util::start_sink(
+            self.record_port.clone(),
+            move |value: f32| {
+                let mut shared = shared.lock().unwrap();
+                let (now, quantize) = (shared.now, shared.quantize);
+                let map = graph.tempo_map();
+                let edge = shared.record.edge(value);
+                match edge {
+                    Some(true) if quantize => shared.recorder.record(Shared::next_bar(&map, now)),
+                    Some(true) => shared.recorder.record(now),
+                    Some(false) => {
+                        let end = match shared.recorder.state() {
+                            State::Recording(start) if quantize => Shared::nearest_bar(&map, start, now),
+                            _ => now,
+                        };
+                        shared.recorder.finish(now, end);
+                    }
+                    None => {}
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.play_port.clone(),
+            move |value: f32| {
+                let mut shared = shared.lock().unwrap();
+                if shared.play.edge(value) == Some(true) {
+                    let now = shared.now;
+                    shared.recorder.play(now);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.loop_port.clone(),
+            move |value: f32| shared.lock().unwrap().recorder.looping = value > 0.5,
+            self.breaker.clone(),
+            &exec,
+        );
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.quantize_port.clone(),
+            move |value: f32| shared.lock().unwrap().quantize = value > 0.5,
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let (mut value_tx, value_rx) = mpsc::channel(1);
+        let shared = self.shared.clone();
+        // the sample at the end of the last frame, counted here if frames have no time
+        let mut position: u64 = 0;
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                position = frame.time.unwrap_or(position) + frame.data.dim().0 as u64;
+                let mut shared = shared.lock().unwrap();
+                shared.now = position as f64 / frame.rate as f64;
+                let now = shared.now;
+                if let Some(value) = shared.recorder.advance(now) {
+                    let _ = value_tx.try_send(value);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(value_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.shared.lock().unwrap().recorder.gesture()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(gesture) => self.shared.lock().unwrap().recorder.set_gesture(gesture),
+            Err(e) => println!("gesture state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_gesture() {
+    let mut recorder = GestureRecorder::new();
+    recorder.input(0.0, 0.5);
+    recorder.record(1.0);
+    // the knob moves before and during the recording
+    recorder.input(0.5, 0.25);
+    assert_eq!(recorder.advance(1.0), None);
+    assert_eq!(recorder.state(), State::Recording(1.0));
+    recorder.input(1.5, 1.0);
+    recorder.input(2.5, 2.0);
+    recorder.finish(3.0, 3.0);
+    assert_eq!(
+        recorder.gesture().points,
+        vec![(0.0, 0.25), (0.5, 1.0), (1.5, 2.0)]
+    );
+    assert_eq!(recorder.state(), State::Playing(3.0));
+
+    assert_eq!(recorder.advance(3.25), Some(0.25));
+    assert_eq!(recorder.advance(3.4), None);
+    assert_eq!(recorder.advance(3.5), Some(1.0));
+    assert_eq!(recorder.advance(4.75), Some(2.0));
+    // and around again
+    assert_eq!(recorder.advance(5.25), Some(0.25));
+
+    recorder.looping = false;
+    assert_eq!(recorder.advance(7.5), Some(2.0));
+    assert_eq!(recorder.state(), State::Stopped);
+    assert_eq!(recorder.advance(8.0), None);
+    recorder.play(10.0);
+    assert_eq!(recorder.advance(10.0), Some(0.25));
+
+    // rounded down, dropping what came after the end
+    recorder.record(20.0);
+    recorder.input(20.0, 3.0);
+    recorder.input(21.5, 4.0);
+    recorder.finish(21.5, 21.0);
+    assert_eq!(recorder.gesture().points, vec![(0.0, 2.0), (0.0, 3.0)]);
+    assert_eq!(recorder.gesture().length, 1.0);
+
+    // bar lines of 4/4 at 120 BPM are two seconds apart
+    let map = TempoMap::new();
+    assert_eq!(Shared::next_bar(&map, 3.0), 4.0);
+    assert_eq!(Shared::next_bar(&map, 4.0), 4.0);
+    assert_eq!(Shared::nearest_bar(&map, 4.0, 8.5), 8.0);
+    assert_eq!(Shared::nearest_bar(&map, 4.0, 4.5), 6.0);
+}
@@ -0,0 +1,214 @@
+//! Regression tests against known good output.
+//!
+//! `render_graph_to_vec` runs a patch offline through a `BlockScheduler`, the way the audio thread
+//! would, and collects what reaches the host. `check_golden` compares that with frames saved by an
+//! earlier run, within a tolerance, so a test of a DSP module takes a few lines:
+//!
+//! ```ignore
+//! let graph = flow::Graph::new();
+//! let (host_in, host_out) = add_host(&graph);
+//! let mut filter = Processor::<Filter>::new(graph.add_node());
+//! // ... connect host_out -> filter -> host_in
+//! let frames = render_graph_to_vec(&graph, 16);
+//! check_golden(&frames, "tests/golden/filter.jsonl", 1e-6).unwrap();
+//! ```
+//!
+//! A golden file that doesn't exist yet is written instead of compared with, and setting
+//! `FLOW_SYNTH_BLESS` rewrites them all after an intended change. They are recordings in the
+//! format of `module::record`, so they can also be played back with an `Audio Replay` node.
+
+use crate::module::audio_io::Frame;
+use crate::module::flow;
+use crate::module::record::{read_items, Recordable};
+use crate::module::scheduler::BlockScheduler;
+
+use ndarray::Array2;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+pub const RATE: f32 = 48000.0;
+pub const BLOCK_SIZE: usize = 64;
+pub const CHANNELS: usize = 2;
+/// The tag marking the node `render_graph_to_vec` plays the host for.
+pub const HOST_TAG: &str = "host";
+
+/// Add a node standing in for the audio interface: its `Input` is what gets rendered, and its
+/// `Output` plays the capture, silence for `render_graph_to_vec`.
+pub fn add_host(graph: &Arc<flow::Graph>) -> (Arc<flow::Port<Frame, ()>>, Arc<flow::Port<(), Frame>>) {
+    let host = graph.add_node();
+    graph.node(host.id()).unwrap().add_tag(HOST_TAG);
+    (
+        host.get_or_create_port("Input".into()),
+        host.get_or_create_port("Output".into()),
+    )
+}
+
+/// Run `blocks` blocks of `BLOCK_SIZE` samples through the graph, with the node tagged `HOST_TAG`
+/// as the host, and return the frames arriving at the host's input.
+pub fn render_graph_to_vec(graph: &Arc<flow::Graph>, blocks: usize) -> Vec<Frame> {
+    let capture = Frame {
+        rate: RATE,
+        time: Some(0),
+        data: Array2::zeros((BLOCK_SIZE, CHANNELS)),
+        meta: None,
+    };
+    render_with_capture(graph, blocks, capture)
+}
+
+/// Like `render_graph_to_vec`, but playing `capture` on the host's output for every block, with
+/// the time moving on by its length each block.
+pub fn render_with_capture(graph: &Arc<flow::Graph>, blocks: usize, mut capture: Frame) -> Vec<Frame> {
+    let host = graph
+        .nodes()
+        .into_iter()
+        .find(|node| node.has_tag(HOST_TAG))
+        .expect("the graph has no host node, see add_host");
+    let mut scheduler = BlockScheduler::new(graph.clone(), host.id());
+    let len = capture.data.dim().0 as u64;
+    (0..blocks)
+        .map(|_| {
+            let frame = scheduler.run(&capture);
+            capture.time = capture.time.map(|time| time + len);
+            frame
+        })
+        .collect()
+}
+
+/// How rendered frames differ from the golden ones.
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(io::Error),
+    /// The number of frames differs.
+    Length {
+        expected: usize,
+        actual: usize,
+    },
+    /// A frame has a different number of samples or channels.
+    Shape {
+        frame: usize,
+        expected: (usize, usize),
+        actual: (usize, usize),
+    },
+    /// The first sample further than the tolerance from the golden one.
+    Sample {
+        frame: usize,
+        row: usize,
+        channel: usize,
+        expected: f32,
+        actual: f32,
+    },
+}
+
+impl From<io::Error> for GoldenError {
+    fn from(e: io::Error) -> GoldenError {
+        GoldenError::Io(e)
+    }
+}
+
+/// Compare two runs, allowing every sample to be off by `tolerance`.
+pub fn compare(expected: &[Frame], actual: &[Frame], tolerance: f32) -> Result<(), GoldenError> {
+    if expected.len() != actual.len() {
+        return Err(GoldenError::Length {
+            expected: expected.len(),
+            actual: actual.len(),
+        });
+    }
+    for (idx, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+        if expected.data.dim() != actual.data.dim() {
+            return Err(GoldenError::Shape {
+                frame: idx,
+                expected: expected.data.dim(),
+                actual: actual.data.dim(),
+            });
+        }
+        for ((row, channel), &x) in expected.data.indexed_iter() {
+            let y = actual.data[[row, channel]];
+            // NaN never matches, so a module blowing up is caught too
+            if !((x - y).abs() <= tolerance) {
+                return Err(GoldenError::Sample {
+                    frame: idx,
+                    row,
+                    channel,
+                    expected: x,
+                    actual: y,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compare `frames` with the golden file at `path`, or write them to it if it doesn't exist yet
+/// or `FLOW_SYNTH_BLESS` is set.
+pub fn check_golden<P: AsRef<Path>>(frames: &[Frame], path: P, tolerance: f32) -> Result<(), GoldenError> {
+    let path = path.as_ref();
+    if env::var_os("FLOW_SYNTH_BLESS").is_some() || !path.exists() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = File::create(path)?;
+        for frame in frames {
+            writeln!(file, "{}", frame.to_record())?;
+        }
+        return Ok(());
+    }
+    let golden: Vec<Frame> = read_items(BufReader::new(File::open(path)?))?;
+    compare(&golden, frames, tolerance)
+}
+
+#[test]
+fn test_golden() {
+    use crate::module::mix::Gain;
+    use crate::module::process::Processor;
+    use crate::module::Module;
+
+    let graph = flow::Graph::new();
+    let (host_in, host_out) = add_host(&graph);
+    let node = graph.add_node();
+    let _gain = Processor::<Gain>::new(node.clone());
+    let (input, output) = (
+        node.get_or_create_port::<Frame, ()>("Input".into()),
+        node.get_or_create_port::<(), Frame>("Output".into()),
+    );
+    host_out.connect(&input).unwrap();
+    output.connect(&host_in).unwrap();
+    node.params().unwrap().set("Gain", 0.5);
+
+    let capture = Frame {
+        rate: RATE,
+        time: Some(0),
+        data: Array2::from_elem((BLOCK_SIZE, CHANNELS), 1.0),
+        meta: None,
+    };
+    // long enough for the connections to have faded in
+    let frames = render_with_capture(&graph, 8, capture);
+    assert_eq!(frames.len(), 8);
+    assert!(frames[7].data.iter().all(|&x| x == 0.5));
+    assert!(render_graph_to_vec(&graph, 1)[0].data.iter().all(|&x| x == 0.0));
+
+    let path = env::temp_dir().join(format!("flow-synth-golden-{}.jsonl", ::std::process::id()));
+    let _ = fs::remove_file(&path);
+    check_golden(&frames, &path, 0.0).unwrap();
+    check_golden(&frames, &path, 0.0).unwrap();
+    let mut louder = frames.clone();
+    louder[1].data[[3, 1]] += 0.01;
+    assert!(check_golden(&louder, &path, 0.1).is_ok());
+    match check_golden(&louder, &path, 0.001) {
+        Err(GoldenError::Sample {
+            frame: 1,
+            row: 3,
+            channel: 1,
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert!(match compare(&frames, &frames[1..], 0.0) {
+        Err(GoldenError::Length { .. }) => true,
+        _ => false,
+    });
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,626 @@
+//! Raspberry Pi GPIO, for installations with buttons, lights and sensors wired straight to the board.
+//!
+//! `GpioIn` watches a pin and sends an event on every change that outlasts the debounce time, so a
+//! bouncing button contact gives one press. `GpioPwm` drives a pin with software PWM, its duty
+//! cycle following the `Duty` control input. `I2cSensor` polls a register of an I2C device and
+//! outputs its value, scaled, which covers most simple sensors; `read_register` and `decode` help
+//! with the rest.
+//!
+//! Each module is set up with a line of text in its body, which is saved with the patch.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use rppal::gpio::{Gpio, OutputPin};
+use rppal::i2c::I2c;
+
+use crate::future_ext::Breaker;
+use crate::module::{flow, util, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often input pins are read.
+const INPUT_POLL: Duration = Duration::from_millis(1);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pull {
+    Up,
+    Down,
+    Float,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InConfig {
+    pub pin: u8,
+    pub pull: Pull,
+    pub debounce: Duration,
+}
+
+impl InConfig {
+    /// Parse a config of the form `pin [up|down|float [debounce ms]]`.
+    pub fn parse(s: &str) -> Option<InConfig> {
+        let mut words = s.split_whitespace();
+        let pin = words.next()?.parse().ok()?;
+        let pull = match words.next() {
+            None | Some("up") => Pull::Up,
+            Some("down") => Pull::Down,
+            Some("float") => Pull::Float,
+            Some(_) => return None,
+        };
+        let millis = words.next().map(|w| w.parse().ok()).unwrap_or(Some(20))?;
+        Some(InConfig {
+            pin,
+            pull,
+            debounce: Duration::from_millis(millis),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PwmConfig {
+    pub pin: u8,
+    pub frequency: f64,
+}
+
+impl PwmConfig {
+    /// Parse a config of the form `pin [frequency]`.
+    pub fn parse(s: &str) -> Option<PwmConfig> {
+        let mut words = s.split_whitespace();
+        let pin = words.next()?.parse().ok()?;
+        let frequency = words.next().map(|w| w.parse().ok()).unwrap_or(Some(1000.0))?;
+        if frequency <= 0.0 {
+            return None;
+        }
+        Some(PwmConfig { pin, frequency })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorConfig {
+    pub address: u16,
+    pub register: u8,
+    /// Length of the value, read big endian and signed.
+    pub bytes: usize,
+    /// Multiplies the raw value, to get it in a useful unit.
+    pub scale: f32,
+    /// Reads per second.
+    pub rate: f32,
+}
+
+impl SensorConfig {
+    /// Parse a config of the form `address register [bytes [scale [rate]]]`, with the address and
+    /// register in decimal or hex like `0x48`.
+    pub fn parse(s: &str) -> Option<SensorConfig> {
+        let mut words = s.split_whitespace();
+        let address = parse_int(words.next()?)?;
+        let register = parse_int(words.next()?)?;
+        let bytes = words.next().map(|w| w.parse().ok()).unwrap_or(Some(1))?;
+        let scale = words.next().map(|w| w.parse().ok()).unwrap_or(Some(1.0))?;
+        let rate: f32 = words.next().map(|w| w.parse().ok()).unwrap_or(Some(20.0))?;
+        if address > 0x3ff || register > 0xff || bytes == 0 || bytes > 4 || rate <= 0.0 {
+            return None;
+        }
+        Some(SensorConfig {
+            address: address as u16,
+            register: register as u8,
+            bytes,
+            scale,
+            rate,
+        })
+    }
+}
+
+fn parse_int(s: &str) -> Option<u32> {
+    if s.starts_with("0x") {
+        u32::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// A change of a debounced input pin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PinEdge {
+    pub pin: u8,
+    pub high: bool,
+}
+
+/// Follows a level once it has held for the debounce time, ignoring the bounces in between.
+pub struct Debounce {
+    time: Duration,
+    level: Option<bool>,
+    /// The latest level read, and when it was first read.
+    candidate: (bool, Duration),
+}
+
+impl Debounce {
+    pub fn new(time: Duration) -> Debounce {
+        Debounce {
+            time,
+            level: None,
+            candidate: (false, Duration::from_secs(0)),
+        }
+    }
+    /// Feed the level read at `now`, returning the new level if it changed. The first reading is
+    /// taken as it is, without a change.
+    pub fn update(&mut self, level: bool, now: Duration) -> Option<bool> {
+        if self.level.is_none() || level != self.candidate.0 {
+            self.candidate = (level, now);
+        }
+        match self.level {
+            None => {
+                self.level = Some(level);
+                None
+            }
+            Some(current) if level != current && now - self.candidate.1 >= self.time => {
+                self.level = Some(level);
+                Some(level)
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// Read `buffer.len()` bytes starting at `register` of the device at `address`.
+pub fn read_register(i2c: &mut I2c, address: u16, register: u8, buffer: &mut [u8]) -> Result<(), String> {
+    i2c.set_slave_address(address).map_err(|e| e.to_string())?;
+    i2c.write_read(&[register], buffer).map_err(|e| e.to_string())
+}
+
+/// The signed big endian value in `bytes`, as most sensors send their readings.
+pub fn decode(bytes: &[u8]) -> f32 {
+    let bits = 8 * bytes.len() as u32;
+    let raw = bytes.iter().fold(0u32, |raw, &byte| raw << 8 | byte as u32);
+    let shift = 32 - bits;
+    ((raw << shift) as i32 >> shift) as f32
+}
+
+/// Settings shared by the modules of this file: the config line, kept for saving and the GUI, and
+/// a breaker for whatever runs on it.
+#[derive(Default)]
+struct Setup {
+    config: Option<String>,
+    session: Option<Breaker>,
+}
+
+impl Setup {
+    /// Replace the running session with a new one for `config`.
+    fn restart(&mut self, config: &str) -> Breaker {
+        self.config = Some(config.into());
+        self.session.take().map(|session| session.brake());
+        let session = Breaker::new();
+        self.session = Some(session.clone());
+        session
+    }
+    fn save(&self) -> serde_json::Value {
+        match self.config {
+            Some(ref config) => json!({ "config": config }),
+            None => serde_json::Value::Null,
+        }
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        self.session.take().map(|session| session.brake());
+    }
+}
+
+pub struct GpioIn {
+    ifc: Arc<flow::Interface>,
+    events_port: Arc<flow::Port<(), PinEdge>>,
+    cmd_rx: Option<UnboundedReceiver<String>>,
+    cmd_tx: Option<UnboundedSender<String>>,
+    setup: Arc<Mutex<Setup>>,
+}
+
+impl Module for GpioIn {
+    fn new(ifc: Arc<flow::Interface>) -> GpioIn {
+        let events_port = ifc.get_or_create_port::<(), PinEdge>("Events".into());
+        events_port.set_meta(flow::PortMeta {
+            signal: Some(flow::Signal::Event),
+            ..events_port.meta()
+        });
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        GpioIn {
+            ifc,
+            events_port,
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            setup: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "GPIO In"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (events_tx, events_rx) = mpsc::channel(64);
+        let setup = self.setup.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(line) = cmd_rx.next().await {
+                if let Some(config) = InConfig::parse(&line) {
+                    let session = setup.lock().unwrap().restart(&line);
+                    let events_tx = events_tx.clone();
+                    thread::spawn(move || watch(config, events_tx, session));
+                }
+            }
+        })
+        .unwrap();
+        util::start_source(events_rx, self.events_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.setup.lock().unwrap().session.take().map(|session| session.brake());
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        self.setup.lock().unwrap().save()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        load(&self.setup, &self.cmd_tx, state);
+    }
+}
+
+/// Send the config line of a saved state to be applied once the module starts.
+fn load(setup: &Mutex<Setup>, cmd_tx: &Option<UnboundedSender<String>>, state: serde_json::Value) {
+    if let (Some(config), Some(cmd_tx)) = (state["config"].as_str(), cmd_tx.as_ref()) {
+        setup.lock().unwrap().config = Some(config.into());
+        cmd_tx.unbounded_send(config.into()).unwrap();
+    }
+}
+
+fn watch(config: InConfig, mut tx: mpsc::Sender<PinEdge>, session: Breaker) {
+    let pin = match Gpio::new().and_then(|gpio| gpio.get(config.pin)) {
+        Ok(pin) => pin,
+        Err(e) => {
+            println!("gpio pin {} err: {}", config.pin, e);
+            return;
+        }
+    };
+    let pin = match config.pull {
+        Pull::Up => pin.into_input_pullup(),
+        Pull::Down => pin.into_input_pulldown(),
+        Pull::Float => pin.into_input(),
+    };
+    let start = Instant::now();
+    let mut debounce = Debounce::new(config.debounce);
+    while !session.test() {
+        if let Some(high) = debounce.update(pin.is_high(), start.elapsed()) {
+            // drop events if nobody is consuming them
+            let _ = tx.try_send(PinEdge {
+                pin: config.pin,
+                high,
+            });
+        }
+        thread::sleep(INPUT_POLL);
+    }
+}
+
+pub struct GpioPwm {
+    ifc: Arc<flow::Interface>,
+    duty_port: Arc<flow::Port<f32, ()>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<String>>,
+    cmd_tx: Option<UnboundedSender<String>>,
+    setup: Arc<Mutex<Setup>>,
+    /// The pin being driven, and its frequency.
+    output: Arc<Mutex<Option<(OutputPin, f64)>>>,
+}
+
+impl Module for GpioPwm {
+    fn new(ifc: Arc<flow::Interface>) -> GpioPwm {
+        let duty_port = ifc.get_or_create_port::<f32, ()>("Duty".into());
+        duty_port.set_meta(flow::PortMeta {
+            signal: Some(flow::Signal::Control),
+            ..duty_port.meta()
+        });
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        GpioPwm {
+            ifc,
+            duty_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            setup: Arc::default(),
+            output: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "GPIO PWM"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let setup = self.setup.clone();
+        let output = self.output.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(line) = cmd_rx.next().await {
+                if let Some(config) = PwmConfig::parse(&line) {
+                    setup.lock().unwrap().restart(&line);
+                    let mut output = output.lock().unwrap();
+                    // the previous pin goes back to an input when dropped
+                    *output = None;
+                    match Gpio::new().and_then(|gpio| gpio.get(config.pin)) {
+                        Ok(pin) => {
+                            let mut pin = pin.into_output();
+                            pin.set_low();
+                            *output = Some((pin, config.frequency));
+                        }
+                        Err(e) => println!("gpio pin {} err: {}", config.pin, e),
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let output = self.output.clone();
+        let mut last = None;
+        util::start_sink(
+            self.duty_port.clone(),
+            move |duty: f32| {
+                let duty = duty.max(0.0).min(1.0) as f64;
+                if last == Some(duty) {
+                    return;
+                }
+                last = Some(duty);
+                if let Some((ref mut pin, frequency)) = *output.lock().unwrap() {
+                    if let Err(e) = pin.set_pwm_frequency(frequency, duty) {
+                        println!("gpio pwm err: {}", e);
+                    }
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        *self.output.lock().unwrap() = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        self.setup.lock().unwrap().save()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        load(&self.setup, &self.cmd_tx, state);
+    }
+}
+
+pub struct I2cSensor {
+    ifc: Arc<flow::Interface>,
+    value_port: Arc<flow::Port<(), f32>>,
+    cmd_rx: Option<UnboundedReceiver<String>>,
+    cmd_tx: Option<UnboundedSender<String>>,
+    setup: Arc<Mutex<Setup>>,
+}
+
+impl Module for I2cSensor {
+    fn new(ifc: Arc<flow::Interface>) -> I2cSensor {
+        let value_port = ifc.get_or_create_port::<(), f32>("Value".into());
+        value_port.set_meta(flow::PortMeta {
+            signal: Some(flow::Signal::Control),
+            ..value_port.meta()
+        });
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        I2cSensor {
+            ifc,
+            value_port,
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            setup: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "I2C Sensor"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (value_tx, value_rx) = mpsc::channel(64);
+        let setup = self.setup.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(line) = cmd_rx.next().await {
+                if let Some(config) = SensorConfig::parse(&line) {
+                    let session = setup.lock().unwrap().restart(&line);
+                    let value_tx = value_tx.clone();
+                    thread::spawn(move || poll_sensor(config, value_tx, session));
+                }
+            }
+        })
+        .unwrap();
+        util::start_source(value_rx, self.value_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.setup.lock().unwrap().session.take().map(|session| session.brake());
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        self.setup.lock().unwrap().save()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        load(&self.setup, &self.cmd_tx, state);
+    }
+}
+
+fn poll_sensor(config: SensorConfig, mut tx: mpsc::Sender<f32>, session: Breaker) {
+    let mut i2c = match I2c::new() {
+        Ok(i2c) => i2c,
+        Err(e) => {
+            println!("i2c err: {}", e);
+            return;
+        }
+    };
+    let period = Duration::from_micros((1e6 / config.rate) as u64);
+    let mut buffer = vec![0; config.bytes];
+    while !session.test() {
+        match read_register(&mut i2c, config.address, config.register, &mut buffer) {
+            // drop values if nobody is consuming them
+            Ok(()) => {
+                let _ = tx.try_send(decode(&buffer) * config.scale);
+            }
+            Err(e) => println!("i2c read {:#x} err: {}", config.address, e),
+        }
+        thread::sleep(period);
+    }
+}
+
+#[test]
+fn test_gpio() {
+    assert_eq!(
+        InConfig::parse("17 down 5"),
+        Some(InConfig {
+            pin: 17,
+            pull: Pull::Down,
+            debounce: Duration::from_millis(5),
+        })
+    );
+    assert_eq!(InConfig::parse("17").map(|config| config.pull), Some(Pull::Up));
+    assert_eq!(InConfig::parse("17 sideways"), None);
+    assert_eq!(PwmConfig::parse("18").map(|config| config.frequency), Some(1000.0));
+    assert_eq!(PwmConfig::parse("18 0"), None);
+    let sensor = SensorConfig::parse("0x48 0x00 2 0.0078125").unwrap();
+    assert_eq!((sensor.address, sensor.register, sensor.bytes), (0x48, 0, 2));
+    assert_eq!(SensorConfig::parse("0x48 0x100"), None);
+
+    assert_eq!(decode(&[0x01, 0x00]), 256.0);
+    assert_eq!(decode(&[0xff, 0xfe]), -2.0);
+    assert_eq!(decode(&[0x80]), -128.0);
+
+    // bounces shorter than the debounce time are ignored
+    let ms = Duration::from_millis;
+    let mut debounce = Debounce::new(ms(10));
+    assert_eq!(debounce.update(true, ms(0)), None);
+    assert_eq!(debounce.update(false, ms(1)), None);
+    assert_eq!(debounce.update(true, ms(3)), None);
+    assert_eq!(debounce.update(false, ms(5)), None);
+    assert_eq!(debounce.update(false, ms(14)), None);
+    assert_eq!(debounce.update(false, ms(15)), Some(false));
+    assert_eq!(debounce.update(false, ms(30)), None);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+/// A config line and a button applying it, for all the modules here.
+struct SetupGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<String>,
+    /// What the button reads once a line is applied, or None if it doesn't parse.
+    describe: fn(&str) -> Option<String>,
+    usage: &'static str,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl SetupGui {
+    fn new(
+        ctx: &mut RenderContext,
+        bounds: Box3,
+        setup: &Mutex<Setup>,
+        cmd_tx: UnboundedSender<String>,
+        default: &str,
+        describe: fn(&str) -> Option<String>,
+        usage: &'static str,
+    ) -> SetupGui {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = setup.lock().unwrap().config.clone();
+        let label = config.as_ref().and_then(|config| describe(config)).unwrap_or("Apply".into());
+        SetupGui {
+            bounds,
+            config_box: TextBox::new(ctx.clone(), config.unwrap_or(default.into()), row(0.0)),
+            apply_button: Button::new(ctx.clone(), label, row(1.0)),
+            cmd_tx,
+            describe,
+            usage,
+        }
+    }
+}
+impl ModuleGui for GpioIn {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let describe = |line: &str| InConfig::parse(line).map(|config| format!("Pin {}", config.pin));
+        Box::new(SetupGui::new(
+            ctx,
+            bounds,
+            &self.setup,
+            self.cmd_tx.take().unwrap(),
+            "17 up 20",
+            describe,
+            "Invalid: pin [up|down|float [debounce ms]]",
+        ))
+    }
+}
+impl ModuleGui for GpioPwm {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let describe = |line: &str| {
+            PwmConfig::parse(line).map(|config| format!("Pin {} @ {} Hz", config.pin, config.frequency))
+        };
+        Box::new(SetupGui::new(
+            ctx,
+            bounds,
+            &self.setup,
+            self.cmd_tx.take().unwrap(),
+            "18 1000",
+            describe,
+            "Invalid: pin [frequency]",
+        ))
+    }
+}
+impl ModuleGui for I2cSensor {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let describe = |line: &str| {
+            SensorConfig::parse(line)
+                .map(|config| format!("{:#x} register {:#x}", config.address, config.register))
+        };
+        Box::new(SetupGui::new(
+            ctx,
+            bounds,
+            &self.setup,
+            self.cmd_tx.take().unwrap(),
+            "0x48 0x00 2 0.0078125 20",
+            describe,
+            "Invalid: address register [bytes [scale [rate]]]",
+        ))
+    }
+}
+impl GuiComponent<bool> for SetupGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let line = self.config_box.content().to_string();
+                match (self.describe)(&line) {
+                    Some(label) => {
+                        self.apply_button.set_label(label);
+                        self.cmd_tx.unbounded_send(line).unwrap();
+                    }
+                    None => self.apply_button.set_label(self.usage.into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//! Processing audio with a compute shader, through `wgpu`. Experimental.
+//!
+//! `GPU Shader` gathers `BATCH` frames from `Input` and hands them to the GPU together, since each
+//! trip there and back costs about as much as a lot of processing, then sends the processed frames
+//! on `Output` in order. That suits massively parallel work, like a long convolution or additive
+//! synthesis with thousands of partials, and delays the audio by a batch, which the module declares
+//! as its latency. Frames of a different shape from the batch before them start a new one.
+//!
+//! The shader is WGSL, read from the path in the module's state, or `shaders/passthrough.wgsl`,
+//! which documents how the samples are laid out. Its `main` runs once for every sample of the
+//! batch, in workgroups of `WORKGROUP` invocations. Without a GPU, with a shader that doesn't
+//! compile, or when a batch can't be read back from the GPU, frames pass through unchanged. Built
+//! with the `gpu` feature.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::executor::block_on;
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use wgpu;
+use wgpu::util::DeviceExt;
+
+use serde_json;
+
+use std::fs;
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Frames processed on the GPU at a time.
+pub const BATCH: usize = 8;
+/// Invocations in each workgroup, which shaders have to declare with `@workgroup_size`.
+pub const WORKGROUP: u32 = 64;
+pub const DEFAULT_SHADER: &str = include_str!("../../shaders/passthrough.wgsl");
+/// How often the GPU thread checks whether the module stopped.
+const POLL: Duration = Duration::from_millis(50);
+
+/// A compiled shader, with the device it runs on.
+pub struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Gpu {
+    /// Compile the WGSL `source` for the first GPU available.
+    pub fn new(source: &str) -> Result<Gpu, String> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .ok_or("no GPU adapter".to_string())?;
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))
+                .map_err(|e| format!("{}", e))?;
+        // a shader that doesn't compile is reported here rather than taking the process down
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("GPU Shader"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+        });
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!("{}", e));
+        }
+        Ok(Gpu {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+
+    /// Run the shader on `frames`, which must all have the same shape, and give the processed
+    /// frames. Fails if they can't be read back, e.g. when the device was lost.
+    pub fn run(&self, frames: &[Frame]) -> Result<Vec<Frame>, String> {
+        let (samples, channels) = match frames.first() {
+            Some(frame) => frame.data.dim(),
+            None => return Ok(Vec::new()),
+        };
+        let input: Vec<f32> = frames
+            .iter()
+            .flat_map(|frame| frame.data.iter().cloned())
+            .collect();
+        let size = (input.len() * 4) as wgpu::BufferAddress;
+        if size == 0 {
+            return Ok(frames.to_vec());
+        }
+        let header = [
+            samples as u32,
+            channels as u32,
+            frames.len() as u32,
+            frames[0].rate.to_bits(),
+        ];
+        let input_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("input"),
+            contents: &bytes(input.iter().map(|x| x.to_bits())),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let block_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("block"),
+            contents: &bytes(header.iter().cloned()),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("output"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: input_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: output_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: block_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            let invocations = (samples * frames.len()) as u32;
+            pass.dispatch_workgroups((invocations + WORKGROUP - 1) / WORKGROUP, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output_buffer, 0, &staging, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (mapped_tx, mapped_rx) = std_mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = mapped_tx.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        match mapped_rx.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(format!("{}", e)),
+            Err(_) => return Err("staging buffer was never mapped".to_string()),
+        }
+        let output: Vec<f32> = {
+            let mapped = slice.get_mapped_range();
+            mapped
+                .chunks(4)
+                .map(|b| {
+                    f32::from_bits(
+                        b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24,
+                    )
+                })
+                .collect()
+        };
+        staging.unmap();
+
+        Ok(frames
+            .iter()
+            .zip(output.chunks(samples * channels))
+            .map(|(frame, data)| {
+                let mut out = frame.clone();
+                for (x, &y) in out.data.iter_mut().zip(data) {
+                    *x = y;
+                }
+                out
+            })
+            .collect())
+    }
+}
+
+/// Little endian bytes of `words`, the layout of GPU buffers.
+fn bytes<I: Iterator<Item = u32>>(words: I) -> Vec<u8> {
+    words
+        .flat_map(|word| (0..4).map(move |byte| (word >> (byte * 8)) as u8))
+        .collect()
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Load(String),
+}
+
+/// Work for the GPU thread.
+enum Job {
+    Compile(String),
+    Run(Vec<Frame>),
+}
+
+pub struct GpuShader {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    path: Arc<Mutex<Option<String>>>,
+}
+
+impl Module for GpuShader {
+    fn new(ifc: Arc<flow::Interface>) -> GpuShader {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        GpuShader {
+            in_port: ifc.get_or_create_port("Input".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            path: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "GPU Shader"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (job_tx, job_rx) = std_mpsc::sync_channel(1);
+        let (out_tx, out_rx) = mpsc::channel(BATCH);
+        let breaker = self.breaker.clone();
+        thread::spawn(move || run_jobs(job_rx, out_tx, breaker));
+
+        let compile_tx = job_tx.clone();
+        let path = self.path.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Load(file) => match fs::read_to_string(&file) {
+                        Ok(source) => {
+                            *path.lock().unwrap() = Some(file);
+                            let _ = compile_tx.send(Job::Compile(source));
+                        }
+                        Err(e) => println!("gpu shader load {} err: {}", file, e),
+                    },
+                }
+            }
+        })
+        .unwrap();
+
+        let ifc = self.ifc.clone();
+        let mut batch: Vec<Frame> = Vec::with_capacity(BATCH);
+        util::start_sink(
+            self.in_port.clone(),
+            move |frame: Frame| {
+                if batch
+                    .first()
+                    .map_or(false, |first| first.data.dim() != frame.data.dim())
+                {
+                    let _ = job_tx.send(Job::Run(batch.split_off(0)));
+                }
+                if batch.is_empty() {
+                    ifc.set_latency(frame.data.dim().0 * BATCH);
+                }
+                batch.push(frame);
+                if batch.len() == BATCH {
+                    // waits while the GPU is busy with the batch before
+                    let _ = job_tx.send(Job::Run(batch.split_off(0)));
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        match *self.path.lock().unwrap() {
+            Some(ref path) => json!({ "path": path }),
+            None => serde_json::Value::Null,
+        }
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        if let (Some(path), Some(cmd_tx)) = (state["path"].as_str(), self.cmd_tx.as_ref()) {
+            // compiled once the module starts, and shown in the GUI meanwhile
+            *self.path.lock().unwrap() = Some(path.into());
+            cmd_tx.unbounded_send(UserCommand::Load(path.into())).unwrap();
+        }
+    }
+}
+
+/// Run the jobs of a module on its GPU, sending the frames processed on `out_tx`.
+fn run_jobs(jobs: std_mpsc::Receiver<Job>, mut out_tx: mpsc::Sender<Frame>, breaker: Breaker) {
+    let mut gpu = match Gpu::new(DEFAULT_SHADER) {
+        Ok(gpu) => Some(gpu),
+        Err(e) => {
+            println!("gpu shader err: {}", e);
+            None
+        }
+    };
+    while !breaker.test() {
+        match jobs.recv_timeout(POLL) {
+            Ok(Job::Compile(source)) => match Gpu::new(&source) {
+                Ok(compiled) => gpu = Some(compiled),
+                Err(e) => println!("gpu shader compile err: {}", e),
+            },
+            Ok(Job::Run(frames)) => {
+                let frames = match gpu {
+                    Some(ref gpu) => gpu.run(&frames).unwrap_or_else(|e| {
+                        println!("gpu shader run err: {}", e);
+                        frames
+                    }),
+                    None => frames,
+                };
+                for frame in frames {
+                    if block_on(out_tx.send(frame)).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(std_mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std_mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[test]
+fn test_gpu() {
+    assert_eq!(
+        bytes(vec![0x04030201, 0xff].into_iter()),
+        vec![1, 2, 3, 4, 0xff, 0, 0, 0]
+    );
+    // CI machines rarely have a GPU, so only check the shader where there is one
+    let gpu = match Gpu::new(DEFAULT_SHADER) {
+        Ok(gpu) => gpu,
+        Err(_) => return,
+    };
+    assert!(Gpu::new("not a shader").is_err());
+    let frame = Frame {
+        rate: 48000.0,
+        time: Some(0),
+        data: ::ndarray::Array2::from_shape_fn((64, 2), |(i, c)| i as f32 + c as f32 * 0.5),
+        meta: None,
+    };
+    let frames = vec![frame; 3];
+    let out = gpu.run(&frames).unwrap();
+    assert_eq!(out.len(), 3);
+    assert!(out.iter().zip(&frames).all(|(out, frame)| out.data == frame.data));
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct GpuGui {
+    bounds: Box3,
+    path_box: TextBox,
+    load_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for GpuShader {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let path = self
+            .path
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| "shader.wgsl".into());
+        Box::new(GpuGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            path_box: TextBox::new(ctx.clone(), path, row(0.0)),
+            load_button: Button::new(ctx.clone(), "Load".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for GpuGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.path_box.render(device, ctx);
+        self.load_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.path_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.load_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let path = self.path_box.content().trim().to_string();
+                if path.is_empty() {
+                    self.load_button.set_label("Invalid: path".into());
+                } else {
+                    self.load_button.set_label(format!("Loaded {}", path));
+                    self.cmd_tx.unbounded_send(UserCommand::Load(path)).unwrap();
+                }
+                true
+            }
+        }
+    }
+}
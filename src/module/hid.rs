@@ -0,0 +1,303 @@
+//! Human interface device inputs, so patches can be played without MIDI hardware.
+//!
+//! `Keyboard` forwards key events received by its GUI body while capture is enabled. `Gamepad`
+//! polls every connected controller through gilrs.
+
+use futures::channel::mpsc;
+use futures::task::Spawn;
+
+use gilrs;
+
+use crate::future_ext::Breaker;
+use crate::gui::event::VirtualKeyCode;
+use crate::module::{flow, util, Module};
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const GAMEPAD_POLL: Duration = Duration::from_millis(5);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyInput {
+    pub key: VirtualKeyCode,
+    pub pressed: bool,
+}
+
+pub struct Keyboard {
+    ifc: Arc<flow::Interface>,
+    keys_port: Arc<flow::Port<(), KeyInput>>,
+    chars_port: Arc<flow::Port<(), char>>,
+    keys_tx: Option<mpsc::Sender<KeyInput>>,
+    keys_rx: Option<mpsc::Receiver<KeyInput>>,
+    chars_tx: Option<mpsc::Sender<char>>,
+    chars_rx: Option<mpsc::Receiver<char>>,
+}
+
+impl Module for Keyboard {
+    fn new(ifc: Arc<flow::Interface>) -> Keyboard {
+        let keys_port = ifc.get_or_create_port("Keys".into());
+        let chars_port = ifc.get_or_create_port("Characters".into());
+        let (keys_tx, keys_rx) = mpsc::channel(64);
+        let (chars_tx, chars_rx) = mpsc::channel(64);
+        Keyboard {
+            ifc,
+            keys_port,
+            chars_port,
+            keys_tx: Some(keys_tx),
+            keys_rx: Some(keys_rx),
+            chars_tx: Some(chars_tx),
+            chars_rx: Some(chars_rx),
+        }
+    }
+    fn name() -> &'static str {
+        "Keyboard"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        util::start_source(self.keys_rx.take().unwrap(), self.keys_port.clone(), &exec);
+        util::start_source(self.chars_rx.take().unwrap(), self.chars_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        // the source tasks end once the GUI body drops its senders too
+        self.keys_tx = None;
+        self.chars_tx = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadButton {
+    pub gamepad: usize,
+    pub button: String,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadAxis {
+    pub gamepad: usize,
+    pub axis: String,
+    pub value: f32,
+}
+
+pub struct Gamepad {
+    ifc: Arc<flow::Interface>,
+    buttons_port: Arc<flow::Port<(), GamepadButton>>,
+    axes_port: Arc<flow::Port<(), GamepadAxis>>,
+    breaker: Breaker,
+}
+
+impl Module for Gamepad {
+    fn new(ifc: Arc<flow::Interface>) -> Gamepad {
+        let buttons_port = ifc.get_or_create_port("Buttons".into());
+        let axes_port = ifc.get_or_create_port("Axes".into());
+        Gamepad {
+            ifc,
+            buttons_port,
+            axes_port,
+            breaker: Breaker::new(),
+        }
+    }
+    fn name() -> &'static str {
+        "Gamepad"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (buttons_tx, buttons_rx) = mpsc::channel(64);
+        let (axes_tx, axes_rx) = mpsc::channel(64);
+        let breaker = self.breaker.clone();
+        thread::spawn(move || poll_gamepads(buttons_tx, axes_tx, breaker));
+        util::start_source(buttons_rx, self.buttons_port.clone(), &exec);
+        util::start_source(axes_rx, self.axes_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+fn poll_gamepads(
+    mut buttons: mpsc::Sender<GamepadButton>,
+    mut axes: mpsc::Sender<GamepadAxis>,
+    breaker: Breaker,
+) {
+    // gilrs is not Send, so it lives entirely on this thread
+    let mut gilrs = match gilrs::Gilrs::new() {
+        Ok(gilrs) => gilrs,
+        Err(e) => {
+            println!("gamepad init err: {:?}", e);
+            return;
+        }
+    };
+    while !breaker.test() {
+        while let Some(gilrs::Event {
+            id,
+            event,
+            ..
+        }) = gilrs.next_event()
+        {
+            let gamepad = id.into();
+            // drop events if nobody is consuming them
+            match event {
+                gilrs::EventType::ButtonPressed(button, _) => {
+                    let _ = buttons.try_send(GamepadButton {
+                        gamepad,
+                        button: format!("{:?}", button),
+                        pressed: true,
+                    });
+                }
+                gilrs::EventType::ButtonReleased(button, _) => {
+                    let _ = buttons.try_send(GamepadButton {
+                        gamepad,
+                        button: format!("{:?}", button),
+                        pressed: false,
+                    });
+                }
+                gilrs::EventType::AxisChanged(axis, value, _) => {
+                    let _ = axes.try_send(GamepadAxis {
+                        gamepad,
+                        axis: format!("{:?}", axis),
+                        value,
+                    });
+                }
+                _ => {}
+            }
+        }
+        thread::sleep(GAMEPAD_POLL);
+    }
+}
+
+#[test]
+fn test_keyboard() {
+    let graph = flow::Graph::new();
+    let ifc = graph.add_node();
+    let mut keyboard = Keyboard::new(ifc.clone());
+    assert!(ifc.find_port::<(), KeyInput>("Keys").is_some());
+    assert!(ifc.find_port::<(), char>("Characters").is_some());
+
+    let mut capture = KeyCapture {
+        capturing: false,
+        keys_tx: keyboard.keys_tx.clone().unwrap(),
+        chars_tx: keyboard.chars_tx.clone().unwrap(),
+    };
+    let key = |state| {
+        EventData::Key(KeyEvent {
+            code: VirtualKeyCode::A,
+            modifiers: KeyModifiers {
+                shift: false,
+                ctrl: false,
+                alt: false,
+                logo: false,
+            },
+            state,
+        })
+    };
+    let mut next_key = || keyboard.keys_rx.as_mut().unwrap().try_next().ok().and_then(|key| key);
+
+    // nothing is sent until capture is switched on
+    capture.handle(&key(ButtonState::Pressed));
+    assert_eq!(next_key(), None);
+    capture.capturing = true;
+    capture.handle(&key(ButtonState::Pressed));
+    capture.handle(&EventData::MouseMove(Pt2::new(1.0, 1.0)));
+    capture.handle(&key(ButtonState::Released));
+    let pressed = |pressed| {
+        Some(KeyInput {
+            key: VirtualKeyCode::A,
+            pressed,
+        })
+    };
+    assert_eq!(next_key(), pressed(true));
+    assert_eq!(next_key(), pressed(false));
+    assert_eq!(next_key(), None);
+
+    capture.handle(&EventData::Character('a'));
+    assert_eq!(keyboard.chars_rx.as_mut().unwrap().try_next().ok(), Some(Some('a')));
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*};
+/// Passes the key events the body gets on to the ports, while capturing.
+struct KeyCapture {
+    capturing: bool,
+    keys_tx: mpsc::Sender<KeyInput>,
+    chars_tx: mpsc::Sender<char>,
+}
+impl KeyCapture {
+    fn handle(&mut self, event: &EventData) {
+        match *event {
+            EventData::Key(kev) if self.capturing => {
+                let _ = self.keys_tx.try_send(KeyInput {
+                    key: kev.code,
+                    pressed: kev.state == ButtonState::Pressed,
+                });
+            }
+            EventData::Character(ch) if self.capturing => {
+                let _ = self.chars_tx.try_send(ch);
+            }
+            _ => {}
+        }
+    }
+}
+struct KeyboardGui {
+    bounds: Box3,
+    capture_button: Button,
+    capture: KeyCapture,
+}
+const PADDING: f32 = 4.0;
+impl KeyboardGui {
+    fn label(capturing: bool) -> String {
+        if capturing {
+            "Capturing keys".into()
+        } else {
+            "Capture keys".into()
+        }
+    }
+}
+impl ModuleGui for Keyboard {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let keys_tx = self.keys_tx.as_ref().unwrap().clone();
+        let chars_tx = self.chars_tx.as_ref().unwrap().clone();
+        Box::new(KeyboardGui {
+            bounds,
+            capture_button: Button::new(
+                ctx.clone(),
+                KeyboardGui::label(false),
+                Box3 {
+                    pos: bounds.pos + Pt3::new(PADDING, PADDING, 0.0),
+                    size: Pt3::new(bounds.size.x - PADDING * 2.0, 26.0, 0.0),
+                },
+            ),
+            capture: KeyCapture {
+                capturing: false,
+                keys_tx,
+                chars_tx,
+            },
+        })
+    }
+}
+impl GuiComponent<bool> for KeyboardGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.capture_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        self.capture.handle(&event.data);
+        match self.capture_button.handle(event) {
+            ButtonUpdate::Unchanged => false,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                self.capture.capturing = !self.capture.capturing;
+                self.capture_button.set_label(KeyboardGui::label(self.capture.capturing));
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Embedded HTTP endpoint for kiosks and dashboards.
+//!
+//! Every request (other than `GET /values`) becomes a `Request` on the output port. The latest
+//! value seen on the `Watch` input is served as JSON from `GET /values`.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use serde_json;
+
+use crate::future_ext::Breaker;
+use crate::module::{flow, util, Module};
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// An incoming request, with the body parsed as JSON when possible.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: serde_json::Value,
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Listen(String),
+}
+
+pub struct Webhook {
+    ifc: Arc<flow::Interface>,
+    out_port: Arc<flow::Port<(), Request>>,
+    watch_port: Arc<flow::Port<serde_json::Value, ()>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    server: Arc<Mutex<Option<Breaker>>>,
+    latest: Arc<Mutex<serde_json::Value>>,
+}
+
+impl Drop for Webhook {
+    fn drop(&mut self) {
+        self.server.lock().unwrap().take().map(|server| server.brake());
+    }
+}
+
+impl Module for Webhook {
+    fn new(ifc: Arc<flow::Interface>) -> Webhook {
+        let out_port = ifc.get_or_create_port("Output".into());
+        let watch_port = ifc.get_or_create_port("Watch".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Webhook {
+            ifc,
+            out_port,
+            watch_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            server: Arc::default(),
+            latest: Arc::new(Mutex::new(serde_json::Value::Null)),
+        }
+    }
+    fn name() -> &'static str {
+        "Webhook"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (req_tx, req_rx) = mpsc::channel(64);
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        let server_handle = self.server.clone();
+        let latest = self.latest.clone();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Listen(addr) => {
+                        let listener = match TcpListener::bind(&addr[..]) {
+                            Ok(listener) => listener,
+                            Err(e) => {
+                                println!("http bind {} err: {:?}", addr, e);
+                                continue;
+                            }
+                        };
+                        // only one listener at a time
+                        let server = Breaker::new();
+                        let mut server_handle = server_handle.lock().unwrap();
+                        server_handle.take().map(|old| old.brake());
+                        *server_handle = Some(server.clone());
+
+                        let req_tx = req_tx.clone();
+                        let latest = latest.clone();
+                        thread::spawn(move || serve(listener, req_tx, latest, server));
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let latest = self.latest.clone();
+        util::start_sink(
+            self.watch_port.clone(),
+            move |value| *latest.lock().unwrap() = value,
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(req_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.server.lock().unwrap().take().map(|server| server.brake());
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+fn serve(
+    listener: TcpListener,
+    mut tx: mpsc::Sender<Request>,
+    latest: Arc<Mutex<serde_json::Value>>,
+    breaker: Breaker,
+) {
+    // poll so that we notice when the breaker is braked
+    listener.set_nonblocking(true).unwrap();
+    while !breaker.test() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = handle_connection(stream, &mut tx, &latest) {
+                    println!("http err: {:?}", e);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => {
+                println!("http accept err: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    tx: &mut mpsc::Sender<Request>,
+    latest: &Mutex<serde_json::Value>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let request = match parse_request(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            respond(&mut stream, "400 Bad Request", "{}")?;
+            return Err(e);
+        }
+    };
+    if request.method == "GET" && request.path == "/values" {
+        let body = json_object("Watch", &latest.lock().unwrap());
+        respond(&mut stream, "200 OK", &body)
+    } else if tx.try_send(request).is_ok() {
+        respond(&mut stream, "202 Accepted", "{}")
+    } else {
+        // nobody is consuming the events fast enough
+        respond(&mut stream, "503 Service Unavailable", "{}")
+    }
+}
+
+fn json_object(key: &str, value: &serde_json::Value) -> String {
+    let mut map = serde_json::Map::new();
+    map.insert(key.into(), value.clone());
+    serde_json::Value::Object(map).to_string()
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn parse_request<R: Read>(stream: &mut R) -> io::Result<Request> {
+    let invalid = |msg: &'static str| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    // read until the end of the headers
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let header_end = loop {
+        if let Some(idx) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break idx;
+        }
+        if buf.len() > MAX_HEADER_SIZE {
+            return Err(invalid("headers too large"));
+        }
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Err(invalid("connection closed"));
+        }
+        buf.extend(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().ok_or_else(|| invalid("missing method"))?;
+    let path = request_line.next().ok_or_else(|| invalid("missing path"))?;
+    let content_length = lines
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next()?.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                parts.next()?.trim().parse::<usize>().ok()
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap_or(0);
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("body too large"));
+    }
+
+    let mut body = buf.split_off(header_end + 4);
+    if body.len() < content_length {
+        let already = body.len();
+        body.resize(content_length, 0);
+        stream.read_exact(&mut body[already..])?;
+    }
+    body.truncate(content_length);
+
+    let body = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(&body)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&body).into()))
+    };
+    Ok(Request {
+        method: method.into(),
+        path: path.into(),
+        body,
+    })
+}
+
+#[test]
+fn test_parse_request() {
+    use std::io::Cursor;
+
+    let parse = |raw: &str| parse_request(&mut Cursor::new(raw.as_bytes().to_vec()));
+    let request = parse("POST /hook HTTP/1.1\r\nContent-Length: 9\r\n\r\n{\"a\": 1}\n").unwrap();
+    assert_eq!((&request.method[..], &request.path[..]), ("POST", "/hook"));
+    assert_eq!(request.body, json!({"a": 1}));
+
+    // bodies that aren't JSON come through as text, past the content length is ignored
+    let request = parse("PUT /x HTTP/1.1\r\ncontent-length: 5\r\n\r\nhello world").unwrap();
+    assert_eq!(request.body, json!("hello"));
+    assert_eq!(parse("GET / HTTP/1.1\r\n\r\n").unwrap().body, serde_json::Value::Null);
+
+    assert!(parse("GET / HTTP/1.1\r\n").is_err());
+    assert!(parse("GET / HTTP/1.1\r\nContent-Length: 20\r\n\r\nshort").is_err());
+    let huge = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+    assert!(parse(&huge).is_err());
+}
+
+#[test]
+fn test_webhook() {
+    use std::net::Shutdown;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (mut tx, mut rx) = mpsc::channel(0);
+    let latest = Mutex::new(json!({"level": 3}));
+    let mut send = |raw: &'static str| {
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let _ = handle_connection(stream, &mut tx, &latest);
+        client.join().unwrap()
+    };
+
+    // the watched value is served, everything else is passed on
+    let response = send("GET /values HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with("{\"Watch\":{\"level\":3}}"));
+    assert!(send("POST /go HTTP/1.1\r\nContent-Length: 2\r\n\r\n[]").starts_with("HTTP/1.1 202"));
+    let request = rx.try_next().unwrap().unwrap();
+    assert_eq!((&request.path[..], request.body), ("/go", json!([])));
+
+    // requests nobody is reading are refused rather than queued without bound
+    send("POST /a HTTP/1.1\r\n\r\n");
+    assert!(send("POST /b HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 503"));
+    assert!(send("nonsense").starts_with("HTTP/1.1 400"));
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct WebhookGui {
+    bounds: Box3,
+    addr_box: TextBox,
+    listen_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Webhook {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(WebhookGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            addr_box: TextBox::new(ctx.clone(), "127.0.0.1:8080".into(), row(0.0)),
+            listen_button: Button::new(ctx.clone(), "Listen".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for WebhookGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.addr_box.render(device, ctx);
+        self.listen_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.addr_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.listen_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let addr = self.addr_box.content().to_string();
+                self.listen_button.set_label(format!("Listening on {}", addr));
+                self.cmd_tx.unbounded_send(UserCommand::Listen(addr)).unwrap();
+                true
+            }
+        }
+    }
+}
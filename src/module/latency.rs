@@ -0,0 +1,178 @@
+//! Latency accounting and compensation.
+//!
+//! Modules declare how long they delay their data with `Interface::set_latency`. Summing those
+//! along every path through the graph shows how far out of step parallel paths are by the time they
+//! meet, and how much delay each input needs to line them back up, like plugin delay compensation
+//! in a DAW.
+
+use crate::module::audio_io::Frame;
+use crate::module::flow::{Direction, Graph, NodeId, PortRef};
+
+use ndarray::Array2;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// A connection along which latency accumulates, from an output port to an input port.
+struct Edge {
+    from: NodeId,
+    to: PortRef,
+}
+
+fn edges(graph: &Graph) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    for node in graph.nodes() {
+        for port in node.ports() {
+            let meta = port.meta();
+            if meta.direction != Some(Direction::Output) || meta.feedback_delay {
+                continue;
+            }
+            if let Some(other) = port.edge() {
+                if other.meta().direction != Some(Direction::Output) && !other.meta().feedback_delay {
+                    edges.push(Edge {
+                        from: node.id(),
+                        to: other.port_ref(),
+                    });
+                }
+            }
+        }
+    }
+    edges
+}
+
+/// The total latency of the data leaving each node, in samples: the longest path from any source,
+/// including the node's own latency. Nodes caught in a cycle without a feedback delay are omitted.
+pub fn path_latencies(graph: &Graph) -> BTreeMap<NodeId, usize> {
+    let edges = edges(graph);
+    let nodes = graph.node_map();
+
+    // Kahn's algorithm, so every node is visited after everything upstream of it
+    let mut in_degree: HashMap<NodeId, usize> = nodes.keys().map(|&id| (id, 0)).collect();
+    for edge in &edges {
+        *in_degree.get_mut(&edge.to.node).unwrap() += 1;
+    }
+    let mut ready: VecDeque<_> = in_degree
+        .iter()
+        .filter(|&(_, &degree)| degree == 0)
+        .map(|(&id, _)| id)
+        .collect();
+    let mut arrival: HashMap<NodeId, usize> = HashMap::new();
+    let mut latencies = BTreeMap::new();
+    while let Some(id) = ready.pop_front() {
+        let latency = arrival.get(&id).cloned().unwrap_or(0) + nodes[&id].latency();
+        latencies.insert(id, latency);
+        for edge in edges.iter().filter(|edge| edge.from == id) {
+            let to = edge.to.node;
+            let best = arrival.entry(to).or_insert(0);
+            *best = (*best).max(latency);
+            let degree = in_degree.get_mut(&to).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.push_back(to);
+            }
+        }
+    }
+    latencies
+}
+
+/// How many samples of delay each connected input port needs so that everything arriving at a node
+/// lines up with its slowest input. Ports that need no delay are omitted.
+pub fn compensation(graph: &Graph) -> BTreeMap<PortRef, usize> {
+    let latencies = path_latencies(graph);
+    let edges: Vec<_> = edges(graph)
+        .into_iter()
+        .filter_map(|edge| latencies.get(&edge.from).map(|&latency| (edge.to, latency)))
+        .collect();
+    let mut slowest: HashMap<NodeId, usize> = HashMap::new();
+    for &(to, latency) in &edges {
+        let best = slowest.entry(to.node).or_insert(0);
+        *best = (*best).max(latency);
+    }
+    edges
+        .into_iter()
+        .filter_map(|(to, latency)| {
+            let delay = slowest[&to.node] - latency;
+            if delay > 0 {
+                Some((to, delay))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Delays a stream of frames by a fixed number of samples, for compensating a fast path.
+pub struct FrameDelay {
+    samples: usize,
+    /// Per channel history, oldest first.
+    history: Vec<VecDeque<f32>>,
+}
+
+impl FrameDelay {
+    pub fn new(samples: usize) -> FrameDelay {
+        FrameDelay {
+            samples,
+            history: Vec::new(),
+        }
+    }
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        if self.samples == 0 {
+            return frame;
+        }
+        let (len, channels) = frame.data.dim();
+        if self.history.len() != channels {
+            self.history = vec![(0..self.samples).map(|_| 0.0).collect(); channels];
+        }
+        let mut data = Array2::zeros((len, channels));
+        for (channel, history) in self.history.iter_mut().enumerate() {
+            for i in 0..len {
+                history.push_back(frame.data[[i, channel]]);
+                data[[i, channel]] = history.pop_front().unwrap();
+            }
+        }
+        Frame {
+            data,
+            ..frame
+        }
+    }
+}
+
+#[test]
+fn test_compensation() {
+    use crate::module::flow::PortMeta;
+
+    // a source feeding a mixer both directly and through a slow effect
+    let graph = Graph::new();
+    let source = graph.add_node();
+    let effect = graph.add_node();
+    let mixer = graph.add_node();
+    effect.set_latency(64);
+
+    let source_out = source.get_or_create_group::<(), f32, _>("Out", &["1", "2"]);
+    let effect_in = effect.get_or_create_port::<f32, ()>("In".into());
+    let effect_out = effect.get_or_create_port::<(), f32>("Out".into());
+    let dry = mixer.get_or_create_port::<f32, ()>("Dry".into());
+    let wet = mixer.get_or_create_port::<f32, ()>("Wet".into());
+    source_out.ports()[0].connect(&effect_in).unwrap();
+    source_out.ports()[1].connect(&dry).unwrap();
+    effect_out.connect(&wet).unwrap();
+
+    let latencies = path_latencies(&graph);
+    assert_eq!(latencies[&effect.id()], 64);
+    assert_eq!(latencies[&mixer.id()], 64);
+    let compensation = compensation(&graph);
+    assert_eq!(compensation.len(), 1);
+    assert_eq!(compensation[&dry.port_ref()], 64);
+
+    // a feedback delay keeps a loop from hiding the rest of the graph
+    let feedback = effect.get_or_create_port::<f32, ()>("Feedback".into());
+    let send = mixer.get_or_create_port::<(), f32>("Send".into());
+    feedback.set_meta(PortMeta {
+        feedback_delay: true,
+        ..feedback.meta()
+    });
+    send.connect(&feedback).unwrap();
+    assert_eq!(path_latencies(&graph).len(), 3);
+}
@@ -0,0 +1,221 @@
+//! Brick-wall limiter for protecting ears and speakers, built with `Process`.
+//!
+//! Meant to sit just before the audio interface. The limiter looks `LOOKAHEAD` samples ahead, so
+//! the gain comes down smoothly before a peak arrives and nothing gets past the ceiling. A patch
+//! caught in runaway feedback keeps the gain pulled down for as long as it howls, so once the
+//! reduction has stayed deeper than `MUTE_REDUCTION` for the `Mute After` time, the output is
+//! muted outright until the input has stayed under the ceiling for `UNMUTE_SECONDS`.
+
+use crate::module::audio_io::Frame;
+use crate::module::process::Process;
+
+use std::collections::VecDeque;
+
+/// Samples the output is delayed by, giving the gain this long to come down.
+pub const LOOKAHEAD: usize = 256;
+/// Seconds for the gain to mostly recover after a peak.
+const RELEASE: f32 = 0.1;
+/// Gain below which the input counts as a sustained over.
+const MUTE_REDUCTION: f32 = 0.5;
+/// Seconds of input under the ceiling before a muted limiter lets sound through again.
+const UNMUTE_SECONDS: f32 = 2.0;
+
+pub struct Limiter {
+    /// The highest level let through, as an amplitude.
+    ceiling: f32,
+    /// Seconds of sustained overs before muting, or zero to never mute.
+    mute_after: f32,
+    rate: f32,
+    channels: usize,
+    /// Input waiting to be output, interleaved.
+    delay: VecDeque<f32>,
+    /// The gain each sample needs, dropping at once and recovering over `RELEASE`.
+    envelope: f32,
+    /// Increasing envelope values, with the sample they were seen at, so the front is the lowest
+    /// over the lookahead window.
+    minima: VecDeque<(usize, f32)>,
+    /// The minima over the last window, averaged to smooth the gain.
+    window: VecDeque<f32>,
+    window_sum: f64,
+    position: usize,
+    /// Samples the envelope has stayed below `MUTE_REDUCTION`.
+    reduced_for: usize,
+    /// Samples the input has stayed under the ceiling.
+    quiet_for: usize,
+    muted: bool,
+}
+
+impl Limiter {
+    /// Whether sustained overs have muted the output.
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+    /// Start over for a new rate or channel count, with the gain at rest.
+    fn reset(&mut self, rate: f32, channels: usize) {
+        self.rate = rate;
+        self.channels = channels;
+        self.delay = vec![0.0; LOOKAHEAD * channels].into_iter().collect();
+        self.envelope = 1.0;
+        self.minima.clear();
+        self.window = vec![1.0; LOOKAHEAD + 1].into_iter().collect();
+        self.window_sum = (LOOKAHEAD + 1) as f64;
+        self.reduced_for = 0;
+        self.quiet_for = 0;
+        self.muted = false;
+    }
+    /// Take in the peak of the next input sample, returning the gain for the sample leaving the
+    /// delay line.
+    fn gain(&mut self, peak: f32, release: f32) -> f32 {
+        let required = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+        self.envelope = if required < self.envelope {
+            required
+        } else {
+            self.envelope + (required - self.envelope) * release
+        };
+
+        // the lowest envelope over the window reaching from the delayed sample to the newest
+        while self
+            .minima
+            .back()
+            .map_or(false, |&(_, value)| value >= self.envelope)
+        {
+            self.minima.pop_back();
+        }
+        self.minima.push_back((self.position, self.envelope));
+        while self
+            .minima
+            .front()
+            .map_or(false, |&(idx, _)| idx + LOOKAHEAD < self.position)
+        {
+            self.minima.pop_front();
+        }
+        let lowest = self.minima.front().unwrap().1;
+        self.position += 1;
+
+        // every minimum averaged here covers the delayed sample, so the average is low enough
+        self.window.push_back(lowest);
+        self.window_sum += lowest as f64 - self.window.pop_front().unwrap() as f64;
+
+        self.reduced_for = if self.envelope < MUTE_REDUCTION {
+            self.reduced_for + 1
+        } else {
+            0
+        };
+        self.quiet_for = if required < 1.0 { 0 } else { self.quiet_for + 1 };
+        let sustained = self.reduced_for as f32 > self.mute_after * self.rate;
+        if self.mute_after > 0.0 && sustained && !self.muted {
+            println!("limiter: muting after sustained overs");
+            self.muted = true;
+        }
+        if self.muted && self.quiet_for as f32 > UNMUTE_SECONDS * self.rate {
+            self.muted = false;
+        }
+        (self.window_sum / (LOOKAHEAD + 1) as f64) as f32
+    }
+}
+
+impl Process for Limiter {
+    const NAME: &'static str = "Limiter";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] = &[("Ceiling dB", -1.0), ("Mute After", 1.0)];
+    const LATENCY: usize = LOOKAHEAD;
+    fn new() -> Limiter {
+        Limiter {
+            ceiling: 10f32.powf(-1.0 / 20.0),
+            mute_after: 1.0,
+            rate: 0.0,
+            channels: 0,
+            delay: VecDeque::new(),
+            envelope: 1.0,
+            minima: VecDeque::new(),
+            window: VecDeque::new(),
+            window_sum: 0.0,
+            position: 0,
+            reduced_for: 0,
+            quiet_for: 0,
+            muted: false,
+        }
+    }
+    fn set_param(&mut self, idx: usize, value: f32) {
+        match idx {
+            0 => self.ceiling = 10f32.powf(value.min(0.0) / 20.0),
+            _ => self.mute_after = value.max(0.0),
+        }
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let input = &inputs[0];
+        let channels = input.data.dim().1;
+        if input.rate != self.rate || channels != self.channels {
+            self.reset(input.rate, channels);
+        }
+        let release = 1.0 - (-1.0 / (RELEASE * input.rate)).exp();
+        for (samples, mut out) in input.data.outer_iter().zip(outputs[0].data.outer_iter_mut()) {
+            let peak = samples.iter().fold(0.0f32, |peak, &x| peak.max(x.abs()));
+            let gain = self.gain(peak, release);
+            let gain = if self.muted { 0.0 } else { gain };
+            self.delay.extend(samples.iter());
+            for out in out.iter_mut() {
+                let x = self.delay.pop_front().unwrap() * gain;
+                // rounding in the average could leave the smallest overshoot
+                *out = x.max(-self.ceiling).min(self.ceiling);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_limiter() {
+    use ndarray::Array2;
+
+    let rate = 1000.0;
+    let sine = |amplitude: f32, start: usize| Frame {
+        rate,
+        time: None,
+        data: Array2::from_shape_fn((100, 2), |(i, _)| amplitude * ((start + i) as f32 * 0.3).sin()),
+        meta: None,
+    };
+    let mut limiter = Limiter::new();
+    limiter.set_param(0, -6.0);
+    let ceiling = 10f32.powf(-6.0 / 20.0);
+    let mut outputs = [sine(0.0, 0)];
+    let mut loudest: f32 = 0.0;
+    for block in 0..10 {
+        limiter.process(&[sine(4.0, block * 100)], &mut outputs);
+        loudest = outputs[0].data.iter().fold(loudest, |peak, &x| peak.max(x.abs()));
+    }
+    assert!(loudest <= ceiling && loudest > 0.9 * ceiling);
+    assert!(!limiter.muted());
+
+    // an impulse under the ceiling comes out unchanged, a lookahead later
+    let mut limiter = Limiter::new();
+    let mut impulse = sine(0.0, 0);
+    impulse.data[[0, 0]] = 0.5;
+    let mut delayed = Vec::new();
+    for block in 0..3 {
+        limiter.process(
+            &[if block == 0 { impulse.clone() } else { sine(0.0, 0) }],
+            &mut outputs,
+        );
+        delayed.extend(outputs[0].data.column(0).iter().cloned());
+    }
+    assert_eq!(delayed[LOOKAHEAD], 0.5);
+    assert_eq!(delayed.iter().filter(|&&x| x != 0.0).count(), 1);
+
+    // feedback that won't stop gets muted, and comes back once it does
+    let mut limiter = Limiter::new();
+    limiter.set_param(1, 0.5);
+    for block in 0..10 {
+        limiter.process(&[sine(8.0, block * 100)], &mut outputs);
+    }
+    assert!(limiter.muted());
+    assert!(outputs[0].data.iter().all(|&x| x == 0.0));
+    for _ in 0..25 {
+        limiter.process(&[sine(0.1, 0)], &mut outputs);
+    }
+    assert!(!limiter.muted());
+}
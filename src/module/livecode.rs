@@ -1,12 +1,11 @@
 use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use futures::executor;
-use futures::future;
 use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
 
 use notify::*;
 
-use future_ext::{Breaker, FutureWrapExt};
-use module::{audio_io::Frame, flow, Module};
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
 
 use std::path::{Path, PathBuf};
 use std::process;
@@ -14,84 +13,6 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-fn start_simple_processor<F: FnMut(Frame) -> Frame + Send + 'static, Ex: executor::Executor>(
-    processor: F,
-    in_port: Arc<flow::Port<Frame, ()>>,
-    out_port: Arc<flow::Port<(), Frame>>,
-    breaker: Breaker,
-    mut exec: Ex,
-) {
-    exec.spawn(Box::new(future::loop_fn(
-        (processor, in_port, out_port, breaker),
-        |(processor, in_port, out_port, breaker)| {
-            in_port
-                .write1(())
-                .wrap((processor, out_port, breaker))
-                .map_err(|((processor, out_port, breaker), (in_port, err))| {
-                    (
-                        processor,
-                        in_port,
-                        out_port,
-                        breaker,
-                        format!("in write1 {:?}", err),
-                    )
-                })
-                .and_then(|((processor, out_port, breaker), in_port)| {
-                    in_port.read1().wrap((processor, out_port, breaker)).map_err(
-                        |((processor, out_port, breaker), (in_port, err))| {
-                            (
-                                processor,
-                                in_port,
-                                out_port,
-                                breaker,
-                                format!("in read1 {:?}", err),
-                            )
-                        },
-                    )
-                })
-                .and_then(|((processor, out_port, breaker), (in_port, frame))| {
-                    out_port
-                        .read1()
-                        .wrap((processor, in_port, breaker, frame))
-                        .map_err(|((processor, in_port, breaker, frame), (out_port, err))| {
-                            (
-                                processor,
-                                in_port,
-                                out_port,
-                                breaker,
-                                format!("out read1 {:?}", err),
-                            )
-                        })
-                })
-                .and_then(move |((mut processor, in_port, breaker, frame), (out_port, _))| {
-                    out_port
-                        .write1(processor(frame))
-                        .wrap((processor, in_port, breaker))
-                        .map_err(|((processor, in_port, breaker), (out_port, err))| {
-                            (
-                                processor,
-                                in_port,
-                                out_port,
-                                breaker,
-                                format!("out write1 {:?}", err),
-                            )
-                        })
-                })
-                .recover(|(processor, in_port, out_port, breaker, err)| {
-                    println!("err: {}", err);
-                    ((processor, in_port, breaker), out_port)
-                })
-                .map(|((processor, in_port, breaker), out_port)| {
-                    if breaker.test() {
-                        future::Loop::Break(())
-                    } else {
-                        future::Loop::Continue((processor, in_port, out_port, breaker))
-                    }
-                })
-        },
-    ))).unwrap();
-}
-
 #[derive(Debug)]
 enum UserCommand {
     NewFile(String),
@@ -134,61 +55,59 @@ impl Module for LiveCode {
         }
     }
 
-    fn start<Ex: executor::Executor>(&mut self, mut exec: Ex) {
-        let cmd_rx = self.cmd_rx.take().unwrap();
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
         let watcher_handle = self.watcher.clone();
         let child_handle = self.child.clone();
-        exec.spawn(Box::new(
-            cmd_rx
-                .for_each(move |event| {
-                    println!("event {:?}", event);
-                    match event {
-                        UserCommand::NewFile(filename) => {
-                            let (tx, rx) = ::std::sync::mpsc::channel();
-                            let mut watcher: RecommendedWatcher =
-                                Watcher::new(tx, Duration::from_secs(1)).unwrap();
-                            // watch parent dir and filter later, because if we just watch the file and
-                            // it gets removed it will stop watching it
-                            let parent_dir = Path::new(&filename).parent().unwrap();
-                            watcher.watch(parent_dir, RecursiveMode::NonRecursive).unwrap();
-                            // store it globally because otherwise it gets dropped and stops watching
-                            *watcher_handle.lock().unwrap() = Some(watcher);
-                            let child_handle = child_handle.clone();
-                            spawn_child(&child_handle, PathBuf::from(&filename));
+        exec.spawn(async move {
+            while let Some(event) = cmd_rx.next().await {
+                println!("event {:?}", event);
+                match event {
+                    UserCommand::NewFile(filename) => {
+                        let (tx, rx) = ::std::sync::mpsc::channel();
+                        let mut watcher: RecommendedWatcher =
+                            Watcher::new(tx, Duration::from_secs(1)).unwrap();
+                        // watch parent dir and filter later, because if we just watch the file and
+                        // it gets removed it will stop watching it
+                        let parent_dir = Path::new(&filename).parent().unwrap();
+                        watcher.watch(parent_dir, RecursiveMode::NonRecursive).unwrap();
+                        // store it globally because otherwise it gets dropped and stops watching
+                        *watcher_handle.lock().unwrap() = Some(watcher);
+                        let child_handle = child_handle.clone();
+                        spawn_child(&child_handle, PathBuf::from(&filename));
 
-                            // TODO
-                            // This thread gets leaked, as does the thread spawned internally inside
-                            // the watcher... the notify crate is not cleaning up properly.
-                            // Not sure if it's mio that's broken or what.
-                            thread::spawn(move || loop {
-                                match rx.recv() {
-                                    Ok(event) => {
-                                        println!("{:?}", event);
-                                        match event {
-                                            DebouncedEvent::Write(path) => {
-                                                if path.to_str().unwrap() != filename {
-                                                    continue;
-                                                }
-                                                spawn_child(&child_handle, path);
+                        // TODO
+                        // This thread gets leaked, as does the thread spawned internally inside
+                        // the watcher... the notify crate is not cleaning up properly.
+                        // Not sure if it's mio that's broken or what.
+                        thread::spawn(move || loop {
+                            match rx.recv() {
+                                Ok(event) => {
+                                    println!("{:?}", event);
+                                    match event {
+                                        DebouncedEvent::Write(path) => {
+                                            if path.to_str().unwrap() != filename {
+                                                continue;
                                             }
-                                            _ => {}
+                                            spawn_child(&child_handle, path);
                                         }
+                                        _ => {}
                                     }
-                                    Err(e) => {
-                                        println!("Watcher thread done: {:?}", e);
-                                        return;
-                                    }
                                 }
-                            });
-                        }
+                                Err(e) => {
+                                    println!("Watcher thread done: {:?}", e);
+                                    return;
+                                }
+                            }
+                        });
                     }
-                    Ok(())
-                })
-                .then(|x| Ok(())),
-        )).unwrap();
+                }
+            }
+        })
+        .unwrap();
 
         let child_handle = self.child.clone();
-        start_simple_processor(
+        util::start_simple_processor(
             move |mut frame: Frame| -> Frame {
                 let mut guard = child_handle.lock().unwrap();
                 let child = match *guard {
@@ -215,10 +134,11 @@ impl Module for LiveCode {
                 }
                 frame
             },
+            self.ifc.clone(),
             self.in_port.clone(),
             self.out_port.clone(),
             self.breaker.clone(),
-            exec,
+            &exec,
         );
     }
     fn name() -> &'static str {
@@ -253,7 +173,7 @@ fn spawn_child(child_handle: &Arc<Mutex<Option<process::Child>>>, path: PathBuf)
 }
 
 use gfx_device_gl as gl;
-use gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*};
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*};
 struct LiveCodeGui {
     bounds: Box3,
     open_button: Button,
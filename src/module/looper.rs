@@ -0,0 +1,251 @@
+//! A live looper, built with `Process`.
+//!
+//! Raising `Record` above 0.5 arms the looper, which starts recording at the next bar line of the
+//! transport, as placed by the graph's tempo map on the input frames' sample counters. After
+//! `Bars` bars it loops the recording, mixed on top of the input passing through. While `Overdub` is up
+//! the input is added to the loop as it plays, `Reverse` plays it backwards and `Speed` changes
+//! the playback rate, and with it the pitch. Recording again replaces the loop, and raising
+//! `Clear` stops and discards it. Frames without a sample counter start recording right away.
+
+use crate::module::audio_io::Frame;
+use crate::module::flow;
+use crate::module::process::Process;
+use crate::module::timeline::TempoMap;
+
+use std::sync::{Arc, Weak};
+
+/// Longest loop kept, in seconds, so a slow tempo and many bars can't take all the memory.
+const MAX_SECONDS: f64 = 600.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum State {
+    Empty,
+    /// Waiting for the next bar line to start recording.
+    Armed,
+    /// Recording, with this many samples written.
+    Recording(usize),
+    Playing,
+}
+
+pub struct Looper {
+    state: State,
+    graph: Option<Weak<flow::Graph>>,
+    /// The graph's tempo map as of the last block.
+    tempo_map: Arc<TempoMap>,
+    bars: f32,
+    speed: f32,
+    overdub: bool,
+    reverse: bool,
+    record_held: bool,
+    clear_held: bool,
+    /// The loop, interleaved by channel.
+    buffer: Vec<f32>,
+    channels: usize,
+    /// Position in samples, between samples when the speed isn't 1.0.
+    position: f64,
+}
+
+impl Looper {
+    pub fn state(&self) -> State {
+        self.state
+    }
+    /// The bar sample `time` falls in at `rate`.
+    fn bar(&self, time: u64, rate: f32) -> u64 {
+        self.tempo_map.position(time as f64 / rate as f64).bar
+    }
+    /// Whether sample `time` is the first of a bar.
+    fn on_bar(&self, time: u64, rate: f32) -> bool {
+        time == 0 || self.bar(time, rate) != self.bar(time - 1, rate)
+    }
+    /// Samples in `bars` bars from the start of `bar`, at `rate`.
+    fn bars_len(&self, bar: u64, bars: u64, rate: f32) -> f64 {
+        (self.tempo_map.bar_seconds(bar + bars) - self.tempo_map.bar_seconds(bar)) * rate as f64
+    }
+    /// Samples in the loop, one per frame row.
+    fn len(&self) -> usize {
+        self.buffer.len() / self.channels.max(1)
+    }
+    /// The loop at the current position, interpolating between samples.
+    fn read(&self, channel: usize) -> f32 {
+        let len = self.len();
+        let index = self.position.floor() as usize % len;
+        let next = (index + 1) % len;
+        let fraction = (self.position - self.position.floor()) as f32;
+        let (a, b) = (
+            self.buffer[index * self.channels + channel],
+            self.buffer[next * self.channels + channel],
+        );
+        a + (b - a) * fraction
+    }
+}
+
+impl Process for Looper {
+    const NAME: &'static str = "Looper";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] = &[
+        ("Record", 0.0),
+        ("Overdub", 0.0),
+        ("Reverse", 0.0),
+        ("Speed", 1.0),
+        ("Bars", 1.0),
+        ("Clear", 0.0),
+    ];
+    fn new() -> Looper {
+        Looper {
+            state: State::Empty,
+            graph: None,
+            tempo_map: Arc::new(TempoMap::new()),
+            bars: 1.0,
+            speed: 1.0,
+            overdub: false,
+            reverse: false,
+            record_held: false,
+            clear_held: false,
+            buffer: Vec::new(),
+            channels: 0,
+            position: 0.0,
+        }
+    }
+    fn attach(&mut self, ifc: &Arc<flow::Interface>) {
+        self.graph = Some(Arc::downgrade(&ifc.graph()));
+    }
+    fn set_param(&mut self, idx: usize, value: f32) {
+        match idx {
+            0 => {
+                if value > 0.5 && !self.record_held {
+                    self.state = State::Armed;
+                }
+                self.record_held = value > 0.5;
+            }
+            1 => self.overdub = value > 0.5,
+            2 => self.reverse = value > 0.5,
+            3 => self.speed = value.max(0.0),
+            4 => self.bars = value.round().max(1.0),
+            _ => {
+                if value > 0.5 && !self.clear_held {
+                    self.state = State::Empty;
+                    self.buffer = Vec::new();
+                }
+                self.clear_held = value > 0.5;
+            }
+        }
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        if let Some(graph) = self.graph.as_ref().and_then(|graph| graph.upgrade()) {
+            self.tempo_map = graph.tempo_map();
+        }
+        let input = &inputs[0];
+        outputs[0].data.assign(&input.data);
+        let step = self.speed as f64 * if self.reverse { -1.0 } else { 1.0 };
+        let rows = input.data.outer_iter().zip(outputs[0].data.outer_iter_mut());
+        for (row, (samples, mut out)) in rows.enumerate() {
+            if self.state == State::Armed {
+                let time = input.time.map(|time| time + row as u64);
+                if time.map_or(true, |time| self.on_bar(time, input.rate)) {
+                    // the whole loop is allocated up front, rather than growing while recording
+                    let bar = time.map_or(0, |time| self.bar(time, input.rate));
+                    let len = self
+                        .bars_len(bar, self.bars as u64, input.rate)
+                        .min(MAX_SECONDS * input.rate as f64);
+                    self.channels = samples.len();
+                    self.buffer = vec![0.0; (len.round() as usize).max(1) * self.channels];
+                    self.state = State::Recording(0);
+                }
+            }
+            let channels = samples.len().min(self.channels);
+            match self.state {
+                State::Recording(written) => {
+                    let frame = &mut self.buffer[written * self.channels..][..self.channels];
+                    for (sample, &x) in frame.iter_mut().zip(samples.iter()) {
+                        *sample = x;
+                    }
+                    self.state = if written + 1 == self.len() {
+                        self.position = 0.0;
+                        State::Playing
+                    } else {
+                        State::Recording(written + 1)
+                    };
+                }
+                State::Playing if self.channels > 0 => {
+                    for channel in 0..channels {
+                        out[channel] += self.read(channel);
+                    }
+                    if self.overdub {
+                        let index = self.position.floor() as usize % self.len();
+                        for channel in 0..channels {
+                            self.buffer[index * self.channels + channel] += samples[channel];
+                        }
+                    }
+                    let len = self.len() as f64;
+                    self.position = (self.position + step) % len;
+                    if self.position < 0.0 {
+                        self.position += len;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_looper() {
+    use ndarray::Array2;
+
+    // a bar of 4/4 at 240 BPM is 16 samples at a rate of 16, and of 2/4 is 8
+    let frame = |time: u64, values: Vec<f32>| Frame {
+        rate: 16.0,
+        time: Some(time),
+        data: Array2::from_shape_vec((values.len(), 1), values).unwrap(),
+        meta: None,
+    };
+    let graph = flow::Graph::new();
+    graph.set_tempo_map(Arc::new(TempoMap::parse("0 240 4/4; 32 240 2/4").unwrap()));
+    let mut looper = Looper::new();
+    looper.attach(&graph.add_node());
+    for (idx, &(_, value)) in Looper::PARAMS.iter().enumerate() {
+        looper.set_param(idx, value);
+    }
+    looper.set_param(0, 1.0);
+    let mut outputs = [frame(0, vec![0.0; 8])];
+    looper.process(&[frame(8, vec![1.0; 8])], &mut outputs);
+    assert_eq!(looper.state(), State::Armed);
+    // recording starts at the bar line, and the input passes through meanwhile
+    let mut outputs = [frame(0, vec![0.0; 16])];
+    looper.process(&[frame(16, (16..32).map(|x| x as f32).collect())], &mut outputs);
+    assert_eq!(looper.state(), State::Playing);
+    assert_eq!(outputs[0].data[[3, 0]], 19.0);
+
+    let mut outputs = [frame(0, vec![0.0; 4])];
+    looper.process(&[frame(32, vec![0.0; 4])], &mut outputs);
+    assert_eq!(outputs[0].data.column(0).to_vec(), vec![16.0, 17.0, 18.0, 19.0]);
+
+    // half speed backwards from sample 4, interpolating between samples
+    looper.set_param(2, 1.0);
+    looper.set_param(3, 0.5);
+    looper.process(&[frame(36, vec![100.0; 4])], &mut outputs);
+    assert_eq!(
+        outputs[0].data.column(0).to_vec(),
+        vec![120.0, 119.5, 119.0, 118.5]
+    );
+
+    looper.set_param(5, 1.0);
+    looper.process(&[frame(40, vec![1.0; 4])], &mut outputs);
+    assert_eq!(looper.state(), State::Empty);
+    assert!(outputs[0].data.iter().all(|&x| x == 1.0));
+
+    // the loop follows the signature, here two bars of 2/4 from the bar line at sample 128
+    looper.set_param(5, 0.0);
+    looper.set_param(2, 0.0);
+    looper.set_param(3, 1.0);
+    looper.set_param(4, 2.0);
+    looper.set_param(0, 0.0);
+    looper.set_param(0, 1.0);
+    let mut outputs = [frame(0, vec![0.0; 8])];
+    looper.process(&[frame(124, vec![0.0; 8])], &mut outputs);
+    assert_eq!(looper.state(), State::Recording(4));
+    let mut outputs = [frame(0, vec![0.0; 12])];
+    looper.process(&[frame(132, vec![0.0; 12])], &mut outputs);
+    assert_eq!(looper.state(), State::Playing);
+}
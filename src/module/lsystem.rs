@@ -0,0 +1,393 @@
+//! An L-system pattern generator: `L-System` rewrites its axiom by its production rules `Depth`
+//! times, and steps through the resulting string of symbols, one for every value on `Step`.
+//!
+//! Rules are of the form `A=AB`, replacing every `A` with `AB` at once on each rewrite, and
+//! symbols without a rule stay as they are. The mapping says what each symbol plays: `A=c4` sends
+//! key 60 as a `Note`, releasing the one before, and on `Key`, while `F=trig` sends a value on
+//! `Trigger`. Other symbols are rests. Every symbol is also sent as is on `Symbol`. The pattern
+//! repeats from the start once it reaches the end, or when a value arrives on `Reset`. Strings
+//! grow quickly with depth, so they are cut short at `MAX_SYMBOLS`.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::sampler::{parse_key, Note};
+use crate::module::{flow, util, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+/// Most rewrites of the axiom.
+pub const MAX_DEPTH: usize = 12;
+/// Longest string of symbols kept.
+pub const MAX_SYMBOLS: usize = 1 << 16;
+/// Velocity of the notes played.
+const VELOCITY: u8 = 100;
+
+/// What a symbol plays.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Key(u8),
+    Trigger,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Grammar {
+    axiom: String,
+    rules: Vec<(char, String)>,
+    mapping: Vec<(char, Action)>,
+}
+
+/// Parse entries of the form `symbol=value`, separated by `;`, newlines or spaces if `spaces`.
+fn parse_entries<T, F>(s: &str, spaces: bool, parse: F) -> Option<Vec<(char, T)>>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    let separator = |c: char| c == ';' || c == '\n' || (spaces && c.is_whitespace());
+    let mut entries = Vec::new();
+    for entry in s.split(separator).filter(|entry| !entry.trim().is_empty()) {
+        let mut parts = entry.splitn(2, '=');
+        let mut symbol = parts.next()?.trim().chars();
+        let value = parse(parts.next()?.trim())?;
+        match (symbol.next(), symbol.next()) {
+            (Some(symbol), None) => entries.push((symbol, value)),
+            _ => return None,
+        }
+    }
+    Some(entries)
+}
+
+impl Grammar {
+    /// Parse an axiom, rules like `A=AB; B=A` and a mapping like `A=c4 B=62 F=trig`.
+    pub fn parse(axiom: &str, rules: &str, mapping: &str) -> Option<Grammar> {
+        let axiom: String = axiom.chars().filter(|c| !c.is_whitespace()).collect();
+        let rules = parse_entries(rules, false, |rule| {
+            Some(rule.chars().filter(|c| !c.is_whitespace()).collect())
+        })?;
+        let mapping = parse_entries(mapping, true, |value| match value {
+            "trig" => Some(Action::Trigger),
+            key => parse_key(key).map(Action::Key),
+        })?;
+        if axiom.is_empty() {
+            return None;
+        }
+        Some(Grammar {
+            axiom,
+            rules,
+            mapping,
+        })
+    }
+    fn describe(&self) -> (String, String, String) {
+        let rules: Vec<String> = self
+            .rules
+            .iter()
+            .map(|&(symbol, ref rule)| format!("{}={}", symbol, rule))
+            .collect();
+        let mapping: Vec<String> = self
+            .mapping
+            .iter()
+            .map(|&(symbol, action)| match action {
+                Action::Key(key) => format!("{}={}", symbol, key),
+                Action::Trigger => format!("{}=trig", symbol),
+            })
+            .collect();
+        (self.axiom.clone(), rules.join("; "), mapping.join(" "))
+    }
+    /// The axiom rewritten `depth` times. A symbol with more than one rule takes the last.
+    pub fn expand(&self, depth: usize) -> Vec<char> {
+        let mut symbols: Vec<char> = self.axiom.chars().collect();
+        for _ in 0..depth.min(MAX_DEPTH) {
+            let mut next = Vec::with_capacity(symbols.len() * 2);
+            for &symbol in &symbols {
+                match self.rules.iter().rev().find(|&&(lhs, _)| lhs == symbol) {
+                    Some(&(_, ref rule)) => next.extend(rule.chars()),
+                    None => next.push(symbol),
+                }
+                if next.len() >= MAX_SYMBOLS {
+                    next.truncate(MAX_SYMBOLS);
+                    break;
+                }
+            }
+            symbols = next;
+        }
+        symbols
+    }
+    pub fn action(&self, symbol: char) -> Option<Action> {
+        self.mapping
+            .iter()
+            .rev()
+            .find(|&&(lhs, _)| lhs == symbol)
+            .map(|&(_, action)| action)
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    SetGrammar(Grammar),
+}
+
+struct Shared {
+    grammar: Grammar,
+    depth: usize,
+    /// The expanded string, made again when the grammar or depth changes.
+    symbols: Option<Vec<char>>,
+    position: usize,
+    sounding: Option<u8>,
+}
+
+pub struct LSystem {
+    ifc: Arc<flow::Interface>,
+    step_port: Arc<flow::Port<f32, ()>>,
+    depth_port: Arc<flow::Port<f32, ()>>,
+    reset_port: Arc<flow::Port<f32, ()>>,
+    notes_port: Arc<flow::Port<(), Note>>,
+    key_port: Arc<flow::Port<(), f32>>,
+    trigger_port: Arc<flow::Port<(), f32>>,
+    symbol_port: Arc<flow::Port<(), String>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Module for LSystem {
+    fn new(ifc: Arc<flow::Interface>) -> LSystem {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        LSystem {
+            step_port: ifc.get_or_create_port("Step".into()),
+            depth_port: ifc.get_or_create_port("Depth".into()),
+            reset_port: ifc.get_or_create_port("Reset".into()),
+            notes_port: ifc.get_or_create_port("Notes".into()),
+            key_port: ifc.get_or_create_port("Key".into()),
+            trigger_port: ifc.get_or_create_port("Trigger".into()),
+            symbol_port: ifc.get_or_create_port("Symbol".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            shared: Arc::new(Mutex::new(Shared {
+                grammar: Grammar::parse("A", "A=AB; B=A", "A=c4 B=g4").unwrap(),
+                depth: 4,
+                symbols: None,
+                position: 0,
+                sounding: None,
+            })),
+        }
+    }
+    fn name() -> &'static str {
+        "L-System"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let shared = self.shared.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::SetGrammar(grammar) => {
+                        let mut shared = shared.lock().unwrap();
+                        shared.grammar = grammar;
+                        shared.symbols = None;
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.depth_port.clone(),
+            move |depth: f32| {
+                let depth = (depth.round().max(0.0) as usize).min(MAX_DEPTH);
+                let mut shared = shared.lock().unwrap();
+                if depth != shared.depth {
+                    shared.depth = depth;
+                    shared.symbols = None;
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.reset_port.clone(),
+            move |_: f32| shared.lock().unwrap().position = 0,
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let (mut note_tx, note_rx) = mpsc::channel(4);
+        let (mut key_tx, key_rx) = mpsc::channel(1);
+        let (mut trigger_tx, trigger_rx) = mpsc::channel(1);
+        let (mut symbol_tx, symbol_rx) = mpsc::channel(1);
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.step_port.clone(),
+            move |_: f32| {
+                let mut shared = shared.lock().unwrap();
+                let shared = &mut *shared;
+                if shared.symbols.is_none() {
+                    shared.symbols = Some(shared.grammar.expand(shared.depth));
+                }
+                let symbol = {
+                    let symbols = shared.symbols.as_ref().unwrap();
+                    // rules can erase symbols, leaving nothing to play
+                    if symbols.is_empty() {
+                        return;
+                    }
+                    if shared.position >= symbols.len() {
+                        shared.position = 0;
+                    }
+                    symbols[shared.position]
+                };
+                shared.position += 1;
+                if let Some(key) = shared.sounding.take() {
+                    let _ = note_tx.try_send(Note { key, velocity: 0 });
+                }
+                match shared.grammar.action(symbol) {
+                    Some(Action::Key(key)) => {
+                        let _ = note_tx.try_send(Note {
+                            key,
+                            velocity: VELOCITY,
+                        });
+                        let _ = key_tx.try_send(key as f32);
+                        shared.sounding = Some(key);
+                    }
+                    Some(Action::Trigger) => {
+                        let _ = trigger_tx.try_send(1.0);
+                    }
+                    None => {}
+                }
+                let _ = symbol_tx.try_send(symbol.to_string());
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(note_rx, self.notes_port.clone(), &exec);
+        util::start_source(key_rx, self.key_port.clone(), &exec);
+        util::start_source(trigger_rx, self.trigger_port.clone(), &exec);
+        util::start_source(symbol_rx, self.symbol_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(&self.shared.lock().unwrap().grammar).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(grammar) => {
+                let mut shared = self.shared.lock().unwrap();
+                shared.grammar = grammar;
+                shared.symbols = None;
+            }
+            Err(e) => println!("l-system state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_lsystem() {
+    let algae = Grammar::parse("A", "A=AB\n B = A", "A=c4 B=trig").unwrap();
+    assert_eq!(algae.expand(0), vec!['A']);
+    assert_eq!(algae.expand(3).into_iter().collect::<String>(), "ABAAB");
+    assert_eq!(algae.expand(5).len(), 13);
+    assert_eq!(algae.action('A'), Some(Action::Key(60)));
+    assert_eq!(algae.action('B'), Some(Action::Trigger));
+    assert_eq!(algae.action('C'), None);
+    assert_eq!(
+        algae.describe(),
+        (
+            "A".to_string(),
+            "A=AB; B=A".to_string(),
+            "A=60 B=trig".to_string()
+        )
+    );
+
+    // symbols without rules are kept, and the string is cut short
+    let koch = Grammar::parse("F", "F=F+F-F-F+F", "").unwrap();
+    assert_eq!(koch.expand(1).into_iter().collect::<String>(), "F+F-F-F+F");
+    assert_eq!(koch.expand(MAX_DEPTH).len(), MAX_SYMBOLS);
+
+    assert!(Grammar::parse("", "A=AB", "").is_none());
+    assert!(Grammar::parse("A", "AB=A", "").is_none());
+    assert!(Grammar::parse("A", "A=B", "A=h4").is_none());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct LSystemGui {
+    bounds: Box3,
+    axiom_box: TextBox,
+    rules_box: TextBox,
+    mapping_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for LSystem {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let (axiom, rules, mapping) = self.shared.lock().unwrap().grammar.describe();
+        Box::new(LSystemGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            axiom_box: TextBox::new(ctx.clone(), axiom, row(0.0)),
+            rules_box: TextBox::new(ctx.clone(), rules, row(1.0)),
+            mapping_box: TextBox::new(ctx.clone(), mapping, row(2.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(3.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for LSystemGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.axiom_box.render(device, ctx);
+        self.rules_box.render(device, ctx);
+        self.mapping_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let mut dirty = false;
+        for text_box in &mut [&mut self.axiom_box, &mut self.rules_box, &mut self.mapping_box] {
+            dirty |= text_box.handle(event) != TextBoxUpdate::Unchanged;
+        }
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let grammar = Grammar::parse(
+                    self.axiom_box.content(),
+                    self.rules_box.content(),
+                    self.mapping_box.content(),
+                );
+                match grammar {
+                    Some(grammar) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx
+                            .unbounded_send(UserCommand::SetGrammar(grammar))
+                            .unwrap();
+                    }
+                    None => self
+                        .apply_button
+                        .set_label("Invalid: axiom, A=AB; ..., A=c4 ...".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Hosting LV2 plugins through `livi`.
+//!
+//! An `LV2 Plugin` node loads the plugin named by the `uri` in its state, and gets a mono audio
+//! port for each of the plugin's audio ports and a control input for each of its control ports,
+//! which are also its parameters for scenes. Plugins without audio inputs, like synths, get a
+//! `Clock` input instead, which only sets the rate and length of the blocks. Like `Processor`s,
+//! plugins run as tasks or in the `BlockScheduler`. The plugin is instantiated with the rate of
+//! the first block, and again if it changes.
+//!
+//! Event ports are given empty sequences for now, so synths play nothing until note events can be
+//! patched in, and plugins with CV ports aren't supported. Every installed plugin is also
+//! registered under its own name, see `add_installed`.
+//!
+//! Built with the `lv2` feature, which needs lilv installed.
+
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::gui::module_gui::StatefulGuiModuleFactory;
+use crate::module::declick::DEFAULT_RAMP;
+use crate::module::process::start_blocks;
+use crate::module::scene::Params;
+use crate::module::scheduler::{Block, BlockNode};
+use crate::module::{audio_io::Frame, flow, util, Module};
+use crate::registry::Registry;
+
+use livi;
+use livi::event::LV2AtomSequence;
+
+use serde_json::Value;
+
+use std::sync::{Arc, Mutex};
+
+/// The longest block a plugin is run with. Longer blocks are cut short.
+pub const MAX_BLOCK: usize = 8192;
+/// Bytes of each event sequence.
+const SEQUENCE_CAPACITY: usize = 4096;
+
+/// The control inputs of a plugin, as parameters.
+struct Controls {
+    names: Vec<String>,
+    indices: Vec<livi::PortIndex>,
+    values: Mutex<Vec<f32>>,
+}
+
+impl Controls {
+    fn set_index(&self, idx: usize, value: f32) {
+        self.values.lock().unwrap()[idx] = value;
+    }
+}
+
+impl Params for Controls {
+    fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+    fn get(&self, name: &str) -> Option<f32> {
+        let idx = self.names.iter().position(|param| param == name)?;
+        Some(self.values.lock().unwrap()[idx])
+    }
+    fn set(&self, name: &str, value: f32) {
+        if let Some(idx) = self.names.iter().position(|param| param == name) {
+            self.set_index(idx, value);
+        }
+    }
+}
+
+struct Lv2Block {
+    plugin: livi::Plugin,
+    features: Arc<livi::Features>,
+    /// The rate the instance was made for.
+    instance: Option<(f32, livi::Instance)>,
+    controls: Arc<Controls>,
+    /// Whether the first input is a `Clock` rather than audio for the plugin.
+    clocked: bool,
+    in_buffers: Vec<Vec<f32>>,
+    out_buffers: Vec<Vec<f32>>,
+    event_inputs: Vec<LV2AtomSequence>,
+    event_outputs: Vec<LV2AtomSequence>,
+}
+
+impl Block for Lv2Block {
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let rate = inputs[0].rate;
+        let len = inputs[0].data.dim().0.min(MAX_BLOCK);
+        if self
+            .instance
+            .as_ref()
+            .map(|&(instance_rate, _)| instance_rate != rate)
+            .unwrap_or(true)
+        {
+            self.instance = match unsafe { self.plugin.instantiate(self.features.clone(), rate as f64) } {
+                Ok(instance) => Some((rate, instance)),
+                Err(e) => {
+                    println!("lv2 instantiate err: {:?}", e);
+                    None
+                }
+            };
+        }
+        let instance = match self.instance {
+            Some((_, ref mut instance)) => instance,
+            None => return,
+        };
+        for (&index, &value) in self
+            .controls
+            .indices
+            .iter()
+            .zip(self.controls.values.lock().unwrap().iter())
+        {
+            instance.set_control_input(index, value);
+        }
+
+        let audio = if self.clocked { &inputs[1..] } else { inputs };
+        for (buffer, frame) in self.in_buffers.iter_mut().zip(audio) {
+            buffer.clear();
+            buffer.extend(frame.data.column(0).iter().take(len));
+            buffer.resize(len, 0.0);
+        }
+        for buffer in &mut self.out_buffers {
+            buffer.clear();
+            buffer.resize(len, 0.0);
+        }
+        for sequence in self.event_inputs.iter_mut().chain(&mut self.event_outputs) {
+            sequence.clear();
+        }
+        let ports = livi::EmptyPortConnections::new()
+            .with_audio_inputs(self.in_buffers.iter().map(|buffer| buffer.as_slice()))
+            .with_audio_outputs(self.out_buffers.iter_mut().map(|buffer| buffer.as_mut_slice()))
+            .with_atom_sequence_inputs(self.event_inputs.iter())
+            .with_atom_sequence_outputs(self.event_outputs.iter_mut());
+        if let Err(e) = unsafe { instance.run(len, ports) } {
+            println!("lv2 run err: {:?}", e);
+            return;
+        }
+
+        for (frame, buffer) in outputs.iter_mut().zip(&self.out_buffers) {
+            for (x, &y) in frame.data.column_mut(0).iter_mut().zip(buffer) {
+                *x = y;
+            }
+        }
+    }
+}
+
+/// A loaded plugin's part of an `Lv2Plugin`.
+struct Loaded {
+    uri: String,
+    name: String,
+    inputs: Vec<Arc<flow::Port<Frame, ()>>>,
+    outputs: Vec<Arc<flow::Port<(), Frame>>>,
+    params: Vec<Arc<flow::Port<f32, ()>>>,
+    controls: Arc<Controls>,
+    block: Arc<Mutex<Lv2Block>>,
+}
+
+/// Hosts the LV2 plugin given by the `uri` in its state.
+pub struct Lv2Plugin {
+    ifc: Arc<flow::Interface>,
+    breaker: Breaker,
+    loaded: Option<Loaded>,
+}
+
+impl Lv2Plugin {
+    /// The name of the loaded plugin.
+    pub fn plugin_name(&self) -> Option<&str> {
+        self.loaded.as_ref().map(|loaded| loaded.name.as_str())
+    }
+
+    fn load(&mut self, uri: &str) -> Result<(), String> {
+        let world = livi::World::new();
+        let plugin = world
+            .plugin_by_uri(uri)
+            .ok_or_else(|| format!("no plugin {}", uri))?;
+        let counts = plugin.port_counts();
+        if counts.cv_inputs > 0 || counts.cv_outputs > 0 {
+            return Err(format!("{} has CV ports", uri));
+        }
+        let features = world.build_features(livi::FeaturesBuilder {
+            min_block_length: 1,
+            max_block_length: MAX_BLOCK,
+        });
+
+        let audio_in: Vec<livi::Port> = plugin.ports_with_type(livi::PortType::AudioInput).collect();
+        let audio_out: Vec<livi::Port> = plugin.ports_with_type(livi::PortType::AudioOutput).collect();
+        let control_in: Vec<livi::Port> = plugin.ports_with_type(livi::PortType::ControlInput).collect();
+        let clocked = audio_in.is_empty();
+        let mut in_names: Vec<String> = audio_in.iter().map(|port| port.name.clone()).collect();
+        if clocked {
+            in_names.insert(0, "Clock".into());
+        }
+        let inputs: Vec<Arc<flow::Port<Frame, ()>>> = in_names
+            .into_iter()
+            .map(|name| self.ifc.get_or_create_port(name))
+            .collect();
+        let outputs: Vec<Arc<flow::Port<(), Frame>>> = audio_out
+            .iter()
+            .map(|port| self.ifc.get_or_create_port(port.name.clone()))
+            .collect();
+        inputs[0].set_meta(flow::PortMeta {
+            required: true,
+            ..inputs[0].meta()
+        });
+        for (idx, port) in inputs.iter().enumerate() {
+            if idx > 0 || !clocked {
+                port.set_meta(flow::PortMeta {
+                    ramp: Some(DEFAULT_RAMP),
+                    channels: Some(1),
+                    ..port.meta()
+                });
+            }
+        }
+        for port in &outputs {
+            port.set_meta(flow::PortMeta {
+                ramp: Some(DEFAULT_RAMP),
+                channels: Some(1),
+                ..port.meta()
+            });
+        }
+
+        let controls = Arc::new(Controls {
+            names: control_in.iter().map(|port| port.name.clone()).collect(),
+            indices: control_in.iter().map(|port| port.index).collect(),
+            values: Mutex::new(control_in.iter().map(|port| port.default_value).collect()),
+        });
+        let params = controls
+            .names
+            .iter()
+            .map(|name| self.ifc.get_or_create_port(name.clone()))
+            .collect();
+        let counts = (counts.atom_sequence_inputs, counts.atom_sequence_outputs);
+        let sequences = |count: usize| -> Vec<LV2AtomSequence> {
+            (0..count)
+                .map(|_| LV2AtomSequence::new(&features, SEQUENCE_CAPACITY))
+                .collect()
+        };
+        let block = Arc::new(Mutex::new(Lv2Block {
+            plugin: plugin.clone(),
+            features: features.clone(),
+            instance: None,
+            controls: controls.clone(),
+            clocked,
+            in_buffers: audio_in.iter().map(|_| Vec::with_capacity(MAX_BLOCK)).collect(),
+            out_buffers: audio_out.iter().map(|_| Vec::with_capacity(MAX_BLOCK)).collect(),
+            event_inputs: sequences(counts.0),
+            event_outputs: sequences(counts.1),
+        }));
+        self.ifc.set_params(controls.clone());
+        self.ifc.set_block(BlockNode {
+            inputs: inputs.iter().map(|port| port.id()).collect(),
+            outputs: outputs.iter().map(|port| port.id()).collect(),
+            block: block.clone(),
+        });
+        self.loaded = Some(Loaded {
+            uri: uri.into(),
+            name: plugin.name(),
+            inputs,
+            outputs,
+            params,
+            controls,
+            block,
+        });
+        Ok(())
+    }
+}
+
+impl Module for Lv2Plugin {
+    fn new(ifc: Arc<flow::Interface>) -> Lv2Plugin {
+        Lv2Plugin {
+            ifc,
+            breaker: Breaker::new(),
+            loaded: None,
+        }
+    }
+    fn name() -> &'static str {
+        "LV2 Plugin"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let loaded = match self.loaded {
+            Some(ref loaded) => loaded,
+            None => return,
+        };
+        for (idx, port) in loaded.params.iter().enumerate() {
+            let controls = loaded.controls.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| controls.set_index(idx, value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+        start_blocks(
+            Lv2Plugin::name(),
+            self.ifc.clone(),
+            loaded.inputs.clone(),
+            loaded.outputs.clone(),
+            loaded.block.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> Value {
+        match self.loaded {
+            Some(ref loaded) => {
+                let values = loaded.controls.values.lock().unwrap();
+                let params: serde_json::Map<String, Value> = loaded
+                    .controls
+                    .names
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(name, &value)| (name.clone(), json!(value)))
+                    .collect();
+                json!({ "uri": loaded.uri, "params": params })
+            }
+            None => Value::Null,
+        }
+    }
+    fn load_state(&mut self, state: Value) {
+        if self.loaded.is_some() {
+            println!("lv2 state err: a plugin is already loaded");
+            return;
+        }
+        let uri = match state["uri"].as_str() {
+            Some(uri) => uri,
+            None => {
+                println!("lv2 state err: no uri");
+                return;
+            }
+        };
+        if let Err(e) = self.load(uri) {
+            println!("lv2 load err: {}", e);
+            return;
+        }
+        if let (Some(params), Some(loaded)) = (state["params"].as_object(), self.loaded.as_ref()) {
+            for (name, value) in params {
+                if let Some(value) = value.as_f64() {
+                    loaded.controls.set(name, value as f32);
+                }
+            }
+        }
+    }
+}
+
+/// Register every installed plugin without CV ports under its own name, in the `LV2` category.
+/// Patches save them as `LV2 Plugin`s.
+pub fn add_installed(registry: &mut Registry) {
+    let world = livi::World::new();
+    for plugin in world.iter_plugins() {
+        let counts = plugin.port_counts();
+        if counts.cv_inputs > 0 || counts.cv_outputs > 0 {
+            continue;
+        }
+        let state = json!({ "uri": plugin.uri() });
+        let factory = StatefulGuiModuleFactory::<Lv2Plugin>::new(plugin.name(), state);
+        registry.add_factory(Box::new(factory), "LV2", "An installed LV2 plugin");
+    }
+}
+
+#[test]
+fn test_lv2_plugin() {
+    use crate::module::testkit::TestHarness;
+    use ndarray::Array2;
+
+    let controls = Controls {
+        names: vec!["Gain".into(), "Drive".into()],
+        indices: vec![livi::PortIndex(0), livi::PortIndex(3)],
+        values: Mutex::new(vec![0.0, 1.0]),
+    };
+    controls.set("Drive", 0.5);
+    controls.set("Missing", 2.0);
+    assert_eq!(controls.names(), vec!["Gain".to_string(), "Drive".to_string()]);
+    assert_eq!(controls.get("Drive"), Some(0.5));
+    assert_eq!(controls.get("Missing"), None);
+
+    // without a plugin there is nothing to save, and no ports
+    let mut harness = TestHarness::<Lv2Plugin>::with_state(json!({ "uri": "urn:flow-synth:missing" }));
+    assert!(harness.interface().ports().is_empty());
+    assert!(harness.module().plugin_name().is_none());
+    assert_eq!(harness.module().save_state(), Value::Null);
+
+    // the amp from the LV2 examples, where it's installed
+    const AMP: &str = "http://lv2plug.in/plugins/eg-amp";
+    if livi::World::new().plugin_by_uri(AMP).is_none() {
+        return;
+    }
+    let mut harness = TestHarness::<Lv2Plugin>::with_state(json!({ "uri": AMP, "params": { "Gain": 0.0 } }));
+    assert!(harness.module().plugin_name().is_some());
+    let state = harness.module().save_state();
+    assert_eq!(state["uri"], AMP);
+    assert_eq!(state["params"]["Gain"], 0.0);
+    let (in_name, out_name) = {
+        let loaded = harness.module().loaded.as_ref().unwrap();
+        assert_eq!((loaded.inputs.len(), loaded.outputs.len()), (1, 1));
+        (loaded.inputs[0].name().to_string(), loaded.outputs[0].name().to_string())
+    };
+    for port in harness.interface().ports() {
+        port.set_meta(flow::PortMeta {
+            ramp: None,
+            ..port.meta()
+        });
+    }
+    let input = harness.input::<Frame>(&in_name);
+    let output = harness.output::<Frame>(&out_name);
+    input.push(Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((64, 1), 0.25),
+        meta: None,
+    });
+    harness.run();
+    let frames = output.take();
+    assert_eq!(frames.len(), 1);
+    // at 0 dB the amp passes its input through
+    assert!(frames[0].data.iter().all(|&x| (x - 0.25).abs() < 1e-4));
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{component::*, event::*, geom::*, module_gui::*, render::*};
+struct Lv2Gui {
+    bounds: Box3,
+    name: String,
+}
+const PADDING: f32 = 4.0;
+impl ModuleGui for Lv2Plugin {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        Box::new(Lv2Gui {
+            bounds,
+            name: self.plugin_name().unwrap_or("No plugin loaded").into(),
+        })
+    }
+}
+impl GuiComponent<bool> for Lv2Gui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        ctx.draw_text(
+            &self.name,
+            self.bounds.pos + Pt3::new(PADDING, PADDING, 0.0),
+            [1.0, 1.0, 1.0],
+        );
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        false
+    }
+}
@@ -0,0 +1,182 @@
+//! Macro knobs, each turning many params of the patch at once, so a complex patch can be
+//! performed with a few controls.
+//!
+//! The `Macros` module has eight knobs, `Macro 1` to `Macro 8`, which it exposes as params and as
+//! control inputs. Turning one sets every param assigned to it, on any node of the graph, to the
+//! point of the target's range and curve where the knob is, like a control mapped to them (see
+//! `module::mapping`). Being params themselves, the knobs are saved in scenes and can be mapped to
+//! a controller, and the inputs let a recorded gesture play them back.
+//!
+//! The targets are kept in the node's metadata under `"macros"`, a list of targets for each knob
+//! in order, so frontends can assign them and they're saved with the patch.
+
+use futures::task::Spawn;
+
+use serde_json::{self, Value};
+
+use crate::future_ext::Breaker;
+use crate::module::flow::{self, Graph, NodeId};
+use crate::module::mapping::Target;
+use crate::module::scene::Params;
+use crate::module::{util, Module};
+
+use std::sync::{Arc, Mutex, Weak};
+
+pub const MACROS: usize = 8;
+/// The metadata entry holding the targets.
+pub const META_KEY: &str = "macros";
+
+/// The name of the knob `idx`.
+pub fn knob_name(idx: usize) -> String {
+    format!("Macro {}", idx + 1)
+}
+
+struct Knobs {
+    graph: Weak<Graph>,
+    id: NodeId,
+    values: Mutex<Vec<f32>>,
+}
+
+impl Knobs {
+    fn set_index(&self, idx: usize, x: f32) {
+        self.values.lock().unwrap()[idx] = x;
+        let graph = match self.graph.upgrade() {
+            Some(graph) => graph,
+            None => return,
+        };
+        let targets = graph
+            .node(self.id)
+            .and_then(|node| node.meta::<Vec<Vec<Target>>>(META_KEY))
+            .and_then(|mut targets| {
+                if idx < targets.len() {
+                    Some(targets.swap_remove(idx))
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default();
+        for target in targets {
+            // a knob turning the knobs of its own node would go around in circles
+            if target.node == self.id {
+                continue;
+            }
+            if let Some(params) = graph.node(target.node).and_then(|node| node.params()) {
+                params.set(&target.param, target.value(x));
+            }
+        }
+    }
+}
+
+impl Params for Knobs {
+    fn names(&self) -> Vec<String> {
+        (0..MACROS).map(knob_name).collect()
+    }
+    fn get(&self, name: &str) -> Option<f32> {
+        let idx = (0..MACROS).position(|idx| knob_name(idx) == name)?;
+        Some(self.values.lock().unwrap()[idx])
+    }
+    fn set(&self, name: &str, value: f32) {
+        if let Some(idx) = (0..MACROS).position(|idx| knob_name(idx) == name) {
+            self.set_index(idx, value);
+        }
+    }
+    fn range(&self, name: &str) -> Option<(f32, f32)> {
+        self.get(name).map(|_| (0.0, 1.0))
+    }
+}
+
+pub struct Macros {
+    ifc: Arc<flow::Interface>,
+    ports: Vec<Arc<flow::Port<f32, ()>>>,
+    knobs: Arc<Knobs>,
+    breaker: Breaker,
+}
+
+impl Module for Macros {
+    fn new(ifc: Arc<flow::Interface>) -> Macros {
+        let knobs = Arc::new(Knobs {
+            graph: Arc::downgrade(&ifc.graph()),
+            id: ifc.id(),
+            values: Mutex::new(vec![0.0; MACROS]),
+        });
+        ifc.set_params(knobs.clone());
+        Macros {
+            ports: (0..MACROS)
+                .map(|idx| ifc.get_or_create_port(knob_name(idx)))
+                .collect(),
+            ifc,
+            knobs,
+            breaker: Breaker::new(),
+        }
+    }
+    fn name() -> &'static str {
+        "Macros"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        for (idx, port) in self.ports.iter().enumerate() {
+            let knobs = self.knobs.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| knobs.set_index(idx, value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> Value {
+        json!({ "values": *self.knobs.values.lock().unwrap() })
+    }
+    fn load_state(&mut self, state: Value) {
+        // only the knobs come back, the params they turn are restored with their own modules
+        if let Ok(values) = serde_json::from_value::<Vec<f32>>(state["values"].clone()) {
+            for (value, loaded) in self.knobs.values.lock().unwrap().iter_mut().zip(values) {
+                *value = loaded;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_macros() {
+    use crate::module::mapping::Curve;
+    use crate::module::mix::Gain;
+    use crate::module::process::Processor;
+
+    let graph = Graph::new();
+    let gains: Vec<_> = (0..2)
+        .map(|_| {
+            let ifc = graph.add_node();
+            (ifc.id(), Processor::<Gain>::new(ifc))
+        })
+        .collect();
+    let ifc = graph.add_node();
+    let _macros = Macros::new(ifc.clone());
+    let target = |node, min, max| Target {
+        node,
+        param: "Gain".into(),
+        min,
+        max,
+        curve: Curve::Linear,
+    };
+    let node = graph.node(ifc.id()).unwrap();
+    node.set_meta(
+        META_KEY,
+        vec![vec![target(gains[0].0, 0.0, 1.0), target(gains[1].0, 1.0, 0.0)]],
+    );
+    let params = node.params().unwrap();
+    params.set("Macro 1", 0.25);
+    let gain = |idx: usize| graph.node(gains[idx].0).unwrap().params().unwrap().get("Gain");
+    assert_eq!(gain(0), Some(0.25));
+    assert_eq!(gain(1), Some(0.75));
+    assert_eq!(params.get("Macro 1"), Some(0.25));
+    // knobs without targets only keep their value
+    params.set("Macro 2", 0.5);
+    assert_eq!(params.get("Macro 2"), Some(0.5));
+    assert_eq!(gain(0), Some(0.25));
+}
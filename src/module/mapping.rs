@@ -0,0 +1,235 @@
+//! Mapping MIDI controllers and OSC addresses to the params of any node.
+//!
+//! A `Mapping` binds a control, a MIDI CC or an OSC address, to a param a node exposes through
+//! `Interface::set_params`, scaling the control's `0.0..=1.0` onto a range of the param along a
+//! curve. Mappings belong to the graph, so every module with params can be played from a
+//! controller without doing anything itself, and they're saved with the patch.
+//!
+//! To map a control, `Graph::learn` a param and move the control: the first control to move after
+//! that is bound to it, replacing whatever the param was mapped to before, and learning ends.
+//!
+//! The `Control In` module listens for MIDI on a JACK port and for OSC messages on a UDP port, 9000
+//! unless its state says otherwise, and passes the controls it receives on to its graph. OSC
+//! messages are mapped by their address and their first argument, a float or an int, which should
+//! be in `0.0..=1.0` like a control value.
+
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::flow::{self, Graph};
+use crate::module::Module;
+
+pub use flow_synth_core::mapping::*;
+
+use jack::{AsyncClient, Client, ClientOptions, MidiIn, Port, ProcessHandler, ProcessScope};
+use jack::{Control as JackControl, Error as JackError};
+
+use serde_json::Value;
+
+use std::io;
+use std::net::UdpSocket;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const OSC_PORT: u16 = 9000;
+/// How often the listening threads check whether the module stopped.
+const POLL: Duration = Duration::from_millis(50);
+/// Controls buffered between the audio thread and the graph, a burst from a fast knob.
+const MIDI_QUEUE: usize = 256;
+
+/// Read a MIDI control change.
+pub fn parse_midi(bytes: &[u8]) -> Option<(Control, f32)> {
+    if bytes.len() != 3 || bytes[0] & 0xf0 != 0xb0 {
+        return None;
+    }
+    let control = Control::Cc {
+        channel: bytes[0] & 0x0f,
+        number: bytes[1] & 0x7f,
+    };
+    Some((control, (bytes[2] & 0x7f) as f32 / 127.0))
+}
+
+/// Read an OSC message with a number as its first argument. Bundles aren't supported.
+pub fn parse_osc(packet: &[u8]) -> Option<(Control, f32)> {
+    fn string(packet: &[u8]) -> Option<(&str, &[u8])> {
+        let end = packet.iter().position(|&b| b == 0)?;
+        let s = ::std::str::from_utf8(&packet[..end]).ok()?;
+        // strings are padded with zeros to a multiple of four bytes
+        let padded = (end + 4) & !3;
+        Some((s, packet.get(padded..)?))
+    }
+    fn word(args: &[u8]) -> Option<u32> {
+        let bytes = args.get(..4)?;
+        Some(bytes.iter().fold(0, |word, &b| word << 8 | b as u32))
+    }
+
+    let (address, rest) = string(packet)?;
+    if !address.starts_with('/') {
+        return None;
+    }
+    let (tags, args) = string(rest)?;
+    let value = if tags.starts_with(",f") {
+        f32::from_bits(word(args)?)
+    } else if tags.starts_with(",i") {
+        word(args)? as i32 as f32
+    } else {
+        return None;
+    };
+    Some((Control::Osc(address.into()), value))
+}
+
+/// Hands MIDI control changes from the audio thread to the graph's thread.
+struct MidiProcessor {
+    port: Port<MidiIn>,
+    tx: SyncSender<(Control, f32)>,
+}
+
+impl ProcessHandler for MidiProcessor {
+    fn process(&mut self, _: &Client, ps: &ProcessScope) -> JackControl {
+        for event in self.port.iter(ps) {
+            if let Some(control) = parse_midi(event.bytes) {
+                // dropped if the graph falls behind, the next move of the control catches up
+                let _ = self.tx.try_send(control);
+            }
+        }
+        JackControl::Continue
+    }
+}
+
+/// Passes MIDI and OSC controls on to the graph's mappings.
+pub struct ControlIn {
+    ifc: Arc<flow::Interface>,
+    osc_port: u16,
+    client: Option<AsyncClient<(), MidiProcessor>>,
+    breaker: Breaker,
+}
+
+impl ControlIn {
+    fn start_midi(&mut self) -> Result<Receiver<(Control, f32)>, JackError> {
+        let (client, _status) = Client::new("flow-synth-control", ClientOptions::NO_START_SERVER)?;
+        let port = client.register_port("midi-in", MidiIn::default())?;
+        let (tx, rx) = mpsc::sync_channel(MIDI_QUEUE);
+        self.client = Some(AsyncClient::new(client, (), MidiProcessor { port, tx })?);
+        Ok(rx)
+    }
+}
+
+impl Module for ControlIn {
+    fn new(ifc: Arc<flow::Interface>) -> ControlIn {
+        ControlIn {
+            ifc,
+            osc_port: OSC_PORT,
+            client: None,
+            breaker: Breaker::new(),
+        }
+    }
+    fn name() -> &'static str {
+        "Control In"
+    }
+    fn start<Ex: Spawn>(&mut self, _exec: Ex) {
+        match self.start_midi() {
+            Ok(rx) => {
+                let (graph, breaker) = (self.ifc.graph(), self.breaker.clone());
+                thread::spawn(move || {
+                    while !breaker.test() {
+                        match rx.recv_timeout(POLL) {
+                            Ok((control, x)) => graph.control(&control, x),
+                            Err(mpsc::RecvTimeoutError::Timeout) => {}
+                            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                        }
+                    }
+                });
+            }
+            Err(e) => println!("control in midi err: {:?}", e),
+        }
+        let (graph, breaker, port) = (self.ifc.graph(), self.breaker.clone(), self.osc_port);
+        thread::spawn(move || {
+            if let Err(e) = listen_osc(port, &graph, &breaker) {
+                println!("control in osc err: {}", e);
+            }
+        });
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.client = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> Value {
+        json!({ "osc_port": self.osc_port })
+    }
+    fn load_state(&mut self, state: Value) {
+        if let Some(port) = state["osc_port"].as_u64() {
+            self.osc_port = port as u16;
+        }
+    }
+}
+
+fn listen_osc(port: u16, graph: &Graph, breaker: &Breaker) -> io::Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    socket.set_read_timeout(Some(POLL))?;
+    let mut packet = [0; 1024];
+    while !breaker.test() {
+        match socket.recv(&mut packet) {
+            Ok(len) => {
+                if let Some((control, x)) = parse_osc(&packet[..len]) {
+                    graph.control(&control, x);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mapping() {
+    use crate::module::flow::NodeId;
+
+    let cc = |number| Control::Cc { channel: 0, number };
+    assert_eq!(
+        parse_midi(&[0xb2, 7, 127]),
+        Some((
+            Control::Cc {
+                channel: 2,
+                number: 7
+            },
+            1.0
+        ))
+    );
+    assert_eq!(parse_midi(&[0x92, 60, 100]), None);
+    let mut osc = b"/mix/fader1\0,f\0\0".to_vec();
+    osc.extend_from_slice(&[0x3f, 0x00, 0x00, 0x00]);
+    assert_eq!(parse_osc(&osc), Some((Control::Osc("/mix/fader1".into()), 0.5)));
+    assert_eq!(parse_osc(b"/a\0\0,s\0\0x\0\0\0"), None);
+
+    let target = |param: &str, curve| Target {
+        node: NodeId(1),
+        param: param.into(),
+        min: 100.0,
+        max: 10000.0,
+        curve,
+    };
+    assert!((target("Cutoff", Curve::Exponential).value(0.5) - 1000.0).abs() < 0.01);
+    assert_eq!(target("Cutoff", Curve::Linear).value(2.0), 10000.0);
+
+    let mut mappings = Mappings::default();
+    assert!(mappings.control(&cc(1), 0.5).is_empty());
+    mappings.learning = Some(target("Cutoff", Curve::Linear));
+    assert_eq!(
+        mappings.control(&cc(1), 0.0),
+        vec![(NodeId(1), "Cutoff".into(), 100.0)]
+    );
+    // learning again moves the param to another control
+    mappings.learning = Some(target("Cutoff", Curve::Linear));
+    mappings.control(&cc(2), 0.0);
+    assert!(mappings.control(&cc(1), 1.0).is_empty());
+    assert_eq!(
+        mappings.control(&cc(2), 1.0),
+        vec![(NodeId(1), "Cutoff".into(), 10000.0)]
+    );
+}
@@ -0,0 +1,433 @@
+//! A generative sequencer playing new melodies in the style of those it has heard.
+//!
+//! `Markov` counts which keys follow which runs of `Order` keys, in the notes arriving on `Learn`
+//! or a corpus typed into its body, and every value on `Trigger` plays a key chosen by those
+//! counts, following on from the keys it played before. Runs it hasn't heard are shortened until
+//! it has. `Temperature` shapes the choice: at 1.0 keys are as likely as they were heard, lower
+//! values favour the most common and 0.0 always picks it, and higher values even the odds out.
+//! Each key is sent as a `Note`, releasing the one before, and as a key number for `Pitch`.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::physical::Noise;
+use crate::module::sampler::{parse_key, Note};
+use crate::module::{flow, util, Module};
+
+use serde_json;
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+/// Longest run of keys a choice can depend on.
+pub const MAX_ORDER: usize = 8;
+/// Keys remembered, the oldest being forgotten to make room for new ones.
+pub const MAX_KEYS: usize = 4096;
+/// Velocity of the keys played until a note has been heard.
+const DEFAULT_VELOCITY: u8 = 100;
+
+/// Parse a corpus of keys, as numbers or note names like `c4`, separated by spaces into phrases
+/// separated by `;` or newlines.
+pub fn parse_corpus(s: &str) -> Option<Vec<Vec<u8>>> {
+    s.split(|c| c == ';' || c == '\n')
+        .filter(|phrase| !phrase.trim().is_empty())
+        .map(|phrase| phrase.split_whitespace().map(parse_key).collect())
+        .collect()
+}
+
+/// Counts of the keys following each run of keys in phrases.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Chain {
+    order: usize,
+    /// The keys heard, in phrases which runs don't cross.
+    phrases: Vec<Vec<u8>>,
+    /// How often each key followed each run of up to `order` keys, the empty run counting every
+    /// key. Rebuilt from the phrases when they're loaded.
+    #[serde(skip)]
+    counts: BTreeMap<Vec<u8>, BTreeMap<u8, u32>>,
+    /// Whether learned keys continue the last phrase, rather than starting one.
+    #[serde(skip)]
+    live: bool,
+}
+
+impl Chain {
+    pub fn new(order: usize) -> Chain {
+        Chain {
+            order: order.min(MAX_ORDER),
+            phrases: Vec::new(),
+            counts: BTreeMap::new(),
+            live: false,
+        }
+    }
+    /// Forget everything learned, and learn `phrases` instead.
+    pub fn set_phrases(&mut self, phrases: Vec<Vec<u8>>) {
+        self.phrases = phrases.into_iter().filter(|phrase| !phrase.is_empty()).collect();
+        self.live = false;
+        self.rebuild();
+    }
+    fn describe(&self) -> String {
+        let phrases: Vec<String> = self
+            .phrases
+            .iter()
+            .map(|phrase| {
+                let keys: Vec<String> = phrase.iter().map(|key| key.to_string()).collect();
+                keys.join(" ")
+            })
+            .collect();
+        phrases.join("; ")
+    }
+    pub fn order(&self) -> usize {
+        self.order
+    }
+    pub fn set_order(&mut self, order: usize) {
+        let order = order.min(MAX_ORDER);
+        if order != self.order {
+            self.order = order;
+            self.rebuild();
+        }
+    }
+    /// Count every transition again, after loading or changing the order.
+    pub fn rebuild(&mut self) {
+        self.counts.clear();
+        let phrases = mem::replace(&mut self.phrases, Vec::new());
+        for phrase in &phrases {
+            for idx in 0..phrase.len() {
+                self.count(phrase, idx);
+            }
+        }
+        self.phrases = phrases;
+    }
+    /// Count the transitions to `phrase[idx]` from each run before it.
+    fn count(&mut self, phrase: &[u8], idx: usize) {
+        for len in 0..self.order.min(idx) + 1 {
+            let next = self
+                .counts
+                .entry(phrase[idx - len..idx].to_vec())
+                .or_insert_with(BTreeMap::new);
+            *next.entry(phrase[idx]).or_insert(0) += 1;
+        }
+    }
+    /// Learn `key`, following the keys learned before it.
+    pub fn learn(&mut self, key: u8) {
+        if !self.live || self.phrases.is_empty() {
+            self.phrases.push(Vec::new());
+            self.live = true;
+        }
+        let mut phrase = self.phrases.pop().unwrap();
+        phrase.push(key);
+        let idx = phrase.len() - 1;
+        self.count(&phrase, idx);
+        self.phrases.push(phrase);
+        while self.phrases.iter().map(|phrase| phrase.len()).sum::<usize>() > MAX_KEYS {
+            self.forget_oldest();
+        }
+    }
+    fn forget_oldest(&mut self) {
+        let mut phrase = self.phrases.remove(0);
+        // only the transitions whose run starts at the oldest key depend on it
+        for idx in 0..self.order.min(phrase.len() - 1) + 1 {
+            let run = phrase[..idx].to_vec();
+            let key = phrase[idx];
+            let remove = self.counts.get_mut(&run).map_or(false, |next| {
+                if let Some(count) = next.get_mut(&key) {
+                    *count -= 1;
+                }
+                next.retain(|_, &mut count| count > 0);
+                next.is_empty()
+            });
+            if remove {
+                self.counts.remove(&run);
+            }
+        }
+        phrase.remove(0);
+        if !phrase.is_empty() {
+            self.phrases.insert(0, phrase);
+        }
+    }
+    /// Choose a key to follow `played`, or nothing if no keys have been learned.
+    pub fn next(&self, played: &[u8], temperature: f32, noise: &mut Noise) -> Option<u8> {
+        let longest = self.order.min(played.len());
+        let next = (0..longest + 1)
+            .rev()
+            .filter_map(|len| self.counts.get(&played[played.len() - len..]))
+            .next()?;
+        if temperature <= 0.0 {
+            // the most common, the lowest key of those as common
+            let most = next.values().cloned().max()?;
+            return next
+                .iter()
+                .find(|&(_, &count)| count == most)
+                .map(|(&key, _)| key);
+        }
+        let weight = |count: u32| (count as f32).powf(1.0 / temperature);
+        let total: f32 = next.values().map(|&count| weight(count)).sum();
+        let mut pick = (noise.next() + 1.0) / 2.0 * total;
+        for (&key, &count) in next {
+            pick -= weight(count);
+            if pick <= 0.0 {
+                return Some(key);
+            }
+        }
+        next.keys().cloned().last()
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    SetCorpus(Vec<Vec<u8>>),
+}
+
+struct Shared {
+    chain: Chain,
+    temperature: f32,
+    /// The latest keys played, up to `MAX_ORDER`.
+    played: Vec<u8>,
+    sounding: Option<u8>,
+    velocity: u8,
+    noise: Noise,
+}
+
+pub struct Markov {
+    ifc: Arc<flow::Interface>,
+    learn_port: Arc<flow::Port<Note, ()>>,
+    trigger_port: Arc<flow::Port<f32, ()>>,
+    order_port: Arc<flow::Port<f32, ()>>,
+    temperature_port: Arc<flow::Port<f32, ()>>,
+    notes_port: Arc<flow::Port<(), Note>>,
+    key_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Module for Markov {
+    fn new(ifc: Arc<flow::Interface>) -> Markov {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Markov {
+            learn_port: ifc.get_or_create_port("Learn".into()),
+            trigger_port: ifc.get_or_create_port("Trigger".into()),
+            order_port: ifc.get_or_create_port("Order".into()),
+            temperature_port: ifc.get_or_create_port("Temperature".into()),
+            notes_port: ifc.get_or_create_port("Notes".into()),
+            key_port: ifc.get_or_create_port("Key".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            shared: Arc::new(Mutex::new(Shared {
+                chain: Chain::new(2),
+                temperature: 1.0,
+                played: Vec::new(),
+                sounding: None,
+                velocity: DEFAULT_VELOCITY,
+                noise: Noise(0x6c07_8965),
+            })),
+        }
+    }
+    fn name() -> &'static str {
+        "Markov"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let shared = self.shared.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::SetCorpus(phrases) => shared.lock().unwrap().chain.set_phrases(phrases),
+                }
+            }
+        })
+        .unwrap();
+
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.learn_port.clone(),
+            move |note: Note| {
+                if note.velocity > 0 {
+                    let mut shared = shared.lock().unwrap();
+                    shared.chain.learn(note.key);
+                    shared.velocity = note.velocity;
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.order_port.clone(),
+            move |order: f32| {
+                let order = order.round().max(0.0) as usize;
+                shared.lock().unwrap().chain.set_order(order);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.temperature_port.clone(),
+            move |temperature: f32| shared.lock().unwrap().temperature = temperature,
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let (mut note_tx, note_rx) = mpsc::channel(4);
+        let (mut key_tx, key_rx) = mpsc::channel(1);
+        let shared = self.shared.clone();
+        util::start_sink(
+            self.trigger_port.clone(),
+            move |_: f32| {
+                let mut shared = shared.lock().unwrap();
+                let shared = &mut *shared;
+                let key = match shared
+                    .chain
+                    .next(&shared.played, shared.temperature, &mut shared.noise)
+                {
+                    Some(key) => key,
+                    None => return,
+                };
+                if let Some(key) = shared.sounding.take() {
+                    let _ = note_tx.try_send(Note { key, velocity: 0 });
+                }
+                let _ = note_tx.try_send(Note {
+                    key,
+                    velocity: shared.velocity,
+                });
+                let _ = key_tx.try_send(key as f32);
+                shared.sounding = Some(key);
+                shared.played.push(key);
+                if shared.played.len() > MAX_ORDER {
+                    shared.played.remove(0);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(note_rx, self.notes_port.clone(), &exec);
+        util::start_source(key_rx, self.key_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(&self.shared.lock().unwrap().chain).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value::<Chain>(state) {
+            Ok(mut chain) => {
+                chain.order = chain.order.min(MAX_ORDER);
+                chain.rebuild();
+                self.shared.lock().unwrap().chain = chain;
+            }
+            Err(e) => println!("markov state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_markov() {
+    let mut noise = Noise(1);
+    let mut chain = Chain::new(1);
+    chain.set_phrases(parse_corpus("c4 d4 e4 c4 d4 f4\n 60 62 64").unwrap());
+    assert_eq!(chain.describe(), "60 62 64 60 62 65; 60 62 64");
+    // after 60 always comes 62, and after 62 mostly 64
+    assert_eq!(chain.next(&[60], 0.0, &mut noise), Some(62));
+    assert_eq!(chain.next(&[60], 1.0, &mut noise), Some(62));
+    assert_eq!(chain.next(&[62], 0.0, &mut noise), Some(64));
+    let mut heard = BTreeMap::new();
+    for _ in 0..1000 {
+        *heard
+            .entry(chain.next(&[62], 1.0, &mut noise).unwrap())
+            .or_insert(0) += 1;
+    }
+    assert_eq!(heard.keys().cloned().collect::<Vec<u8>>(), vec![64, 65]);
+    assert!(heard[&64] > heard[&65]);
+    // 65 was never followed, so any key heard might come next
+    assert_eq!(chain.next(&[65], 0.0, &mut noise), Some(60));
+
+    // two keys tell which phrase it is in
+    chain.set_order(2);
+    assert_eq!(chain.next(&[64, 60], 0.0, &mut noise), Some(62));
+    assert_eq!(chain.next(&[60, 62], 0.0, &mut noise), Some(64));
+    for &key in &[70, 60, 62, 70, 60, 62, 70, 60, 62, 70] {
+        chain.learn(key);
+    }
+    assert_eq!(chain.next(&[60, 62], 0.0, &mut noise), Some(70));
+    assert_eq!(chain.phrases.len(), 3);
+
+    // forgetting takes back exactly what learning counted
+    let mut chain = Chain::new(2);
+    for idx in 0..MAX_KEYS + 3 {
+        chain.learn((idx % 5) as u8);
+    }
+    let mut rebuilt = chain.clone();
+    rebuilt.rebuild();
+    assert_eq!(chain.counts, rebuilt.counts);
+    assert!(parse_corpus("60 h4").is_none());
+    assert_eq!(Chain::new(1).next(&[60], 1.0, &mut noise), None);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct MarkovGui {
+    bounds: Box3,
+    corpus_box: TextBox,
+    load_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Markov {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let corpus = self.shared.lock().unwrap().chain.describe();
+        Box::new(MarkovGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            corpus_box: TextBox::new(ctx.clone(), corpus, row(0.0)),
+            load_button: Button::new(ctx.clone(), "Load corpus".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for MarkovGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.corpus_box.render(device, ctx);
+        self.load_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.corpus_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.load_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match parse_corpus(self.corpus_box.content()) {
+                    Some(phrases) => {
+                        self.load_button.set_label("Load corpus".into());
+                        self.cmd_tx
+                            .unbounded_send(UserCommand::SetCorpus(phrases))
+                            .unwrap();
+                    }
+                    None => self
+                        .load_button
+                        .set_label("Invalid: keys or note names; ...".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Placeholders for modules whose type isn't available, e.g. because the plugin providing it
+//! isn't installed.
+//!
+//! A `Missing` module keeps everything the patch said about the module it stands in for: its type,
+//! its own saved state with the parameters, and its connections, so saving the patch again writes
+//! it back unchanged. Without the type the ports can't be created, so the node has none and the
+//! connections are only remembered, by port name. Once the type is registered the placeholder can
+//! be filled in by the real module, see `Root::fill_missing` and `Installation::fill_missing`.
+
+use futures::task::Spawn;
+
+use crate::module::{flow, Module};
+
+use serde_json::{self, Value};
+
+use std::sync::{Arc, Mutex};
+
+/// The module type name of placeholders.
+pub const NAME: &str = "Missing";
+/// The tag of placeholder nodes.
+pub const TAG: &str = "missing";
+/// The metadata key holding the type a placeholder stands in for.
+pub const META_KEY: &str = "missing_type";
+
+/// A remembered connection of one of the missing module's ports.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub port: String,
+    pub node: flow::NodeId,
+    pub other_port: String,
+    /// Whether the connection was saved from this end, to save it the same way again.
+    pub outgoing: bool,
+    /// See `flow::ConnectOptions::gain`.
+    #[serde(default)]
+    pub gain: Option<f32>,
+}
+
+/// What is kept of the missing module.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Placeholder {
+    pub type_name: String,
+    /// From the module's `save_state`.
+    pub state: Value,
+    pub links: Vec<Link>,
+}
+
+impl Placeholder {
+    /// The names of the ports known from the connections, each listed once.
+    pub fn port_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for link in &self.links {
+            if !names.contains(&link.port) {
+                names.push(link.port.clone());
+            }
+        }
+        names
+    }
+}
+
+/// Stands in for a module of an unknown type, saving the `Placeholder` as its state.
+pub struct Missing {
+    ifc: Arc<flow::Interface>,
+    placeholder: Arc<Mutex<Placeholder>>,
+}
+
+impl Missing {
+    pub fn placeholder(&self) -> Placeholder {
+        self.placeholder.lock().unwrap().clone()
+    }
+}
+
+impl Module for Missing {
+    fn new(ifc: Arc<flow::Interface>) -> Missing {
+        Missing {
+            ifc,
+            placeholder: Arc::new(Mutex::new(Placeholder::default())),
+        }
+    }
+    fn name() -> &'static str {
+        NAME
+    }
+    fn start<Ex: Spawn>(&mut self, _exec: Ex) {}
+    fn stop(&mut self) {}
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> Value {
+        serde_json::to_value(self.placeholder()).unwrap()
+    }
+    fn load_state(&mut self, state: Value) {
+        match serde_json::from_value::<Placeholder>(state) {
+            Ok(placeholder) => {
+                if let Some(node) = self.ifc.graph().node(self.ifc.id()) {
+                    node.add_tag(TAG);
+                    node.set_meta(META_KEY, &placeholder.type_name);
+                }
+                *self.placeholder.lock().unwrap() = placeholder;
+            }
+            Err(e) => println!("missing state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_missing() {
+    let graph = flow::Graph::new();
+    let ifc = graph.add_node();
+    let mut missing = Missing::new(ifc.clone());
+    let link = |port: &str, node| Link {
+        port: port.into(),
+        node: flow::NodeId(node),
+        other_port: "Output".into(),
+        outgoing: false,
+        gain: None,
+    };
+    let placeholder = Placeholder {
+        type_name: "Theremin".into(),
+        state: json!({ "params": { "Pitch": 0.5 } }),
+        links: vec![link("Pitch", 3), link("Volume", 4), link("Pitch", 5)],
+    };
+    missing.load_state(serde_json::to_value(&placeholder).unwrap());
+    assert_eq!(missing.placeholder(), placeholder);
+    assert_eq!(missing.save_state(), serde_json::to_value(&placeholder).unwrap());
+    assert_eq!(
+        placeholder.port_names(),
+        vec!["Pitch".to_string(), "Volume".to_string()]
+    );
+    let node = graph.node(ifc.id()).unwrap();
+    assert!(node.has_tag(TAG));
+    assert_eq!(node.meta::<String>(META_KEY), Some("Theremin".to_string()));
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{component::*, event::*, geom::*, module_gui::*, render::*};
+struct MissingGui {
+    bounds: Box3,
+    placeholder: Arc<Mutex<Placeholder>>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Missing {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        Box::new(MissingGui {
+            bounds,
+            placeholder: self.placeholder.clone(),
+        })
+    }
+}
+impl GuiComponent<bool> for MissingGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        let placeholder = self.placeholder.lock().unwrap();
+        let mut pos = self.bounds.pos + Pt3::new(PADDING, PADDING, 0.0);
+        ctx.draw_text(&placeholder.type_name, pos, [1.0, 0.4, 0.4]);
+        for name in placeholder.port_names() {
+            pos.y += ROW_HEIGHT;
+            ctx.draw_text(&name, pos, [0.7, 0.7, 0.7]);
+        }
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        false
+    }
+}
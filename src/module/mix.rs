@@ -0,0 +1,328 @@
+//! Basic level, mixing and routing modules, built with `Process`.
+
+use crate::module::audio_io::Frame;
+use crate::module::process::Process;
+use crate::module::simd;
+
+use ndarray::Axis;
+
+use std::f32::consts::PI;
+
+/// Scales its input by the `Gain` control.
+pub struct Gain {
+    gain: f32,
+}
+
+impl Process for Gain {
+    const NAME: &'static str = "Gain";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] = &[("Gain", 1.0)];
+    const RANGES: &'static [(f32, f32)] = &[(0.0, 2.0)];
+    fn new() -> Gain {
+        Gain {
+            gain: 1.0,
+        }
+    }
+    fn set_param(&mut self, _idx: usize, value: f32) {
+        self.gain = value;
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        outputs[0].data.assign(&inputs[0].data);
+        simd::scale_array(&mut outputs[0].data, self.gain);
+    }
+}
+
+const N_CHANNELS: usize = 4;
+
+/// Sums four inputs, each with its own level.
+pub struct Mixer {
+    levels: [f32; N_CHANNELS],
+}
+
+impl Process for Mixer {
+    const NAME: &'static str = "Mixer";
+    const INPUTS: &'static [&'static str] = &["In 1", "In 2", "In 3", "In 4"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] =
+        &[("Level 1", 1.0), ("Level 2", 1.0), ("Level 3", 1.0), ("Level 4", 1.0)];
+    const RANGES: &'static [(f32, f32)] = &[(0.0, 1.0); N_CHANNELS];
+    fn new() -> Mixer {
+        Mixer {
+            levels: [1.0; N_CHANNELS],
+        }
+    }
+    fn set_param(&mut self, idx: usize, value: f32) {
+        self.levels[idx] = value;
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        for (input, &level) in inputs.iter().zip(&self.levels) {
+            simd::mix_array(&mut outputs[0].data, &input.data, level);
+        }
+    }
+}
+
+/// Seconds for gain and pan changes to mostly settle, so moving them doesn't click.
+const SMOOTHING: f32 = 0.02;
+/// Corner frequency of the DC blocker, in Hz.
+const DC_CUTOFF: f32 = 10.0;
+/// Level above which the clipper starts to bend the signal. It never exceeds 1.0.
+const CLIP_KNEE: f32 = 0.5;
+
+/// The usual end of an output chain in one node: gain in dB, constant power balance, a DC
+/// blocker and a soft clipper, for stereo audio. The DC blocker and clipper are switched on by
+/// params above 0.5.
+pub struct ChannelStrip {
+    gain_db: f32,
+    pan: f32,
+    dc_block: bool,
+    clip: bool,
+    /// Smoothed gain of each channel, or `None` before the first block.
+    gains: Option<[f32; 2]>,
+    /// Previous input and output of the DC blocker for each channel.
+    dc: [(f32, f32); 2],
+}
+
+impl ChannelStrip {
+    /// The gain of each channel that the params ask for.
+    fn targets(&self) -> [f32; 2] {
+        let gain = 10f32.powf(self.gain_db / 20.0);
+        let angle = (self.pan.max(-1.0).min(1.0) + 1.0) * PI / 4.0;
+        [gain * angle.cos(), gain * angle.sin()]
+    }
+}
+
+fn soft_clip(x: f32) -> f32 {
+    if x.abs() <= CLIP_KNEE {
+        x
+    } else {
+        let bend = (1.0 - CLIP_KNEE) * ((x.abs() - CLIP_KNEE) / (1.0 - CLIP_KNEE)).tanh();
+        x.signum() * (CLIP_KNEE + bend)
+    }
+}
+
+impl Process for ChannelStrip {
+    const NAME: &'static str = "Channel Strip";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] =
+        &[("Gain dB", 0.0), ("Pan", 0.0), ("DC Block", 1.0), ("Clip", 1.0)];
+    // the switches have no range, so exploring a patch doesn't flip them
+    const RANGES: &'static [(f32, f32)] = &[(-24.0, 6.0), (-1.0, 1.0)];
+    const INPUT_CHANNELS: Option<usize> = Some(2);
+    const OUTPUT_CHANNELS: Option<usize> = Some(2);
+    fn new() -> ChannelStrip {
+        ChannelStrip {
+            gain_db: 0.0,
+            pan: 0.0,
+            dc_block: true,
+            clip: true,
+            gains: None,
+            dc: [(0.0, 0.0); 2],
+        }
+    }
+    fn set_param(&mut self, idx: usize, value: f32) {
+        match idx {
+            0 => self.gain_db = value,
+            1 => self.pan = value,
+            2 => self.dc_block = value > 0.5,
+            _ => self.clip = value > 0.5,
+        }
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let input = &inputs[0];
+        let in_channels = input.data.dim().1;
+        if in_channels == 0 {
+            return;
+        }
+        // a mono signal from a port that didn't declare its channels goes to both sides
+        for (channel, mut column) in outputs[0].data.axis_iter_mut(Axis(1)).enumerate() {
+            column.assign(&input.data.column(channel.min(in_channels - 1)));
+        }
+        let targets = self.targets();
+        let mut gains = self.gains.unwrap_or(targets);
+        let smoothing = 1.0 - (-1.0 / (SMOOTHING * input.rate)).exp();
+        let pole = 1.0 - 2.0 * PI * DC_CUTOFF / input.rate;
+        for mut samples in outputs[0].data.outer_iter_mut() {
+            for (channel, sample) in samples.iter_mut().enumerate().take(2) {
+                gains[channel] += (targets[channel] - gains[channel]) * smoothing;
+                let mut x = *sample;
+                if self.dc_block {
+                    let (last_in, last_out) = self.dc[channel];
+                    let y = x - last_in + pole * last_out;
+                    self.dc[channel] = (x, y);
+                    x = y;
+                }
+                x *= gains[channel];
+                *sample = if self.clip { soft_clip(x) } else { x };
+            }
+        }
+        self.gains = Some(gains);
+    }
+}
+
+/// Sends each of four inputs to each of four outputs with its own gain, for quadraphonic and
+/// ambisonic routing or feedback networks in one node. `In 2 > Out 3` is the gain from the
+/// second input to the third output, and the matrix starts out passing each input straight
+/// through. Gains glide like the channel strip's, so the matrix can be modulated from its ports
+/// or set through the params API without clicks.
+pub struct MatrixMixer {
+    /// The gains asked for, by input then output.
+    targets: [[f32; N_CHANNELS]; N_CHANNELS],
+    /// Smoothed gains, or `None` before the first block.
+    gains: Option<[[f32; N_CHANNELS]; N_CHANNELS]>,
+}
+
+impl Process for MatrixMixer {
+    const NAME: &'static str = "Matrix Mixer";
+    const INPUTS: &'static [&'static str] = &["In 1", "In 2", "In 3", "In 4"];
+    const OUTPUTS: &'static [&'static str] = &["Out 1", "Out 2", "Out 3", "Out 4"];
+    const PARAMS: &'static [(&'static str, f32)] = &[
+        ("In 1 > Out 1", 1.0),
+        ("In 1 > Out 2", 0.0),
+        ("In 1 > Out 3", 0.0),
+        ("In 1 > Out 4", 0.0),
+        ("In 2 > Out 1", 0.0),
+        ("In 2 > Out 2", 1.0),
+        ("In 2 > Out 3", 0.0),
+        ("In 2 > Out 4", 0.0),
+        ("In 3 > Out 1", 0.0),
+        ("In 3 > Out 2", 0.0),
+        ("In 3 > Out 3", 1.0),
+        ("In 3 > Out 4", 0.0),
+        ("In 4 > Out 1", 0.0),
+        ("In 4 > Out 2", 0.0),
+        ("In 4 > Out 3", 0.0),
+        ("In 4 > Out 4", 1.0),
+    ];
+    const RANGES: &'static [(f32, f32)] = &[(0.0, 1.0); N_CHANNELS * N_CHANNELS];
+    fn new() -> MatrixMixer {
+        MatrixMixer {
+            targets: [[0.0; N_CHANNELS]; N_CHANNELS],
+            gains: None,
+        }
+    }
+    fn set_param(&mut self, idx: usize, value: f32) {
+        self.targets[idx / N_CHANNELS][idx % N_CHANNELS] = value;
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let rows = inputs[0].data.dim().0;
+        let smoothing = 1.0 - (-1.0 / (SMOOTHING * inputs[0].rate)).exp();
+        let mut gains = self.gains.unwrap_or(self.targets);
+        for (input, (gains, targets)) in inputs.iter().zip(gains.iter_mut().zip(&self.targets)) {
+            for (output, (gain, &target)) in outputs.iter_mut().zip(gains.iter_mut().zip(targets)) {
+                if *gain == 0.0 && target == 0.0 {
+                    continue;
+                }
+                let channels = input.data.dim().1.min(output.data.dim().1);
+                for row in 0..rows {
+                    *gain += (target - *gain) * smoothing;
+                    for channel in 0..channels {
+                        output.data[[row, channel]] += input.data[[row, channel]] * *gain;
+                    }
+                }
+                // settle exactly, so routes that are switched off stop costing anything
+                if (target - *gain).abs() < 1e-6 {
+                    *gain = target;
+                }
+            }
+        }
+        self.gains = Some(gains);
+    }
+}
+
+#[test]
+fn test_mixer() {
+    use ndarray::Array2;
+
+    let frame = |value: f32| Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 2), value),
+        meta: None,
+    };
+    let mut mixer = Mixer::new();
+    mixer.set_param(1, 0.5);
+    let inputs = [frame(1.0), frame(2.0), frame(0.0), frame(0.0)];
+    let mut outputs = [frame(0.0)];
+    mixer.process(&inputs, &mut outputs);
+    assert!(outputs[0].data.iter().all(|&x| x == 2.0));
+}
+
+#[test]
+fn test_channel_strip() {
+    use ndarray::Array2;
+
+    let frame = |value: f32| Frame {
+        rate: 1000.0,
+        time: None,
+        data: Array2::from_elem((100, 2), value),
+        meta: None,
+    };
+    let mut strip = ChannelStrip::new();
+    let mut outputs = [frame(0.0)];
+    // an offset decays away
+    for _ in 0..10 {
+        strip.process(&[frame(0.5)], &mut outputs);
+    }
+    assert!(outputs[0].data.iter().all(|&x| x.abs() < 1e-3));
+
+    // hard left after smoothing, and loud signals stay under 1.0
+    let mut strip = ChannelStrip::new();
+    strip.set_param(2, 0.0);
+    strip.set_param(1, -1.0);
+    strip.set_param(0, 20.0);
+    strip.process(&[frame(1.0)], &mut outputs);
+    assert!(outputs[0].data[[99, 0]] > 0.99 && outputs[0].data[[99, 0]] <= 1.0);
+    assert!(outputs[0].data[[99, 1]].abs() < 1e-6);
+
+    // gain changes glide instead of jumping
+    let mut strip = ChannelStrip::new();
+    strip.set_param(2, 0.0);
+    strip.set_param(3, 0.0);
+    strip.process(&[frame(0.1)], &mut outputs);
+    strip.set_param(0, -6.0);
+    strip.process(&[frame(0.1)], &mut outputs);
+    let centre = 0.1 * (PI / 4.0).cos();
+    assert!(outputs[0].data[[0, 0]] > 0.9 * centre);
+    assert!((outputs[0].data[[99, 0]] - centre * 0.501).abs() < 0.01 * centre);
+}
+
+#[test]
+fn test_matrix_mixer() {
+    use ndarray::Array2;
+
+    let frame = |value: f32| Frame {
+        rate: 1000.0,
+        time: None,
+        data: Array2::from_elem((100, 2), value),
+        meta: None,
+    };
+    let mut matrix = MatrixMixer::new();
+    for (idx, &(_, value)) in MatrixMixer::PARAMS.iter().enumerate() {
+        matrix.set_param(idx, value);
+    }
+    let inputs = [frame(1.0), frame(2.0), frame(3.0), frame(4.0)];
+    let mut outputs = [frame(0.0), frame(0.0), frame(0.0), frame(0.0)];
+    matrix.process(&inputs, &mut outputs);
+    for (output, value) in outputs.iter().zip(&[1.0, 2.0, 3.0, 4.0]) {
+        assert!(output.data.iter().all(|x| x == value));
+    }
+
+    // swap the first two inputs and send the fourth to the third output as well
+    matrix.set_param(0, 0.0);
+    matrix.set_param(1, 1.0);
+    matrix.set_param(4, 1.0);
+    matrix.set_param(5, 0.0);
+    matrix.set_param(14, 0.5);
+    for _ in 0..10 {
+        let mut outputs = [frame(0.0), frame(0.0), frame(0.0), frame(0.0)];
+        matrix.process(&inputs, &mut outputs);
+        if outputs[0].data[[99, 0]] > 1.999 {
+            assert!((outputs[1].data[[99, 1]] - 1.0).abs() < 1e-3);
+            assert!((outputs[2].data[[99, 0]] - 5.0).abs() < 1e-3);
+            return;
+        }
+    }
+    panic!("gains didn't settle");
+}
@@ -1,9 +1,102 @@
+#[cfg(feature = "alsa-io")]
+pub mod alsa_io;
+#[cfg(feature = "dsp")]
+pub mod ambisonics;
+#[cfg(feature = "network")]
+pub mod artnet;
 pub mod audio_io;
+#[cfg(feature = "dsp")]
+pub mod automaton;
+pub mod build;
+pub mod channels;
+pub mod chaos;
+#[cfg(feature = "clap")]
+pub mod clap;
+pub mod comment;
 pub mod debug;
-pub mod flow;
+#[cfg(feature = "dsp")]
+pub mod draw;
+#[cfg(feature = "dsp")]
+pub mod dynamics;
+#[cfg(feature = "evolve")]
+pub mod evolve;
+pub mod expr;
+#[cfg(feature = "dsp")]
+pub mod filter;
+pub mod fft;
+#[cfg(feature = "dsp")]
+pub mod freeze;
+pub mod gesture;
+pub mod golden;
+#[cfg(feature = "gpio")]
+pub mod gpio;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+#[cfg(feature = "hardware")]
+pub mod hid;
+#[cfg(feature = "network")]
+pub mod http;
+pub mod latency;
+pub mod limiter;
+#[cfg(feature = "livecode")]
 pub mod livecode;
+#[cfg(feature = "dsp")]
+pub mod looper;
+#[cfg(feature = "dsp")]
+pub mod lsystem;
+#[cfg(feature = "lv2")]
+pub mod lv2;
+pub mod macros;
+pub mod mapping;
+#[cfg(feature = "dsp")]
+pub mod markov;
+pub mod missing;
+pub mod mix;
+#[cfg(feature = "network")]
+pub mod mqtt;
+#[cfg(feature = "ndi")]
+pub mod ndi;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+#[cfg(feature = "dsp")]
+pub mod particles;
+pub mod perlin;
+#[cfg(feature = "dsp")]
+pub mod physical;
+pub mod process;
+pub mod record;
+#[cfg(feature = "dsp")]
+pub mod resample;
+#[cfg(feature = "dsp")]
+pub mod reverb;
+pub mod routing;
+#[cfg(feature = "dsp")]
+pub mod sampler;
+pub mod scheduler;
+pub mod screen_capture;
+#[cfg(feature = "hardware")]
+pub mod serial;
+pub mod slew;
+#[cfg(feature = "dsp")]
+pub mod spectrogram;
+#[cfg(feature = "dsp")]
+pub mod tap;
+pub mod tensor;
+pub mod testkit;
+pub mod throttle;
+pub mod timeline;
+pub mod tuning;
+pub mod util;
+pub mod video_out;
+#[cfg(feature = "dsp")]
+pub mod wavetable;
 
-use futures::executor;
+pub use flow_synth_core::{
+    compare, declick, flow, partition, pool, randomize, scene, simd, snapshot, solo, workers,
+};
+
+use futures::task::Spawn;
+use serde_json;
 use std::sync::Arc;
 
 pub trait Module: Send {
@@ -13,7 +106,61 @@ pub trait Module: Send {
     fn name() -> &'static str
     where
         Self: Sized;
-    fn start<Ex: executor::Executor>(&mut self, exec: Ex);
+    fn start<Ex: Spawn>(&mut self, exec: Ex);
     fn stop(&mut self);
     fn ports(&self) -> Vec<Arc<flow::OpaquePort>>;
+    /// Serialize internal state which the patch doesn't otherwise capture, like loaded files or
+    /// settings made in the module's GUI, so a saved patch comes back the way it was left.
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+    /// Restore state produced by `save_state`. Called before the module is started.
+    fn load_state(&mut self, state: serde_json::Value) {}
+}
+
+/// Static description of a module type, for presenting to external tools.
+#[derive(Clone, Debug, Serialize)]
+pub struct ModuleInfo {
+    pub name: String,
+    /// Where the module belongs in a palette, empty if it isn't registered.
+    pub category: String,
+    pub description: String,
+    pub ports: Vec<PortInfo>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PortInfo {
+    pub name: String,
+    pub input: String,
+    pub output: String,
+    /// Declared channel count of an audio port.
+    pub channels: Option<usize>,
+    /// Declared rate of the signal, see `flow::Signal`.
+    pub signal: Option<flow::Signal>,
+}
+
+impl ModuleInfo {
+    pub fn of<T: Module>() -> ModuleInfo {
+        // ports are created on construction, so build a throwaway instance to find them
+        let graph = flow::Graph::new();
+        let module = T::new(graph.add_node());
+        ModuleInfo {
+            name: T::name().into(),
+            category: String::new(),
+            description: String::new(),
+            ports: module.ports().iter().map(|port| PortInfo::of(&**port)).collect(),
+        }
+    }
+}
+
+impl PortInfo {
+    pub fn of(port: &flow::OpaquePort) -> PortInfo {
+        PortInfo {
+            name: port.name().into(),
+            input: port.in_type_name().into(),
+            output: port.out_type_name().into(),
+            channels: port.meta().channels,
+            signal: port.meta().signal,
+        }
+    }
 }
@@ -0,0 +1,456 @@
+//! MQTT subscriber bridging IoT sensors into the graph.
+//!
+//! Speaks just enough MQTT 3.1.1 over a plain TCP socket to subscribe to a set of topics. Payloads
+//! are parsed as JSON when possible and forwarded as `Message`s on the output port.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use serde_json;
+
+use crate::future_ext::Breaker;
+use crate::module::{flow, util, Module};
+
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_PORT: u16 = 1883;
+const KEEP_ALIVE_SECS: u16 = 30;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+    ExactlyOnce = 2,
+}
+
+impl QoS {
+    fn from_u8(val: u8) -> Option<QoS> {
+        match val {
+            0 => Some(QoS::AtMostOnce),
+            1 => Some(QoS::AtLeastOnce),
+            2 => Some(QoS::ExactlyOnce),
+            _ => None,
+        }
+    }
+}
+
+/// Broker address and subscriptions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub topics: Vec<(String, QoS)>,
+}
+
+impl MqttConfig {
+    /// Parse a config of the form `host[:port] topic[@qos] [topic[@qos] ...]`,
+    /// e.g. `localhost sensors/+/temp@1 lights/#`.
+    pub fn parse(s: &str) -> Option<MqttConfig> {
+        let mut words = s.split_whitespace();
+        let addr = words.next()?;
+        let (host, port) = match addr.rfind(':') {
+            Some(idx) => (&addr[..idx], addr[idx + 1..].parse().ok()?),
+            None => (addr, DEFAULT_PORT),
+        };
+        let topics = words
+            .map(|word| match word.rfind('@') {
+                Some(idx) => Some((word[..idx].into(), QoS::from_u8(word[idx + 1..].parse().ok()?)?)),
+                None => Some((word.into(), QoS::AtMostOnce)),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if topics.is_empty() {
+            return None;
+        }
+        Some(MqttConfig {
+            host: host.into(),
+            port,
+            client_id: format!("flow-synth-{}", ::std::process::id()),
+            topics,
+        })
+    }
+}
+
+/// A message received on one of the subscribed topics.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub topic: String,
+    pub value: serde_json::Value,
+}
+
+impl Message {
+    fn new(topic: String, payload: &[u8]) -> Message {
+        // not everything on a broker is JSON, so fall back to passing the raw text along
+        let value = serde_json::from_slice(payload)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(payload).into()));
+        Message {
+            topic,
+            value,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Connect(MqttConfig),
+}
+
+pub struct MqttIn {
+    ifc: Arc<flow::Interface>,
+    out_port: Arc<flow::Port<(), Message>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    session: Arc<Mutex<Option<Breaker>>>,
+}
+
+impl Drop for MqttIn {
+    fn drop(&mut self) {
+        self.session.lock().unwrap().take().map(|session| session.brake());
+    }
+}
+
+impl Module for MqttIn {
+    fn new(ifc: Arc<flow::Interface>) -> MqttIn {
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        MqttIn {
+            ifc,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            session: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "MqttIn"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (msg_tx, msg_rx) = mpsc::channel(64);
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        let session_handle = self.session.clone();
+        let module_breaker = self.breaker.clone();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Connect(config) => {
+                        // only one broker connection at a time
+                        let session = Breaker::new();
+                        let mut session_handle = session_handle.lock().unwrap();
+                        session_handle.take().map(|old| old.brake());
+                        *session_handle = Some(session.clone());
+
+                        let msg_tx = msg_tx.clone();
+                        let module_breaker = module_breaker.clone();
+                        thread::spawn(move || run_client(config, msg_tx, session, module_breaker));
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        util::start_source(msg_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.session.lock().unwrap().take().map(|session| session.brake());
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Keep a session to the broker alive until either breaker is braked, reconnecting with
+/// exponential backoff whenever the connection drops.
+fn run_client(config: MqttConfig, mut tx: mpsc::Sender<Message>, session: Breaker, module: Breaker) {
+    let mut delay = Duration::from_secs(1);
+    while !session.test() && !module.test() {
+        match run_session(&config, &mut tx, &session, &module) {
+            Ok(()) => return,
+            Err(e) => println!("mqtt {}:{} err: {:?}", config.host, config.port, e),
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+fn run_session(
+    config: &MqttConfig,
+    tx: &mut mpsc::Sender<Message>,
+    session: &Breaker,
+    module: &Breaker,
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect((config.host.as_str(), config.port))?;
+
+    stream.write_all(&packet::connect(&config.client_id, KEEP_ALIVE_SECS))?;
+    let (header, body) = packet::read(&mut stream)?;
+    if header >> 4 != packet::CONNACK || body.len() != 2 || body[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "broker rejected connection",
+        ));
+    }
+    stream.write_all(&packet::subscribe(1, &config.topics))?;
+
+    // wake up periodically to check the breakers and to keep the connection alive
+    stream.set_read_timeout(Some(Duration::from_secs(KEEP_ALIVE_SECS as u64 / 2)))?;
+    loop {
+        if session.test() || module.test() {
+            let _ = stream.write_all(&packet::disconnect());
+            return Ok(());
+        }
+        let (header, body) = match packet::read(&mut stream) {
+            Ok(packet) => packet,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                stream.write_all(&packet::pingreq())?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        match header >> 4 {
+            packet::PUBLISH => {
+                let publish = packet::parse_publish(header, &body)?;
+                match publish.qos {
+                    QoS::AtMostOnce => {}
+                    QoS::AtLeastOnce => stream.write_all(&packet::ack(packet::PUBACK, publish.id))?,
+                    QoS::ExactlyOnce => stream.write_all(&packet::ack(packet::PUBREC, publish.id))?,
+                }
+                // drop messages if nobody is consuming them
+                let _ = tx.try_send(Message::new(publish.topic, publish.payload));
+            }
+            packet::PUBREL if body.len() >= 2 => {
+                let id = (body[0] as u16) << 8 | body[1] as u16;
+                stream.write_all(&packet::ack(packet::PUBCOMP, id))?;
+            }
+            packet::SUBACK if body.iter().skip(2).any(|&code| code == 0x80) => {
+                println!("mqtt: broker refused some subscriptions");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Encoding/decoding of the handful of control packets we need.
+mod packet {
+    use super::QoS;
+    use std::io::{self, Read};
+
+    pub const CONNECT: u8 = 1;
+    pub const CONNACK: u8 = 2;
+    pub const PUBLISH: u8 = 3;
+    pub const PUBACK: u8 = 4;
+    pub const PUBREC: u8 = 5;
+    pub const PUBREL: u8 = 6;
+    pub const PUBCOMP: u8 = 7;
+    pub const SUBSCRIBE: u8 = 8;
+    pub const SUBACK: u8 = 9;
+    pub const PINGREQ: u8 = 12;
+    pub const DISCONNECT: u8 = 14;
+
+    pub struct Publish<'a> {
+        pub topic: String,
+        pub qos: QoS,
+        pub id: u16,
+        pub payload: &'a [u8],
+    }
+
+    fn push_str(buf: &mut Vec<u8>, s: &str) {
+        buf.push((s.len() >> 8) as u8);
+        buf.push(s.len() as u8);
+        buf.extend(s.as_bytes());
+    }
+
+    pub fn encode_length(mut len: usize, buf: &mut Vec<u8>) {
+        loop {
+            let mut byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                byte |= 0x80;
+            }
+            buf.push(byte);
+            if len == 0 {
+                break;
+            }
+        }
+    }
+
+    fn finish(header: u8, body: Vec<u8>) -> Vec<u8> {
+        let mut buf = vec![header];
+        encode_length(body.len(), &mut buf);
+        buf.extend(body);
+        buf
+    }
+
+    pub fn connect(client_id: &str, keep_alive: u16) -> Vec<u8> {
+        let mut body = Vec::new();
+        push_str(&mut body, "MQTT");
+        body.push(4); // protocol level 3.1.1
+        body.push(0x02); // clean session
+        body.push((keep_alive >> 8) as u8);
+        body.push(keep_alive as u8);
+        push_str(&mut body, client_id);
+        finish(CONNECT << 4, body)
+    }
+
+    pub fn subscribe(id: u16, topics: &[(String, QoS)]) -> Vec<u8> {
+        let mut body = vec![(id >> 8) as u8, id as u8];
+        for (topic, qos) in topics {
+            push_str(&mut body, topic);
+            body.push(*qos as u8);
+        }
+        finish(SUBSCRIBE << 4 | 0x02, body)
+    }
+
+    pub fn ack(ty: u8, id: u16) -> Vec<u8> {
+        finish(ty << 4, vec![(id >> 8) as u8, id as u8])
+    }
+
+    pub fn pingreq() -> Vec<u8> {
+        finish(PINGREQ << 4, Vec::new())
+    }
+
+    pub fn disconnect() -> Vec<u8> {
+        finish(DISCONNECT << 4, Vec::new())
+    }
+
+    /// Read one packet, returning the fixed header byte and the remaining bytes.
+    pub fn read<R: Read>(stream: &mut R) -> io::Result<(u8, Vec<u8>)> {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        let header = byte[0];
+        let mut len = 0;
+        for shift in 0..4 {
+            stream.read_exact(&mut byte)?;
+            len |= ((byte[0] & 0x7f) as usize) << (7 * shift);
+            if byte[0] & 0x80 == 0 {
+                let mut body = vec![0; len];
+                stream.read_exact(&mut body)?;
+                return Ok((header, body));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed remaining length",
+        ))
+    }
+
+    pub fn parse_publish(header: u8, body: &[u8]) -> io::Result<Publish> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed publish");
+        let qos = QoS::from_u8((header >> 1) & 0x03).ok_or_else(invalid)?;
+        if body.len() < 2 {
+            return Err(invalid());
+        }
+        let topic_len = (body[0] as usize) << 8 | body[1] as usize;
+        let mut offset = 2 + topic_len;
+        let topic = body.get(2..offset).ok_or_else(invalid)?;
+        let topic = String::from_utf8_lossy(topic).into();
+        let mut id = 0;
+        if qos != QoS::AtMostOnce {
+            let bytes = body.get(offset..offset + 2).ok_or_else(invalid)?;
+            id = (bytes[0] as u16) << 8 | bytes[1] as u16;
+            offset += 2;
+        }
+        Ok(Publish {
+            topic,
+            qos,
+            id,
+            payload: &body[offset..],
+        })
+    }
+}
+
+#[test]
+fn test_mqtt_packets() {
+    let mut buf = Vec::new();
+    packet::encode_length(321, &mut buf);
+    assert_eq!(buf, vec![0xc1, 0x02]);
+
+    let mut publish = vec![packet::PUBLISH << 4 | 0x02, 0];
+    let body = [0, 3, b'a', b'/', b'b', 0, 7, b'4', b'2'];
+    publish[1] = body.len() as u8;
+    publish.extend(&body);
+    let (header, body) = packet::read(&mut &publish[..]).unwrap();
+    let parsed = packet::parse_publish(header, &body).unwrap();
+    assert_eq!(parsed.topic, "a/b");
+    assert_eq!(parsed.qos, QoS::AtLeastOnce);
+    assert_eq!(parsed.id, 7);
+    assert_eq!(
+        Message::new(parsed.topic, parsed.payload).value,
+        serde_json::Value::from(42)
+    );
+
+    let config = MqttConfig::parse("broker:1884 sensors/+/temp@1 lights/#").unwrap();
+    assert_eq!(config.host, "broker");
+    assert_eq!(config.port, 1884);
+    assert_eq!(
+        config.topics,
+        vec![
+            (String::from("sensors/+/temp"), QoS::AtLeastOnce),
+            (String::from("lights/#"), QoS::AtMostOnce),
+        ]
+    );
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct MqttGui {
+    bounds: Box3,
+    config_box: TextBox,
+    connect_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for MqttIn {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(MqttGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), "localhost #".into(), row(0.0)),
+            connect_button: Button::new(ctx.clone(), "Connect".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for MqttGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.connect_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.connect_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match MqttConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.connect_button
+                            .set_label(format!("{}:{}", config.host, config.port));
+                        self.cmd_tx.unbounded_send(UserCommand::Connect(config)).unwrap();
+                    }
+                    None => self
+                        .connect_button
+                        .set_label("Invalid: host[:port] topic[@qos]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Video output over NDI, for feeding visuals to Resolume, OBS and the like on the network.
+//!
+//! An `NDI Out` node publishes the image frames arriving at its input (one row per line of pixels,
+//! values from 0.0 to 1.0, as drawn by the visual modules) as an NDI source with the name given in
+//! its body. Pictures are sent as grey BGRA at the rate of the frames, each as soon as it arrives.
+//!
+//! The NDI runtime is loaded when the first source is started, rather than linked, so the SDK isn't
+//! needed to build. It is looked for in `NDI_RUNTIME_DIR_V6` and `NDI_RUNTIME_DIR_V5`, where the
+//! runtime installers put it, and then in the library search path.
+//!
+//! Built with the `ndi` feature.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use libloading::Library;
+
+use std::env;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_float, c_int, c_void};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::{mpsc as std_mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Frames waiting to be sent before new ones are dropped.
+const QUEUE: usize = 2;
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[cfg(target_os = "windows")]
+const LIBRARY: &str = "Processing.NDI.Lib.x64.dll";
+#[cfg(target_os = "macos")]
+const LIBRARY: &str = "libndi.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIBRARY: &str = "libndi.so.5";
+
+const FOURCC_BGRA: u32 = (b'B' as u32) | (b'G' as u32) << 8 | (b'R' as u32) << 16 | (b'A' as u32) << 24;
+const FRAME_FORMAT_PROGRESSIVE: c_int = 1;
+/// Lets NDI fill in the timecode from its own clock.
+const TIMECODE_SYNTHESIZE: i64 = ::std::i64::MAX;
+
+#[repr(C)]
+struct SendCreate {
+    ndi_name: *const c_char,
+    groups: *const c_char,
+    clock_video: bool,
+    clock_audio: bool,
+}
+
+#[repr(C)]
+struct VideoFrame {
+    xres: c_int,
+    yres: c_int,
+    fourcc: u32,
+    frame_rate_n: c_int,
+    frame_rate_d: c_int,
+    /// Zero for square pixels.
+    picture_aspect_ratio: c_float,
+    frame_format_type: c_int,
+    timecode: i64,
+    data: *const u8,
+    line_stride_in_bytes: c_int,
+    metadata: *const c_char,
+    timestamp: i64,
+}
+
+/// The entry points of the NDI runtime which are used.
+struct Runtime {
+    _library: Library,
+    send_create: unsafe extern "C" fn(*const SendCreate) -> *mut c_void,
+    send_destroy: unsafe extern "C" fn(*mut c_void),
+    send_send_video_v2: unsafe extern "C" fn(*mut c_void, *const VideoFrame),
+}
+
+/// Load and initialize the NDI runtime, once for the whole process.
+fn runtime() -> Result<&'static Runtime, String> {
+    static RUNTIME: OnceLock<Result<Runtime, String>> = OnceLock::new();
+    RUNTIME.get_or_init(load_runtime).as_ref().map_err(|e| e.clone())
+}
+
+fn load_runtime() -> Result<Runtime, String> {
+    let mut paths: Vec<PathBuf> = ["NDI_RUNTIME_DIR_V6", "NDI_RUNTIME_DIR_V5"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .map(|dir| PathBuf::from(dir).join(LIBRARY))
+        .collect();
+    paths.push(LIBRARY.into());
+    let library = paths
+        .iter()
+        .filter_map(|path| Library::new(path).ok())
+        .next()
+        .ok_or_else(|| format!("NDI runtime {} not found", LIBRARY))?;
+    unsafe {
+        let initialize = *library
+            .get::<unsafe extern "C" fn() -> bool>(b"NDIlib_initialize\0")
+            .map_err(|e| e.to_string())?;
+        if !initialize() {
+            return Err("NDI isn't supported on this CPU".into());
+        }
+        let send_create = *library
+            .get::<unsafe extern "C" fn(*const SendCreate) -> *mut c_void>(b"NDIlib_send_create\0")
+            .map_err(|e| e.to_string())?;
+        let send_destroy = *library
+            .get::<unsafe extern "C" fn(*mut c_void)>(b"NDIlib_send_destroy\0")
+            .map_err(|e| e.to_string())?;
+        let send_send_video_v2 = *library
+            .get::<unsafe extern "C" fn(*mut c_void, *const VideoFrame)>(b"NDIlib_send_send_video_v2\0")
+            .map_err(|e| e.to_string())?;
+        Ok(Runtime {
+            _library: library,
+            send_create,
+            send_destroy,
+            send_send_video_v2,
+        })
+    }
+}
+
+/// A source on the network, until dropped.
+struct Sender {
+    runtime: &'static Runtime,
+    instance: *mut c_void,
+}
+
+unsafe impl Send for Sender {}
+
+impl Sender {
+    fn new(name: &str) -> Result<Sender, String> {
+        let runtime = runtime()?;
+        let name = CString::new(name).map_err(|e| e.to_string())?;
+        let create = SendCreate {
+            ndi_name: name.as_ptr(),
+            groups: ptr::null(),
+            // frames are sent at the rate they are produced at
+            clock_video: false,
+            clock_audio: false,
+        };
+        let instance = unsafe { (runtime.send_create)(&create) };
+        if instance.is_null() {
+            return Err("couldn't create the NDI source".into());
+        }
+        Ok(Sender { runtime, instance })
+    }
+    /// Send one picture, which NDI is done with when this returns.
+    fn send(&mut self, frame: &Frame) {
+        if frame.data.is_empty() {
+            return;
+        }
+        let data = bgra(frame);
+        let video = video_frame(frame, &data);
+        unsafe { (self.runtime.send_send_video_v2)(self.instance, &video) };
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        unsafe { (self.runtime.send_destroy)(self.instance) };
+    }
+}
+
+/// The pixels of `frame` as opaque grey BGRA, quantized to 8 bits.
+fn bgra(frame: &Frame) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(frame.data.len() * 4);
+    for &x in frame.data.iter() {
+        let value = (x.max(0.0).min(1.0) * 255.0).round() as u8;
+        bytes.extend_from_slice(&[value, value, value, 255]);
+    }
+    bytes
+}
+
+/// Describe the picture in `data`, converted from `frame`, to NDI.
+fn video_frame(frame: &Frame, data: &[u8]) -> VideoFrame {
+    let (height, width) = frame.data.dim();
+    VideoFrame {
+        xres: width as c_int,
+        yres: height as c_int,
+        fourcc: FOURCC_BGRA,
+        // thousandths keep fractional rates like 29.97
+        frame_rate_n: (frame.rate * 1000.0).round().max(1.0) as c_int,
+        frame_rate_d: 1000,
+        picture_aspect_ratio: 0.0,
+        frame_format_type: FRAME_FORMAT_PROGRESSIVE,
+        timecode: TIMECODE_SYNTHESIZE,
+        data: data.as_ptr(),
+        line_stride_in_bytes: (width * 4) as c_int,
+        metadata: ptr::null(),
+        timestamp: 0,
+    }
+}
+
+/// Publish frames from `rx` as the source `name` until `session` is braked.
+fn send_frames(name: String, rx: std_mpsc::Receiver<Frame>, session: Breaker) {
+    let mut sender = match Sender::new(&name) {
+        Ok(sender) => sender,
+        Err(e) => {
+            println!("ndi out {} err: {}", name, e);
+            return;
+        }
+    };
+    while !session.test() {
+        match rx.recv_timeout(RECV_TIMEOUT) {
+            Ok(frame) => sender.send(&frame),
+            Err(std_mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(std_mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Start(String),
+}
+
+type Output = Arc<Mutex<Option<std_mpsc::SyncSender<Frame>>>>;
+
+pub struct NdiOut {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    session: Arc<Mutex<Option<Breaker>>>,
+    output: Output,
+}
+
+impl Drop for NdiOut {
+    fn drop(&mut self) {
+        self.session.lock().unwrap().take().map(|session| session.brake());
+    }
+}
+
+impl Module for NdiOut {
+    fn new(ifc: Arc<flow::Interface>) -> NdiOut {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        NdiOut {
+            ifc,
+            in_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            session: Arc::default(),
+            output: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "NDI Out"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let session_handle = self.session.clone();
+        let output_handle = self.output.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Start(name) => {
+                        // take the previous source off the network before starting another
+                        let session = Breaker::new();
+                        let mut session_handle = session_handle.lock().unwrap();
+                        session_handle.take().map(|old| old.brake());
+                        *session_handle = Some(session.clone());
+
+                        let (tx, rx) = std_mpsc::sync_channel(QUEUE);
+                        *output_handle.lock().unwrap() = Some(tx);
+                        thread::spawn(move || send_frames(name, rx, session));
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let output = self.output.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |frame: Frame| {
+                if let Some(ref tx) = *output.lock().unwrap() {
+                    // drop frames while sending is behind
+                    let _ = tx.try_send(frame);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.session.lock().unwrap().take().map(|session| session.brake());
+        *self.output.lock().unwrap() = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_ndi_frame() {
+    use ndarray::Array2;
+
+    let frame = Frame {
+        rate: 29.97,
+        time: None,
+        data: Array2::from_shape_vec((2, 3), vec![0.0, 0.5, 1.0, -1.0, 2.0, 0.25]).unwrap(),
+        meta: None,
+    };
+    let data = bgra(&frame);
+    assert_eq!(data.len(), 2 * 3 * 4);
+    assert_eq!(&data[..8], &[0, 0, 0, 255, 128, 128, 128, 255]);
+    assert_eq!(&data[20..], &[64, 64, 64, 255]);
+
+    let video = video_frame(&frame, &data);
+    assert_eq!((video.xres, video.yres), (3, 2));
+    assert_eq!(video.line_stride_in_bytes, 12);
+    assert_eq!((video.frame_rate_n, video.frame_rate_d), (29970, 1000));
+    assert_eq!(FOURCC_BGRA, 0x4152_4742);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct NdiOutGui {
+    bounds: Box3,
+    name_box: TextBox,
+    start_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for NdiOut {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(NdiOutGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            name_box: TextBox::new(ctx.clone(), "flow-synth".into(), row(0.0)),
+            start_button: Button::new(ctx.clone(), "Start".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for NdiOutGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.name_box.render(device, ctx);
+        self.start_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.name_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.start_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let name = self.name_box.content().trim().to_string();
+                if name.is_empty() {
+                    self.start_button.set_label("Invalid: name".into());
+                } else {
+                    self.start_button.set_label(format!("Sending {}", name));
+                    self.cmd_tx.unbounded_send(UserCommand::Start(name)).unwrap();
+                }
+                true
+            }
+        }
+    }
+}
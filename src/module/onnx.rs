@@ -0,0 +1,325 @@
+//! Running neural networks saved as ONNX models, through `tract`.
+//!
+//! `ONNX Model` loads the model at the path in its state, and runs it on every frame on `Input`,
+//! like a block of audio features, flattened row by row into the model's first input. Models
+//! with a fixed input shape get the values cut short or padded with zeros to fit it, and the rest
+//! are given the frame as a single row, a batch of one. The model's first output is sent on
+//! `Output` as a frame of a single row with a channel for each value, and its first four values
+//! on `Out 1` to `Out 4` as control values, for mapping features to parameters. Tensors on
+//! `Tensor In` are run the same way, but keep their shape for models without a fixed one, and
+//! only send the control values. Either way, the output is also sent whole on `Tensor Out`.
+//!
+//! Models are loaded on their own thread, as large ones take a while to optimize, and frames are
+//! passed over until one is loaded. Built with the `onnx` feature.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::tensor::Tensor;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+// the `Tensor` imported by name above takes precedence over tract's here
+use tract_onnx::prelude::*;
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Outputs sending the first values of the model's output.
+pub const CONTROL_OUTPUTS: usize = 4;
+
+pub struct Model {
+    plan: TypedRunnableModel<TypedModel>,
+    /// The shape of the first input, if it is known.
+    input_shape: Option<Vec<usize>>,
+}
+
+impl Model {
+    pub fn load(path: &str) -> Result<Model, String> {
+        let model = tract_onnx::onnx()
+            .model_for_path(path)
+            .map_err(|e| format!("{}", e))?;
+        let input_shape = model
+            .input_fact(0)
+            .map_err(|e| format!("{}", e))?
+            .shape
+            .as_concrete_finite()
+            .ok()
+            .and_then(|shape| shape.map(|shape| shape.to_vec()));
+        let plan = model
+            .into_optimized()
+            .and_then(|model| model.into_runnable())
+            .map_err(|e| format!("{}", e))?;
+        Ok(Model { plan, input_shape })
+    }
+    /// Run the model on `input`, of `shape` unless the model's is known, giving the shape and
+    /// values of its first output.
+    pub fn run(&self, shape: &[usize], input: &[f32]) -> Result<(Vec<usize>, Vec<f32>), String> {
+        let shape = match self.input_shape {
+            Some(ref shape) => shape.clone(),
+            None => shape.to_vec(),
+        };
+        let data = fit(input, shape.iter().product());
+        let tensor = tract_onnx::prelude::Tensor::from_shape(&shape, &data).map_err(|e| format!("{}", e))?;
+        let outputs = self
+            .plan
+            .run(tvec!(tensor.into()))
+            .map_err(|e| format!("{}", e))?;
+        let output = outputs[0].to_array_view::<f32>().map_err(|e| format!("{}", e))?;
+        Ok((output.shape().to_vec(), output.iter().cloned().collect()))
+    }
+}
+
+/// `input` cut short or padded with zeros to `len` values.
+pub fn fit(input: &[f32], len: usize) -> Vec<f32> {
+    let mut data: Vec<f32> = input.iter().take(len).cloned().collect();
+    data.resize(len, 0.0);
+    data
+}
+
+/// The outputs shared by frames and tensors.
+struct Outputs {
+    control_txs: Vec<mpsc::Sender<f32>>,
+    tensor_tx: mpsc::Sender<Tensor>,
+}
+
+impl Outputs {
+    fn send(&mut self, shape: Vec<usize>, output: &[f32]) {
+        for (tx, &value) in self.control_txs.iter_mut().zip(output) {
+            let _ = tx.try_send(value);
+        }
+        match Tensor::new(shape, output.to_vec()) {
+            Ok(tensor) => {
+                let _ = self.tensor_tx.try_send(tensor);
+            }
+            Err(e) => println!("onnx output err: {}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Load(String),
+}
+
+#[derive(Default)]
+struct Loaded {
+    path: Option<String>,
+    model: Option<Arc<Model>>,
+}
+
+pub struct OnnxModel {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    control_ports: Vec<Arc<flow::Port<(), f32>>>,
+    tensor_in_port: Arc<flow::Port<Tensor, ()>>,
+    tensor_out_port: Arc<flow::Port<(), Tensor>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    loaded: Arc<Mutex<Loaded>>,
+}
+
+impl Module for OnnxModel {
+    fn new(ifc: Arc<flow::Interface>) -> OnnxModel {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        OnnxModel {
+            in_port: ifc.get_or_create_port("Input".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            control_ports: (0..CONTROL_OUTPUTS)
+                .map(|idx| ifc.get_or_create_port(format!("Out {}", idx + 1)))
+                .collect(),
+            tensor_in_port: ifc.get_or_create_port("Tensor In".into()),
+            tensor_out_port: ifc.get_or_create_port("Tensor Out".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            loaded: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "ONNX Model"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let loaded = self.loaded.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Load(path) => {
+                        loaded.lock().unwrap().path = Some(path.clone());
+                        let loaded = loaded.clone();
+                        thread::spawn(move || match Model::load(&path) {
+                            Ok(model) => loaded.lock().unwrap().model = Some(Arc::new(model)),
+                            Err(e) => println!("onnx load {} err: {}", path, e),
+                        });
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let mut control_txs = Vec::new();
+        for port in &self.control_ports {
+            let (tx, rx) = mpsc::channel(1);
+            util::start_source(rx, port.clone(), &exec);
+            control_txs.push(tx);
+        }
+        let (tensor_tx, tensor_rx) = mpsc::channel(1);
+        util::start_source(tensor_rx, self.tensor_out_port.clone(), &exec);
+        let outputs = Arc::new(Mutex::new(Outputs {
+            control_txs,
+            tensor_tx,
+        }));
+
+        let loaded = self.loaded.clone();
+        let tensor_outputs = outputs.clone();
+        util::start_sink(
+            self.tensor_in_port.clone(),
+            move |tensor: Tensor| {
+                let model = match loaded.lock().unwrap().model {
+                    Some(ref model) => model.clone(),
+                    None => return,
+                };
+                match model.run(tensor.shape(), &tensor.to_f32()) {
+                    Ok((shape, output)) => tensor_outputs.lock().unwrap().send(shape, &output),
+                    Err(e) => println!("onnx run err: {}", e),
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let (mut out_tx, out_rx) = mpsc::channel(1);
+        let loaded = self.loaded.clone();
+        let out_port = self.out_port.clone();
+        let pool = self.ifc.graph().pool();
+        util::start_sink(
+            self.in_port.clone(),
+            move |frame: Frame| {
+                let model = match loaded.lock().unwrap().model {
+                    Some(ref model) => model.clone(),
+                    None => return pool.recycle(frame),
+                };
+                let input: Vec<f32> = frame.data.iter().cloned().collect();
+                let (shape, output) = match model.run(&[1, input.len()], &input) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        println!("onnx run err: {}", e);
+                        return pool.recycle(frame);
+                    }
+                };
+                if out_port.meta().channels != Some(output.len()) {
+                    out_port.set_meta(flow::PortMeta {
+                        channels: Some(output.len()),
+                        ..out_port.meta()
+                    });
+                }
+                // one row per input frame
+                let rate = frame.rate / frame.data.dim().0.max(1) as f32;
+                let mut out = pool.zeros(rate, frame.time, (1, output.len()));
+                for (x, &y) in out.data.iter_mut().zip(&output) {
+                    *x = y;
+                }
+                pool.recycle(frame);
+                let _ = out_tx.try_send(out);
+                outputs.lock().unwrap().send(shape, &output);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        match self.loaded.lock().unwrap().path {
+            Some(ref path) => json!({ "path": path }),
+            None => serde_json::Value::Null,
+        }
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        if let (Some(path), Some(cmd_tx)) = (state["path"].as_str(), self.cmd_tx.as_ref()) {
+            // loaded once the module starts, and shown in the GUI meanwhile
+            self.loaded.lock().unwrap().path = Some(path.into());
+            cmd_tx.unbounded_send(UserCommand::Load(path.into())).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_onnx() {
+    assert_eq!(fit(&[1.0, 2.0, 3.0], 2), vec![1.0, 2.0]);
+    assert_eq!(fit(&[1.0, 2.0], 4), vec![1.0, 2.0, 0.0, 0.0]);
+    assert!(Model::load("no such model.onnx").is_err());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct OnnxGui {
+    bounds: Box3,
+    path_box: TextBox,
+    load_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for OnnxModel {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let path = self
+            .loaded
+            .lock()
+            .unwrap()
+            .path
+            .clone()
+            .unwrap_or_else(|| "model.onnx".into());
+        Box::new(OnnxGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            path_box: TextBox::new(ctx.clone(), path, row(0.0)),
+            load_button: Button::new(ctx.clone(), "Load".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for OnnxGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.path_box.render(device, ctx);
+        self.load_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.path_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.load_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let path = self.path_box.content().trim().to_string();
+                if path.is_empty() {
+                    self.load_button.set_label("Invalid: path".into());
+                } else {
+                    self.load_button.set_label(format!("Loaded {}", path));
+                    self.cmd_tx.unbounded_send(UserCommand::Load(path)).unwrap();
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Particle system for visuals.
+//!
+//! Particles are emitted from the middle of a square running from -1.0 to 1.0 on both axes, fly
+//! off in random directions, fall under gravity, get pushed around by turbulence and die once
+//! their lifetime is up. Emission rate, burst size, lifetime, speed and forces are all control
+//! inputs, so envelope followers and onset detectors can drive the system from audio.
+//!
+//! Like the oscillators, `Input` frames only set the timing: each one advances the simulation by
+//! its duration. `Positions` answers with one row per live particle holding its x, y and the
+//! fraction of its life left, for a custom renderer. `Image` answers with the particles drawn
+//! into a grayscale frame, one row per line of pixels, and is only rendered while connected.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::physical::Noise;
+use crate::module::pool::FramePool;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use serde_json;
+
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Columns in a row of `Positions`: x, y and life left.
+pub const POSITION_CHANNELS: usize = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParticleConfig {
+    /// The most particles alive at once. Emission stops while the system is full.
+    pub capacity: usize,
+    /// Size of the rendered image in pixels.
+    pub width: usize,
+    pub height: usize,
+}
+
+impl ParticleConfig {
+    /// Parse a config of the form `capacity width height`.
+    pub fn parse(s: &str) -> Option<ParticleConfig> {
+        let mut words = s.split_whitespace();
+        let capacity: usize = words.next()?.parse().ok()?;
+        let width: usize = words.next()?.parse().ok()?;
+        let height: usize = words.next()?.parse().ok()?;
+        if capacity == 0 || width == 0 || height == 0 || words.next().is_some() {
+            return None;
+        }
+        Some(ParticleConfig {
+            capacity,
+            width,
+            height,
+        })
+    }
+    fn describe(&self) -> String {
+        format!("{} {} {}", self.capacity, self.width, self.height)
+    }
+}
+
+impl Default for ParticleConfig {
+    fn default() -> ParticleConfig {
+        ParticleConfig {
+            capacity: 1024,
+            width: 64,
+            height: 64,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Particle {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    age: f32,
+    lifetime: f32,
+}
+
+pub struct ParticleSystem {
+    config: ParticleConfig,
+    particles: Vec<Particle>,
+    /// Particles per second.
+    emission: f32,
+    /// Fractional particles owed by emission so far.
+    owed: f32,
+    /// Particles to emit at the next step regardless of the rate.
+    burst: usize,
+    /// Seconds each new particle lives.
+    lifetime: f32,
+    /// Launch speed of new particles, in units per second.
+    speed: f32,
+    /// Downward acceleration in units per second squared.
+    gravity: f32,
+    /// Strength of random acceleration in units per second squared.
+    turbulence: f32,
+    noise: Noise,
+}
+
+impl ParticleSystem {
+    pub fn new(config: ParticleConfig) -> ParticleSystem {
+        ParticleSystem {
+            config,
+            particles: Vec::with_capacity(config.capacity),
+            emission: 100.0,
+            owed: 0.0,
+            burst: 0,
+            lifetime: 2.0,
+            speed: 0.5,
+            gravity: 0.5,
+            turbulence: 0.0,
+            noise: Noise(0x2545_f491),
+        }
+    }
+    pub fn config(&self) -> ParticleConfig {
+        self.config
+    }
+    pub fn set_config(&mut self, config: ParticleConfig) {
+        self.config = config;
+        self.particles.truncate(config.capacity);
+    }
+    pub fn set_emission(&mut self, emission: f32) {
+        self.emission = emission.max(0.0);
+    }
+    /// Emit `count` particles at the next step.
+    pub fn burst(&mut self, count: f32) {
+        self.burst += count.max(0.0) as usize;
+    }
+    pub fn set_lifetime(&mut self, lifetime: f32) {
+        self.lifetime = lifetime.max(0.0);
+    }
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+    pub fn set_gravity(&mut self, gravity: f32) {
+        self.gravity = gravity;
+    }
+    pub fn set_turbulence(&mut self, turbulence: f32) {
+        self.turbulence = turbulence.max(0.0);
+    }
+    /// The number of live particles.
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+    /// Advance the simulation by `dt` seconds.
+    pub fn step(&mut self, dt: f32) {
+        self.particles.retain(|p| p.age + dt < p.lifetime);
+        self.owed += self.emission * dt;
+        let due = self.owed as usize + self.burst;
+        self.owed = self.owed.fract();
+        self.burst = 0;
+        let room = self.config.capacity - self.particles.len();
+        for _ in 0..due.min(room) {
+            let angle = self.noise.next() * PI;
+            // between half and full speed, so a burst spreads out instead of forming a ring
+            let speed = self.speed * (0.75 + 0.25 * self.noise.next());
+            self.particles.push(Particle {
+                x: 0.0,
+                y: 0.0,
+                vx: angle.cos() * speed,
+                vy: angle.sin() * speed,
+                age: 0.0,
+                lifetime: self.lifetime,
+            });
+        }
+
+        let (gravity, turbulence) = (self.gravity, self.turbulence);
+        let noise = &mut self.noise;
+        for particle in &mut self.particles {
+            particle.vx += noise.next() * turbulence * dt;
+            particle.vy += (noise.next() * turbulence - gravity) * dt;
+            particle.x += particle.vx * dt;
+            particle.y += particle.vy * dt;
+            particle.age += dt;
+        }
+    }
+    /// Write the live particles into a frame of `POSITION_CHANNELS` columns, one row each.
+    pub fn positions(&self, rate: f32, time: Option<u64>, pool: &FramePool) -> Frame {
+        let mut out = pool.zeros(rate, time, (self.particles.len(), POSITION_CHANNELS));
+        for (particle, mut row) in self.particles.iter().zip(out.data.outer_iter_mut()) {
+            row[0] = particle.x;
+            row[1] = particle.y;
+            row[2] = 1.0 - particle.age / particle.lifetime;
+        }
+        out
+    }
+    /// Draw the live particles into a `height` by `width` frame, each pixel adding up the life
+    /// left in the particles over it, up to 1.0. Up is the first row.
+    pub fn render(&self, rate: f32, time: Option<u64>, pool: &FramePool) -> Frame {
+        let ParticleConfig { width, height, .. } = self.config;
+        let mut out = pool.zeros(rate, time, (height, width));
+        for particle in &self.particles {
+            let column = (particle.x + 1.0) / 2.0 * width as f32;
+            let row = (1.0 - particle.y) / 2.0 * height as f32;
+            if column < 0.0 || row < 0.0 || column >= width as f32 || row >= height as f32 {
+                continue;
+            }
+            let pixel = &mut out.data[[row as usize, column as usize]];
+            *pixel = (*pixel + 1.0 - particle.age / particle.lifetime).min(1.0);
+        }
+        out
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Configure(ParticleConfig),
+}
+
+pub struct Particles {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    emission_port: Arc<flow::Port<f32, ()>>,
+    burst_port: Arc<flow::Port<f32, ()>>,
+    lifetime_port: Arc<flow::Port<f32, ()>>,
+    speed_port: Arc<flow::Port<f32, ()>>,
+    gravity_port: Arc<flow::Port<f32, ()>>,
+    turbulence_port: Arc<flow::Port<f32, ()>>,
+    positions_port: Arc<flow::Port<(), Frame>>,
+    image_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    system: Arc<Mutex<ParticleSystem>>,
+}
+
+/// Declare the image width of `config` on the output.
+fn declare_width(port: &flow::Port<(), Frame>, config: &ParticleConfig) {
+    port.set_meta(flow::PortMeta {
+        channels: Some(config.width),
+        ..port.meta()
+    });
+}
+
+impl Module for Particles {
+    fn new(ifc: Arc<flow::Interface>) -> Particles {
+        let positions_port: Arc<flow::Port<(), Frame>> = ifc.get_or_create_port("Positions".into());
+        positions_port.set_meta(flow::PortMeta {
+            channels: Some(POSITION_CHANNELS),
+            ..positions_port.meta()
+        });
+        let image_port = ifc.get_or_create_port("Image".into());
+        let config = ParticleConfig::default();
+        declare_width(&image_port, &config);
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Particles {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            emission_port: ifc.get_or_create_port("Emission".into()),
+            burst_port: ifc.get_or_create_port("Burst".into()),
+            lifetime_port: ifc.get_or_create_port("Lifetime".into()),
+            speed_port: ifc.get_or_create_port("Speed".into()),
+            gravity_port: ifc.get_or_create_port("Gravity".into()),
+            turbulence_port: ifc.get_or_create_port("Turbulence".into()),
+            positions_port,
+            image_port,
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            system: Arc::new(Mutex::new(ParticleSystem::new(config))),
+        }
+    }
+    fn name() -> &'static str {
+        "Particles"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let system = self.system.clone();
+        let image_port = self.image_port.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Configure(config) => {
+                        declare_width(&image_port, &config);
+                        system.lock().unwrap().set_config(config);
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let controls: [(&Arc<flow::Port<f32, ()>>, fn(&mut ParticleSystem, f32)); 6] = [
+            (&self.emission_port, ParticleSystem::set_emission),
+            (&self.burst_port, ParticleSystem::burst),
+            (&self.lifetime_port, ParticleSystem::set_lifetime),
+            (&self.speed_port, ParticleSystem::set_speed),
+            (&self.gravity_port, ParticleSystem::set_gravity),
+            (&self.turbulence_port, ParticleSystem::set_turbulence),
+        ];
+        for &(port, set) in &controls {
+            let system = self.system.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| set(&mut system.lock().unwrap(), value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let (mut image_tx, image_rx) = mpsc::channel(1);
+        let system = self.system.clone();
+        let image_port = self.image_port.clone();
+        let pool = self.ifc.graph().pool();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                let rows = frame.data.dim().0;
+                // one step per input frame
+                let (rate, time) = (frame.rate / rows.max(1) as f32, frame.time);
+                let mut system = system.lock().unwrap();
+                system.step(rows as f32 / frame.rate);
+                pool.recycle(frame);
+                if image_port.edge().is_some() {
+                    // the positions set the pace, a slow image consumer just misses frames
+                    let _ = image_tx.try_send(system.render(rate, time, &pool));
+                }
+                system.positions(rate, time, &pool)
+            },
+            self.ifc.clone(),
+            self.clock_port.clone(),
+            self.positions_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(image_rx, self.image_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.system.lock().unwrap().config()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(config) => {
+                declare_width(&self.image_port, &config);
+                self.system.lock().unwrap().set_config(config);
+            }
+            Err(e) => println!("particles state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_particles() {
+    let pool = FramePool::new();
+    let mut system = ParticleSystem::new(ParticleConfig::parse("50 8 8").unwrap());
+    system.set_emission(100.0);
+    system.set_lifetime(0.25);
+    system.set_gravity(0.0);
+    for _ in 0..10 {
+        system.step(0.1);
+    }
+    // ten a step, each living through two steps
+    assert_eq!(system.len(), 20);
+    let positions = system.positions(10.0, None, &pool);
+    assert_eq!(positions.data.dim(), (20, POSITION_CHANNELS));
+    for row in positions.data.outer_iter() {
+        assert!(row[0].hypot(row[1]) <= 0.5 * 0.2 + 1e-6);
+        assert!(row[2] > 0.0 && row[2] <= 1.0);
+    }
+    let image = system.render(10.0, None, &pool);
+    assert_eq!(image.data.dim(), (8, 8));
+    assert!(image.data.iter().all(|&x| x >= 0.0 && x <= 1.0));
+    // everything starts in the middle and hasn't gone far
+    let middle = |idx: usize| idx >= 3 && idx < 5;
+    for ((row, column), &x) in image.data.indexed_iter() {
+        assert!(x == 0.0 || middle(row) && middle(column));
+    }
+    assert!(image.data.iter().sum::<f32>() > 0.0);
+
+    // bursts fill up to capacity, and gravity pulls down
+    system.set_emission(0.0);
+    system.set_gravity(10.0);
+    system.burst(100.0);
+    system.step(0.1);
+    assert_eq!(system.len(), 50);
+    system.step(0.1);
+    let positions = system.positions(10.0, None, &pool);
+    assert!(positions.data.column(1).iter().sum::<f32>() < 0.0);
+    system.step(0.1);
+    assert_eq!(system.len(), 0);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct ParticlesGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Particles {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = self.system.lock().unwrap().config();
+        Box::new(ParticlesGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), config.describe(), row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for ParticlesGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match ParticleConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::Configure(config)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: capacity w h".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! Perlin's gradient noise, smooth random values in up to three dimensions, for control signals
+//! and visuals.
+//!
+//! `Noise LFO` sends a value per `Input` frame from a line through the noise, moving along it at
+//! `Rate` units a second, or jumping to the values arriving on `Position` to scrub it by hand.
+//! `Noise Field` answers each `Input` frame with a grayscale image of a plane through the noise,
+//! one row per line of pixels, moving through the third dimension at `Rate` units a second so the
+//! field drifts and changes. `Scale` is the units of noise across the width of the image.
+//!
+//! Both sum `Octaves` layers of noise, each `Lacunarity` times finer than the last and weighted
+//! `Gain` times as much, for detail at every scale. The result is kept between about -1.0 and 1.0
+//! for the LFO, and 0.0 and 1.0 for the field.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::pool::FramePool;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+/// Most layers summed, since each costs as much as the first.
+pub const MAX_OCTAVES: usize = 8;
+
+/// Gradient noise over a lattice of 256 units a side, repeating beyond that.
+pub struct Perlin {
+    /// A shuffle of 0..256, twice over to save wrapping indices.
+    perm: Vec<u8>,
+}
+
+impl Perlin {
+    pub fn new(seed: u32) -> Perlin {
+        let mut perm: Vec<u8> = (0..256).map(|x| x as u8).collect();
+        // xorshift, which needs a seed other than zero
+        let mut state = seed.max(1);
+        for idx in (1..256).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            perm.swap(idx, state as usize % (idx + 1));
+        }
+        let twice = perm.clone();
+        perm.extend(twice);
+        Perlin { perm }
+    }
+    /// The noise at a point, between about -1.0 and 1.0, and 0.0 at every lattice point.
+    pub fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+        // one of the twelve directions to the edges of a cube, dotted with the offset
+        let grad = |hash: u8, x: f32, y: f32, z: f32| {
+            let h = hash & 15;
+            let u = if h < 8 { x } else { y };
+            let v = if h < 4 {
+                y
+            } else if h == 12 || h == 14 {
+                x
+            } else {
+                z
+            };
+            (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+        };
+        let cell = |x: f32| (x.floor() as i64 & 255) as usize;
+        let (xi, yi, zi) = (cell(x), cell(y), cell(z));
+        let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        let p = &self.perm;
+        let a = p[xi] as usize + yi;
+        let (aa, ab) = (p[a] as usize + zi, p[a + 1] as usize + zi);
+        let b = p[xi + 1] as usize + yi;
+        let (ba, bb) = (p[b] as usize + zi, p[b + 1] as usize + zi);
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+                lerp(u, grad(p[ab], x, y - 1.0, z), grad(p[bb], x - 1.0, y - 1.0, z)),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], x, y, z - 1.0),
+                    grad(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], x, y - 1.0, z - 1.0),
+                    grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+/// Layers of noise summed for detail at every scale.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fractal {
+    pub octaves: usize,
+    /// How much finer each layer is than the one before.
+    pub lacunarity: f32,
+    /// How much each layer is weighted compared to the one before.
+    pub gain: f32,
+}
+
+impl Fractal {
+    pub fn new() -> Fractal {
+        Fractal {
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+    pub fn set_octaves(&mut self, octaves: f32) {
+        self.octaves = (octaves.round().max(1.0) as usize).min(MAX_OCTAVES);
+    }
+    /// The layers summed at a point, divided by their total weight.
+    pub fn sample(&self, perlin: &Perlin, x: f32, y: f32, z: f32) -> f32 {
+        let (mut sum, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+        for octave in 0..self.octaves.max(1) {
+            // offset each layer, so their lattice points don't line up
+            let shift = octave as f32 * 17.31;
+            sum += amplitude * perlin.noise3(x * frequency + shift, y * frequency + shift, z * frequency);
+            total += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        if total > 0.0 {
+            sum / total
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Seconds covered by `frame`.
+fn duration(frame: &Frame) -> f32 {
+    frame.data.dim().0 as f32 / frame.rate
+}
+
+/// Set `fractal`'s parameter `idx` of `Octaves`, `Lacunarity` and `Gain`.
+fn set_fractal(fractal: &mut Fractal, idx: usize, value: f32) {
+    match idx {
+        0 => fractal.set_octaves(value),
+        1 => fractal.lacunarity = value,
+        _ => fractal.gain = value,
+    }
+}
+
+struct Lfo {
+    perlin: Perlin,
+    fractal: Fractal,
+    position: f32,
+    /// Units a second.
+    rate: f32,
+}
+
+pub struct NoiseLfo {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    rate_port: Arc<flow::Port<f32, ()>>,
+    position_port: Arc<flow::Port<f32, ()>>,
+    fractal_ports: Vec<Arc<flow::Port<f32, ()>>>,
+    out_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    lfo: Arc<Mutex<Lfo>>,
+}
+
+impl Module for NoiseLfo {
+    fn new(ifc: Arc<flow::Interface>) -> NoiseLfo {
+        NoiseLfo {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            rate_port: ifc.get_or_create_port("Rate".into()),
+            position_port: ifc.get_or_create_port("Position".into()),
+            fractal_ports: ["Octaves", "Lacunarity", "Gain"]
+                .iter()
+                .map(|&name| ifc.get_or_create_port(name.into()))
+                .collect(),
+            out_port: ifc.get_or_create_port("Output".into()),
+            breaker: Breaker::new(),
+            lfo: Arc::new(Mutex::new(Lfo {
+                perlin: Perlin::new(ifc.id().0 as u32),
+                fractal: Fractal::new(),
+                position: 0.0,
+                rate: 1.0,
+            })),
+            ifc,
+        }
+    }
+    fn name() -> &'static str {
+        "Noise LFO"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let lfo = self.lfo.clone();
+        util::start_sink(
+            self.rate_port.clone(),
+            move |rate: f32| lfo.lock().unwrap().rate = rate,
+            self.breaker.clone(),
+            &exec,
+        );
+        let lfo = self.lfo.clone();
+        util::start_sink(
+            self.position_port.clone(),
+            move |position: f32| lfo.lock().unwrap().position = position,
+            self.breaker.clone(),
+            &exec,
+        );
+        for (idx, port) in self.fractal_ports.iter().enumerate() {
+            let lfo = self.lfo.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| set_fractal(&mut lfo.lock().unwrap().fractal, idx, value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let (mut value_tx, value_rx) = mpsc::channel(1);
+        let lfo = self.lfo.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                let mut lfo = lfo.lock().unwrap();
+                // off the lattice planes, where the noise would be flatter
+                let value = lfo.fractal.sample(&lfo.perlin, lfo.position, 0.37, 0.73);
+                lfo.position += lfo.rate * duration(&frame);
+                let _ = value_tx.try_send(value);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(value_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldConfig {
+    /// Size of the rendered image in pixels.
+    pub width: usize,
+    pub height: usize,
+}
+
+impl FieldConfig {
+    /// Parse a config of the form `width height`.
+    pub fn parse(s: &str) -> Option<FieldConfig> {
+        let mut words = s.split_whitespace();
+        let width: usize = words.next()?.parse().ok()?;
+        let height: usize = words.next()?.parse().ok()?;
+        if width == 0 || height == 0 || words.next().is_some() {
+            return None;
+        }
+        Some(FieldConfig { width, height })
+    }
+    fn describe(&self) -> String {
+        format!("{} {}", self.width, self.height)
+    }
+}
+
+impl Default for FieldConfig {
+    fn default() -> FieldConfig {
+        FieldConfig {
+            width: 64,
+            height: 64,
+        }
+    }
+}
+
+pub struct Field {
+    config: FieldConfig,
+    perlin: Perlin,
+    pub fractal: Fractal,
+    /// Units of noise across the width.
+    pub scale: f32,
+    /// Units a second through the third dimension.
+    pub rate: f32,
+    depth: f32,
+}
+
+impl Field {
+    pub fn new(config: FieldConfig, seed: u32) -> Field {
+        Field {
+            config,
+            perlin: Perlin::new(seed),
+            fractal: Fractal::new(),
+            scale: 4.0,
+            rate: 0.25,
+            depth: 0.0,
+        }
+    }
+    pub fn config(&self) -> FieldConfig {
+        self.config
+    }
+    pub fn set_config(&mut self, config: FieldConfig) {
+        self.config = config;
+    }
+    pub fn advance(&mut self, seconds: f32) {
+        self.depth += self.rate * seconds;
+    }
+    pub fn render(&self, rate: f32, time: Option<u64>, pool: &FramePool) -> Frame {
+        let FieldConfig { width, height } = self.config;
+        let mut out = pool.zeros(rate, time, (height, width));
+        let unit = self.scale / width as f32;
+        for ((row, column), x) in out.data.indexed_iter_mut() {
+            let value =
+                self.fractal
+                    .sample(&self.perlin, column as f32 * unit, row as f32 * unit, self.depth);
+            *x = (value * 0.5 + 0.5).max(0.0).min(1.0);
+        }
+        out
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Configure(FieldConfig),
+}
+
+pub struct NoiseField {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    rate_port: Arc<flow::Port<f32, ()>>,
+    scale_port: Arc<flow::Port<f32, ()>>,
+    fractal_ports: Vec<Arc<flow::Port<f32, ()>>>,
+    image_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    field: Arc<Mutex<Field>>,
+}
+
+fn declare_width(port: &flow::Port<(), Frame>, config: &FieldConfig) {
+    port.set_meta(flow::PortMeta {
+        channels: Some(config.width),
+        ..port.meta()
+    });
+}
+
+impl Module for NoiseField {
+    fn new(ifc: Arc<flow::Interface>) -> NoiseField {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        let config = FieldConfig::default();
+        let image_port = ifc.get_or_create_port("Image".into());
+        declare_width(&image_port, &config);
+        NoiseField {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            rate_port: ifc.get_or_create_port("Rate".into()),
+            scale_port: ifc.get_or_create_port("Scale".into()),
+            fractal_ports: ["Octaves", "Lacunarity", "Gain"]
+                .iter()
+                .map(|&name| ifc.get_or_create_port(name.into()))
+                .collect(),
+            image_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            field: Arc::new(Mutex::new(Field::new(config, ifc.id().0 as u32))),
+            ifc,
+        }
+    }
+    fn name() -> &'static str {
+        "Noise Field"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let field = self.field.clone();
+        let image_port = self.image_port.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Configure(config) => {
+                        declare_width(&image_port, &config);
+                        field.lock().unwrap().set_config(config);
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let field = self.field.clone();
+        util::start_sink(
+            self.rate_port.clone(),
+            move |rate: f32| field.lock().unwrap().rate = rate,
+            self.breaker.clone(),
+            &exec,
+        );
+        let field = self.field.clone();
+        util::start_sink(
+            self.scale_port.clone(),
+            move |scale: f32| field.lock().unwrap().scale = scale,
+            self.breaker.clone(),
+            &exec,
+        );
+        for (idx, port) in self.fractal_ports.iter().enumerate() {
+            let field = self.field.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| set_fractal(&mut field.lock().unwrap().fractal, idx, value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let field = self.field.clone();
+        let pool = self.ifc.graph().pool();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                // one image per input frame
+                let rate = frame.rate / frame.data.dim().0.max(1) as f32;
+                let mut field = field.lock().unwrap();
+                field.advance(duration(&frame));
+                let image = field.render(rate, frame.time, &pool);
+                pool.recycle(frame);
+                image
+            },
+            self.ifc.clone(),
+            self.clock_port.clone(),
+            self.image_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.field.lock().unwrap().config()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(config) => {
+                declare_width(&self.image_port, &config);
+                self.field.lock().unwrap().set_config(config);
+            }
+            Err(e) => println!("noise field state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_perlin() {
+    let perlin = Perlin::new(1);
+    assert_eq!(perlin.noise3(3.0, 4.0, 5.0), 0.0);
+    let mut values = Vec::new();
+    for idx in 0..1000 {
+        let x = idx as f32 * 0.0173;
+        let value = perlin.noise3(x, x * 0.7 + 0.5, 0.3);
+        assert!(value.abs() <= 1.0);
+        values.push(value);
+    }
+    // smooth, but not flat
+    assert!(values.windows(2).all(|pair| (pair[0] - pair[1]).abs() < 0.05));
+    assert!(values.iter().any(|&value| value > 0.2) && values.iter().any(|&value| value < -0.2));
+    // repeats every 256 units, and differs with the seed
+    assert!((perlin.noise3(0.3, 0.4, 0.5) - perlin.noise3(256.3, 0.4, 0.5)).abs() < 1e-4);
+    assert!(Perlin::new(2).noise3(0.3, 0.4, 0.5) != perlin.noise3(0.3, 0.4, 0.5));
+
+    let mut fractal = Fractal::new();
+    fractal.set_octaves(1.0);
+    assert_eq!(
+        fractal.sample(&perlin, 0.3, 0.4, 0.5),
+        perlin.noise3(0.3, 0.4, 0.5)
+    );
+    fractal.set_octaves(100.0);
+    assert_eq!(fractal.octaves, MAX_OCTAVES);
+    assert!(fractal.sample(&perlin, 0.3, 0.4, 0.5).abs() <= 1.0);
+
+    let pool = FramePool::new();
+    let mut field = Field::new(FieldConfig::parse("8 4").unwrap(), 1);
+    let image = field.render(10.0, None, &pool);
+    assert_eq!(image.data.dim(), (4, 8));
+    assert!(image.data.iter().all(|&x| x >= 0.0 && x <= 1.0));
+    field.advance(1.0);
+    assert!(field.render(10.0, None, &pool).data != image.data);
+    assert!(FieldConfig::parse("8").is_none());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct FieldGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for NoiseField {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = self.field.lock().unwrap().config();
+        Box::new(FieldGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), config.describe(), row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for FieldGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match FieldConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx
+                            .unbounded_send(UserCommand::Configure(config))
+                            .unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: width height".into()),
+                }
+                true
+            }
+        }
+    }
+}
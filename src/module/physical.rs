@@ -0,0 +1,287 @@
+//! Physical modelling instruments.
+//!
+//! `PluckedString` is a Karplus-Strong string: a delay line one period long, fed back through a
+//! lowpass filter so the tone darkens and decays like a real string. Plucking fills the line with a
+//! noise burst, or with whatever is arriving on `Excitation` when that is connected.
+//!
+//! Like the oscillators, its `Input` frames only set the timing and shape of the output.
+
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Axis;
+
+use std::sync::{Arc, Mutex};
+
+const MIN_FREQUENCY: f32 = 20.0;
+
+/// A tiny xorshift generator, plenty for excitation noise. The seed must not be zero.
+pub struct Noise(pub u32);
+
+impl Noise {
+    /// The next value, between -1.0 and 1.0.
+    pub fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / ::std::u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+pub struct KarplusStrong {
+    line: Vec<f32>,
+    position: usize,
+    period: usize,
+    frequency: f32,
+    /// `0.0` rings for a long time, `1.0` dies almost immediately.
+    damping: f32,
+    /// Amplitude of a pluck to start on the next frame.
+    pluck: Option<f32>,
+    /// Samples of the current pluck left to inject.
+    excite: usize,
+    amplitude: f32,
+    noise: Noise,
+    last: f32,
+}
+
+impl KarplusStrong {
+    pub fn new() -> KarplusStrong {
+        KarplusStrong {
+            line: Vec::new(),
+            position: 0,
+            period: 1,
+            frequency: 110.0,
+            damping: 0.2,
+            pluck: None,
+            excite: 0,
+            amplitude: 0.0,
+            noise: Noise(0x9e37_79b9),
+            last: 0.0,
+        }
+    }
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency.max(MIN_FREQUENCY);
+    }
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.max(0.0).min(1.0);
+    }
+    /// Excite the string for one period, starting with the next frame.
+    pub fn pluck(&mut self, amplitude: f32) {
+        self.pluck = Some(amplitude);
+    }
+    /// Run the string for one frame. `excitation` replaces the noise burst when given.
+    pub fn process(&mut self, mut frame: Frame, excitation: Option<&Frame>) -> Frame {
+        if self.line.len() < (frame.rate / MIN_FREQUENCY) as usize + 1 {
+            self.line = vec![0.0; (frame.rate / MIN_FREQUENCY) as usize + 1];
+            self.position = 0;
+        }
+        self.period = ((frame.rate / self.frequency) as usize).max(2).min(self.line.len());
+        if let Some(amplitude) = self.pluck.take() {
+            self.excite = self.period;
+            self.amplitude = amplitude;
+        }
+        // averaging two samples already loses highs, damping adds more loss per pass
+        let loss = 1.0 - self.damping * 0.05;
+        let smoothing = 0.5 + self.damping * 0.45;
+
+        let len = self.line.len();
+        for (i, mut samples) in frame.data.axis_iter_mut(Axis(0)).enumerate() {
+            let delayed = self.line[(self.position + len - self.period) % len];
+            let mut value = (delayed * (1.0 - smoothing) + self.last * smoothing) * loss;
+            self.last = delayed;
+            if self.excite > 0 {
+                self.excite -= 1;
+                let input = match excitation {
+                    Some(excitation) if excitation.data.len_of(Axis(0)) > i => {
+                        excitation.data.subview(Axis(0), i).iter().sum::<f32>()
+                            / excitation.data.len_of(Axis(1)).max(1) as f32
+                    }
+                    _ => self.noise.next(),
+                };
+                value += input * self.amplitude;
+            }
+            self.line[self.position] = value;
+            self.position = (self.position + 1) % len;
+            for sample in samples.iter_mut() {
+                *sample = value;
+            }
+        }
+        frame
+    }
+}
+
+pub struct PluckedString {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    excitation_port: Arc<flow::Port<Frame, ()>>,
+    pluck_port: Arc<flow::Port<f32, ()>>,
+    frequency_port: Arc<flow::Port<f32, ()>>,
+    damping_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    string: Arc<Mutex<KarplusStrong>>,
+}
+
+impl Module for PluckedString {
+    fn new(ifc: Arc<flow::Interface>) -> PluckedString {
+        PluckedString {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            excitation_port: ifc.get_or_create_port("Excitation".into()),
+            pluck_port: ifc.get_or_create_port("Pluck".into()),
+            frequency_port: ifc.get_or_create_port("Frequency".into()),
+            damping_port: ifc.get_or_create_port("Damping".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            string: Arc::new(Mutex::new(KarplusStrong::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "String"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let string = self.string.clone();
+        util::start_sink(
+            self.pluck_port.clone(),
+            move |amplitude: f32| string.lock().unwrap().pluck(amplitude),
+            self.breaker.clone(),
+            &exec,
+        );
+        let string = self.string.clone();
+        util::start_sink(
+            self.frequency_port.clone(),
+            move |frequency: f32| string.lock().unwrap().set_frequency(frequency),
+            self.breaker.clone(),
+            &exec,
+        );
+        let string = self.string.clone();
+        util::start_sink(
+            self.damping_port.clone(),
+            move |damping: f32| string.lock().unwrap().set_damping(damping),
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let excitation = Arc::new(Mutex::new(None));
+        let excitation_handle = excitation.clone();
+        util::start_sink(
+            self.excitation_port.clone(),
+            move |frame: Frame| *excitation_handle.lock().unwrap() = Some(frame),
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let string = self.string.clone();
+        let excitation_port = self.excitation_port.clone();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                let excitation = if excitation_port.edge().is_some() {
+                    excitation.lock().unwrap().take()
+                } else {
+                    None
+                };
+                string.lock().unwrap().process(frame, excitation.as_ref())
+            },
+            self.ifc.clone(),
+            self.clock_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_plucked_string() {
+    use ndarray::Array2;
+
+    let frame = |data: Array2<f32>| Frame {
+        rate: 1000.0,
+        time: None,
+        data,
+        meta: None,
+    };
+    let silence = || frame(Array2::zeros((200, 1)));
+    let energy = |frame: &Frame| frame.data.iter().map(|x| x * x).sum::<f32>();
+
+    // silent until plucked
+    let mut string = KarplusStrong::new();
+    string.set_frequency(100.0);
+    assert!(string.process(silence(), None).data.iter().all(|&x| x == 0.0));
+
+    // an impulse on the excitation comes back around one period later
+    let mut impulse = Array2::zeros((200, 1));
+    impulse[[0, 0]] = 1.0;
+    string.pluck(1.0);
+    let out = string.process(silence(), Some(&frame(impulse)));
+    assert_eq!(out.data[[0, 0]], 1.0);
+    assert!((1..10).all(|i| out.data[[i, 0]] == 0.0));
+    assert!(out.data[[10, 0]] > 0.0);
+
+    // and dies away, faster with more damping
+    let ring = |damping| {
+        let mut string = KarplusStrong::new();
+        string.set_frequency(100.0);
+        string.set_damping(damping);
+        string.pluck(1.0);
+        (0..20).map(|_| energy(&string.process(silence(), None))).collect::<Vec<_>>()
+    };
+    let (long, short) = (ring(0.2), ring(1.0));
+    assert!(long[0] > 0.0 && long[19] < long[0]);
+    assert!(short[19] < long[19]);
+    assert!(long.iter().all(|x| x.is_finite()));
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*};
+struct PluckedStringGui {
+    bounds: Box3,
+    pluck_button: Button,
+    string: Arc<Mutex<KarplusStrong>>,
+}
+const PADDING: f32 = 4.0;
+impl ModuleGui for PluckedString {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        Box::new(PluckedStringGui {
+            bounds,
+            pluck_button: Button::new(
+                ctx.clone(),
+                "Pluck".into(),
+                Box3 {
+                    pos: bounds.pos + Pt3::new(PADDING, PADDING, 0.0),
+                    size: Pt3::new(bounds.size.x - PADDING * 2.0, 26.0, 0.0),
+                },
+            ),
+            string: self.string.clone(),
+        })
+    }
+}
+impl GuiComponent<bool> for PluckedStringGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.pluck_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        match self.pluck_button.handle(event) {
+            ButtonUpdate::Unchanged => false,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                self.string.lock().unwrap().pluck(1.0);
+                true
+            }
+        }
+    }
+}
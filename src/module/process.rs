@@ -0,0 +1,406 @@
+//! Block processing helper for module authors.
+//!
+//! Most audio modules just want to turn some input frames into some output frames. Implementing
+//! `Process` declares the ports and parameters, and `Processor<P>` wraps it into a full `Module`
+//! that does the port plumbing:
+//!
+//! ```ignore
+//! pub struct Gain(f32);
+//! impl Process for Gain {
+//!     const NAME: &'static str = "Gain";
+//!     const INPUTS: &'static [&'static str] = &["Input"];
+//!     const OUTPUTS: &'static [&'static str] = &["Output"];
+//!     const PARAMS: &'static [(&'static str, f32)] = &[("Gain", 1.0)];
+//!     fn new() -> Gain {
+//!         Gain(1.0)
+//!     }
+//!     fn set_param(&mut self, _idx: usize, value: f32) {
+//!         self.0 = value;
+//!     }
+//!     fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+//!         outputs[0].data.assign(&(&inputs[0].data * self.0));
+//!     }
+//! }
+//! // register with registry.add::<Processor<Gain>>("Mixing", "Scales a signal")
+//! ```
+//!
+//! When run as tasks, each block waits for a request on every output, pulls one frame from every
+//! connected input, then answers all the outputs. Processors also register themselves for the
+//! `BlockScheduler`, which calls them directly on the audio thread instead. The first input is
+//! required and sets the rate and shape of the block; other inputs that aren't connected read as
+//! silence. Inactive nodes skip `process` and muted nodes discard its output, answering with
+//! silence either way. Bypassed nodes skip `process` and pass the first input to the first
+//! output. All audio ports crossfade when their connection or the node's state changes, and
+//! outputs are scaled by the node's level. Blocks only start while the graph is running. The
+//! parameters are registered with the node, so scenes can recall them.
+
+use futures::future;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::declick::{Declick, DEFAULT_RAMP};
+use crate::module::scene::Params;
+use crate::module::scheduler::{Block, BlockNode};
+use crate::module::{audio_io::Frame, flow, simd, util, Module};
+
+pub use flow_synth_core::process::Process;
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub struct Processor<P: Process> {
+    ifc: Arc<flow::Interface>,
+    inputs: Vec<Arc<flow::Port<Frame, ()>>>,
+    outputs: Vec<Arc<flow::Port<(), Frame>>>,
+    params: Vec<Arc<flow::Port<f32, ()>>>,
+    breaker: Breaker,
+    process: Arc<Mutex<P>>,
+    values: Arc<ParamValues<P>>,
+}
+
+/// Keeps track of the values given to `set_param`, which processes don't report back, so they can
+/// be saved in scenes.
+struct ParamValues<P: Process> {
+    process: Arc<Mutex<P>>,
+    values: Mutex<Vec<f32>>,
+}
+
+impl<P: Process> ParamValues<P> {
+    fn set_index(&self, idx: usize, value: f32) {
+        self.values.lock().unwrap()[idx] = value;
+        self.process.lock().unwrap().set_param(idx, value);
+    }
+}
+
+impl<P: Process> Params for ParamValues<P> {
+    fn names(&self) -> Vec<String> {
+        P::PARAMS.iter().map(|&(name, _)| name.to_string()).collect()
+    }
+    fn get(&self, name: &str) -> Option<f32> {
+        let idx = P::PARAMS.iter().position(|&(param, _)| param == name)?;
+        Some(self.values.lock().unwrap()[idx])
+    }
+    fn set(&self, name: &str, value: f32) {
+        if let Some(idx) = P::PARAMS.iter().position(|&(param, _)| param == name) {
+            self.set_index(idx, value);
+        }
+    }
+    fn range(&self, name: &str) -> Option<(f32, f32)> {
+        let idx = P::PARAMS.iter().position(|&(param, _)| param == name)?;
+        P::RANGES.get(idx).cloned()
+    }
+}
+
+impl<P: Process> Processor<P> {
+    /// Access the wrapped processor, e.g. from a GUI body.
+    pub fn process(&self) -> &Arc<Mutex<P>> {
+        &self.process
+    }
+}
+
+impl<P: Process> Module for Processor<P> {
+    fn new(ifc: Arc<flow::Interface>) -> Processor<P> {
+        assert!(!P::INPUTS.is_empty(), "{} needs an input to clock it", P::NAME);
+        let inputs: Vec<Arc<flow::Port<Frame, ()>>> = P::INPUTS
+            .iter()
+            .map(|&name| ifc.get_or_create_port(name.into()))
+            .collect();
+        inputs[0].set_meta(flow::PortMeta {
+            required: true,
+            ..inputs[0].meta()
+        });
+        let mut process = P::new();
+        process.attach(&ifc);
+        for (idx, &(_, value)) in P::PARAMS.iter().enumerate() {
+            process.set_param(idx, value);
+        }
+        let outputs: Vec<Arc<flow::Port<(), Frame>>> = P::OUTPUTS
+            .iter()
+            .map(|&name| ifc.get_or_create_port(name.into()))
+            .collect();
+        for port in &inputs {
+            port.set_meta(flow::PortMeta {
+                ramp: Some(DEFAULT_RAMP),
+                channels: P::INPUT_CHANNELS,
+                signal: Some(flow::Signal::Audio),
+                ..port.meta()
+            });
+        }
+        for port in &outputs {
+            port.set_meta(flow::PortMeta {
+                ramp: Some(DEFAULT_RAMP),
+                channels: P::OUTPUT_CHANNELS,
+                signal: Some(if P::CONTROL_RATE {
+                    flow::Signal::Control
+                } else {
+                    flow::Signal::Audio
+                }),
+                ..port.meta()
+            });
+        }
+        ifc.set_latency(P::LATENCY);
+        let process = Arc::new(Mutex::new(process));
+        let values = Arc::new(ParamValues {
+            process: process.clone(),
+            values: Mutex::new(P::PARAMS.iter().map(|&(_, value)| value).collect()),
+        });
+        ifc.set_params(values.clone());
+        ifc.set_block(BlockNode {
+            inputs: inputs.iter().map(|port| port.id()).collect(),
+            outputs: outputs.iter().map(|port| port.id()).collect(),
+            block: process.clone(),
+        });
+        let params: Vec<Arc<flow::Port<f32, ()>>> = P::PARAMS
+            .iter()
+            .map(|&(name, _)| ifc.get_or_create_port(name.into()))
+            .collect();
+        for port in &params {
+            port.set_meta(flow::PortMeta {
+                signal: Some(flow::Signal::Control),
+                ..port.meta()
+            });
+        }
+        Processor {
+            inputs,
+            outputs,
+            params,
+            ifc,
+            breaker: Breaker::new(),
+            process,
+            values,
+        }
+    }
+    fn name() -> &'static str {
+        P::NAME
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        for (idx, port) in self.params.iter().enumerate() {
+            let values = self.values.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| values.set_index(idx, value),
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        start_blocks(
+            P::NAME,
+            self.ifc.clone(),
+            self.inputs.clone(),
+            self.outputs.clone(),
+            self.process.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Run `block` as a task, the way `Processor` does: each block waits for a request on every
+/// output, pulls a frame from the first input and every connected one, and answers the outputs.
+/// `name` is used in error messages.
+pub fn start_blocks<Ex: Spawn>(
+    name: &'static str,
+    ifc: Arc<flow::Interface>,
+    inputs: Vec<Arc<flow::Port<Frame, ()>>>,
+    outputs: Vec<Arc<flow::Port<(), Frame>>>,
+    process: Arc<Mutex<dyn Block>>,
+    breaker: Breaker,
+    exec: &Ex,
+) {
+    let mut declick = (
+        inputs.iter().map(|_| Declick::new()).collect::<Vec<_>>(),
+        outputs.iter().map(|_| Declick::new()).collect::<Vec<_>>(),
+    );
+    exec.spawn(async move {
+        loop {
+            if let Err(err) = run_block(&ifc, &inputs, &outputs, &process, &mut declick).await {
+                println!("{} err: {}", name, err);
+            }
+            if breaker.test() {
+                break;
+            }
+        }
+    })
+    .unwrap();
+}
+
+/// One block of `start_blocks`, with the crossfades of the inputs and the outputs.
+async fn run_block(
+    ifc: &Arc<flow::Interface>,
+    inputs: &[Arc<flow::Port<Frame, ()>>],
+    outputs: &[Arc<flow::Port<(), Frame>>],
+    process: &Mutex<dyn Block>,
+    declick: &mut (
+        Vec<Declick<Option<flow::PortRef>>>,
+        Vec<Declick<(bool, bool, bool)>>,
+    ),
+) -> Result<(), String> {
+    let graph = ifc.graph();
+    let pool = graph.pool();
+    let requests = outputs.iter().map(|port| port.clone().read1());
+    future::try_join_all(requests)
+        .await
+        .map_err(|(_port, err)| format!("out read1 {:?}", err))?;
+    // a paused graph holds blocks here, once they've been asked for
+    graph.block_boundary().await;
+    // unconnected inputs are filled in with silence once the block's shape is known
+    let pulls = inputs
+        .iter()
+        .enumerate()
+        .filter(|&(idx, port)| idx == 0 || port.edge().is_some())
+        .map(|(idx, port)| async move {
+            let port = port.clone().write1(()).await?;
+            let (_port, frame) = port.read1().await?;
+            Ok((idx, frame))
+        });
+    let pulled = future::try_join_all(pulls)
+        .await
+        .map_err(|(_port, err): (Arc<flow::Port<Frame, ()>>, flow::Error)| format!("in read1 {:?}", err))?;
+
+    let clock = (pulled[0].1.rate, pulled[0].1.time, pulled[0].1.data.dim());
+    let silence = |channels: Option<usize>| {
+        pool.zeros(clock.0, clock.1, ((clock.2).0, channels.unwrap_or((clock.2).1)))
+    };
+    let mut frames: Vec<_> = inputs.iter().map(|_| None).collect();
+    for (idx, frame) in pulled {
+        frames[idx] = Some(frame);
+    }
+    let mut frames: Vec<_> = frames
+        .into_iter()
+        .zip(inputs)
+        .map(|(frame, port)| frame.unwrap_or_else(|| silence(port.meta().channels)))
+        .collect();
+    let (ref mut in_declick, ref mut out_declick) = *declick;
+    for ((port, frame), declick) in inputs.iter().zip(&mut frames).zip(in_declick) {
+        if let Some(ramp) = port.meta().ramp {
+            declick.process(port.edge().map(|other| other.port_ref()), ramp, frame);
+        }
+    }
+    let mut out_frames: Vec<_> = outputs.iter().map(|port| silence(port.meta().channels)).collect();
+    // the block may replace it, but otherwise metadata flows on from the first input
+    for frame in &mut out_frames {
+        frame.meta = frames[0].meta.clone();
+    }
+    if ifc.active() {
+        if ifc.bypassed() {
+            if let Some(output) = out_frames.first_mut() {
+                pool.recycle(mem::replace(output, pool.copy(&frames[0])));
+            }
+        } else {
+            let start = Instant::now();
+            process.lock().unwrap().process(&frames, &mut out_frames);
+            ifc.add_busy(start.elapsed());
+        }
+        if ifc.muted() {
+            for frame in &mut out_frames {
+                frame.data.fill(0.0);
+            }
+        }
+        let level = ifc.level();
+        if level != 1.0 {
+            for frame in &mut out_frames {
+                simd::scale_array(&mut frame.data, level);
+            }
+        }
+    }
+    for frame in frames {
+        pool.recycle(frame);
+    }
+    let state = (ifc.active(), ifc.bypassed(), ifc.muted());
+    for ((port, frame), declick) in outputs.iter().zip(&mut out_frames).zip(out_declick) {
+        if let Some(ramp) = port.meta().ramp {
+            declick.process(state, ramp, frame);
+        }
+    }
+    let writes = outputs
+        .iter()
+        .zip(out_frames)
+        .map(|(port, frame)| port.clone().write1(frame));
+    future::try_join_all(writes)
+        .await
+        .map_err(|(_port, err)| format!("out write1 {:?}", err))?;
+    Ok(())
+}
+
+#[test]
+fn test_bypass() {
+    use crate::module::mix::Gain;
+    use crate::module::testkit::TestHarness;
+    use ndarray::Array2;
+
+    let mut harness = TestHarness::<Processor<Gain>>::with_module();
+    harness.module().process().lock().unwrap().set_param(0, 0.5);
+    // without crossfades, so every change shows up in the next block
+    for port in harness.interface().ports() {
+        port.set_meta(flow::PortMeta {
+            ramp: None,
+            ..port.meta()
+        });
+    }
+    let node = harness.interface().graph().node(harness.interface().id()).unwrap();
+    let input = harness.input::<Frame>("Input");
+    let output = harness.output::<Frame>("Output");
+    let frame = Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 2), 1.0),
+        meta: None,
+    };
+    let mut block = || {
+        input.push(frame.clone());
+        harness.run();
+        output
+            .take()
+            .iter()
+            .map(|frame| frame.data[[0, 0]])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(block(), vec![0.5]);
+
+    // a bypassed node passes its input through untouched, but still answers to mute
+    node.set_bypassed(true);
+    assert_eq!(block(), vec![1.0]);
+    node.set_muted(true);
+    assert_eq!(block(), vec![0.0]);
+    node.set_muted(false);
+    node.set_bypassed(false);
+    assert_eq!(block(), vec![0.5]);
+}
+
+#[test]
+fn test_frame_meta() {
+    use crate::module::mix::Gain;
+    use crate::module::testkit::TestHarness;
+    use ndarray::Array2;
+
+    let mut harness = TestHarness::<Processor<Gain>>::with_module();
+    harness.module().process().lock().unwrap().set_param(0, 0.5);
+    let input = harness.input::<Frame>("Input");
+    let output = harness.output::<Frame>("Output");
+    let mut frame = Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 1), 1.0),
+        meta: None,
+    };
+    frame.set_tag("beat", json!(3));
+    // tagging a copy leaves the frame it was copied from alone
+    let mut copy = frame.clone();
+    copy.set_tag("beat", json!(4));
+    assert_eq!(frame.tag("beat"), Some(&json!(3)));
+
+    input.push(frame);
+    harness.run();
+    let frames = output.take();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].tag("beat"), Some(&json!(3)));
+    assert!(frames[0].tag("bar").is_none());
+}
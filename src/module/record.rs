@@ -0,0 +1,380 @@
+//! Recording the items passing through a connection, and replaying them, for reproducing bugs.
+//!
+//! Patch a `Recorder` into the connection to capture: it passes everything through unchanged, and
+//! while a file is open writes every item to it, one JSON value per line. A `Replay` node loads
+//! such a file and serves the items again in the same order, as fast as they're asked for, so a
+//! downstream module sees exactly the stream it saw when recording, without the hardware or
+//! network source that produced it. Loading a file again queues another playthrough after the
+//! current one. Every playthrough closes the output when it's done, see `flow::Port::close`, so the
+//! reader knows the recording has ended.
+//!
+//! Recordings of frames keep their rate, time and samples, but not their metadata: tags and
+//! channel layouts set upstream are dropped, and replayed frames come without any.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Array2;
+use serde_json::{self, Value};
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long the writer waits for items before flushing what it has.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// Items buffered between a replay file and its output.
+const REPLAY_QUEUE: usize = 16;
+
+/// Items which can be written to a recording and read back.
+pub trait Recordable: util::Silence + Clone + Send + Sized + 'static {
+    /// Module names, which have to differ between the item types registered.
+    const RECORDER_NAME: &'static str = "Recorder";
+    const REPLAY_NAME: &'static str = "Replay";
+    fn to_record(&self) -> Value;
+    fn from_record(value: Value) -> Option<Self>;
+}
+
+macro_rules! recordable_with_serde {
+    ($($ty:ty),*) => {$(
+        impl Recordable for $ty {
+            fn to_record(&self) -> Value {
+                json!(self)
+            }
+            fn from_record(value: Value) -> Option<$ty> {
+                serde_json::from_value(value).ok()
+            }
+        }
+    )*};
+}
+
+recordable_with_serde!(f32, i32, bool, String);
+
+/// Recorded without `meta`, see the module docs.
+impl Recordable for Frame {
+    const RECORDER_NAME: &'static str = "Audio Recorder";
+    const REPLAY_NAME: &'static str = "Audio Replay";
+    fn to_record(&self) -> Value {
+        let rows: Vec<Vec<f32>> = self.data.outer_iter().map(|row| row.to_vec()).collect();
+        json!({"rate": self.rate, "time": self.time, "data": rows})
+    }
+    fn from_record(value: Value) -> Option<Frame> {
+        let rows: Vec<Vec<f32>> = serde_json::from_value(value.get("data")?.clone()).ok()?;
+        let channels = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != channels) {
+            return None;
+        }
+        let data = Array2::from_shape_vec((rows.len(), channels), rows.concat()).ok()?;
+        Some(Frame {
+            rate: value.get("rate")?.as_f64()? as f32,
+            time: value.get("time").and_then(|time| time.as_u64()),
+            data,
+            meta: None,
+        })
+    }
+}
+
+/// Write the items from `rx` to `path` until all senders are gone.
+fn write_items(path: String, rx: std_mpsc::Receiver<Value>) {
+    let write = || -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(&path)?);
+        loop {
+            match rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(item) => writeln!(file, "{}", item)?,
+                Err(std_mpsc::RecvTimeoutError::Timeout) => file.flush()?,
+                Err(std_mpsc::RecvTimeoutError::Disconnected) => return file.flush(),
+            }
+        }
+    };
+    if let Err(e) = write() {
+        println!("recorder {} err: {:?}", path, e);
+    }
+}
+
+/// Read a recording made by a `Recorder`.
+pub fn read_items<T: Recordable, R: BufRead>(reader: R) -> io::Result<Vec<T>> {
+    let mut items = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let item = serde_json::from_str(&line).ok().and_then(T::from_record);
+        let item = item.ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {} is not a recorded item of this type", idx + 1),
+        ))?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    /// Record to a file, or stop recording if None.
+    Record(Option<String>),
+    Load(String),
+}
+
+/// Passes its input through, writing every item to a file while recording.
+pub struct Recorder<T: Recordable> {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<T, ()>>,
+    out_port: Arc<flow::Port<(), T>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    /// Items for the writer thread. Nothing is dropped, so a recording has every item.
+    output: Arc<Mutex<Option<std_mpsc::Sender<Value>>>>,
+}
+
+impl<T: Recordable> Module for Recorder<T> {
+    fn new(ifc: Arc<flow::Interface>) -> Recorder<T> {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Recorder {
+            ifc,
+            in_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            output: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        T::RECORDER_NAME
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let output_handle = self.output.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Record(path) => {
+                        // dropping the old sender lets the previous writer finish its file
+                        *output_handle.lock().unwrap() = path.map(|path| {
+                            let (tx, rx) = std_mpsc::channel();
+                            thread::spawn(move || write_items(path, rx));
+                            tx
+                        });
+                    }
+                    UserCommand::Load(_) => {}
+                }
+            }
+        })
+        .unwrap();
+
+        let output = self.output.clone();
+        util::start_simple_processor(
+            move |item: T| -> T {
+                if let Some(ref tx) = *output.lock().unwrap() {
+                    let _ = tx.send(item.to_record());
+                }
+                item
+            },
+            self.ifc.clone(),
+            self.in_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        *self.output.lock().unwrap() = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Serves the items of a recording, in order, on its output.
+pub struct Replay<T: Recordable> {
+    ifc: Arc<flow::Interface>,
+    out_port: Arc<flow::Port<(), T>>,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    /// For loading the file saved with the patch, the GUI has taken `cmd_tx` by then.
+    load_tx: UnboundedSender<UserCommand>,
+    /// The file last loaded, saved with the patch.
+    path: Arc<Mutex<Option<String>>>,
+}
+
+impl<T: Recordable> Module for Replay<T> {
+    fn new(ifc: Arc<flow::Interface>) -> Replay<T> {
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Replay {
+            ifc,
+            out_port,
+            cmd_rx: Some(cmd_rx),
+            load_tx: cmd_tx.clone(),
+            cmd_tx: Some(cmd_tx),
+            path: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        T::REPLAY_NAME
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (items_tx, items_rx) = mpsc::channel(REPLAY_QUEUE);
+        let path_handle = self.path.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            let mut items_tx = items_tx;
+            while let Some(cmd) = cmd_rx.next().await {
+                let items = match cmd {
+                    UserCommand::Load(path) => {
+                        let items = File::open(&path).and_then(|file| read_items(BufReader::new(file)));
+                        *path_handle.lock().unwrap() = Some(path.clone());
+                        items.unwrap_or_else(|e| {
+                            println!("replay {} err: {:?}", path, e);
+                            Vec::new()
+                        })
+                    }
+                    UserCommand::Record(_) => Vec::new(),
+                };
+                // waits for the items to be taken, so the next load plays after this one, and
+                // ends the stream after the last, so readers can tell the recording is done
+                for item in items.into_iter().map(Some).chain(Some(None)) {
+                    if items_tx.send(item).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        })
+        .unwrap();
+        util::start_stream_source(items_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {}
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> Value {
+        json!(*self.path.lock().unwrap())
+    }
+    fn load_state(&mut self, state: Value) {
+        if let Some(path) = state.as_str() {
+            self.load_tx
+                .unbounded_send(UserCommand::Load(path.into()))
+                .unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_record() {
+    let frame = Frame {
+        rate: 48000.0,
+        time: Some(64),
+        data: Array2::from_shape_vec((2, 2), vec![0.5, -0.5, 0.25, 1.0]).unwrap(),
+        meta: None,
+    };
+    let lines = format!("{}\n\n{}\n", frame.to_record(), json!({"rate": 1.0, "data": []}));
+    let frames: Vec<Frame> = read_items(lines.as_bytes()).unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].data, frame.data);
+    assert_eq!((frames[0].rate, frames[0].time), (48000.0, Some(64)));
+    assert_eq!(frames[1].data.dim(), (0, 0));
+
+    let values: Vec<f32> = read_items("1.5\n-2\n".as_bytes()).unwrap();
+    assert_eq!(values, vec![1.5, -2.0]);
+    assert!(read_items::<f32, _>("1.5\n\"a\"\n".as_bytes()).is_err());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+/// The file box and button of both modules, starting a recording or loading a replay.
+struct FileGui {
+    bounds: Box3,
+    path_box: TextBox,
+    button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+    /// Whether this is a `Recorder`, where an empty path stops recording.
+    record: bool,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl FileGui {
+    fn new(
+        ctx: &mut RenderContext,
+        bounds: Box3,
+        label: &str,
+        cmd_tx: UnboundedSender<UserCommand>,
+        record: bool,
+    ) -> FileGui {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        FileGui {
+            cmd_tx,
+            bounds,
+            path_box: TextBox::new(ctx.clone(), "/tmp/recording.jsonl".into(), row(0.0)),
+            button: Button::new(ctx.clone(), label.into(), row(1.0)),
+            record,
+        }
+    }
+}
+impl<T: Recordable> ModuleGui for Recorder<T> {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let cmd_tx = self.cmd_tx.take().unwrap();
+        Box::new(FileGui::new(ctx, bounds, "Record", cmd_tx, true))
+    }
+}
+impl<T: Recordable> ModuleGui for Replay<T> {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let cmd_tx = self.cmd_tx.take().unwrap();
+        Box::new(FileGui::new(ctx, bounds, "Load", cmd_tx, false))
+    }
+}
+impl GuiComponent<bool> for FileGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.path_box.render(device, ctx);
+        self.button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.path_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let path = self.path_box.content().trim().to_string();
+                let cmd = match (self.record, path.is_empty()) {
+                    (true, true) => {
+                        self.button.set_label("Record".into());
+                        UserCommand::Record(None)
+                    }
+                    (true, false) => {
+                        self.button.set_label(format!("Recording {}", path));
+                        UserCommand::Record(Some(path))
+                    }
+                    (false, true) => {
+                        self.button.set_label("Invalid: path".into());
+                        return true;
+                    }
+                    (false, false) => {
+                        self.button.set_label(format!("Loaded {}", path));
+                        UserCommand::Load(path)
+                    }
+                };
+                self.cmd_tx.unbounded_send(cmd).unwrap();
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! Sample rate conversion by an arbitrary ratio.
+//!
+//! Each output sample is interpolated from the input with a windowed sinc filter, looked up from
+//! an oversampled table. When the rate goes down the filter is stretched to cut everything above
+//! the new Nyquist frequency, so nothing folds back as aliasing. The ratio can be fixed by a
+//! target rate, or follow the `Ratio` input, gliding across each frame so that varispeed effects
+//! don't click. Output frames hold however many samples the input works out to, so the resampler
+//! runs as a task rather than in a `BlockScheduler`.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::pool::FramePool;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Axis;
+use serde_json;
+
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Zero crossings of the sinc on each side of the interpolated point.
+const HALF_TAPS: usize = 32;
+/// Table entries per zero crossing.
+const OVERSAMPLE: usize = 512;
+/// Passband as a fraction of the Nyquist frequency, leaving room for the filter to roll off.
+const ROLLOFF: f64 = 0.95;
+/// Ratios are kept within `MIN_RATIO..=1 / MIN_RATIO`, which bounds the history needed.
+const MIN_RATIO: f64 = 1.0 / 8.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Mode {
+    /// Convert to this sample rate, whatever rate arrives.
+    Rate(f32),
+    /// Stretch by the value on the `Ratio` input, in output samples per input sample.
+    Varying,
+}
+
+impl Mode {
+    /// Parse a target rate in Hz, or `varying`.
+    pub fn parse(s: &str) -> Option<Mode> {
+        match s.trim() {
+            "varying" => Some(Mode::Varying),
+            rate => match rate.parse() {
+                Ok(rate) if rate > 0.0 => Some(Mode::Rate(rate)),
+                _ => None,
+            },
+        }
+    }
+    fn describe(&self) -> String {
+        match *self {
+            Mode::Rate(rate) => rate.to_string(),
+            Mode::Varying => "varying".into(),
+        }
+    }
+}
+
+pub struct Resampler {
+    /// The right half of the windowed sinc, `OVERSAMPLE` entries per zero crossing.
+    table: Vec<f64>,
+    /// Recent input of each channel, as much as the widest filter reaches back.
+    history: Vec<Vec<f32>>,
+    /// Where the next output sample falls in `history`, in input samples.
+    position: f64,
+    /// The ratio the previous frame ended with.
+    ratio: f64,
+    /// Positions of the samples in the frame being produced, kept to save allocating.
+    positions: Vec<(f64, f64)>,
+}
+
+impl Resampler {
+    pub fn new() -> Resampler {
+        let table = (0..HALF_TAPS * OVERSAMPLE + 2)
+            .map(|idx| {
+                let t = idx as f64 / OVERSAMPLE as f64;
+                let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+                sinc * blackman_harris(t / HALF_TAPS as f64)
+            })
+            .collect();
+        Resampler {
+            table,
+            history: Vec::new(),
+            position: 0.0,
+            ratio: 0.0,
+            positions: Vec::new(),
+        }
+    }
+    /// Forget the input seen so far.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.position = 0.0;
+        self.ratio = 0.0;
+    }
+    /// Resample a frame by `ratio` output samples per input sample. The ratio glides from the
+    /// previous frame's over the course of this one.
+    ///
+    /// Each output sample needs the input the filter reaches ahead of it, `HALF_TAPS` samples at
+    /// ratios above one, so the first frame comes out short and the rest trail by that much.
+    pub fn process(&mut self, frame: &Frame, ratio: f32, pool: &FramePool) -> Frame {
+        let ratio = (ratio as f64).max(MIN_RATIO).min(1.0 / MIN_RATIO);
+        let (len, channels) = frame.data.dim();
+        if self.history.len() != channels {
+            self.reset();
+            self.history = vec![Vec::new(); channels];
+        }
+        if self.ratio == 0.0 {
+            self.ratio = ratio;
+        }
+        for (history, input) in self.history.iter_mut().zip(frame.data.axis_iter(Axis(1))) {
+            history.extend(input.iter());
+        }
+
+        let available = self.history.first().map_or(0, |history| history.len());
+        let start = (available - len) as f64;
+        let (from, to) = (self.ratio, ratio);
+        self.positions.clear();
+        loop {
+            let progress = ((self.position - start) / len.max(1) as f64).max(0.0).min(1.0);
+            let ratio = from + (to - from) * progress;
+            let cutoff = ROLLOFF * ratio.min(1.0);
+            if self.position + HALF_TAPS as f64 / cutoff >= available as f64 {
+                break;
+            }
+            self.positions.push((self.position, cutoff));
+            self.position += 1.0 / ratio;
+        }
+        self.ratio = ratio;
+
+        let dim = (self.positions.len(), channels);
+        let mut out = pool.zeros(frame.rate * ratio as f32, frame.time, dim);
+        out.meta = frame.meta.clone();
+        for (mut samples, &(position, cutoff)) in out.data.outer_iter_mut().zip(&self.positions) {
+            for (sample, history) in samples.iter_mut().zip(&self.history) {
+                *sample = self.interpolate(history, position, cutoff);
+            }
+        }
+
+        // keep as much history as the widest filter needs
+        let reach = (HALF_TAPS as f64 / (ROLLOFF * MIN_RATIO)).ceil() as usize;
+        let drop = (self.position as usize).saturating_sub(reach);
+        if drop > 0 {
+            for history in &mut self.history {
+                history.drain(..drop);
+            }
+            self.position -= drop as f64;
+        }
+        out
+    }
+    /// The filtered value of `samples` at `position`, with the given cutoff relative to the input
+    /// Nyquist frequency. Samples before the start of the history count as silence.
+    fn interpolate(&self, samples: &[f32], position: f64, cutoff: f64) -> f32 {
+        let reach = HALF_TAPS as f64 / cutoff;
+        let first = (position - reach).ceil().max(0.0) as usize;
+        let last = ((position + reach).floor() as usize).min(samples.len() - 1);
+        let mut sum = 0.0;
+        for (idx, &sample) in samples.iter().enumerate().take(last + 1).skip(first) {
+            sum += sample as f64 * self.kernel((position - idx as f64).abs() * cutoff);
+        }
+        (sum * cutoff) as f32
+    }
+    /// The windowed sinc at `t` zero crossings from the center.
+    fn kernel(&self, t: f64) -> f64 {
+        let scaled = t * OVERSAMPLE as f64;
+        let idx = scaled as usize;
+        if idx + 1 >= self.table.len() {
+            return 0.0;
+        }
+        let frac = scaled - idx as f64;
+        self.table[idx] + (self.table[idx + 1] - self.table[idx]) * frac
+    }
+}
+
+/// Four term Blackman-Harris window, for `x` from -1 at one edge to 1 at the other.
+fn blackman_harris(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let x = PI * x;
+    0.35875 + 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos() + 0.01168 * (3.0 * x).cos()
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    SetMode(Mode),
+}
+
+pub struct ResamplerModule {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    ratio_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    mode: Arc<Mutex<Mode>>,
+}
+
+impl Module for ResamplerModule {
+    fn new(ifc: Arc<flow::Interface>) -> ResamplerModule {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let ratio_port = ifc.get_or_create_port("Ratio".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        ResamplerModule {
+            ifc,
+            in_port,
+            ratio_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            mode: Arc::new(Mutex::new(Mode::Rate(48000.0))),
+        }
+    }
+    fn name() -> &'static str {
+        "Resampler"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let mode = self.mode.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::SetMode(new_mode) => *mode.lock().unwrap() = new_mode,
+                }
+            }
+        })
+        .unwrap();
+
+        let ratio = Arc::new(Mutex::new(1.0));
+        let ratio_handle = ratio.clone();
+        util::start_sink(
+            self.ratio_port.clone(),
+            move |value: f32| *ratio_handle.lock().unwrap() = value,
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let mode = self.mode.clone();
+        let pool = self.ifc.graph().pool();
+        let mut resampler = Resampler::new();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                let ratio = match *mode.lock().unwrap() {
+                    Mode::Rate(rate) => rate / frame.rate,
+                    Mode::Varying => *ratio.lock().unwrap(),
+                };
+                let out = resampler.process(&frame, ratio, &pool);
+                pool.recycle(frame);
+                out
+            },
+            self.ifc.clone(),
+            self.in_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(*self.mode.lock().unwrap()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(mode) => *self.mode.lock().unwrap() = mode,
+            Err(e) => println!("resampler state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_resampler() {
+    use ndarray::Array2;
+
+    let pool = FramePool::new();
+    let sine = |freq: f64, rate: f64, start: usize, len: usize| Frame {
+        rate: rate as f32,
+        time: None,
+        data: Array2::from_shape_fn((len, 2), |(i, _)| {
+            (2.0 * PI * freq * (start + i) as f64 / rate).sin() as f32
+        }),
+        meta: None,
+    };
+
+    // 1 kHz at 44.1 kHz up to 48 kHz, compared against the ideal sine after the filter delay
+    let mut resampler = Resampler::new();
+    let mut out = Vec::new();
+    for block in 0..20 {
+        let frame = resampler.process(&sine(1000.0, 44100.0, block * 256, 256), 48000.0 / 44100.0, &pool);
+        assert!((frame.rate - 48000.0).abs() < 0.1);
+        out.extend(frame.data.column(0).iter().cloned());
+    }
+    assert!((out.len() as f64 - 20.0 * 256.0 * 48000.0 / 44100.0).abs() < HALF_TAPS as f64 * 2.0);
+    for (idx, &sample) in out.iter().enumerate().skip(100) {
+        let expected = (2.0 * PI * 1000.0 * idx as f64 / 48000.0).sin() as f32;
+        assert!((sample - expected).abs() < 1e-3);
+    }
+
+    // halving the rate removes a tone above the new Nyquist frequency
+    let mut resampler = Resampler::new();
+    let mut peak: f32 = 0.0;
+    for block in 0..20 {
+        let frame = resampler.process(&sine(15000.0, 48000.0, block * 256, 256), 0.5, &pool);
+        if block > 2 {
+            peak = frame.data.iter().fold(peak, |peak, &x| peak.max(x.abs()));
+        }
+    }
+    assert!(peak < 1e-2);
+
+    assert_eq!(Mode::parse("varying"), Some(Mode::Varying));
+    assert_eq!(Mode::parse("44100"), Some(Mode::Rate(44100.0)));
+    assert_eq!(Mode::parse("-1"), None);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct ResamplerGui {
+    bounds: Box3,
+    mode_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for ResamplerModule {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let mode = *self.mode.lock().unwrap();
+        Box::new(ResamplerGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            mode_box: TextBox::new(ctx.clone(), mode.describe(), row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for ResamplerGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.mode_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.mode_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match Mode::parse(self.mode_box.content()) {
+                    Some(mode) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::SetMode(mode)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: Hz or varying".into()),
+                }
+                true
+            }
+        }
+    }
+}
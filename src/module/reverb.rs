@@ -0,0 +1,404 @@
+//! Algorithmic reverb built on a feedback delay network.
+//!
+//! Four delay lines are fed from the input and from each other through a Householder matrix, which
+//! mixes energy evenly between the lines without adding any. A one pole lowpass in each feedback
+//! path makes high frequencies decay faster, as they do in real rooms.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Axis;
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+const N_LINES: usize = 4;
+/// Delay line lengths in seconds at full size, chosen to be mutually prime in samples at common
+/// rates so the echoes don't pile up.
+const LINE_SECONDS: [f32; N_LINES] = [0.0297, 0.0371, 0.0411, 0.0437];
+const MAX_SCALE: f32 = 4.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReverbConfig {
+    /// Room size in `0.0..=1.0`, scaling both the delay lengths and the decay time.
+    pub size: f32,
+    /// High frequency absorption in `0.0..=1.0`.
+    pub damping: f32,
+    /// Wet/dry balance in `0.0..=1.0`.
+    pub mix: f32,
+}
+
+impl ReverbConfig {
+    /// Parse a config of the form `size damping mix`.
+    pub fn parse(s: &str) -> Option<ReverbConfig> {
+        let values: Vec<f32> = s
+            .split_whitespace()
+            .map(|w| w.parse().ok())
+            .collect::<Option<_>>()?;
+        if values.len() != 3 || values.iter().any(|&v| v < 0.0 || v > 1.0) {
+            return None;
+        }
+        Some(ReverbConfig {
+            size: values[0],
+            damping: values[1],
+            mix: values[2],
+        })
+    }
+    /// Seconds for the tail to decay by 60 dB.
+    fn rt60(&self) -> f32 {
+        0.3 + self.size * 5.0
+    }
+}
+
+impl Default for ReverbConfig {
+    fn default() -> ReverbConfig {
+        ReverbConfig {
+            size: 0.5,
+            damping: 0.5,
+            mix: 0.3,
+        }
+    }
+}
+
+struct DelayLine {
+    buffer: Vec<f32>,
+    length: usize,
+    position: usize,
+    /// State of the damping lowpass.
+    lowpass: f32,
+}
+
+impl DelayLine {
+    fn read(&self) -> f32 {
+        let idx = (self.position + self.buffer.len() - self.length) % self.buffer.len();
+        self.buffer[idx]
+    }
+    fn write(&mut self, value: f32) {
+        self.buffer[self.position] = value;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+}
+
+pub struct Reverb {
+    config: ReverbConfig,
+    rate: f32,
+    lines: Vec<DelayLine>,
+    gains: [f32; N_LINES],
+}
+
+impl Reverb {
+    pub fn new(config: ReverbConfig) -> Reverb {
+        Reverb {
+            config,
+            rate: 0.0,
+            lines: Vec::new(),
+            gains: [0.0; N_LINES],
+        }
+    }
+    pub fn config(&self) -> ReverbConfig {
+        self.config
+    }
+    pub fn set_config(&mut self, config: ReverbConfig) {
+        self.config = config;
+        let rate = self.rate;
+        self.configure(rate);
+    }
+    /// Size the delay lines for a sample rate, reallocating only when the rate changes.
+    fn configure(&mut self, rate: f32) {
+        if rate <= 0.0 {
+            return;
+        }
+        if rate != self.rate {
+            self.rate = rate;
+            self.lines = LINE_SECONDS
+                .iter()
+                .map(|&seconds| DelayLine {
+                    buffer: vec![0.0; (seconds * MAX_SCALE * rate) as usize + 1],
+                    length: 1,
+                    position: 0,
+                    lowpass: 0.0,
+                })
+                .collect();
+        }
+        let scale = 1.0 + self.config.size * (MAX_SCALE - 1.0);
+        let rt60 = self.config.rt60();
+        for (idx, line) in self.lines.iter_mut().enumerate() {
+            line.length = ((LINE_SECONDS[idx] * scale * rate) as usize).max(1);
+            // attenuate each pass so the loop loses 60 dB over rt60 seconds
+            self.gains[idx] = 10f32.powf(-3.0 * line.length as f32 / (rt60 * rate));
+        }
+    }
+    pub fn process(&mut self, mut frame: Frame) -> Frame {
+        self.configure(frame.rate);
+        let damping = self.config.damping * 0.9;
+        let mix = self.config.mix;
+        for mut samples in frame.data.axis_iter_mut(Axis(0)) {
+            let channels = samples.len();
+            if channels == 0 {
+                continue;
+            }
+            let input = samples.iter().sum::<f32>() / channels as f32;
+
+            let mut taps = [0.0; N_LINES];
+            for (tap, line) in taps.iter_mut().zip(&self.lines) {
+                *tap = line.read();
+            }
+            // Householder reflection: x - 2/N * sum(x)
+            let sum: f32 = taps.iter().sum();
+            for (idx, line) in self.lines.iter_mut().enumerate() {
+                let feedback = (taps[idx] - 2.0 / N_LINES as f32 * sum) * self.gains[idx];
+                line.lowpass = feedback + damping * (line.lowpass - feedback);
+                let value = input + line.lowpass;
+                line.write(value);
+            }
+
+            // spread alternate lines across the outputs for some stereo width
+            for (channel, sample) in samples.iter_mut().enumerate() {
+                let wet = taps.iter().skip(channel % 2).step_by(2).sum::<f32>();
+                *sample = *sample * (1.0 - mix) + wet * mix;
+            }
+        }
+        frame
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Configure(ReverbConfig),
+}
+
+pub struct ReverbModule {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    reverb: Arc<Mutex<Reverb>>,
+}
+
+impl Module for ReverbModule {
+    fn new(ifc: Arc<flow::Interface>) -> ReverbModule {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        ReverbModule {
+            ifc,
+            in_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            reverb: Arc::new(Mutex::new(Reverb::new(ReverbConfig::default()))),
+        }
+    }
+    fn name() -> &'static str {
+        "Reverb"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let reverb = self.reverb.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Configure(config) => reverb.lock().unwrap().set_config(config),
+                }
+            }
+        })
+        .unwrap();
+
+        let reverb = self.reverb.clone();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame { reverb.lock().unwrap().process(frame) },
+            self.ifc.clone(),
+            self.in_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.reverb.lock().unwrap().config()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(config) => self.reverb.lock().unwrap().set_config(config),
+            Err(e) => println!("reverb state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_reverb() {
+    use ndarray::Array2;
+
+    let rate = 1000.0;
+    let mut impulse = Array2::zeros((2000, 2));
+    impulse[[0, 0]] = 1.0;
+    impulse[[0, 1]] = 1.0;
+    let frame = Frame {
+        rate,
+        time: None,
+        data: impulse,
+        meta: None,
+    };
+
+    let dry = Reverb::new(ReverbConfig::parse("0.5 0.5 0").unwrap()).process(frame.clone());
+    assert_eq!(dry.data, frame.data);
+
+    let wet = Reverb::new(ReverbConfig::parse("0.5 0.2 1").unwrap()).process(frame);
+    let energy = |from: usize, to: usize| -> f32 {
+        wet.data
+            .outer_iter()
+            .skip(from)
+            .take(to - from)
+            .flat_map(|row| row.to_vec())
+            .map(|x| x * x)
+            .sum()
+    };
+    // a tail follows the impulse, and dies away
+    assert!(energy(1, 500) > 0.0);
+    assert!(energy(1500, 2000) < energy(1, 500));
+    assert!(wet.data.iter().all(|x| x.is_finite() && x.abs() < 10.0));
+}
+
+#[test]
+fn test_reverb_state() {
+    let graph = flow::Graph::new();
+    let mut reverb = ReverbModule::new(graph.add_node());
+    let config = ReverbConfig::parse("0.8 0.1 0.4").unwrap();
+    reverb.load_state(serde_json::to_value(config).unwrap());
+    assert_eq!(reverb.reverb.lock().unwrap().config(), config);
+    let state = reverb.save_state();
+
+    // a fresh module picks up where the saved one left off, and ignores state it can't read
+    let mut restored = ReverbModule::new(graph.add_node());
+    restored.load_state(state);
+    assert_eq!(restored.reverb.lock().unwrap().config(), config);
+    restored.load_state(json!("nonsense"));
+    assert_eq!(restored.reverb.lock().unwrap().config(), config);
+}
+
+#[test]
+fn test_reverb_bypass() {
+    use crate::module::testkit::TestHarness;
+    use ndarray::Array2;
+
+    let config = ReverbConfig::parse("0.5 0.2 1").unwrap();
+    let mut harness = TestHarness::<ReverbModule>::with_state(serde_json::to_value(config).unwrap());
+    let node = harness.interface().graph().node(harness.interface().id()).unwrap();
+    let input = harness.input::<Frame>("Input");
+    let output = harness.output::<Frame>("Output");
+    let mut impulse = Array2::zeros((100, 2));
+    impulse[[0, 0]] = 1.0;
+    impulse[[0, 1]] = 1.0;
+    let frame = Frame {
+        rate: 1000.0,
+        time: None,
+        data: impulse,
+        meta: None,
+    };
+    let mut block = || {
+        input.push(frame.clone());
+        harness.run();
+        let mut out = output.take();
+        assert_eq!(out.len(), 1);
+        out.remove(0).data
+    };
+    assert!(block() != frame.data);
+
+    // bypassed, the input passes through untouched, and muted or inactive there's silence
+    node.set_bypassed(true);
+    assert_eq!(block(), frame.data);
+    node.set_muted(true);
+    assert!(block().iter().all(|&x| x == 0.0));
+    node.set_muted(false);
+    node.set_bypassed(false);
+    node.set_active(false);
+    assert!(block().iter().all(|&x| x == 0.0));
+    node.set_active(true);
+    assert!(block() != frame.data);
+
+    // pausing finishes the block already waiting on its input, and holds the next one until the
+    // graph is started again
+    let graph = harness.interface().graph();
+    graph.pause();
+    input.push(frame.clone());
+    harness.run();
+    assert_eq!(output.take().len(), 1);
+    input.push(frame.clone());
+    harness.run();
+    assert!(output.take().is_empty());
+    graph.start();
+    harness.run();
+    assert_eq!(output.take().len(), 1);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct ReverbGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for ReverbModule {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = self.reverb.lock().unwrap().config();
+        Box::new(ReverbGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(
+                ctx.clone(),
+                format!("{} {} {}", config.size, config.damping, config.mix),
+                row(0.0),
+            ),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for ReverbGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match ReverbConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::Configure(config)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: size damping mix".into()),
+                }
+                true
+            }
+        }
+    }
+}
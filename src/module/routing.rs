@@ -0,0 +1,310 @@
+//! Routing for performance setups: `Switch` passes on one of several inputs, `Router` sends its
+//! input to one of several outputs, and `Crossfader` blends two audio signals.
+//!
+//! `Switch` and `Router` work with any item type, like control values or events. Every input is
+//! read all the time, so sources on unselected inputs keep running and their items are dropped,
+//! and unselected outputs of a `Router` get nothing. Audio gets `AudioSwitch` and `AudioRouter`,
+//! built with `Process`, which crossfade over a block on every change and keep unselected outputs
+//! silent. Indices count from 0, so 0 selects `In 1` or `Out 1`, and are rounded and clamped to
+//! the ports there are.
+
+use futures::channel::mpsc;
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::audio_io::Frame;
+use crate::module::process::Process;
+use crate::module::{flow, util, Module};
+
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The number of inputs of a switch and outputs of a router.
+pub const WAYS: usize = 4;
+const INPUT_NAMES: &[&str] = &["In 1", "In 2", "In 3", "In 4"];
+const OUTPUT_NAMES: &[&str] = &["Out 1", "Out 2", "Out 3", "Out 4"];
+
+/// The port picked by an `Index` value.
+fn select(index: f32) -> usize {
+    index.round().max(0.0).min((WAYS - 1) as f32) as usize
+}
+
+/// Passes on the items of the input picked by `Index`.
+pub struct Switch<T: Send + 'static> {
+    ifc: Arc<flow::Interface>,
+    inputs: Vec<Arc<flow::Port<T, ()>>>,
+    index_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), T>>,
+    breaker: Breaker,
+    selected: Arc<AtomicUsize>,
+}
+
+impl<T: Send + 'static> Module for Switch<T> {
+    fn new(ifc: Arc<flow::Interface>) -> Switch<T> {
+        Switch {
+            inputs: INPUT_NAMES
+                .iter()
+                .map(|&name| ifc.get_or_create_port(name.into()))
+                .collect(),
+            index_port: ifc.get_or_create_port("Index".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            selected: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+    fn name() -> &'static str {
+        "Switch"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let selected = self.selected.clone();
+        util::start_sink(
+            self.index_port.clone(),
+            move |index: f32| selected.store(select(index), Ordering::Relaxed),
+            self.breaker.clone(),
+            &exec,
+        );
+        let (out_tx, out_rx) = mpsc::channel(1);
+        for (idx, port) in self.inputs.iter().enumerate() {
+            let selected = self.selected.clone();
+            let mut out_tx = out_tx.clone();
+            util::start_sink(
+                port.clone(),
+                move |item: T| {
+                    if selected.load(Ordering::Relaxed) == idx {
+                        let _ = out_tx.try_send(item);
+                    }
+                },
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Sends its input to the output picked by `Index`.
+pub struct Router<T: Send + 'static> {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<T, ()>>,
+    index_port: Arc<flow::Port<f32, ()>>,
+    outputs: Vec<Arc<flow::Port<(), T>>>,
+    breaker: Breaker,
+    selected: Arc<AtomicUsize>,
+}
+
+impl<T: Send + 'static> Module for Router<T> {
+    fn new(ifc: Arc<flow::Interface>) -> Router<T> {
+        Router {
+            in_port: ifc.get_or_create_port("Input".into()),
+            index_port: ifc.get_or_create_port("Index".into()),
+            outputs: OUTPUT_NAMES
+                .iter()
+                .map(|&name| ifc.get_or_create_port(name.into()))
+                .collect(),
+            ifc,
+            breaker: Breaker::new(),
+            selected: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+    fn name() -> &'static str {
+        "Router"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let selected = self.selected.clone();
+        util::start_sink(
+            self.index_port.clone(),
+            move |index: f32| selected.store(select(index), Ordering::Relaxed),
+            self.breaker.clone(),
+            &exec,
+        );
+        let mut out_txs = Vec::new();
+        for port in &self.outputs {
+            let (out_tx, out_rx) = mpsc::channel(1);
+            out_txs.push(out_tx);
+            util::start_source(out_rx, port.clone(), &exec);
+        }
+        let selected = self.selected.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |item: T| {
+                let _ = out_txs[selected.load(Ordering::Relaxed)].try_send(item);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// The gain of sample `i` of `len` fading in over a block.
+fn fade_in(i: usize, len: usize) -> f32 {
+    (i + 1) as f32 / len as f32
+}
+
+/// Passes on the audio input picked by `Index`, crossfading over a block when it changes.
+pub struct AudioSwitch {
+    selected: usize,
+    /// The input playing now, which moves to `selected` over the next block.
+    current: usize,
+}
+
+impl Process for AudioSwitch {
+    const NAME: &'static str = "Audio Switch";
+    const INPUTS: &'static [&'static str] = INPUT_NAMES;
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] = &[("Index", 0.0)];
+    fn new() -> AudioSwitch {
+        AudioSwitch {
+            selected: 0,
+            current: 0,
+        }
+    }
+    fn set_param(&mut self, _idx: usize, value: f32) {
+        self.selected = select(value);
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let (from, to) = (self.current, self.selected);
+        outputs[0].data.assign(&inputs[to].data);
+        if from != to {
+            let len = outputs[0].data.dim().0;
+            let old = inputs[from].data.outer_iter();
+            for (i, (mut samples, old)) in outputs[0].data.outer_iter_mut().zip(old).enumerate() {
+                let gain = fade_in(i, len);
+                samples.zip_mut_with(&old, |x, &old| *x = old + (*x - old) * gain);
+            }
+            self.current = to;
+        }
+    }
+}
+
+/// Sends its audio input to the output picked by `Index`, crossfading over a block when it
+/// changes. The other outputs are silent.
+pub struct AudioRouter {
+    selected: usize,
+    /// The output playing now, which moves to `selected` over the next block.
+    current: usize,
+}
+
+impl Process for AudioRouter {
+    const NAME: &'static str = "Audio Router";
+    const INPUTS: &'static [&'static str] = &["Input"];
+    const OUTPUTS: &'static [&'static str] = OUTPUT_NAMES;
+    const PARAMS: &'static [(&'static str, f32)] = &[("Index", 0.0)];
+    fn new() -> AudioRouter {
+        AudioRouter {
+            selected: 0,
+            current: 0,
+        }
+    }
+    fn set_param(&mut self, _idx: usize, value: f32) {
+        self.selected = select(value);
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let (from, to) = (self.current, self.selected);
+        outputs[to].data.assign(&inputs[0].data);
+        if from != to {
+            outputs[from].data.assign(&inputs[0].data);
+            let len = inputs[0].data.dim().0;
+            for i in 0..len {
+                let gain = fade_in(i, len);
+                outputs[to].data.row_mut(i).mapv_inplace(|x| x * gain);
+                outputs[from].data.row_mut(i).mapv_inplace(|x| x * (1.0 - gain));
+            }
+            self.current = to;
+        }
+    }
+}
+
+/// Blends `A` into `B` as `Position` goes from 0.0 to 1.0, keeping the power constant. Position
+/// changes are ramped over a block.
+pub struct Crossfader {
+    position: f32,
+    /// The position reached by the end of the last block.
+    current: f32,
+}
+
+impl Process for Crossfader {
+    const NAME: &'static str = "Crossfader";
+    const INPUTS: &'static [&'static str] = &["A", "B"];
+    const OUTPUTS: &'static [&'static str] = &["Output"];
+    const PARAMS: &'static [(&'static str, f32)] = &[("Position", 0.0)];
+    const RANGES: &'static [(f32, f32)] = &[(0.0, 1.0)];
+    fn new() -> Crossfader {
+        Crossfader {
+            position: 0.0,
+            current: 0.0,
+        }
+    }
+    fn set_param(&mut self, _idx: usize, value: f32) {
+        self.position = value.max(0.0).min(1.0);
+    }
+    fn process(&mut self, inputs: &[Frame], outputs: &mut [Frame]) {
+        let len = inputs[0].data.dim().0;
+        let (from, to) = (self.current, self.position);
+        let samples = inputs[0].data.outer_iter().zip(inputs[1].data.outer_iter());
+        for (i, (mut out, (a, b))) in outputs[0].data.outer_iter_mut().zip(samples).enumerate() {
+            let position = from + (to - from) * fade_in(i, len);
+            let (gain_a, gain_b) = ((position * PI / 2.0).cos(), (position * PI / 2.0).sin());
+            for ((out, &a), &b) in out.iter_mut().zip(&a).zip(&b) {
+                *out = a * gain_a + b * gain_b;
+            }
+        }
+        self.current = to;
+    }
+}
+
+#[test]
+fn test_routing() {
+    use ndarray::Array2;
+
+    assert_eq!(
+        (select(-1.0), select(1.4), select(2.6), select(9.0)),
+        (0, 1, 3, 3)
+    );
+
+    let frame = |value: f32| Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 2), value),
+        meta: None,
+    };
+    let inputs = [frame(1.0), frame(2.0), frame(3.0), frame(4.0)];
+    let mut outputs = [frame(0.0)];
+    let mut switch = AudioSwitch::new();
+    switch.process(&inputs, &mut outputs);
+    assert!(outputs[0].data.iter().all(|&x| x == 1.0));
+    switch.set_param(0, 2.0);
+    switch.process(&inputs, &mut outputs);
+    // from 1.0 to 3.0 over the block
+    assert_eq!(outputs[0].data.column(0).to_vec(), vec![1.5, 2.0, 2.5, 3.0]);
+    switch.process(&inputs, &mut outputs);
+    assert!(outputs[0].data.iter().all(|&x| x == 3.0));
+
+    let mut router = AudioRouter::new();
+    let mut outputs = [frame(0.0), frame(0.0), frame(0.0), frame(0.0)];
+    router.set_param(0, 1.0);
+    router.process(&[frame(1.0)], &mut outputs);
+    assert_eq!(outputs[0].data.column(0).to_vec(), vec![0.75, 0.5, 0.25, 0.0]);
+    assert_eq!(outputs[1].data.column(0).to_vec(), vec![0.25, 0.5, 0.75, 1.0]);
+
+    let mut crossfader = Crossfader::new();
+    let mut outputs = [frame(0.0)];
+    crossfader.set_param(0, 0.5);
+    crossfader.process(&[frame(1.0), frame(1.0)], &mut outputs);
+    crossfader.process(&[frame(1.0), frame(0.0)], &mut outputs);
+    // equal power, so each side is down by 3 dB in the middle
+    assert!(outputs[0].data.iter().all(|&x| (x - 0.5f32.sqrt()).abs() < 1e-6));
+}
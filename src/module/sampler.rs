@@ -0,0 +1,1064 @@
+//! Sample instruments, playing SF2 soundfonts and SFZ instruments from note events.
+//!
+//! An instrument is a set of regions, each a sample played over a range of keys and velocities,
+//! transposed from its root key. Every `Note` on the `Notes` input starts a voice for each region
+//! it falls in, and a note with velocity 0 releases the voices of its key, fading them out quickly
+//! unless they play to the end anyway. Samples are mixed down to mono, and the output fills every
+//! channel alike.
+//!
+//! From SF2 files one preset is used, the first unless the state names another by its index, with
+//! the key and velocity ranges, root key, tuning, attenuation and loop of each zone. Envelopes,
+//! filters and modulators are ignored. From SFZ files `sample`, `lokey`, `hikey`, `key`,
+//! `pitch_keycenter`, `lovel`, `hivel`, `tune`, `transpose`, `volume`, `offset`, `end`,
+//! `loop_mode`, `loop_start`, `loop_end` and `default_path` are understood, in `<control>`,
+//! `<global>`, `<group>` and `<region>` headers. Samples are WAV files.
+//!
+//! Keys sound at the pitch the graph's tuning gives them, see `module::tuning`, so a region is
+//! transposed by the ratio of that to the equal tempered pitch of its root. Keys the tuning leaves
+//! silent don't play. Like oscillators, the `Input` frames only act as a clock.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use hound;
+
+use crate::future_ext::Breaker;
+use crate::module::tuning::Tuning;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Axis;
+use serde_json;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Voices sounding at once. Starting another takes over the oldest.
+pub const MAX_VOICES: usize = 32;
+/// Seconds a released voice takes to fade out.
+pub const RELEASE: f32 = 0.05;
+
+/// A key being pressed or, with velocity 0, released, as in MIDI.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    pub key: u8,
+    pub velocity: u8,
+}
+
+/// A sample played over a range of keys and velocities.
+#[derive(Clone)]
+pub struct Region {
+    /// The samples, maybe shared with other regions, and the part of them played.
+    pub data: Arc<Vec<f32>>,
+    pub start: usize,
+    pub end: usize,
+    /// Start and end of the part repeated while the key is held.
+    pub looped: Option<(usize, usize)>,
+    pub rate: f32,
+    /// The key playing the sample at its own pitch, with any fine tuning as a fraction.
+    pub root: f32,
+    pub keys: (u8, u8),
+    pub velocities: (u8, u8),
+    pub gain: f32,
+    /// Ignore releases, playing the sample to its end.
+    pub one_shot: bool,
+}
+
+impl Region {
+    /// A region playing all of `data` on every key and velocity.
+    pub fn new(data: Vec<f32>, rate: f32, root: f32) -> Region {
+        Region {
+            end: data.len(),
+            data: Arc::new(data),
+            start: 0,
+            looped: None,
+            rate,
+            root,
+            keys: (0, 127),
+            velocities: (0, 127),
+            gain: 1.0,
+            one_shot: false,
+        }
+    }
+    fn contains(&self, note: Note) -> bool {
+        self.keys.0 <= note.key
+            && note.key <= self.keys.1
+            && self.velocities.0 <= note.velocity
+            && note.velocity <= self.velocities.1
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Instrument {
+    pub regions: Vec<Region>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Wav(hound::Error),
+    /// Not a file this can read, with what was wrong with it.
+    Format(String),
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+impl From<hound::Error> for LoadError {
+    fn from(e: hound::Error) -> LoadError {
+        LoadError::Wav(e)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "{}", e),
+            LoadError::Wav(ref e) => write!(f, "{}", e),
+            LoadError::Format(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn format_err<T>(message: &str) -> Result<T, LoadError> {
+    Err(LoadError::Format(message.into()))
+}
+
+impl Instrument {
+    /// Load an SF2 or SFZ file, by its extension. `preset` picks the preset of a soundfont.
+    pub fn load<P: AsRef<Path>>(path: P, preset: usize) -> Result<Instrument, LoadError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension.as_ref().map(|ext| ext.as_str()) {
+            Some("sf2") => {
+                let mut data = Vec::new();
+                File::open(path)?.read_to_end(&mut data)?;
+                Instrument::from_sf2(&data, preset)
+            }
+            Some("sfz") => {
+                let text = fs::read_to_string(path)?;
+                let dir = path.parent().unwrap_or(Path::new(""));
+                Instrument::from_sfz(&text, dir)
+            }
+            _ => format_err("not an .sf2 or .sfz file"),
+        }
+    }
+
+    /// The regions playing `note`.
+    fn regions_for<'a>(&'a self, note: Note) -> impl Iterator<Item = (usize, &'a Region)> + 'a {
+        self.regions
+            .iter()
+            .enumerate()
+            .filter(move |(_, region)| region.contains(note))
+    }
+}
+
+/// Little endian reads out of a chunk, failing past its end.
+struct Bytes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        if self.data.len() < len {
+            return format_err("truncated soundfont");
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        let b = self.take(2)?;
+        Ok(b[0] as u16 | (b[1] as u16) << 8)
+    }
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        let b = self.take(4)?;
+        Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+    }
+}
+
+/// The chunks of a RIFF list by id, with the lists inside it flattened in by their type.
+fn riff_chunks(data: &[u8]) -> Result<HashMap<[u8; 4], &[u8]>, LoadError> {
+    let mut chunks = HashMap::new();
+    let mut bytes = Bytes { data };
+    while !bytes.data.is_empty() {
+        let mut id = [0; 4];
+        id.copy_from_slice(bytes.take(4)?);
+        let len = bytes.u32()? as usize;
+        let body = bytes.take(len)?;
+        if len % 2 == 1 && !bytes.data.is_empty() {
+            bytes.take(1)?;
+        }
+        if &id == b"LIST" && body.len() >= 4 {
+            chunks.extend(riff_chunks(&body[4..])?);
+        } else {
+            chunks.insert(id, body);
+        }
+    }
+    Ok(chunks)
+}
+
+const GEN_INSTRUMENT: u16 = 41;
+const GEN_KEY_RANGE: u16 = 43;
+const GEN_VEL_RANGE: u16 = 44;
+const GEN_ATTENUATION: u16 = 48;
+const GEN_COARSE_TUNE: u16 = 51;
+const GEN_FINE_TUNE: u16 = 52;
+const GEN_SAMPLE_ID: u16 = 53;
+const GEN_SAMPLE_MODES: u16 = 54;
+const GEN_ROOT_KEY: u16 = 58;
+
+/// The generators of a zone, by operator, with the raw amount.
+type Zone = HashMap<u16, u16>;
+
+/// The zones of each preset or instrument, from its header's bag index up to the next header's.
+fn zones(headers: &[usize], bags: &[u8], gens: &[u8]) -> Result<Vec<Vec<Zone>>, LoadError> {
+    let mut bag_gens = Vec::new();
+    let mut bytes = Bytes { data: bags };
+    while bytes.data.len() >= 4 {
+        bag_gens.push(bytes.u16()? as usize);
+        bytes.u16()?;
+    }
+    let mut generators = Vec::new();
+    let mut bytes = Bytes { data: gens };
+    while bytes.data.len() >= 4 {
+        generators.push((bytes.u16()?, bytes.u16()?));
+    }
+    let zone = |bag: usize| -> Result<Zone, LoadError> {
+        match (bag_gens.get(bag), bag_gens.get(bag + 1)) {
+            (Some(&from), Some(&to)) if from <= to && to <= generators.len() => {
+                Ok(generators[from..to].iter().cloned().collect())
+            }
+            _ => format_err("bad soundfont zone"),
+        }
+    };
+    headers
+        .windows(2)
+        .map(|bags| {
+            (bags[0]..bags[1])
+                .map(|bag| zone(bag))
+                .collect::<Result<Vec<Zone>, LoadError>>()
+        })
+        .collect()
+}
+
+/// Split a list of records of `size` bytes, each starting with a 20 byte name, into their bag
+/// indices, taken from `offset` past the name.
+fn bag_indices(data: &[u8], size: usize, offset: usize) -> Result<Vec<usize>, LoadError> {
+    data.chunks(size)
+        .filter(|record| record.len() == size)
+        .map(|record| {
+            Bytes {
+                data: &record[20 + offset..],
+            }
+            .u16()
+            .map(|bag| bag as usize)
+        })
+        .collect()
+}
+
+fn range(amount: u16) -> (u8, u8) {
+    (amount as u8, (amount >> 8) as u8)
+}
+
+fn intersect(a: (u8, u8), b: (u8, u8)) -> (u8, u8) {
+    (a.0.max(b.0), a.1.min(b.1))
+}
+
+impl Instrument {
+    /// Read the preset with index `preset` out of a soundfont.
+    pub fn from_sf2(data: &[u8], preset: usize) -> Result<Instrument, LoadError> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"sfbk" {
+            return format_err("not a soundfont");
+        }
+        let chunks = riff_chunks(&data[12..])?;
+        let chunk = |id: &[u8; 4]| match chunks.get(id) {
+            Some(chunk) => Ok(*chunk),
+            None => format_err("soundfont chunk missing"),
+        };
+        let samples: Arc<Vec<f32>> = Arc::new(
+            chunk(b"smpl")?
+                .chunks(2)
+                .filter(|b| b.len() == 2)
+                .map(|b| (b[0] as u16 | (b[1] as u16) << 8) as i16 as f32 / 32768.0)
+                .collect(),
+        );
+        let presets = zones(
+            &bag_indices(chunk(b"phdr")?, 38, 4)?,
+            chunk(b"pbag")?,
+            chunk(b"pgen")?,
+        )?;
+        let instruments = zones(
+            &bag_indices(chunk(b"inst")?, 22, 0)?,
+            chunk(b"ibag")?,
+            chunk(b"igen")?,
+        )?;
+        let headers: Vec<&[u8]> = chunk(b"shdr")?.chunks(46).filter(|h| h.len() == 46).collect();
+
+        let preset_zones = match presets.get(preset) {
+            Some(zones) => zones,
+            None => return format_err("no such preset"),
+        };
+        let (preset_global, preset_zones) = split_global(preset_zones, GEN_INSTRUMENT);
+        let mut regions = Vec::new();
+        for preset_zone in preset_zones {
+            let get = |op| preset_zone.get(&op).or_else(|| preset_global.get(&op)).cloned();
+            let keys = get(GEN_KEY_RANGE).map(range).unwrap_or((0, 127));
+            let velocities = get(GEN_VEL_RANGE).map(range).unwrap_or((0, 127));
+            let inst_zones = match instruments.get(preset_zone[&GEN_INSTRUMENT] as usize) {
+                Some(zones) => zones,
+                None => return format_err("no such instrument"),
+            };
+            let (inst_global, inst_zones) = split_global(inst_zones, GEN_SAMPLE_ID);
+            for zone in inst_zones {
+                let get = |op| zone.get(&op).or_else(|| inst_global.get(&op)).cloned();
+                let header = match headers.get(zone[&GEN_SAMPLE_ID] as usize) {
+                    Some(header) => header,
+                    None => return format_err("no such sample"),
+                };
+                let mut bytes = Bytes { data: &header[20..] };
+                let (start, end) = (bytes.u32()? as usize, bytes.u32()? as usize);
+                let (loop_start, loop_end) = (bytes.u32()? as usize, bytes.u32()? as usize);
+                // the loop is cut off at the end of the sample, and left out if that leaves nothing
+                let loop_end = loop_end.min(end);
+                let rate = bytes.u32()? as f32;
+                let original = bytes.u8()?;
+                let correction = bytes.u8()? as i8;
+                if start > end || end > samples.len() {
+                    return format_err("sample out of range");
+                }
+                let key = get(GEN_ROOT_KEY)
+                    .filter(|&key| key < 128)
+                    .unwrap_or(original as u16);
+                let tune = get(GEN_COARSE_TUNE).unwrap_or(0) as i16 as f32
+                    + (get(GEN_FINE_TUNE).unwrap_or(0) as i16 as f32 + correction as f32) / 100.0;
+                let mode = get(GEN_SAMPLE_MODES).unwrap_or(0) & 3;
+                let attenuation = get(GEN_ATTENUATION).unwrap_or(0) as i16 as f32 / 10.0;
+                regions.push(Region {
+                    data: samples.clone(),
+                    start,
+                    end,
+                    looped: if (mode == 1 || mode == 3) && start <= loop_start && loop_start < loop_end {
+                        Some((loop_start, loop_end))
+                    } else {
+                        None
+                    },
+                    rate,
+                    // transposing up means playing as if the root were lower
+                    root: key as f32 - tune,
+                    keys: intersect(keys, get(GEN_KEY_RANGE).map(range).unwrap_or((0, 127))),
+                    velocities: intersect(velocities, get(GEN_VEL_RANGE).map(range).unwrap_or((0, 127))),
+                    gain: 10f32.powf(-attenuation / 20.0),
+                    one_shot: false,
+                });
+            }
+        }
+        Ok(Instrument { regions })
+    }
+}
+
+/// Split off the global zone, the first one if it lacks the generator that every other zone has.
+fn split_global(zones: &[Zone], op: u16) -> (Zone, Vec<&Zone>) {
+    match zones.split_first() {
+        Some((first, rest)) if !first.contains_key(&op) => (
+            first.clone(),
+            rest.iter().filter(|zone| zone.contains_key(&op)).collect(),
+        ),
+        _ => (
+            Zone::new(),
+            zones.iter().filter(|zone| zone.contains_key(&op)).collect(),
+        ),
+    }
+}
+
+/// Parse a key number or note name, with `c4` as middle C, 60.
+pub fn parse_key(value: &str) -> Option<u8> {
+    if let Ok(key) = value.parse::<u8>() {
+        return Some(key);
+    }
+    let value = value.to_lowercase();
+    let mut chars = value.chars();
+    let mut key: i32 = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let octave = if rest.starts_with('#') {
+        key += 1;
+        &rest[1..]
+    } else if rest.starts_with('b') {
+        key -= 1;
+        &rest[1..]
+    } else {
+        rest
+    };
+    let key = key + (octave.parse::<i32>().ok()? + 1) * 12;
+    if 0 <= key && key < 128 {
+        Some(key as u8)
+    } else {
+        None
+    }
+}
+
+/// The opcodes of each `<region>`, with those of the `<global>` and `<group>` above it filled in.
+pub fn parse_sfz(text: &str) -> Vec<HashMap<String, String>> {
+    let text: String = text
+        .lines()
+        .map(|line| match line.find("//") {
+            Some(idx) => &line[..idx],
+            None => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .replace("<", " <")
+        .replace(">", "> ");
+
+    let mut regions = Vec::new();
+    let (mut control, mut global, mut group) = (HashMap::new(), HashMap::new(), HashMap::new());
+    let mut region: Option<HashMap<String, String>> = None;
+    let mut header = String::new();
+    let mut last: Option<String> = None;
+    for token in text.split_whitespace() {
+        if token.starts_with('<') {
+            regions.extend(region.take());
+            header = token.to_string();
+            match header.as_str() {
+                "<global>" => global.clear(),
+                "<group>" => group.clear(),
+                "<region>" => {
+                    let mut opcodes: HashMap<String, String> = control.clone();
+                    opcodes.extend(global.clone());
+                    opcodes.extend(group.clone());
+                    region = Some(opcodes);
+                }
+                _ => {}
+            }
+            last = None;
+            continue;
+        }
+        let opcodes = match header.as_str() {
+            "<control>" => &mut control,
+            "<global>" => &mut global,
+            "<group>" => &mut group,
+            "<region>" => region.as_mut().unwrap(),
+            _ => continue,
+        };
+        match token.find('=') {
+            Some(idx) => {
+                let name = token[..idx].to_string();
+                opcodes.insert(name.clone(), token[idx + 1..].to_string());
+                last = Some(name);
+            }
+            // file names may have spaces
+            None => {
+                if let Some(value) = last.as_ref().and_then(|name| opcodes.get_mut(name)) {
+                    value.push(' ');
+                    value.push_str(token);
+                }
+            }
+        }
+    }
+    regions.extend(region);
+    regions
+}
+
+/// Read a WAV file, mixed down to mono.
+fn read_wav(path: &Path) -> Result<(Vec<f32>, f32), LoadError> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|x| x.map(|x| x as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let mono = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate as f32))
+}
+
+impl Instrument {
+    /// Build an SFZ instrument, loading its samples relative to `dir`.
+    pub fn from_sfz(text: &str, dir: &Path) -> Result<Instrument, LoadError> {
+        let mut samples: HashMap<PathBuf, (Arc<Vec<f32>>, f32)> = HashMap::new();
+        let mut regions = Vec::new();
+        for opcodes in parse_sfz(text) {
+            let get = |name: &str| opcodes.get(name).map(|value| value.as_str());
+            let number = |name: &str| get(name).and_then(|value| value.parse::<f32>().ok());
+            let key = |name: &str| get(name).and_then(parse_key);
+            let sample = match get("sample") {
+                Some(sample) => sample.replace('\\', "/"),
+                None => continue,
+            };
+            let path = dir
+                .join(get("default_path").unwrap_or("").replace('\\', "/"))
+                .join(sample);
+            if !samples.contains_key(&path) {
+                let (data, rate) = read_wav(&path)?;
+                samples.insert(path.clone(), (Arc::new(data), rate));
+            }
+            let (data, rate) = samples[&path].clone();
+
+            let end = number("end")
+                .map(|end| end as usize + 1)
+                .unwrap_or(data.len())
+                .min(data.len());
+            let start = number("offset").map(|start| start as usize).unwrap_or(0).min(end);
+            let loop_mode = get("loop_mode").unwrap_or("no_loop");
+            let looped = match (number("loop_start"), number("loop_end")) {
+                (Some(from), Some(to)) if loop_mode.starts_with("loop_") => {
+                    Some((from as usize, (to as usize + 1).min(end)))
+                }
+                _ => None,
+            }
+            .filter(|&(from, to)| from < to);
+            let center = key("pitch_keycenter").or_else(|| key("key")).unwrap_or(60) as f32;
+            let tune = number("transpose").unwrap_or(0.0) + number("tune").unwrap_or(0.0) / 100.0;
+            let keys = match key("key") {
+                Some(key) => (key, key),
+                None => (key("lokey").unwrap_or(0), key("hikey").unwrap_or(127)),
+            };
+            regions.push(Region {
+                data,
+                start,
+                end,
+                looped,
+                rate,
+                root: center - tune,
+                keys,
+                velocities: (key("lovel").unwrap_or(0), key("hivel").unwrap_or(127)),
+                gain: 10f32.powf(number("volume").unwrap_or(0.0) / 20.0),
+                one_shot: loop_mode == "one_shot",
+            });
+        }
+        if regions.is_empty() {
+            return format_err("no regions with samples");
+        }
+        Ok(Instrument { regions })
+    }
+}
+
+struct Voice {
+    region: usize,
+    key: u8,
+    /// Position in the sample, in samples at its own rate.
+    position: f64,
+    /// The pitch of the key in the tuning the note was played with.
+    frequency: f32,
+    gain: f32,
+    released: bool,
+    /// Level of the release fade.
+    level: f32,
+}
+
+/// The instrument and its voices, shared between the port tasks.
+struct Player {
+    instrument: Arc<Instrument>,
+    /// Path and preset of the most recently requested instrument, if any.
+    source: Option<(String, usize)>,
+    voices: Vec<Voice>,
+}
+
+impl Player {
+    fn new() -> Player {
+        Player {
+            instrument: Arc::new(Instrument::default()),
+            source: None,
+            voices: Vec::new(),
+        }
+    }
+
+    fn note(&mut self, note: Note, tuning: &Tuning) {
+        if note.velocity == 0 {
+            for voice in &mut self.voices {
+                if voice.key == note.key {
+                    voice.released = true;
+                }
+            }
+            return;
+        }
+        let frequency = match tuning.frequency(note.key as i32) {
+            Some(frequency) => frequency,
+            None => return,
+        };
+        let velocity = note.velocity as f32 / 127.0;
+        for (idx, region) in self.instrument.regions_for(note) {
+            if self.voices.len() >= MAX_VOICES {
+                self.voices.remove(0);
+            }
+            self.voices.push(Voice {
+                region: idx,
+                key: note.key,
+                position: region.start as f64,
+                frequency,
+                gain: region.gain * velocity * velocity,
+                released: false,
+                level: 1.0,
+            });
+        }
+    }
+
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        frame.data.fill(0.0);
+        let fade = 1.0 / (RELEASE * frame.rate);
+        let instrument = self.instrument.clone();
+        for voice in &mut self.voices {
+            let region = match instrument.regions.get(voice.region) {
+                Some(region) => region,
+                None => {
+                    voice.level = 0.0;
+                    continue;
+                }
+            };
+            let root = 440.0 * 2f64.powf((region.root as f64 - 69.0) / 12.0);
+            let step = voice.frequency as f64 / root * region.rate as f64 / frame.rate as f64;
+            for mut samples in frame.data.axis_iter_mut(Axis(0)) {
+                let idx = voice.position as usize;
+                if idx >= region.end || voice.level <= 0.0 {
+                    voice.level = 0.0;
+                    break;
+                }
+                let frac = (voice.position - idx as f64) as f32;
+                let next = if idx + 1 < region.end {
+                    region.data[idx + 1]
+                } else {
+                    0.0
+                };
+                let value = (region.data[idx] * (1.0 - frac) + next * frac) * voice.gain * voice.level;
+                for sample in samples.iter_mut() {
+                    *sample += value;
+                }
+                voice.position += step;
+                if let Some((from, to)) = region.looped {
+                    if !voice.released && voice.position >= to as f64 {
+                        voice.position -= (to - from) as f64;
+                    }
+                }
+                if voice.released && !region.one_shot {
+                    voice.level -= fade;
+                }
+            }
+        }
+        self.voices.retain(|voice| voice.level > 0.0);
+        frame
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Load(String, usize),
+}
+
+pub struct Sampler {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    notes_port: Arc<flow::Port<Note, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    player: Arc<Mutex<Player>>,
+}
+
+impl Module for Sampler {
+    fn new(ifc: Arc<flow::Interface>) -> Sampler {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Sampler {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            notes_port: ifc.get_or_create_port("Notes".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            player: Arc::new(Mutex::new(Player::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Sampler"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let player = self.player.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Load(path, preset) => {
+                        player.lock().unwrap().source = Some((path.clone(), preset));
+                        // reading a large soundfont takes a while
+                        let player = player.clone();
+                        thread::spawn(move || match Instrument::load(&path, preset) {
+                            Ok(instrument) => {
+                                let mut player = player.lock().unwrap();
+                                player.voices.clear();
+                                player.instrument = Arc::new(instrument);
+                            }
+                            Err(e) => println!("sampler load {} err: {}", path, e),
+                        });
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let player = self.player.clone();
+        let graph = self.ifc.graph();
+        util::start_sink(
+            self.notes_port.clone(),
+            move |note: Note| player.lock().unwrap().note(note, &graph.tuning()),
+            self.breaker.clone(),
+            &exec,
+        );
+        let player = self.player.clone();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame { player.lock().unwrap().process(frame) },
+            self.ifc.clone(),
+            self.clock_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        match self.player.lock().unwrap().source {
+            Some((ref path, preset)) => json!({ "path": path, "preset": preset }),
+            None => serde_json::Value::Null,
+        }
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        let path = state["path"].as_str();
+        let preset = state["preset"].as_u64().unwrap_or(0) as usize;
+        if let (Some(path), Some(cmd_tx)) = (path, self.cmd_tx.as_ref()) {
+            // loaded once the module starts, and shown in the GUI meanwhile
+            self.player.lock().unwrap().source = Some((path.into(), preset));
+            cmd_tx
+                .unbounded_send(UserCommand::Load(path.into(), preset))
+                .unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_sampler() {
+    use crate::module::tuning::Scale;
+    use ndarray::Array2;
+
+    let ramp: Vec<f32> = (0..100).map(|i| i as f32 / 100.0).collect();
+    let mut region = Region::new(ramp, 1000.0, 60.0);
+    region.keys = (48, 72);
+    let mut player = Player::new();
+    player.instrument = Arc::new(Instrument {
+        regions: vec![region.clone()],
+    });
+    let equal = Tuning::equal();
+    let frame = || Frame {
+        rate: 1000.0,
+        time: None,
+        data: Array2::zeros((10, 2)),
+        meta: None,
+    };
+
+    // at the root key, the sample plays as it is
+    player.note(
+        Note {
+            key: 60,
+            velocity: 127,
+        },
+        &equal,
+    );
+    let out = player.process(frame());
+    assert!((out.data[[3, 0]] - 0.03).abs() < 1e-6);
+    assert_eq!(out.data[[3, 0]], out.data[[3, 1]]);
+    // an octave up, at twice the speed, and outside the region nothing
+    player.voices.clear();
+    player.note(
+        Note {
+            key: 72,
+            velocity: 127,
+        },
+        &equal,
+    );
+    player.note(
+        Note {
+            key: 73,
+            velocity: 127,
+        },
+        &equal,
+    );
+    assert_eq!(player.voices.len(), 1);
+    let out = player.process(frame());
+    assert!((out.data[[3, 0]] - 0.06).abs() < 1e-6);
+
+    // in quarter tones, with key 69 still at 440 Hz, key 72 is 21 quarter tones above the root
+    player.voices.clear();
+    let quarter_tones = Tuning::new(Scale::equal(24, 1200.0));
+    player.note(
+        Note {
+            key: 72,
+            velocity: 127,
+        },
+        &quarter_tones,
+    );
+    let out = player.process(frame());
+    assert!((out.data[[3, 0]] - 0.03 * 2f32.powf(21.0 / 24.0)).abs() < 1e-6);
+
+    // released voices fade out, and finished ones are dropped
+    player.note(Note { key: 72, velocity: 0 }, &equal);
+    for _ in 0..6 {
+        player.process(frame());
+    }
+    assert!(player.voices.is_empty());
+
+    // looped regions keep playing while held
+    player.instrument = Arc::new(Instrument {
+        regions: vec![Region {
+            looped: Some((50, 100)),
+            ..region
+        }],
+    });
+    player.note(
+        Note {
+            key: 60,
+            velocity: 127,
+        },
+        &equal,
+    );
+    for _ in 0..30 {
+        player.process(frame());
+    }
+    assert_eq!(player.voices.len(), 1);
+    assert!(player.voices[0].position < 100.0);
+
+    assert_eq!(parse_key("60"), Some(60));
+    assert_eq!(parse_key("c4"), Some(60));
+    assert_eq!(parse_key("C#4"), Some(61));
+    assert_eq!(parse_key("bb3"), Some(58));
+    assert_eq!(parse_key("c-1"), Some(0));
+    assert_eq!(parse_key("h4"), None);
+
+    let regions = parse_sfz(
+        "<control> default_path=Samples\\ // where they are\n\
+         <group> lovel=64 volume=-6\n\
+         <region> sample=piano c4.wav key=c4\n\
+         <region>sample=piano d4.wav lokey=61 hikey=63 pitch_keycenter=62 volume=0\n\
+         <group> <region> sample=soft.wav",
+    );
+    assert_eq!(regions.len(), 3);
+    assert_eq!(regions[0]["sample"], "piano c4.wav");
+    assert_eq!(regions[0]["default_path"], "Samples\\");
+    assert_eq!(regions[0]["lovel"], "64");
+    assert_eq!(regions[1]["volume"], "0");
+    assert_eq!(regions[1]["hikey"], "63");
+    assert!(!regions[2].contains_key("lovel"));
+}
+
+#[test]
+fn test_sf2() {
+    use ndarray::Array2;
+
+    fn le(value: u32, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (value >> (8 * i)) as u8).collect()
+    }
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend(le(body.len() as u32, 4));
+        data.extend_from_slice(body);
+        data
+    }
+    fn list(kind: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = kind.to_vec();
+        for chunk in chunks {
+            body.extend_from_slice(chunk);
+        }
+        chunk(b"LIST", &body)
+    }
+    fn header(name_len: usize, fields: &[u16]) -> Vec<u8> {
+        let mut data = vec![0; name_len];
+        for field in fields {
+            data.extend(le(*field as u32, 2));
+        }
+        data
+    }
+    fn gens(gens: &[(u16, u16)]) -> Vec<u8> {
+        header(
+            0,
+            &gens
+                .iter()
+                .flat_map(|&(op, amount)| vec![op, amount])
+                .collect::<Vec<_>>(),
+        )
+    }
+    let samples: Vec<u8> = (0..64).flat_map(|i| le(i * 256, 2)).collect();
+    let mut shdr = vec![0; 20];
+    for field in &[0u32, 64, 16, 48, 22050] {
+        shdr.extend(le(*field, 4));
+    }
+    shdr.extend_from_slice(&[69, 0, 0, 0, 1, 0]);
+    shdr.extend_from_slice(&[0; 46]);
+    // preset and instrument headers, each with 6 or 4 more bytes of fields in a preset, then
+    // terminated by a record pointing past the last bag
+    let mut phdr = header(20, &[0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    phdr.extend(header(20, &[0, 0, 1, 0, 0, 0, 0, 0, 0]));
+    let mut inst = header(20, &[0]);
+    inst.extend(header(20, &[2]));
+    let sfbk = [
+        list(b"INFO", &[chunk(b"ifil", &[2, 0, 1, 0])]),
+        list(b"sdta", &[chunk(b"smpl", &samples)]),
+        list(
+            b"pdta",
+            &[
+                chunk(b"phdr", &phdr),
+                chunk(b"pbag", &header(0, &[0, 0, 1, 0])),
+                chunk(b"pgen", &gens(&[(GEN_INSTRUMENT, 0)])),
+                chunk(b"inst", &inst),
+                chunk(b"ibag", &header(0, &[0, 0, 1, 0, 4, 0])),
+                chunk(
+                    b"igen",
+                    &gens(&[
+                        (GEN_VEL_RANGE, 100 << 8),
+                        (GEN_KEY_RANGE, 60 | 80 << 8),
+                        (GEN_SAMPLE_MODES, 1),
+                        (GEN_SAMPLE_ID, 0),
+                    ]),
+                ),
+                chunk(b"shdr", &shdr),
+            ],
+        ),
+    ]
+    .concat();
+    let mut data = chunk(b"RIFF", &[&b"sfbk"[..], &sfbk[..]].concat());
+    let instrument = Instrument::from_sf2(&data, 0).unwrap();
+    assert_eq!(instrument.regions.len(), 1);
+    let region = &instrument.regions[0];
+    assert_eq!((region.start, region.end, region.looped), (0, 64, Some((16, 48))));
+    assert_eq!(region.rate, 22050.0);
+    assert_eq!(region.root, 69.0);
+    // the global zone's velocity range applies to the zone with the sample
+    assert_eq!((region.keys, region.velocities), ((60, 80), (0, 100)));
+    assert!((region.data[2] - 2.0 / 128.0).abs() < 1e-6);
+
+    assert!(Instrument::from_sf2(&data, 1).is_err());
+
+    // a loop starting past the end of the sample is left out, instead of looping backwards
+    let loop_at = data.len() - shdr.len() + 28;
+    data[loop_at..loop_at + 8].copy_from_slice(&[le(70, 4), le(80, 4)].concat());
+    let instrument = Instrument::from_sf2(&data, 0).unwrap();
+    assert_eq!(instrument.regions[0].looped, None);
+    let mut player = Player::new();
+    player.instrument = Arc::new(instrument);
+    player.note(
+        Note {
+            key: 69,
+            velocity: 100,
+        },
+        &Tuning::equal(),
+    );
+    for _ in 0..10 {
+        player.process(Frame {
+            rate: 22050.0,
+            time: None,
+            data: Array2::zeros((10, 1)),
+            meta: None,
+        });
+    }
+    assert!(player.voices.is_empty());
+
+    data.truncate(100);
+    assert!(Instrument::from_sf2(&data, 0).is_err());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct SamplerGui {
+    bounds: Box3,
+    path_box: TextBox,
+    load_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Sampler {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let source = match self.player.lock().unwrap().source {
+            Some((ref path, preset)) => format!("{} {}", path, preset),
+            None => "instrument.sf2 0".into(),
+        };
+        Box::new(SamplerGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            path_box: TextBox::new(ctx.clone(), source, row(0.0)),
+            load_button: Button::new(ctx.clone(), "Load".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for SamplerGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.path_box.render(device, ctx);
+        self.load_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.path_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.load_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let mut words = self.path_box.content().split_whitespace();
+                let path = words.next().map(String::from);
+                let preset = words.next().map(|w| w.parse().ok()).unwrap_or(Some(0));
+                match (path, preset) {
+                    (Some(path), Some(preset)) => {
+                        self.load_button.set_label(format!("Loaded {}", path));
+                        self.cmd_tx
+                            .unbounded_send(UserCommand::Load(path, preset))
+                            .unwrap();
+                    }
+                    _ => self.load_button.set_label("Invalid: path [preset]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Running the block scheduler of `flow_synth_core::scheduler` on a JACK audio thread.
+//!
+//! `BlockAudioIO` is an audio interface like `AudioIO`, but instead of sending frames to the tasks
+//! of the modules feeding its `Input`, it runs their blocks directly with a `BlockScheduler`, once
+//! per buffer.
+
+use futures::task::Spawn;
+
+use jack::*;
+
+use crate::future_ext::Breaker;
+use crate::module::audio_io::{Frame, Xruns};
+use crate::module::{flow, Module};
+
+pub use flow_synth_core::scheduler::*;
+
+use ndarray::{Array, Axis};
+
+use std::sync::Arc;
+
+
+/// Audio interface that runs the block modules feeding it on the audio thread.
+pub struct BlockAudioIO {
+    ifc: Arc<flow::Interface>,
+    graph: Arc<flow::Graph>,
+    client: Option<AsyncClient<Xruns, BlockProcessor>>,
+    breaker: Breaker,
+}
+
+impl Module for BlockAudioIO {
+    fn new(ifc: Arc<flow::Interface>) -> BlockAudioIO {
+        // the ports only describe the topology, frames never travel over them
+        ifc.get_or_create_port::<Frame, ()>("Input".into());
+        ifc.get_or_create_port::<(), Frame>("Output".into());
+        let graph = ifc.graph();
+        BlockAudioIO {
+            ifc,
+            graph,
+            client: None,
+            breaker: Breaker::new(),
+        }
+    }
+    fn name() -> &'static str {
+        "BlockAudioIO"
+    }
+    fn start<Ex: Spawn>(&mut self, _exec: Ex) {
+        let (client, _status) = match Client::new("flow-synth-block", ClientOptions::NO_START_SERVER) {
+            Ok(client) => client,
+            Err(e) => {
+                println!("block audio err: {:?}", e);
+                return;
+            }
+        };
+        let inputs = (0..2)
+            .map(|i| {
+                client
+                    .register_port(&format!("in-{}", i), AudioIn::default())
+                    .unwrap()
+            })
+            .collect();
+        let outputs = (0..2)
+            .map(|i| {
+                client
+                    .register_port(&format!("out-{}", i), AudioOut::default())
+                    .unwrap()
+            })
+            .collect();
+        let processor = BlockProcessor {
+            inputs,
+            outputs,
+            scheduler: BlockScheduler::new(self.graph.clone(), self.ifc.id()),
+            breaker: self.breaker.clone(),
+            time: 0,
+        };
+        self.client = AsyncClient::new(client, Xruns(self.graph.clone()), processor).ok();
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.client = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+struct BlockProcessor {
+    inputs: Vec<Port<AudioIn>>,
+    outputs: Vec<Port<AudioOut>>,
+    scheduler: BlockScheduler,
+    breaker: Breaker,
+    time: u64,
+}
+
+impl ProcessHandler for BlockProcessor {
+    fn process(&mut self, client: &Client, ps: &ProcessScope) -> Control {
+        let capture = Frame {
+            rate: client.sample_rate() as f32,
+            time: Some(self.time),
+            data: Array::from_iter(self.inputs.iter().flat_map(|input| input.as_slice(ps).to_vec()))
+                .into_shape((self.inputs.len(), client.buffer_size() as usize))
+                .unwrap()
+                .reversed_axes(),
+            meta: None,
+        };
+        self.time += ps.n_frames() as u64;
+
+        let frame = self.scheduler.run(&capture);
+        for (output, buffer) in self.outputs.iter_mut().zip(frame.data.axis_iter(Axis(1))) {
+            for (sample_out, sample) in output.as_mut_slice(ps).iter_mut().zip(buffer.iter()) {
+                *sample_out = *sample;
+            }
+        }
+        self.scheduler.graph().pool().recycle(frame);
+
+        if self.breaker.test() {
+            Control::Quit
+        } else {
+            Control::Continue
+        }
+    }
+}
+
+#[test]
+fn test_block_scheduler() {
+    use crate::module::mix::{Gain, Mixer};
+    use crate::module::process::{Process, Processor};
+    use ndarray::Array2;
+
+    let graph = flow::Graph::new();
+    let host = graph.add_node();
+    let host_in = host.get_or_create_port::<Frame, ()>("Input".into());
+    let host_out = host.get_or_create_port::<(), Frame>("Output".into());
+    let gain_ifc = graph.add_node();
+    let mixer_ifc = graph.add_node();
+    let gain = Processor::<Gain>::new(gain_ifc.clone());
+    let _mixer = Processor::<Mixer>::new(mixer_ifc.clone());
+    gain.process().lock().unwrap().set_param(0, 0.5);
+
+    // capture -> gain -> mixer -> host, with the mixer's other inputs left silent
+    let port = |ifc: &flow::Interface, name: &str| ifc.find_port::<Frame, ()>(name).unwrap();
+    let out = |ifc: &flow::Interface, name: &str| ifc.find_port::<(), Frame>(name).unwrap();
+    host_out.connect(&port(&gain_ifc, "Input")).unwrap();
+    out(&gain_ifc, "Output")
+        .connect(&port(&mixer_ifc, "In 1"))
+        .unwrap();
+    out(&mixer_ifc, "Output").connect(&host_in).unwrap();
+
+    // without crossfades, so every change shows up in the next buffer
+    for node in graph.nodes() {
+        for port in node.ports() {
+            port.set_meta(flow::PortMeta {
+                ramp: None,
+                ..port.meta()
+            });
+        }
+    }
+
+    let mut scheduler = BlockScheduler::new(graph.clone(), host.id());
+    let capture = Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 2), 1.0),
+        meta: None,
+    };
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.5));
+
+    // once warmed up, buffers are recycled rather than allocated
+    let pool = graph.pool();
+    pool.recycle(scheduler.run(&capture));
+    let allocated = pool.stats().allocated;
+    pool.recycle(scheduler.run(&capture));
+    assert_eq!(pool.stats().allocated, allocated);
+
+    // bypassing the gain passes the capture through, muting the mixer silences it
+    let gain_node = graph.node(gain_ifc.id()).unwrap();
+    gain_node.set_bypassed(true);
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 1.0));
+    graph.node(mixer_ifc.id()).unwrap().set_muted(true);
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.0));
+    graph.node(mixer_ifc.id()).unwrap().set_muted(false);
+    gain_node.set_bypassed(false);
+
+    // stopped graphs are silent until started again
+    graph.stop();
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.0));
+    graph.start();
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.5));
+
+    // metadata set on the capture survives the chain
+    let mut tagged = capture.clone();
+    tagged.set_tag("beat", json!(1));
+    assert_eq!(scheduler.run(&tagged).tag("beat"), Some(&json!(1)));
+
+    // recompiles after the graph changes
+    out(&gain_ifc, "Output").disconnect().unwrap();
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.0));
+}
+
+#[test]
+fn test_lazy() {
+    use crate::module::mix::Gain;
+    use crate::module::process::Processor;
+    use ndarray::Array2;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    struct Counter(Arc<AtomicUsize>);
+    impl Block for Counter {
+        fn process(&mut self, _inputs: &[Frame], _outputs: &mut [Frame]) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // capture -> counter -> gain -> host
+    let graph = flow::Graph::new();
+    let host = graph.add_node();
+    let host_in = host.get_or_create_port::<Frame, ()>("Input".into());
+    let host_out = host.get_or_create_port::<(), Frame>("Output".into());
+    let counter_ifc = graph.add_node();
+    let counter_in = counter_ifc.get_or_create_port::<Frame, ()>("Input".into());
+    let counter_out = counter_ifc.get_or_create_port::<(), Frame>("Output".into());
+    let count = Arc::new(AtomicUsize::new(0));
+    counter_ifc.set_block(BlockNode {
+        inputs: vec![counter_in.id()],
+        outputs: vec![counter_out.id()],
+        block: Arc::new(Mutex::new(Counter(count.clone()))),
+    });
+    let gain_ifc = graph.add_node();
+    let _gain = Processor::<Gain>::new(gain_ifc.clone());
+    host_out.connect(&counter_in).unwrap();
+    counter_out
+        .connect(&gain_ifc.find_port("Input").unwrap())
+        .unwrap();
+    gain_ifc
+        .find_port::<(), Frame>("Output")
+        .unwrap()
+        .connect(&host_in)
+        .unwrap();
+
+    let mut scheduler = BlockScheduler::new(graph.clone(), host.id());
+    let capture = Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 2), 1.0),
+        meta: None,
+    };
+    graph.set_lazy(Some(2));
+    scheduler.run(&capture);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // nobody hears the counter through a muted gain
+    let gain_node = graph.node(gain_ifc.id()).unwrap();
+    gain_node.set_muted(true);
+    scheduler.run(&capture);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // waking up runs the pre-roll first
+    gain_node.set_muted(false);
+    scheduler.run(&capture);
+    assert_eq!(count.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn test_control_rate() {
+    use crate::module::mix::Gain;
+    use crate::module::process::Processor;
+    use ndarray::Array2;
+    use std::sync::Mutex;
+
+    /// Outputs how many times it ran.
+    struct Steps(f32);
+    impl Block for Steps {
+        fn process(&mut self, _inputs: &[Frame], outputs: &mut [Frame]) {
+            self.0 += 1.0;
+            outputs[0].data.fill(self.0);
+        }
+    }
+
+    // capture -> steps, at control rate -> gain -> host
+    let graph = flow::Graph::new();
+    graph.set_control_division(2);
+    let host = graph.add_node();
+    let host_in = host.get_or_create_port::<Frame, ()>("Input".into());
+    let host_out = host.get_or_create_port::<(), Frame>("Output".into());
+    let steps_ifc = graph.add_node();
+    let steps_in = steps_ifc.get_or_create_port::<Frame, ()>("Input".into());
+    let steps_out = steps_ifc.get_or_create_port::<(), Frame>("Output".into());
+    steps_out.set_meta(flow::PortMeta {
+        signal: Some(flow::Signal::Control),
+        ..steps_out.meta()
+    });
+    steps_ifc.set_block(BlockNode {
+        inputs: vec![steps_in.id()],
+        outputs: vec![steps_out.id()],
+        block: Arc::new(Mutex::new(Steps(0.0))),
+    });
+    let gain_ifc = graph.add_node();
+    let _gain = Processor::<Gain>::new(gain_ifc.clone());
+    let gain_in = gain_ifc.find_port::<Frame, ()>("Input").unwrap();
+    host_out.connect(&steps_in).unwrap();
+    steps_out.connect(&gain_in).unwrap();
+    gain_ifc
+        .find_port::<(), Frame>("Output")
+        .unwrap()
+        .connect(&host_in)
+        .unwrap();
+
+    for node in graph.nodes() {
+        for port in node.ports() {
+            port.set_meta(flow::PortMeta {
+                ramp: None,
+                ..port.meta()
+            });
+        }
+    }
+
+    let mut scheduler = BlockScheduler::new(graph.clone(), host.id());
+    let capture = Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 1), 1.0),
+        meta: None,
+    };
+    let mut run = || scheduler.run(&capture).data.column(0).to_vec();
+    // the first run starts at its value, then it's held for the division
+    assert_eq!(run(), vec![1.0; 4]);
+    assert_eq!(run(), vec![1.0; 4]);
+    // the next value is reached by the end of the division, smoothly
+    assert_eq!(run(), vec![1.125, 1.25, 1.375, 1.5]);
+    assert_eq!(run(), vec![1.625, 1.75, 1.875, 2.0]);
+    assert_eq!(run(), vec![2.125, 2.25, 2.375, 2.5]);
+}
+
+#[test]
+fn test_parallel() {
+    use crate::module::mix::{Gain, Mixer};
+    use crate::module::process::{Process, Processor};
+    use crate::module::workers::SchedulerConfig;
+    use ndarray::Array2;
+
+    // capture -> two gains, each a branch of its own -> mixer -> host
+    let graph = flow::Graph::new();
+    graph.set_workers(2);
+    let host = graph.add_node();
+    let host_in = host.get_or_create_port::<Frame, ()>("Input".into());
+    let mixer_ifc = graph.add_node();
+    let _mixer = Processor::<Mixer>::new(mixer_ifc.clone());
+    let mut gains = Vec::new();
+    for (idx, &gain) in [0.25, 0.5].iter().enumerate() {
+        let ifc = graph.add_node();
+        let processor = Processor::<Gain>::new(ifc.clone());
+        processor.process().lock().unwrap().set_param(0, gain);
+        // an output connects to a single input, so each gain reads the capture from one of its own
+        let host_out = host.get_or_create_port::<(), Frame>(format!("Output {}", idx + 1));
+        host_out.connect(&ifc.find_port("Input").unwrap()).unwrap();
+        ifc.find_port::<(), Frame>("Output")
+            .unwrap()
+            .connect(&mixer_ifc.find_port(&format!("In {}", idx + 1)).unwrap())
+            .unwrap();
+        gains.push(processor);
+    }
+    mixer_ifc
+        .find_port::<(), Frame>("Output")
+        .unwrap()
+        .connect(&host_in)
+        .unwrap();
+    for node in graph.nodes() {
+        for port in node.ports() {
+            port.set_meta(flow::PortMeta {
+                ramp: None,
+                ..port.meta()
+            });
+        }
+    }
+
+    let mut scheduler = BlockScheduler::new(graph.clone(), host.id());
+    let capture = Frame {
+        rate: 48000.0,
+        time: None,
+        data: Array2::from_elem((4, 2), 1.0),
+        meta: None,
+    };
+    // the mixer hears the gains a buffer late
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.0));
+    assert_eq!(scheduler.lanes(), 2);
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.75));
+
+    // pinning the worker restarts it, without a gap
+    graph.set_scheduler_config(SchedulerConfig::new().with_workers(2).with_worker_cores(vec![0]));
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 0.75));
+    assert_eq!(scheduler.workers().count(), 1);
+
+    // back on one thread, nothing is held
+    graph.set_workers(1);
+    gains[0].process().lock().unwrap().set_param(0, 0.5);
+    assert!(scheduler.run(&capture).data.iter().all(|&x| x == 1.0));
+    assert_eq!(scheduler.lanes(), 1);
+}
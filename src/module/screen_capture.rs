@@ -0,0 +1,394 @@
+//! Screen capture into the frame pipeline, so other visual software can be processed further.
+//!
+//! Capturing is left to ffmpeg, which already knows each platform's screen grabbing API. The
+//! module runs it on a region of the screen at the configured rate, asking for grayscale
+//! pictures in a YUV4MPEG2 stream, and sends every picture on `Output` as a frame with one row
+//! per line of pixels and values from 0.0 to 1.0, the same shape the visual modules draw.
+//! Pictures arriving while nothing is reading `Output` are dropped.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Array2;
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CaptureConfig {
+    /// The top left corner of the region, in pixels.
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Pictures per second.
+    pub rate: f32,
+}
+
+impl CaptureConfig {
+    /// Parse a config of the form `x y width height [rate]`.
+    pub fn parse(s: &str) -> Option<CaptureConfig> {
+        let mut words = s.split_whitespace();
+        let mut number = || -> Option<usize> { words.next()?.parse().ok() };
+        let (x, y, width, height) = (number()?, number()?, number()?, number()?);
+        let rate = words.next().map(|w| w.parse().ok()).unwrap_or(Some(30.0))?;
+        if width == 0 || height == 0 || !(rate > 0.0) {
+            return None;
+        }
+        Some(CaptureConfig {
+            x,
+            y,
+            width,
+            height,
+            rate,
+        })
+    }
+}
+
+/// ffmpeg's arguments for grabbing the region on this platform.
+fn capture_args(config: &CaptureConfig) -> Vec<String> {
+    let (x, y) = (config.x.to_string(), config.y.to_string());
+    let size = format!("{}x{}", config.width, config.height);
+    let rate = config.rate.to_string();
+    // avfoundation grabs the whole screen, the region is cropped out afterwards
+    let crop = format!("crop={}:{}:{}:{}", config.width, config.height, x, y);
+    let display = ::std::env::var("DISPLAY").unwrap_or(":0.0".into());
+    let display = format!("{}+{},{}", display, x, y);
+    let mut args = if cfg!(target_os = "windows") {
+        vec![
+            "-f",
+            "gdigrab",
+            "-framerate",
+            &rate,
+            "-offset_x",
+            &x,
+            "-offset_y",
+            &y,
+            "-video_size",
+            &size,
+            "-i",
+            "desktop",
+        ]
+    } else if cfg!(target_os = "macos") {
+        vec![
+            "-f",
+            "avfoundation",
+            "-framerate",
+            &rate,
+            "-i",
+            "Capture screen 0:none",
+            "-vf",
+            &crop,
+        ]
+    } else {
+        vec![
+            "-f",
+            "x11grab",
+            "-framerate",
+            &rate,
+            "-video_size",
+            &size,
+            "-i",
+            &display,
+        ]
+    };
+    // grayscale is all the frames carry
+    args.extend(&["-loglevel", "error", "-pix_fmt", "gray"]);
+    args.extend(&["-f", "yuv4mpegpipe", "-"]);
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Reads the pictures of a YUV4MPEG2 stream as frames, keeping only their brightness.
+pub struct PictureReader<R> {
+    reader: R,
+    width: usize,
+    height: usize,
+    rate: f32,
+    /// Bytes of color following the brightness of each picture.
+    chroma: usize,
+    line: String,
+}
+
+impl<R: BufRead> PictureReader<R> {
+    /// Read the stream header.
+    pub fn new(mut reader: R) -> io::Result<PictureReader<R>> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut words = line.split_whitespace();
+        if words.next() != Some("YUV4MPEG2") {
+            return Err(invalid("not a YUV4MPEG2 stream"));
+        }
+        let (mut width, mut height, mut rate, mut colors) = (0, 0, 0.0, "420");
+        for word in words {
+            let mut chars = word.chars();
+            let tag = chars.next();
+            let value = chars.as_str();
+            match tag {
+                Some('W') => width = value.parse().map_err(|_| invalid("bad width"))?,
+                Some('H') => height = value.parse().map_err(|_| invalid("bad height"))?,
+                Some('F') => {
+                    let mut parts = value.split(':').map(|part| part.parse::<f32>());
+                    rate = match (parts.next(), parts.next()) {
+                        (Some(Ok(num)), Some(Ok(den))) if den > 0.0 => num / den,
+                        _ => return Err(invalid("bad rate")),
+                    };
+                }
+                Some('C') => colors = value,
+                _ => {}
+            }
+        }
+        // chroma planes are subsampled by rounding up
+        let (half_width, half_height) = ((width + 1) / 2, (height + 1) / 2);
+        let chroma = if colors == "mono" {
+            0
+        } else if colors.starts_with("420") {
+            2 * half_width * half_height
+        } else if colors.starts_with("422") {
+            2 * half_width * height
+        } else if colors.starts_with("444") {
+            2 * width * height
+        } else {
+            return Err(invalid("unsupported colors"));
+        };
+        Ok(PictureReader {
+            reader,
+            width,
+            height,
+            rate,
+            chroma,
+            line,
+        })
+    }
+    /// Read the next picture, or `None` at the end of the stream.
+    pub fn next(&mut self) -> io::Result<Option<Frame>> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line)? == 0 {
+            return Ok(None);
+        }
+        if !self.line.starts_with("FRAME") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing FRAME"));
+        }
+        let mut luma = vec![0u8; self.width * self.height];
+        self.reader.read_exact(&mut luma)?;
+        io::copy(&mut (&mut self.reader).take(self.chroma as u64), &mut io::sink())?;
+        Ok(Some(Frame {
+            rate: self.rate,
+            time: None,
+            data: Array2::from_shape_vec((self.height, self.width), luma)
+                .unwrap()
+                .mapv(|x| x as f32 / 255.0),
+            meta: None,
+        }))
+    }
+}
+
+fn read_pictures<R: Read>(stdout: R, mut tx: mpsc::Sender<Frame>, session: Breaker) {
+    let mut reader = match PictureReader::new(BufReader::new(stdout)) {
+        Ok(reader) => reader,
+        Err(e) => {
+            println!("screen capture err: {:?}", e);
+            return;
+        }
+    };
+    while !session.test() {
+        match reader.next() {
+            Ok(Some(frame)) => {
+                // drop pictures if nobody is consuming them
+                let _ = tx.try_send(frame);
+            }
+            Ok(None) => return,
+            Err(e) => {
+                println!("screen capture err: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Capture(CaptureConfig),
+}
+
+pub struct ScreenCapture {
+    ifc: Arc<flow::Interface>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    session: Arc<Mutex<Option<Breaker>>>,
+    child: Arc<Mutex<Option<process::Child>>>,
+}
+
+impl ScreenCapture {
+    /// Stop capturing, killing ffmpeg.
+    fn end_session(&self) {
+        self.session.lock().unwrap().take().map(|session| session.brake());
+        self.child.lock().unwrap().take().map(|mut child| child.kill());
+    }
+}
+
+impl Drop for ScreenCapture {
+    fn drop(&mut self) {
+        self.end_session();
+    }
+}
+
+impl Module for ScreenCapture {
+    fn new(ifc: Arc<flow::Interface>) -> ScreenCapture {
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        ScreenCapture {
+            ifc,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            session: Arc::default(),
+            child: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "Screen Capture"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (frame_tx, frame_rx) = mpsc::channel(2);
+        let session_handle = self.session.clone();
+        let child_handle = self.child.clone();
+        let out_port = self.out_port.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Capture(config) => {
+                        // stop the previous capture before starting a new one
+                        let session = Breaker::new();
+                        let mut session_handle = session_handle.lock().unwrap();
+                        session_handle.take().map(|old| old.brake());
+                        *session_handle = Some(session.clone());
+                        let mut child_handle = child_handle.lock().unwrap();
+                        child_handle.take().map(|mut child| child.kill());
+
+                        out_port.set_meta(flow::PortMeta {
+                            channels: Some(config.width),
+                            ..out_port.meta()
+                        });
+                        let child = process::Command::new("ffmpeg")
+                            .args(capture_args(&config))
+                            .stdin(process::Stdio::null())
+                            .stdout(process::Stdio::piped())
+                            .spawn();
+                        match child {
+                            Ok(mut child) => {
+                                let stdout = child.stdout.take().unwrap();
+                                let frame_tx = frame_tx.clone();
+                                thread::spawn(move || read_pictures(stdout, frame_tx, session));
+                                *child_handle = Some(child);
+                            }
+                            Err(e) => println!("screen capture ffmpeg err: {:?}", e),
+                        }
+                    }
+                }
+            }
+        })
+        .unwrap();
+        util::start_source(frame_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.end_session();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_screen_capture() {
+    use std::io::Cursor;
+
+    let config = CaptureConfig::parse("10 20 3 2 25").unwrap();
+    assert_eq!((config.x, config.width, config.rate), (10, 3, 25.0));
+    assert_eq!(CaptureConfig::parse("0 0 640 480").unwrap().rate, 30.0);
+    assert!(CaptureConfig::parse("0 0 0 480").is_none());
+    assert!(capture_args(&config).ends_with(&["yuv4mpegpipe".to_string(), "-".to_string()]));
+
+    // two 3x2 pictures with 4:2:0 color, which is skipped
+    let mut stream = b"YUV4MPEG2 W3 H2 F30000:1001 Ip A1:1 C420jpeg\n".to_vec();
+    for &(luma, chroma) in &[(0u8, 1u8), (255, 2)] {
+        stream.extend(b"FRAME\n");
+        stream.extend(vec![luma; 6]);
+        stream.extend(vec![chroma; 4]);
+    }
+    let mut reader = PictureReader::new(Cursor::new(stream)).unwrap();
+    let frame = reader.next().unwrap().unwrap();
+    assert_eq!(frame.data.dim(), (2, 3));
+    assert!((frame.rate - 29.97).abs() < 0.01);
+    assert!(frame.data.iter().all(|&x| x == 0.0));
+    let frame = reader.next().unwrap().unwrap();
+    assert!(frame.data.iter().all(|&x| x == 1.0));
+    assert!(reader.next().unwrap().is_none());
+    assert!(PictureReader::new(Cursor::new(b"P5\n".to_vec())).is_err());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct ScreenCaptureGui {
+    bounds: Box3,
+    config_box: TextBox,
+    capture_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for ScreenCapture {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(ScreenCaptureGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), "0 0 320 240 30".into(), row(0.0)),
+            capture_button: Button::new(ctx.clone(), "Capture".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for ScreenCaptureGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.capture_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.capture_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match CaptureConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        let label = format!("{}x{} @ {}", config.width, config.height, config.rate);
+                        self.capture_button.set_label(label);
+                        self.cmd_tx.unbounded_send(UserCommand::Capture(config)).unwrap();
+                    }
+                    None => self.capture_button.set_label("Invalid: x y w h [rate]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Serial port bridge, typically to an Arduino or other custom sensor hardware.
+//!
+//! Incoming bytes are split into frames (newline terminated lines, or fixed size binary records)
+//! and sent on `Output`. Frames written to `Input` are sent back to the device.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use serialport::{self, SerialPort};
+
+use crate::future_ext::Breaker;
+use crate::module::{flow, util, Module};
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_LINE: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Frames are terminated by `\n`, with an optional preceding `\r` stripped.
+    Line,
+    /// Every frame is exactly this many bytes.
+    Fixed(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SerialConfig {
+    pub path: String,
+    pub baud_rate: u32,
+    pub framing: Framing,
+}
+
+impl SerialConfig {
+    /// Parse a config of the form `path [baud [line|<frame size>]]`.
+    pub fn parse(s: &str) -> Option<SerialConfig> {
+        let mut words = s.split_whitespace();
+        let path = words.next()?;
+        let baud_rate = words.next().map(|w| w.parse().ok()).unwrap_or(Some(9600))?;
+        let framing = match words.next() {
+            None | Some("line") => Framing::Line,
+            Some(size) => match size.parse() {
+                Ok(0) | Err(_) => return None,
+                Ok(size) => Framing::Fixed(size),
+            },
+        };
+        Some(SerialConfig {
+            path: path.into(),
+            baud_rate,
+            framing,
+        })
+    }
+}
+
+/// Splits a byte stream into frames.
+struct Framer {
+    framing: Framing,
+    buffer: Vec<u8>,
+}
+
+impl Framer {
+    fn new(framing: Framing) -> Framer {
+        Framer {
+            framing,
+            buffer: Vec::new(),
+        }
+    }
+    fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        for &byte in data {
+            match self.framing {
+                Framing::Line if byte == b'\n' => {
+                    let mut line = mem::replace(&mut self.buffer, Vec::new());
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    frames.push(line);
+                }
+                Framing::Line => {
+                    // garbage without newlines shouldn't grow forever
+                    if self.buffer.len() < MAX_LINE {
+                        self.buffer.push(byte);
+                    }
+                }
+                Framing::Fixed(size) => {
+                    self.buffer.push(byte);
+                    if self.buffer.len() == size {
+                        frames.push(mem::replace(&mut self.buffer, Vec::new()));
+                    }
+                }
+            }
+        }
+        frames
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Open(SerialConfig),
+}
+
+type Device = Arc<Mutex<Option<(Box<dyn SerialPort>, Framing)>>>;
+
+pub struct Serial {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Vec<u8>, ()>>,
+    out_port: Arc<flow::Port<(), Vec<u8>>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    session: Arc<Mutex<Option<Breaker>>>,
+    device: Device,
+}
+
+impl Drop for Serial {
+    fn drop(&mut self) {
+        self.session.lock().unwrap().take().map(|session| session.brake());
+    }
+}
+
+impl Module for Serial {
+    fn new(ifc: Arc<flow::Interface>) -> Serial {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Serial {
+            ifc,
+            in_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            session: Arc::default(),
+            device: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "Serial"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (frame_tx, frame_rx) = mpsc::channel(64);
+        let session_handle = self.session.clone();
+        let device_handle = self.device.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Open(config) => {
+                        // stop reading from the previous device before opening a new one
+                        let session = Breaker::new();
+                        let mut session_handle = session_handle.lock().unwrap();
+                        session_handle.take().map(|old| old.brake());
+                        *session_handle = Some(session.clone());
+                        *device_handle.lock().unwrap() = None;
+
+                        match open(&config) {
+                            Ok((reader, writer)) => {
+                                *device_handle.lock().unwrap() = Some((writer, config.framing));
+                                let frame_tx = frame_tx.clone();
+                                let framing = config.framing;
+                                thread::spawn(move || read_frames(reader, framing, frame_tx, session));
+                            }
+                            Err(e) => println!("serial open {} err: {:?}", config.path, e),
+                        }
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let device = self.device.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |mut frame: Vec<u8>| {
+                if let Some((ref mut port, framing)) = *device.lock().unwrap() {
+                    if framing == Framing::Line {
+                        frame.push(b'\n');
+                    }
+                    if let Err(e) = port.write_all(&frame) {
+                        println!("serial write err: {:?}", e);
+                    }
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(frame_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.session.lock().unwrap().take().map(|session| session.brake());
+        *self.device.lock().unwrap() = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Open the device, returning separate handles for reading and writing.
+fn open(config: &SerialConfig) -> io::Result<(Box<dyn SerialPort>, Box<dyn SerialPort>)> {
+    let reader = serialport::new(config.path.as_str(), config.baud_rate)
+        .timeout(READ_TIMEOUT)
+        .open()?;
+    let writer = reader.try_clone()?;
+    Ok((reader, writer))
+}
+
+fn read_frames(
+    mut port: Box<dyn SerialPort>,
+    framing: Framing,
+    mut tx: mpsc::Sender<Vec<u8>>,
+    breaker: Breaker,
+) {
+    let mut framer = Framer::new(framing);
+    let mut buf = [0u8; 256];
+    while !breaker.test() {
+        match port.read(&mut buf) {
+            Ok(n) => {
+                for frame in framer.push(&buf[..n]) {
+                    // drop frames if nobody is consuming them
+                    let _ = tx.try_send(frame);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => {
+                println!("serial read err: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_serial_framing() {
+    let mut framer = Framer::new(Framing::Line);
+    assert_eq!(framer.push(b"12\n3"), vec![b"12".to_vec()]);
+    assert_eq!(framer.push(b"4\r\n\n"), vec![b"34".to_vec(), vec![]]);
+    // a line without a newline is cut off, not kept growing
+    let mut long = vec![b'x'; MAX_LINE + 10];
+    long.push(b'\n');
+    assert_eq!(framer.push(&long), vec![vec![b'x'; MAX_LINE]]);
+
+    let mut framer = Framer::new(Framing::Fixed(3));
+    assert_eq!(framer.push(b"ab"), Vec::<Vec<u8>>::new());
+    assert_eq!(framer.push(b"c\ndef"), vec![b"abc".to_vec(), b"\nde".to_vec()]);
+    assert_eq!(framer.push(b"\r\n"), vec![b"f\r\n".to_vec()]);
+}
+
+#[test]
+fn test_serial_config() {
+    assert_eq!(
+        SerialConfig::parse("/dev/ttyACM0"),
+        Some(SerialConfig {
+            path: "/dev/ttyACM0".into(),
+            baud_rate: 9600,
+            framing: Framing::Line,
+        })
+    );
+    let config = SerialConfig::parse("/dev/ttyUSB0 115200 12").unwrap();
+    assert_eq!((config.baud_rate, config.framing), (115200, Framing::Fixed(12)));
+    assert_eq!(SerialConfig::parse("COM3 57600 line").unwrap().framing, Framing::Line);
+    for malformed in &["", "  ", "COM3 fast", "COM3 -1", "COM3 9600 0", "COM3 9600 lines"] {
+        assert_eq!(SerialConfig::parse(malformed), None, "{:?}", malformed);
+    }
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct SerialGui {
+    bounds: Box3,
+    config_box: TextBox,
+    open_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Serial {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(SerialGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), "/dev/ttyACM0 9600 line".into(), row(0.0)),
+            open_button: Button::new(ctx.clone(), "Open".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for SerialGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.open_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.open_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match SerialConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.open_button.set_label(format!("{} @ {}", config.path, config.baud_rate));
+                        self.cmd_tx.unbounded_send(UserCommand::Open(config)).unwrap();
+                    }
+                    None => self.open_button.set_label("Invalid: path [baud [line|size]]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! A slew limiter turning stepped control values, like MIDI CCs or values from the network, into
+//! smooth ramps so parameters don't zipper.
+//!
+//! Like the generators in `timeline`, `Slew` is clocked by its `Clock` frames and sends a value
+//! per frame while it is moving, and nothing once it has reached its target.
+
+use futures::channel::mpsc;
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use std::sync::{Arc, Mutex};
+
+/// Difference from the target below which an exponential ramp is considered to have arrived.
+const SETTLED: f32 = 1e-6;
+
+/// Seconds covered by `frame`.
+fn duration(frame: &Frame) -> f32 {
+    frame.data.dim().0 as f32 / frame.rate
+}
+
+/// Follows a target value. A linear ramp takes `time` seconds for every step, however large. An
+/// exponential one covers the same fraction of the distance left in equal times, getting within
+/// 1% of the target in `time` seconds.
+pub struct Slewer {
+    /// Seconds to reach the target.
+    pub time: f32,
+    pub exponential: bool,
+    target: f32,
+    /// None until the first target, which is taken as is.
+    value: Option<f32>,
+    /// Change per second of a linear ramp.
+    speed: f32,
+}
+
+impl Slewer {
+    pub fn new() -> Slewer {
+        Slewer {
+            time: 0.05,
+            exponential: false,
+            target: 0.0,
+            value: None,
+            speed: 0.0,
+        }
+    }
+    /// Start moving towards `target`.
+    pub fn set_target(&mut self, target: f32) {
+        let value = *self.value.get_or_insert(target);
+        self.target = target;
+        self.speed = (target - value).abs() / self.time.max(1e-6);
+    }
+    /// Move on by `seconds`, returning the value reached if it was still moving. The target is
+    /// returned once when it is reached.
+    pub fn advance(&mut self, seconds: f32) -> Option<f32> {
+        let value = self.value?;
+        if value == self.target {
+            return None;
+        }
+        let next = if self.time <= 0.0 {
+            self.target
+        } else if self.exponential {
+            // ln(100), so that 1% of the distance is left after `time`
+            let coefficient = 1.0 - (-seconds * 4.6052 / self.time).exp();
+            let next = value + (self.target - value) * coefficient;
+            if (self.target - next).abs() < SETTLED {
+                self.target
+            } else {
+                next
+            }
+        } else {
+            let step = self.speed * seconds;
+            if (self.target - value).abs() <= step {
+                self.target
+            } else {
+                value + step * (self.target - value).signum()
+            }
+        };
+        self.value = Some(next);
+        Some(next)
+    }
+}
+
+/// Ramps to every value on `Input` over `Time` seconds, exponentially with `Shape` above 0.5 and
+/// linearly otherwise.
+pub struct Slew {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    in_port: Arc<flow::Port<f32, ()>>,
+    time_port: Arc<flow::Port<f32, ()>>,
+    shape_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    slewer: Arc<Mutex<Slewer>>,
+}
+
+impl Module for Slew {
+    fn new(ifc: Arc<flow::Interface>) -> Slew {
+        Slew {
+            clock_port: ifc.get_or_create_port("Clock".into()),
+            in_port: ifc.get_or_create_port("Input".into()),
+            time_port: ifc.get_or_create_port("Time".into()),
+            shape_port: ifc.get_or_create_port("Shape".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            slewer: Arc::new(Mutex::new(Slewer::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Slew"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let slewer = self.slewer.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |value: f32| slewer.lock().unwrap().set_target(value),
+            self.breaker.clone(),
+            &exec,
+        );
+        let slewer = self.slewer.clone();
+        util::start_sink(
+            self.time_port.clone(),
+            move |time: f32| slewer.lock().unwrap().time = time,
+            self.breaker.clone(),
+            &exec,
+        );
+        let slewer = self.slewer.clone();
+        util::start_sink(
+            self.shape_port.clone(),
+            move |shape: f32| slewer.lock().unwrap().exponential = shape > 0.5,
+            self.breaker.clone(),
+            &exec,
+        );
+        let (mut value_tx, value_rx) = mpsc::channel(1);
+        let slewer = self.slewer.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                if let Some(value) = slewer.lock().unwrap().advance(duration(&frame)) {
+                    let _ = value_tx.try_send(value);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(value_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_slew() {
+    let mut slewer = Slewer::new();
+    slewer.time = 1.0;
+    assert_eq!(slewer.advance(0.25), None);
+    // the first value is taken as is
+    slewer.set_target(1.0);
+    assert_eq!(slewer.advance(0.25), None);
+    slewer.set_target(3.0);
+    assert_eq!(slewer.advance(0.25), Some(1.5));
+    assert_eq!(slewer.advance(0.5), Some(2.5));
+    assert_eq!(slewer.advance(0.5), Some(3.0));
+    assert_eq!(slewer.advance(0.5), None);
+
+    slewer.exponential = true;
+    slewer.set_target(2.0);
+    let value = slewer.advance(1.0).unwrap();
+    assert!((value - 2.01).abs() < 1e-4);
+    // settles on the target eventually
+    while slewer.advance(1.0).is_some() {}
+    assert_eq!(slewer.value, Some(2.0));
+}
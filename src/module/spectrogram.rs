@@ -0,0 +1,343 @@
+//! Spectrogram rows for visualizations.
+//!
+//! The input is mixed down to mono and cut into overlapping Hann windowed blocks, and each block's
+//! FFT becomes one row of magnitudes, one column per bin from DC to Nyquist, scaled into
+//! `0.0..=1.0` either linearly or in decibels above a floor. Rows are sent as a `Frame` whose
+//! channels are the bins and whose rate is rows per second, so a waterfall display is just the
+//! rows drawn one after another. The output declares its bin count as its channel count, which
+//! keeps it from being patched into an audio input by mistake.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::fft::fft;
+use crate::module::pool::FramePool;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use num::complex::Complex32;
+use serde_json;
+
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpectrogramConfig {
+    /// FFT length in samples, a power of two.
+    pub size: usize,
+    /// Samples between the starts of consecutive rows.
+    pub hop: usize,
+    /// Scale magnitudes in decibels instead of linearly.
+    pub log: bool,
+    /// With `log`, the level shown as 0.0. Full scale is always 1.0.
+    pub floor_db: f32,
+}
+
+impl SpectrogramConfig {
+    /// Parse a config of the form `size hop [log [floor dB] | linear]`.
+    pub fn parse(s: &str) -> Option<SpectrogramConfig> {
+        let mut words = s.split_whitespace();
+        let size: usize = words.next()?.parse().ok()?;
+        let hop: usize = words.next()?.parse().ok()?;
+        if !size.is_power_of_two() || size < 2 || hop == 0 {
+            return None;
+        }
+        let log = match words.next() {
+            None | Some("log") => true,
+            Some("linear") => false,
+            Some(_) => return None,
+        };
+        let floor_db = words.next().map(|w| w.parse().ok()).unwrap_or(Some(-90.0))?;
+        if floor_db >= 0.0 {
+            return None;
+        }
+        Some(SpectrogramConfig {
+            size,
+            hop,
+            log,
+            floor_db,
+        })
+    }
+    /// The number of bins in each row.
+    pub fn bins(&self) -> usize {
+        self.size / 2 + 1
+    }
+    fn describe(&self) -> String {
+        if self.log {
+            format!("{} {} log {}", self.size, self.hop, self.floor_db)
+        } else {
+            format!("{} {} linear", self.size, self.hop)
+        }
+    }
+}
+
+impl Default for SpectrogramConfig {
+    fn default() -> SpectrogramConfig {
+        SpectrogramConfig {
+            size: 1024,
+            hop: 512,
+            log: true,
+            floor_db: -90.0,
+        }
+    }
+}
+
+pub struct Spectrogram {
+    config: SpectrogramConfig,
+    window: Vec<f32>,
+    /// Mono input not yet covered by a complete row.
+    pending: Vec<f32>,
+    /// Reused FFT buffer.
+    spectrum: Vec<Complex32>,
+}
+
+impl Spectrogram {
+    pub fn new(config: SpectrogramConfig) -> Spectrogram {
+        let mut spectrogram = Spectrogram {
+            config,
+            window: Vec::new(),
+            pending: Vec::new(),
+            spectrum: Vec::new(),
+        };
+        spectrogram.set_config(config);
+        spectrogram
+    }
+    pub fn config(&self) -> SpectrogramConfig {
+        self.config
+    }
+    pub fn set_config(&mut self, config: SpectrogramConfig) {
+        self.config = config;
+        let size = config.size;
+        self.window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+            .collect();
+        self.pending.clear();
+    }
+    /// Take in a frame of audio, returning a frame holding the rows it completed, which may be
+    /// none.
+    pub fn process(&mut self, frame: &Frame, pool: &FramePool) -> Frame {
+        let channels = frame.data.dim().1.max(1) as f32;
+        for samples in frame.data.outer_iter() {
+            self.pending.push(samples.iter().sum::<f32>() / channels);
+        }
+        let SpectrogramConfig {
+            size,
+            hop,
+            log,
+            floor_db,
+        } = self.config;
+        let rows = if self.pending.len() >= size {
+            (self.pending.len() - size) / hop + 1
+        } else {
+            0
+        };
+        let rate = frame.rate / hop as f32;
+        let mut out = pool.zeros(rate, frame.time, (rows, self.config.bins()));
+        // a full scale sine peaks at half the window's sum
+        let full_scale = self.window.iter().sum::<f32>() / 2.0;
+        for (row, mut bins) in out.data.outer_iter_mut().enumerate() {
+            let start = row * hop;
+            self.spectrum.clear();
+            self.spectrum.extend(
+                self.pending[start..start + size]
+                    .iter()
+                    .zip(&self.window)
+                    .map(|(&x, &w)| Complex32::new(x * w, 0.0)),
+            );
+            fft(&mut self.spectrum, false);
+            for (bin, value) in bins.iter_mut().zip(&self.spectrum) {
+                let magnitude = value.norm() / full_scale;
+                let level = if log {
+                    let db = 20.0 * magnitude.max(1e-12).log10();
+                    1.0 - db / floor_db
+                } else {
+                    magnitude
+                };
+                *bin = level.max(0.0).min(1.0);
+            }
+        }
+        self.pending.drain(..rows * hop);
+        out
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Configure(SpectrogramConfig),
+}
+
+pub struct SpectrogramModule {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    spectrogram: Arc<Mutex<Spectrogram>>,
+}
+
+/// Declare the row width of `config` on the output.
+fn declare_bins(port: &flow::Port<(), Frame>, config: &SpectrogramConfig) {
+    port.set_meta(flow::PortMeta {
+        channels: Some(config.bins()),
+        ..port.meta()
+    });
+}
+
+impl Module for SpectrogramModule {
+    fn new(ifc: Arc<flow::Interface>) -> SpectrogramModule {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let out_port = ifc.get_or_create_port("Rows".into());
+        let config = SpectrogramConfig::default();
+        declare_bins(&out_port, &config);
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        SpectrogramModule {
+            ifc,
+            in_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            spectrogram: Arc::new(Mutex::new(Spectrogram::new(config))),
+        }
+    }
+    fn name() -> &'static str {
+        "Spectrogram"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let spectrogram = self.spectrogram.clone();
+        let out_port = self.out_port.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Configure(config) => {
+                        declare_bins(&out_port, &config);
+                        spectrogram.lock().unwrap().set_config(config);
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let spectrogram = self.spectrogram.clone();
+        let pool = self.ifc.graph().pool();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                let rows = spectrogram.lock().unwrap().process(&frame, &pool);
+                pool.recycle(frame);
+                rows
+            },
+            self.ifc.clone(),
+            self.in_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.spectrogram.lock().unwrap().config()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(config) => {
+                declare_bins(&self.out_port, &config);
+                self.spectrogram.lock().unwrap().set_config(config);
+            }
+            Err(e) => println!("spectrogram state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_spectrogram() {
+    use ndarray::Array2;
+
+    let pool = FramePool::new();
+    let config = SpectrogramConfig::parse("64 32 linear").unwrap();
+    let mut spectrogram = Spectrogram::new(config);
+    // a sine right on bin 8, in both channels
+    let frame = Frame {
+        rate: 6400.0,
+        time: None,
+        data: Array2::from_shape_fn((100, 2), |(i, _)| (2.0 * PI * 8.0 * i as f32 / 64.0).sin()),
+        meta: None,
+    };
+    let rows = spectrogram.process(&frame, &pool);
+    assert_eq!(rows.data.dim(), (2, 33));
+    assert_eq!(rows.rate, 200.0);
+    for row in rows.data.outer_iter() {
+        assert!((row[8] - 1.0).abs() < 1e-3);
+        assert!(row[20] < 1e-3);
+    }
+    // the rest carries over into the next frame
+    assert_eq!(spectrogram.process(&frame, &pool).data.dim().0, 3);
+
+    let mut spectrogram = Spectrogram::new(SpectrogramConfig::parse("64 64 log -60").unwrap());
+    let rows = spectrogram.process(&frame, &pool);
+    assert!((rows.data[[0, 8]] - 1.0).abs() < 1e-3);
+    assert_eq!(rows.data[[0, 20]], 0.0);
+    assert!(SpectrogramConfig::parse("100 50").is_none());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct SpectrogramGui {
+    bounds: Box3,
+    config_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for SpectrogramModule {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let config = self.spectrogram.lock().unwrap().config();
+        Box::new(SpectrogramGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            config_box: TextBox::new(ctx.clone(), config.describe(), row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for SpectrogramGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.config_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.config_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match SpectrogramConfig::parse(self.config_box.content()) {
+                    Some(config) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::Configure(config)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: size hop [log|linear]".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Passes audio through unchanged while offering copies on a second output, so a stream can be
+//! monitored or used as a side-chain without rerouting the main connection.
+
+use futures::channel::mpsc;
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use std::sync::Arc;
+
+pub struct Tap {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    tap_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+}
+
+impl Module for Tap {
+    fn new(ifc: Arc<flow::Interface>) -> Tap {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        let tap_port = ifc.get_or_create_port("Tap".into());
+        Tap {
+            ifc,
+            in_port,
+            out_port,
+            tap_port,
+            breaker: Breaker::new(),
+        }
+    }
+    fn name() -> &'static str {
+        "Tap"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (mut tap_tx, tap_rx) = mpsc::channel(1);
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame {
+                // the main path sets the pace, a slow tap consumer just misses frames
+                let _ = tap_tx.try_send(frame.clone());
+                frame
+            },
+            self.ifc.clone(),
+            self.in_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(tap_rx, self.tap_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
@@ -0,0 +1,568 @@
+//! `Tensor`s, arrays of any shape, for passing features, model inputs and outputs, and images
+//! between analysis, ML and visual modules without squeezing them into `Frame`s.
+//!
+//! A tensor's data is shared, so it is cheap to clone and send to several ports, and reshaping
+//! only changes the shape. Its values are stored row-major, the last axis varying fastest, as
+//! `f32`, `i32` or `u8`.
+//!
+//! `To Tensor` and `To Frame` convert between frames and two dimensional tensors of a row per
+//! sample and a column per channel. `To Frame` gives its frames the rate on `Rate`, 1.0 until one
+//! arrives, as tensors don't keep time, and accepts one dimensional tensors as a single row.
+//! `Reshape` and `Slice Tensor` apply the operation in their state to every tensor, see `Reshape`
+//! and `Slice` for the forms they take.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::pool::FramePool;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DType {
+    F32,
+    I32,
+    U8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TensorData {
+    F32(Arc<Vec<f32>>),
+    I32(Arc<Vec<i32>>),
+    U8(Arc<Vec<u8>>),
+}
+
+/// The types a tensor can hold.
+pub trait Element: Copy + Send + Sync + 'static {
+    const DTYPE: DType;
+    fn wrap(data: Vec<Self>) -> TensorData;
+    fn unwrap(data: &TensorData) -> Option<&[Self]>;
+}
+
+impl Element for f32 {
+    const DTYPE: DType = DType::F32;
+    fn wrap(data: Vec<f32>) -> TensorData {
+        TensorData::F32(Arc::new(data))
+    }
+    fn unwrap(data: &TensorData) -> Option<&[f32]> {
+        match *data {
+            TensorData::F32(ref data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+impl Element for i32 {
+    const DTYPE: DType = DType::I32;
+    fn wrap(data: Vec<i32>) -> TensorData {
+        TensorData::I32(Arc::new(data))
+    }
+    fn unwrap(data: &TensorData) -> Option<&[i32]> {
+        match *data {
+            TensorData::I32(ref data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+impl Element for u8 {
+    const DTYPE: DType = DType::U8;
+    fn wrap(data: Vec<u8>) -> TensorData {
+        TensorData::U8(Arc::new(data))
+    }
+    fn unwrap(data: &TensorData) -> Option<&[u8]> {
+        match *data {
+            TensorData::U8(ref data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tensor {
+    shape: Vec<usize>,
+    data: TensorData,
+}
+
+impl Tensor {
+    /// A tensor of `shape` holding `data`, which must have a value for every element.
+    pub fn new<T: Element>(shape: Vec<usize>, data: Vec<T>) -> Result<Tensor, String> {
+        let len: usize = shape.iter().product();
+        if data.len() != len {
+            return Err(format!("{} values for shape {:?}", data.len(), shape));
+        }
+        Ok(Tensor {
+            shape,
+            data: T::wrap(data),
+        })
+    }
+    /// A tensor of a row per sample of `frame` and a column per channel.
+    pub fn from_frame(frame: &Frame) -> Tensor {
+        let (rows, channels) = frame.data.dim();
+        Tensor {
+            shape: vec![rows, channels],
+            data: f32::wrap(frame.data.iter().cloned().collect()),
+        }
+    }
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+    pub fn dtype(&self) -> DType {
+        match self.data {
+            TensorData::F32(_) => DType::F32,
+            TensorData::I32(_) => DType::I32,
+            TensorData::U8(_) => DType::U8,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+    /// The values, if they are of type `T`.
+    pub fn values<T: Element>(&self) -> Option<&[T]> {
+        T::unwrap(&self.data)
+    }
+    /// The values converted to `f32`.
+    pub fn to_f32(&self) -> Vec<f32> {
+        match self.data {
+            TensorData::F32(ref data) => data.to_vec(),
+            TensorData::I32(ref data) => data.iter().map(|&x| x as f32).collect(),
+            TensorData::U8(ref data) => data.iter().map(|&x| x as f32).collect(),
+        }
+    }
+    /// A frame of a row per sample and a column per channel, from a two dimensional tensor, or a
+    /// one dimensional one as a single row.
+    pub fn to_frame(&self, rate: f32, time: Option<u64>, pool: &FramePool) -> Result<Frame, String> {
+        let dim = match *self.shape {
+            [len] => (1, len),
+            [rows, channels] => (rows, channels),
+            _ => return Err(format!("shape {:?} isn't a frame", self.shape)),
+        };
+        let mut frame = pool.zeros(rate, time, dim);
+        for (x, y) in frame.data.iter_mut().zip(self.to_f32()) {
+            *x = y;
+        }
+        Ok(frame)
+    }
+    /// The same values in a new shape, sharing the data.
+    pub fn reshape(&self, shape: Vec<usize>) -> Result<Tensor, String> {
+        if shape.iter().product::<usize>() != self.len() {
+            return Err(format!("can't reshape {:?} to {:?}", self.shape, shape));
+        }
+        Ok(Tensor {
+            shape,
+            data: self.data.clone(),
+        })
+    }
+    /// The indices `start..end` along `axis`, copied.
+    pub fn slice(&self, axis: usize, start: usize, end: usize) -> Result<Tensor, String> {
+        let size = *self
+            .shape
+            .get(axis)
+            .ok_or_else(|| format!("no axis {} in {:?}", axis, self.shape))?;
+        if start > end || end > size {
+            return Err(format!("{}..{} is out of 0..{}", start, end, size));
+        }
+        // the data is blocks of `size` runs of `inner` values each
+        let inner: usize = self.shape[axis + 1..].iter().product();
+        let outer: usize = self.shape[..axis].iter().product();
+        let mut shape = self.shape.clone();
+        shape[axis] = end - start;
+        let data = match self.data {
+            TensorData::F32(ref data) => f32::wrap(slice_data(data, outer, size, inner, start, end)),
+            TensorData::I32(ref data) => i32::wrap(slice_data(data, outer, size, inner, start, end)),
+            TensorData::U8(ref data) => u8::wrap(slice_data(data, outer, size, inner, start, end)),
+        };
+        Ok(Tensor { shape, data })
+    }
+}
+
+fn slice_data<T: Copy>(
+    data: &[T],
+    outer: usize,
+    size: usize,
+    inner: usize,
+    start: usize,
+    end: usize,
+) -> Vec<T> {
+    let mut out = Vec::with_capacity(outer * (end - start) * inner);
+    for block in 0..outer {
+        let base = block * size * inner;
+        out.extend_from_slice(&data[base + start * inner..base + end * inner]);
+    }
+    out
+}
+
+/// An operation on every tensor passing through a `TensorModule`, kept in its state.
+pub trait TensorOp: Sized + Send + 'static {
+    const NAME: &'static str;
+    /// The form of the operation, shown when it doesn't parse.
+    const FORM: &'static str;
+    fn parse(s: &str) -> Option<Self>;
+    fn describe(&self) -> String;
+    fn default() -> Self;
+    fn apply(&self, tensor: &Tensor) -> Result<Tensor, String>;
+}
+
+/// A new shape of `dims`, where one may be `-1`, meaning whatever fits the rest, like `-1 4`.
+pub struct Reshape {
+    dims: Vec<Option<usize>>,
+}
+
+impl TensorOp for Reshape {
+    const NAME: &'static str = "Reshape";
+    const FORM: &'static str = "dims";
+    fn parse(s: &str) -> Option<Reshape> {
+        let dims: Vec<Option<usize>> = s
+            .split_whitespace()
+            .map(|word| match word {
+                "-1" => Some(None),
+                _ => word.parse().ok().map(Some),
+            })
+            .collect::<Option<_>>()?;
+        if dims.is_empty() || dims.iter().filter(|dim| dim.is_none()).count() > 1 {
+            return None;
+        }
+        Some(Reshape { dims })
+    }
+    fn describe(&self) -> String {
+        let dims: Vec<String> = self
+            .dims
+            .iter()
+            .map(|dim| dim.map(|dim| dim.to_string()).unwrap_or_else(|| "-1".into()))
+            .collect();
+        dims.join(" ")
+    }
+    fn default() -> Reshape {
+        Reshape { dims: vec![None] }
+    }
+    fn apply(&self, tensor: &Tensor) -> Result<Tensor, String> {
+        let known: usize = self.dims.iter().filter_map(|&dim| dim).product();
+        let rest = if known == 0 { 0 } else { tensor.len() / known };
+        tensor.reshape(self.dims.iter().map(|dim| dim.unwrap_or(rest)).collect())
+    }
+}
+
+/// The indices `start` up to `end` along `axis`, like `0 2 5`, or to the end if `end` is left out.
+pub struct Slice {
+    axis: usize,
+    start: usize,
+    end: Option<usize>,
+}
+
+impl TensorOp for Slice {
+    const NAME: &'static str = "Slice Tensor";
+    const FORM: &'static str = "axis start [end]";
+    fn parse(s: &str) -> Option<Slice> {
+        let words: Vec<usize> = s
+            .split_whitespace()
+            .map(|word| word.parse().ok())
+            .collect::<Option<_>>()?;
+        match *words {
+            [axis, start] => Some(Slice {
+                axis,
+                start,
+                end: None,
+            }),
+            [axis, start, end] if start <= end => Some(Slice {
+                axis,
+                start,
+                end: Some(end),
+            }),
+            _ => None,
+        }
+    }
+    fn describe(&self) -> String {
+        match self.end {
+            Some(end) => format!("{} {} {}", self.axis, self.start, end),
+            None => format!("{} {}", self.axis, self.start),
+        }
+    }
+    fn default() -> Slice {
+        Slice {
+            axis: 0,
+            start: 0,
+            end: None,
+        }
+    }
+    fn apply(&self, tensor: &Tensor) -> Result<Tensor, String> {
+        let size = tensor.shape().get(self.axis).cloned().unwrap_or(0);
+        tensor.slice(self.axis, self.start, self.end.unwrap_or(size))
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    SetOp(String),
+}
+
+pub struct TensorModule<Op: TensorOp> {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Tensor, ()>>,
+    out_port: Arc<flow::Port<(), Tensor>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    op: Arc<Mutex<Op>>,
+}
+
+impl<Op: TensorOp> Module for TensorModule<Op> {
+    fn new(ifc: Arc<flow::Interface>) -> TensorModule<Op> {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        TensorModule {
+            in_port: ifc.get_or_create_port("Input".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            op: Arc::new(Mutex::new(Op::default())),
+        }
+    }
+    fn name() -> &'static str {
+        Op::NAME
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let op = self.op.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::SetOp(s) => match Op::parse(&s) {
+                        Some(new_op) => *op.lock().unwrap() = new_op,
+                        None => println!("{} err: expected {}", Op::NAME, Op::FORM),
+                    },
+                }
+            }
+        })
+        .unwrap();
+
+        let (mut out_tx, out_rx) = mpsc::channel(1);
+        let op = self.op.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |tensor: Tensor| match op.lock().unwrap().apply(&tensor) {
+                Ok(out) => {
+                    let _ = out_tx.try_send(out);
+                }
+                Err(e) => println!("{} err: {}", Op::NAME, e),
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        json!({ "op": self.op.lock().unwrap().describe() })
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match state["op"].as_str().and_then(Op::parse) {
+            Some(op) => *self.op.lock().unwrap() = op,
+            None => println!("{} state err: expected {}", Op::NAME, Op::FORM),
+        }
+    }
+}
+
+pub struct ToTensor {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), Tensor>>,
+    breaker: Breaker,
+}
+
+impl Module for ToTensor {
+    fn new(ifc: Arc<flow::Interface>) -> ToTensor {
+        ToTensor {
+            in_port: ifc.get_or_create_port("Input".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+        }
+    }
+    fn name() -> &'static str {
+        "To Tensor"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (mut out_tx, out_rx) = mpsc::channel(1);
+        let pool = self.ifc.graph().pool();
+        util::start_sink(
+            self.in_port.clone(),
+            move |frame: Frame| {
+                let _ = out_tx.try_send(Tensor::from_frame(&frame));
+                pool.recycle(frame);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+pub struct ToFrame {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Tensor, ()>>,
+    rate_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    rate: Arc<Mutex<f32>>,
+}
+
+impl Module for ToFrame {
+    fn new(ifc: Arc<flow::Interface>) -> ToFrame {
+        ToFrame {
+            in_port: ifc.get_or_create_port("Input".into()),
+            rate_port: ifc.get_or_create_port("Rate".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            rate: Arc::new(Mutex::new(1.0)),
+        }
+    }
+    fn name() -> &'static str {
+        "To Frame"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let rate = self.rate.clone();
+        util::start_sink(
+            self.rate_port.clone(),
+            move |new_rate: f32| *rate.lock().unwrap() = new_rate,
+            self.breaker.clone(),
+            &exec,
+        );
+        let (mut out_tx, out_rx) = mpsc::channel(1);
+        let rate = self.rate.clone();
+        let pool = self.ifc.graph().pool();
+        util::start_sink(
+            self.in_port.clone(),
+            move |tensor: Tensor| match tensor.to_frame(*rate.lock().unwrap(), None, &pool) {
+                Ok(frame) => {
+                    let _ = out_tx.try_send(frame);
+                }
+                Err(e) => println!("to frame err: {}", e),
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_tensor() {
+    let tensor = Tensor::new(vec![2, 3], (0..6).map(|x| x as f32).collect()).unwrap();
+    assert_eq!(tensor.dtype(), DType::F32);
+    assert!(Tensor::new(vec![2, 3], vec![0u8; 5]).is_err());
+    assert_eq!(tensor.values::<u8>(), None);
+
+    let reshaped = Reshape::parse("3 -1").unwrap().apply(&tensor).unwrap();
+    assert_eq!(reshaped.shape(), &[3, 2]);
+    assert_eq!(reshaped.values::<f32>(), tensor.values::<f32>());
+    assert!(Reshape::parse("4 -1").unwrap().apply(&tensor).is_err());
+    assert!(Reshape::parse("-1 -1").is_none());
+    assert_eq!(Reshape::parse("3 -1").unwrap().describe(), "3 -1");
+
+    // the middle column of each row
+    let column = tensor.slice(1, 1, 2).unwrap();
+    assert_eq!(column.shape(), &[2, 1]);
+    assert_eq!(column.values::<f32>().unwrap(), &[1.0, 4.0]);
+    let row = Slice::parse("0 1").unwrap().apply(&tensor).unwrap();
+    assert_eq!(row.values::<f32>().unwrap(), &[3.0, 4.0, 5.0]);
+    assert!(tensor.slice(1, 2, 4).is_err());
+    assert!(tensor.slice(2, 0, 1).is_err());
+    let bytes = Tensor::new(vec![2, 2, 2], (0..8).collect::<Vec<u8>>()).unwrap();
+    assert_eq!(
+        bytes.slice(1, 1, 2).unwrap().values::<u8>().unwrap(),
+        &[2, 3, 6, 7]
+    );
+
+    let pool = FramePool::new();
+    let frame = tensor.to_frame(10.0, Some(4), &pool).unwrap();
+    assert_eq!(frame.data.dim(), (2, 3));
+    assert_eq!(Tensor::from_frame(&frame), tensor);
+    assert_eq!(row.to_frame(10.0, None, &pool).unwrap().data.dim(), (1, 3));
+    assert!(bytes.to_frame(10.0, None, &pool).is_err());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct TensorOpGui {
+    bounds: Box3,
+    op_box: TextBox,
+    apply_button: Button,
+    form: &'static str,
+    parses: fn(&str) -> bool,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl<Op: TensorOp> ModuleGui for TensorModule<Op> {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let op = self.op.lock().unwrap().describe();
+        fn parses<Op: TensorOp>(s: &str) -> bool {
+            Op::parse(s).is_some()
+        }
+        Box::new(TensorOpGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            op_box: TextBox::new(ctx.clone(), op, row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+            form: Op::FORM,
+            parses: parses::<Op>,
+        })
+    }
+}
+impl GuiComponent<bool> for TensorOpGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.op_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.op_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let op = self.op_box.content().to_string();
+                if (self.parses)(&op) {
+                    self.apply_button.set_label("Apply".into());
+                    self.cmd_tx.unbounded_send(UserCommand::SetOp(op)).unwrap();
+                } else {
+                    self.apply_button.set_label(format!("Invalid: {}", self.form));
+                }
+                true
+            }
+        }
+    }
+}
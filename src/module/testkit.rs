@@ -0,0 +1,173 @@
+//! Testing modules without standing up a graph of real modules and an executor.
+//!
+//! `TestHarness::with_module` creates a module on a graph of its own, next to a test node whose
+//! virtual ports connect to the module's. Items pushed into an `Input` arrive on the module's input
+//! of that name, and an `Output` collects what the module writes to its output, the way a sink
+//! module downstream would. The module's tasks run on a local executor which only moves when the
+//! test calls `run`, so every test sees the same order of events:
+//!
+//! ```ignore
+//! let mut harness = TestHarness::<Router<f32>>::with_module();
+//! let input = harness.input::<f32>("Input");
+//! let out = harness.output::<f32>("Out 1");
+//! input.push(1.0);
+//! harness.run();
+//! assert_eq!(out.take(), vec![1.0]);
+//! ```
+//!
+//! Modules running threads or timers of their own still do so, and what those send only shows up
+//! once they have, so tests of them need to wait as before.
+
+use crate::future_ext::Breaker;
+use crate::module::flow::{self, PortData};
+use crate::module::{util, Module};
+
+use futures::executor::{LocalPool, LocalSpawner};
+
+use serde_json;
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+/// A module on a graph of its own, with virtual ports to drive it from a test.
+pub struct TestHarness<M: Module> {
+    module: M,
+    /// Nodes only hold on to their graph weakly, so the harness keeps it alive.
+    graph: Arc<flow::Graph>,
+    ifc: Arc<flow::Interface>,
+    /// The node the virtual ports belong to.
+    node: Arc<flow::Interface>,
+    pool: LocalPool,
+    exec: LocalSpawner,
+    breaker: Breaker,
+}
+
+impl<M: Module> TestHarness<M> {
+    /// Create and start a module.
+    pub fn with_module() -> TestHarness<M> {
+        TestHarness::with_state(serde_json::Value::Null)
+    }
+    /// Create a module and start it after loading `state`, like a module from a saved patch.
+    pub fn with_state(state: serde_json::Value) -> TestHarness<M> {
+        let graph = flow::Graph::new();
+        let ifc = graph.add_node();
+        let node = graph.add_node();
+        let mut module = M::new(ifc.clone());
+        if !state.is_null() {
+            module.load_state(state);
+        }
+        let pool = LocalPool::new();
+        let exec = pool.spawner();
+        module.start(exec.clone());
+        TestHarness {
+            module,
+            graph,
+            ifc,
+            node,
+            pool,
+            exec,
+            breaker: Breaker::new(),
+        }
+    }
+    pub fn module(&mut self) -> &mut M {
+        &mut self.module
+    }
+    /// The node of the module, to set its metadata or check what it declared.
+    pub fn interface(&self) -> &Arc<flow::Interface> {
+        &self.ifc
+    }
+    /// Connect to the module's input `name`. Panics if it has no input of that name and type.
+    pub fn input<T: PortData>(&self, name: &str) -> Input<T> {
+        let port = self
+            .ifc
+            .find_port::<T, ()>(name)
+            .unwrap_or_else(|| panic!("{} has no input {:?} of that type", M::name(), name));
+        let input = self.node.get_or_create_port::<(), T>(name.into());
+        input.connect(&port).unwrap();
+        Input { port: input }
+    }
+    /// Connect to the module's output `name`, collecting everything it writes from now on. Panics
+    /// if it has no output of that name and type.
+    pub fn output<T: Send + 'static>(&mut self, name: &str) -> Output<T> {
+        let port = self
+            .ifc
+            .find_port::<(), T>(name)
+            .unwrap_or_else(|| panic!("{} has no output {:?} of that type", M::name(), name));
+        let output = self.node.get_or_create_port::<T, ()>(name.into());
+        output.connect(&port).unwrap();
+        let items = Arc::new(Mutex::new(Vec::new()));
+        let collected = items.clone();
+        util::start_sink(
+            output,
+            move |item: T| collected.lock().unwrap().push(item),
+            self.breaker.clone(),
+            &self.exec,
+        );
+        Output { items }
+    }
+    /// Run the module's tasks, and the collecting ones of the outputs, until they're all waiting.
+    /// Tasks woken by a port lock being released after the others have had their turn run again.
+    pub fn run(&mut self) {
+        self.pool.run_until_stalled();
+    }
+}
+
+impl<M: Module> Drop for TestHarness<M> {
+    fn drop(&mut self) {
+        self.module.stop();
+        self.breaker.brake();
+    }
+}
+
+/// Feeds one of the module's inputs.
+pub struct Input<T: 'static> {
+    port: Arc<flow::Port<(), T>>,
+}
+
+impl<T: PortData> Input<T> {
+    /// Send an item to the module, to be seen on the next `TestHarness::run`.
+    pub fn push(&self, item: T) {
+        self.push_all(vec![item]);
+    }
+    pub fn push_all(&self, items: Vec<T>) {
+        if let Err((_, err)) = self.port.try_write(items) {
+            panic!("push to {:?} err: {:?}", self.port.name(), err);
+        }
+    }
+    /// End the stream, so the module's reads get `Error::EndOfStream`. See `flow::Port::close`.
+    pub fn close(&self) {
+        self.port.close().unwrap();
+    }
+}
+
+/// Collects what the module writes to one of its outputs.
+pub struct Output<T> {
+    items: Arc<Mutex<Vec<T>>>,
+}
+
+impl<T> Output<T> {
+    /// Everything collected since the last call.
+    pub fn take(&self) -> Vec<T> {
+        mem::replace(&mut *self.items.lock().unwrap(), Vec::new())
+    }
+}
+
+#[test]
+fn test_harness() {
+    use crate::module::routing::Router;
+
+    let mut harness = TestHarness::<Router<f32>>::with_module();
+    let input = harness.input::<f32>("Input");
+    let index = harness.input::<f32>("Index");
+    let out_1 = harness.output::<f32>("Out 1");
+    let out_2 = harness.output::<f32>("Out 2");
+    input.push(1.0);
+    harness.run();
+    assert_eq!(out_1.take(), vec![1.0]);
+    index.push(1.0);
+    harness.run();
+    input.push(2.0);
+    harness.run();
+    assert_eq!(out_1.take(), Vec::<f32>::new());
+    assert_eq!(out_2.take(), vec![2.0]);
+}
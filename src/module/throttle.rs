@@ -0,0 +1,328 @@
+//! Thinning of control and event streams: `Throttle` passes at most a number of items per second,
+//! `Decimate` keeps every Nth item and `Debounce` waits for a stream to settle.
+//!
+//! All of them work with any item type, so noisy sensors or network controls can be calmed
+//! before they reach the rest of the patch. Like the generators in `timeline`, `Throttle` and
+//! `Debounce` are clocked by their `Clock` frames, usually straight from the audio interface, so
+//! without a clock `Throttle` lets only its first item through and `Debounce` none.
+
+use futures::channel::mpsc;
+use futures::task::Spawn;
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use std::sync::{Arc, Mutex};
+
+/// Seconds covered by `frame`.
+fn duration(frame: &Frame) -> f32 {
+    frame.data.dim().0 as f32 / frame.rate
+}
+
+/// Lets through at most `rate` items per second. Items arriving too soon are dropped, or with
+/// `coalesce` the latest of them is kept and let through as soon as the rate allows.
+pub struct RateLimiter<T> {
+    /// Items per second, no limit if not positive.
+    pub rate: f32,
+    pub coalesce: bool,
+    /// Seconds until the next item may pass.
+    wait: f32,
+    pending: Option<T>,
+}
+
+impl<T> RateLimiter<T> {
+    pub fn new() -> RateLimiter<T> {
+        RateLimiter {
+            rate: 10.0,
+            coalesce: false,
+            wait: 0.0,
+            pending: None,
+        }
+    }
+    /// Offer an item, returning it if it may pass now.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if self.wait <= 0.0 {
+            self.pass();
+            Some(item)
+        } else {
+            if self.coalesce {
+                self.pending = Some(item);
+            }
+            None
+        }
+    }
+    /// Move on by `seconds`, returning the item held back if it may pass now.
+    pub fn advance(&mut self, seconds: f32) -> Option<T> {
+        self.wait -= seconds;
+        if self.wait <= 0.0 && self.pending.is_some() {
+            self.pass();
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+    fn pass(&mut self) {
+        self.wait = if self.rate > 0.0 { 1.0 / self.rate } else { 0.0 };
+    }
+}
+
+/// Holds on to the latest item until none has arrived for `time` seconds.
+pub struct Debouncer<T> {
+    pub time: f32,
+    /// Seconds since the held item arrived.
+    quiet: f32,
+    pending: Option<T>,
+}
+
+impl<T> Debouncer<T> {
+    pub fn new() -> Debouncer<T> {
+        Debouncer {
+            time: 0.1,
+            quiet: 0.0,
+            pending: None,
+        }
+    }
+    /// Replace the held item, and start waiting over.
+    pub fn push(&mut self, item: T) {
+        self.pending = Some(item);
+        self.quiet = 0.0;
+    }
+    /// Move on by `seconds`, returning the held item once the stream has been quiet for long
+    /// enough.
+    pub fn advance(&mut self, seconds: f32) -> Option<T> {
+        self.quiet += seconds;
+        if self.quiet >= self.time {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// Passes on at most `Rate` items per second. With `Coalesce` above 0.5 the latest item of those
+/// arriving too soon is sent when the next one may pass, instead of dropping them all.
+pub struct Throttle<T: Send + 'static> {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    in_port: Arc<flow::Port<T, ()>>,
+    rate_port: Arc<flow::Port<f32, ()>>,
+    coalesce_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), T>>,
+    breaker: Breaker,
+    limiter: Arc<Mutex<RateLimiter<T>>>,
+}
+
+impl<T: Send + 'static> Module for Throttle<T> {
+    fn new(ifc: Arc<flow::Interface>) -> Throttle<T> {
+        Throttle {
+            clock_port: ifc.get_or_create_port("Clock".into()),
+            in_port: ifc.get_or_create_port("Input".into()),
+            rate_port: ifc.get_or_create_port("Rate".into()),
+            coalesce_port: ifc.get_or_create_port("Coalesce".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            limiter: Arc::new(Mutex::new(RateLimiter::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Throttle"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let limiter = self.limiter.clone();
+        util::start_sink(
+            self.rate_port.clone(),
+            move |rate: f32| limiter.lock().unwrap().rate = rate,
+            self.breaker.clone(),
+            &exec,
+        );
+        let limiter = self.limiter.clone();
+        util::start_sink(
+            self.coalesce_port.clone(),
+            move |coalesce: f32| limiter.lock().unwrap().coalesce = coalesce > 0.5,
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let (out_tx, out_rx) = mpsc::channel(1);
+        let limiter = self.limiter.clone();
+        let mut tx = out_tx.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |item: T| {
+                if let Some(item) = limiter.lock().unwrap().push(item) {
+                    let _ = tx.try_send(item);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        let limiter = self.limiter.clone();
+        let mut tx = out_tx;
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                if let Some(item) = limiter.lock().unwrap().advance(duration(&frame)) {
+                    let _ = tx.try_send(item);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Passes on the first of every `Factor` items, rounded and at least 1.
+pub struct Decimate<T: Send + 'static> {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<T, ()>>,
+    factor_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), T>>,
+    breaker: Breaker,
+    factor: Arc<Mutex<usize>>,
+}
+
+impl<T: Send + 'static> Module for Decimate<T> {
+    fn new(ifc: Arc<flow::Interface>) -> Decimate<T> {
+        Decimate {
+            in_port: ifc.get_or_create_port("Input".into()),
+            factor_port: ifc.get_or_create_port("Factor".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            factor: Arc::new(Mutex::new(2)),
+        }
+    }
+    fn name() -> &'static str {
+        "Decimate"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let factor = self.factor.clone();
+        util::start_sink(
+            self.factor_port.clone(),
+            move |value: f32| *factor.lock().unwrap() = value.round().max(1.0) as usize,
+            self.breaker.clone(),
+            &exec,
+        );
+        let (mut out_tx, out_rx) = mpsc::channel(1);
+        let factor = self.factor.clone();
+        let mut count = 0;
+        util::start_sink(
+            self.in_port.clone(),
+            move |item: T| {
+                if count == 0 {
+                    let _ = out_tx.try_send(item);
+                }
+                count = (count + 1) % *factor.lock().unwrap();
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+/// Passes on the last of a burst of items once none has arrived for `Time` seconds.
+pub struct Debounce<T: Send + 'static> {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    in_port: Arc<flow::Port<T, ()>>,
+    time_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), T>>,
+    breaker: Breaker,
+    debouncer: Arc<Mutex<Debouncer<T>>>,
+}
+
+impl<T: Send + 'static> Module for Debounce<T> {
+    fn new(ifc: Arc<flow::Interface>) -> Debounce<T> {
+        Debounce {
+            clock_port: ifc.get_or_create_port("Clock".into()),
+            in_port: ifc.get_or_create_port("Input".into()),
+            time_port: ifc.get_or_create_port("Time".into()),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            debouncer: Arc::new(Mutex::new(Debouncer::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Debounce"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let debouncer = self.debouncer.clone();
+        util::start_sink(
+            self.time_port.clone(),
+            move |time: f32| debouncer.lock().unwrap().time = time,
+            self.breaker.clone(),
+            &exec,
+        );
+        let debouncer = self.debouncer.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |item: T| debouncer.lock().unwrap().push(item),
+            self.breaker.clone(),
+            &exec,
+        );
+        let (mut out_tx, out_rx) = mpsc::channel(1);
+        let debouncer = self.debouncer.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                if let Some(item) = debouncer.lock().unwrap().advance(duration(&frame)) {
+                    let _ = out_tx.try_send(item);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(out_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_throttle() {
+    let mut limiter = RateLimiter::new();
+    limiter.rate = 4.0;
+    assert_eq!(limiter.push(1), Some(1));
+    assert_eq!(limiter.push(2), None);
+    assert_eq!(limiter.advance(0.25), None);
+    assert_eq!(limiter.push(3), Some(3));
+    // the latest of the items arriving too soon passes when the rate allows
+    limiter.coalesce = true;
+    assert_eq!(limiter.push(4), None);
+    assert_eq!(limiter.push(5), None);
+    assert_eq!(limiter.advance(0.125), None);
+    assert_eq!(limiter.advance(0.125), Some(5));
+    assert_eq!(limiter.push(6), None);
+    limiter.rate = 0.0;
+    assert_eq!(limiter.advance(0.25), Some(6));
+    assert_eq!(limiter.push(7), Some(7));
+    assert_eq!(limiter.push(8), Some(8));
+
+    let mut debouncer = Debouncer::new();
+    debouncer.push(1);
+    assert_eq!(debouncer.advance(0.05), None);
+    debouncer.push(2);
+    assert_eq!(debouncer.advance(0.05), None);
+    assert_eq!(debouncer.advance(0.05), Some(2));
+    assert_eq!(debouncer.advance(0.5), None);
+}
@@ -0,0 +1,653 @@
+//! Generators of control values over time: a one-shot `Ramp`, a `Metronome`, a `Cues` list and the
+//! `Transport`, which follows a `TempoMap` through bars and beats. Tempo maps are in
+//! `flow_synth_core::timeline`.
+//!
+//! Like the oscillators, these are clocked by their `Input` frames, usually straight from the
+//! audio interface. Each frame moves time on by its duration, and cues and bars are placed on the
+//! transport by the frames' sample counters, so everything stays in step with the audio. Values
+//! are sent once per frame at most, and dropped when nothing is reading them.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+pub use flow_synth_core::timeline::*;
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+/// Seconds covered by `frame`.
+fn duration(frame: &Frame) -> f32 {
+    frame.data.dim().0 as f32 / frame.rate
+}
+
+/// A one-shot ramp from `start` to `end`, shaped by raising its progress to the power of `curve`.
+pub struct RampGenerator {
+    pub start: f32,
+    pub end: f32,
+    /// Seconds from start to end.
+    pub duration: f32,
+    /// 1.0 is linear, higher values start slower and lower values start faster.
+    pub curve: f32,
+    /// Seconds since the ramp was triggered, while it is running.
+    elapsed: Option<f32>,
+}
+
+impl RampGenerator {
+    pub fn new() -> RampGenerator {
+        RampGenerator {
+            start: 0.0,
+            end: 1.0,
+            duration: 1.0,
+            curve: 1.0,
+            elapsed: None,
+        }
+    }
+    /// Start the ramp over from `start`.
+    pub fn trigger(&mut self) {
+        self.elapsed = Some(0.0);
+    }
+    /// Move on by `seconds`, returning the value reached if the ramp is running. The end value
+    /// is returned once when it is reached.
+    pub fn advance(&mut self, seconds: f32) -> Option<f32> {
+        let elapsed = self.elapsed? + seconds;
+        let progress = if self.duration > 0.0 {
+            (elapsed / self.duration).min(1.0)
+        } else {
+            1.0
+        };
+        self.elapsed = if progress < 1.0 { Some(elapsed) } else { None };
+        Some(self.start + (self.end - self.start) * progress.powf(self.curve.max(0.01)))
+    }
+}
+
+/// Counts beats at a tempo.
+pub struct BeatCounter {
+    /// Beats per minute.
+    pub tempo: f32,
+    /// Beats so far, the fraction being the progress towards the next one.
+    beats: f64,
+    started: bool,
+}
+
+impl BeatCounter {
+    pub fn new() -> BeatCounter {
+        BeatCounter {
+            tempo: 120.0,
+            beats: 0.0,
+            started: false,
+        }
+    }
+    /// Count from zero again, starting with the next call to `advance`.
+    pub fn reset(&mut self) {
+        self.beats = 0.0;
+        self.started = false;
+    }
+    /// Move on by `seconds`, returning the count of every beat falling in that time.
+    pub fn advance(&mut self, seconds: f32) -> Vec<u64> {
+        let mut counts = Vec::new();
+        if !self.started {
+            // the first beat falls right at the start
+            self.started = true;
+            counts.push(0);
+        }
+        let before = self.beats.floor() as u64;
+        self.beats += seconds as f64 * self.tempo.max(0.0) as f64 / 60.0;
+        counts.extend(before + 1..self.beats.floor() as u64 + 1);
+        counts
+    }
+}
+
+/// Named events at times on the transport.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CueList {
+    /// Seconds and names, sorted by time.
+    cues: Vec<(f32, String)>,
+}
+
+impl CueList {
+    /// Parse cues of the form `seconds name`, separated by `;` or newlines.
+    pub fn parse(s: &str) -> Option<CueList> {
+        let mut cues = Vec::new();
+        let lines = s.split(|c| c == ';' || c == '\n').filter(|cue| !cue.trim().is_empty());
+        for cue in lines {
+            let mut words = cue.split_whitespace();
+            let time: f32 = words.next()?.parse().ok()?;
+            let name = words.collect::<Vec<_>>().join(" ");
+            if !(time >= 0.0) || name.is_empty() {
+                return None;
+            }
+            cues.push((time, name));
+        }
+        cues.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Some(CueList { cues })
+    }
+    fn describe(&self) -> String {
+        let cues: Vec<String> = self
+            .cues
+            .iter()
+            .map(|&(time, ref name)| format!("{} {}", time, name))
+            .collect();
+        cues.join("; ")
+    }
+    /// The names of the cues from just after `from` up to and including `to`, in order. Cues at
+    /// 0.0 fire when the transport starts at 0.0.
+    pub fn between(&self, from: Option<f32>, to: f32) -> Vec<String> {
+        self.cues
+            .iter()
+            .filter(|&&(time, _)| from.map_or(true, |from| time > from) && time <= to)
+            .map(|&(_, ref name)| name.clone())
+            .collect()
+    }
+}
+
+pub struct Ramp {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    trigger_port: Arc<flow::Port<f32, ()>>,
+    param_ports: Vec<Arc<flow::Port<f32, ()>>>,
+    out_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    ramp: Arc<Mutex<RampGenerator>>,
+}
+
+impl Module for Ramp {
+    fn new(ifc: Arc<flow::Interface>) -> Ramp {
+        Ramp {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            trigger_port: ifc.get_or_create_port("Trigger".into()),
+            param_ports: ["Start", "End", "Duration", "Curve"]
+                .iter()
+                .map(|&name| ifc.get_or_create_port(name.into()))
+                .collect(),
+            out_port: ifc.get_or_create_port("Output".into()),
+            ifc,
+            breaker: Breaker::new(),
+            ramp: Arc::new(Mutex::new(RampGenerator::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Ramp"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let ramp = self.ramp.clone();
+        util::start_sink(
+            self.trigger_port.clone(),
+            move |_: f32| ramp.lock().unwrap().trigger(),
+            self.breaker.clone(),
+            &exec,
+        );
+        for (idx, port) in self.param_ports.iter().enumerate() {
+            let ramp = self.ramp.clone();
+            util::start_sink(
+                port.clone(),
+                move |value: f32| {
+                    let mut ramp = ramp.lock().unwrap();
+                    match idx {
+                        0 => ramp.start = value,
+                        1 => ramp.end = value,
+                        2 => ramp.duration = value,
+                        _ => ramp.curve = value,
+                    }
+                },
+                self.breaker.clone(),
+                &exec,
+            );
+        }
+
+        let (mut value_tx, value_rx) = mpsc::channel(1);
+        let ramp = self.ramp.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                if let Some(value) = ramp.lock().unwrap().advance(duration(&frame)) {
+                    let _ = value_tx.try_send(value);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(value_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+pub struct Metronome {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    tempo_port: Arc<flow::Port<f32, ()>>,
+    reset_port: Arc<flow::Port<f32, ()>>,
+    count_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    counter: Arc<Mutex<BeatCounter>>,
+}
+
+impl Module for Metronome {
+    fn new(ifc: Arc<flow::Interface>) -> Metronome {
+        Metronome {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            tempo_port: ifc.get_or_create_port("Tempo".into()),
+            reset_port: ifc.get_or_create_port("Reset".into()),
+            count_port: ifc.get_or_create_port("Count".into()),
+            ifc,
+            breaker: Breaker::new(),
+            counter: Arc::new(Mutex::new(BeatCounter::new())),
+        }
+    }
+    fn name() -> &'static str {
+        "Metronome"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let counter = self.counter.clone();
+        util::start_sink(
+            self.tempo_port.clone(),
+            move |tempo: f32| counter.lock().unwrap().tempo = tempo,
+            self.breaker.clone(),
+            &exec,
+        );
+        let counter = self.counter.clone();
+        util::start_sink(
+            self.reset_port.clone(),
+            move |_: f32| counter.lock().unwrap().reset(),
+            self.breaker.clone(),
+            &exec,
+        );
+
+        let (mut count_tx, count_rx) = mpsc::channel(4);
+        let counter = self.counter.clone();
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                for count in counter.lock().unwrap().advance(duration(&frame)) {
+                    let _ = count_tx.try_send(count as f32);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(count_rx, self.count_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    SetCues(CueList),
+    SetTempoMap(TempoMap),
+}
+
+pub struct Cues {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    out_port: Arc<flow::Port<(), String>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    cues: Arc<Mutex<CueList>>,
+}
+
+impl Module for Cues {
+    fn new(ifc: Arc<flow::Interface>) -> Cues {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Cues {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            out_port: ifc.get_or_create_port("Events".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            cues: Arc::new(Mutex::new(CueList { cues: Vec::new() })),
+        }
+    }
+    fn name() -> &'static str {
+        "Cues"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let cues = self.cues.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::SetCues(list) => *cues.lock().unwrap() = list,
+                    UserCommand::SetTempoMap(_) => {}
+                }
+            }
+        })
+        .unwrap();
+
+        let (mut event_tx, event_rx) = mpsc::channel(16);
+        let cues = self.cues.clone();
+        // the sample at the end of the last frame, counted here if frames have no time
+        let mut position: Option<u64> = None;
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                let start = frame.time.unwrap_or(position.unwrap_or(0));
+                // a jump backwards, like a loop, arms the cues after it again
+                let from = match position {
+                    Some(position) if position <= start => Some(position),
+                    _ if start == 0 => None,
+                    _ => Some(start),
+                };
+                let end = start + frame.data.dim().0 as u64;
+                let seconds = |samples: u64| samples as f32 / frame.rate;
+                for event in cues.lock().unwrap().between(from.map(seconds), seconds(end)) {
+                    let _ = event_tx.try_send(event);
+                }
+                position = Some(end);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(event_rx, self.out_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(&*self.cues.lock().unwrap()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value(state) {
+            Ok(cues) => *self.cues.lock().unwrap() = cues,
+            Err(e) => println!("cues state err: {}", e),
+        }
+    }
+}
+
+/// Follows its tempo map along the transport, sending the bar and beat as they start and the tempo
+/// as it changes. The map is the graph's while this is running, for everything else keeping time
+/// by bars, like the looper. With more than one of these in a patch, the last one started or
+/// edited wins.
+pub struct Transport {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    bar_port: Arc<flow::Port<(), f32>>,
+    beat_port: Arc<flow::Port<(), f32>>,
+    tempo_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    map: Arc<Mutex<Arc<TempoMap>>>,
+}
+
+impl Module for Transport {
+    fn new(ifc: Arc<flow::Interface>) -> Transport {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        Transport {
+            clock_port: ifc.get_or_create_port("Input".into()),
+            bar_port: ifc.get_or_create_port("Bar".into()),
+            beat_port: ifc.get_or_create_port("Beat".into()),
+            tempo_port: ifc.get_or_create_port("Tempo".into()),
+            ifc,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            map: Arc::new(Mutex::new(Arc::new(TempoMap::new()))),
+        }
+    }
+    fn name() -> &'static str {
+        "Transport"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let graph = self.ifc.graph();
+        graph.set_tempo_map(self.map.lock().unwrap().clone());
+        let map = self.map.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::SetTempoMap(edited) => {
+                        let edited = Arc::new(edited);
+                        *map.lock().unwrap() = edited.clone();
+                        graph.set_tempo_map(edited);
+                    }
+                    UserCommand::SetCues(_) => {}
+                }
+            }
+        })
+        .unwrap();
+
+        let (mut bar_tx, bar_rx) = mpsc::channel(1);
+        let (mut beat_tx, beat_rx) = mpsc::channel(1);
+        let (mut tempo_tx, tempo_rx) = mpsc::channel(1);
+        let map = self.map.clone();
+        // the sample at the end of the last frame, counted here if frames have no time
+        let mut position: u64 = 0;
+        let mut last: Option<Position> = None;
+        util::start_sink(
+            self.clock_port.clone(),
+            move |frame: Frame| {
+                let start = frame.time.unwrap_or(position);
+                position = start + frame.data.dim().0 as u64;
+                let now = map.lock().unwrap().position(start as f64 / frame.rate as f64);
+                let (bar, beat) = (now.bar, now.beat.floor());
+                // a jump, like a loop, sends the position it lands on
+                if last.map_or(true, |last| last.bar != bar || last.beat.floor() != beat) {
+                    if last.map_or(true, |last| last.bar != bar) {
+                        let _ = bar_tx.try_send(bar as f32);
+                    }
+                    let _ = beat_tx.try_send(beat as f32);
+                }
+                if last.map_or(true, |last| last.tempo != now.tempo) {
+                    let _ = tempo_tx.try_send(now.tempo);
+                }
+                last = Some(now);
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(bar_rx, self.bar_port.clone(), &exec);
+        util::start_source(beat_rx, self.beat_port.clone(), &exec);
+        util::start_source(tempo_rx, self.tempo_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        // leave the graph as it was without this node, unless another has taken over
+        let graph = self.ifc.graph();
+        if Arc::ptr_eq(&graph.tempo_map(), &self.map.lock().unwrap()) {
+            graph.set_tempo_map(Arc::new(TempoMap::new()));
+        }
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(&**self.map.lock().unwrap()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value::<TempoMap>(state) {
+            Ok(map) => *self.map.lock().unwrap() = Arc::new(map),
+            Err(e) => println!("transport state err: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let mut ramp = RampGenerator::new();
+    ramp.end = 2.0;
+    ramp.curve = 2.0;
+    assert_eq!(ramp.advance(0.25), None);
+    ramp.trigger();
+    assert_eq!(ramp.advance(0.5), Some(0.5));
+    assert_eq!(ramp.advance(0.75), Some(2.0));
+    assert_eq!(ramp.advance(0.25), None);
+
+    let mut counter = BeatCounter::new();
+    assert_eq!(counter.advance(0.25), vec![0]);
+    assert_eq!(counter.advance(0.5), vec![1]);
+    counter.tempo = 240.0;
+    assert_eq!(counter.advance(0.5), vec![2, 3]);
+    counter.reset();
+    assert_eq!(counter.advance(0.1), vec![0]);
+
+    let cues = CueList::parse("2 drop; 0 intro\n 10.5 the end").unwrap();
+    assert_eq!(cues.describe(), "0 intro; 2 drop; 10.5 the end");
+    assert_eq!(cues.between(None, 1.0), vec!["intro".to_string()]);
+    assert_eq!(cues.between(Some(0.0), 2.0), vec!["drop".to_string()]);
+    assert!(cues.between(Some(2.0), 10.0).is_empty());
+    assert!(CueList::parse("soon intro").is_none());
+}
+
+#[test]
+fn test_transport() {
+    use crate::module::testkit::TestHarness;
+    use ndarray::Array2;
+
+    let map = TempoMap::parse("0 120 4/4; 8 60").unwrap();
+    let mut harness = TestHarness::<Transport>::with_state(serde_json::to_value(&map).unwrap());
+    let graph = harness.interface().graph();
+    assert_eq!(*graph.tempo_map(), map);
+    let input = harness.input::<Frame>("Input");
+    let bar = harness.output::<f32>("Bar");
+    let beat = harness.output::<f32>("Beat");
+    let tempo = harness.output::<f32>("Tempo");
+    // half a second each, a beat at 120 BPM
+    let frame = |time| Frame {
+        rate: 8.0,
+        time,
+        data: Array2::zeros((4, 1)),
+        meta: None,
+    };
+    for _ in 0..11 {
+        input.push(frame(None));
+        harness.run();
+    }
+    assert_eq!(bar.take(), vec![0.0, 1.0, 2.0]);
+    assert_eq!(beat.take(), vec![0.0, 1.0, 2.0, 3.0, 0.0, 1.0, 2.0, 3.0, 0.0, 1.0]);
+    assert_eq!(tempo.take(), vec![120.0, 60.0]);
+
+    // jumping back to the start sends where it lands
+    input.push(frame(Some(0)));
+    harness.run();
+    assert_eq!(bar.take(), vec![0.0]);
+    assert_eq!(beat.take(), vec![0.0]);
+    assert_eq!(tempo.take(), vec![120.0]);
+
+    // the graph goes back to the default map once the transport stops
+    harness.module().stop();
+    assert_eq!(*graph.tempo_map(), TempoMap::new());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct CuesGui {
+    bounds: Box3,
+    cues_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for Cues {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let cues = self.cues.lock().unwrap().describe();
+        Box::new(CuesGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            cues_box: TextBox::new(ctx.clone(), cues, row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for CuesGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.cues_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.cues_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match CueList::parse(self.cues_box.content()) {
+                    Some(cues) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::SetCues(cues)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: seconds name; ...".into()),
+                }
+                true
+            }
+        }
+    }
+}
+struct TransportGui {
+    bounds: Box3,
+    map_box: TextBox,
+    apply_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+impl ModuleGui for Transport {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let map = self.map.lock().unwrap().describe();
+        Box::new(TransportGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            map_box: TextBox::new(ctx.clone(), map, row(0.0)),
+            apply_button: Button::new(ctx.clone(), "Apply".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for TransportGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.map_box.render(device, ctx);
+        self.apply_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.map_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.apply_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                match TempoMap::parse(self.map_box.content()) {
+                    Some(map) => {
+                        self.apply_button.set_label("Apply".into());
+                        self.cmd_tx.unbounded_send(UserCommand::SetTempoMap(map)).unwrap();
+                    }
+                    None => self.apply_button.set_label("Invalid: beat tempo [n/d]; ...".into()),
+                }
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Tunings, mapping key numbers to frequencies.
+//!
+//! A tuning is a scale in the Scala `.scl` format, with the keyboard mapping of a `.kbm` file
+//! placing it on the keys, or by default one scale step per key with degree 0 on key 60 and key 69
+//! at 440 Hz. Every graph has one active tuning, twelve tone equal temperament until a `Tuning`
+//! node sets another, and everything turning key numbers into pitch consults it: the `Pitch`
+//! module, for driving oscillators from key numbers, and the `Sampler`. The tunings themselves and
+//! the parsing of `.scl` and `.kbm` files are in `flow_synth_core::tuning`.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{flow, util, Module};
+
+pub use flow_synth_core::tuning::*;
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+
+/// Turns key numbers into frequencies in Hz with the graph's tuning. Fractional keys bend between
+/// their neighbours, and keys the tuning leaves silent are dropped.
+pub struct Pitch {
+    ifc: Arc<flow::Interface>,
+    key_port: Arc<flow::Port<f32, ()>>,
+    frequency_port: Arc<flow::Port<(), f32>>,
+    breaker: Breaker,
+}
+
+impl Module for Pitch {
+    fn new(ifc: Arc<flow::Interface>) -> Pitch {
+        Pitch {
+            key_port: ifc.get_or_create_port("Key".into()),
+            frequency_port: ifc.get_or_create_port("Frequency".into()),
+            ifc,
+            breaker: Breaker::new(),
+        }
+    }
+    fn name() -> &'static str {
+        "Pitch"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let (mut tx, rx) = mpsc::channel(64);
+        let graph = self.ifc.graph();
+        util::start_sink(
+            self.key_port.clone(),
+            move |key: f32| {
+                if let Some(frequency) = graph.tuning().bend(key) {
+                    let _ = tx.try_send(frequency);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+        util::start_source(rx, self.frequency_port.clone(), &exec);
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Load(String, Option<String>),
+}
+
+/// Sets the graph's tuning, from Scala files. The tuning itself is saved with the patch, so it
+/// doesn't depend on the files being around later. With more than one of these in a patch, the
+/// last one started or loaded wins.
+pub struct TuningModule {
+    ifc: Arc<flow::Interface>,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    tuning: Arc<Mutex<Arc<Tuning>>>,
+}
+
+impl Module for TuningModule {
+    fn new(ifc: Arc<flow::Interface>) -> TuningModule {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        TuningModule {
+            ifc,
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            tuning: Arc::new(Mutex::new(Arc::new(Tuning::equal()))),
+        }
+    }
+    fn name() -> &'static str {
+        "Tuning"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let graph = self.ifc.graph();
+        graph.set_tuning(self.tuning.lock().unwrap().clone());
+        let tuning = self.tuning.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Load(scale, mapping) => match Tuning::load(&scale, mapping.as_ref()) {
+                        Ok(loaded) => {
+                            let loaded = Arc::new(loaded);
+                            *tuning.lock().unwrap() = loaded.clone();
+                            graph.set_tuning(loaded);
+                        }
+                        Err(e) => println!("tuning load {} err: {}", scale, e),
+                    },
+                }
+            }
+        })
+        .unwrap();
+    }
+    fn stop(&mut self) {
+        // leave the graph as it was without this node, unless another has taken over
+        let graph = self.ifc.graph();
+        if Arc::ptr_eq(&graph.tuning(), &self.tuning.lock().unwrap()) {
+            graph.set_tuning(Arc::new(Tuning::equal()));
+        }
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(&**self.tuning.lock().unwrap()).unwrap()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        match serde_json::from_value::<Tuning>(state) {
+            Ok(tuning) => *self.tuning.lock().unwrap() = Arc::new(tuning),
+            Err(e) => println!("tuning state err: {}", e),
+        }
+    }
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct TuningGui {
+    bounds: Box3,
+    path_box: TextBox,
+    load_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+    tuning: Arc<Mutex<Arc<Tuning>>>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for TuningModule {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(TuningGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            path_box: TextBox::new(ctx.clone(), "scale.scl".into(), row(0.0)),
+            load_button: Button::new(ctx.clone(), "Load".into(), row(1.0)),
+            tuning: self.tuning.clone(),
+        })
+    }
+}
+impl GuiComponent<bool> for TuningGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.path_box.render(device, ctx);
+        self.load_button.render(device, ctx);
+        let description = self.tuning.lock().unwrap().scale.description.clone();
+        let pos = self.bounds.pos + Pt3::new(PADDING, PADDING + 2.0 * (ROW_HEIGHT + PADDING), 0.0);
+        ctx.draw_text(&description, pos, [0.7, 0.7, 0.7]);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.path_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.load_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let mut words = self.path_box.content().split_whitespace();
+                match words.next().map(String::from) {
+                    Some(scale) => {
+                        let mapping = words.next().map(String::from);
+                        self.load_button.set_label(format!("Loaded {}", scale));
+                        self.cmd_tx
+                            .unbounded_send(UserCommand::Load(scale, mapping))
+                            .unwrap();
+                    }
+                    None => self
+                        .load_button
+                        .set_label("Invalid: scale.scl [mapping.kbm]".into()),
+                }
+                true
+            }
+        }
+    }
+}
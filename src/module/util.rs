@@ -0,0 +1,191 @@
+//! Plumbing shared by modules that bridge the outside world (threads, sockets, devices) into the
+//! flow graph.
+
+use futures::channel::mpsc;
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow};
+
+use std::sync::Arc;
+
+/// Serve items from `rx` on a pull-style output port: each `()` request read from the port is
+/// answered with the next item from the channel. The task ends when all senders are dropped.
+pub fn start_source<T: Send + 'static, Ex: Spawn>(
+    mut rx: mpsc::Receiver<T>,
+    port: Arc<flow::Port<(), T>>,
+    exec: &Ex,
+) {
+    exec.spawn(async move {
+        while let Some(item) = rx.next().await {
+            if let Err((_port, err)) = answer(&port, item).await {
+                println!("source err: {:?}", err);
+            }
+        }
+    })
+    .unwrap();
+}
+
+/// Like `start_source`, but a `None` from `rx` closes the port, ending the stream for the reader,
+/// e.g. at the end of a file. Items after it start a new stream.
+pub fn start_stream_source<T: Send + 'static, Ex: Spawn>(
+    mut rx: mpsc::Receiver<Option<T>>,
+    port: Arc<flow::Port<(), T>>,
+    exec: &Ex,
+) {
+    exec.spawn(async move {
+        while let Some(item) = rx.next().await {
+            match item {
+                Some(item) => {
+                    if let Err((_port, err)) = answer(&port, item).await {
+                        println!("source err: {:?}", err);
+                    }
+                }
+                None => {
+                    let _ = port.close();
+                }
+            }
+        }
+    })
+    .unwrap();
+}
+
+/// Wait for a request on `port`, then answer it with `item`.
+async fn answer<T: Send + 'static>(
+    port: &Arc<flow::Port<(), T>>,
+    item: T,
+) -> Result<Arc<flow::Port<(), T>>, (Arc<flow::Port<(), T>>, flow::Error)> {
+    let (port, _req) = port.clone().read1().await?;
+    port.write1(item).await
+}
+
+/// Continuously pull items from an input port, handing each one to `sink`. Stops once `breaker` is
+/// braked.
+pub fn start_sink<T: 'static, F: FnMut(T) + Send + 'static, Ex: Spawn>(
+    port: Arc<flow::Port<T, ()>>,
+    sink: F,
+    breaker: Breaker,
+    exec: &Ex,
+) {
+    exec.spawn(sink_task(port, sink, breaker)).unwrap();
+}
+
+/// The task run by `start_sink`, for spawning from within another task.
+pub async fn sink_task<T: 'static, F: FnMut(T) + Send + 'static>(
+    port: Arc<flow::Port<T, ()>>,
+    mut sink: F,
+    breaker: Breaker,
+) {
+    loop {
+        // request 1 item
+        let pulled = match port.clone().write1(()).await {
+            Ok(port) => port.read1().await,
+            Err(err) => Err(err),
+        };
+        match pulled {
+            Ok((_port, item)) => sink(item),
+            Err((_port, err)) => println!("sink err: {:?}", err),
+        }
+        if breaker.test() {
+            break;
+        }
+    }
+}
+
+/// Items a processor can answer with in place of its output while its node is muted or inactive,
+/// like silent frames.
+pub trait Silence {
+    /// Replace the item with silence, keeping its shape.
+    fn silence(&mut self);
+}
+
+impl Silence for Frame {
+    fn silence(&mut self) {
+        self.data.fill(0.0);
+    }
+}
+
+macro_rules! silence_with_default {
+    ($($ty:ty),*) => {$(
+        impl Silence for $ty {
+            fn silence(&mut self) {
+                *self = Default::default();
+            }
+        }
+    )*};
+}
+
+silence_with_default!(f32, i32, bool, String);
+
+/// Answer each request on `out_port` by pulling an item, usually a frame, from `in_port` and
+/// passing it through `processor`. Stops once `breaker` is braked.
+///
+/// Like a `Processor`, a bypassed node passes the item through untouched, an inactive one answers
+/// with silence without running `processor`, and a muted one runs it but answers with silence.
+pub fn start_simple_processor<T, F, Ex>(
+    mut processor: F,
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<T, ()>>,
+    out_port: Arc<flow::Port<(), T>>,
+    breaker: Breaker,
+    exec: &Ex,
+) where
+    T: Silence + Send + 'static,
+    F: FnMut(T) -> T + Send + 'static,
+    Ex: Spawn,
+{
+    exec.spawn(async move {
+        loop {
+            if let Err(err) = process_one(&mut processor, &ifc, &in_port, &out_port).await {
+                println!("err: {}", err);
+            }
+            if breaker.test() {
+                break;
+            }
+        }
+    })
+    .unwrap();
+}
+
+/// Pull one item from `in_port` and write it to `out_port` through `processor`, once asked for it.
+async fn process_one<T: Silence + Send + 'static, F: FnMut(T) -> T>(
+    processor: &mut F,
+    ifc: &flow::Interface,
+    in_port: &Arc<flow::Port<T, ()>>,
+    out_port: &Arc<flow::Port<(), T>>,
+) -> Result<(), String> {
+    let (out_port, _) = out_port
+        .clone()
+        .read1()
+        .await
+        .map_err(|(_port, err)| format!("out read1 {:?}", err))?;
+    // a paused graph holds items here, once they've been asked for
+    ifc.graph().block_boundary().await;
+    let in_port = in_port
+        .clone()
+        .write1(())
+        .await
+        .map_err(|(_port, err)| format!("in write1 {:?}", err))?;
+    let (_in_port, frame) = in_port
+        .read1()
+        .await
+        .map_err(|(_port, err)| format!("in read1 {:?}", err))?;
+    let mut frame = if !ifc.active() {
+        let mut frame = frame;
+        frame.silence();
+        frame
+    } else if ifc.bypassed() {
+        frame
+    } else {
+        processor(frame)
+    };
+    if ifc.muted() {
+        frame.silence();
+    }
+    out_port
+        .write1(frame)
+        .await
+        .map_err(|(_port, err)| format!("out write1 {:?}", err))?;
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! Video output to other software through a pipe.
+//!
+//! Image frames (one row per line of pixels, values from 0.0 to 1.0, as drawn by the visual
+//! modules) are written as a YUV4MPEG2 stream of grayscale pictures to a file or named pipe, which
+//! ffmpeg reads directly and OBS can open as a media source:
+//!
+//! ```text
+//! mkfifo /tmp/visuals
+//! ffmpeg -i /tmp/visuals -c:v libx264 visuals.mp4
+//! ```
+//!
+//! To send visuals straight to other software on the network, see `NDI Out` in `ndi`, built with
+//! the `ndi` feature.
+//!
+//! The picture size and rate are taken from the first frame written after opening, and frames of
+//! another size are dropped. When the reader goes away the pipe is reopened, waiting for the next
+//! one.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use crate::future_ext::Breaker;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Frames waiting to be written before new ones are dropped.
+const QUEUE: usize = 2;
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The stream header for pictures the size and rate of `frame`.
+fn header(frame: &Frame) -> String {
+    let (height, width) = frame.data.dim();
+    // the rate is given as a fraction, thousandths keep fractional rates like 29.97
+    let rate = (frame.rate * 1000.0).round().max(1.0) as u64;
+    format!("YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 Cmono\n", width, height, rate)
+}
+
+/// One picture of the stream, quantized to 8 bits.
+fn picture(frame: &Frame) -> Vec<u8> {
+    let mut bytes = b"FRAME\n".to_vec();
+    for &x in frame.data.iter() {
+        bytes.push((x.max(0.0).min(1.0) * 255.0).round() as u8);
+    }
+    bytes
+}
+
+/// Write frames from `rx` to `path` until `session` is braked, reopening it whenever writing fails.
+fn write_frames(path: String, rx: std_mpsc::Receiver<Frame>, session: Breaker) {
+    while !session.test() {
+        // opening a named pipe blocks until something opens it for reading
+        let mut file = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                println!("video out open {} err: {:?}", path, e);
+                return;
+            }
+        };
+        match write_stream(&mut file, &rx, &session) {
+            Ok(()) => return,
+            Err(e) => println!("video out write err: {:?}", e),
+        }
+    }
+}
+
+/// Write a single stream to `file`, returning once `session` is braked or the frames stop.
+fn write_stream(file: &mut File, rx: &std_mpsc::Receiver<Frame>, session: &Breaker) -> io::Result<()> {
+    let mut size = None;
+    while !session.test() {
+        let frame = match rx.recv_timeout(RECV_TIMEOUT) {
+            Ok(frame) => frame,
+            Err(std_mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(std_mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        match size {
+            None => {
+                file.write_all(header(&frame).as_bytes())?;
+                size = Some(frame.data.dim());
+            }
+            Some(size) if size != frame.data.dim() => continue,
+            Some(_) => {}
+        }
+        file.write_all(&picture(&frame))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Open(String),
+}
+
+type Output = Arc<Mutex<Option<std_mpsc::SyncSender<Frame>>>>;
+
+pub struct VideoOut {
+    ifc: Arc<flow::Interface>,
+    in_port: Arc<flow::Port<Frame, ()>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    session: Arc<Mutex<Option<Breaker>>>,
+    output: Output,
+}
+
+impl Drop for VideoOut {
+    fn drop(&mut self) {
+        self.session.lock().unwrap().take().map(|session| session.brake());
+    }
+}
+
+impl Module for VideoOut {
+    fn new(ifc: Arc<flow::Interface>) -> VideoOut {
+        let in_port = ifc.get_or_create_port("Input".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        VideoOut {
+            ifc,
+            in_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            session: Arc::default(),
+            output: Arc::default(),
+        }
+    }
+    fn name() -> &'static str {
+        "Video Out"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let session_handle = self.session.clone();
+        let output_handle = self.output.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Open(path) => {
+                        // stop writing to the previous pipe before opening a new one
+                        let session = Breaker::new();
+                        let mut session_handle = session_handle.lock().unwrap();
+                        session_handle.take().map(|old| old.brake());
+                        *session_handle = Some(session.clone());
+
+                        let (tx, rx) = std_mpsc::sync_channel(QUEUE);
+                        *output_handle.lock().unwrap() = Some(tx);
+                        thread::spawn(move || write_frames(path, rx, session));
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let output = self.output.clone();
+        util::start_sink(
+            self.in_port.clone(),
+            move |frame: Frame| {
+                if let Some(ref tx) = *output.lock().unwrap() {
+                    // drop frames while the reader is behind or not there yet
+                    let _ = tx.try_send(frame);
+                }
+            },
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+        self.session.lock().unwrap().take().map(|session| session.brake());
+        *self.output.lock().unwrap() = None;
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+}
+
+#[test]
+fn test_video_out() {
+    use ndarray::Array2;
+
+    let frame = Frame {
+        rate: 29.97,
+        time: None,
+        data: Array2::from_shape_vec((2, 3), vec![0.0, 0.5, 1.0, -1.0, 2.0, 0.25]).unwrap(),
+        meta: None,
+    };
+    assert_eq!(header(&frame), "YUV4MPEG2 W3 H2 F29970:1000 Ip A1:1 Cmono\n");
+    assert_eq!(picture(&frame), b"FRAME\n\x00\x80\xff\x00\xff\x40".to_vec());
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct VideoOutGui {
+    bounds: Box3,
+    path_box: TextBox,
+    open_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for VideoOut {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        Box::new(VideoOutGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            path_box: TextBox::new(ctx.clone(), "/tmp/visuals".into(), row(0.0)),
+            open_button: Button::new(ctx.clone(), "Open".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for VideoOutGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.path_box.render(device, ctx);
+        self.open_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.path_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.open_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let path = self.path_box.content().trim().to_string();
+                if path.is_empty() {
+                    self.open_button.set_label("Invalid: path".into());
+                } else {
+                    self.open_button.set_label(format!("Writing {}", path));
+                    self.cmd_tx.unbounded_send(UserCommand::Open(path)).unwrap();
+                }
+                true
+            }
+        }
+    }
+}
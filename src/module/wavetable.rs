@@ -0,0 +1,418 @@
+//! Wavetable oscillator.
+//!
+//! A bank is a WAV file holding consecutive single-cycle waveforms. The `Position` input morphs
+//! between them and `Pitch` sets the frequency in Hz. Each waveform is stored as a set of
+//! mipmaps, one per octave, each with the harmonics that would alias at that octave removed.
+//!
+//! Oscillators have no natural block size in the pull model, so the `Input` frames act as a clock:
+//! each one is answered with a frame of the same rate and shape, and its contents are ignored.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use futures::task::{Spawn, SpawnExt};
+
+use hound;
+use num::complex::Complex32;
+
+use crate::future_ext::Breaker;
+use crate::module::fft::fft;
+use crate::module::{audio_io::Frame, flow, util, Module};
+
+use ndarray::Axis;
+use serde_json;
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const DEFAULT_CYCLE_LEN: usize = 2048;
+
+/// One waveform of a bank, band limited at every octave.
+struct MipTable {
+    /// `levels[k]` keeps at most `cycle_len / 2 >> k` harmonics.
+    levels: Vec<Vec<f32>>,
+}
+
+impl MipTable {
+    fn new(cycle: &[f32]) -> MipTable {
+        let len = cycle.len();
+        let mut spectrum: Vec<_> = cycle.iter().map(|&x| Complex32::new(x, 0.0)).collect();
+        fft(&mut spectrum, false);
+        let mut levels = Vec::new();
+        let mut harmonics = len / 2;
+        while harmonics >= 1 {
+            let mut limited: Vec<_> = spectrum
+                .iter()
+                .enumerate()
+                .map(|(bin, &x)| {
+                    // bins above len / 2 mirror the negative frequencies
+                    let harmonic = bin.min(len - bin);
+                    if harmonic <= harmonics {
+                        x
+                    } else {
+                        Complex32::new(0.0, 0.0)
+                    }
+                })
+                .collect();
+            fft(&mut limited, true);
+            levels.push(limited.iter().map(|x| x.re).collect());
+            harmonics /= 2;
+        }
+        MipTable {
+            levels,
+        }
+    }
+    /// Sample the table with linear interpolation. `phase` is in `0.0..1.0`.
+    fn sample(&self, level: usize, phase: f32) -> f32 {
+        let table = &self.levels[level.min(self.levels.len() - 1)];
+        let pos = phase * table.len() as f32;
+        let idx = pos as usize % table.len();
+        let frac = pos - pos.floor();
+        table[idx] * (1.0 - frac) + table[(idx + 1) % table.len()] * frac
+    }
+}
+
+/// A bank of band limited single-cycle waveforms.
+pub struct Wavetable {
+    cycle_len: usize,
+    tables: Vec<MipTable>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Wav(hound::Error),
+    /// The cycle length must be a power of two, and the file must hold at least one cycle.
+    InvalidLength,
+}
+
+impl From<hound::Error> for LoadError {
+    fn from(e: hound::Error) -> LoadError {
+        LoadError::Wav(e)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Wav(ref e) => write!(f, "{}", e),
+            LoadError::InvalidLength => write!(f, "invalid cycle length"),
+        }
+    }
+}
+
+impl Wavetable {
+    /// Split `samples` into waveforms of `cycle_len` samples each. A trailing partial cycle is
+    /// ignored.
+    pub fn from_samples(samples: &[f32], cycle_len: usize) -> Result<Wavetable, LoadError> {
+        if !cycle_len.is_power_of_two() || cycle_len < 2 || samples.len() < cycle_len {
+            return Err(LoadError::InvalidLength);
+        }
+        Ok(Wavetable {
+            cycle_len,
+            tables: samples
+                .chunks(cycle_len)
+                .filter(|c| c.len() == cycle_len)
+                .map(MipTable::new)
+                .collect(),
+        })
+    }
+    /// Load a bank from the first channel of a WAV file.
+    pub fn load<P: AsRef<Path>>(path: P, cycle_len: usize) -> Result<Wavetable, LoadError> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let channels = spec.channels as usize;
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|x| x.map(|x| x as f32 * scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        let first_channel: Vec<_> = samples.iter().step_by(channels).cloned().collect();
+        Wavetable::from_samples(&first_channel, cycle_len)
+    }
+    /// A two waveform bank morphing from a sine to a sawtooth.
+    pub fn sine_to_saw(cycle_len: usize) -> Wavetable {
+        let sine = (0..cycle_len).map(|i| (2.0 * PI * i as f32 / cycle_len as f32).sin());
+        let saw = (0..cycle_len).map(|i| 1.0 - 2.0 * i as f32 / cycle_len as f32);
+        let samples: Vec<_> = sine.chain(saw).collect();
+        Wavetable::from_samples(&samples, cycle_len).unwrap()
+    }
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+    /// The mipmap level that keeps all harmonics below Nyquist at `freq`.
+    fn level(&self, freq: f32, rate: f32) -> usize {
+        let max_harmonics = rate / 2.0 / freq.abs().max(1e-3);
+        let mut level = 0;
+        let mut harmonics = (self.cycle_len / 2) as f32;
+        while harmonics > max_harmonics && harmonics > 1.0 {
+            harmonics /= 2.0;
+            level += 1;
+        }
+        level
+    }
+    /// Sample at `phase` in `0.0..1.0`, morphing between waveforms by `position` in `0.0..=1.0`.
+    pub fn sample(&self, phase: f32, position: f32, freq: f32, rate: f32) -> f32 {
+        let level = self.level(freq, rate);
+        let pos = position.max(0.0).min(1.0) * (self.tables.len() - 1) as f32;
+        let idx = pos as usize;
+        let frac = pos - idx as f32;
+        let a = self.tables[idx].sample(level, phase);
+        if frac > 0.0 {
+            a * (1.0 - frac) + self.tables[idx + 1].sample(level, phase) * frac
+        } else {
+            a
+        }
+    }
+}
+
+/// Oscillator state shared between the port tasks.
+struct Oscillator {
+    table: Arc<Wavetable>,
+    /// Path and cycle length of the most recently requested bank, if any.
+    bank: Option<(String, usize)>,
+    phase: f32,
+    pitch: f32,
+    position: f32,
+}
+
+impl Oscillator {
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        let step = self.pitch / frame.rate;
+        for mut samples in frame.data.axis_iter_mut(Axis(0)) {
+            let value = self.table.sample(self.phase, self.position, self.pitch, frame.rate);
+            for sample in samples.iter_mut() {
+                *sample = value;
+            }
+            self.phase = (self.phase + step).fract();
+            if self.phase < 0.0 {
+                self.phase += 1.0;
+            }
+        }
+        frame
+    }
+}
+
+#[derive(Debug)]
+enum UserCommand {
+    Load(String, usize),
+}
+
+pub struct WavetableOsc {
+    ifc: Arc<flow::Interface>,
+    clock_port: Arc<flow::Port<Frame, ()>>,
+    pitch_port: Arc<flow::Port<f32, ()>>,
+    position_port: Arc<flow::Port<f32, ()>>,
+    out_port: Arc<flow::Port<(), Frame>>,
+    breaker: Breaker,
+    cmd_rx: Option<UnboundedReceiver<UserCommand>>,
+    cmd_tx: Option<UnboundedSender<UserCommand>>,
+    osc: Arc<Mutex<Oscillator>>,
+}
+
+impl Module for WavetableOsc {
+    fn new(ifc: Arc<flow::Interface>) -> WavetableOsc {
+        let clock_port = ifc.get_or_create_port("Input".into());
+        let pitch_port = ifc.get_or_create_port("Pitch".into());
+        let position_port = ifc.get_or_create_port("Position".into());
+        let out_port = ifc.get_or_create_port("Output".into());
+        let (cmd_tx, cmd_rx) = mpsc::unbounded();
+        WavetableOsc {
+            ifc,
+            clock_port,
+            pitch_port,
+            position_port,
+            out_port,
+            breaker: Breaker::new(),
+            cmd_rx: Some(cmd_rx),
+            cmd_tx: Some(cmd_tx),
+            osc: Arc::new(Mutex::new(Oscillator {
+                table: Arc::new(Wavetable::sine_to_saw(DEFAULT_CYCLE_LEN)),
+                bank: None,
+                phase: 0.0,
+                pitch: 220.0,
+                position: 0.0,
+            })),
+        }
+    }
+    fn name() -> &'static str {
+        "Wavetable"
+    }
+    fn start<Ex: Spawn>(&mut self, exec: Ex) {
+        let osc = self.osc.clone();
+        let mut cmd_rx = self.cmd_rx.take().unwrap();
+        exec.spawn(async move {
+            while let Some(cmd) = cmd_rx.next().await {
+                match cmd {
+                    UserCommand::Load(path, cycle_len) => {
+                        osc.lock().unwrap().bank = Some((path.clone(), cycle_len));
+                        // decoding and band limiting a large bank takes a while
+                        let osc = osc.clone();
+                        thread::spawn(move || match Wavetable::load(&path, cycle_len) {
+                            Ok(table) => osc.lock().unwrap().table = Arc::new(table),
+                            Err(e) => println!("wavetable load {} err: {}", path, e),
+                        });
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let osc = self.osc.clone();
+        util::start_sink(
+            self.pitch_port.clone(),
+            move |pitch: f32| osc.lock().unwrap().pitch = pitch,
+            self.breaker.clone(),
+            &exec,
+        );
+        let osc = self.osc.clone();
+        util::start_sink(
+            self.position_port.clone(),
+            move |position: f32| osc.lock().unwrap().position = position,
+            self.breaker.clone(),
+            &exec,
+        );
+        let osc = self.osc.clone();
+        util::start_simple_processor(
+            move |frame: Frame| -> Frame { osc.lock().unwrap().process(frame) },
+            self.ifc.clone(),
+            self.clock_port.clone(),
+            self.out_port.clone(),
+            self.breaker.clone(),
+            &exec,
+        );
+    }
+    fn stop(&mut self) {
+        self.breaker.brake();
+    }
+    fn ports(&self) -> Vec<Arc<flow::OpaquePort>> {
+        self.ifc.ports()
+    }
+    fn save_state(&self) -> serde_json::Value {
+        match self.osc.lock().unwrap().bank {
+            Some((ref path, cycle_len)) => json!({ "path": path, "cycle_len": cycle_len }),
+            None => serde_json::Value::Null,
+        }
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        let path = state["path"].as_str();
+        let cycle_len = state["cycle_len"].as_u64();
+        if let (Some(path), Some(cycle_len), Some(cmd_tx)) = (path, cycle_len, self.cmd_tx.as_ref()) {
+            // loaded once the module starts, and shown in the GUI meanwhile
+            self.osc.lock().unwrap().bank = Some((path.into(), cycle_len as usize));
+            cmd_tx.unbounded_send(UserCommand::Load(path.into(), cycle_len as usize)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_wavetable() {
+    let table = Wavetable::sine_to_saw(256);
+    assert_eq!(table.len(), 2);
+    // the sine is unchanged by band limiting at any level
+    assert!((table.sample(0.25, 0.0, 10000.0, 44100.0) - 1.0).abs() < 1e-3);
+    // halfway between a sine and a saw
+    let saw = 1.0 - 2.0 * 0.125;
+    let mixed = table.sample(0.125, 0.5, 1.0, 44100.0);
+    assert!((mixed - (0.5f32.sqrt() + saw) / 2.0).abs() < 0.05);
+
+    // at high pitch, the saw keeps only the harmonics below Nyquist
+    assert_eq!(table.level(10000.0, 44100.0), 6);
+    let level = &table.tables[1].levels[6];
+    let mut spectrum: Vec<_> = level.iter().map(|&x| Complex32::new(x, 0.0)).collect();
+    fft(&mut spectrum, false);
+    assert!(spectrum[2].norm() > 1.0);
+    assert!(spectrum[3].norm() < 1e-3);
+
+    assert!(Wavetable::from_samples(&[0.0; 100], 100).is_err());
+}
+
+#[test]
+fn test_wavetable_state() {
+    let graph = flow::Graph::new();
+    let mut osc = WavetableOsc::new(graph.add_node());
+    assert_eq!(osc.save_state(), serde_json::Value::Null);
+
+    // a saved bank is loaded again once the module starts
+    let state = json!({ "path": "bank.wav", "cycle_len": 512 });
+    osc.load_state(state.clone());
+    assert_eq!(osc.save_state(), state);
+    match osc.cmd_rx.as_mut().unwrap().try_next() {
+        Ok(Some(UserCommand::Load(path, 512))) => assert_eq!(path, "bank.wav"),
+        cmd => panic!("expected a load, got {:?}", cmd),
+    }
+    // incomplete state is ignored
+    let mut osc = WavetableOsc::new(graph.add_node());
+    osc.load_state(json!({ "path": "bank.wav" }));
+    assert_eq!(osc.save_state(), serde_json::Value::Null);
+}
+
+use gfx_device_gl as gl;
+use crate::gui::{button::*, component::*, event::*, geom::*, module_gui::*, render::*, textbox::*};
+struct WavetableGui {
+    bounds: Box3,
+    path_box: TextBox,
+    load_button: Button,
+    cmd_tx: UnboundedSender<UserCommand>,
+}
+const PADDING: f32 = 4.0;
+const ROW_HEIGHT: f32 = 26.0;
+impl ModuleGui for WavetableOsc {
+    fn new_body(&mut self, ctx: &mut RenderContext, bounds: Box3) -> Box<dyn GuiComponent<BodyUpdate>> {
+        let row = |idx: f32| Box3 {
+            pos: bounds.pos + Pt3::new(PADDING, PADDING + idx * (ROW_HEIGHT + PADDING), 0.0),
+            size: Pt3::new(bounds.size.x - PADDING * 2.0, ROW_HEIGHT, 0.0),
+        };
+        let bank = match self.osc.lock().unwrap().bank {
+            Some((ref path, cycle_len)) => format!("{} {}", path, cycle_len),
+            None => format!("bank.wav {}", DEFAULT_CYCLE_LEN),
+        };
+        Box::new(WavetableGui {
+            cmd_tx: self.cmd_tx.take().unwrap(),
+            bounds,
+            path_box: TextBox::new(ctx.clone(), bank, row(0.0)),
+            load_button: Button::new(ctx.clone(), "Load".into(), row(1.0)),
+        })
+    }
+}
+impl GuiComponent<bool> for WavetableGui {
+    fn set_bounds(&mut self, bounds: Box3) {
+        self.bounds = bounds;
+    }
+    fn bounds(&self) -> Box3 {
+        self.bounds
+    }
+    fn render(&mut self, device: &mut gl::Device, ctx: &mut RenderContext) {
+        self.path_box.render(device, ctx);
+        self.load_button.render(device, ctx);
+    }
+    fn handle(&mut self, event: &Event) -> BodyUpdate {
+        let dirty = self.path_box.handle(event) != TextBoxUpdate::Unchanged;
+        match self.load_button.handle(event) {
+            ButtonUpdate::Unchanged => dirty,
+            ButtonUpdate::NeedRender => true,
+            ButtonUpdate::Clicked => {
+                let mut words = self.path_box.content().split_whitespace();
+                let path = words.next().map(String::from);
+                let cycle_len = words
+                    .next()
+                    .map(|w| w.parse().ok())
+                    .unwrap_or(Some(DEFAULT_CYCLE_LEN));
+                match (path, cycle_len) {
+                    (Some(path), Some(cycle_len)) => {
+                        self.load_button.set_label(format!("Loaded {}", path));
+                        self.cmd_tx.unbounded_send(UserCommand::Load(path, cycle_len)).unwrap();
+                    }
+                    _ => self.load_button.set_label("Invalid: path [cycle length]".into()),
+                }
+                true
+            }
+        }
+    }
+}
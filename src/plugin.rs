@@ -0,0 +1,333 @@
+//! Running a patch inside another host, as an audio plugin.
+//!
+//! A `PatchPlugin` builds a saved patch without a window, like an installation, with its
+//! `BlockAudioIO` node standing in for the plugin host: it isn't started, and instead `process`
+//! runs the blocks feeding it once per buffer on the host's audio thread, with the host's input as
+//! the capture. Every parameter of every node, as scenes see them, is a plugin parameter, in the
+//! order of the nodes and then their parameters, so a generative patch can be automated from a DAW.
+//! The plugin's own state is the patch path with the parameter values.
+//!
+//! This is the part of a plugin that doesn't depend on the plugin API. The library is also built as
+//! a `cdylib` exporting it as a CLAP plugin, see `plugin::clap`. There's no LV2 export, since LV2
+//! wants the plugin's ports and parameters listed in the bundle's Turtle files, which would have
+//! to be written for each patch.
+
+#[cfg(feature = "clap")]
+pub mod clap;
+
+use futures::executor::ThreadPool;
+
+use crate::gui::root::serial;
+use crate::install::DynModule;
+use crate::module::scheduler::{BlockAudioIO, BlockScheduler};
+use crate::module::{audio_io::Frame, flow, Module};
+use crate::registry::Registry;
+
+use ndarray::Array2;
+
+use serde_json::{Map, Value};
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Why a patch couldn't be loaded as a plugin.
+#[derive(Debug)]
+pub enum Error {
+    Load(serial::Error),
+    /// The patch has no `BlockAudioIO` node to play the host.
+    NoHost,
+}
+
+impl From<serial::Error> for Error {
+    fn from(e: serial::Error) -> Error {
+        Error::Load(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Load(e.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Load(ref e) => write!(f, "{}", e),
+            Error::NoHost => write!(f, "the patch has no {} node", BlockAudioIO::name()),
+        }
+    }
+}
+
+/// A plugin parameter, standing for the parameter `name` of `node`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub node: flow::NodeId,
+    pub name: String,
+    /// What the host shows, the node's type with its id and the parameter.
+    pub label: String,
+}
+
+pub struct PatchPlugin {
+    path: Option<PathBuf>,
+    graph: Arc<flow::Graph>,
+    modules: Vec<Box<dyn DynModule>>,
+    scheduler: BlockScheduler,
+    params: Vec<Param>,
+    /// Samples processed, for the time of the capture frames.
+    time: u64,
+}
+
+impl PatchPlugin {
+    /// Load the patch at `path`. Modules of types this build doesn't have are an error, since a
+    /// plugin missing part of its patch would quietly sound different.
+    pub fn open(path: &Path, exec: ThreadPool) -> Result<PatchPlugin, Error> {
+        let registry = Registry::standard();
+        let root = serial::read(
+            File::open(path)?,
+            &serial::Migrations::standard(),
+            &registry,
+            false,
+        )?;
+        let mut plugin = PatchPlugin::new(root, &registry, exec)?;
+        plugin.path = Some(path.to_path_buf());
+        Ok(plugin)
+    }
+
+    /// Build and start the patch in `root`, all but the host node.
+    pub fn new(root: serial::Root, registry: &Registry, exec: ThreadPool) -> Result<PatchPlugin, Error> {
+        let unknown = root.unknown_types(registry);
+        if !unknown.is_empty() {
+            return Err(serial::Error::UnknownModules(unknown).into());
+        }
+        let host = root
+            .modules
+            .iter()
+            .find(|saved| saved.type_name == BlockAudioIO::name())
+            .map(|saved| saved.id)
+            .ok_or(Error::NoHost)?;
+
+        let graph = flow::Graph::new();
+        let mut modules = Vec::new();
+        let mut params = Vec::new();
+        for saved in root.modules {
+            let factory = registry.factory(&saved.type_name).unwrap();
+            let mut module = factory.new_headless(graph.add_node_with_id(saved.id));
+            let node = graph.node(saved.id).unwrap();
+            for tag in &saved.tags {
+                node.add_tag(tag);
+            }
+            for (key, value) in saved.meta {
+                node.set_meta(&key, value);
+            }
+            node.set_muted(saved.muted);
+            node.set_bypassed(saved.bypassed);
+            node.set_active(saved.active);
+            if !saved.state.is_null() {
+                module.load_state(saved.state);
+            }
+            if let Some(node_params) = node.params() {
+                for name in node_params.names() {
+                    params.push(Param {
+                        node: saved.id,
+                        label: format!("{} {}: {}", saved.type_name, saved.id.0, name),
+                        name,
+                    });
+                }
+            }
+            if saved.id != host {
+                modules.push(module);
+            }
+        }
+        for connection in &root.connections {
+            let port = |node: flow::NodeId, name: &str| {
+                graph
+                    .node(node)
+                    .and_then(|node| node.ports().into_iter().find(|port| port.name() == name))
+            };
+            let src = port(connection.src_node, &connection.src_port);
+            let dst = port(connection.dst_node, &connection.dst_port);
+            if let (Some(src), Some(dst)) = (src, dst) {
+                if let Err(e) = src.connect(&dst) {
+                    println!("plugin connect err: {:?}", e);
+                }
+            }
+        }
+        for module in &mut modules {
+            module.start(exec.clone());
+        }
+        Ok(PatchPlugin {
+            path: None,
+            scheduler: BlockScheduler::new(graph.clone(), host),
+            graph,
+            modules,
+            params,
+            time: 0,
+        })
+    }
+
+    pub fn graph(&self) -> &Arc<flow::Graph> {
+        &self.graph
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    pub fn get_param(&self, index: usize) -> Option<f32> {
+        let param = self.params.get(index)?;
+        self.graph.node(param.node)?.params()?.get(&param.name)
+    }
+
+    pub fn set_param(&self, index: usize, value: f32) {
+        let param = match self.params.get(index) {
+            Some(param) => param,
+            None => return,
+        };
+        if let Some(params) = self.graph.node(param.node).and_then(|node| node.params()) {
+            params.set(&param.name, value);
+        }
+    }
+
+    /// Run one buffer, reading each of the host's input channels and writing each output channel.
+    /// Channels beyond those of the patch's output are silent.
+    pub fn process(&mut self, rate: f32, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) {
+        let len = outputs.iter().map(|output| output.len()).min().unwrap_or(0);
+        let mut data = Array2::zeros((len, inputs.len()));
+        for (channel, input) in inputs.iter().enumerate() {
+            for (row, &x) in input.iter().take(len).enumerate() {
+                data[[row, channel]] = x;
+            }
+        }
+        let capture = Frame {
+            rate,
+            time: Some(self.time),
+            data,
+            meta: None,
+        };
+        self.time += len as u64;
+        let frame = self.scheduler.run(&capture);
+        let (rows, channels) = frame.data.dim();
+        for (channel, output) in outputs.iter_mut().enumerate() {
+            for (row, y) in output.iter_mut().take(len).enumerate() {
+                *y = if channel < channels && row < rows {
+                    frame.data[[row, channel]]
+                } else {
+                    0.0
+                };
+            }
+        }
+    }
+
+    /// The state for the host to save with its session.
+    pub fn save_state(&self) -> Value {
+        let params: Map<String, Value> = self
+            .params
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, param)| {
+                self.get_param(idx)
+                    .map(|value| (param.label.clone(), json!(value)))
+            })
+            .collect();
+        json!({ "patch": self.path, "params": params })
+    }
+
+    /// Restore the parameter values of a saved session. The patch itself is opened by the host
+    /// glue, from the `patch` of the state.
+    pub fn load_state(&self, state: &Value) {
+        let params = match state["params"].as_object() {
+            Some(params) => params,
+            None => return,
+        };
+        for idx in 0..self.params.len() {
+            if let Some(value) = params
+                .get(&self.params[idx].label)
+                .and_then(|value| value.as_f64())
+            {
+                self.set_param(idx, value as f32);
+            }
+        }
+    }
+}
+
+impl Drop for PatchPlugin {
+    fn drop(&mut self) {
+        for module in &mut self.modules {
+            module.stop();
+        }
+    }
+}
+
+#[test]
+fn test_plugin() {
+    use crate::gui::geom::Box3;
+    use std::collections::BTreeMap;
+
+    let module = |id, type_name: &str| serial::Module {
+        bounds: Box3::default(),
+        id: flow::NodeId(id),
+        type_name: type_name.into(),
+        tags: Vec::new(),
+        muted: false,
+        bypassed: false,
+        state: Value::Null,
+        active: true,
+        meta: BTreeMap::new(),
+    };
+    let connection = |src_node, src_port: &str, dst_node, dst_port: &str| serial::Connection {
+        src_node: flow::NodeId(src_node),
+        src_port: src_port.into(),
+        dst_node: flow::NodeId(dst_node),
+        dst_port: dst_port.into(),
+        gain: None,
+    };
+    let root = serial::Root {
+        version: serial::VERSION,
+        modules: vec![module(1, "BlockAudioIO"), module(2, "Gain")],
+        connections: vec![
+            connection(1, "Output", 2, "Input"),
+            connection(2, "Output", 1, "Input"),
+        ],
+        scenes: Vec::new(),
+        mappings: Vec::new(),
+    };
+    let registry = Registry::standard();
+    let mut plugin = PatchPlugin::new(root, &registry, ThreadPool::new().unwrap()).unwrap();
+    assert_eq!(plugin.params().len(), 1);
+    assert_eq!(plugin.params()[0].label, "Gain 2: Gain");
+    plugin.set_param(0, 0.5);
+    assert_eq!(plugin.get_param(0), Some(0.5));
+
+    let input = vec![1.0; 64];
+    let mut left = vec![0.0; 64];
+    let mut right = vec![0.0; 64];
+    // long enough for the connections to have faded in
+    for _ in 0..8 {
+        let mut outputs: Vec<&mut [f32]> = vec![&mut left, &mut right];
+        plugin.process(48000.0, &[&input, &input], &mut outputs);
+    }
+    assert!(left.iter().chain(&right).all(|&x| x == 0.5));
+
+    let state = plugin.save_state();
+    plugin.set_param(0, 1.0);
+    plugin.load_state(&state);
+    assert_eq!(plugin.get_param(0), Some(0.5));
+
+    let root = serial::Root {
+        version: serial::VERSION,
+        modules: vec![module(2, "Gain")],
+        connections: Vec::new(),
+        scenes: Vec::new(),
+        mappings: Vec::new(),
+    };
+    assert!(
+        match PatchPlugin::new(root, &registry, ThreadPool::new().unwrap()) {
+            Err(Error::NoHost) => true,
+            _ => false,
+        }
+    );
+}
@@ -0,0 +1,647 @@
+//! The CLAP entry point of the `cdylib`, exporting a patch as a plugin.
+//!
+//! The library is installed as `<name>.clap` with the patch next to it as `<name>.ron`, and then
+//! has one plugin, `flow-synth.<name>`, playing that patch through a `PatchPlugin`. It has one
+//! stereo input and one stereo output, and every parameter of the patch as a plugin parameter,
+//! by its index, from 0 to 1 since nodes don't declare ranges. The plugin's state is that of the
+//! `PatchPlugin`, as JSON.
+//!
+//! Built with the `clap` feature.
+
+use futures::executor::ThreadPool;
+
+use crate::plugin::PatchPlugin;
+
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::{
+    clap_event_header, clap_event_param_value, clap_input_events, clap_output_events,
+    CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_PARAM_VALUE,
+};
+use clap_sys::ext::audio_ports::{
+    clap_audio_port_info, clap_plugin_audio_ports, CLAP_AUDIO_PORT_IS_MAIN, CLAP_EXT_AUDIO_PORTS,
+    CLAP_PORT_STEREO,
+};
+use clap_sys::ext::params::{
+    clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS, CLAP_PARAM_IS_AUTOMATABLE,
+};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::host::clap_host;
+use clap_sys::id::CLAP_INVALID_ID;
+use clap_sys::plugin::{clap_plugin, clap_plugin_descriptor};
+use clap_sys::plugin_features::CLAP_PLUGIN_FEATURE_AUDIO_EFFECT;
+use clap_sys::process::{clap_process, clap_process_status, CLAP_PROCESS_CONTINUE, CLAP_PROCESS_ERROR};
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::CLAP_VERSION;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+/// Channels of the plugin's input and output.
+const CHANNELS: usize = 2;
+
+const VENDOR: &[u8] = b"flow-synth\0";
+const URL: &[u8] = b"https://github.com/flipscholtz/flow-synth\0";
+const VERSION: &[u8] = b"0.1.0\0";
+
+/// The exported plugin's descriptor, with the strings it points to.
+struct Export {
+    patch: PathBuf,
+    _id: CString,
+    _name: CString,
+    _features: Box<[*const c_char; 2]>,
+    descriptor: Box<clap_plugin_descriptor>,
+}
+
+unsafe impl Send for Export {}
+
+impl Export {
+    /// The export of the patch next to the library at `path`.
+    fn new(path: &Path) -> Option<Export> {
+        let patch = path.with_extension("ron");
+        if !patch.is_file() {
+            return None;
+        }
+        let stem = path.file_stem()?.to_string_lossy().into_owned();
+        let id = CString::new(format!("flow-synth.{}", stem)).ok()?;
+        let name = CString::new(stem).ok()?;
+        let features = Box::new([CLAP_PLUGIN_FEATURE_AUDIO_EFFECT.as_ptr(), ptr::null()]);
+        let descriptor = Box::new(clap_plugin_descriptor {
+            clap_version: CLAP_VERSION,
+            id: id.as_ptr(),
+            name: name.as_ptr(),
+            vendor: VENDOR.as_ptr() as *const c_char,
+            url: URL.as_ptr() as *const c_char,
+            manual_url: ptr::null(),
+            support_url: ptr::null(),
+            version: VERSION.as_ptr() as *const c_char,
+            description: ptr::null(),
+            features: features.as_ptr(),
+        });
+        Some(Export {
+            patch,
+            _id: id,
+            _name: name,
+            _features: features,
+            descriptor,
+        })
+    }
+}
+
+/// Set by the host calling `init`, until `deinit`.
+static EXPORT: Mutex<Option<Export>> = Mutex::new(None);
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static clap_entry: clap_plugin_entry = clap_plugin_entry {
+    clap_version: CLAP_VERSION,
+    init: Some(entry_init),
+    deinit: Some(entry_deinit),
+    get_factory: Some(entry_get_factory),
+};
+
+unsafe extern "C" fn entry_init(plugin_path: *const c_char) -> bool {
+    if plugin_path.is_null() {
+        return false;
+    }
+    let path = PathBuf::from(CStr::from_ptr(plugin_path).to_string_lossy().into_owned());
+    *EXPORT.lock().unwrap() = Export::new(&path);
+    true
+}
+
+unsafe extern "C" fn entry_deinit() {
+    *EXPORT.lock().unwrap() = None;
+}
+
+static FACTORY: clap_plugin_factory = clap_plugin_factory {
+    get_plugin_count: Some(factory_count),
+    get_plugin_descriptor: Some(factory_descriptor),
+    create_plugin: Some(factory_create),
+};
+
+unsafe extern "C" fn entry_get_factory(factory_id: *const c_char) -> *const c_void {
+    if CStr::from_ptr(factory_id) == CLAP_PLUGIN_FACTORY_ID {
+        &FACTORY as *const clap_plugin_factory as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn factory_count(_factory: *const clap_plugin_factory) -> u32 {
+    EXPORT.lock().unwrap().is_some() as u32
+}
+
+unsafe extern "C" fn factory_descriptor(
+    _factory: *const clap_plugin_factory,
+    index: u32,
+) -> *const clap_plugin_descriptor {
+    match *EXPORT.lock().unwrap() {
+        Some(ref export) if index == 0 => &*export.descriptor,
+        _ => ptr::null(),
+    }
+}
+
+unsafe extern "C" fn factory_create(
+    _factory: *const clap_plugin_factory,
+    _host: *const clap_host,
+    plugin_id: *const c_char,
+) -> *const clap_plugin {
+    let export = EXPORT.lock().unwrap();
+    let export = match *export {
+        Some(ref export) if CStr::from_ptr(plugin_id) == CStr::from_ptr(export.descriptor.id) => export,
+        _ => return ptr::null(),
+    };
+    let plugin = Box::into_raw(Box::new(Exported {
+        clap: clap_plugin {
+            desc: &*export.descriptor,
+            plugin_data: ptr::null_mut(),
+            init: Some(plugin_init),
+            destroy: Some(plugin_destroy),
+            activate: Some(plugin_activate),
+            deactivate: Some(plugin_deactivate),
+            start_processing: Some(plugin_start_processing),
+            stop_processing: Some(plugin_stop_processing),
+            reset: Some(plugin_reset),
+            process: Some(plugin_process),
+            get_extension: Some(plugin_get_extension),
+            on_main_thread: Some(plugin_on_main_thread),
+        },
+        path: export.patch.clone(),
+        patch: None,
+        rate: 0.0,
+    }));
+    (*plugin).clap.plugin_data = plugin as *mut c_void;
+    &(*plugin).clap
+}
+
+/// An instance of the exported plugin. The host only sees the `clap_plugin`.
+struct Exported {
+    clap: clap_plugin,
+    path: PathBuf,
+    /// Opened by `init`. Locked since the host may ask for parameters on its main thread while
+    /// the audio thread processes.
+    patch: Option<Mutex<PatchPlugin>>,
+    rate: f32,
+}
+
+unsafe fn exported<'a>(plugin: *const clap_plugin) -> &'a Exported {
+    &*((*plugin).plugin_data as *const Exported)
+}
+
+/// For `init` and `activate`, which CLAP never calls while the plugin is processing.
+unsafe fn exported_mut<'a>(plugin: *const clap_plugin) -> &'a mut Exported {
+    &mut *((*plugin).plugin_data as *mut Exported)
+}
+
+unsafe extern "C" fn plugin_init(plugin: *const clap_plugin) -> bool {
+    let exported = exported_mut(plugin);
+    let exec = match ThreadPool::new() {
+        Ok(exec) => exec,
+        Err(e) => {
+            println!("plugin init err: {}", e);
+            return false;
+        }
+    };
+    match PatchPlugin::open(&exported.path, exec) {
+        Ok(patch) => {
+            exported.patch = Some(Mutex::new(patch));
+            true
+        }
+        Err(e) => {
+            println!("plugin init err: {}: {}", exported.path.display(), e);
+            false
+        }
+    }
+}
+
+unsafe extern "C" fn plugin_destroy(plugin: *const clap_plugin) {
+    drop(Box::from_raw((*plugin).plugin_data as *mut Exported));
+}
+
+unsafe extern "C" fn plugin_activate(
+    plugin: *const clap_plugin,
+    sample_rate: f64,
+    _min_frames: u32,
+    _max_frames: u32,
+) -> bool {
+    exported_mut(plugin).rate = sample_rate as f32;
+    true
+}
+
+unsafe extern "C" fn plugin_deactivate(_plugin: *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_start_processing(_plugin: *const clap_plugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_stop_processing(_plugin: *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_reset(_plugin: *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_on_main_thread(_plugin: *const clap_plugin) {}
+
+/// Set the parameters the host changed, by their index.
+unsafe fn apply_events(patch: &PatchPlugin, events: *const clap_input_events) {
+    let events = match events.as_ref() {
+        Some(events) => events,
+        None => return,
+    };
+    let (size, get) = match (events.size, events.get) {
+        (Some(size), Some(get)) => (size, get),
+        _ => return,
+    };
+    for idx in 0..size(events) {
+        let header = match get(events, idx).as_ref() {
+            Some(header) => header,
+            None => continue,
+        };
+        if header.space_id == CLAP_CORE_EVENT_SPACE_ID && header.type_ == CLAP_EVENT_PARAM_VALUE {
+            let event = &*(header as *const clap_event_header as *const clap_event_param_value);
+            patch.set_param(event.param_id as usize, event.value as f32);
+        }
+    }
+}
+
+unsafe extern "C" fn plugin_process(
+    plugin: *const clap_plugin,
+    process: *const clap_process,
+) -> clap_process_status {
+    let exported = exported(plugin);
+    let process = &*process;
+    let mut patch = match exported.patch {
+        Some(ref patch) => patch.lock().unwrap(),
+        None => return CLAP_PROCESS_ERROR,
+    };
+    apply_events(&patch, process.in_events);
+    let len = process.frames_count as usize;
+    let inputs: Vec<&[f32]> = if process.audio_inputs_count > 0 {
+        let buffer = &*process.audio_inputs;
+        (0..buffer.channel_count as usize)
+            .map(|channel| slice::from_raw_parts(*buffer.data32.add(channel), len))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut outputs: Vec<&mut [f32]> = if process.audio_outputs_count > 0 {
+        let buffer = &*process.audio_outputs;
+        (0..buffer.channel_count as usize)
+            .map(|channel| slice::from_raw_parts_mut(*buffer.data32.add(channel), len))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    patch.process(exported.rate, &inputs, &mut outputs);
+    CLAP_PROCESS_CONTINUE
+}
+
+static AUDIO_PORTS: clap_plugin_audio_ports = clap_plugin_audio_ports {
+    count: Some(audio_ports_count),
+    get: Some(audio_ports_get),
+};
+
+static PARAMS: clap_plugin_params = clap_plugin_params {
+    count: Some(params_count),
+    get_info: Some(params_get_info),
+    get_value: Some(params_get_value),
+    value_to_text: None,
+    text_to_value: None,
+    flush: Some(params_flush),
+};
+
+static STATE: clap_plugin_state = clap_plugin_state {
+    save: Some(state_save),
+    load: Some(state_load),
+};
+
+unsafe extern "C" fn plugin_get_extension(_plugin: *const clap_plugin, id: *const c_char) -> *const c_void {
+    let id = CStr::from_ptr(id);
+    if id == CLAP_EXT_AUDIO_PORTS {
+        &AUDIO_PORTS as *const clap_plugin_audio_ports as *const c_void
+    } else if id == CLAP_EXT_PARAMS {
+        &PARAMS as *const clap_plugin_params as *const c_void
+    } else if id == CLAP_EXT_STATE {
+        &STATE as *const clap_plugin_state as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+/// Copy `s` into a fixed size C string, cutting it short if it doesn't fit.
+fn copy_name(s: &str, dst: &mut [c_char]) {
+    let len = s.len().min(dst.len() - 1);
+    for (dst, &byte) in dst.iter_mut().zip(&s.as_bytes()[..len]) {
+        *dst = byte as c_char;
+    }
+    dst[len] = 0;
+}
+
+unsafe extern "C" fn audio_ports_count(_plugin: *const clap_plugin, _is_input: bool) -> u32 {
+    1
+}
+
+unsafe extern "C" fn audio_ports_get(
+    _plugin: *const clap_plugin,
+    index: u32,
+    is_input: bool,
+    info: *mut clap_audio_port_info,
+) -> bool {
+    if index != 0 {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = 0;
+    copy_name(if is_input { "Input" } else { "Output" }, &mut info.name);
+    info.flags = CLAP_AUDIO_PORT_IS_MAIN;
+    info.channel_count = CHANNELS as u32;
+    info.port_type = CLAP_PORT_STEREO.as_ptr();
+    info.in_place_pair = CLAP_INVALID_ID;
+    true
+}
+
+unsafe extern "C" fn params_count(plugin: *const clap_plugin) -> u32 {
+    match exported(plugin).patch {
+        Some(ref patch) => patch.lock().unwrap().params().len() as u32,
+        None => 0,
+    }
+}
+
+unsafe extern "C" fn params_get_info(
+    plugin: *const clap_plugin,
+    index: u32,
+    info: *mut clap_param_info,
+) -> bool {
+    let patch = match exported(plugin).patch {
+        Some(ref patch) => patch.lock().unwrap(),
+        None => return false,
+    };
+    let param = match patch.params().get(index as usize) {
+        Some(param) => param,
+        None => return false,
+    };
+    let info = &mut *info;
+    info.id = index;
+    info.flags = CLAP_PARAM_IS_AUTOMATABLE;
+    info.cookie = ptr::null_mut();
+    copy_name(&param.label, &mut info.name);
+    copy_name("", &mut info.module);
+    info.min_value = 0.0;
+    info.max_value = 1.0;
+    info.default_value = patch.get_param(index as usize).unwrap_or(0.0) as f64;
+    true
+}
+
+unsafe extern "C" fn params_get_value(plugin: *const clap_plugin, param_id: u32, value: *mut f64) -> bool {
+    let param = match exported(plugin).patch {
+        Some(ref patch) => patch.lock().unwrap().get_param(param_id as usize),
+        None => None,
+    };
+    match param {
+        Some(param) => {
+            *value = param as f64;
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn params_flush(
+    plugin: *const clap_plugin,
+    in_events: *const clap_input_events,
+    _out_events: *const clap_output_events,
+) {
+    if let Some(ref patch) = exported(plugin).patch {
+        apply_events(&patch.lock().unwrap(), in_events);
+    }
+}
+
+unsafe extern "C" fn state_save(plugin: *const clap_plugin, stream: *const clap_ostream) -> bool {
+    let state = match exported(plugin).patch {
+        Some(ref patch) => patch.lock().unwrap().save_state(),
+        None => return false,
+    };
+    let data = state.to_string().into_bytes();
+    let write = match (*stream).write {
+        Some(write) => write,
+        None => return false,
+    };
+    let mut written = 0;
+    while written < data.len() {
+        let len = write(
+            stream,
+            data[written..].as_ptr() as *const c_void,
+            (data.len() - written) as u64,
+        );
+        if len <= 0 {
+            return false;
+        }
+        written += len as usize;
+    }
+    true
+}
+
+unsafe extern "C" fn state_load(plugin: *const clap_plugin, stream: *const clap_istream) -> bool {
+    let read = match (*stream).read {
+        Some(read) => read,
+        None => return false,
+    };
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let len = read(stream, buffer.as_mut_ptr() as *mut c_void, buffer.len() as u64);
+        if len < 0 {
+            return false;
+        } else if len == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..len as usize]);
+    }
+    let state = match serde_json::from_slice(&data) {
+        Ok(state) => state,
+        Err(_) => return false,
+    };
+    match exported(plugin).patch {
+        Some(ref patch) => {
+            patch.lock().unwrap().load_state(&state);
+            true
+        }
+        None => false,
+    }
+}
+
+#[test]
+fn test_clap_entry() {
+    use crate::gui::geom::Box3;
+    use crate::gui::root::serial;
+    use crate::module::flow::NodeId;
+    use clap_sys::audio_buffer::clap_audio_buffer;
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    let module = |id, type_name: &str| serial::Module {
+        bounds: Box3::default(),
+        id: NodeId(id),
+        type_name: type_name.into(),
+        tags: Vec::new(),
+        muted: false,
+        bypassed: false,
+        state: Value::Null,
+        active: true,
+        meta: BTreeMap::new(),
+    };
+    let connection = |src_node, src_port: &str, dst_node, dst_port: &str| serial::Connection {
+        src_node: NodeId(src_node),
+        src_port: src_port.into(),
+        dst_node: NodeId(dst_node),
+        dst_port: dst_port.into(),
+        gain: None,
+    };
+    let root = serial::Root {
+        version: serial::VERSION,
+        modules: vec![module(1, "BlockAudioIO"), module(2, "Gain")],
+        connections: vec![
+            connection(1, "Output", 2, "Input"),
+            connection(2, "Output", 1, "Input"),
+        ],
+        scenes: Vec::new(),
+        mappings: Vec::new(),
+    };
+    let dir = std::env::temp_dir().join(format!("flow-synth-clap-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("gain.ron"), ron::ser::to_string(&root).unwrap()).unwrap();
+    let path = CString::new(dir.join("gain.clap").to_string_lossy().into_owned()).unwrap();
+
+    unsafe {
+        assert!((clap_entry.init.unwrap())(path.as_ptr()));
+        let factory = &*((clap_entry.get_factory.unwrap())(CLAP_PLUGIN_FACTORY_ID.as_ptr())
+            as *const clap_plugin_factory);
+        assert_eq!((factory.get_plugin_count.unwrap())(factory), 1);
+        let desc = &*(factory.get_plugin_descriptor.unwrap())(factory, 0);
+        assert_eq!(CStr::from_ptr(desc.id).to_str().unwrap(), "flow-synth.gain");
+        let plugin = (factory.create_plugin.unwrap())(factory, ptr::null(), desc.id);
+        assert!(!plugin.is_null());
+        let plugin = &*plugin;
+        assert!((plugin.init.unwrap())(plugin));
+
+        let params = &*((plugin.get_extension.unwrap())(plugin, CLAP_EXT_PARAMS.as_ptr())
+            as *const clap_plugin_params);
+        assert_eq!((params.count.unwrap())(plugin), 1);
+        let mut info: clap_param_info = std::mem::zeroed();
+        assert!((params.get_info.unwrap())(plugin, 0, &mut info));
+        assert_eq!(
+            CStr::from_ptr(info.name.as_ptr()).to_str().unwrap(),
+            "Gain 2: Gain"
+        );
+
+        // the gain comes in as an event with the first block
+        let events = vec![clap_event_param_value {
+            header: clap_event_header {
+                size: std::mem::size_of::<clap_event_param_value>() as u32,
+                time: 0,
+                space_id: CLAP_CORE_EVENT_SPACE_ID,
+                type_: CLAP_EVENT_PARAM_VALUE,
+                flags: 0,
+            },
+            param_id: 0,
+            cookie: ptr::null_mut(),
+            note_id: -1,
+            port_index: -1,
+            channel: -1,
+            key: -1,
+            value: 0.5,
+        }];
+        unsafe extern "C" fn size(list: *const clap_input_events) -> u32 {
+            (*((*list).ctx as *const Vec<clap_event_param_value>)).len() as u32
+        }
+        unsafe extern "C" fn get(list: *const clap_input_events, idx: u32) -> *const clap_event_header {
+            let events = &*((*list).ctx as *const Vec<clap_event_param_value>);
+            &events[idx as usize].header
+        }
+        let in_events = clap_input_events {
+            ctx: &events as *const Vec<clap_event_param_value> as *mut c_void,
+            size: Some(size),
+            get: Some(get),
+        };
+        let no_events = clap_input_events {
+            ctx: &Vec::<clap_event_param_value>::new() as *const Vec<clap_event_param_value> as *mut c_void,
+            size: Some(size),
+            get: Some(get),
+        };
+
+        assert!((plugin.activate.unwrap())(plugin, 48000.0, 1, 64));
+        assert!((plugin.start_processing.unwrap())(plugin));
+        let mut input = vec![vec![1.0f32; 64]; 2];
+        let mut output = vec![vec![0.0f32; 64]; 2];
+        // long enough for the connections to have faded in
+        for block in 0..8 {
+            let mut in_ptrs: Vec<*mut f32> = input.iter_mut().map(|channel| channel.as_mut_ptr()).collect();
+            let mut out_ptrs: Vec<*mut f32> = output.iter_mut().map(|channel| channel.as_mut_ptr()).collect();
+            let buffer = |pointers: &mut Vec<*mut f32>| clap_audio_buffer {
+                data32: pointers.as_mut_ptr(),
+                data64: ptr::null_mut(),
+                channel_count: 2,
+                latency: 0,
+                constant_mask: 0,
+            };
+            let audio_in = buffer(&mut in_ptrs);
+            let mut audio_out = buffer(&mut out_ptrs);
+            let process = clap_process {
+                steady_time: block * 64,
+                frames_count: 64,
+                transport: ptr::null(),
+                audio_inputs: &audio_in,
+                audio_outputs: &mut audio_out,
+                audio_inputs_count: 1,
+                audio_outputs_count: 1,
+                in_events: if block == 0 { &in_events } else { &no_events },
+                out_events: ptr::null(),
+            };
+            assert_eq!((plugin.process.unwrap())(plugin, &process), CLAP_PROCESS_CONTINUE);
+        }
+        assert!(output.iter().flatten().all(|&x| x == 0.5));
+
+        // the state goes through the host's streams
+        let state =
+            &*((plugin.get_extension.unwrap())(plugin, CLAP_EXT_STATE.as_ptr()) as *const clap_plugin_state);
+        unsafe extern "C" fn write(stream: *const clap_ostream, buffer: *const c_void, size: u64) -> i64 {
+            let data = &mut *((*stream).ctx as *mut Vec<u8>);
+            data.extend_from_slice(slice::from_raw_parts(buffer as *const u8, size as usize));
+            size as i64
+        }
+        unsafe extern "C" fn read(stream: *const clap_istream, buffer: *mut c_void, size: u64) -> i64 {
+            let reader = &mut *((*stream).ctx as *mut &[u8]);
+            let len = reader.len().min(size as usize);
+            ptr::copy_nonoverlapping(reader.as_ptr(), buffer as *mut u8, len);
+            *reader = &reader[len..];
+            len as i64
+        }
+        let mut data: Vec<u8> = Vec::new();
+        let ostream = clap_ostream {
+            ctx: &mut data as *mut Vec<u8> as *mut c_void,
+            write: Some(write),
+        };
+        assert!((state.save.unwrap())(plugin, &ostream));
+        if let Some(ref patch) = exported(plugin).patch {
+            patch.lock().unwrap().set_param(0, 1.0);
+        }
+        let mut reader: &[u8] = &data;
+        let istream = clap_istream {
+            ctx: &mut reader as *mut &[u8] as *mut c_void,
+            read: Some(read),
+        };
+        assert!((state.load.unwrap())(plugin, &istream));
+        let mut value = 0.0;
+        assert!((params.get_value.unwrap())(plugin, 0, &mut value));
+        assert_eq!(value, 0.5);
+
+        (plugin.stop_processing.unwrap())(plugin);
+        (plugin.deactivate.unwrap())(plugin);
+        (plugin.destroy.unwrap())(plugin);
+        (clap_entry.deinit.unwrap())();
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
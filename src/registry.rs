@@ -0,0 +1,281 @@
+//! The module types a host can create.
+//!
+//! Module libraries beyond the core are built only when their cargo feature is enabled: `dsp`
+//! for effects and synthesis, `network` for Art-Net, HTTP and MQTT, `hardware` for HID and serial
+//! devices, `livecode`, and `lv2` and `clap` for hosting LV2 and CLAP plugins. All of them but the
+//! plugin hosts are on by default, `lv2` needing lilv installed. The registry describes whichever
+//! modules were built, grouped into categories, so hosts can show a palette to pick from.
+
+use crate::gui::module_gui::{BasicGuiModuleFactory, GuiModuleFactory};
+use crate::module::{Module, ModuleInfo};
+
+struct Entry {
+    factory: Box<dyn GuiModuleFactory>,
+    category: &'static str,
+    description: &'static str,
+}
+
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// A registry with no module types.
+    pub fn new() -> Registry {
+        Registry { entries: Vec::new() }
+    }
+
+    pub fn add<T: Module + 'static>(&mut self, category: &'static str, description: &'static str) {
+        self.add_factory(Box::new(BasicGuiModuleFactory::<T>::new()), category, description);
+    }
+
+    /// Add a module type created by `factory`, for types only known at runtime, like plugins.
+    pub fn add_factory(
+        &mut self,
+        factory: Box<dyn GuiModuleFactory>,
+        category: &'static str,
+        description: &'static str,
+    ) {
+        self.entries.push(Entry {
+            factory,
+            category,
+            description,
+        });
+    }
+
+    /// Every module type built into this binary.
+    pub fn standard() -> Registry {
+        use crate::module::audio_io::*;
+        use crate::module::channels::*;
+        use crate::module::chaos::*;
+        use crate::module::comment::*;
+        use crate::module::debug::*;
+        use crate::module::expr::*;
+        use crate::module::gesture::*;
+        use crate::module::limiter::*;
+        use crate::module::macros::*;
+        use crate::module::mapping::*;
+        use crate::module::mix::*;
+        use crate::module::perlin::*;
+        use crate::module::process::*;
+        use crate::module::record::*;
+        use crate::module::routing::*;
+        use crate::module::scheduler::*;
+        use crate::module::screen_capture::*;
+        use crate::module::slew::*;
+        use crate::module::tensor::*;
+        use crate::module::throttle::*;
+        use crate::module::timeline::*;
+        use crate::module::tuning::*;
+        use crate::module::video_out::*;
+
+        let mut registry = Registry::new();
+        registry.add::<AudioIO>("I/O", "Audio in and out through JACK, one task per port");
+        registry.add::<BlockAudioIO>("I/O", "Audio in and out through JACK, scheduled in blocks");
+        registry.add::<VideoOut>("I/O", "Writes images to a pipe as a video stream for ffmpeg");
+        registry.add::<ScreenCapture>("I/O", "Captures a screen region as images through ffmpeg");
+        registry.add::<ControlIn>("I/O", "MIDI and OSC controls for the params mapped to them");
+        registry.add::<Printer<i32>>("Utility", "Prints every value it receives");
+        registry.add::<Counter<i32>>("Utility", "Counts up on every request");
+        registry.add::<Comment>("Utility", "A note saved with the patch");
+        registry.add::<Recorder<f32>>("Utility", "Records the values passing through to a file");
+        registry.add::<Replay<f32>>("Utility", "Plays back values recorded to a file");
+        registry.add::<Recorder<Frame>>("Utility", "Records the audio passing through to a file");
+        registry.add::<Replay<Frame>>("Utility", "Plays back audio recorded to a file");
+        registry.add::<ToTensor>("Utility", "Converts frames to tensors of a row per sample");
+        registry.add::<ToFrame>("Utility", "Converts tensors of one or two dimensions to frames");
+        registry.add::<TensorModule<Reshape>>("Utility", "Gives tensors a new shape");
+        registry.add::<TensorModule<Slice>>("Utility", "Cuts a range out of tensors along an axis");
+        registry.add::<Processor<Gain>>("Mixing", "Scales a signal");
+        registry.add::<Processor<Mixer>>("Mixing", "Sums four signals");
+        registry.add::<Processor<MatrixMixer>>("Mixing", "Gain from each of four inputs to four outputs");
+        registry.add::<Processor<ChannelStrip>>("Mixing", "Gain, balance, DC blocking and soft clipping");
+        registry.add::<Processor<Limiter>>("Mixing", "Lookahead limiter that mutes runaway feedback");
+        registry.add::<Processor<MonoToStereo>>("Mixing", "Copies a mono signal to both stereo channels");
+        registry.add::<Processor<StereoToMono>>("Mixing", "Averages the channels of a stereo signal");
+        registry.add::<Processor<Crossfader>>("Mixing", "Equal-power crossfade between two signals");
+        registry.add::<Processor<AudioSwitch>>("Mixing", "Passes on one of four signals");
+        registry.add::<Processor<AudioRouter>>("Mixing", "Sends a signal to one of four outputs");
+        registry.add::<Ramp>("Control", "One-shot ramp between two values when triggered");
+        registry.add::<Metronome>("Control", "Counts beats at a tempo");
+        registry.add::<Cues>("Control", "Fires named events at transport times");
+        registry.add::<Transport>("Control", "Follows a tempo map through bars and beats");
+        registry.add::<Expr>("Control", "Evaluates a math expression of its inputs");
+        registry.add::<Macros>("Control", "Knobs each turning many params of the patch at once");
+        registry.add::<Switch<f32>>("Control", "Passes on values from one of four inputs");
+        registry.add::<Router<f32>>("Control", "Sends values to one of four outputs");
+        registry.add::<Throttle<f32>>("Control", "Passes on at most a number of values per second");
+        registry.add::<Decimate<f32>>("Control", "Passes on every Nth value");
+        registry.add::<Debounce<f32>>("Control", "Passes on the last of a burst of values");
+        registry.add::<Slew>("Control", "Ramps smoothly to every value it receives");
+        registry.add::<Attractor<Lorenz>>("Control", "Smooth chaotic values from the Lorenz attractor");
+        registry.add::<Attractor<Rossler>>("Control", "Smooth chaotic values from the Rossler attractor");
+        registry.add::<BouncingBall>("Control", "A ball bouncing to rest, with its height and impacts");
+        registry.add::<Spring>("Control", "A mass on a spring swinging towards its target");
+        registry.add::<NoiseLfo>("Control", "Smooth random values from layers of Perlin noise");
+        registry.add::<NoiseField>("Visuals", "Images of drifting Perlin noise");
+        registry.add::<GestureModule>("Control", "Records the moves of a control and loops them");
+        registry.add::<TuningModule>("Control", "Sets the tuning of the patch from Scala files");
+        registry.add::<Pitch>("Control", "The frequency of a key number in the patch's tuning");
+        #[cfg(feature = "dsp")]
+        {
+            use crate::module::ambisonics::*;
+            use crate::module::automaton::*;
+            use crate::module::draw::*;
+            use crate::module::dynamics::*;
+            use crate::module::filter::*;
+            use crate::module::freeze::*;
+            use crate::module::looper::*;
+            use crate::module::lsystem::*;
+            use crate::module::markov::*;
+            use crate::module::particles::*;
+            use crate::module::physical::*;
+            use crate::module::resample::*;
+            use crate::module::reverb::*;
+            use crate::module::sampler::*;
+            use crate::module::spectrogram::*;
+            use crate::module::tap::*;
+            use crate::module::wavetable::*;
+            registry.add::<Processor<Filter>>("Effects", "Resonant lowpass filter");
+            registry.add::<DynamicsModule>("Effects", "Compressor and noise gate with side-chain");
+            registry.add::<Follower>("Analysis", "The level of a signal as a control value");
+            registry.add::<ReverbModule>("Effects", "Feedback delay network reverb");
+            registry.add::<Freeze>("Effects", "Records a chain once and loops the recording");
+            registry.add::<Processor<Looper>>("Effects", "Records bars in time and loops them with overdub");
+            registry.add::<ResamplerModule>("Effects", "Converts between sample rates, fixed or varispeed");
+            registry.add::<SpectrogramModule>("Analysis", "Spectrogram rows for waterfall displays");
+            registry.add::<Tap>("Effects", "Passes audio through with a copy on a second output");
+            registry.add::<WavetableOsc>("Sources", "Oscillator morphing through a bank of waveforms");
+            registry.add::<PluckedString>("Sources", "Karplus-Strong plucked string");
+            registry.add::<Sampler>("Sources", "Plays SF2 and SFZ instruments from note events");
+            registry.add::<Markov>("Control", "Plays new melodies from what it has learned of others");
+            registry.add::<LSystem>("Control", "Plays the string an L-system grows as notes and triggers");
+            registry.add::<Particles>("Visuals", "Particle system driven by control inputs");
+            registry.add::<CellularAutomaton>("Visuals", "Cellular automaton stepping rhythms and images");
+            registry.add::<Draw>("Visuals", "Draws paths, strokes and fills from a short program");
+            registry.add::<Processor<AmbisonicEncoder>>("Spatial", "Pans a source into ambisonics");
+            registry.add::<AmbisonicDecoder>("Spatial", "Decodes first order ambisonics to a speaker layout");
+        }
+        #[cfg(feature = "network")]
+        {
+            use crate::module::artnet::*;
+            use crate::module::http::*;
+            use crate::module::mqtt::*;
+            registry.add::<ArtNetOut>("Network", "Sends DMX over Art-Net");
+            registry.add::<Webhook>("Network", "Embedded HTTP endpoint");
+            registry.add::<MqttIn>("Network", "Subscribes to MQTT topics");
+        }
+        #[cfg(feature = "hardware")]
+        {
+            use crate::module::hid::*;
+            use crate::module::serial::*;
+            registry.add::<Keyboard>("Hardware", "Key presses captured by its body");
+            registry.add::<Gamepad>("Hardware", "Buttons and axes of connected gamepads");
+            registry.add::<Serial>("Hardware", "Reads and writes a serial port");
+        }
+        #[cfg(feature = "alsa-io")]
+        {
+            use crate::module::alsa_io::*;
+            registry.add::<AlsaAudioIO>("I/O", "Audio straight through an ALSA device, scheduled in blocks");
+        }
+        #[cfg(feature = "gpio")]
+        {
+            use crate::module::gpio::*;
+            registry.add::<GpioIn>("Hardware", "Debounced changes of a Raspberry Pi pin");
+            registry.add::<GpioPwm>("Hardware", "Drives a Raspberry Pi pin with PWM");
+            registry.add::<I2cSensor>("Hardware", "Polls a register of an I2C sensor");
+        }
+        #[cfg(feature = "livecode")]
+        {
+            use crate::module::livecode::*;
+            registry.add::<LiveCode>("Control", "Runs a script, reloading it when the file changes");
+        }
+        #[cfg(feature = "lv2")]
+        {
+            use crate::module::lv2::*;
+            registry.add::<Lv2Plugin>("LV2", "Hosts the LV2 plugin given by its URI");
+            add_installed(&mut registry);
+        }
+        #[cfg(feature = "clap")]
+        {
+            use crate::module::clap::*;
+            registry.add::<ClapPlugin>("CLAP", "Hosts the CLAP plugin given by its file and id");
+            add_installed(&mut registry);
+        }
+        #[cfg(feature = "ndi")]
+        {
+            use crate::module::ndi::*;
+            registry.add::<NdiOut>("I/O", "Publishes images as an NDI video source");
+        }
+        #[cfg(feature = "onnx")]
+        {
+            use crate::module::onnx::*;
+            registry.add::<OnnxModel>("Control", "Runs an ONNX neural network on incoming frames");
+        }
+        #[cfg(feature = "gpu")]
+        {
+            use crate::module::gpu::*;
+            registry.add::<GpuShader>("Effects", "Processes audio in batches with a WGSL compute shader");
+        }
+        registry
+    }
+
+    /// A description of every module type, with its category and ports.
+    pub fn available_modules(&self) -> Vec<ModuleInfo> {
+        self.entries
+            .iter()
+            .map(|entry| ModuleInfo {
+                category: entry.category.into(),
+                description: entry.description.into(),
+                ..entry.factory.describe()
+            })
+            .collect()
+    }
+
+    /// The categories in the order they were first added.
+    pub fn categories(&self) -> Vec<&'static str> {
+        let mut categories: Vec<&'static str> = Vec::new();
+        for entry in &self.entries {
+            if !categories.contains(&entry.category) {
+                categories.push(entry.category);
+            }
+        }
+        categories
+    }
+
+    /// The names of the module types in `category`.
+    pub fn names_in(&self, category: &str) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.category == category)
+            .map(|entry| entry.factory.name().to_string())
+            .collect()
+    }
+
+    pub fn factory(&self, name: &str) -> Option<&dyn GuiModuleFactory> {
+        self.entries
+            .iter()
+            .find(|entry| entry.factory.name() == name)
+            .map(|entry| &*entry.factory)
+    }
+
+    pub fn factory_mut(&mut self, name: &str) -> Option<&mut (dyn GuiModuleFactory + 'static)> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.factory.name() == name)
+            .map(|entry| &mut *entry.factory)
+    }
+}
+
+#[test]
+fn test_registry() {
+    let registry = Registry::standard();
+    let modules = registry.available_modules();
+    let gain = modules.iter().find(|info| info.name == "Gain").unwrap();
+    assert_eq!(gain.category, "Mixing");
+    assert!(gain.ports.iter().any(|port| port.name == "Input"));
+    assert_eq!(&registry.categories()[..3], &["I/O", "Utility", "Mixing"]);
+    assert!(registry.names_in("Mixing").contains(&"Mixer".to_string()));
+    assert!(registry.factory("Comment").is_some());
+    assert!(registry.factory("Nonexistent").is_none());
+}
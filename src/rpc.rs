@@ -0,0 +1,676 @@
+//! JSON-RPC 2.0 introspection API for external tooling.
+//!
+//! Requests and responses are newline delimited JSON over TCP. Supported methods:
+//!
+//! - `modules.list`: every registered module type with its category, description and port schema
+//! - `graph.get`: the current nodes, their ports, metadata and connections
+//! - `metrics.subscribe` (`{"interval_ms": n}`): start receiving `metrics` notifications on this
+//!   connection, every 100 ms by default. Each has the buffer depths of every port, the share of
+//!   the CPU every node took since the last one, and the number of xruns so far, for live views
+//!   of where a patch spends its time
+//! - `params.get` (`{"node": id}`): the named parameters of a node and their values
+//! - `params.set` (`{"node": id, "params": {name: value, ...}}`): set parameters of a node, like
+//!   the gains of a matrix mixer
+//! - `graph.apply` (`{"ops": [op, ...]}`): apply a batch of edits, all or none. Each op is one of
+//!   `{"op": "connect", "from": port, "to": port}`, `{"op": "disconnect", "port": port}`,
+//!   `{"op": "remove", "node": id}`, `{"op": "set_param", "node": id, "name": name, "value": x}`
+//!   or `{"op": "set_gain", "port": port, "gain": x}`, with ports given as
+//!   `{"node": id, "port": id}`. Audio connections take an optional `"gain"`, which scales the
+//!   signal passing through, and `null` removes it
+//! - `solo.get`: the solo mode, and whether each source and channel is soloed, muted and silenced
+//! - `solo.set` (`{"node": id, "solo": bool}`), `solo.mute` (`{"node": id, "muted": bool}`),
+//!   `solo.mode` (`{"mode": "exclusive" | "additive"}`) and `solo.clear`: work the solo and mute
+//!   buttons of the mix, see `module::solo`
+//! - `mapping.list`: the controls mapped to params, and the param being learned
+//! - `mapping.learn` (`{"node": id, "param": name, "min": x, "max": x, "curve": curve}`): map the
+//!   next MIDI CC or OSC address to move to a param, with `curve` one of `"Linear"` (the default),
+//!   `"Exponential"` or `"Logarithmic"`. See `module::mapping`
+//! - `mapping.remove` (`{"node": id, "param": name}`): unmap a param
+//! - `params.lock` (`{"node": id, "param": name, "locked": bool}`): keep a param out of
+//!   randomizations and mutations, or let it back in
+//! - `graph.randomize` (`{"scope": scope, "amount": x}`) and `graph.mutate` (`{"scope": scope}`):
+//!   change the params of the nodes in `scope`, one of `"all"`, `{"nodes": [id, ...]}` or
+//!   `{"tagged": tag}`, and give their new values as `[[id, name, value], ...]`. See
+//!   `module::randomize`
+//! - `compare.store` (`{"slot": "a" | "b"}`), `compare.select` (`{"slot": "a" | "b"}`) and
+//!   `compare.toggle`: snapshot the patch into an A/B slot and fade between the slots, see
+//!   `module::compare`
+//! - `compare.diff`: the params set differently in the two slots, as
+//!   `[{"node": id, "param": name, "a": x, "b": x}, ...]`
+
+use crate::future_ext::Breaker;
+use crate::module::compare::Slot;
+use crate::module::mapping::Target;
+use crate::module::randomize::Scope;
+use crate::module::scene::Params;
+use crate::module::solo::{self, SoloMode};
+use crate::module::{flow, ModuleInfo};
+
+use serde_json::{self, Value};
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+const DEFAULT_METRICS_INTERVAL: u64 = 100;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Handle to a running server. The server shuts down when this is dropped.
+pub struct Server {
+    graph: Arc<Mutex<Arc<flow::Graph>>>,
+    breaker: Breaker,
+}
+
+impl Server {
+    pub fn start(addr: &str, graph: Arc<flow::Graph>, modules: Vec<ModuleInfo>) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let server = Server {
+            graph: Arc::new(Mutex::new(graph)),
+            breaker: Breaker::new(),
+        };
+        let state = State {
+            graph: server.graph.clone(),
+            modules: Arc::new(modules),
+            breaker: server.breaker.clone(),
+        };
+        thread::spawn(move || accept(listener, state));
+        Ok(server)
+    }
+    /// Point the server at a different graph, e.g. after loading a project.
+    pub fn set_graph(&self, graph: Arc<flow::Graph>) {
+        *self.graph.lock().unwrap() = graph;
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.breaker.brake();
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    graph: Arc<Mutex<Arc<flow::Graph>>>,
+    modules: Arc<Vec<ModuleInfo>>,
+    breaker: Breaker,
+}
+
+impl State {
+    fn graph(&self) -> Arc<flow::Graph> {
+        self.graph.lock().unwrap().clone()
+    }
+}
+
+fn accept(listener: TcpListener, state: State) {
+    while !state.breaker.test() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let state = state.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, state) {
+                        println!("rpc err: {:?}", e);
+                    }
+                });
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => {
+                println!("rpc accept err: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: State) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle_request(&request, &state, &writer),
+            Err(e) => Some(error(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        if let Some(response) = response {
+            send(&writer, &response)?;
+        }
+    }
+    Ok(())
+}
+
+fn send(writer: &Mutex<TcpStream>, message: &Value) -> io::Result<()> {
+    let mut writer = writer.lock().unwrap();
+    writeln!(writer, "{}", message)
+}
+
+/// Returns the response, or None for notifications.
+fn handle_request(request: &Value, state: &State, writer: &Arc<Mutex<TcpStream>>) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let method = match request.get("method").and_then(|method| method.as_str()) {
+        Some(method) => method,
+        None => return Some(error(id.unwrap_or(Value::Null), INVALID_REQUEST, "missing method")),
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let result = match method {
+        "modules.list" => Ok(serde_json::to_value(&*state.modules).unwrap()),
+        "graph.get" => Ok(describe_graph(&state.graph())),
+        "metrics.subscribe" => subscribe(&params, state, writer),
+        "params.get" => get_params(&params, &state.graph()),
+        "params.set" => set_params(&params, &state.graph()),
+        "graph.apply" => apply(&params, &state.graph()),
+        "solo.get" => Ok(get_solo(&state.graph())),
+        "solo.set" => set_solo(&params, &state.graph()),
+        "solo.mute" => mute(&params, &state.graph()),
+        "solo.mode" => solo_mode(&params, &state.graph()),
+        "solo.clear" => {
+            state.graph().clear_solo();
+            Ok(Value::Bool(true))
+        }
+        "mapping.list" => {
+            let graph = state.graph();
+            Ok(json!({ "mappings": graph.mappings(), "learning": graph.learning() }))
+        }
+        "mapping.learn" => learn(&params, &state.graph()),
+        "mapping.remove" => unmap(&params, &state.graph()),
+        "params.lock" => lock_param(&params, &state.graph()),
+        "graph.randomize" => randomize(&params, &state.graph()),
+        "graph.mutate" => scope(&params).map(|scope| json!(state.graph().mutate(&scope))),
+        "compare.store" => slot(&params).map(|slot| {
+            state.graph().store_compare(slot);
+            Value::Bool(true)
+        }),
+        "compare.select" => slot(&params).and_then(|slot| {
+            state
+                .graph()
+                .select_compare(slot)
+                .map(|()| Value::Bool(true))
+                .map_err(|e| (INVALID_PARAMS, format!("{:?}", e)))
+        }),
+        "compare.toggle" => state
+            .graph()
+            .toggle_compare()
+            .map(|slot| json!(slot))
+            .map_err(|e| (INVALID_PARAMS, format!("{:?}", e))),
+        "compare.diff" => state
+            .graph()
+            .compare_differences()
+            .map(|differences| json!(differences))
+            .map_err(|e| (INVALID_PARAMS, format!("{:?}", e))),
+        _ => Err((METHOD_NOT_FOUND, format!("unknown method {:?}", method))),
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => error(id, code, &message),
+    })
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn describe_graph(graph: &flow::Graph) -> Value {
+    // a snapshot, so a client never sees one end of a connection being made
+    let snapshot = graph.snapshot();
+    let nodes: Vec<_> = snapshot
+        .nodes
+        .values()
+        .map(|node| {
+            let ports: Vec<_> = node
+                .ports
+                .iter()
+                .map(|port| {
+                    let edge = port
+                        .edge
+                        .map(|other| json!({"node": other.node.0, "port": other.port.0}));
+                    json!({
+                        "id": port.id.0,
+                        "name": port.name,
+                        "input": port.in_type,
+                        "output": port.out_type,
+                        "edge": edge,
+                        "gain": port.gain,
+                    })
+                })
+                .collect();
+            json!({"id": node.id.0, "ports": ports, "meta": node.meta})
+        })
+        .collect();
+    json!({ "nodes": nodes })
+}
+
+/// How long every node had spent processing at the last notification, to work out the CPU it took
+/// since.
+struct Load {
+    at: Instant,
+    busy: HashMap<flow::NodeId, Duration>,
+}
+
+impl Load {
+    fn new(graph: &flow::Graph) -> Load {
+        Load {
+            at: Instant::now(),
+            busy: graph.nodes().iter().map(|node| (node.id(), node.busy())).collect(),
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+fn metrics(graph: &flow::Graph, load: &mut Load) -> Value {
+    let now = Instant::now();
+    let interval = seconds(now - load.at);
+    let mut cpu = Vec::new();
+    let mut ports = Vec::new();
+    let mut edges = 0;
+    for node in graph.nodes() {
+        let busy = node.busy();
+        let last = load.busy.insert(node.id(), busy).unwrap_or_default();
+        let share = if interval > 0.0 {
+            // after `set_graph`, an id can belong to a node that has been busy for less time
+            seconds(busy.checked_sub(last).unwrap_or_default()) / interval
+        } else {
+            0.0
+        };
+        cpu.push(json!({"node": node.id().0, "cpu": share}));
+        for port in node.ports() {
+            if port.edge().is_some() {
+                edges += 1;
+            }
+            ports.push(json!({
+                "node": node.id().0,
+                "port": port.id().0,
+                "buffered": port.buffered(),
+                "dropped": port.dropped(),
+                "received": port.received(),
+            }));
+        }
+    }
+    load.at = now;
+    let pool = graph.pool().stats();
+    json!({
+        "nodes": graph.nodes().len(),
+        // each connection is seen from both ends
+        "edges": edges / 2,
+        "ports": ports,
+        "cpu": cpu,
+        "xruns": graph.xruns(),
+        "pool": {
+            "allocated": pool.allocated,
+            "reused": pool.reused,
+            "free": pool.free,
+        },
+    })
+}
+
+fn subscribe(params: &Value, state: &State, writer: &Arc<Mutex<TcpStream>>) -> Result<Value, (i64, String)> {
+    let interval = match params.get("interval_ms") {
+        None => DEFAULT_METRICS_INTERVAL,
+        Some(interval) => interval
+            .as_u64()
+            .filter(|&interval| interval > 0)
+            .ok_or((INVALID_PARAMS, "interval_ms must be a positive integer".to_string()))?,
+    };
+    let state = state.clone();
+    let writer = writer.clone();
+    thread::spawn(move || {
+        let mut load = Load::new(&state.graph());
+        while !state.breaker.test() {
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "metrics",
+                "params": metrics(&state.graph(), &mut load),
+            });
+            // stop once the client goes away
+            if send(&writer, &notification).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(interval));
+        }
+    });
+    Ok(Value::Bool(true))
+}
+
+/// The parameters of the node named by `params`.
+fn node_params(params: &Value, graph: &flow::Graph) -> Result<Arc<dyn Params>, (i64, String)> {
+    let id = params
+        .get("node")
+        .and_then(|id| id.as_u64())
+        .ok_or((INVALID_PARAMS, "node must be a node id".to_string()))?;
+    let node = graph
+        .node(flow::NodeId(id as usize))
+        .ok_or((INVALID_PARAMS, format!("no node {}", id)))?;
+    node.params()
+        .ok_or((INVALID_PARAMS, format!("node {} has no params", id)))
+}
+
+fn get_params(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let node_params = node_params(params, graph)?;
+    let values: serde_json::Map<String, Value> = node_params
+        .names()
+        .into_iter()
+        .filter_map(|name| {
+            let value = node_params.get(&name)?;
+            Some((name, json!(value)))
+        })
+        .collect();
+    Ok(Value::Object(values))
+}
+
+fn set_params(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let node_params = node_params(params, graph)?;
+    let values = params
+        .get("params")
+        .and_then(|values| values.as_object())
+        .ok_or((INVALID_PARAMS, "params must be an object".to_string()))?;
+    // check everything first, so a bad request changes nothing
+    let names = node_params.names();
+    let mut changes = Vec::new();
+    for (name, value) in values {
+        if !names.contains(name) {
+            return Err((INVALID_PARAMS, format!("unknown param {:?}", name)));
+        }
+        let value = value
+            .as_f64()
+            .ok_or((INVALID_PARAMS, format!("param {:?} must be a number", name)))?;
+        changes.push((name, value as f32));
+    }
+    for (name, value) in changes {
+        node_params.set(name, value);
+    }
+    Ok(Value::Bool(true))
+}
+
+fn parse_op(op: &Value) -> Result<flow::GraphOp, String> {
+    fn field<T: ::serde::de::DeserializeOwned>(op: &Value, name: &str) -> Result<T, String> {
+        op.get(name)
+            .cloned()
+            .and_then(|value| serde_json::from_value(value).ok())
+            .ok_or(format!("invalid or missing {:?}", name))
+    }
+    fn gain(op: &Value) -> Result<Option<f32>, String> {
+        match op.get("gain") {
+            None | Some(&Value::Null) => Ok(None),
+            Some(gain) => gain.as_f64().map(|gain| Some(gain as f32)).ok_or("invalid \"gain\"".to_string()),
+        }
+    }
+    match op.get("op").and_then(|op| op.as_str()) {
+        Some("connect") => Ok(flow::GraphOp::Connect(
+            field(op, "from")?,
+            field(op, "to")?,
+            flow::ConnectOptions {
+                gain: gain(op)?,
+                ..flow::ConnectOptions::default()
+            },
+        )),
+        Some("disconnect") => Ok(flow::GraphOp::Disconnect(field(op, "port")?)),
+        Some("remove") => Ok(flow::GraphOp::RemoveNode(field(op, "node")?)),
+        Some("set_param") => Ok(flow::GraphOp::SetParam(
+            field(op, "node")?,
+            field(op, "name")?,
+            field(op, "value")?,
+        )),
+        Some("set_gain") => Ok(flow::GraphOp::SetGain(field(op, "port")?, gain(op)?)),
+        _ => Err("unknown op".to_string()),
+    }
+}
+
+fn apply(params: &Value, graph: &Arc<flow::Graph>) -> Result<Value, (i64, String)> {
+    let ops = params
+        .get("ops")
+        .and_then(|ops| ops.as_array())
+        .ok_or((INVALID_PARAMS, "ops must be an array".to_string()))?
+        .iter()
+        .enumerate()
+        .map(|(idx, op)| parse_op(op).map_err(|message| (INVALID_PARAMS, format!("op {}: {}", idx, message))))
+        .collect::<Result<Vec<_>, _>>()?;
+    graph
+        .apply(ops)
+        .map_err(|error| (INVALID_PARAMS, format!("batch rejected: {:?}", error)))?;
+    Ok(Value::Bool(true))
+}
+
+fn get_solo(graph: &flow::Graph) -> Value {
+    let soloed = graph.solo();
+    let nodes: Vec<_> = solo::nodes(graph)
+        .into_iter()
+        .filter_map(|id| graph.node(id))
+        .map(|node| {
+            json!({
+                "node": node.id().0,
+                "solo": soloed.soloed.contains(&node.id()),
+                "muted": node.muted(),
+                "silenced": node.silenced(),
+            })
+        })
+        .collect();
+    json!({ "mode": soloed.mode, "nodes": nodes })
+}
+
+/// The node named by `params`, with the flag `name`.
+fn node_flag(
+    params: &Value,
+    graph: &flow::Graph,
+    name: &str,
+) -> Result<(Arc<flow::Node>, bool), (i64, String)> {
+    let id = params
+        .get("node")
+        .and_then(|id| id.as_u64())
+        .ok_or((INVALID_PARAMS, "node must be a node id".to_string()))?;
+    let node = graph
+        .node(flow::NodeId(id as usize))
+        .ok_or((INVALID_PARAMS, format!("no node {}", id)))?;
+    let flag = params
+        .get(name)
+        .and_then(|flag| flag.as_bool())
+        .ok_or((INVALID_PARAMS, format!("{} must be a bool", name)))?;
+    Ok((node, flag))
+}
+
+fn set_solo(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let (node, solo) = node_flag(params, graph, "solo")?;
+    graph
+        .set_solo(node.id(), solo)
+        .map_err(|e| (INVALID_PARAMS, format!("{:?}", e)))?;
+    Ok(Value::Bool(true))
+}
+
+fn mute(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let (node, muted) = node_flag(params, graph, "muted")?;
+    node.set_muted(muted);
+    Ok(Value::Bool(true))
+}
+
+fn solo_mode(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let mode = params
+        .get("mode")
+        .cloned()
+        .and_then(|mode| serde_json::from_value::<SoloMode>(mode).ok())
+        .ok_or((INVALID_PARAMS, "mode must be \"exclusive\" or \"additive\"".to_string()))?;
+    graph.set_solo_mode(mode);
+    Ok(Value::Bool(true))
+}
+
+fn learn(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let node_params = node_params(params, graph)?;
+    let target: Target = serde_json::from_value(params.clone()).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+    if !node_params.names().contains(&target.param) {
+        return Err((INVALID_PARAMS, format!("unknown param {:?}", target.param)));
+    }
+    graph.learn(target);
+    Ok(Value::Bool(true))
+}
+
+fn lock_param(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let (node, locked) = node_flag(params, graph, "locked")?;
+    let param = params
+        .get("param")
+        .and_then(|param| param.as_str())
+        .ok_or((INVALID_PARAMS, "param must be a string".to_string()))?;
+    if !node.params().map_or(false, |params| params.names().iter().any(|name| name == param)) {
+        return Err((INVALID_PARAMS, format!("unknown param {:?}", param)));
+    }
+    node.lock_param(param, locked);
+    Ok(Value::Bool(true))
+}
+
+fn randomize(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let scope = scope(params)?;
+    let amount = params
+        .get("amount")
+        .and_then(|amount| amount.as_f64())
+        .ok_or((INVALID_PARAMS, "amount must be a number".to_string()))?;
+    Ok(json!(graph.randomize(&scope, amount as f32)))
+}
+
+fn slot(params: &Value) -> Result<Slot, (i64, String)> {
+    params
+        .get("slot")
+        .cloned()
+        .and_then(|slot| serde_json::from_value(slot).ok())
+        .ok_or((INVALID_PARAMS, "slot must be \"a\" or \"b\"".to_string()))
+}
+
+fn scope(params: &Value) -> Result<Scope, (i64, String)> {
+    let scope = params.get("scope").cloned().unwrap_or(Value::Null);
+    serde_json::from_value(scope).map_err(|e| (INVALID_PARAMS, format!("bad scope: {}", e)))
+}
+
+fn unmap(params: &Value, graph: &flow::Graph) -> Result<Value, (i64, String)> {
+    let id = params
+        .get("node")
+        .and_then(|id| id.as_u64())
+        .ok_or((INVALID_PARAMS, "node must be a node id".to_string()))?;
+    let param = params
+        .get("param")
+        .and_then(|param| param.as_str())
+        .ok_or((INVALID_PARAMS, "param must be a string".to_string()))?;
+    graph.unmap(flow::NodeId(id as usize), param);
+    Ok(Value::Bool(true))
+}
+
+#[test]
+fn test_metrics() {
+    let graph = flow::Graph::new();
+    let busy = graph.add_node();
+    let idle = graph.add_node();
+    let mut load = Load::new(&graph);
+    load.at = Instant::now() - Duration::from_millis(100);
+    busy.add_busy(Duration::from_millis(50));
+    graph.xrun();
+    graph.xrun();
+    let cpu = |sent: &Value, id: flow::NodeId| {
+        sent["cpu"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|node| node["node"] == id.0)
+            .and_then(|node| node["cpu"].as_f64())
+            .unwrap()
+    };
+
+    let sent = metrics(&graph, &mut load);
+    assert_eq!(sent["xruns"], 2);
+    // a little under half, as the interval is a little over 100 ms by now
+    assert!(cpu(&sent, busy.id()) > 0.4 && cpu(&sent, busy.id()) <= 0.5);
+    assert_eq!(cpu(&sent, idle.id()), 0.0);
+    assert_eq!(graph.node(busy.id()).unwrap().busy(), Duration::from_millis(50));
+
+    // the next notification only counts what was spent since
+    let sent = metrics(&graph, &mut load);
+    assert_eq!(cpu(&sent, busy.id()), 0.0);
+}
+
+#[test]
+fn test_requests() {
+    use crate::module::audio_io::Frame;
+
+    let graph = flow::Graph::new();
+    let source = graph.add_node();
+    let sink = graph.add_node();
+    let out = source.get_or_create_port::<(), Frame>("Output".into());
+    let inp = sink.get_or_create_port::<Frame, ()>("Input".into());
+    out.connect(&inp).unwrap();
+    let state = State {
+        graph: Arc::new(Mutex::new(graph.clone())),
+        modules: Arc::new(vec![ModuleInfo {
+            name: "Gain".into(),
+            category: "Mix".into(),
+            description: String::new(),
+            ports: Vec::new(),
+        }]),
+        breaker: Breaker::new(),
+    };
+    // metrics notifications would be written here
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let writer = Arc::new(Mutex::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap()));
+    let call = |method: &str, params: Value| {
+        let request = json!({"jsonrpc": "2.0", "id": 7, "method": method, "params": params});
+        handle_request(&request, &state, &writer).unwrap()
+    };
+    let code = |response: Value| {
+        assert_eq!(response["id"], 7);
+        response["error"]["code"].as_i64()
+    };
+    let port = |port: &Arc<flow::OpaquePort>| json!({"node": port.node_id().0, "port": port.id().0});
+
+    let response = call("modules.list", Value::Null);
+    assert_eq!(response["result"][0]["name"], "Gain");
+    assert_eq!(response["result"][0]["category"], "Mix");
+    // notifications have no id, and get no response
+    let notification = json!({"jsonrpc": "2.0", "method": "solo.clear"});
+    assert_eq!(handle_request(&notification, &state, &writer), None);
+
+    let missing = handle_request(&json!({"jsonrpc": "2.0", "id": 7}), &state, &writer).unwrap();
+    assert_eq!(code(missing), Some(INVALID_REQUEST));
+    assert_eq!(code(call("graph.delete", Value::Null)), Some(METHOD_NOT_FOUND));
+    assert_eq!(code(call("params.get", json!({}))), Some(INVALID_PARAMS));
+    assert_eq!(code(call("graph.apply", json!({"ops": {}}))), Some(INVALID_PARAMS));
+    let response = call("graph.apply", json!({"ops": [{"op": "rewire"}]}));
+    assert_eq!(response["error"]["message"], "op 0: unknown op");
+    assert_eq!(code(call("compare.store", json!({"slot": "c"}))), Some(INVALID_PARAMS));
+
+    let node = |id: flow::NodeId| {
+        let nodes = call("graph.get", Value::Null)["result"]["nodes"].clone();
+        nodes.as_array().unwrap().iter().find(|node| node["id"] == id.0).unwrap().clone()
+    };
+    let ports = node(source.id())["ports"].clone();
+    assert_eq!(ports.as_array().unwrap().len(), 1);
+    assert_eq!(ports[0]["id"], out.id().0);
+    assert_eq!(ports[0]["name"], "Output");
+    assert_eq!(ports[0]["input"], "()");
+    assert_eq!(ports[0]["edge"], port(&inp.as_opaque()));
+    assert_eq!(ports[0]["gain"], Value::Null);
+    assert_eq!(node(sink.id())["ports"][0]["edge"], port(&out.as_opaque()));
+
+    // a batch is applied whole, and seen by the next `graph.get`
+    let ops = json!({"ops": [
+        {"op": "disconnect", "port": port(&out.as_opaque())},
+        {"op": "connect", "from": port(&out.as_opaque()), "to": port(&inp.as_opaque()), "gain": 0.5},
+    ]});
+    assert_eq!(call("graph.apply", ops)["result"], true);
+    assert_eq!(out.gain(), Some(0.5));
+    let ports = node(source.id())["ports"].clone();
+    assert_eq!(ports[0]["edge"], port(&inp.as_opaque()));
+    assert_eq!(ports[0]["gain"], 0.5);
+    // or not at all
+    let ops = json!({"ops": [
+        {"op": "set_gain", "port": port(&out.as_opaque()), "gain": null},
+        {"op": "connect", "from": port(&out.as_opaque()), "to": port(&inp.as_opaque())},
+    ]});
+    assert_eq!(code(call("graph.apply", ops)), Some(INVALID_PARAMS));
+    assert_eq!(out.gain(), Some(0.5));
+}